@@ -0,0 +1,16 @@
+// Config files: any TOML must deserialize or fail with an error, and a
+// config that deserializes must build its prior or fail with an error.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nested_sampling::Config;
+
+fuzz_target!(|text: &str| {
+    if let Ok(config) = Config::from_toml(text) {
+        let _ = config.n_params();
+        // a restricted prior reads a bounds file named in the config
+        if config.restrict.is_none() {
+            let _ = config.prior();
+        }
+    }
+});
@@ -0,0 +1,15 @@
+// Binary data files: the header is untrusted, so any bytes must either be
+// rejected or give columns that lie inside the buffer.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nested_sampling::data::Dataset;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(data) = Dataset::from_binary_bytes(bytes, "fuzz") {
+        assert_eq!(data.names().len(), data.ncols());
+        for j in 0..data.ncols() {
+            assert_eq!(data.column(j).len(), data.nrows());
+        }
+    }
+});
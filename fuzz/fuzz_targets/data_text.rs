@@ -0,0 +1,14 @@
+// Text data files: any string must parse to a dataset or a DataError.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nested_sampling::data::Dataset;
+
+fuzz_target!(|text: &str| {
+    if let Ok(data) = Dataset::parse_text(text, "fuzz") {
+        assert_eq!(data.names().len(), data.ncols());
+        for j in 0..data.ncols() {
+            assert_eq!(data.column(j).len(), data.nrows());
+        }
+    }
+});
@@ -0,0 +1,8 @@
+// R looks up the package's routines through R_init_<package>; forwarding
+// it to Rust also keeps the linker from dropping the static library.
+
+void R_init_nestedsampling_extendr(void *dll);
+
+void R_init_nestedsampling(void *dll) {
+    R_init_nestedsampling_extendr(dll);
+}
@@ -0,0 +1,146 @@
+// Glue between R and the sampler: an R closure as the likelihood, the
+// prior independent normals, and the weighted posterior handed back as
+// plain vectors for R/nested_sampling.R to shape into a data.frame.
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, ThreadId};
+
+use extendr_api::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use nested_sampling::models::LogLikelihood;
+use nested_sampling::observer::Collect;
+use nested_sampling::{run_with_model, Config};
+
+
+/// why a run on an R likelihood stopped, carried out of the sampler by
+/// unwinding; `ns_run` catches it and returns it to R as an error
+struct Stopped(String);
+
+
+/// an R function of theta returning log L
+///
+/// R is single-threaded, so the closure may only be called on the thread
+/// R runs on. The configs built here make no threads of their own, and a
+/// call from any other thread stops the run rather than touch R from it.
+/// A likelihood cannot fail, so an R error, or a value that is not a
+/// number, stops the run too.
+///
+/// Fields:
+/// f: the R function
+/// dim: length of theta
+/// thread: the thread of the R session
+struct RLikelihood {
+    f: Function,
+    dim: usize,
+    thread: ThreadId,
+}
+
+
+// SAFETY: `LogLikelihood` needs `Sync`, which lets other threads hold a
+// `&RLikelihood`. The only shared state that is not `Sync` is `f`, an R
+// object that R's API may only touch on R's own thread. `f` is read in
+// `log_lik` alone, and only after checking that the caller is on
+// `thread`, the thread that built the likelihood and called into R; any
+// other thread stops the run without touching `f`. The likelihood lives
+// on the stack of `ns_run`, so `f` is also dropped, and released to R's
+// garbage collector, on that thread. `dim` and `thread` are `Sync`.
+unsafe impl Sync for RLikelihood {}
+
+
+impl RLikelihood {
+    /// stop the run with `message`, without the panic hook printing it
+    fn stop(message: String) -> ! {
+        panic::resume_unwind(Box::new(Stopped(message)))
+    }
+}
+
+
+impl LogLikelihood for RLikelihood {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        if thread::current().id() != self.thread {
+            RLikelihood::stop("the R likelihood was called off the R thread; run without parallel sampling".to_string())
+        }
+        let value = match self.f.call(pairlist!(theta.to_vec())) {
+            Ok(value) => value,
+            Err(e) => RLikelihood::stop(format!("the likelihood failed at theta = {:?}: {}", theta, e)),
+        };
+        match value.as_real() {
+            Some(ll) if !ll.is_nan() => ll,
+            Some(_) => f64::NEG_INFINITY,
+            None => RLikelihood::stop(format!("the likelihood must return a single number, not {:?}", value.rtype())),
+        }
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+}
+
+
+/// Run nested sampling on an R log-likelihood under independent normal
+/// priors; see `nested_sampling()` for the R interface.
+/// @keywords internal
+#[extendr]
+fn ns_run(loglik: Function, mu: &[f64], sd: &[f64], live: i32, iterations: i32, tolerance: f64, seed: f64) -> Result<List> {
+    if mu.len() != sd.len() {
+        return Err(Error::Other(format!("mu has {} entries but sd has {}", mu.len(), sd.len())))
+    }
+    if live < 1 || iterations < 1 {
+        return Err(Error::Other("live and iterations must be positive".to_string()))
+    }
+    if seed < 0.0 || seed.is_infinite() {
+        return Err(Error::Other(format!("the seed {} must be a non-negative number", seed)))
+    }
+    let config = Config{
+        sample_num: iterations as usize,
+        particle_num: live as usize,
+        mu: mu.to_vec(),
+        sd: sd.to_vec(),
+        // NA, a NaN in R, leaves the run to stop after `iterations`
+        tolerance: (!tolerance.is_nan()).then_some(tolerance),
+        ..Default::default()
+    };
+    let model = RLikelihood{ f: loglik, dim: mu.len(), thread: thread::current().id() };
+    let mut rng = match seed.is_nan() {
+        true => StdRng::from_entropy(),
+        false => StdRng::seed_from_u64(seed as u64),
+    };
+    let mut warnings = Collect::default();
+    let run = panic::catch_unwind(AssertUnwindSafe(|| run_with_model(&config, &model, &mut warnings, &mut rng)));
+    let result = match run {
+        Ok(result) => result.map_err(|e| Error::Other(e.to_string()))?,
+        Err(payload) => match payload.downcast::<Stopped>() {
+            Ok(stopped) => return Err(Error::Other(stopped.0)),
+            // a bug of the sampler, not of the likelihood
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    };
+
+    // theta column by column, as R fills a matrix
+    let theta: Vec<f64> = (0..mu.len())
+        .flat_map(|j| result.posterior.iter().map(move |(t, _)| t[j]))
+        .collect();
+    let log_weight: Vec<f64> = result.posterior.iter().map(|(_, lw)| *lw).collect();
+    let log_l: Vec<f64> = match result.dead_birth.len() == result.posterior.len() {
+        true => result.dead_birth.iter().map(|(ll, _)| *ll).collect(),
+        false => Vec::new(),
+    };
+    Ok(list!(
+        theta = theta,
+        log_weight = log_weight,
+        log_l = log_l,
+        log_z = result.log_z,
+        log_z_err = result.log_z_err,
+        information = result.info,
+        ess = result.ess,
+        iterations = result.iterations as f64,
+        warnings = warnings.warnings
+    ))
+}
+
+
+extendr_module! {
+    mod nestedsampling;
+    fn ns_run;
+}
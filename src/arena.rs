@@ -0,0 +1,96 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_reused() {
+        let mut arena = Arena::new(2, 3);
+        let a = arena.insert(&[1.0, 2.0]).unwrap();
+        let b = arena.insert(&[3.0, 4.0]).unwrap();
+        arena.remove(a);
+        let c = arena.insert(&[5.0, 6.0]).unwrap();
+        assert_eq!(c, a);
+        assert_eq!(arena.get(b), &[3.0, 4.0]);
+        assert_eq!(arena.get(c), &[5.0, 6.0]);
+        assert_eq!(arena.len(), 2);
+        assert!(arena.insert(&[1.0]).is_err());
+
+        // zero-width rows still hand out distinct slots
+        let mut empty = Arena::new(0, 2);
+        assert_ne!(empty.insert(&[]).unwrap(), empty.insert(&[]).unwrap());
+    }
+}
+
+
+/// fixed-width rows of f64 in one contiguous buffer
+///
+/// Rows are addressed by slot. The buffer is sized for the expected number
+/// of rows up front, and the slots of removed rows are handed out again
+/// before the buffer grows, so a steady-state run does no allocation.
+///
+/// Fields:
+/// width: values per row
+/// values: the rows, back to back
+/// slots: rows ever allocated, live or free
+/// free: slots of removed rows, reused first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Arena {
+    width: usize,
+    values: Vec<f64>,
+    slots: usize,
+    free: Vec<usize>,
+}
+
+
+impl Arena {
+    /// an empty arena with room for `slots` rows of `width` values
+    pub fn new(width: usize, slots: usize) -> Arena {
+        Arena{ width, values: Vec::with_capacity(width * slots), slots: 0, free: Vec::new() }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// rows currently stored
+    pub fn len(&self) -> usize {
+        self.slots - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// copy `row` into a free slot and return the slot
+    pub fn insert(&mut self, row: &[f64]) -> Result<usize, Box<dyn Error>> {
+        if row.len() != self.width {
+            return Err(format!("arena rows hold {} values, got {}", self.width, row.len()).into())
+        }
+        if let Some(slot) = self.free.pop() {
+            self.get_mut(slot).copy_from_slice(row);
+            return Ok(slot)
+        }
+        self.values.extend_from_slice(row);
+        self.slots += 1;
+        Ok(self.slots - 1)
+    }
+
+    /// free `slot` for reuse; its row must not be read afterwards
+    pub fn remove(&mut self, slot: usize) {
+        debug_assert!(slot < self.slots && !self.free.contains(&slot));
+        self.free.push(slot);
+    }
+
+    pub fn get(&self, slot: usize) -> &[f64] {
+        &self.values[slot * self.width..(slot + 1) * self.width]
+    }
+
+    pub fn get_mut(&mut self, slot: usize) -> &mut [f64] {
+        &mut self.values[slot * self.width..(slot + 1) * self.width]
+    }
+}
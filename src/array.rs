@@ -0,0 +1,356 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::sweep::{combinations, overrides, run_combination, Axis, SweepOutcome, SweepRow};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_run_and_collect() {
+        let root = std::env::temp_dir().join(format!("ns_array_{}", std::process::id()));
+        let data_file = root.join("line.csv");
+        fs::create_dir_all(&root).unwrap();
+        let rows: String = (0..20).map(|i| format!("{},{}\n", i as f64 / 10.0, 1.0 + 2.0 * i as f64 / 10.0)).collect();
+        fs::write(&data_file, format!("x,y\n{}", rows)).unwrap();
+        let base: Value = toml::from_str(&format!(
+            "data_file = {:?}\nsample_num = 100\nparticle_num = 10\nbeta_num = 0\nmu = [1.0, 2.0]\nsd = [1.0, 1.0]\nnoise_sd = 0.5\n",
+            data_file,
+        )).unwrap();
+        let axes = vec![Axis::parse("particle_num=10,1").unwrap()];
+        let dir = root.join("array");
+        let settings = ArrayConfig{ tasks: 3, replicates: 2, seed: 7 };
+        let manifest = prepare(&dir, &base, &axes, &settings, Path::new("ns")).unwrap();
+        assert_eq!(manifest.jobs.len(), 4);
+        assert_eq!(manifest.jobs.iter().map(|j| j.seed).collect::<Vec<u64>>(), vec![7, 8, 9, 10]);
+        assert!(fs::read_to_string(dir.join("submit.slurm")).unwrap().contains("--array=0-2"));
+        assert!(prepare(&dir, &base, &axes, &settings, Path::new("ns")).is_err());
+        assert_eq!(sh_quote(Path::new("/runs/it's $HOME `x` \\n")).unwrap(), "'/runs/it'\\''s $HOME `x` \\n'");
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            assert!(sh_quote(Path::new(std::ffi::OsStr::from_bytes(b"/runs/\xff"))).is_err());
+        }
+        let script = fs::read_to_string(dir.join("submit.pbs")).unwrap();
+        assert!(script.contains(&format!("'ns' array-run '{}'", dir.canonicalize().unwrap().display())), "{}", script);
+
+        // collecting before every task has run names the missing ones
+        run_task(&dir, 0).unwrap();
+        let e = collect(&dir).unwrap_err().to_string();
+        assert!(e.contains("1, 2"), "{}", e);
+        for task in 1..3 {
+            run_task(&dir, task).unwrap();
+        }
+        assert!(run_task(&dir, 3).is_err());
+        let (_, rows) = collect(&dir).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1].settings, vec!["10", "1", "8"]);
+        assert!(rows[0].outcome.is_ok() && rows[3].outcome.is_err());
+
+        // the same seed gives the same run wherever it runs
+        let first = rows[0].outcome.as_ref().unwrap().log_z;
+        run_task(&dir, 0).unwrap();
+        let (_, rows) = collect(&dir).unwrap();
+        assert_eq!(rows[0].outcome.as_ref().unwrap().log_z, first);
+
+        // results of another preparation are refused
+        let mut other = TaskResult::read(&dir, 1).unwrap();
+        other.manifest = "0".repeat(16);
+        fs::write(task_file(&dir, 1), toml::to_string(&other).unwrap()).unwrap();
+        assert!(collect(&dir).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
+
+
+/// settings of an array job
+///
+/// Fields:
+/// tasks: array tasks the runs are dealt to, round robin; at most one
+///     per run
+/// replicates: runs of every combination of the axis values
+/// seed: seed of the first run; run i is seeded with seed + i
+#[derive(Debug, Clone)]
+pub struct ArrayConfig {
+    pub tasks: usize,
+    pub replicates: usize,
+    pub seed: u64,
+}
+
+
+impl Default for ArrayConfig {
+    fn default() -> ArrayConfig {
+        ArrayConfig{ tasks: 1, replicates: 1, seed: 0 }
+    }
+}
+
+
+/// one run of an array job
+///
+/// Fields:
+/// index: position of the run among all runs
+/// task: the array task that makes it
+/// settings: the value of each axis
+/// replicate: which run of these settings it is
+/// seed: seed of the run's random numbers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArrayJob {
+    pub index: usize,
+    pub task: usize,
+    pub settings: Vec<String>,
+    pub replicate: usize,
+    pub seed: u64,
+}
+
+
+/// what `prepare` writes to array.toml and every task reads back
+///
+/// Fields:
+/// version: version of the crate that prepared the job
+/// tasks: number of array tasks
+/// keys: the config key of each axis
+/// config: the config every run starts from, overrides applied
+/// jobs: every run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArrayManifest {
+    pub version: String,
+    pub tasks: usize,
+    pub keys: Vec<String>,
+    pub config: Value,
+    pub jobs: Vec<ArrayJob>,
+}
+
+
+/// the outcome of one run of an array task, or why it failed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobResult {
+    pub index: usize,
+    pub outcome: Option<SweepOutcome>,
+    pub error: Option<String>,
+}
+
+
+/// what an array task writes when it is done
+///
+/// Fields:
+/// manifest: fingerprint of the array.toml the task ran, so results of an
+///     earlier preparation in the same directory are not mixed in
+/// version: version of the crate that ran the task
+/// task: index of the task
+/// jobs: the outcome of each of its runs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskResult {
+    pub manifest: String,
+    pub version: String,
+    pub task: usize,
+    pub jobs: Vec<JobResult>,
+}
+
+
+impl TaskResult {
+    pub fn read(dir: &Path, task: usize) -> Result<TaskResult, Box<dyn Error>> {
+        let path = task_file(dir, task);
+        let text = fs::read_to_string(&path)?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+}
+
+
+fn manifest_file(dir: &Path) -> PathBuf {
+    dir.join("array.toml")
+}
+
+
+fn task_file(dir: &Path, task: usize) -> PathBuf {
+    dir.join(format!("task-{}.toml", task))
+}
+
+
+/// the manifest of the job prepared in `dir` and its fingerprint
+fn read_manifest(dir: &Path) -> Result<(ArrayManifest, String), Box<dyn Error>> {
+    let path = manifest_file(dir);
+    let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let manifest = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok((manifest, fingerprint(&text)))
+}
+
+
+/// 64-bit FNV-1a of the manifest's text, as hex
+fn fingerprint(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    format!("{:016x}", hash)
+}
+
+
+/// the array task a scheduler started this process as, from Slurm's
+/// SLURM_ARRAY_TASK_ID or PBS's PBS_ARRAY_INDEX or PBS_ARRAYID
+pub fn task_from_env() -> Option<usize> {
+    ["SLURM_ARRAY_TASK_ID", "PBS_ARRAY_INDEX", "PBS_ARRAYID"].iter()
+        .find_map(|var| std::env::var(var).ok()?.trim().parse().ok())
+}
+
+
+/// shard the runs of a sweep, `settings.replicates` of every combination of
+/// the axis values, into array tasks, each run with a seed of its own
+///
+/// Writes array.toml, which lists every run, and submit.slurm and
+/// submit.pbs, which start `program array-run` once per task in the
+/// current directory, so relative paths in the config resolve as they do
+/// here. `dir` is created and must not hold an earlier preparation.
+pub fn prepare(
+        dir: &Path,
+        base: &Value,
+        axes: &[Axis],
+        settings: &ArrayConfig,
+        program: &Path,
+) -> Result<ArrayManifest, Box<dyn Error>> {
+    if settings.tasks == 0 || settings.replicates == 0 {
+        return Err("an array job needs at least one task and one replicate".into())
+    }
+    if manifest_file(dir).exists() {
+        return Err(format!("{} already holds an array job", dir.display()).into())
+    }
+    let mut jobs = Vec::new();
+    for combination in combinations(axes) {
+        for replicate in 0..settings.replicates {
+            jobs.push(ArrayJob{ index: jobs.len(), task: 0, settings: combination.clone(), replicate, seed: 0 });
+        }
+    }
+    // TOML integers are signed
+    if settings.seed.checked_add(jobs.len() as u64).is_none_or(|last| last > i64::MAX as u64) {
+        return Err(format!("the seed {} leaves no room for {} runs", settings.seed, jobs.len()).into())
+    }
+    let tasks = settings.tasks.min(jobs.len());
+    for job in jobs.iter_mut() {
+        job.task = job.index % tasks;
+        job.seed = settings.seed + job.index as u64;
+    }
+    let manifest = ArrayManifest{
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        tasks,
+        keys: axes.iter().map(|a| a.key.clone()).collect(),
+        config: base.clone(),
+        jobs,
+    };
+
+    fs::create_dir_all(dir)?;
+    let dir = dir.canonicalize()?;
+    let cwd = sh_quote(&std::env::current_dir()?)?;
+    let run = format!("{} array-run {}", sh_quote(program)?, sh_quote(&dir)?);
+    fs::write(manifest_file(&dir), toml::to_string(&manifest)?)?;
+    fs::write(dir.join("submit.slurm"), format!(
+        "#!/bin/sh\n#SBATCH --job-name=ns-array\n#SBATCH --array=0-{}\ncd {}\n{} --task \"$SLURM_ARRAY_TASK_ID\"\n",
+        tasks - 1, cwd, run,
+    ))?;
+    fs::write(dir.join("submit.pbs"), format!(
+        "#!/bin/sh\n#PBS -N ns-array\n#PBS -J 0-{}\ncd {}\n{} --task \"$PBS_ARRAY_INDEX\"\n",
+        tasks - 1, cwd, run,
+    ))?;
+    Ok(manifest)
+}
+
+
+/// `path` quoted as one word for a POSIX shell: inside single quotes
+/// nothing is expanded, and a quote of its own is closed, escaped and
+/// reopened
+fn sh_quote(path: &Path) -> Result<String, Box<dyn Error>> {
+    let text = path.to_str()
+        .ok_or_else(|| format!("{} is not valid UTF-8 and cannot go in a submit script", path.display()))?;
+    Ok(format!("'{}'", text.replace('\'', "'\\''")))
+}
+
+
+/// make the runs of array task `task` of the job prepared in `dir`, one
+/// after another, and write task-<task>.toml there. A run that fails
+/// records its error and the others carry on
+pub fn run_task(dir: &Path, task: usize) -> Result<TaskResult, Box<dyn Error>> {
+    let (manifest, fingerprint) = read_manifest(dir)?;
+    if task >= manifest.tasks {
+        return Err(format!("the array job has tasks 0 to {}, not {}", manifest.tasks - 1, task).into())
+    }
+    let axes: Vec<Axis> = manifest.keys.iter().map(|key| Axis{ key: key.clone(), values: Vec::new() }).collect();
+    let jobs = manifest.jobs.iter()
+        .filter(|job| job.task == task)
+        .map(|job| {
+            let sets = overrides(&axes, &job.settings);
+            match run_combination(&manifest.config, &sets, &mut StdRng::seed_from_u64(job.seed)) {
+                Ok(outcome) => JobResult{ index: job.index, outcome: Some(outcome), error: None },
+                Err(e) => JobResult{ index: job.index, outcome: None, error: Some(e.to_string()) },
+            }
+        })
+        .collect();
+    let result = TaskResult{ manifest: fingerprint, version: env!("CARGO_PKG_VERSION").to_string(), task, jobs };
+    fs::write(task_file(dir, task), toml::to_string(&result)?)?;
+    Ok(result)
+}
+
+
+/// merge the results of every task of the job prepared in `dir`, one row
+/// per run in the order of the manifest, its settings followed by the
+/// replicate and the seed
+///
+/// Fails, naming them, if some tasks have not written their results, and
+/// if any results were made for another array.toml or by another version
+/// than the one that prepared the job, so the table has one provenance.
+pub fn collect(dir: &Path) -> Result<(ArrayManifest, Vec<SweepRow>), Box<dyn Error>> {
+    let (manifest, expected) = read_manifest(dir)?;
+    let mut outcomes: Vec<Option<Result<SweepOutcome, String>>> = vec![None; manifest.jobs.len()];
+    let mut missing = Vec::new();
+    for task in 0..manifest.tasks {
+        if !task_file(dir, task).is_file() {
+            missing.push(task.to_string());
+            continue
+        }
+        let result = TaskResult::read(dir, task)?;
+        if result.manifest != expected {
+            return Err(format!("{} was made for another array.toml; run task {} again", task_file(dir, task).display(), task).into())
+        }
+        if result.version != manifest.version {
+            return Err(format!(
+                "task {} ran version {} of the crate, but the job was prepared with {}",
+                task, result.version, manifest.version,
+            ).into())
+        }
+        for job in result.jobs {
+            if manifest.jobs.get(job.index).is_none_or(|j| j.task != task) {
+                return Err(format!("task {} reports run {}, which is not one of its runs", task, job.index).into())
+            }
+            outcomes[job.index] = Some(match (job.outcome, job.error) {
+                (Some(outcome), _) => Ok(outcome),
+                (None, error) => Err(error.unwrap_or_else(|| "no outcome recorded".to_string())),
+            });
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!("tasks {} have not written their results", missing.join(", ")).into())
+    }
+    let rows = manifest.jobs.iter()
+        .zip(outcomes)
+        .map(|(job, outcome)| {
+            let mut settings = job.settings.clone();
+            settings.extend([job.replicate.to_string(), job.seed.to_string()]);
+            let outcome = outcome.unwrap_or_else(|| Err(format!("task {} did not report this run", job.task)));
+            SweepRow{ settings, outcome }
+        })
+        .collect();
+    Ok((manifest, rows))
+}
+
+
+/// the axes of the collected table: those of the sweep, then the
+/// replicate and the seed, for `sweep::write_table`
+pub fn collected_axes(manifest: &ArrayManifest) -> Vec<Axis> {
+    manifest.keys.iter()
+        .cloned()
+        .chain(["replicate".to_string(), "seed".to_string()])
+        .map(|key| Axis{ key, values: Vec::new() })
+        .collect()
+}
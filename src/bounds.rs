@@ -0,0 +1,366 @@
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::dist::{ln_gamma, Normal};
+use crate::evidence::log_add_exp;
+use crate::geometry::Whitening;
+use crate::linalg::Matrix;
+use crate::priors::Prior;
+use crate::stats::weighted_quantile;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priors::NormalPrior;
+
+    #[test]
+    fn test_restricting_to_learned_bounds_keeps_the_prior_inside() {
+        // a posterior N((1, -2), diag(0.1^2, 0.2^2)) as equally weighted draws
+        let mut rng = StdRng::seed_from_u64(430);
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        let n = 4000;
+        let posterior: Vec<(Vec<f64>, f64)> = (0..n)
+            .map(|_| (vec![1.0 + 0.1 * unit.sample(&mut rng), -2.0 + 0.2 * unit.sample(&mut rng)], -(n as f64).ln()))
+            .collect();
+        let bounds = learn_bounds(&posterior, 0.999, 1.2).unwrap();
+        assert!(bounds.lower[0] < 0.7 && bounds.upper[0] > 1.3 && bounds.upper[0] < 1.8, "{:?}", bounds);
+        assert!(bounds.lower[1] < -2.6 && bounds.lower[1] > -3.6, "{:?}", bounds);
+        let inside = posterior.iter().filter(|(t, _)| bounds.contains(t, Shape::Ellipsoid)).count();
+        assert!(inside as f64 > 0.998 * n as f64);
+
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[2.0, 2.0]).unwrap());
+        for shape in [Shape::Box, Shape::Ellipsoid] {
+            let restricted = Restricted::new(Arc::clone(&prior), &bounds, shape).unwrap();
+            // the prior mass of the region, counted among prior draws
+            let mut theta = vec![0.0; 2];
+            let draws = 200_000;
+            let inside = (0..draws).filter(|_| {
+                prior.sample_into(&mut theta, &mut rng);
+                bounds.contains(&theta, shape)
+            }).count();
+            let mass = (inside as f64 / draws as f64).ln();
+            assert!((restricted.log_mass() - mass).abs() < 0.05, "{:?}: {} vs {}", shape, restricted.log_mass(), mass);
+            let mut mean = 0.0;
+            for _ in 0..2000 {
+                restricted.sample_into(&mut theta, &mut rng);
+                assert!(restricted.log_density(&theta).is_finite());
+                mean += theta[0] / 2000.0;
+            }
+            assert!(mean < 1.05 && mean > 0.9, "{}", mean);
+            assert_eq!(restricted.log_density(&[0.0, 0.0]), f64::NEG_INFINITY);
+        }
+
+        let text = toml::to_string_pretty(&bounds).unwrap();
+        let back: Bounds = toml::from_str(&text).unwrap();
+        assert_eq!(back.radius, bounds.radius);
+    }
+}
+
+
+/// which region of a bounds file restricts the prior
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    #[default]
+    Box,
+    Ellipsoid,
+}
+
+
+/// settings of the bounds exported after a run
+///
+/// Fields:
+/// file: TOML file the bounds are written to
+/// mass: posterior mass the bulk holds
+/// expand: factor the box and ellipsoid are widened by around their
+///     centres, as a margin for the posterior of a similar dataset
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BoundsExport {
+    pub file: PathBuf,
+    pub mass: f64,
+    pub expand: f64,
+}
+
+
+impl Default for BoundsExport {
+    fn default() -> BoundsExport {
+        BoundsExport{ file: PathBuf::new(), mass: 0.999, expand: 1.2 }
+    }
+}
+
+
+/// settings of a run restricted to previously learned bounds
+///
+/// Fields:
+/// file: bounds file of the previous run
+/// shape: restrict to its box or to its ellipsoid
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RestrictConfig {
+    pub file: PathBuf,
+    pub shape: Shape,
+}
+
+
+/// a region holding the bulk of a posterior, written after one run and
+/// read by follow-up runs on similar data
+///
+/// Fields:
+/// mass: posterior mass of the bulk
+/// lower, upper: the smallest box around the bulk, widened
+/// mean, cov: centre and covariance of the posterior
+/// radius: the ellipsoid holds the points within this Mahalanobis
+///     distance of the mean
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Bounds {
+    pub mass: f64,
+    pub lower: Vec<f64>,
+    pub upper: Vec<f64>,
+    pub mean: Vec<f64>,
+    pub cov: Vec<Vec<f64>>,
+    pub radius: f64,
+}
+
+
+impl Bounds {
+    pub fn load(path: &Path) -> Result<Bounds, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn whitening(&self) -> Result<Whitening, Box<dyn Error>> {
+        Ok(Whitening::new(self.mean.clone(), &Matrix::from_rows(self.cov.clone()))
+            .ok_or("the covariance of the bounds is not positive definite")?)
+    }
+
+    /// whether theta lies in the box or the ellipsoid
+    pub fn contains(&self, theta: &[f64], shape: Shape) -> bool {
+        match self.whitening() {
+            Ok(whitening) => self.inside(theta, shape, &whitening),
+            Err(_) => false,
+        }
+    }
+
+    fn inside(&self, theta: &[f64], shape: Shape, whitening: &Whitening) -> bool {
+        match shape {
+            Shape::Box => theta.iter().zip(&self.lower).zip(&self.upper).all(|((t, lo), hi)| lo <= t && t <= hi),
+            Shape::Ellipsoid => whitening.whiten(theta).iter().map(|z| z * z).sum::<f64>() <= self.radius * self.radius,
+        }
+    }
+}
+
+
+/// the box and ellipsoid around the bulk of a weighted posterior
+///
+/// The ellipsoid follows the posterior covariance, with the radius that
+/// takes in `mass` of the weight; the box is the smallest one around the
+/// points inside it. Both are then widened by `expand`.
+pub fn learn_bounds(posterior: &[(Vec<f64>, f64)], mass: f64, expand: f64) -> Result<Bounds, Box<dyn Error>> {
+    if !(mass > 0.0 && mass <= 1.0) || expand < 1.0 {
+        return Err(format!("bounds need a mass in (0, 1] and expand >= 1, not {} and {}", mass, expand).into())
+    }
+    let dim = posterior.first().ok_or("there is no posterior to learn bounds from")?.0.len();
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let total: f64 = weights.iter().sum();
+    let mut mean = vec![0.0; dim];
+    for ((theta, _), w) in posterior.iter().zip(&weights) {
+        mean.iter_mut().zip(theta).for_each(|(m, t)| *m += w * t / total);
+    }
+    let mut cov = vec![vec![0.0; dim]; dim];
+    for ((theta, _), w) in posterior.iter().zip(&weights) {
+        for i in 0..dim {
+            for j in 0..dim {
+                cov[i][j] += w * (theta[i] - mean[i]) * (theta[j] - mean[j]) / total;
+            }
+        }
+    }
+    let whitening = Whitening::new(mean.clone(), &Matrix::from_rows(cov.clone()))
+        .ok_or("the posterior covariance is not positive definite")?;
+    let distances: Vec<(f64, f64)> = posterior.iter().zip(&weights)
+        .map(|((theta, _), w)| (whitening.whiten(theta).iter().map(|z| z * z).sum::<f64>().sqrt(), *w))
+        .collect();
+    let bulk = weighted_quantile(&distances, mass);
+    let (mut lower, mut upper) = (vec![f64::INFINITY; dim], vec![f64::NEG_INFINITY; dim]);
+    for ((theta, _), (d, _)) in posterior.iter().zip(&distances) {
+        if *d <= bulk {
+            for j in 0..dim {
+                lower[j] = lower[j].min(theta[j]);
+                upper[j] = upper[j].max(theta[j]);
+            }
+        }
+    }
+    for (lo, hi) in lower.iter_mut().zip(upper.iter_mut()) {
+        let (centre, half) = (0.5 * (*lo + *hi), 0.5 * expand * (*hi - *lo));
+        (*lo, *hi) = (centre - half, centre + half);
+    }
+    Ok(Bounds{ mass, lower, upper, mean, cov, radius: expand * bulk })
+}
+
+
+/// uniform draws in the region that measure the prior mass inside it
+const REGION_DRAWS: usize = 20000;
+
+
+/// a prior restricted to the bounds learned from an earlier posterior
+///
+/// New points are drawn uniformly in the region and kept with probability
+/// prior / bound, where the bound is twice the largest prior density seen
+/// among REGION_DRAWS uniform draws, so a prior that peaks sharply inside
+/// the region is sampled only approximately. The same draws measure the
+/// prior mass of the region, which the density is divided by; the
+/// evidence of the restricted run plus log_mass is the evidence of the
+/// full prior as long as the likelihood outside the region is negligible.
+///
+/// Fields:
+/// prior: the unrestricted prior
+/// bounds, shape: the region
+/// whitening: maps the ellipsoid onto the ball of radius bounds.radius
+/// log_bound: log of the envelope of the prior density in the region
+/// log_mass: log of the prior mass inside the region
+pub struct Restricted {
+    prior: Arc<dyn Prior>,
+    bounds: Bounds,
+    shape: Shape,
+    whitening: Whitening,
+    log_bound: f64,
+    log_mass: f64,
+}
+
+
+impl Restricted {
+    pub fn new(prior: Arc<dyn Prior>, bounds: &Bounds, shape: Shape) -> Result<Restricted, Box<dyn Error>> {
+        if bounds.lower.len() != prior.dim() {
+            return Err(format!("the bounds have {} parameters but the prior has {}", bounds.lower.len(), prior.dim()).into())
+        }
+        let mut restricted = Restricted{
+            prior,
+            bounds: bounds.clone(),
+            shape,
+            whitening: bounds.whitening()?,
+            log_bound: 0.0,
+            log_mass: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut theta = vec![0.0; restricted.dim()];
+        let mut log_sum = f64::NEG_INFINITY;
+        let mut log_max = f64::NEG_INFINITY;
+        for _ in 0..REGION_DRAWS {
+            restricted.uniform(&mut theta, &mut rng);
+            let log_p = restricted.prior.log_density(&theta);
+            log_sum = log_add_exp(log_sum, log_p);
+            log_max = log_max.max(log_p);
+        }
+        if log_max == f64::NEG_INFINITY {
+            return Err("the prior has no mass inside the bounds".into())
+        }
+        restricted.log_bound = log_max + 2f64.ln();
+        restricted.log_mass = restricted.log_region_volume() + log_sum - (REGION_DRAWS as f64).ln();
+        Ok(restricted)
+    }
+
+    /// log of the prior mass inside the region, the term that turns the
+    /// restricted evidence into that of the full prior
+    pub fn log_mass(&self) -> f64 {
+        self.log_mass
+    }
+
+    fn log_region_volume(&self) -> f64 {
+        let d = self.dim() as f64;
+        match self.shape {
+            Shape::Box => self.bounds.lower.iter().zip(&self.bounds.upper).map(|(lo, hi)| (hi - lo).ln()).sum(),
+            Shape::Ellipsoid => {
+                0.5 * d * std::f64::consts::PI.ln() - ln_gamma(0.5 * d + 1.0) + d * self.bounds.radius.ln()
+                    + 0.5 * self.whitening.chol().ln_det()
+            },
+        }
+    }
+
+    /// overwrite theta with a uniform draw in the region
+    fn uniform(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        match self.shape {
+            Shape::Box => {
+                for ((t, lo), hi) in theta.iter_mut().zip(&self.bounds.lower).zip(&self.bounds.upper) {
+                    *t = lo + (hi - lo) * rng.gen::<f64>();
+                }
+            },
+            Shape::Ellipsoid => {
+                let unit = Normal::new(0.0, 1.0).unwrap();
+                let mut z: Vec<f64> = (0..theta.len()).map(|_| unit.sample(rng)).collect();
+                let norm = z.iter().map(|v| v * v).sum::<f64>().sqrt();
+                let r = self.bounds.radius * rng.gen::<f64>().powf(1.0 / theta.len() as f64) / norm;
+                z.iter_mut().for_each(|v| *v *= r);
+                theta.copy_from_slice(&self.whitening.unwhiten(&z));
+            },
+        }
+    }
+}
+
+
+impl Debug for Restricted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Restricted")
+            .field("prior", &self.prior)
+            .field("shape", &self.shape)
+            .field("log_mass", &self.log_mass)
+            .finish()
+    }
+}
+
+
+impl Prior for Restricted {
+    fn dim(&self) -> usize {
+        self.prior.dim()
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        loop {
+            self.uniform(theta, rng);
+            if rng.gen::<f64>().ln() < self.prior.log_density(theta) - self.log_bound {
+                return
+            }
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        match self.bounds.inside(theta, self.shape, &self.whitening) {
+            true => self.prior.log_density(theta) - self.log_mass,
+            false => f64::NEG_INFINITY,
+        }
+    }
+
+    /// the prior's widths, capped by the region's
+    fn scale(&self) -> Vec<f64> {
+        let d = self.dim() as f64;
+        self.prior.scale().iter().enumerate().map(|(j, s)| {
+            let region = match self.shape {
+                Shape::Box => (self.bounds.upper[j] - self.bounds.lower[j]) / 12f64.sqrt(),
+                Shape::Ellipsoid => self.bounds.radius * (self.bounds.cov[j][j] / (d + 2.0)).sqrt(),
+            };
+            s.min(region)
+        }).collect()
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        let mut stats = self.prior.stats();
+        stats.push(("bounds_log_mass".to_string(), self.log_mass));
+        stats
+    }
+
+    fn max_log_density(&self) -> Option<f64> {
+        self.prior.max_log_density().map(|m| m - self.log_mass)
+    }
+}
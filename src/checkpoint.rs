@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::Particles;
+use crate::diagnostics::ShrinkageTrace;
+use crate::evidence::{Evidence, Shrinkage};
+use crate::sampler::SamplerState;
+
+
+/// format version written into every checkpoint; files of another
+/// version are refused rather than misread
+pub const CHECKPOINT_VERSION: u32 = 12;
+
+
+/// the first bytes of every checkpoint since version 11
+const MAGIC: &[u8; 8] = b"NSCHKPT\0";
+
+
+/// magic bytes and the version, ahead of the state
+const HEADER_LEN: usize = 12;
+
+
+/// the encoding of the state: little-endian, fixed-width integers, usize
+/// as u64 and floats as their IEEE bits, so that a checkpoint reads the
+/// same on every architecture
+fn encoding() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding().with_little_endian()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums_catch_damaged_files() {
+        // the standard check value of CRC-32
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+
+        let path = std::env::temp_dir().join(format!("ns_checkpoint_{}.bin", std::process::id()));
+        let error = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            Checkpoint::read(&path).unwrap_err().to_string()
+        };
+        let framed = |version: u32, payload: &[u8]| {
+            let mut bytes = [MAGIC.as_slice(), &version.to_le_bytes(), payload].concat();
+            bytes.extend(crc32(&bytes).to_le_bytes());
+            bytes
+        };
+        assert!(error(b"xy").contains("truncated"));
+        assert!(error(b"an old checkpoint without a header").contains("no checkpoint header"));
+        let future = error(&framed(CHECKPOINT_VERSION + 1, b"state"));
+        assert!(future.contains(&format!("unsupported version {}", CHECKPOINT_VERSION + 1)), "{}", future);
+        let mut damaged = framed(CHECKPOINT_VERSION, b"state");
+        damaged[HEADER_LEN] ^= 1;
+        assert!(error(&damaged).contains("checksum"));
+        assert!(error(&framed(CHECKPOINT_VERSION, b"state")).contains("not a readable checkpoint"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+
+/// settings of checkpointing in run directories
+///
+/// Fields:
+/// keep: number of most recent checkpoints kept; older ones are deleted
+///     as new ones are written
+/// interval_secs: also write a checkpoint when this many seconds have
+///     passed since the last one, whatever checkpoint_every says
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CheckpointConfig {
+    pub keep: usize,
+    pub interval_secs: Option<u64>,
+}
+
+
+impl Default for CheckpointConfig {
+    fn default() -> CheckpointConfig {
+        CheckpointConfig{ keep: 3, interval_secs: None }
+    }
+}
+
+
+/// CRC-32 (IEEE 802.3, as in zip and png) of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+
+/// the state of a static run after some iterations, enough to carry on
+/// as if it had not stopped (apart from the random numbers)
+///
+/// Fields:
+/// iteration: iterations completed
+/// particles: the live and dead particles
+/// evidence: the evidence accumulated from the dead particles
+/// shrinkage: the prior-volume shrinkage so far
+/// trace: per-iteration shrinkage and insertion ranks
+/// sampler: the adapted sampler settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub iteration: usize,
+    pub(crate) particles: Particles,
+    pub(crate) evidence: Evidence,
+    pub(crate) shrinkage: Shrinkage,
+    pub(crate) trace: ShrinkageTrace,
+    pub(crate) sampler: SamplerState,
+}
+
+
+impl Checkpoint {
+    pub(crate) fn new(
+            iteration: usize,
+            particles: Particles,
+            evidence: Evidence,
+            shrinkage: Shrinkage,
+            trace: ShrinkageTrace,
+            sampler: SamplerState,
+    ) -> Checkpoint {
+        Checkpoint{ iteration, particles, evidence, shrinkage, trace, sampler }
+    }
+
+    /// file name of the checkpoint after `iteration` iterations; the
+    /// padding makes the names sort in iteration order
+    pub fn file_name(iteration: usize) -> String {
+        format!("checkpoint-{:010}.bin", iteration)
+    }
+
+    /// write to a temporary file, flush it to disk and move it into
+    /// place, so that a run killed while writing leaves the previous
+    /// checkpoints intact
+    ///
+    /// The file holds MAGIC, the version as a little-endian u32, the state
+    /// in the portable `encoding` and a CRC-32 of everything before it.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = path.with_extension("tmp");
+        let mut bytes = [MAGIC.as_slice(), &CHECKPOINT_VERSION.to_le_bytes()].concat();
+        encoding().serialize_into(&mut bytes, self)?;
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(&bytes)?;
+        out.write_all(&crc32(&bytes).to_le_bytes())?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        drop(out);
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// read a checkpoint written on any architecture by a build with the
+    /// same CHECKPOINT_VERSION. Other versions are refused by number before
+    /// their state is decoded, and files whose checksum does not match,
+    /// e.g. those of a disk that filled up or a copy cut short, are refused
+    pub fn read(path: &Path) -> Result<Checkpoint, Box<dyn Error>> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < HEADER_LEN + 4 {
+            return Err(format!("{} is truncated", path.display()).into())
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(format!(
+                "{} has no checkpoint header: it is not a checkpoint, or one of a version before 11, which this \
+                 build cannot read",
+                path.display(),
+            ).into())
+        }
+        let version = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_LEN].try_into()?);
+        if version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "{} is a checkpoint of unsupported version {}; this build reads version {}",
+                path.display(), version, CHECKPOINT_VERSION,
+            ).into())
+        }
+        let (framed, sum) = bytes.split_at(bytes.len() - 4);
+        if crc32(framed).to_le_bytes() != sum {
+            return Err(format!("{} is damaged: its checksum does not match", path.display()).into())
+        }
+        let checkpoint: Checkpoint = encoding().deserialize(&framed[HEADER_LEN..])
+            .map_err(|e| format!("{} is not a readable checkpoint: {}", path.display(), e))?;
+        Ok(checkpoint)
+    }
+}
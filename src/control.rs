@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Deserialize;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_file_changes_are_acted_on_once() {
+        let path = std::env::temp_dir().join(format!("ns_control_{}.toml", std::process::id()));
+        let mut control = ControlFile::new(path.clone(), Duration::ZERO);
+        assert_eq!(control.poll().unwrap(), None);
+
+        std::fs::write(&path, "checkpoint = true\ntolerance = 0.001\n").unwrap();
+        let first = control.poll().unwrap().unwrap();
+        assert!(first.checkpoint && !first.stop);
+        assert_eq!(first.tolerance, Some(0.001));
+        // unchanged, so the checkpoint is not requested again
+        assert_eq!(control.poll().unwrap(), None);
+
+        std::fs::write(&path, "stop = true\n").unwrap();
+        assert!(control.poll().unwrap().unwrap().stop);
+        std::fs::write(&path, "stpo = true\n").unwrap();
+        assert!(control.poll().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+
+/// instructions to a running job, written to `control.toml` in its run
+/// directory while it runs
+///
+/// Fields:
+/// sample_num: new cap on the iterations
+/// tolerance: new stopping tolerance (see `Config::tolerance`)
+/// checkpoint: write a checkpoint at the next iteration
+/// stop: checkpoint and finish at the next iteration, writing the outputs
+///     of the run so far as if it had reached sample_num
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Control {
+    pub sample_num: Option<usize>,
+    pub tolerance: Option<f64>,
+    pub checkpoint: bool,
+    pub stop: bool,
+}
+
+
+/// seconds between looks at the control file of a running job
+pub const CONTROL_POLL_SECS: u64 = 5;
+
+
+/// the control file of a run, looked at no more often than every `every`
+///
+/// Fields:
+/// path: the file
+/// every: time between looks
+/// last_poll: when it was last looked at
+/// seen: modification time and contents last acted on
+#[derive(Debug)]
+pub struct ControlFile {
+    path: PathBuf,
+    every: Duration,
+    last_poll: Option<Instant>,
+    seen: Option<(SystemTime, String)>,
+}
+
+
+impl ControlFile {
+    pub fn new(path: PathBuf, every: Duration) -> ControlFile {
+        ControlFile{ path, every, last_poll: None, seen: None }
+    }
+
+    /// the instructions in the file if it was written since the last look,
+    /// None if it is absent, unchanged or not due for a look. Rewriting the
+    /// file, even with the same contents, repeats its instructions
+    pub fn poll(&mut self) -> Result<Option<Control>, Box<dyn Error>> {
+        if self.last_poll.is_some_and(|t| t.elapsed() < self.every) {
+            return Ok(None)
+        }
+        self.last_poll = Some(Instant::now());
+        let modified = match std::fs::metadata(&self.path) {
+            Ok(meta) => meta.modified()?,
+            Err(_) => return Ok(None),
+        };
+        let text = std::fs::read_to_string(&self.path)?;
+        let current = (modified, text);
+        if self.seen.as_ref() == Some(&current) {
+            return Ok(None)
+        }
+        let control = toml::from_str(&current.1).map_err(|e| format!("{}: {}", self.path.display(), e));
+        self.seen = Some(current);
+        Ok(Some(control?))
+    }
+}
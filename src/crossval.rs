@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::data::Dataset;
+use crate::evidence::log_add_exp;
+use crate::observer::{Collect, Observer};
+use crate::{build_model, run_with_data, Config};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::linalg::Matrix;
+    use crate::sampler::{Method, SamplerConfig};
+
+    #[test]
+    fn test_folds_cover_the_data_once() {
+        let x: Vec<f64> = (0..20).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let config = Config{
+            sample_num: 300,
+            particle_num: 30,
+            mu: vec![0.0, 0.0],
+            sd: vec![5.0, 5.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(456);
+        let report = crossval(&config, &CrossvalConfig{ folds: 4 }, &data, &mut Collect::default(), &mut rng).unwrap();
+        assert_eq!(report.folds.len(), 4);
+        assert_eq!(report.folds.iter().map(|f| f.n_test).sum::<usize>(), 20);
+        assert!(report.folds.iter().all(|f| f.n_train + f.n_test == 20));
+        assert_eq!(report.score(), report.folds.iter().map(|f| f.log_pred).sum::<f64>());
+        // the exact score: the shuffle is crossval's first draw, and the
+        // conjugate posterior of the line predicts the held-out rows as
+        // N(X_t m, 0.25 I + X_t S X_t'). The spread S of the line costs
+        // about 1.2 nats over the density of the noise at zero
+        let (x, y) = (data.column(0), data.column(1));
+        let mut order: Vec<usize> = (0..20).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(456));
+        let design = |rows: &[usize]| Matrix::from_rows(rows.iter().map(|&i| vec![1.0, x[i]]).collect());
+        let exact: f64 = (0..4)
+            .map(|k| {
+                let test: Vec<usize> = order.iter().skip(k).step_by(4).copied().collect();
+                let train: Vec<usize> = (0..20).filter(|i| !test.contains(i)).collect();
+                let (fit, held) = (design(&train), design(&test));
+                let precision = fit.transpose().mul(&fit).scale(4.0).add(&Matrix::identity(2).scale(1.0 / 25.0));
+                let posterior = precision.cholesky().unwrap();
+                let y_train: Vec<f64> = train.iter().map(|&i| 4.0 * y[i]).collect();
+                let mean = posterior.solve(&fit.transpose().mul_vec(&y_train));
+                let cov = Matrix::identity(test.len()).scale(0.25).add(&held.mul(&posterior.inverse()).mul(&held.transpose()));
+                let resid: Vec<f64> = test.iter().zip(held.mul_vec(&mean)).map(|(&i, f)| y[i] - f).collect();
+                let predictive = cov.cholesky().unwrap();
+                -0.5 * (test.len() as f64 * (2.0 * PI).ln() + predictive.ln_det() + predictive.quad_form(&resid))
+            })
+            .sum();
+        let at_zero = -20.0 * 0.5 * (2.0 * PI * 0.25).ln();
+        assert!(exact < at_zero - 1.0);
+        assert!((report.score() - exact).abs() < 0.2, "{} {}", report.score(), exact);
+        assert!(report.score_err() >= 0.0);
+
+        for folds in [1, 21] {
+            assert!(crossval(&config, &CrossvalConfig{ folds }, &data, &mut Collect::default(), &mut rng).is_err());
+        }
+    }
+}
+
+
+/// settings of cross-validation
+///
+/// Fields:
+/// folds: the parts the rows are split into; each is held out once while
+///     the others are fitted
+#[derive(Debug, Clone)]
+pub struct CrossvalConfig {
+    pub folds: usize,
+}
+
+
+impl Default for CrossvalConfig {
+    fn default() -> CrossvalConfig {
+        CrossvalConfig{ folds: 5 }
+    }
+}
+
+
+/// the outcome of one fold
+///
+/// Fields:
+/// fold: index of the held-out part
+/// n_train, n_test: rows fitted and held out
+/// log_z, log_z_err: evidence of the training rows
+/// log_pred: log posterior predictive density of the held-out rows,
+///     log of the posterior mean of their likelihood
+#[derive(Debug, Clone)]
+pub struct FoldScore {
+    pub fold: usize,
+    pub n_train: usize,
+    pub n_test: usize,
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub log_pred: f64,
+}
+
+
+/// the outcome of cross-validation
+///
+/// Fields:
+/// folds: the score of every fold
+/// log_z, log_z_err: evidence of the full data, for comparison
+#[derive(Debug, Clone)]
+pub struct CrossvalReport {
+    pub folds: Vec<FoldScore>,
+    pub log_z: f64,
+    pub log_z_err: f64,
+}
+
+
+impl CrossvalReport {
+    /// the cross-validated score, the summed log predictive density of
+    /// every row when it was held out; higher is better
+    pub fn score(&self) -> f64 {
+        self.folds.iter().map(|f| f.log_pred).sum()
+    }
+
+    /// standard error of the score from the scatter of the fold scores
+    pub fn score_err(&self) -> f64 {
+        let k = self.folds.len() as f64;
+        let mean = self.score() / k;
+        let var = self.folds.iter().map(|f| (f.log_pred - mean).powi(2)).sum::<f64>() / (k - 1.0);
+        (k * var).sqrt()
+    }
+}
+
+
+/// the given rows of a dataset, in memory
+fn select_rows(data: &Dataset, rows: &[usize]) -> Result<Dataset, Box<dyn Error>> {
+    let columns = (0..data.ncols())
+        .map(|j| {
+            let column = data.column(j);
+            rows.iter().map(|&i| column[i]).collect()
+        })
+        .collect();
+    Dataset::from_columns(columns, Some(data.names().to_vec()))
+}
+
+
+/// k-fold cross-validation of the config on `data`
+///
+/// The rows are shuffled and dealt into `settings.folds` parts. Each part
+/// is held out in turn, the config is run on the rest, and the held-out
+/// rows are scored by their likelihood averaged over that posterior. The
+/// evidence scores a model by how well the prior predicts the data; the
+/// summed held-out scores measure how well the fitted model predicts new
+/// data, which depends far less on how wide the prior is. The full data
+/// is run as well, so the report has both. Warnings of the runs are passed
+/// on to `observer`.
+pub fn crossval<R: Rng>(
+        config: &Config,
+        settings: &CrossvalConfig,
+        data: &Dataset,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<CrossvalReport, Box<dyn Error>> {
+    let n = data.nrows();
+    if settings.folds < 2 || settings.folds > n {
+        return Err(format!("cannot split {} rows into {} folds; use 2 to {}", n, settings.folds, n).into())
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+
+    let mut folds = Vec::with_capacity(settings.folds);
+    for k in 0..settings.folds {
+        let (mut train, mut test) = (Vec::new(), Vec::new());
+        for (i, &row) in order.iter().enumerate() {
+            match i % settings.folds == k {
+                true => test.push(row),
+                false => train.push(row),
+            }
+        }
+        train.sort_unstable();
+        test.sort_unstable();
+        let train_data = select_rows(data, &train)?;
+        let test_data = select_rows(data, &test)?;
+
+        let mut warnings = Collect::default();
+        let result = run_with_data(config, &train_data, &mut warnings, rng)?;
+        for warning in warnings.warnings {
+            observer.warn(&format!("fold {}: {}", k, warning));
+        }
+        let held_out = build_model(config, &test_data)?;
+        let log_pred = result.posterior.iter()
+            .filter(|(_, lw)| *lw > f64::NEG_INFINITY)
+            .fold(f64::NEG_INFINITY, |acc, (theta, lw)| log_add_exp(acc, lw + held_out.log_lik(theta)));
+        folds.push(FoldScore{
+            fold: k,
+            n_train: train.len(),
+            n_test: test.len(),
+            log_z: result.log_z,
+            log_z_err: result.log_z_err,
+            log_pred,
+        });
+    }
+
+    let mut warnings = Collect::default();
+    let full = run_with_data(config, data, &mut warnings, rng)?;
+    for warning in warnings.warnings {
+        observer.warn(&format!("full data: {}", warning));
+    }
+    Ok(CrossvalReport{ folds, log_z: full.log_z, log_z_err: full.log_z_err })
+}
+
+
+/// write one row per fold
+pub fn write_folds(path: &Path, report: &CrossvalReport) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "fold,n_train,n_test,log_z,log_z_err,log_pred")?;
+    for f in &report.folds {
+        writeln!(out, "{},{},{},{:e},{:e},{:e}", f.fold, f.n_train, f.n_test, f.log_z, f.log_z_err, f.log_pred)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::dynamic::{live_counts, DeadPoint};
+use crate::evidence::log_add_exp;
+use crate::RunResult;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_function_of_a_gaussian_peak() {
+        // L = exp(-r^2 / 2) in two dimensions under a uniform prior of
+        // area A: X(r) = pi r^2 / A, so log L = -A X / (2 pi) and
+        // Z(beta) = 2 pi (1 - exp(-beta A / (2 pi))) / (beta A)
+        let area: f64 = 100.0;
+        let c = area / (2.0 * std::f64::consts::PI);
+        let log_x: Vec<f64> = (1..3000).map(|i| -(i as f64) / 100.0).collect();
+        let log_l: Vec<f64> = log_x.iter().map(|lx| -c * lx.exp()).collect();
+        let curve = LikelihoodCurve::new(log_x, log_l).unwrap();
+        for beta in [0.0, 0.1, 0.5, 1.0, 3.0] {
+            let exact = match beta {
+                0.0 => 0.0,
+                _ => ((1.0 - (-beta * c).exp()) / (beta * c)).ln(),
+            };
+            assert!((curve.log_z(beta) - exact).abs() < 1e-3, "beta {}: {} against {}", beta, curve.log_z(beta), exact);
+        }
+
+        // the spline passes through the points and keeps log L monotone between them
+        assert_eq!(curve.log_l_at(-1.0), -c * (-1.0f64).exp());
+        let between: Vec<f64> = (0..50).map(|k| curve.log_l_at(-1.0 - k as f64 * 0.001)).collect();
+        assert!(between.windows(2).all(|w| w[1] >= w[0]));
+        assert!((curve.log_l_at(-1.0055) + c * (-1.0055f64).exp()).abs() < 1e-5);
+        assert_eq!(curve.log_l_at(0.0), curve.log_l()[0]);
+        // d/d beta of the log of Z(beta) above, at beta = 1
+        let mean = c * (-c).exp() / (1.0 - (-c).exp()) - 1.0;
+        assert!((curve.mean_log_l(1.0) - mean).abs() < 1e-3, "{} {}", curve.mean_log_l(1.0), mean);
+
+        assert!(LikelihoodCurve::new(vec![-1.0, -0.5], vec![0.0, 1.0]).is_err());
+        assert!(LikelihoodCurve::new(vec![-1.0, -2.0], vec![1.0, 0.0]).is_err());
+    }
+
+    #[test]
+    fn test_curve_of_dead_points() {
+        // three points born from the prior, then one born above the lowest
+        let point = |log_l: f64, log_l_birth: f64| DeadPoint{ theta: vec![], log_l, log_l_birth };
+        let mut points = vec![
+            point(2.0, f64::NEG_INFINITY), point(0.0, f64::NEG_INFINITY),
+            point(3.0, 0.0), point(1.0, f64::NEG_INFINITY),
+        ];
+        let curve = LikelihoodCurve::from_dead_points(&mut points).unwrap();
+        assert_eq!(curve.log_l(), &[0.0, 1.0, 2.0, 3.0]);
+        let expected = [-1.0 / 3.0, -1.0 / 3.0 - 1.0 / 3.0, -2.0 / 3.0 - 0.5, -2.0 / 3.0 - 0.5 - 1.0];
+        for (a, b) in curve.log_x().iter().zip(expected) {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        let path = std::env::temp_dir().join(format!("ns_curve_{}.csv", std::process::id()));
+        curve.write_csv(&path).unwrap();
+        let read = LikelihoodCurve::read_csv(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.log_l(), curve.log_l());
+        assert_eq!(read.log_z(1.0), curve.log_z(1.0));
+    }
+}
+
+
+/// nodes and weights of 5-point Gauss-Legendre quadrature on [-1, 1]
+const GAUSS_NODES: [(f64, f64); 5] = [
+    (0.0, 0.568_888_888_888_888_9),
+    (-0.538_469_310_105_683, 0.478_628_670_499_366_5),
+    (0.538_469_310_105_683, 0.478_628_670_499_366_5),
+    (-0.906_179_845_938_664, 0.236_926_885_056_189_1),
+    (0.906_179_845_938_664, 0.236_926_885_056_189_1),
+];
+
+
+/// the likelihood as a function of the prior volume it encloses, log L
+/// against log X, from the dead points of a run
+///
+/// Nested sampling reduces any integral over the prior of a function of L
+/// to one over X, so this one curve gives the partition function
+/// Z(beta) = int L^beta dX at every inverse temperature (Skilling 2006),
+/// and with it the mean energy and heat capacity, without new likelihood
+/// calls. log L is interpolated between the points by a monotone cubic
+/// (Fritsch and Carlson 1980) in log X, so that it never turns back.
+///
+/// Fields:
+/// log_x: expected log prior volume of each point, decreasing
+/// log_l: log-likelihood of each point, increasing
+/// slopes: d log L / d log X at each point, for the spline
+#[derive(Debug, Clone)]
+pub struct LikelihoodCurve {
+    log_x: Vec<f64>,
+    log_l: Vec<f64>,
+    slopes: Vec<f64>,
+}
+
+
+impl LikelihoodCurve {
+    /// the curve through points of decreasing log X and non-decreasing,
+    /// finite log L
+    pub fn new(log_x: Vec<f64>, log_l: Vec<f64>) -> Result<LikelihoodCurve, Box<dyn Error>> {
+        if log_x.len() != log_l.len() || log_x.len() < 2 {
+            return Err(format!("a curve needs at least two points, and as many log X ({}) as log L ({})", log_x.len(), log_l.len()).into())
+        }
+        if log_x[0] > 0.0 || log_x.windows(2).any(|w| w[1] >= w[0] || w[1].is_nan() || w[0].is_nan()) {
+            return Err("log X must decrease from at most 0".into())
+        }
+        if log_l.iter().any(|l| !l.is_finite()) || log_l.windows(2).any(|w| w[1] < w[0]) {
+            return Err("log L must be finite and increase as log X decreases".into())
+        }
+        let secant: Vec<f64> = (0..log_x.len() - 1)
+            .map(|k| (log_l[k + 1] - log_l[k]) / (log_x[k + 1] - log_x[k]))
+            .collect();
+        let mut slopes = Vec::with_capacity(log_x.len());
+        slopes.push(secant[0]);
+        for k in 1..log_x.len() - 1 {
+            let (d0, d1) = (secant[k - 1], secant[k]);
+            if d0 * d1 <= 0.0 {
+                slopes.push(0.0);
+            } else {
+                // the weighted harmonic mean keeps the cubic monotone
+                let (h0, h1) = (log_x[k - 1] - log_x[k], log_x[k] - log_x[k + 1]);
+                let (w0, w1) = (2.0 * h1 + h0, h1 + 2.0 * h0);
+                slopes.push((w0 + w1) / (w0 / d0 + w1 / d1));
+            }
+        }
+        slopes.push(secant[secant.len() - 1]);
+        Ok(LikelihoodCurve{ log_x, log_l, slopes })
+    }
+
+    /// the curve of dead points, each at the log X expected from the live
+    /// points around its contour; points of log L = -inf hold no
+    /// likelihood and only shrink the volume
+    pub fn from_dead_points(points: &mut [DeadPoint]) -> Result<LikelihoodCurve, Box<dyn Error>> {
+        let n_live = live_counts(points);
+        LikelihoodCurve::from_sorted(points, &n_live)
+    }
+
+    /// `from_dead_points` of points already sorted by `live_counts`
+    pub(crate) fn from_sorted(points: &[DeadPoint], n_live: &[usize]) -> Result<LikelihoodCurve, Box<dyn Error>> {
+        let mut log_x = 0.0;
+        let (mut xs, mut ls) = (Vec::with_capacity(points.len()), Vec::with_capacity(points.len()));
+        for (p, n) in points.iter().zip(n_live) {
+            log_x -= 1.0 / *n as f64;
+            if p.log_l.is_finite() {
+                xs.push(log_x);
+                ls.push(p.log_l);
+            }
+        }
+        LikelihoodCurve::new(xs, ls)
+    }
+
+    /// the curve of a nested sampling run; tempering and SMC runs keep no
+    /// dead points to make one from
+    pub fn from_result(result: &RunResult) -> Result<LikelihoodCurve, Box<dyn Error>> {
+        if result.dead_birth.len() != result.posterior.len() || result.dead_birth.is_empty() {
+            return Err("the run kept no dead points with birth contours".into())
+        }
+        let mut points: Vec<DeadPoint> = result.dead_birth.iter()
+            .map(|(log_l, log_l_birth)| DeadPoint{ theta: Vec::new(), log_l: *log_l, log_l_birth: *log_l_birth })
+            .collect();
+        LikelihoodCurve::from_dead_points(&mut points)
+    }
+
+    pub fn log_x(&self) -> &[f64] {
+        &self.log_x
+    }
+
+    pub fn log_l(&self) -> &[f64] {
+        &self.log_l
+    }
+
+    /// the interpolated log L at `log_x`; flat beyond the first and last
+    /// points
+    pub fn log_l_at(&self, log_x: f64) -> f64 {
+        let last = self.log_x.len() - 1;
+        if log_x >= self.log_x[0] {
+            return self.log_l[0]
+        }
+        if log_x <= self.log_x[last] {
+            return self.log_l[last]
+        }
+        // the interval [k, k + 1] with log_x[k] > log_x >= log_x[k + 1]
+        let k = self.log_x.partition_point(|x| *x > log_x) - 1;
+        let h = self.log_x[k + 1] - self.log_x[k];
+        let s = (log_x - self.log_x[k]) / h;
+        let (s2, s3) = (s * s, s * s * s);
+        (2.0 * s3 - 3.0 * s2 + 1.0) * self.log_l[k]
+            + (s3 - 2.0 * s2 + s) * h * self.slopes[k]
+            + (-2.0 * s3 + 3.0 * s2) * self.log_l[k + 1]
+            + (s3 - s2) * h * self.slopes[k + 1]
+    }
+
+    /// log of the partition function Z(beta) = int_0^1 L(X)^beta dX
+    ///
+    /// The integral over log X of L^beta X follows the spline between the
+    /// points, by Gauss-Legendre quadrature on each interval. Above the
+    /// first point L is taken as that point's, and the volume left below
+    /// the last point at its L, as the live points are retired. beta = 1
+    /// is the evidence; the larger beta, the more the result rests on the
+    /// few points near the peak, and the smaller, on the poorly resolved
+    /// low-likelihood start.
+    pub fn log_z(&self, beta: f64) -> f64 {
+        let last = self.log_x.len() - 1;
+        // (0, log X_0]: 1 - X_0 of volume at L_0
+        let mut log_z = beta * self.log_l[0] + (-self.log_x[0].exp_m1()).ln();
+        for k in 0..last {
+            let (a, b) = (self.log_x[k + 1], self.log_x[k]);
+            let half = 0.5 * (b - a);
+            for (node, weight) in GAUSS_NODES {
+                let t = a + half * (node + 1.0);
+                log_z = log_add_exp(log_z, (weight * half).ln() + beta * self.log_l_at(t) + t);
+            }
+        }
+        log_add_exp(log_z, beta * self.log_l[last] + self.log_x[last])
+    }
+
+    /// mean of log L under the posterior tempered to beta, the negative
+    /// of the mean energy, as d log Z / d beta by a central difference
+    pub fn mean_log_l(&self, beta: f64) -> f64 {
+        let h = 1e-4 * beta.abs().max(1.0);
+        (self.log_z(beta + h) - self.log_z(beta - h)) / (2.0 * h)
+    }
+
+    /// write `log_x,log_l` rows, the points the spline passes through
+    pub fn write_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "log_x,log_l")?;
+        for (x, l) in self.log_x.iter().zip(&self.log_l) {
+            writeln!(out, "{:e},{:e}", x, l)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// read a curve written by `write_csv`
+    pub fn read_csv(path: &Path) -> Result<LikelihoodCurve, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let (mut xs, mut ls) = (Vec::new(), Vec::new());
+        for (i, line) in text.lines().enumerate().skip(1).filter(|(_, l)| !l.trim().is_empty()) {
+            let row = line.split(',')
+                .map(|v| v.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| format!("line {} of {}: {}", i + 1, path.display(), e))?;
+            if row.len() != 2 {
+                return Err(format!("line {} of {} has {} columns, expected log_x and log_l", i + 1, path.display(), row.len()).into())
+            }
+            xs.push(row[0]);
+            ls.push(row[1]);
+        }
+        LikelihoodCurve::new(xs, ls)
+    }
+}
@@ -0,0 +1,509 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::iter::zip;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tmp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ns_data_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_csv_binary_round_trip() {
+        let csv = tmp_path("round_trip.csv");
+        let bin = tmp_path("round_trip.nsd");
+        fs::write(&csv, "t,y\n0.0,1.5\n1.0,2.5\n2.0,3.5\n").unwrap();
+
+        csv_to_binary(&csv, &bin).unwrap();
+        let data = Dataset::load(&bin).unwrap();
+        assert!(data.is_mapped());
+        assert_eq!(data.nrows(), 3);
+        assert_eq!(data.ncols(), 2);
+        assert_eq!(data.names(), &["t".to_string(), "y".to_string()]);
+        assert_eq!(data.column(0), &[0.0, 1.0, 2.0]);
+        assert_eq!(data.column_by_name("y").unwrap(), &[1.5, 2.5, 3.5]);
+
+        fs::remove_file(csv).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_header_is_rejected() {
+        let csv = tmp_path("corrupt.csv");
+        let bin = tmp_path("corrupt.nsd");
+        fs::write(&csv, "y\n1.0\n").unwrap();
+        csv_to_binary(&csv, &bin).unwrap();
+
+        // nrows = 2^61 with one column makes nrows * ncols * 8 wrap to 0
+        let mut bytes = fs::read(&bin).unwrap();
+        bytes[8..16].copy_from_slice(&(1u64 << 61).to_le_bytes());
+        bytes.truncate(HEADER_LEN + 8);
+        fs::write(&bin, &bytes).unwrap();
+        assert!(Dataset::open_binary(&bin).is_err());
+
+        fs::remove_file(csv).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_text_is_an_error() {
+        let err = Dataset::parse_text("x,y\n1.0,2.0\n3.0,,\n", "stray").unwrap_err();
+        assert!(matches!(err, DataError::Ragged{ row: 1, fields: 1, expected: 2, .. }), "{}", err);
+        let err = Dataset::parse_text("1.0 2.0\n3.0 4.0e\n", "typo").unwrap_err();
+        assert!(matches!(err, DataError::Parse{ row: 1, .. }), "{}", err);
+        assert_eq!(Dataset::parse_text("x,y,\n1.0,2.0,\n", "trailing").unwrap().ncols(), 2);
+    }
+
+    #[test]
+    fn test_every_truncated_binary_file_is_rejected() {
+        let data = Dataset::from_columns(vec![vec![1.0, 2.0], vec![3.0, 4.0]], None).unwrap();
+        let bin = tmp_path("truncated.nsd");
+        data.write_binary(&bin).unwrap();
+        let bytes = fs::read(&bin).unwrap();
+        let full = Dataset::from_binary_bytes(&bytes, "full").unwrap();
+        assert!(!full.is_mapped());
+        assert_eq!(full.column(1), &[3.0, 4.0]);
+        for len in 0..bytes.len() {
+            assert!(Dataset::from_binary_bytes(&bytes[..len], "truncated").is_err(), "{} bytes", len);
+        }
+        fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn test_empty_column_names_survive_the_binary_format() {
+        let names = vec!["".to_string(), "y".to_string(), "".to_string()];
+        let data = Dataset::from_columns(vec![vec![1.0], vec![2.0], vec![3.0]], Some(names.clone())).unwrap();
+        let bin = tmp_path("empty_names.nsd");
+        data.write_binary(&bin).unwrap();
+        assert_eq!(Dataset::open_binary(&bin).unwrap().names(), &names[..]);
+
+        let split = Dataset::from_columns(vec![vec![1.0]], Some(vec!["a\nb".to_string()])).unwrap();
+        assert!(split.write_binary(&bin).is_err());
+        fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_single_line() {
+        let path = tmp_path("legacy.txt");
+        fs::write(&path, "1.0 2.0 3.0\n").unwrap();
+        let data = Dataset::load(&path).unwrap();
+        assert_eq!(data.ncols(), 1);
+        assert_eq!(data.column(0), &[1.0, 2.0, 3.0]);
+        fs::remove_file(path).unwrap();
+    }
+}
+
+
+/// magic bytes at the start of every binary data file
+const MAGIC: &[u8; 8] = b"NSDATA01";
+/// size of the fixed part of the binary header: magic, nrows, ncols, names length
+const HEADER_LEN: usize = 32;
+/// file extension used to recognize the binary format in `Dataset::load`
+pub const BINARY_EXTENSION: &str = "nsd";
+
+
+/// why a data file could not be read
+///
+/// The text and binary parsers return these rather than panicking, whatever
+/// the bytes, so that a malformed file is reported against its line or
+/// header field.
+#[derive(Debug)]
+pub enum DataError {
+    Io(std::io::Error),
+    /// a field of data row `row` (counting from 0, after any header) is not a number
+    Parse{ source: String, row: usize, field: String },
+    /// data row `row` has a different number of fields from the first row or header
+    Ragged{ source: String, row: usize, fields: usize, expected: usize },
+    /// the file does not start with the binary magic bytes
+    NotBinary{ source: String },
+    /// the binary header disagrees with the length of the file
+    Truncated{ source: String },
+    /// the binary column-name block is not utf-8 or names the wrong number of columns
+    Names{ source: String },
+    /// the table as a whole is inconsistent, e.g. header and columns disagree
+    Shape(String),
+    BigEndian,
+}
+
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::Io(e) => write!(f, "{}", e),
+            DataError::Parse{ source, row, field } => {
+                write!(f, "{}: could not parse {:?} on data row {}", source, field, row)
+            },
+            DataError::Ragged{ source, row, fields, expected } => {
+                write!(f, "{}: data row {} has {} fields, expected {}", source, row, fields, expected)
+            },
+            DataError::NotBinary{ source } => write!(f, "{} is not a binary data file", source),
+            DataError::Truncated{ source } => write!(f, "{} is truncated or corrupt", source),
+            DataError::Names{ source } => write!(f, "{} has a corrupt column-name block", source),
+            DataError::Shape(msg) => write!(f, "{}", msg),
+            DataError::BigEndian => {
+                write!(f, "binary data files can only be mapped on little-endian targets")
+            },
+        }
+    }
+}
+
+
+impl Error for DataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<std::io::Error> for DataError {
+    fn from(e: std::io::Error) -> DataError {
+        DataError::Io(e)
+    }
+}
+
+
+/// backing memory for a dataset
+#[derive(Debug)]
+enum Storage {
+    Owned(Vec<f64>),
+    Mapped{ map: Mmap, offset: usize },
+}
+
+
+/// column-major table of observations
+///
+/// Columns are stored contiguously, so `column` hands out a borrowed slice
+/// whether the table lives on the heap or in a memory-mapped binary file.
+/// Likelihoods should hold on to those slices rather than copying them.
+///
+/// Fields:
+/// storage: heap buffer or memory map holding the values
+/// nrows: the number of observations
+/// ncols: the number of variables
+/// names: column names, either from a header or "col0", "col1", ...
+#[derive(Debug)]
+pub struct Dataset {
+    storage: Storage,
+    nrows: usize,
+    ncols: usize,
+    names: Vec<String>,
+}
+
+
+impl Dataset {
+    /// build an in-memory dataset from a vec of equal-length columns
+    pub fn from_columns(
+            columns: Vec<Vec<f64>>,
+            names: Option<Vec<String>>,
+    ) -> Result<Dataset, Box<dyn Error>> {
+        Ok(columns_to_dataset(columns, names)?)
+    }
+
+    /// read a dataset, choosing the parser from the file extension
+    ///
+    /// Files ending in `.nsd` are memory-mapped, everything else is parsed
+    /// as delimited text by `read_text`.
+    pub fn load(path: &Path) -> Result<Dataset, Box<dyn Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(BINARY_EXTENSION) => Dataset::open_binary(path),
+            _ => Dataset::read_text(path),
+        }
+    }
+
+    /// parse a comma- or whitespace-delimited text file
+    ///
+    /// Each line is an observation and each field a variable. A first line
+    /// that does not parse as numbers is taken to be a header. A file with a
+    /// single line of values is read as one column, which is the original
+    /// space-separated y-value format.
+    pub fn read_text(path: &Path) -> Result<Dataset, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Dataset::parse_text(&contents, &path.display().to_string())?)
+    }
+
+    /// parse the contents of a text data file (see `read_text`), naming the
+    /// file as `source` in errors
+    pub fn parse_text(contents: &str, source: &str) -> Result<Dataset, DataError> {
+        let mut lines = contents
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .peekable();
+        let mut names: Option<Vec<String>> = None;
+        if let Some(first) = lines.peek() {
+            if split_fields(first).any(|f| f.parse::<f64>().is_err()) {
+                names = Some(split_fields(first).map(|f| f.to_string()).collect());
+                lines.next();
+            }
+        }
+        let mut rows: Vec<Vec<f64>> = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let row = split_fields(line)
+                .map(|f| f.parse::<f64>().map_err(|_| DataError::Parse{
+                    source: source.to_string(),
+                    row: i,
+                    field: f.to_string(),
+                }))
+                .collect::<Result<Vec<f64>, DataError>>()?;
+            rows.push(row);
+        }
+        // legacy format: a single row holding every y value
+        if rows.len() == 1 && names.is_none() {
+            return columns_to_dataset(rows, None)
+        }
+        let ncols = names.as_ref().map(|n| n.len())
+            .or_else(|| rows.first().map(|r| r.len()))
+            .unwrap_or(0);
+        let mut columns: Vec<Vec<f64>> = vec![Vec::with_capacity(rows.len()); ncols];
+        for (i, row) in rows.into_iter().enumerate() {
+            if row.len() != ncols {
+                return Err(DataError::Ragged{
+                    source: source.to_string(),
+                    row: i,
+                    fields: row.len(),
+                    expected: ncols,
+                })
+            }
+            for (col, val) in zip(&mut columns, row) {
+                col.push(val);
+            }
+        }
+        columns_to_dataset(columns, names)
+    }
+
+    /// memory-map a binary data file written by `write_binary`
+    pub fn open_binary(path: &Path) -> Result<Dataset, Box<dyn Error>> {
+        if cfg!(target_endian = "big") {
+            return Err(DataError::BigEndian.into())
+        }
+        let file = fs::File::open(path)?;
+        // the map is read-only; callers are expected not to rewrite the
+        // file while a run is using it
+        let map = unsafe { Mmap::map(&file)? };
+        let header = BinaryHeader::parse(&map, &path.display().to_string())?;
+        Ok(Dataset{
+            storage: Storage::Mapped{ map, offset: header.offset },
+            nrows: header.nrows,
+            ncols: header.ncols,
+            names: header.names,
+        })
+    }
+
+    /// copy a dataset out of the bytes of a binary data file, checking them
+    /// as `open_binary` does; the result is held on the heap
+    pub fn from_binary_bytes(bytes: &[u8], source: &str) -> Result<Dataset, DataError> {
+        let header = BinaryHeader::parse(bytes, source)?;
+        let values: Vec<f64> = bytes[header.offset..]
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().expect("chunks of 8 bytes")))
+            .collect();
+        Ok(Dataset{
+            storage: Storage::Owned(values),
+            nrows: header.nrows,
+            ncols: header.ncols,
+            names: header.names,
+        })
+    }
+
+    /// write the dataset in the binary, memory-mappable format
+    ///
+    /// Layout (little-endian): 8 magic bytes, u64 nrows, u64 ncols, u64
+    /// length of the newline-joined column names, the names padded to a
+    /// multiple of 8 bytes, then the values column by column. Names may be
+    /// empty but cannot contain a newline.
+    pub fn write_binary(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(name) = self.names.iter().find(|n| n.contains('\n')) {
+            return Err(format!("column name {:?} contains a newline", name).into())
+        }
+        let names = self.names.join("\n");
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.nrows as u64).to_le_bytes())?;
+        out.write_all(&(self.ncols as u64).to_le_bytes())?;
+        out.write_all(&(names.len() as u64).to_le_bytes())?;
+        out.write_all(names.as_bytes())?;
+        let pad = padded(names.len()).ok_or("column names are too long")? - names.len();
+        out.write_all(&vec![0u8; pad])?;
+        for j in 0..self.ncols {
+            for val in self.column(j) {
+                out.write_all(&val.to_le_bytes())?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// write the dataset as comma-separated text with a header line, or in
+    /// the binary format if the path ends in `.nsd`
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if path.extension().and_then(|e| e.to_str()) == Some(BINARY_EXTENSION) {
+            return self.write_binary(path)
+        }
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "{}", self.names.join(","))?;
+        for i in 0..self.nrows {
+            let row: Vec<String> = (0..self.ncols).map(|j| format!("{:e}", self.column(j)[i])).collect();
+            writeln!(out, "{}", row.join(","))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    pub fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.storage, Storage::Mapped{..})
+    }
+
+    /// borrow column `j` without copying
+    pub fn column(&self, j: usize) -> &[f64] {
+        assert!(j < self.ncols, "column {} out of range for {} columns", j, self.ncols);
+        let start = j * self.nrows;
+        match &self.storage {
+            Storage::Owned(values) => &values[start..start + self.nrows],
+            Storage::Mapped{ map, offset } => {
+                let bytes = &map[*offset..];
+                // the map is page aligned and offset is a multiple of 8,
+                // and open_binary checked the length, so this view is valid
+                let values: &[f64] = unsafe {
+                    std::slice::from_raw_parts(
+                        bytes.as_ptr() as *const f64,
+                        self.nrows * self.ncols,
+                    )
+                };
+                &values[start..start + self.nrows]
+            },
+        }
+    }
+
+    /// borrow the column with the given header name
+    pub fn column_by_name(&self, name: &str) -> Result<&[f64], Box<dyn Error>> {
+        let j = self.names
+            .iter()
+            .position(|n| n == name)
+            .ok_or_else(|| format!("no data column named {:?}", name))?;
+        Ok(self.column(j))
+    }
+}
+
+
+/// the checks behind `Dataset::from_columns`, with a typed error
+fn columns_to_dataset(
+        columns: Vec<Vec<f64>>,
+        names: Option<Vec<String>>,
+) -> Result<Dataset, DataError> {
+    let ncols = columns.len();
+    let nrows = columns.first().map(|c| c.len()).unwrap_or(0);
+    if columns.iter().any(|c| c.len() != nrows) {
+        return Err(DataError::Shape("all data columns must have the same length".to_string()))
+    }
+    let names = match names {
+        Some(names) => {
+            if names.len() != ncols {
+                return Err(DataError::Shape(format!(
+                    "got {} column names for {} columns", names.len(), ncols
+                )))
+            }
+            names
+        },
+        None => default_names(ncols),
+    };
+    let values: Vec<f64> = columns.into_iter().flatten().collect();
+    Ok(Dataset{ storage: Storage::Owned(values), nrows, ncols, names })
+}
+
+
+/// the sizes and names read from the header of a binary data file
+struct BinaryHeader {
+    nrows: usize,
+    ncols: usize,
+    names: Vec<String>,
+    offset: usize,
+}
+
+
+impl BinaryHeader {
+    /// check the header of `bytes` against their length; the header is
+    /// untrusted, so none of its sizes may wrap around
+    fn parse(bytes: &[u8], source: &str) -> Result<BinaryHeader, DataError> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return Err(DataError::NotBinary{ source: source.to_string() })
+        }
+        let nrows = usize::try_from(read_u64(bytes, 8));
+        let ncols = usize::try_from(read_u64(bytes, 16));
+        let names_len = usize::try_from(read_u64(bytes, 24));
+        let (nrows, ncols, names_len) = match (nrows, ncols, names_len) {
+            (Ok(nrows), Ok(ncols), Ok(names_len)) => (nrows, ncols, names_len),
+            _ => return Err(DataError::Truncated{ source: source.to_string() }),
+        };
+        let offset = padded(names_len).and_then(|n| n.checked_add(HEADER_LEN));
+        let values = nrows.checked_mul(ncols).and_then(|n| n.checked_mul(8));
+        let offset = match (offset, values) {
+            (Some(offset), Some(values)) if offset.checked_add(values) == Some(bytes.len()) => offset,
+            _ => return Err(DataError::Truncated{ source: source.to_string() }),
+        };
+        let block = std::str::from_utf8(&bytes[HEADER_LEN..HEADER_LEN + names_len])
+            .map_err(|_| DataError::Names{ source: source.to_string() })?;
+        // names may be empty, so only a dataset without columns has an
+        // empty block
+        let names: Vec<String> = match (ncols, block) {
+            (0, "") => Vec::new(),
+            _ => block.split('\n').map(|n| n.to_string()).collect(),
+        };
+        if names.len() != ncols {
+            return Err(DataError::Names{ source: source.to_string() })
+        }
+        Ok(BinaryHeader{ nrows, ncols, names, offset })
+    }
+}
+
+
+/// convert a delimited text data file into the binary format
+pub fn csv_to_binary(csv: &Path, out: &Path) -> Result<(), Box<dyn Error>> {
+    Dataset::read_text(csv)?.write_binary(out)
+}
+
+
+fn split_fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+}
+
+
+fn default_names(ncols: usize) -> Vec<String> {
+    (0..ncols).map(|j| format!("col{}", j)).collect()
+}
+
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+
+/// len rounded up to a multiple of 8, or None if that overflows
+fn padded(len: usize) -> Option<usize> {
+    len.checked_add(7).map(|l| l / 8 * 8)
+}
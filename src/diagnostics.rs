@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::ks_uniform_p_value;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insertion_ranks_flag_a_biased_sampler() {
+        let mut uniform = ShrinkageTrace::default();
+        let mut biased = ShrinkageTrace::default();
+        for i in 0..1000 {
+            uniform.push(10, -0.1, i % 10);
+            biased.push(10, -0.1, i % 3);
+        }
+        assert!(uniform.insertion_p_value() > 0.5);
+        assert!(biased.insertion_p_value() < 1e-6);
+        // with t fixed at its expectation the trace never departs
+        assert!(uniform.max_deviation() < 1e-9);
+        let (mean, sd) = uniform.expected_log_x()[99];
+        assert!((mean + 10.0).abs() < 1e-9 && (sd - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_convergence_rows_are_flushed() {
+        let file = std::env::temp_dir().join(format!("ns_convergence_{}.csv", std::process::id()));
+        let mut trace = ConvergenceTrace::create(&ConvergenceConfig{ file: file.clone(), every: 10 }).unwrap();
+        for i in 0..25 {
+            trace.record(i, -1.0, -2.0, 3.0).unwrap();
+        }
+        // rows can be read while the run is still going
+        let text = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec!["iteration,log_z,log_z_remaining,ess", "10,-1,-2,3", "20,-1,-2,3"]);
+    }
+
+    #[test]
+    fn test_truncation_is_flagged() {
+        // a Gaussian peak: log L = -x^2 / 2 at log X = log x, so log L
+        // rises by x^2 per e-fold, steeply far from the peak
+        let mut far = LikelihoodRise::default();
+        let mut near = LikelihoodRise::default();
+        for i in 0..=100 {
+            let log_x = -(i as f64) / 20.0;
+            far.push(log_x, -0.5 * (6.0 + log_x).exp().powi(2));
+            near.push(log_x - 10.0, -0.5 * (log_x - 10.0).exp().powi(2));
+        }
+        assert!(far.slope() > MAX_LOG_L_SLOPE);
+        assert!(near.slope() < 1e-3);
+
+        assert!(truncation(-10.0, -1.0, near.slope()).is_empty());
+        let flagged = truncation(-3.0, -1.0, far.slope());
+        assert_eq!(flagged.len(), 2);
+        assert!(matches!(flagged[0], Truncation::LiveEvidence{ fraction } if (fraction - (-2f64).exp()).abs() < 1e-12));
+        assert!(flagged[1].to_string().contains("still rising"));
+    }
+}
+
+
+/// insertion-rank p-value below which a run is flagged
+pub const RANK_P_VALUE: f64 = 0.01;
+/// departure of log X from its expectation, in standard deviations, above
+/// which a run is flagged
+pub const MAX_DEVIATION: f64 = 4.0;
+/// share of the evidence left in the live points at termination above
+/// which a run is flagged as stopped too early
+pub const LIVE_EVIDENCE_FRACTION: f64 = 0.01;
+/// rise of the best log-likelihood per e-fold of prior volume above which
+/// a run is flagged as stopped too early; above one, L_max X, the most the
+/// live points could add, is still growing
+pub const MAX_LOG_L_SLOPE: f64 = 1.0;
+
+
+/// per-iteration record of the prior-volume shrinkage
+///
+/// Each iteration shrinks the volume by t, with log t ~ -1/n on average for
+/// n live points; the trace compares the realized log X with that
+/// expectation. The sampled t cannot reveal a faulty sampler, since they
+/// are drawn whatever the sampler does, so the trace also keeps the rank
+/// at which each new point entered the live set: for correct draws from
+/// the constrained prior the rank is uniform on 0..n (Fowlie et al. 2020).
+///
+/// Fields:
+/// n_live: live points at each iteration
+/// log_t: log of the shrinkage factor used at each iteration
+/// insertion: rank of the replacement point among the live points
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShrinkageTrace {
+    pub n_live: Vec<usize>,
+    pub log_t: Vec<f64>,
+    pub insertion: Vec<usize>,
+}
+
+
+impl ShrinkageTrace {
+    pub fn push(&mut self, n_live: usize, log_t: f64, insertion: usize) {
+        self.n_live.push(n_live);
+        self.log_t.push(log_t);
+        self.insertion.push(insertion);
+    }
+
+    pub fn len(&self) -> usize {
+        self.log_t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log_t.is_empty()
+    }
+
+    /// realized log X after each iteration
+    pub fn log_x(&self) -> Vec<f64> {
+        self.log_t.iter()
+            .scan(0.0, |acc, lt| {
+                *acc += lt;
+                Some(*acc)
+            })
+            .collect()
+    }
+
+    /// mean and standard deviation of log X after each iteration; log t is
+    /// minus an exponential with mean and sd 1/n
+    pub fn expected_log_x(&self) -> Vec<(f64, f64)> {
+        self.n_live.iter()
+            .scan((0.0, 0.0), |(mean, var), n| {
+                let n = *n as f64;
+                *mean -= 1.0 / n;
+                *var += 1.0 / (n * n);
+                Some((*mean, var.sqrt()))
+            })
+            .collect()
+    }
+
+    /// largest departure of log X from its expectation, in standard deviations
+    pub fn max_deviation(&self) -> f64 {
+        self.log_x().iter()
+            .zip(self.expected_log_x())
+            .map(|(lx, (mean, sd))| (lx - mean).abs() / sd)
+            .fold(0.0, f64::max)
+    }
+
+    /// Kolmogorov-Smirnov p-value of the insertion ranks against a uniform.
+    /// The ranks are spread over their unit-width bins by a low-discrepancy
+    /// offset, since the steps of a discrete uniform alone fail a
+    /// continuous KS test once the run is much longer than n_live^2
+    pub fn insertion_p_value(&self) -> f64 {
+        let golden = 0.5 * (5f64.sqrt() - 1.0);
+        let u: Vec<f64> = self.insertion.iter()
+            .zip(&self.n_live)
+            .enumerate()
+            .map(|(i, (r, n))| (*r as f64 + (i as f64 * golden).fract()) / *n as f64)
+            .collect();
+        ks_uniform_p_value(&u)
+    }
+
+    /// reasons to distrust the run, if any
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.is_empty() {
+            return warnings
+        }
+        let p = self.insertion_p_value();
+        if p < RANK_P_VALUE {
+            warnings.push(format!(
+                "insertion ranks of new live points are not uniform (KS p = {:.2e}); \
+                 the sampler is not drawing from the constrained prior",
+                p,
+            ));
+        }
+        let dev = self.max_deviation();
+        if dev > MAX_DEVIATION {
+            warnings.push(format!("log X departs from its expectation by {:.1} sd", dev));
+        }
+        warnings
+    }
+
+    /// one row per iteration: iteration, n_live, log_t, log_x, expected
+    /// log_x, its sd and the insertion rank, ready for plotting
+    pub fn write_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "iteration,n_live,log_t,log_x,expected_log_x,expected_sd,insertion")?;
+        let log_x = self.log_x();
+        for (i, (mean, sd)) in self.expected_log_x().into_iter().enumerate() {
+            writeln!(
+                out, "{},{},{},{},{},{},{}",
+                i, self.n_live[i], self.log_t[i], log_x[i], mean, sd, self.insertion[i],
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// the trace written by `write_csv`
+    pub fn read_csv(path: &Path) -> Result<ShrinkageTrace, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let mut trace = ShrinkageTrace::default();
+        for (i, line) in text.lines().enumerate().skip(1).filter(|(_, l)| !l.trim().is_empty()) {
+            let row: Vec<&str> = line.split(',').collect();
+            let bad = || format!("line {} of {} is not a shrinkage row", i + 1, path.display());
+            if row.len() != 7 {
+                return Err(bad().into())
+            }
+            let n_live = row[1].parse().map_err(|_| bad())?;
+            let log_t = row[2].parse().map_err(|_| bad())?;
+            let insertion = row[6].parse().map_err(|_| bad())?;
+            trace.push(n_live, log_t, insertion);
+        }
+        Ok(trace)
+    }
+}
+
+
+/// settings of the convergence trace written while a run goes on
+///
+/// Fields:
+/// file: CSV file of iteration, log Z so far, log of the evidence the
+///     live points could still add, and the ESS so far
+/// every: write a row every this many iterations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConvergenceConfig {
+    pub file: PathBuf,
+    pub every: usize,
+}
+
+
+impl Default for ConvergenceConfig {
+    fn default() -> ConvergenceConfig {
+        ConvergenceConfig{
+            file: PathBuf::from("convergence.csv"),
+            every: 100,
+        }
+    }
+}
+
+
+/// the open convergence trace of a run; every row is flushed as it is
+/// written, so the file can be watched from elsewhere
+#[derive(Debug)]
+pub struct ConvergenceTrace {
+    every: usize,
+    out: BufWriter<File>,
+}
+
+
+impl ConvergenceTrace {
+    pub fn create(config: &ConvergenceConfig) -> Result<ConvergenceTrace, Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(&config.file)?);
+        writeln!(out, "iteration,log_z,log_z_remaining,ess")?;
+        out.flush()?;
+        Ok(ConvergenceTrace{ every: config.every.max(1), out })
+    }
+
+    /// write a row after `iteration` iterations, counted from 0, if it is
+    /// one of the rows asked for
+    pub fn record(&mut self, iteration: usize, log_z: f64, log_z_remaining: f64, ess: f64) -> Result<(), Box<dyn Error>> {
+        let done = iteration + 1;
+        if !done.is_multiple_of(self.every) {
+            return Ok(())
+        }
+        writeln!(self.out, "{},{},{},{}", done, log_z, log_z_remaining, ess)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+
+/// a sign that the run stopped before the evidence converged, so that log
+/// Z, and Bayes factors built on it, are biased low
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Truncation {
+    /// the final live points hold this fraction of the evidence
+    LiveEvidence{ fraction: f64 },
+    /// the best log-likelihood rose by this much per e-fold of prior volume
+    /// over the last e-fold
+    RisingLikelihood{ slope: f64 },
+}
+
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncation::LiveEvidence{ fraction } => write!(
+                f,
+                "the live points still hold {:.1}% of the evidence at termination; \
+                 log Z is likely underestimated, so raise sample_num",
+                100.0 * fraction,
+            ),
+            Truncation::RisingLikelihood{ slope } => write!(
+                f,
+                "the best log-likelihood is still rising by {:.2} per e-fold of prior volume at termination; \
+                 the peak may not have been reached, so raise sample_num",
+                slope,
+            ),
+        }
+    }
+}
+
+
+/// the best log-likelihood of the live points over the last e-fold of
+/// prior volume
+#[derive(Debug, Clone, Default)]
+pub struct LikelihoodRise {
+    recent: VecDeque<(f64, f64)>,
+}
+
+
+impl LikelihoodRise {
+    /// record the best live log-likelihood when the volume is log X
+    pub fn push(&mut self, log_x: f64, best: f64) {
+        self.recent.push_back((log_x, best));
+        // keep the last point at least one e-fold back
+        while self.recent.len() > 2 && self.recent[1].0 - log_x >= 1.0 {
+            self.recent.pop_front();
+        }
+    }
+
+    /// rise of the best log-likelihood per e-fold of volume; zero until the
+    /// run has shrunk the volume at all
+    pub fn slope(&self) -> f64 {
+        match (self.recent.front(), self.recent.back()) {
+            (Some(&(x0, l0)), Some(&(x1, l1))) if x0 > x1 && l1.is_finite() && l0.is_finite() => (l1 - l0) / (x0 - x1),
+            _ => 0.0,
+        }
+    }
+}
+
+
+/// signs that a run stopped too early, from the log evidence of its final
+/// live points, its total log evidence and the rise of its best
+/// log-likelihood per e-fold of volume
+pub fn truncation(live_log_z: f64, log_z: f64, slope: f64) -> Vec<Truncation> {
+    let mut found = Vec::new();
+    let fraction = (live_log_z - log_z).exp();
+    if fraction > LIVE_EVIDENCE_FRACTION {
+        found.push(Truncation::LiveEvidence{ fraction });
+    }
+    if slope > MAX_LOG_L_SLOPE {
+        found.push(Truncation::RisingLikelihood{ slope });
+    }
+    found
+}
@@ -0,0 +1,413 @@
+use std::collections::VecDeque;
+use std::error::Error;
+
+use ordered_float::OrderedFloat;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::ShrinkageTrace;
+use crate::evidence::{Evidence, Shrinkage, ShrinkageMode};
+use crate::models::LogLikelihood;
+use crate::observer::Observer;
+use crate::sampler::{LiveSnapshot, Sampler};
+use crate::RunResult;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+    use crate::observer::Collect;
+    use crate::priors::NormalPrior;
+    use crate::sampler::SamplerConfig;
+    use crate::test_support::Peak;
+
+    #[test]
+    fn test_merged_threads_count_live_points() {
+        // two threads of one live point each, overlapping between 1 and 2
+        let pt = |log_l: f64, birth: f64| DeadPoint{ theta: vec![], log_l, log_l_birth: birth };
+        let mut points = vec![
+            pt(0.0, f64::NEG_INFINITY),
+            pt(2.0, 0.0),
+            pt(1.0, f64::NEG_INFINITY),
+            pt(3.0, 1.0),
+        ];
+        assert_eq!(live_counts(&mut points), vec![2, 2, 2, 1]);
+    }
+
+    #[test]
+    fn test_reactive_run_hits_target() {
+        let model = Peak{ s: 0.1, dim: 1 };
+        let truth = (0.1f64 / (1.0f64 + 0.01).sqrt()).ln();
+        let dynamic = DynamicConfig{
+            target_log_z_err: Some(0.15),
+            target_ess: Some(300.0),
+            batch_size: 50,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(378);
+        let prior = Arc::new(NormalPrior::new(&[0.0], &[1.0]).unwrap());
+        let mut sampler = Sampler::new(&SamplerConfig::default(), prior);
+        let mut observer = Collect::default();
+        let (result, points) = run_dynamic(&model, &mut sampler, 50, 100_000, &dynamic, &mut observer, &mut rng).unwrap();
+        assert_eq!(result.targets_met, Some(true));
+        assert!(result.ess >= 300.0);
+        assert!(result.log_z_err <= 0.15);
+        assert!((result.log_z - truth).abs() < 4.0 * result.log_z_err);
+        assert_eq!(result.posterior.len(), points.len());
+    }
+
+    #[test]
+    fn test_extension_narrows_the_error() {
+        let model = Peak{ s: 0.1, dim: 1 };
+        let truth = (0.1f64 / (1.0f64 + 0.01).sqrt()).ln();
+        let mut rng = StdRng::seed_from_u64(407);
+        let prior = Arc::new(NormalPrior::new(&[0.0], &[1.0]).unwrap());
+        let mut sampler = Sampler::new(&SamplerConfig::default(), prior);
+        let mut observer = Collect::default();
+        let full = (f64::NEG_INFINITY, f64::INFINITY);
+        let mut points = run_batch(&model, &mut sampler, 20, full, 100_000, &mut observer, &mut rng).unwrap();
+        let before = summarize(&mut points, 200, &mut rng);
+        let n_before = points.len();
+        let more = DynamicConfig{ batch_size: 180, n_sim: 200, ..Default::default() };
+        let (result, points) = extend(&model, &mut sampler, points, 100_000, &more, &mut observer, &mut rng).unwrap();
+        assert!(points.len() > n_before);
+        assert!(result.log_z_err < 0.5 * before.log_z_err);
+        assert!((result.log_z - truth).abs() < 4.0 * result.log_z_err);
+        // the new points start above the bulk of the prior
+        assert!(points.iter().filter(|p| p.log_l_birth > f64::NEG_INFINITY).count() >= 180);
+    }
+}
+
+
+/// a batch stops once its live points could add at most this fraction of
+/// the evidence it has accumulated
+const REMAINING_FRACTION: f64 = 1e-3;
+
+
+/// settings of reactive dynamic nested sampling
+///
+/// Fields:
+/// target_log_z_err: keep adding batches until the log Z error is below this
+/// target_ess: keep adding batches until the posterior ESS is above this
+/// batch_size: live points per additional batch
+/// max_batches: give up after this many additional batches
+/// importance_frac: a batch covers the contours whose importance is at
+///     least this fraction of the maximum
+/// n_sim: simulated shrinkage sequences used for the log Z error
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DynamicConfig {
+    pub target_log_z_err: Option<f64>,
+    pub target_ess: Option<f64>,
+    pub batch_size: usize,
+    pub max_batches: usize,
+    pub importance_frac: f64,
+    pub n_sim: usize,
+}
+
+
+impl Default for DynamicConfig {
+    fn default() -> DynamicConfig {
+        DynamicConfig{
+            target_log_z_err: None,
+            target_ess: None,
+            batch_size: 100,
+            max_batches: 50,
+            importance_frac: 0.9,
+            n_sim: 100,
+        }
+    }
+}
+
+
+/// a dead point together with the contour it was born above
+///
+/// Fields:
+/// theta: the parameters
+/// log_l: log-likelihood
+/// log_l_birth: log-likelihood of the contour the point was sampled above
+#[derive(Debug, Clone)]
+pub struct DeadPoint {
+    pub theta: Vec<f64>,
+    pub log_l: f64,
+    pub log_l_birth: f64,
+}
+
+
+/// evidence and posterior summary of a (possibly merged) set of dead points
+///
+/// Fields:
+/// log_z: log evidence using the expected shrinkage
+/// log_z_err: standard deviation of log Z over simulated shrinkages
+/// info: the information H
+/// ess: Kish effective sample size of the posterior weights
+/// log_wt: per-point log posterior weight (unnormalized), in sorted order
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub info: f64,
+    pub ess: f64,
+    pub log_wt: Vec<f64>,
+}
+
+
+/// run one batch of `n` live points over the likelihood range (start, stop):
+/// from contour `start` until the lowest live point reaches `stop`, the
+/// live points hold less than
+/// `REMAINING_FRACTION` of the evidence accumulated by the batch, or
+/// `max_iter` replacements were made; then retire the remaining live points
+pub(crate) fn run_batch<R: Rng + ?Sized>(
+        model: &dyn LogLikelihood,
+        sampler: &mut Sampler,
+        n: usize,
+        (start, stop): (f64, f64),
+        max_iter: usize,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<Vec<DeadPoint>, Box<dyn Error>> {
+    let mut live: VecDeque<DeadPoint> = VecDeque::with_capacity(n);
+    let insert = |live: &mut VecDeque<DeadPoint>, p: DeadPoint| {
+        let pos = live
+            .binary_search_by_key(&OrderedFloat(p.log_l), |a| OrderedFloat(a.log_l))
+            .unwrap_or_else(|e| e);
+        live.insert(pos, p);
+    };
+    for _ in 0..n {
+        let (theta, log_l) = sampler.draw(model, start, &LiveSnapshot::default(), observer, rng)?;
+        insert(&mut live, DeadPoint{ theta, log_l, log_l_birth: start });
+    }
+
+    // volume and evidence relative to the volume above `start`
+    let mut evidence = Evidence::new();
+    let mut shrinkage = Shrinkage::new(ShrinkageMode::Deterministic);
+    let mut dead = Vec::new();
+    let mut iter = 0;
+    while let (Some(worst), Some(best)) = (live.front(), live.back()) {
+        let remaining = best.log_l + shrinkage.log_x() - evidence.log_z();
+        if worst.log_l >= stop || iter >= max_iter || remaining < REMAINING_FRACTION.ln() {
+            break
+        }
+        let worst = live.pop_front().unwrap();
+        let contour = worst.log_l;
+        let (log_w, _) = shrinkage.step(live.len() + 1, rng);
+        evidence.add(log_w, contour);
+        dead.push(worst);
+        let current = LiveSnapshot::new(iter, live.iter().map(|p| (p.theta.as_slice(), p.log_l)));
+        let (theta, log_l) = sampler.draw(model, contour, &current, observer, rng)?;
+        insert(&mut live, DeadPoint{ theta, log_l, log_l_birth: contour });
+        iter += 1;
+    }
+    dead.extend(live.drain(..));
+    Ok(dead)
+}
+
+
+/// sort the points by likelihood and return the number of live points at
+/// each one: those born below it that have not died yet. Prior draws count
+/// as born below every point, even one of log L = -inf
+pub fn live_counts(points: &mut [DeadPoint]) -> Vec<usize> {
+    points.sort_by_key(|p| OrderedFloat(p.log_l));
+    let mut births: Vec<f64> = points.iter().map(|p| p.log_l_birth).collect();
+    births.sort_by_key(|b| OrderedFloat(*b));
+    points.iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let born_below = births.partition_point(|b| *b < p.log_l || *b == f64::NEG_INFINITY);
+            born_below - i
+        })
+        .collect()
+}
+
+
+/// evidence, its uncertainty and the posterior weights of merged runs
+///
+/// The shrinkage at each dead point uses the number of live points at
+/// that contour, so runs with different numbers of live points (and
+/// batches covering only part of the likelihood range) combine correctly.
+pub fn summarize<R: Rng + ?Sized>(points: &mut [DeadPoint], n_sim: usize, rng: &mut R) -> Summary {
+    let n_live = live_counts(points);
+    summarize_tempered(points, &n_live, 1.0, n_sim, rng)
+}
+
+
+/// `summarize` of points already sorted by `live_counts`, with every
+/// likelihood raised to the power beta
+pub(crate) fn summarize_tempered<R: Rng + ?Sized>(
+        points: &[DeadPoint],
+        n_live: &[usize],
+        beta: f64,
+        n_sim: usize,
+        rng: &mut R,
+) -> Summary {
+    // L^0 = 1 everywhere, even where L = 0
+    let tempered = |log_l: f64| if beta == 0.0 { 0.0 } else { beta * log_l };
+    let mut evidence = Evidence::new();
+    let mut log_wt = Vec::with_capacity(points.len());
+    let mut shrinkage = Shrinkage::new(ShrinkageMode::Deterministic);
+    for (p, n) in points.iter().zip(n_live) {
+        let (log_w, _) = shrinkage.step(*n, rng);
+        evidence.add(log_w, tempered(p.log_l));
+        log_wt.push(log_w + tempered(p.log_l));
+    }
+    let log_z = evidence.log_z();
+
+    // spread of log Z over sampled shrinkage factors t ~ Beta(n, 1)
+    let mut sims = Vec::with_capacity(n_sim);
+    for _ in 0..n_sim {
+        let mut ev = Evidence::new();
+        let mut shrinkage = Shrinkage::new(ShrinkageMode::Stochastic);
+        for (p, n) in points.iter().zip(n_live) {
+            let (log_w, _) = shrinkage.step(*n, rng);
+            ev.add(log_w, tempered(p.log_l));
+        }
+        sims.push(ev.log_z());
+    }
+    let mean = sims.iter().sum::<f64>() / n_sim.max(1) as f64;
+    let log_z_err = (sims.iter().map(|s| (s - mean).powi(2)).sum::<f64>()
+        / (n_sim.max(2) - 1) as f64).sqrt();
+
+    let w: Vec<f64> = log_wt.iter().map(|lw| (lw - log_z).exp()).collect();
+    let ess = crate::stats::ess(&w);
+
+    Summary{ log_z, log_z_err, info: evidence.info(), ess, log_wt }
+}
+
+
+/// likelihood range [start, stop) that the next batch should cover
+fn next_batch_range(points: &[DeadPoint], summary: &Summary, n_live: &[usize], evidence_goal: bool, frac: f64) -> (f64, f64) {
+    let n = points.len();
+    let post: Vec<f64> = summary.log_wt.iter().map(|lw| (lw - summary.log_z).exp()).collect();
+    let importance: Vec<f64> = if evidence_goal {
+        // evidence still to be accumulated above each point, per live point
+        let mut remaining = vec![0.0; n];
+        let mut acc = 0.0;
+        for i in (0..n).rev() {
+            acc += post[i];
+            remaining[i] = acc / n_live[i] as f64;
+        }
+        remaining
+    } else {
+        post
+    };
+    let max = importance.iter().cloned().fold(0.0, f64::max);
+    let first = importance.iter().position(|v| *v >= frac * max).unwrap_or(0);
+    let last = importance.iter().rposition(|v| *v >= frac * max).unwrap_or(n - 1);
+    let start = if first == 0 { f64::NEG_INFINITY } else { points[first - 1].log_l };
+    let stop = if last + 1 >= n { f64::INFINITY } else { points[last].log_l };
+    (start, stop)
+}
+
+
+/// reactive dynamic nested sampling (Higson et al. 2019, UltraNest's
+/// reactive scheme)
+///
+/// An initial run with `n_init` live points is followed by batches of
+/// `batch_size` live points restricted to the likelihood range where they
+/// reduce whichever error is furthest from its target: the log Z error
+/// (batches go where most evidence remains per live point) or the posterior
+/// ESS (batches go where the posterior mass is). All dead points are merged
+/// after every batch. If `max_batches` runs out first, the result says so
+/// through `targets_met`. Returns the result and the merged dead points,
+/// sorted by likelihood and carrying their birth contours.
+pub fn run_dynamic<R: Rng + ?Sized>(
+        model: &dyn LogLikelihood,
+        sampler: &mut Sampler,
+        n_init: usize,
+        max_iter: usize,
+        config: &DynamicConfig,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<(RunResult, Vec<DeadPoint>), Box<dyn Error>> {
+    let full = (f64::NEG_INFINITY, f64::INFINITY);
+    let mut points = run_batch(model, sampler, n_init, full, max_iter, observer, rng)?;
+    let mut summary = summarize(&mut points, config.n_sim, rng);
+
+    let shortfall = |summary: &Summary| (
+        config.target_log_z_err.map_or(0.0, |t| summary.log_z_err / t),
+        config.target_ess.map_or(0.0, |t| t / summary.ess.max(1e-300)),
+    );
+    for _ in 0..config.max_batches {
+        let (z_short, ess_short) = shortfall(&summary);
+        if z_short <= 1.0 && ess_short <= 1.0 {
+            break
+        }
+        let n_live = live_counts(&mut points);
+        let range = next_batch_range(&points, &summary, &n_live, z_short >= ess_short, config.importance_frac);
+        let batch = run_batch(model, sampler, config.batch_size, range, max_iter, observer, rng)?;
+        points.extend(batch);
+        summary = summarize(&mut points, config.n_sim, rng);
+    }
+
+    let (z_short, ess_short) = shortfall(&summary);
+    let result = merged_result(model, sampler, &points, summary, Some(z_short <= 1.0 && ess_short <= 1.0));
+    Ok((result, points))
+}
+
+
+/// carry on a finished run with `batch_size` more live points. The new points
+/// start from the contour below which the run's remaining evidence per
+/// live point is negligible, rather than from the whole prior, and run
+/// until their own live points hold little evidence; the dead points of
+/// both are then merged. `points` are the run's dead points with their
+/// birth contours, as in its dead-birth file
+pub fn extend<R: Rng + ?Sized>(
+        model: &dyn LogLikelihood,
+        sampler: &mut Sampler,
+        mut points: Vec<DeadPoint>,
+        max_iter: usize,
+        config: &DynamicConfig,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<(RunResult, Vec<DeadPoint>), Box<dyn Error>> {
+    if points.is_empty() {
+        return Err("the run to extend has no dead points".into())
+    }
+    let summary = summarize(&mut points, config.n_sim, rng);
+    let n_live = live_counts(&mut points);
+    let (start, _) = next_batch_range(&points, &summary, &n_live, true, config.importance_frac);
+    let batch = run_batch(model, sampler, config.batch_size, (start, f64::INFINITY), max_iter, observer, rng)?;
+    points.extend(batch);
+    let summary = summarize(&mut points, config.n_sim, rng);
+    Ok((merged_result(model, sampler, &points, summary, None), points))
+}
+
+
+/// the result of merged dead points, sorted by likelihood, and their summary
+fn merged_result(
+        model: &dyn LogLikelihood,
+        sampler: &Sampler,
+        points: &[DeadPoint],
+        summary: Summary,
+        targets_met: Option<bool>,
+) -> RunResult {
+    let posterior = points.iter()
+        .zip(&summary.log_wt)
+        .map(|(p, lw)| (p.theta.clone(), lw - summary.log_z))
+        .collect();
+    RunResult{
+        log_z: summary.log_z,
+        log_z_err: summary.log_z_err,
+        info: summary.info,
+        iterations: points.len(),
+        approximate: model.is_approximate(),
+        log_lik_variance: model.log_lik_variance(),
+        model_stats: [model.stats(), sampler.stats()].concat(),
+        ess: summary.ess,
+        targets_met,
+        posterior,
+        modes: Vec::new(),
+        mode_labels: Vec::new(),
+        shrinkage: ShrinkageTrace::default(),
+        dead_birth: points.iter().map(|p| (p.log_l, p.log_l_birth)).collect(),
+        cumulative_log_z: None,
+        cluster_counts: Vec::new(),
+        outputs: Vec::new(),
+        truncation: Vec::new(),
+        prior_kl: Vec::new(),
+        log_z_per_volume: None,
+    }
+}
@@ -0,0 +1,235 @@
+use std::error::Error;
+
+use candle_core::{DType, Device, Tensor, Var};
+use serde::{Deserialize, Serialize};
+
+use crate::screen::{Predictor, ScreenSettings, Screened};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_mlp_learns_quadratic() {
+        let mut rng = StdRng::seed_from_u64(375);
+        let xs: Vec<Vec<f64>> = (0..200)
+            .map(|_| vec![rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0)])
+            .collect();
+        let ys: Vec<f64> = xs.iter().map(|x| -0.5 * (x[0] * x[0] + x[1] * x[1])).collect();
+        let config = EmulatorConfig{ epochs: 1500, ..Default::default() };
+        let mlp = Mlp::train(&xs, &ys, &config).unwrap();
+        let rmse = (xs.iter().zip(&ys)
+            .map(|(x, y)| (mlp.predict(x) - y).powi(2))
+            .sum::<f64>() / xs.len() as f64).sqrt();
+        assert!(rmse < 0.2, "rmse {}", rmse);
+    }
+}
+
+
+/// settings of the neural likelihood emulator
+///
+/// Fields:
+/// hidden: width of the two hidden layers
+/// epochs: full-batch Adam steps per training round
+/// learning_rate: Adam step size
+/// min_train: number of true evaluations before the emulator is used
+/// max_train: train on at most this many of the most recent points
+/// retrain_every: retrain after this many new true evaluations
+/// margin_sd: first-stage rejections need prediction + margin_sd * rmse
+///     below the contour, with rmse measured on recent true evaluations
+/// audit: fraction of first-stage rejections evaluated anyway
+/// acquire: fraction of first-stage rejections evaluated to train on,
+///     those predicted closest to the contour
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmulatorConfig {
+    pub hidden: usize,
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub min_train: usize,
+    pub max_train: usize,
+    pub retrain_every: usize,
+    pub margin_sd: f64,
+    pub audit: f64,
+    pub acquire: f64,
+}
+
+
+impl Default for EmulatorConfig {
+    fn default() -> EmulatorConfig {
+        EmulatorConfig{
+            hidden: 32,
+            epochs: 300,
+            learning_rate: 0.01,
+            min_train: 100,
+            max_train: 5000,
+            retrain_every: 200,
+            margin_sd: 4.0,
+            audit: 0.02,
+            acquire: 0.05,
+        }
+    }
+}
+
+
+/// two-hidden-layer tanh network with standardized inputs and output
+///
+/// Trained with candle, then copied out to plain vectors so that a
+/// prediction is a few small dot products with no tensor overhead.
+///
+/// Fields:
+/// layers: (weights as rows of outputs, biases) for each layer
+/// x_mean, x_sd: input standardization
+/// y_mean, y_sd: output standardization
+#[derive(Debug, Clone)]
+pub struct Mlp {
+    layers: Vec<(Vec<Vec<f64>>, Vec<f64>)>,
+    x_mean: Vec<f64>,
+    x_sd: Vec<f64>,
+    y_mean: f64,
+    y_sd: f64,
+}
+
+
+impl Mlp {
+    pub fn train(
+            xs: &[Vec<f64>],
+            ys: &[f64],
+            config: &EmulatorConfig,
+    ) -> Result<Mlp, Box<dyn Error>> {
+        let n = xs.len();
+        if n < 2 || ys.len() != n {
+            return Err("the emulator needs at least two training points".into())
+        }
+        let d = xs[0].len();
+        let x_mean: Vec<f64> = (0..d).map(|j| xs.iter().map(|x| x[j]).sum::<f64>() / n as f64).collect();
+        let x_sd: Vec<f64> = (0..d)
+            .map(|j| {
+                let v = xs.iter().map(|x| (x[j] - x_mean[j]).powi(2)).sum::<f64>() / n as f64;
+                v.sqrt().max(1e-12)
+            })
+            .collect();
+        let y_mean = ys.iter().sum::<f64>() / n as f64;
+        let y_sd = (ys.iter().map(|y| (y - y_mean).powi(2)).sum::<f64>() / n as f64).sqrt().max(1e-12);
+
+        let dev = Device::Cpu;
+        let x_flat: Vec<f32> = xs.iter()
+            .flat_map(|x| (0..d).map(|j| ((x[j] - x_mean[j]) / x_sd[j]) as f32).collect::<Vec<f32>>())
+            .collect();
+        let y_flat: Vec<f32> = ys.iter().map(|y| ((y - y_mean) / y_sd) as f32).collect();
+        let x = Tensor::from_vec(x_flat, (n, d), &dev)?;
+        let y = Tensor::from_vec(y_flat, (n, 1), &dev)?;
+
+        let h = config.hidden;
+        let sizes = [(d, h), (h, h), (h, 1)];
+        let mut params: Vec<(Var, Var)> = Vec::new();
+        for (fan_in, fan_out) in sizes {
+            let w = Var::randn(0f32, (1.0 / fan_in as f64).sqrt() as f32, (fan_in, fan_out), &dev)?;
+            let b = Var::zeros(fan_out, DType::F32, &dev)?;
+            params.push((w, b));
+        }
+
+        // Adam moments, one pair per parameter tensor
+        let vars: Vec<&Var> = params.iter().flat_map(|(w, b)| [w, b]).collect();
+        let mut m: Vec<Tensor> = vars.iter().map(|v| v.zeros_like()).collect::<Result<_, _>>()?;
+        let mut s: Vec<Tensor> = vars.iter().map(|v| v.zeros_like()).collect::<Result<_, _>>()?;
+        let (beta1, beta2, eps) = (0.9f64, 0.999f64, 1e-8f64);
+        for step in 1..=config.epochs {
+            let mut out = x.clone();
+            for (k, (w, b)) in params.iter().enumerate() {
+                out = out.matmul(w.as_tensor())?.broadcast_add(b.as_tensor())?;
+                if k + 1 < params.len() {
+                    out = out.tanh()?;
+                }
+            }
+            let loss = out.sub(&y)?.sqr()?.mean_all()?;
+            let grads = loss.backward()?;
+            let c1 = 1.0 - beta1.powi(step as i32);
+            let c2 = 1.0 - beta2.powi(step as i32);
+            for (k, var) in vars.iter().enumerate() {
+                let g = match grads.get(var.as_tensor()) {
+                    Some(g) => g,
+                    None => continue,
+                };
+                m[k] = ((&m[k] * beta1)? + (g * (1.0 - beta1))?)?;
+                s[k] = ((&s[k] * beta2)? + (g.sqr()? * (1.0 - beta2))?)?;
+                let update = ((&m[k] / c1)? / ((&s[k] / c2)?.sqrt()? + eps)?)?;
+                var.set(&var.as_tensor().sub(&(update * config.learning_rate)?)?)?;
+            }
+        }
+
+        let mut layers = Vec::new();
+        for (w, b) in params.iter() {
+            // stored transposed, one row per output unit
+            let w: Vec<Vec<f32>> = w.as_tensor().t()?.to_vec2()?;
+            let b: Vec<f32> = b.as_tensor().to_vec1()?;
+            layers.push((
+                w.into_iter().map(|row| row.into_iter().map(f64::from).collect()).collect(),
+                b.into_iter().map(f64::from).collect(),
+            ));
+        }
+        Ok(Mlp{ layers, x_mean, x_sd, y_mean, y_sd })
+    }
+
+    pub fn predict(&self, theta: &[f64]) -> f64 {
+        let mut a: Vec<f64> = theta.iter().zip(&self.x_mean).zip(&self.x_sd)
+            .map(|((t, m), s)| (t - m) / s)
+            .collect();
+        for (k, (w, b)) in self.layers.iter().enumerate() {
+            a = w.iter().zip(b)
+                .map(|(row, bi)| bi + row.iter().zip(&a).map(|(wi, ai)| wi * ai).sum::<f64>())
+                .collect();
+            if k + 1 < self.layers.len() {
+                a.iter_mut().for_each(|ai| *ai = ai.tanh());
+            }
+        }
+        self.y_mean + self.y_sd * a[0]
+    }
+}
+
+
+impl Predictor for Mlp {
+    type Config = EmulatorConfig;
+    const NAME: &'static str = "emulator";
+
+    fn settings(config: &EmulatorConfig) -> ScreenSettings {
+        ScreenSettings{
+            min_train: config.min_train,
+            max_train: config.max_train,
+            retrain_every: config.retrain_every,
+            margin: config.margin_sd,
+            audit: config.audit,
+            acquire: config.acquire,
+        }
+    }
+
+    fn from_training(xs: &[Vec<f64>], ys: &[f64], config: &EmulatorConfig) -> Result<Mlp, Box<dyn Error>> {
+        Mlp::train(xs, ys, config)
+    }
+
+    /// the network has no uncertainty of its own, so the first stage uses
+    /// the rmse measured on recent true evaluations
+    fn estimate(&self, theta: &[f64]) -> (f64, Option<f64>) {
+        (self.predict(theta), None)
+    }
+}
+
+
+/// two-stage delayed-acceptance wrapper around an expensive likelihood
+///
+/// A small neural network is trained on the (theta, log L) pairs that
+/// accumulate during the run and retrained periodically. In the first
+/// stage a candidate is rejected from the network's prediction alone if
+/// prediction + margin_sd * rmse is below the contour; only the survivors
+/// reach the second stage, the true likelihood, which alone decides
+/// acceptance and is what particles carry.
+///
+/// The rmse is measured on every true evaluation against the network that
+/// was current at the time, so the first stage becomes less aggressive as
+/// soon as the emulator degrades. It is reported in the run statistics
+/// together with the audit counters, and a run whose first stage rejected
+/// anything is flagged as approximate (see `Screened`).
+pub type NeuralEmulator<M> = Screened<M, Mlp>;
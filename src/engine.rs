@@ -0,0 +1,224 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+
+use rand::{Rng, RngCore};
+
+use crate::evidence::{log_add_exp, Evidence, Shrinkage, ShrinkageMode};
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // a unit Gaussian likelihood under a uniform prior on [-5, 5]^2,
+    // whose evidence is 1/100 up to the negligible mass outside the box
+    struct Box2;
+
+    impl UnitCube for Box2 {
+        fn dim(&self) -> usize {
+            2
+        }
+
+        fn transform(&self, u: &[f64], theta: &mut [f64]) {
+            for (t, u) in theta.iter_mut().zip(u) {
+                *t = 10.0 * u - 5.0;
+            }
+        }
+
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            theta.iter().map(|t| -0.5 * t * t - 0.5 * (2.0 * core::f64::consts::PI).ln()).sum()
+        }
+    }
+
+    #[test]
+    fn test_engine_recovers_the_analytic_evidence() {
+        let mut rng = StdRng::seed_from_u64(446);
+        let config = EngineConfig{ n_live: 100, ..Default::default() };
+        let result = run_unit_cube(&Box2, &config, &mut rng).unwrap();
+        let expected = -(100f64.ln());
+        assert!((result.log_z - expected).abs() < 4.0 * result.log_z_err + 0.05, "{} {}", result.log_z, result.log_z_err);
+        assert!(result.iterations < config.max_iterations);
+        assert_eq!(result.posterior.len(), result.iterations + config.n_live);
+        let total: f64 = result.posterior.iter().map(|(_, lw)| lw.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        let mean: f64 = result.posterior.iter().map(|(t, lw)| t[0] * lw.exp()).sum();
+        assert!(mean.abs() < 0.2, "{}", mean);
+
+        assert!(run_unit_cube(&Box2, &EngineConfig{ n_live: 1, ..config }, &mut rng).is_err());
+    }
+}
+
+
+/// a model for the engine: the prior as a transform of the unit cube, as
+/// nested samplers on small devices usually take it, and the likelihood
+pub trait UnitCube {
+    /// number of parameters
+    fn dim(&self) -> usize;
+
+    /// write the point that uniform coordinates u map to into theta
+    fn transform(&self, u: &[f64], theta: &mut [f64]);
+
+    /// natural log of the likelihood at theta
+    fn log_lik(&self, theta: &[f64]) -> f64;
+}
+
+
+/// settings of the engine
+///
+/// Fields:
+/// n_live: number of live points
+/// max_iterations: the most points retired before the run stops
+/// tolerance: stop once the live points hold less than this fraction of
+///     the evidence
+/// walk_steps: random-walk steps in the unit cube for each new point
+/// shrinkage: stochastic or deterministic prior-volume shrinkage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineConfig {
+    pub n_live: usize,
+    pub max_iterations: usize,
+    pub tolerance: f64,
+    pub walk_steps: usize,
+    pub shrinkage: ShrinkageMode,
+}
+
+
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
+        EngineConfig{
+            n_live: 50,
+            max_iterations: 10_000,
+            tolerance: 1e-3,
+            walk_steps: 20,
+            shrinkage: ShrinkageMode::Stochastic,
+        }
+    }
+}
+
+
+/// what the engine returns
+///
+/// Fields:
+/// log_z: natural log of the evidence
+/// log_z_err: standard error of log_z, sqrt(H / N)
+/// info: the information H, in nats
+/// iterations: the number of points retired before the live set
+/// posterior: every dead point then every final live point as (theta,
+///     log of its normalized posterior weight)
+#[derive(Debug, Clone)]
+pub struct EngineResult {
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub info: f64,
+    pub iterations: usize,
+    pub posterior: Vec<(Vec<f64>, f64)>,
+}
+
+
+/// a live point, its unit-cube coordinates with the parameters they map to
+struct Live {
+    u: Vec<f64>,
+    theta: Vec<f64>,
+    log_l: f64,
+}
+
+
+/// nested sampling of a unit-cube model with nothing but an allocator and
+/// the caller's random numbers, for evidence computations without std
+///
+/// New points walk from a copy of a random live point by uniform steps in
+/// the cube, keeping those above the contour; the step width shrinks when
+/// most steps are refused and grows when most are kept.
+pub fn run_unit_cube(
+        model: &dyn UnitCube,
+        config: &EngineConfig,
+        rng: &mut dyn RngCore,
+) -> Result<EngineResult, Box<dyn Error>> {
+    if config.n_live < 2 {
+        return Err(format!("the engine needs at least 2 live points, not {}", config.n_live).into())
+    }
+    let dim = model.dim();
+    let draw = |rng: &mut dyn RngCore| {
+        let u: Vec<f64> = (0..dim).map(|_| rng.gen::<f64>()).collect();
+        let mut theta = vec![0.0; dim];
+        model.transform(&u, &mut theta);
+        let log_l = model.log_lik(&theta);
+        Live{ u, theta, log_l }
+    };
+    let mut live: Vec<Live> = (0..config.n_live).map(|_| draw(rng)).collect();
+    let mut dead: Vec<(Vec<f64>, f64)> = Vec::new();
+    let mut evidence = Evidence::new();
+    let mut shrinkage = Shrinkage::new(config.shrinkage);
+    let mut width = 0.1;
+    let mut i = 0;
+    while i < config.max_iterations {
+        let worst = (0..live.len())
+            .min_by(|a, b| live[*a].log_l.total_cmp(&live[*b].log_l))
+            .unwrap_or(0);
+        let contour = live[worst].log_l;
+        let (log_w, _) = shrinkage.step(live.len(), rng);
+        evidence.add(log_w, contour);
+        dead.push((live[worst].theta.clone(), log_w + contour));
+
+        // walk from another live point, which is already above the contour
+        let start = (worst + 1 + rng.gen_range(0..live.len() - 1)) % live.len();
+        let mut u = live[start].u.clone();
+        let mut theta = live[start].theta.clone();
+        let mut log_l = live[start].log_l;
+        let mut trial = vec![0.0; dim];
+        let mut trial_theta = vec![0.0; dim];
+        let mut accepted = 0;
+        for _ in 0..config.walk_steps {
+            for (t, u) in trial.iter_mut().zip(&u) {
+                *t = u + width * (2.0 * rng.gen::<f64>() - 1.0);
+            }
+            if trial.iter().any(|t| !(0.0..1.0).contains(t)) {
+                continue
+            }
+            model.transform(&trial, &mut trial_theta);
+            let trial_log_l = model.log_lik(&trial_theta);
+            if trial_log_l > contour {
+                u.copy_from_slice(&trial);
+                theta.copy_from_slice(&trial_theta);
+                log_l = trial_log_l;
+                accepted += 1;
+            }
+        }
+        width = match 2 * accepted > config.walk_steps {
+            true => (width * 1.1).min(1.0),
+            false => width / 1.1,
+        };
+        live[worst] = Live{ u, theta, log_l };
+        i += 1;
+
+        let log_w_live = shrinkage.log_w_live(live.len());
+        let live_log_z = live.iter().fold(f64::NEG_INFINITY, |z, p| log_add_exp(z, log_w_live + p.log_l));
+        if live_log_z - log_add_exp(evidence.log_z(), live_log_z) < config.tolerance.ln() {
+            break
+        }
+    }
+
+    // the remaining volume is shared equally by the live points
+    let log_w_live = shrinkage.log_w_live(live.len());
+    for point in &live {
+        evidence.add(log_w_live, point.log_l);
+    }
+    let log_z = evidence.log_z();
+    let posterior = dead.into_iter()
+        .chain(live.into_iter().map(|p| (p.theta, log_w_live + p.log_l)))
+        .map(|(theta, log_wt)| (theta, log_wt - log_z))
+        .collect();
+    Ok(EngineResult{
+        log_z,
+        log_z_err: evidence.log_z_err(config.n_live),
+        info: evidence.info(),
+        iterations: i,
+        posterior,
+    })
+}
@@ -0,0 +1,333 @@
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::sync::{Arc, OnceLock, RwLock};
+
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_uniform_likelihood() {
+        // a constant likelihood of 1 integrates to exactly 1
+        let mut ev = Evidence::new();
+        let n = 50;
+        for _ in 0..n {
+            ev.add((1.0 / n as f64).ln(), 0.0);
+        }
+        assert!(ev.log_z().abs() < 1e-12);
+        assert!(ev.info().abs() < 1e-12);
+        assert!((ev.ess() - n as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_shrinkage_with_varying_live_points() {
+        let mut rng = StdRng::seed_from_u64(383);
+        let mut shrinkage = Shrinkage::new(ShrinkageMode::Deterministic);
+        let mut total = f64::NEG_INFINITY;
+        for n in [10, 5, 5, 2] {
+            let (log_w, log_t) = shrinkage.step(n, &mut rng);
+            assert_eq!(log_t, -1.0 / n as f64);
+            total = log_add_exp(total, log_w);
+        }
+        assert!((shrinkage.log_x() + 0.1 + 0.2 + 0.2 + 0.5).abs() < 1e-12);
+        // the dead points and the remaining volume add up to the whole prior
+        total = log_add_exp(total, shrinkage.log_x());
+        assert!(total.abs() < 1e-12);
+
+        let mut shrinkage = Shrinkage::new(ShrinkageMode::Stochastic);
+        let mean = (0..20000).map(|_| shrinkage.step(4, &mut rng).1).sum::<f64>() / 20000.0;
+        assert!((mean + 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_a_plateau_shrinks_by_its_share_of_the_live_points() {
+        // 3 of 10 live points tied on a plateau take 30% of the volume
+        // between them, however they are removed
+        let mut rng = StdRng::seed_from_u64(451);
+        let mut shrinkage = Shrinkage::new(ShrinkageMode::Ties);
+        for tied in [3, 2, 1] {
+            shrinkage.step_tied(10, tied, &mut rng);
+        }
+        assert!((shrinkage.log_x() - 0.7f64.ln()).abs() < 1e-12);
+        assert!((ShrinkageMode::Ties.log_t(4, 1, &mut rng) - 0.75f64.ln()).abs() < 1e-12);
+        // a plateau of every live point still leaves some volume
+        assert!(ShrinkageMode::Ties.log_t(5, 5, &mut rng).is_finite());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_custom_volume_models() {
+        // a model halving the volume at each step, whatever N
+        struct Halve;
+
+        impl VolumeModel for Halve {
+            fn log_t(&mut self, _: usize, _: usize, _: &mut dyn RngCore) -> f64 {
+                -(2f64.ln())
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(452);
+        let mut shrinkage = Shrinkage::new(ShrinkageMode::Stochastic);
+        let (log_w, log_t) = shrinkage.step_with(&mut Halve, 10, 1, &mut rng);
+        assert_eq!(log_t, -(2f64.ln()));
+        assert!((log_w - 0.5f64.ln()).abs() < 1e-12);
+        assert_eq!(shrinkage.log_x(), log_t);
+
+        register_volume_model("test_halve", Arc::new(|| Box::new(Halve) as Box<dyn VolumeModel>)).unwrap();
+        assert!(register_volume_model("test_halve", Arc::new(|| Box::new(Halve) as Box<dyn VolumeModel>)).is_err());
+        assert_eq!(registered_volume_model("test_halve").unwrap().log_t(3, 1, &mut rng), -(2f64.ln()));
+        assert!(registered_volume_model("missing").is_err());
+    }
+}
+
+
+/// how the prior volume shrinks at each iteration
+///
+/// Stochastic draws t ~ Beta(N, 1), which is the true distribution of the
+/// shrinkage, so repeated runs scatter as the error estimate says.
+/// Deterministic uses exp(E[log t]) = exp(-1/N) every time, which gives a
+/// lower-variance evidence for quick comparisons between runs. Ties is
+/// deterministic with t = (N - k) / (N - k + 1) when k live points share
+/// the contour, so that a likelihood plateau removed one point at a time
+/// gets k / N of the volume, its share of the live points (Fowlie et al.
+/// 2021), rather than a slope's worth; off plateaus t = (N - 1) / N.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ShrinkageMode {
+    #[default]
+    Stochastic,
+    Deterministic,
+    Ties,
+}
+
+
+/// a scheme for the fraction of the prior volume kept at each iteration
+///
+/// `Shrinkage` does the bookkeeping of the volume and the weights of the
+/// dead points, and asks the model only for log t, so a new compression
+/// scheme is one method. The built-in schemes are the `ShrinkageMode`s;
+/// others can be registered with `register_volume_model` and chosen with
+/// `volume_model` in the config.
+pub trait VolumeModel {
+    /// log t for the death of the worst of `n_live` live points, of which
+    /// `n_tied` (at least 1) share the worst likelihood
+    fn log_t(&mut self, n_live: usize, n_tied: usize, rng: &mut dyn RngCore) -> f64;
+}
+
+
+impl VolumeModel for ShrinkageMode {
+    fn log_t(&mut self, n_live: usize, n_tied: usize, rng: &mut dyn RngCore) -> f64 {
+        let n = n_live as f64;
+        match self {
+            // the largest of n uniforms is Beta(n, 1)
+            ShrinkageMode::Stochastic => (1.0 - rng.gen::<f64>()).ln() / n,
+            ShrinkageMode::Deterministic => -1.0 / n,
+            // (n - q) / (n - q + 1) for q = k, ..., 1 telescopes to (n - k) / n
+            // over a plateau; a plateau of every live point is taken as one
+            // of n - 1, so that some volume remains
+            ShrinkageMode::Ties => {
+                let q = n_tied.min(n_live.saturating_sub(1)) as f64;
+                ((n - q) / (n - q + 1.0)).ln()
+            },
+        }
+    }
+}
+
+
+/// prior volume left above the contour as the run progresses
+///
+/// Each iteration takes the number of live points above the contour at
+/// that moment, so batches, dynamic runs and the final retirement of the
+/// live set all shrink correctly. Everything is kept in log space.
+///
+/// Fields:
+/// mode: stochastic or deterministic shrinkage
+/// log_x: log of the prior volume remaining
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Shrinkage {
+    mode: ShrinkageMode,
+    log_x: f64,
+}
+
+
+impl Shrinkage {
+    pub fn new(mode: ShrinkageMode) -> Shrinkage {
+        Shrinkage{ mode, log_x: 0.0 }
+    }
+
+    /// shrink the volume for an iteration with `n_live` live points; returns
+    /// the log prior-volume weight of the point that dies and log t
+    pub fn step<R: RngCore + ?Sized>(&mut self, n_live: usize, rng: &mut R) -> (f64, f64) {
+        self.step_tied(n_live, 1, rng)
+    }
+
+    /// `step` for an iteration where `n_tied` live points share the worst
+    /// likelihood, which only the ties-aware mode distinguishes
+    pub fn step_tied<R: RngCore + ?Sized>(&mut self, n_live: usize, n_tied: usize, rng: &mut R) -> (f64, f64) {
+        let mut mode = self.mode;
+        self.step_with(&mut mode, n_live, n_tied, rng)
+    }
+
+    /// `step_tied` with log t from `model` in place of the mode
+    pub fn step_with<R: RngCore + ?Sized>(
+            &mut self,
+            model: &mut dyn VolumeModel,
+            n_live: usize,
+            n_tied: usize,
+            mut rng: &mut R,
+    ) -> (f64, f64) {
+        let log_t = model.log_t(n_live, n_tied.max(1), &mut rng);
+        let log_w = self.log_x + (-log_t.exp_m1()).ln();
+        self.log_x += log_t;
+        (log_w, log_t)
+    }
+
+    pub fn mode(&self) -> ShrinkageMode {
+        self.mode
+    }
+
+    pub fn log_x(&self) -> f64 {
+        self.log_x
+    }
+
+    /// log weight of each of `n_live` points sharing the remaining volume
+    pub fn log_w_live(&self, n_live: usize) -> f64 {
+        self.log_x - (n_live as f64).ln()
+    }
+}
+
+
+/// builds a fresh volume model for each run
+#[cfg(feature = "std")]
+pub type VolumeModelConstructor = Arc<dyn Fn() -> Box<dyn VolumeModel> + Send + Sync>;
+
+
+#[cfg(feature = "std")]
+fn volume_registry() -> &'static RwLock<HashMap<String, VolumeModelConstructor>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, VolumeModelConstructor>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+
+/// make a volume model selectable from configs as `volume_model = "name"`
+#[cfg(feature = "std")]
+pub fn register_volume_model(name: &str, constructor: VolumeModelConstructor) -> Result<(), Box<dyn Error>> {
+    let mut models = volume_registry().write().map_err(|_| "the volume model registry is poisoned")?;
+    if models.contains_key(name) {
+        return Err(format!("a volume model named {:?} is already registered", name).into())
+    }
+    models.insert(name.to_string(), constructor);
+    Ok(())
+}
+
+
+/// a new instance of the volume model registered as `name`
+#[cfg(feature = "std")]
+pub fn registered_volume_model(name: &str) -> Result<Box<dyn VolumeModel>, Box<dyn Error>> {
+    let models = volume_registry().read().map_err(|_| "the volume model registry is poisoned")?;
+    let constructor = models.get(name).ok_or_else(|| format!("no volume model registered as {:?}", name))?;
+    Ok(constructor())
+}
+
+
+/// log(exp(a) + exp(b)) without overflow
+pub fn log_add_exp(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b
+    }
+    if b == f64::NEG_INFINITY {
+        return a
+    }
+    let m = a.max(b);
+    m + ((a - m).exp() + (b - m).exp()).ln()
+}
+
+
+/// the double-double accumulator in place of the f64 one below
+#[cfg(feature = "double-double")]
+pub use crate::extended::ExtendedEvidence as Evidence;
+
+
+/// running evidence and information estimate
+///
+/// Every dead point contributes its prior-volume weight times its
+/// likelihood. The information H (Skilling 2006) is updated alongside,
+/// giving the usual sqrt(H / N) error estimate on log Z.
+///
+/// Fields:
+/// log_z: log of the evidence accumulated so far
+/// h: the information, in nats
+/// log_sum_sq: log of the sum of the squared point weights, for the ESS
+#[cfg(not(feature = "double-double"))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Evidence {
+    log_z: f64,
+    h: f64,
+    log_sum_sq: f64,
+}
+
+
+#[cfg(not(feature = "double-double"))]
+impl Evidence {
+    pub fn new() -> Evidence {
+        Evidence{ log_z: f64::NEG_INFINITY, h: 0.0, log_sum_sq: f64::NEG_INFINITY }
+    }
+
+    /// add a point with log prior-volume weight `log_w` and log-likelihood `log_l`
+    pub fn add(&mut self, log_w: f64, log_l: f64) {
+        let log_wt = log_w + log_l;
+        if log_wt == f64::NEG_INFINITY || log_wt.is_nan() {
+            return
+        }
+        let log_z_new = log_add_exp(self.log_z, log_wt);
+        let old = if self.log_z == f64::NEG_INFINITY {
+            0.0
+        } else {
+            (self.log_z - log_z_new).exp() * (self.h + self.log_z)
+        };
+        self.h = (log_wt - log_z_new).exp() * log_l + old - log_z_new;
+        self.log_z = log_z_new;
+        self.log_sum_sq = log_add_exp(self.log_sum_sq, 2.0 * log_wt);
+    }
+
+    pub fn log_z(&self) -> f64 {
+        self.log_z
+    }
+
+    pub fn info(&self) -> f64 {
+        self.h
+    }
+
+    /// Kish effective sample size of the points added so far
+    pub fn ess(&self) -> f64 {
+        if self.log_sum_sq == f64::NEG_INFINITY {
+            return 0.0
+        }
+        (2.0 * self.log_z - self.log_sum_sq).exp()
+    }
+
+    /// standard error of log Z for a run with `n_live` live points
+    pub fn log_z_err(&self, n_live: usize) -> f64 {
+        (self.h.max(0.0) / n_live as f64).sqrt()
+    }
+}
+
+
+#[cfg(not(feature = "double-double"))]
+impl Default for Evidence {
+    fn default() -> Evidence {
+        Evidence::new()
+    }
+}
@@ -0,0 +1,209 @@
+use core::ops::{Add, AddAssign};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::log_add_exp;
+
+    #[test]
+    fn test_small_weights_are_not_lost_against_a_large_sum() {
+        // each 1e-16 vanishes when added to 1 in f64, but a million of
+        // them raise log Z by 1e-10
+        let mut evidence = ExtendedEvidence::new();
+        let mut plain = f64::NEG_INFINITY;
+        evidence.add(0.0, 0.0);
+        plain = log_add_exp(plain, 0.0);
+        for _ in 0..1_000_000 {
+            evidence.add(1e-16f64.ln(), 0.0);
+            plain = log_add_exp(plain, 1e-16f64.ln());
+        }
+        assert_eq!(plain, 0.0);
+        assert!((evidence.log_z() - 1e-10).abs() < 1e-15, "{}", evidence.log_z());
+    }
+
+    #[test]
+    fn test_agrees_with_the_f64_path() {
+        // weights far apart in scale force the accumulator to rescale
+        let points = [(-5.0, -1000.0), (-4.0, -2.0), (-3.0, 400.0), (-2.5, 401.0), (-2.0, 10.0)];
+        let mut extended = ExtendedEvidence::new();
+        let (mut log_z, mut weights) = (f64::NEG_INFINITY, Vec::new());
+        for (log_w, log_l) in points {
+            extended.add(log_w, log_l);
+            log_z = log_add_exp(log_z, log_w + log_l);
+            weights.push(log_w + log_l);
+        }
+        assert!((extended.log_z() - log_z).abs() < 1e-12);
+        let info: f64 = points.iter().zip(&weights).map(|((_, l), w)| (w - log_z).exp() * l).sum::<f64>() - log_z;
+        assert!((extended.info() - info).abs() < 1e-9, "{} {}", extended.info(), info);
+        let ess = 1.0 / weights.iter().map(|w| (2.0 * (w - log_z)).exp()).sum::<f64>();
+        assert!((extended.ess() - ess).abs() < 1e-12);
+        assert_eq!(ExtendedEvidence::new().ess(), 0.0);
+    }
+}
+
+
+/// an unevaluated sum hi + lo with |lo| <= ulp(hi) / 2, carrying about 32
+/// significant digits
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+
+/// a + b as an exact double-double (Knuth's two-sum)
+fn two_sum(a: f64, b: f64) -> DoubleDouble {
+    let hi = a + b;
+    let bb = hi - a;
+    DoubleDouble{ hi, lo: (a - (hi - bb)) + (b - bb) }
+}
+
+
+/// a * b as an exact double-double, by a fused multiply-add
+fn two_prod(a: f64, b: f64) -> DoubleDouble {
+    let hi = a * b;
+    DoubleDouble{ hi, lo: a.mul_add(b, -hi) }
+}
+
+
+impl DoubleDouble {
+    pub fn new(x: f64) -> DoubleDouble {
+        DoubleDouble{ hi: x, lo: 0.0 }
+    }
+
+    /// the nearest f64
+    pub fn value(&self) -> f64 {
+        self.hi + self.lo
+    }
+
+    /// natural log, with the low part kept as a first-order correction
+    pub fn ln(&self) -> f64 {
+        self.hi.ln() + (self.lo / self.hi).ln_1p()
+    }
+
+    pub fn scale(self, x: f64) -> DoubleDouble {
+        let p = two_prod(self.hi, x);
+        two_sum(p.hi, p.lo + self.lo * x)
+    }
+}
+
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let s = two_sum(self.hi, other.hi);
+        let t = two_sum(self.lo, other.lo);
+        let s = two_sum(s.hi, s.lo + t.hi);
+        two_sum(s.hi, s.lo + t.lo)
+    }
+}
+
+
+impl AddAssign for DoubleDouble {
+    fn add_assign(&mut self, other: DoubleDouble) {
+        *self = *self + other;
+    }
+}
+
+
+/// a term more than this many nats above the current shift moves the
+/// shift up, so that exp never overflows; shifts are rare, as each one
+/// costs the accumulated sums an f64 rounding
+const MAX_SHIFT: f64 = 300.0;
+
+
+/// running evidence and information estimate with double-double sums
+///
+/// The same API as `evidence::Evidence`, which it replaces under the
+/// `double-double` feature, for runs whose evidence piles up from very
+/// many weights spanning more than the 16 digits of an f64: the weights
+/// are summed as exp(log w + log L - shift) in double-double arithmetic,
+/// so small late contributions are not rounded away against a large
+/// total.
+///
+/// Fields:
+/// shift: log of the scale the sums are taken relative to
+/// z: sum of the shifted weights
+/// zl: sum of the shifted weights times their log-likelihoods, for H
+/// sum_sq: sum of the squared shifted weights, for the ESS
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ExtendedEvidence {
+    shift: f64,
+    z: DoubleDouble,
+    zl: DoubleDouble,
+    sum_sq: DoubleDouble,
+}
+
+
+impl ExtendedEvidence {
+    pub fn new() -> ExtendedEvidence {
+        ExtendedEvidence{
+            shift: f64::NEG_INFINITY,
+            z: DoubleDouble::default(),
+            zl: DoubleDouble::default(),
+            sum_sq: DoubleDouble::default(),
+        }
+    }
+
+    /// add a point with log prior-volume weight `log_w` and log-likelihood `log_l`
+    pub fn add(&mut self, log_w: f64, log_l: f64) {
+        let log_wt = log_w + log_l;
+        if log_wt == f64::NEG_INFINITY || log_wt.is_nan() {
+            return
+        }
+        if self.shift == f64::NEG_INFINITY {
+            self.shift = log_wt;
+        } else if log_wt > self.shift + MAX_SHIFT {
+            let factor = (self.shift - log_wt).exp();
+            self.z = self.z.scale(factor);
+            self.zl = self.zl.scale(factor);
+            self.sum_sq = self.sum_sq.scale(factor * factor);
+            self.shift = log_wt;
+        }
+        let w = (log_wt - self.shift).exp();
+        self.z += DoubleDouble::new(w);
+        self.zl += two_prod(w, log_l);
+        self.sum_sq += two_prod(w, w);
+    }
+
+    pub fn log_z(&self) -> f64 {
+        if self.shift == f64::NEG_INFINITY {
+            return f64::NEG_INFINITY
+        }
+        self.shift + self.z.ln()
+    }
+
+    pub fn info(&self) -> f64 {
+        if self.shift == f64::NEG_INFINITY {
+            return 0.0
+        }
+        self.zl.value() / self.z.value() - self.log_z()
+    }
+
+    /// Kish effective sample size of the points added so far
+    pub fn ess(&self) -> f64 {
+        if self.shift == f64::NEG_INFINITY {
+            return 0.0
+        }
+        (2.0 * self.z.ln() - self.sum_sq.ln()).exp()
+    }
+
+    /// standard error of log Z for a run with `n_live` live points
+    pub fn log_z_err(&self, n_live: usize) -> f64 {
+        (self.info().max(0.0) / n_live as f64).sqrt()
+    }
+}
+
+
+impl Default for ExtendedEvidence {
+    fn default() -> ExtendedEvidence {
+        ExtendedEvidence::new()
+    }
+}
@@ -0,0 +1,37 @@
+/// the f64 functions that core lacks, from libm, for builds without std;
+/// with std the inherent methods of the same names are used instead
+pub(crate) trait Float {
+    fn ln(self) -> f64;
+    fn exp(self) -> f64;
+    fn exp_m1(self) -> f64;
+    fn ln_1p(self) -> f64;
+    fn sqrt(self) -> f64;
+    fn mul_add(self, a: f64, b: f64) -> f64;
+}
+
+
+impl Float for f64 {
+    fn ln(self) -> f64 {
+        libm::log(self)
+    }
+
+    fn exp(self) -> f64 {
+        libm::exp(self)
+    }
+
+    fn exp_m1(self) -> f64 {
+        libm::expm1(self)
+    }
+
+    fn ln_1p(self) -> f64 {
+        libm::log1p(self)
+    }
+
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+
+    fn mul_add(self, a: f64, b: f64) -> f64 {
+        libm::fma(self, a, b)
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::models::LogLikelihood;
+    use crate::observer::Collect;
+    use crate::sampler::{Method, SamplerConfig};
+    use crate::{run_core, Config};
+
+    #[test]
+    fn test_estimate_of_weighted_points() {
+        // four points of equal weight, one of them live
+        let dead = [(0.0, 1.0), (0.0, 2.0), (0.0, 3.0)];
+        let live = [(0.0, 6.0)];
+        let estimate = FunctionalEstimate::new(&dead, &live);
+        assert!((estimate.mean - 3.0).abs() < 1e-12);
+        // variance 3.5 over an ESS of 4
+        assert!((estimate.sampling - (3.5f64 / 4.0).sqrt()).abs() < 1e-12);
+        // a quarter of the weight is live, but one live point spans nothing
+        assert_eq!(estimate.remaining, 0.0);
+        let live = [(0.0, 6.0), (0.0, 2.0)];
+        assert!((FunctionalEstimate::new(&dead, &live).remaining - 0.4 * 4.0).abs() < 1e-12);
+    }
+
+    // N(theta; 1, 0.1^2) under a N(0, 1) prior
+    struct Peak;
+
+    impl LogLikelihood for Peak {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            -0.5 * ((theta[0] - 1.0) / 0.1).powi(2)
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_runs_stop_once_the_prediction_is_precise() {
+        register_functional("test_prediction", Arc::new(|t: &[f64]| 10.0 * t[0])).unwrap();
+        assert!(register_functional("test_prediction", Arc::new(|t: &[f64]| t[0])).is_err());
+        let config = Config{
+            sample_num: 100000,
+            particle_num: 200,
+            mu: vec![0.0],
+            sd: vec![1.0],
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            functional: Some(FunctionalConfig{ name: "test_prediction".into(), tolerance: 0.15, every: 50 }),
+            ..Default::default()
+        };
+        let result = run_core(&config, &Peak, None, &mut Collect::default(), &mut StdRng::seed_from_u64(440), None, None).unwrap();
+        assert!(result.iterations < 5000 && result.iterations.is_multiple_of(50), "{}", result.iterations);
+        let stat = |k: &str| result.model_stats.iter().find(|(name, _)| name == k).unwrap().1;
+        let (mean, err) = (stat("functional_mean"), stat("functional_std_error"));
+        assert!(err < 0.15, "{}", err);
+        // posterior mean of theta: 1 / (1 + 0.01)
+        let truth = 10.0 / 1.01;
+        assert!((mean - truth).abs() < 4.0 * 0.15, "{} vs {}", mean, truth);
+    }
+}
+
+
+/// a quantity computed from the parameters, e.g. a prediction of the model
+pub type Functional = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+
+fn functional_registry() -> &'static RwLock<HashMap<String, Functional>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Functional>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+
+/// make a functional selectable from configs as `functional.name`
+pub fn register_functional(name: &str, functional: Functional) -> Result<(), Box<dyn Error>> {
+    let mut functionals = functional_registry().write().map_err(|_| "the functional registry is poisoned")?;
+    if functionals.contains_key(name) {
+        return Err(format!("a functional named {:?} is already registered", name).into())
+    }
+    functionals.insert(name.to_string(), functional);
+    Ok(())
+}
+
+
+/// the functional registered as `name`
+pub fn registered_functional(name: &str) -> Result<Functional, Box<dyn Error>> {
+    let functionals = functional_registry().read().map_err(|_| "the functional registry is poisoned")?;
+    functionals.get(name).cloned().ok_or_else(|| format!("no functional registered as {:?}", name).into())
+}
+
+
+/// settings of stopping a run on the posterior mean of a functional
+///
+/// Fields:
+/// name: the functional, registered with `register_functional`
+/// tolerance: stop once the standard error of its posterior mean is
+///     below this, in the functional's units
+/// every: check every this many iterations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FunctionalConfig {
+    pub name: String,
+    pub tolerance: f64,
+    pub every: usize,
+}
+
+
+impl Default for FunctionalConfig {
+    fn default() -> FunctionalConfig {
+        FunctionalConfig{ name: String::new(), tolerance: 0.0, every: 100 }
+    }
+}
+
+
+/// the posterior mean of a functional part way through a run
+///
+/// The dead points and the live points, which share the remaining prior
+/// volume, together stand in for the posterior. Two things make the mean
+/// uncertain: the finite number of weighted points, an error of sd / sqrt(ESS),
+/// and the mass the live points still hold, which the rest of the run
+/// will spread over the region they occupy. That could move the mean by
+/// up to the live share of the mass times the range of the functional
+/// over the live points. The standard error adds the two in quadrature.
+///
+/// Fields:
+/// mean: the posterior mean
+/// sampling: its error from the finite number of points
+/// remaining: its error from the mass still in the live points
+/// std_error: the two combined
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionalEstimate {
+    pub mean: f64,
+    pub sampling: f64,
+    pub remaining: f64,
+    pub std_error: f64,
+}
+
+
+impl FunctionalEstimate {
+    /// the estimate from (log weight, value) pairs of the dead and the
+    /// live points
+    pub fn new(dead: &[(f64, f64)], live: &[(f64, f64)]) -> FunctionalEstimate {
+        let top = dead.iter().chain(live).fold(f64::NEG_INFINITY, |m, (lw, _)| m.max(*lw));
+        let weight = |lw: f64| if top.is_finite() { (lw - top).exp() } else { 0.0 };
+        let (mut total, mut squares, mut sum) = (0.0, 0.0, 0.0);
+        for (lw, v) in dead.iter().chain(live) {
+            let w = weight(*lw);
+            total += w;
+            squares += w * w;
+            sum += w * v;
+        }
+        if total <= 0.0 {
+            return FunctionalEstimate{ mean: f64::NAN, sampling: f64::INFINITY, remaining: f64::INFINITY, std_error: f64::INFINITY }
+        }
+        let mean = sum / total;
+        let var = dead.iter().chain(live).map(|(lw, v)| weight(*lw) * (v - mean).powi(2)).sum::<f64>() / total;
+        let ess = total * total / squares;
+        let live_share = live.iter().map(|(lw, _)| weight(*lw)).sum::<f64>() / total;
+        let (lo, hi) = live.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (_, v)| (lo.min(*v), hi.max(*v)));
+        let sampling = (var / ess).sqrt();
+        let remaining = if live.is_empty() { 0.0 } else { live_share * (hi - lo) };
+        FunctionalEstimate{ mean, sampling, remaining, std_error: sampling.hypot(remaining) }
+    }
+}
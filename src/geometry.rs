@@ -0,0 +1,337 @@
+use crate::linalg::{Cholesky, Matrix};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 2.0, 0.5],
+            vec![2.0, 1.0, -0.5],
+            vec![0.0, 0.5, 1.5],
+            vec![3.0, 3.5, 0.0],
+            vec![1.5, 2.5, 2.0],
+        ]
+    }
+
+    #[test]
+    fn test_whitening_round_trip() {
+        let pts = points();
+        let (mean, cov) = mean_cov(pts.iter().map(|p| p.as_slice()));
+        let white = Whitening::new(mean, &cov).unwrap();
+        let z: Vec<Vec<f64>> = pts.iter().map(|p| white.whiten(p)).collect();
+        let (zm, zc) = mean_cov(z.iter().map(|p| p.as_slice()));
+        for i in 0..3 {
+            assert!(zm[i].abs() < 1e-12);
+            for j in 0..3 {
+                let id = if i == j { 1.0 } else { 0.0 };
+                assert!((zc[(i, j)] - id).abs() < 1e-12);
+            }
+        }
+        let back = white.unwhiten(&z[3]);
+        assert!(back.iter().zip(&pts[3]).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_pca_and_running_covariance() {
+        let cov = Matrix::from_rows(vec![vec![2.0, 1.0], vec![1.0, 2.0]]);
+        let (values, vectors) = pca(&cov);
+        assert!((values[0] - 3.0).abs() < 1e-12 && (values[1] - 1.0).abs() < 1e-12);
+        let v = vectors.row(0);
+        assert!((v[0].abs() - 0.5f64.sqrt()).abs() < 1e-12 && (v[0] - v[1]).abs() < 1e-12);
+
+        let pts = points();
+        let mut running = RunningCovariance::new(3);
+        for p in &pts[..4] {
+            running.add(p);
+        }
+        running.replace(&pts[1], &pts[4]);
+        let kept = [&pts[0], &pts[2], &pts[3], &pts[4]];
+        let (mean, cov) = mean_cov(kept.iter().map(|p| p.as_slice()));
+        assert_eq!(running.len(), 4);
+        assert!(running.mean().iter().zip(&mean).all(|(a, b)| (a - b).abs() < 1e-12));
+        let inc = running.cov();
+        assert!(cov.as_slice().iter().zip(inc.as_slice()).all(|(a, b)| (a - b).abs() < 1e-12));
+        assert_eq!(running.updates(), 6);
+        running.refit(kept.iter().map(|p| p.as_slice()));
+        assert_eq!(running.updates(), 0);
+        let refit = running.cov();
+        assert!(cov.as_slice().iter().zip(refit.as_slice()).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+    /// cost of keeping the live-set covariance current while points are
+    /// swapped one at a time, N = 5000 and d = 20: the rank-one update must
+    /// beat a full refit by far; run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn bench_incremental_covariance() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+        use std::time::Instant;
+
+        let (n, d, swaps) = (5000, 20, 200);
+        let mut rng = StdRng::seed_from_u64(391);
+        let mut pts: Vec<Vec<f64>> = (0..n).map(|_| (0..d).map(|_| rng.gen()).collect()).collect();
+        let new: Vec<Vec<f64>> = (0..swaps).map(|_| (0..d).map(|_| rng.gen()).collect()).collect();
+
+        let mut full = pts.clone();
+        let start = Instant::now();
+        for (k, p) in new.iter().enumerate() {
+            full[k] = p.clone();
+            std::hint::black_box(mean_cov(full.iter().map(|p| p.as_slice())));
+        }
+        let t_full = start.elapsed();
+
+        let mut running = RunningCovariance::new(d);
+        running.refit(pts.iter().map(|p| p.as_slice()));
+        let start = Instant::now();
+        for (k, p) in new.iter().enumerate() {
+            running.replace(&pts[k], p);
+            pts[k] = p.clone();
+            std::hint::black_box(running.cov());
+        }
+        let t_inc = start.elapsed();
+
+        let (_, cov) = mean_cov(pts.iter().map(|p| p.as_slice()));
+        let err = cov.as_slice().iter().zip(running.cov().as_slice()).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        assert!(err < 1e-10, "{:.1e}", err);
+        assert!(10 * t_inc < t_full, "full refit {:?}, rank-one update {:?}", t_full, t_inc);
+    }
+}
+
+
+/// sample mean and covariance (with the n - 1 denominator) of a set of
+/// points; the covariance is zero for fewer than two points
+pub fn mean_cov<'a>(points: impl Iterator<Item = &'a [f64]>) -> (Vec<f64>, Matrix) {
+    let mut running = RunningCovariance::new(0);
+    for (i, p) in points.enumerate() {
+        if i == 0 {
+            running = RunningCovariance::new(p.len());
+        }
+        running.add(p);
+    }
+    (running.mean().to_vec(), running.cov())
+}
+
+
+/// affine map that turns points with a given mean and covariance into
+/// points with zero mean and identity covariance, z = L^-1 (x - mean)
+/// for the Cholesky factor L of the covariance
+///
+/// Fields:
+/// mean: the centre
+/// chol: Cholesky factor of the covariance
+#[derive(Debug, Clone)]
+pub struct Whitening {
+    mean: Vec<f64>,
+    chol: Cholesky,
+}
+
+
+impl Whitening {
+    /// None if the covariance is not positive definite, e.g. because the
+    /// points lie in a lower-dimensional subspace
+    pub fn new(mean: Vec<f64>, cov: &Matrix) -> Option<Whitening> {
+        let chol = cov.cholesky()?;
+        Some(Whitening{ mean, chol })
+    }
+
+    pub fn mean(&self) -> &[f64] {
+        &self.mean
+    }
+
+    pub fn chol(&self) -> &Cholesky {
+        &self.chol
+    }
+
+    pub fn whiten(&self, x: &[f64]) -> Vec<f64> {
+        let centered: Vec<f64> = x.iter().zip(&self.mean).map(|(a, m)| a - m).collect();
+        self.chol.solve_lower(&centered)
+    }
+
+    pub fn unwhiten(&self, z: &[f64]) -> Vec<f64> {
+        self.chol.l().mul_vec(z).iter().zip(&self.mean).map(|(a, m)| a + m).collect()
+    }
+
+    /// write `L z` into `out`: a whitened displacement mapped back to the
+    /// original coordinates, without the shift by the mean
+    pub fn step(&self, z: &[f64], out: &mut [f64]) {
+        let l = self.chol.l();
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = l.row(i)[..=i].iter().zip(z).map(|(a, b)| a * b).sum();
+        }
+    }
+}
+
+
+/// eigenvalues of a symmetric matrix, largest first, and the matching unit
+/// eigenvectors as the rows of a matrix, by cyclic Jacobi rotations
+pub fn pca(cov: &Matrix) -> (Vec<f64>, Matrix) {
+    let n = cov.rows();
+    let mut a = cov.symmetrize();
+    let mut v = Matrix::identity(n);
+    for _ in 0..100 {
+        let off: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[(i, j)] * a[(i, j)])
+            .sum();
+        if off <= 1e-30 * a.as_slice().iter().map(|x| x * x).sum::<f64>() {
+            break
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[(p, q)] == 0.0 {
+                    continue
+                }
+                let theta = 0.5 * (a[(q, q)] - a[(p, p)]) / a[(p, q)];
+                let sign = if theta >= 0.0 { 1.0 } else { -1.0 };
+                let t = sign / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                    a[(k, p)] = c * akp - s * akq;
+                    a[(k, q)] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                    a[(p, k)] = c * apk - s * aqk;
+                    a[(q, k)] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[(k, p)], v[(k, q)]);
+                    v[(k, p)] = c * vkp - s * vkq;
+                    v[(k, q)] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[(j, j)].total_cmp(&a[(i, i)]));
+    let values = order.iter().map(|&i| a[(i, i)]).collect();
+    let vectors = Matrix::from_rows(order.iter().map(|&i| (0..n).map(|k| v[(k, i)]).collect()).collect());
+    (values, vectors)
+}
+
+
+/// mean and covariance of a changing set of points, updated in O(d^2) per
+/// point added or removed rather than recomputed from the whole set
+/// (Welford's algorithm and its reverse)
+///
+/// Rounding errors of the updates accumulate, so owners of a long-lived
+/// set call `refit` now and then; `updates` says how many updates were
+/// made since the last one.
+///
+/// Fields:
+/// n: number of points in the set
+/// mean: their mean
+/// scatter: sum over the points of (x - mean)(x - mean)'
+/// updates: points added or removed since the last refit
+#[derive(Debug, Clone)]
+pub struct RunningCovariance {
+    n: usize,
+    mean: Vec<f64>,
+    scatter: Matrix,
+    updates: usize,
+}
+
+
+impl Default for RunningCovariance {
+    fn default() -> RunningCovariance {
+        RunningCovariance::new(0)
+    }
+}
+
+
+impl RunningCovariance {
+    pub fn new(dim: usize) -> RunningCovariance {
+        RunningCovariance{ n: 0, mean: vec![0.0; dim], scatter: Matrix::zeros(dim, dim), updates: 0 }
+    }
+
+    /// recompute from scratch from the points currently in the set
+    pub fn refit<'a>(&mut self, points: impl Iterator<Item = &'a [f64]>) {
+        let dim = self.mean.len();
+        let mut mean = vec![0.0; dim];
+        let pts: Vec<&[f64]> = points.collect();
+        for p in &pts {
+            for (m, x) in mean.iter_mut().zip(*p) {
+                *m += x / pts.len() as f64;
+            }
+        }
+        let mut scatter = Matrix::zeros(dim, dim);
+        for p in &pts {
+            for (i, (xi, mi)) in p.iter().zip(&mean).enumerate() {
+                for (j, (xj, mj)) in p.iter().zip(&mean).enumerate() {
+                    scatter[(i, j)] += (xi - mi) * (xj - mj);
+                }
+            }
+        }
+        *self = RunningCovariance{ n: pts.len(), mean, scatter, updates: 0 };
+    }
+
+    pub fn updates(&self) -> usize {
+        self.updates
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    pub fn mean(&self) -> &[f64] {
+        &self.mean
+    }
+
+    pub fn add(&mut self, x: &[f64]) {
+        self.n += 1;
+        self.updates += 1;
+        let before: Vec<f64> = x.iter().zip(&self.mean).map(|(a, m)| a - m).collect();
+        for (m, d) in self.mean.iter_mut().zip(&before) {
+            *m += d / self.n as f64;
+        }
+        let after: Vec<f64> = x.iter().zip(&self.mean).map(|(a, m)| a - m).collect();
+        self.rank_one(&before, &after, 1.0);
+    }
+
+    /// remove a point that was added before
+    pub fn remove(&mut self, x: &[f64]) {
+        if self.n <= 1 {
+            *self = RunningCovariance::new(self.mean.len());
+            return
+        }
+        let after: Vec<f64> = x.iter().zip(&self.mean).map(|(a, m)| a - m).collect();
+        self.n -= 1;
+        self.updates += 1;
+        for (m, d) in self.mean.iter_mut().zip(&after) {
+            *m -= d / self.n as f64;
+        }
+        let before: Vec<f64> = x.iter().zip(&self.mean).map(|(a, m)| a - m).collect();
+        self.rank_one(&before, &after, -1.0);
+    }
+
+    /// swap one point of the set for another, as when a live point dies
+    pub fn replace(&mut self, old: &[f64], new: &[f64]) {
+        self.remove(old);
+        self.add(new);
+    }
+
+    /// sample covariance, zero for fewer than two points
+    pub fn cov(&self) -> Matrix {
+        if self.n < 2 {
+            return Matrix::zeros(self.mean.len(), self.mean.len())
+        }
+        self.scatter.scale(1.0 / (self.n as f64 - 1.0))
+    }
+
+    fn rank_one(&mut self, before: &[f64], after: &[f64], sign: f64) {
+        for (i, b) in before.iter().enumerate() {
+            for (j, a) in after.iter().enumerate() {
+                self.scatter[(i, j)] += sign * b * a;
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use rand::RngCore;
+
+use crate::priors::Prior;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand::distributions::Distribution;
+    use crate::dist::Normal;
+    use crate::priors::NormalPrior;
+
+    #[test]
+    fn test_an_unconstrained_parameter_is_flagged() {
+        let prior = NormalPrior::new(&[0.0, 0.0, 0.0], &[1.0, 1.0, 1.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(460);
+        // the data pin down the first parameter, shift the second a little
+        // and leave the third at its prior
+        let (narrow, shifted, unit) = (Normal::new(0.5, 0.2).unwrap(), Normal::new(0.1, 0.95).unwrap(), Normal::new(0.0, 1.0).unwrap());
+        let log_w = -(2000f64).ln();
+        let posterior: Vec<(Vec<f64>, f64)> = (0..2000)
+            .map(|_| (vec![narrow.sample(&mut rng), shifted.sample(&mut rng), unit.sample(&mut rng)], log_w))
+            .collect();
+        let kl = prior_kl(&prior, &posterior, &mut rng);
+        assert_eq!(kl.len(), 3);
+        assert!(kl[0] > 0.5, "{:?}", kl);
+        assert!(kl[2] < PRIOR_DOMINATED_KL, "{:?}", kl);
+        assert!(kl[1] < kl[0]);
+        assert!(prior_dominated(&kl).contains(&2) && !prior_dominated(&kl).contains(&0));
+        assert!(prior_kl(&prior, &[], &mut rng).is_empty());
+
+        // widening the prior of the pinned-down parameter a thousandfold
+        // flags it and lowers log Z by ln 1000, but not log Z per volume
+        let wide = NormalPrior::new(&[0.0, 0.0, 0.0], &[1000.0, 1.0, 1.0]).unwrap();
+        let ratios = width_ratios(&wide, &posterior);
+        assert_eq!(effectively_improper(&ratios), vec![0]);
+        assert!(effectively_improper(&width_ratios(&prior, &posterior)).is_empty());
+        let log_z = -3.0;
+        let narrow = log_z_per_volume(&prior, &posterior, log_z);
+        let widened = log_z_per_volume(&wide, &posterior, log_z - 1000f64.ln());
+        assert!((narrow - widened).abs() < 0.2, "{} {}", narrow, widened);
+    }
+}
+
+
+/// bins of the marginal histograms, each holding an equal share of the prior
+const BINS: usize = 10;
+/// prior draws the bin edges are placed by
+const PRIOR_DRAWS: usize = 10_000;
+/// parameters whose marginal posterior is less than this many nats from
+/// their marginal prior are reported as prior-dominated; a normal prior
+/// whose sd the data shrink by a fifth is about this far from its posterior
+pub const PRIOR_DOMINATED_KL: f64 = 0.05;
+
+
+/// per parameter, the Kullback-Leibler divergence in nats of the marginal
+/// posterior from the marginal prior, how much the data taught about it
+///
+/// Both marginals are binned into `BINS` bins that each hold an equal
+/// share of the prior, placed by quantiles of prior draws, so the prior
+/// needs no marginal density. Binning can only lower the divergence, and
+/// the finite posterior sample raises it by about (BINS - 1) / (2 ESS),
+/// which is subtracted. Empty when the posterior is, or when its points
+/// do not have the prior's dimension.
+pub fn prior_kl(prior: &dyn Prior, posterior: &[(Vec<f64>, f64)], rng: &mut dyn RngCore) -> Vec<f64> {
+    let dim = prior.dim();
+    if posterior.is_empty() || posterior.iter().any(|(theta, _)| theta.len() != dim) {
+        return Vec::new()
+    }
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let total: f64 = weights.iter().sum();
+    let sum_sq: f64 = weights.iter().map(|w| w * w).sum();
+    if total.is_nan() || total <= 0.0 {
+        return Vec::new()
+    }
+    let ess = total * total / sum_sq;
+
+    let mut draws = vec![vec![0.0; PRIOR_DRAWS]; dim];
+    let mut theta = vec![0.0; dim];
+    for i in 0..PRIOR_DRAWS {
+        prior.sample_into(&mut theta, rng);
+        for (column, t) in draws.iter_mut().zip(&theta) {
+            column[i] = *t;
+        }
+    }
+
+    draws.iter_mut()
+        .enumerate()
+        .map(|(j, column)| {
+            column.sort_by(f64::total_cmp);
+            let edges: Vec<f64> = (1..BINS).map(|k| column[k * PRIOR_DRAWS / BINS]).collect();
+            let mut mass = [0.0; BINS];
+            for ((theta, _), w) in posterior.iter().zip(&weights) {
+                mass[edges.partition_point(|e| *e <= theta[j])] += w / total;
+            }
+            let raw: f64 = mass.iter()
+                .filter(|q| **q > 0.0)
+                .map(|q| q * (q * BINS as f64).ln())
+                .sum();
+            (raw - (BINS - 1) as f64 / (2.0 * ess)).max(0.0)
+        })
+        .collect()
+}
+
+
+/// the parameters whose divergence from the prior is below
+/// `PRIOR_DOMINATED_KL`
+pub fn prior_dominated(kl: &[f64]) -> Vec<usize> {
+    kl.iter().enumerate().filter(|(_, d)| **d < PRIOR_DOMINATED_KL).map(|(j, _)| j).collect()
+}
+
+
+/// a parameter whose prior is more than this many times wider than its
+/// posterior has an effectively improper prior: the evidence depends on
+/// the prior's width more than on the fit
+pub const IMPROPER_WIDTH_RATIO: f64 = 1e3;
+
+
+/// per parameter, the prior's scale over the posterior standard deviation;
+/// NaN where the posterior has no spread. Empty when the posterior is, or
+/// when its points do not have the prior's dimension
+pub fn width_ratios(prior: &dyn Prior, posterior: &[(Vec<f64>, f64)]) -> Vec<f64> {
+    let dim = prior.dim();
+    if posterior.is_empty() || posterior.iter().any(|(theta, _)| theta.len() != dim) {
+        return Vec::new()
+    }
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let total: f64 = weights.iter().sum();
+    prior.scale().iter()
+        .enumerate()
+        .map(|(j, scale)| {
+            let mean = posterior.iter().zip(&weights).map(|((t, _), w)| w * t[j]).sum::<f64>() / total;
+            let var = posterior.iter().zip(&weights).map(|((t, _), w)| w * (t[j] - mean).powi(2)).sum::<f64>() / total;
+            match var > 0.0 {
+                true => scale / var.sqrt(),
+                false => f64::NAN,
+            }
+        })
+        .collect()
+}
+
+
+/// the parameters whose width ratio exceeds `IMPROPER_WIDTH_RATIO`
+pub fn effectively_improper(ratios: &[f64]) -> Vec<usize> {
+    ratios.iter().enumerate().filter(|(_, r)| **r > IMPROPER_WIDTH_RATIO).map(|(j, _)| j).collect()
+}
+
+
+/// log of the evidence per unit prior volume, log Z minus the posterior
+/// mean of the log prior density
+///
+/// Where the prior is flat across the posterior this is the log of the
+/// likelihood integrated over the parameters, which no longer depends on
+/// how wide the prior is. It compares models whose priors are arbitrary
+/// wide placeholders, where log Z itself mostly measures the placeholders.
+pub fn log_z_per_volume(prior: &dyn Prior, posterior: &[(Vec<f64>, f64)], log_z: f64) -> f64 {
+    let mean_log_prior: f64 = posterior.iter()
+        .filter(|(_, lw)| *lw > f64::NEG_INFINITY)
+        .map(|(theta, lw)| lw.exp() * prior.log_density(theta))
+        .sum();
+    log_z - mean_log_prior
+}
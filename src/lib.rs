@@ -1,50 +1,226 @@
+// test modules sit right after the imports, ahead of the items they test
+#![allow(clippy::items_after_test_module)]
+// without std only the evidence machinery and the unit-cube engine build
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fs;
+#[cfg(feature = "std")]
 use std::path::PathBuf;
-use std::iter::zip;
-use rand::thread_rng;
-use rand::distributions::Distribution;
-use statrs::distribution::{Beta, Normal};
+#[cfg(feature = "std")]
+use rand::rngs::StdRng;
+#[cfg(feature = "std")]
+use rand::{thread_rng, SeedableRng};
+#[cfg(feature = "std")]
 use ordered_float::OrderedFloat;
-use serde::Deserialize;
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
 use std::collections::VecDeque;
 
+#[cfg(feature = "rv")]
 use rand::seq::SliceRandom;
+#[cfg(feature = "std")]
 use rand::Rng;
+#[cfg(feature = "rv")]
 use rv::data::Partition;
+#[cfg(feature = "rv")]
 use rv::dist::{Crp, Gaussian, NormalInvGamma};
+#[cfg(feature = "rv")]
 use rv::misc::ln_pflip;
+#[cfg(feature = "rv")]
 use rv::traits::*;
+#[cfg(feature = "rv")]
 use rv::ConjugateModel;
+#[cfg(feature = "std")]
 use std::sync::Arc;
-
-
-
-#[cfg(test)]
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod array;
+#[cfg(feature = "std")]
+pub mod bounds;
+#[cfg(feature = "std")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod control;
+#[cfg(feature = "std")]
+pub mod crossval;
+#[cfg(feature = "std")]
+pub mod curve;
+#[cfg(feature = "std")]
+pub mod data;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod dist;
+#[cfg(feature = "std")]
+pub mod dynamic;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+pub mod engine;
+pub mod evidence;
+pub mod extended;
+#[cfg(not(feature = "std"))]
+mod float;
+#[cfg(feature = "std")]
+pub mod functional;
+#[cfg(feature = "std")]
+pub mod geometry;
+#[cfg(feature = "std")]
+pub mod learned;
+#[cfg(feature = "std")]
+pub mod linalg;
+#[cfg(feature = "std")]
+pub mod models;
+#[cfg(feature = "std")]
+pub mod modes;
+#[cfg(feature = "std")]
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod overrides;
+#[cfg(feature = "std")]
+pub mod posterior;
+#[cfg(feature = "std")]
+pub mod postprocess;
+#[cfg(feature = "std")]
+pub mod ppc;
+#[cfg(feature = "std")]
+pub mod priors;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod replicate;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod rescale;
+#[cfg(feature = "std")]
+pub mod rundir;
+#[cfg(feature = "std")]
+pub mod sampler;
+#[cfg(feature = "std")]
+pub mod sbc;
+#[cfg(feature = "std")]
+pub mod screen;
+#[cfg(feature = "std")]
+pub mod sensitivity;
+#[cfg(feature = "std")]
+pub mod serve;
+#[cfg(feature = "std")]
+pub mod simulate;
+#[cfg(feature = "std")]
+pub mod smc;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod surrogate;
+#[cfg(feature = "std")]
+pub mod sweep;
+#[cfg(feature = "std")]
+pub mod telemetry;
+#[cfg(feature = "std")]
+pub mod tempering;
+#[cfg(all(test, feature = "std"))]
+mod test_support;
+#[cfg(feature = "std")]
+pub mod units;
+#[cfg(feature = "std")]
+pub mod updating;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "std")]
+pub mod warm;
+
+#[cfg(feature = "std")]
+use arena::Arena;
+#[cfg(feature = "std")]
+use bounds::{learn_bounds, Bounds, BoundsExport, RestrictConfig, Restricted};
+#[cfg(feature = "std")]
+use checkpoint::{Checkpoint, CheckpointConfig};
+#[cfg(feature = "std")]
+use control::{ControlFile, CONTROL_POLL_SECS};
+#[cfg(feature = "std")]
+use data::Dataset;
+#[cfg(feature = "std")]
+use diagnostics::{truncation, ConvergenceConfig, ConvergenceTrace, LikelihoodRise, ShrinkageTrace, Truncation};
+#[cfg(feature = "std")]
+use dynamic::DynamicConfig;
+#[cfg(feature = "std")]
+use evidence::{log_add_exp, registered_volume_model, Evidence, Shrinkage, ShrinkageMode, VolumeModel};
+#[cfg(feature = "std")]
+use functional::{registered_functional, Functional, FunctionalConfig, FunctionalEstimate};
+#[cfg(feature = "std")]
+use geometry::RunningCovariance;
+#[cfg(feature = "std")]
+use modes::{Mode, ModeConfig};
+#[cfg(feature = "std")]
+use models::{Averaged, Cached, Counted, DpmmConfig, DpmmMarginal, LogLikelihood, MultivariateConfig, NoiseModel, NoisyConfig, NuisanceConfig, WorkerPool, WorkersConfig};
+#[cfg(feature = "std")]
+use observer::Observer;
+#[cfg(feature = "std")]
+use output::{ExportConfig, GetdistConfig};
+#[cfg(feature = "std")]
+use ppc::PpcConfig;
+#[cfg(feature = "std")]
+use rescale::{RescaleMode, Rescaled, RescaledModel, Scaling};
+#[cfg(feature = "std")]
+use priors::{registered_constraint, registered_prior, Constrained, CopulaConfig, CopulaPrior, NormalPrior, Prior};
+#[cfg(feature = "std")]
+use rundir::RunDir;
+#[cfg(feature = "std")]
+use sampler::{LiveSnapshot, Provenance, Sampler, SamplerConfig};
+#[cfg(feature = "std")]
+use smc::SmcConfig;
+#[cfg(feature = "std")]
+use surrogate::{Surrogate, SurrogateConfig};
+#[cfg(feature = "std")]
+use telemetry::{Telemetry, TelemetryConfig};
+#[cfg(feature = "std")]
+use tempering::TemperingConfig;
+#[cfg(feature = "std")]
+use units::ParameterSpec;
+#[cfg(feature = "std")]
+use updating::UpdateConfig;
+#[cfg(feature = "std")]
+use warm::{InjectConfig, Repartitioned, WarmStart, WarmStartConfig};
+#[cfg(feature = "emulator")]
+use emulator::{EmulatorConfig, NeuralEmulator};
+
+
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
+    use proptest::prelude::{Just, Strategy};
+    use proptest::{prop_assert, prop_assert_eq, prop_oneof, proptest};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     fn set_up_test_particles() -> Particles {
-        let mut live: VecDeque<Particle> = VecDeque::new();
-        let dead: Vec<Particle> = Vec::new();
+        let mut particles = Particles::with_capacity(2, 2, 3);
         let mut eps = 0.0;
-        let mut w = 0.1;
-        let mut i = 0;
+        let mut log_w = 0.1f64.ln();
         for i in 0..3 {
             let theta = vec![i as f64; 2];
             let yhat = vec![(i+1) as f64; 2];
             let part = Particle::new_with_all(
                 eps,
-                theta,
-                yhat,
-                w,
+                log_w,
                 i
             );
-            live.push_back(part);
+            particles.add_to_live(part, &theta, &yhat).unwrap();
             eps += 1.0;
-            w *= 0.5;
+            log_w -= 2f64.ln();
         }
-        Particles::new_with_particles(live, dead)
+        particles
     }
 
     #[test]
@@ -72,7 +248,80 @@ mod tests {
         let mut particles = set_up_test_particles();
         particles.update_worst(5.0, 7);
         assert_eq!(particles.live[0].i, 7);
-        assert_eq!(particles.live[0].w, 5.0);
+        assert_eq!(particles.live[0].log_w, 5.0);
+    }
+
+    #[test]
+    fn test_posterior_weights_do_not_underflow() {
+        let mut particles = set_up_test_particles();
+        let mut evidence = Evidence::new();
+        // far below the smallest positive f64, as after a long run
+        for (i, log_w) in [-1000.0, -1001.0].into_iter().enumerate() {
+            evidence.add(log_w, particles.live[0].eps);
+            particles.update_worst(log_w, i);
+            particles.move_worst_to_dead();
+        }
+        particles.live[0].log_w = -1002.0;
+        evidence.add(-1002.0, particles.live[0].eps);
+        let posterior = particles.posterior(evidence.log_z());
+        assert!(evidence.log_z() > -1001.0);
+        let total: f64 = posterior.iter().map(|(_, lw)| lw.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        assert!(posterior.iter().all(|(_, lw)| lw.is_finite()));
+    }
+
+    #[derive(Debug, Clone)]
+    enum LiveOp {
+        /// add a particle this far above the last contour, as samplers do
+        Add(f64),
+        Kill,
+        Update(f64, usize),
+    }
+
+    fn live_op() -> impl Strategy<Value = LiveOp> {
+        prop_oneof![
+            (0.0..10.0f64).prop_map(LiveOp::Add),
+            Just(LiveOp::Kill),
+            (-50.0..0.0f64, 0..1000usize).prop_map(|(log_w, i)| LiveOp::Update(log_w, i)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_live_set_invariants(ops in proptest::collection::vec(live_op(), 0..200)) {
+            let mut particles = Particles::with_capacity(2, 1, 8);
+            let mut added = 0;
+            for op in ops {
+                match op {
+                    LiveOp::Add(delta) => {
+                        let eps = particles.dead().last().map_or(0.0, |p| p.log_l()) + delta;
+                        particles.add_to_live(Particle::new(eps), &[eps, added as f64], &[eps]).unwrap();
+                        added += 1;
+                    },
+                    LiveOp::Kill => if !particles.live().is_empty() {
+                        particles.move_worst_to_dead();
+                    },
+                    LiveOp::Update(log_w, i) => if !particles.live().is_empty() {
+                        particles.update_worst(log_w, i);
+                        let worst = particles.worst().unwrap();
+                        prop_assert_eq!((worst.log_weight(), worst.iteration()), (log_w, i));
+                    },
+                }
+                // every particle is kept exactly once, with its own parameters
+                prop_assert_eq!(particles.live().len() + particles.dead().len(), added);
+                prop_assert_eq!(particles.theta.len(), added);
+                prop_assert_eq!(particles.yhat.len(), particles.live().len());
+                for p in particles.iter_sorted() {
+                    prop_assert_eq!(particles.theta(p)[0], p.log_l());
+                }
+                prop_assert!(particles.live().iter().all(|p| particles.yhat(p) == Some(&[p.log_l()][..])));
+                prop_assert!(particles.dead().iter().all(|p| particles.yhat(p).is_none()));
+                // the live set is sorted, the dead ones rise, and all of
+                // them lie below every live particle
+                let log_l: Vec<f64> = particles.iter_sorted().map(|p| p.log_l()).collect();
+                prop_assert!(log_l.windows(2).all(|w| w[0] <= w[1]), "{:?}", log_l);
+            }
+        }
     }
 
     #[test]
@@ -81,45 +330,656 @@ mod tests {
         println!("{:?}", particles);
         let part = Particle::new_with_all(
             0.5,
-            vec![-1.0; 2],
-            vec![-1.0; 2],
             0.000001,
             20,
         );
-        particles.add_to_live(part).unwrap();
+        particles.add_to_live(part, &[-1.0; 2], &[-1.0; 2]).unwrap();
         assert_eq!(particles.live.len(), 4);
         assert_eq!(particles.dead.len(), 0);
         assert_eq!(particles.live[1].eps, 0.5);
-        assert_eq!(particles.live[1].w, 0.000001);
+        assert_eq!(particles.live[1].log_w, 0.000001);
 
         let part = Particle::new_with_all(
             0.4,
-            vec![-1.0; 2],
-            vec![-1.0; 2],
             0.111,
             20,
         );
-        particles.add_to_live(part).unwrap();
+        particles.add_to_live(part, &[-1.0; 2], &[-1.0; 2]).unwrap();
+        assert_eq!(particles.theta(&particles.live()[1]), &[-1.0, -1.0]);
         assert_eq!(particles.live.len(), 5);
         assert_eq!(particles.live[1].eps, 0.4);
-        assert_eq!(particles.live[1].w, 0.111);
+        assert_eq!(particles.live[1].log_w, 0.111);
+    }
+
+    #[test]
+    fn test_observer_sees_the_live_set() {
+        struct Spread {
+            seen: Vec<(usize, usize)>,
+        }
+
+        impl Observer for Spread {
+            fn every(&self) -> usize {
+                50
+            }
+
+            fn on_iteration(&mut self, iteration: usize, particles: &Particles) {
+                self.seen.push((iteration, particles.live().len()));
+            }
+        }
+
+        let data_file = std::env::temp_dir().join(format!("ns_lib_{}_line.csv", std::process::id()));
+        let rows: String = (0..20).map(|i| format!("{},{}\n", i as f64 / 10.0, 1.0 + 2.0 * i as f64 / 10.0)).collect();
+        std::fs::write(&data_file, format!("x,y\n{}", rows)).unwrap();
+        let config = Config{
+            data_file: data_file.clone(),
+            sample_num: 200,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let mut spread = Spread{ seen: Vec::new() };
+        run_observed(&config, &mut spread).unwrap();
+        std::fs::remove_file(data_file).unwrap();
+        assert_eq!(spread.seen, vec![(49, 20), (99, 20), (149, 20), (199, 20)]);
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let root = std::env::temp_dir().join(format!("ns_lib_{}_resume", std::process::id()));
+        let data_file = std::env::temp_dir().join(format!("ns_lib_{}_resume.csv", std::process::id()));
+        let rows: String = (0..20).map(|i| format!("{},{}\n", i as f64 / 10.0, 1.0 + 2.0 * i as f64 / 10.0)).collect();
+        std::fs::write(&data_file, format!("x,y\n{}", rows)).unwrap();
+        let mut config = Config{
+            data_file: data_file.clone(),
+            sample_num: 100,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            checkpoint_every: Some(20),
+            checkpoint: CheckpointConfig{ keep: 2, interval_secs: None },
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let dir = RunDir::create(&root, "line", false).unwrap();
+        assert!(run_in_dir(&config, &dir, true, &mut observer::Collect::default()).is_err());
+        run_in_dir(&config, &dir, false, &mut observer::Collect::default()).unwrap();
+        let checkpoints = dir.checkpoints().unwrap();
+        assert_eq!(checkpoints.len(), 2);
+        assert!(checkpoints[0].ends_with(Checkpoint::file_name(80)));
+        assert!(checkpoints[1].ends_with(Checkpoint::file_name(100)));
+
+        // a damaged newest checkpoint falls back to the one before, and
+        // the run carries on past the end of the first
+        let mut bytes = std::fs::read(&checkpoints[1]).unwrap();
+        bytes[100] ^= 0xff;
+        std::fs::write(&checkpoints[1], bytes).unwrap();
+        config.sample_num = 150;
+        let mut warnings = observer::Collect::default();
+        let result = run_in_dir(&config, &dir, true, &mut warnings).unwrap();
+        assert!(warnings.warnings.iter().any(|w| w.contains("checksum") && w.contains("falling back")), "{:?}", warnings.warnings);
+        assert_eq!(result.iterations, 150);
+        assert_eq!(result.posterior.len(), 170);
+        assert_eq!(result.shrinkage.n_live.len(), 150);
+        assert!(result.log_z.is_finite());
+        assert!(dir.path().join("summary.toml").is_file());
+        let written = Config::load(&dir.config_file()).unwrap();
+        assert_eq!(written.sample_num, 150);
+        assert_eq!(written.checkpoint.keep, 2);
+        std::fs::remove_file(data_file).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_config_round_trip_fills_in_defaults() {
+        let text = r#"
+            data_file = "data.csv"
+            sample_num = 100
+            particle_num = 20
+            beta_num = 0
+            shrinkage = "ties"
+
+            [copula]
+            correlation = [[1.0, 0.5], [0.5, 1.0]]
+
+            [[copula.marginals]]
+            dist = "normal"
+            mu = 0.0
+            sd = 1.0
+
+            [[copula.marginals]]
+            dist = "mixture"
+            components = [
+                { weight = 0.5, dist = "normal", mu = 0.0, sd = 0.01 },
+                { weight = 0.5, dist = "uniform", lower = -1.0, upper = 1.0 },
+            ]
+
+            [sampler]
+            method = "hit_and_run"
+        "#;
+        let config = Config::from_toml(text).unwrap();
+        let canonical = config.to_toml().unwrap();
+        assert!(canonical.contains("noise_model"), "{}", canonical);
+        assert!(!canonical.contains("noise_sd"), "{}", canonical);
+        let again = Config::from_toml(&canonical).unwrap();
+        assert_eq!(again.to_toml().unwrap(), canonical);
+        assert_eq!(again.shrinkage, ShrinkageMode::Ties);
+        assert_eq!(again.sampler.method, sampler::Method::HitAndRun);
+        assert_eq!(again.copula.unwrap().marginals, config.copula.unwrap().marginals);
+    }
+
+    #[test]
+    fn test_control_file_and_tolerance_end_runs_early() {
+        let root = std::env::temp_dir().join(format!("ns_lib_{}_control", std::process::id()));
+        let data_file = std::env::temp_dir().join(format!("ns_lib_{}_control.csv", std::process::id()));
+        let rows: String = (0..20).map(|i| format!("{},{}\n", i as f64 / 10.0, 1.0 + 2.0 * i as f64 / 10.0)).collect();
+        std::fs::write(&data_file, format!("x,y\n{}", rows)).unwrap();
+        let mut config = Config{
+            data_file: data_file.clone(),
+            sample_num: 400,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let dir = RunDir::create(&root, "controlled", false).unwrap();
+        std::fs::write(dir.control_file(), "sample_num = 30\ncheckpoint = true\n").unwrap();
+        let result = run_in_dir(&config, &dir, false, &mut observer::Collect::default()).unwrap();
+        assert_eq!(result.iterations, 30);
+        assert!(dir.latest_checkpoint().unwrap().unwrap().ends_with(Checkpoint::file_name(1)));
+
+        std::fs::remove_file(dir.control_file()).unwrap();
+        config.tolerance = Some(0.01);
+        let result = run_in_dir(&config, &dir, false, &mut observer::Collect::default()).unwrap();
+        assert!(result.iterations < 400 && result.truncation.is_empty(), "{} {:?}", result.iterations, result.truncation);
+        std::fs::remove_file(data_file).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_summaries_are_kept_for_every_posterior_point() {
+        use models::{Summary, SummaryLikelihood};
+        let forward = |theta: &[f64]| Some((0..10).map(|t| theta[0] + theta[1] * t as f64).collect::<Vec<f64>>());
+        let observed = forward(&[1.0, 0.5]).unwrap();
+        let summaries = vec![Summary::new("ends", |y: &[f64]| vec![y[0], y[9]])];
+        let covariance = linalg::Matrix::diag(&[0.1, 0.1]);
+        let model = SummaryLikelihood::new(forward, 2, &observed, summaries, &covariance).unwrap();
+        let config = Config{
+            sample_num: 60,
+            particle_num: 10,
+            mu: vec![1.0, 0.5],
+            sd: vec![1.0, 1.0],
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(412);
+        let result = run_with_model(&config, &model, &mut observer::Collect::default(), &mut rng).unwrap();
+        assert_eq!(result.outputs.len(), result.posterior.len());
+        for ((theta, _), outputs) in result.posterior.iter().zip(&result.outputs) {
+            assert_eq!(outputs, &model.summaries_at(theta).unwrap());
+        }
+        assert!(run_with_model(&Config{ mu: vec![1.0], ..config }, &model, &mut observer::Collect::default(), &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_seeded_runs_of_a_stochastic_likelihood_repeat() {
+        let x: Vec<f64> = (0..40).map(|i| i as f64 / 20.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x + 0.1 * (7.0 * x).sin()).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let config = Config{
+            sample_num: 80,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            subsample: Some(8),
+            seed: Some(465),
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, parallel_chains: 4, ..Default::default() },
+            ..Default::default()
+        };
+        let run = |config: &Config| run_with_data(config, &data, &mut observer::Collect::default(), &mut config.rng()).unwrap();
+        let first = run(&config);
+        let again = run(&config);
+        assert_eq!(first.log_z, again.log_z);
+        assert_eq!(first.posterior, again.posterior);
+        let other = run(&Config{ seed: Some(466), ..config });
+        assert_ne!(first.log_z, other.log_z);
+    }
+
+    #[test]
+    fn test_dead_points_stream_to_the_compact_posterior() {
+        let out = std::env::temp_dir().join(format!("ns_lib_{}_stream.nsp", std::process::id()));
+        let x: Vec<f64> = (0..20).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let config = Config{
+            sample_num: 100,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            posterior_file: Some(out.clone()),
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let result = run_with_data(&config, &data, &mut observer::Collect::default(), &mut StdRng::seed_from_u64(437)).unwrap();
+        let compact = posterior::Posterior::open(&out).unwrap();
+        assert_eq!(compact.len(), result.posterior.len());
+        assert!((compact.log_total() - result.log_z).abs() < 1e-9);
+        let mean = compact.mean().unwrap();
+        for (j, m) in mean.iter().enumerate() {
+            let expected: f64 = result.posterior.iter().map(|(t, lw)| lw.exp() * t[j]).sum();
+            assert!((m - expected).abs() < 1e-9);
+        }
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn test_provenance_of_every_particle() {
+        let out = std::env::temp_dir().join(format!("ns_lib_{}_provenance.csv", std::process::id()));
+        let x: Vec<f64> = (0..20).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let config = Config{
+            sample_num: 100,
+            particle_num: 20,
+            mu: vec![1.0, 2.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            provenance_file: Some(out.clone()),
+            sampler: SamplerConfig{ method: sampler::Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(425);
+        run_with_data(&config, &data, &mut observer::Collect::default(), &mut rng).unwrap();
+        let text = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        let rows: Vec<Vec<&str>> = text.lines().skip(1).map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 120);
+        assert_eq!(rows.iter().filter(|r| r[1].is_empty()).count(), 20);
+        for row in &rows {
+            let particle: usize = row[0].parse().unwrap();
+            let calls: usize = row[5].parse().unwrap();
+            if particle < 20 {
+                assert_eq!((row[4], calls, row[6]), ("initial", 1, ""));
+            } else {
+                // chains start from an older particle and evaluate every accepted step
+                assert_eq!(row[4], "random_walk");
+                assert!(row[6].parse::<usize>().unwrap() < particle);
+                assert!(calls >= row[7].parse::<usize>().unwrap() && calls > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sorted_views() {
+        let mut particles = set_up_test_particles();
+        particles.move_worst_to_dead();
+        assert_eq!(particles.worst().unwrap().log_l(), 1.0);
+        assert_eq!(particles.dead().len(), 1);
+        let eps: Vec<f64> = particles.iter_sorted().map(|p| p.log_l()).collect();
+        assert_eq!(eps, vec![0.0, 1.0, 2.0]);
+        assert_eq!(particles.clone().live().len(), 2);
+
+        let snapshot = particles.snapshot();
+        assert!(Arc::ptr_eq(&snapshot, &particles.snapshot()));
+        particles.move_worst_to_dead();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(particles.snapshot().len(), 1);
+
+        // dead particles keep their parameters but give back their yhat
+        let dead = &particles.dead()[1];
+        assert_eq!(particles.theta(dead), &[1.0, 1.0]);
+        assert!(particles.yhat(dead).is_none());
+        assert_eq!(particles.yhat(particles.worst().unwrap()), Some(&[3.0, 3.0][..]));
     }
 
 }
 
 
 /// Simple struct to hold command line arguments
-#[derive(Deserialize, Debug)]
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
     pub data_file: PathBuf,
     pub sample_num: usize,
     pub particle_num: usize,
     pub beta_num: usize,
+    #[serde(default)]
     pub mu: Vec<f64>,
+    #[serde(default)]
     pub sd: Vec<f64>,
+    /// marginals tied by a Gaussian copula, a prior in place of the
+    /// independent normals given by mu and sd, which stay empty
+    pub copula: Option<CopulaConfig>,
+    /// name of a prior registered in code with `priors::register_prior`,
+    /// in place of mu and sd, which stay empty
+    pub registered_prior: Option<String>,
+    /// names of constraints g(theta) <= 0, registered in code with
+    /// `priors::register_constraint`, outside of which the prior is zero
+    #[serde(default)]
+    pub constraints: Vec<String>,
+    /// restrict the prior to the bounds learned by an earlier run; log_z
+    /// is still that of the full prior, corrected by the prior mass of
+    /// the bounds (reported as bounds_log_mass)
+    pub restrict: Option<RestrictConfig>,
+    /// name of a registered likelihood, or "library::model" for one
+    /// loaded from a plugin; inferred from the dpmm, multivariate and
+    /// script sections, and otherwise "regression", if absent
+    pub model: Option<String>,
+    /// directories searched for plugin libraries
+    #[serde(default)]
+    pub plugin_dirs: Vec<PathBuf>,
+    /// known noise sd of the regression; sampled as the last parameter if absent
+    pub noise_sd: Option<f64>,
+    /// evaluate the likelihood on random minibatches of this many observations
+    pub subsample: Option<usize>,
+    /// parameters used as the control variate for subsampled likelihoods
+    pub subsample_reference: Option<Vec<f64>>,
+    /// data column flagging censored observations (0 observed, 1 left,
+    /// 2 right, 3 interval)
+    pub censor_column: Option<String>,
+    /// data column holding the upper bound of interval-censored observations
+    pub upper_column: Option<String>,
+    /// lower truncation bound of the response
+    pub truncate_lower: Option<f64>,
+    /// upper truncation bound of the response
+    pub truncate_upper: Option<f64>,
+    /// file holding the covariance of the observations, an n x n matrix
+    /// one row per line. The noise covariance is then noise_sd^2 times it,
+    /// so noise_sd = 1 uses it as is
+    pub noise_cov_file: Option<PathBuf>,
+    /// data columns whose regression coefficients are integrated out
+    /// analytically against a Gaussian prior instead of being sampled
+    pub nuisance: Option<NuisanceConfig>,
+    /// correlation of the residuals: "white", "ar1" or "arma11"
+    #[serde(default)]
+    pub noise_model: NoiseModel,
+    /// the likelihood is a Monte Carlo estimate (e.g. a stochastic
+    /// simulation or particle filter): its declared variance, and how many
+    /// estimates are averaged for every evaluation
+    pub noisy: Option<NoisyConfig>,
+    /// evaluate the likelihood in this many worker processes instead of
+    /// this one, for likelihood code that is not thread-safe
+    pub workers: Option<WorkersConfig>,
+    /// memoize this many of the most recent likelihood evaluations
+    pub cache_size: Option<usize>,
+    /// pre-screen candidates with a Gaussian-process surrogate of the likelihood
+    pub surrogate: Option<SurrogateConfig>,
+    /// likelihood and derived quantities written in a Rhai script; the
+    /// derived quantities are kept with the posterior like other outputs
+    #[cfg(feature = "scripting")]
+    pub script: Option<models::ScriptConfig>,
+    /// delayed acceptance with a neural emulator of the likelihood
+    #[cfg(feature = "emulator")]
+    pub emulator: Option<EmulatorConfig>,
+    /// add batches of live points where they reduce the error the most;
+    /// sample_num then caps the iterations of each batch
+    pub dynamic: Option<DynamicConfig>,
+    /// sample by parallel tempering, with the evidence from stepping
+    /// stones, instead of nested sampling; sample_num and particle_num are
+    /// then unused
+    pub tempering: Option<TemperingConfig>,
+    /// sample by sequential Monte Carlo with adaptive tempering instead of
+    /// nested sampling; sample_num and particle_num are then unused
+    pub smc: Option<SmcConfig>,
+    /// split the posterior into separated modes and summarize each
+    pub modes: Option<ModeConfig>,
+    /// how new live points are drawn above the contour
+    #[serde(default)]
+    pub sampler: SamplerConfig,
+    /// "auto" (default), "always" or "never": sample the parameters in
+    /// units of their prior widths, which auto does when the widths span
+    /// more than three orders of magnitude. Outputs stay in the
+    /// parameters' own units
+    #[serde(default)]
+    pub rescale: RescaleMode,
+    /// write log Z, the evidence left in the live points and the ESS to
+    /// a CSV file every few iterations while the run goes on
+    pub convergence: Option<ConvergenceConfig>,
+    /// write the per-iteration shrinkage trace to this CSV file
+    pub shrinkage_trace: Option<PathBuf>,
+    /// "stochastic" (default), "deterministic" or "ties" prior-volume
+    /// shrinkage
+    #[serde(default)]
+    pub shrinkage: ShrinkageMode,
+    /// a volume model registered with `evidence::register_volume_model`,
+    /// used in place of `shrinkage`
+    pub volume_model: Option<String>,
+    /// write the dead points with their birth contours to this file, in
+    /// the dead-birth format read by anesthetic
+    pub dead_birth_file: Option<PathBuf>,
+    /// write the (log X, log L) curve of the dead points to this CSV
+    /// file, from which `curve::LikelihoodCurve` computes the partition
+    /// function at any temperature
+    pub curve_file: Option<PathBuf>,
+    /// write how every particle was drawn to this CSV file: its sampler,
+    /// likelihood calls, seed point and accepted steps
+    pub provenance_file: Option<PathBuf>,
+    /// write the weighted posterior points to this file in the compact
+    /// binary format, which `posterior::CompactPosterior` summarizes
+    /// without loading it into memory. Static runs append each dead point
+    /// as it dies; other runs write the file when they end
+    pub posterior_file: Option<PathBuf>,
+    /// stop once the posterior mean of a registered functional, e.g. a
+    /// prediction, is known to a tolerance
+    pub functional: Option<FunctionalConfig>,
+    /// write an equally weighted, thinned posterior sample
+    pub export: Option<ExportConfig>,
+    /// write getdist's .margestats and .likestats summary files
+    pub getdist: Option<GetdistConfig>,
+    /// after a run with an output directory, compare discrepancy
+    /// statistics of the data with those of replicates simulated from the
+    /// posterior, and keep the p-values in its ppc.toml
+    pub ppc: Option<PpcConfig>,
+    /// names of steps registered with `postprocess::register_post_processor`,
+    /// or the built-in "summary", "diagnostics" and "export", run in order
+    /// on the directory of a run once its outputs are written
+    #[serde(default)]
+    pub post_process: Vec<String>,
+    /// also report log Z per unit prior volume, which does not depend on
+    /// the width of priors much wider than the posterior
+    #[serde(default)]
+    pub evidence_per_volume: bool,
+    /// names, units and display scales of the parameters, in order, for
+    /// summaries and exported tables; empty, or one per parameter
+    #[serde(default)]
+    pub parameters: Vec<ParameterSpec>,
+    /// write the box and ellipsoid around the posterior bulk, for
+    /// follow-up runs to `restrict` their prior to
+    pub export_bounds: Option<BoundsExport>,
+    /// start from the posterior of a previous run instead of the prior
+    pub warm_start: Option<WarmStartConfig>,
+    /// seed the run with known parameter values: part of the live points
+    /// are drawn around them, with the likelihood corrected as for a warm
+    /// start so the evidence is still that of the prior
+    pub inject: Option<InjectConfig>,
+    /// replace the prior by the posterior of a previous run on earlier
+    /// data; log_z is then the evidence of the new data given the old
+    pub update: Option<UpdateConfig>,
+    /// fit a Dirichlet process mixture of normals to one data column
+    /// instead of the regression; theta is then [ln alpha, m0, ln kappa0,
+    /// ln a0, ln b0], the concentration and the base measure
+    pub dpmm: Option<DpmmConfig>,
+    /// regress several response columns on the remaining columns at
+    /// once, with diagonal or full noise covariance between the responses
+    pub multivariate: Option<MultivariateConfig>,
+    /// directory the `ns` command keeps its runs in, one subdirectory
+    /// per run holding the resolved config, the outputs and checkpoints
+    pub output_root: Option<PathBuf>,
+    /// name of the run's subdirectory of output_root; run-<unix seconds>
+    /// if absent
+    pub run_name: Option<String>,
+    /// seed of the run's random numbers, including those of stochastic
+    /// likelihoods, so that the run can be repeated exactly; drawn afresh
+    /// for every run if absent
+    pub seed: Option<u64>,
+    /// write a checkpoint to the run directory every this many iterations
+    pub checkpoint_every: Option<usize>,
+    /// send the progress of static runs to stdout, files or registered
+    /// sinks every so many iterations
+    pub telemetry: Option<TelemetryConfig>,
+    /// stop before sample_num once the live points hold less than this
+    /// fraction of the evidence, e.g. 0.001
+    pub tolerance: Option<f64>,
+    /// how many checkpoints are kept, and how often they are written
+    /// regardless of checkpoint_every
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+}
+
+
+#[cfg(feature = "std")]
+impl Config {
+    /// the random number generator of a run, seeded with `seed` if given
+    pub fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.seed.unwrap_or_else(|| thread_rng().gen()))
+    }
+
+    /// the registered name of the likelihood the config describes
+    pub fn model_name(&self) -> &str {
+        #[cfg(feature = "scripting")]
+        if self.model.is_none() && self.script.is_some() {
+            return "script"
+        }
+        match (&self.model, &self.dpmm, &self.multivariate) {
+            (Some(name), _, _) => name,
+            (None, Some(_), _) => "dpmm",
+            (None, None, Some(_)) => "multivariate",
+            (None, None, None) => "regression",
+        }
+    }
+
+    /// number of parameters of the prior
+    pub fn n_params(&self) -> usize {
+        match (&self.copula, &self.registered_prior) {
+            (Some(copula), _) => copula.marginals.len(),
+            (None, Some(name)) => registered_prior(name).map(|p| p.dim()).unwrap_or(0),
+            (None, None) => self.mu.len(),
+        }
+    }
+
+    /// the prior the config describes, before any update or warm start
+    pub fn prior(&self) -> Result<Arc<dyn Prior>, Box<dyn Error>> {
+        let prior: Arc<dyn Prior> = match (&self.copula, &self.registered_prior) {
+            (Some(copula), _) => Arc::new(CopulaPrior::new(copula.marginals.clone(), &copula.correlation)?),
+            (None, Some(name)) => registered_prior(name)?,
+            (None, None) => Arc::new(NormalPrior::new(&self.mu, &self.sd)?),
+        };
+        let prior: Arc<dyn Prior> = match self.constraints.is_empty() {
+            true => prior,
+            false => {
+                let constraints = self.constraints.iter()
+                    .map(|name| Ok((name.clone(), registered_constraint(name)?)))
+                    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+                Arc::new(Constrained::new(prior, constraints)?)
+            },
+        };
+        Ok(match &self.restrict {
+            Some(restrict) => Arc::new(Restricted::new(prior, &Bounds::load(&restrict.file)?, restrict.shape)?),
+            None => prior,
+        })
+    }
+
+    /// read a config from a TOML file
+    pub fn load(path: &std::path::Path) -> Result<Config, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Config::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// parse a config from TOML text
+    pub fn from_toml(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// the config as TOML with every default written out, which
+    /// `from_toml` reads back to the same config; unset options are left out
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+
+/// summary of a finished run
+///
+/// Runs by parallel tempering (see `tempering`) fill in the same fields
+/// where they apply: their posterior is the chain at beta = 1, ess its
+/// autocorrelation-corrected size and iterations the sweeps after burn-in,
+/// while shrinkage, dead_birth and truncation stay empty. SMC runs (see
+/// `smc`) return their final weighted particles, with iterations the
+/// number of tempering stages.
+///
+/// Fields:
+/// log_z: natural log of the evidence
+/// log_z_err: standard error of log_z, sqrt(H / N)
+/// info: the information H, in nats
+/// iterations: the number of particles moved to the dead set
+/// approximate: true if the likelihood biases the evidence (e.g. a
+///     subsampled likelihood), in which case log_z is only approximate
+/// log_lik_variance: for likelihoods that are Monte Carlo estimates, the
+///     variance of each log-likelihood estimate; log_z then carries extra
+///     estimator noise. None for exact likelihoods
+/// model_stats: counters reported by the likelihood (e.g. surrogate
+///     screening) and by the sampler
+/// ess: Kish effective sample size of the posterior weights
+/// targets_met: for dynamic runs, whether the log Z error and ESS targets
+///     were reached before the batch limit; None for static runs
+/// posterior: every dead point as (theta, log of its normalized posterior weight)
+/// modes: separated posterior modes, largest mass first; empty unless
+///     mode clustering was requested
+/// mode_labels: the mode of each posterior point, in the order of `posterior`
+/// shrinkage: per-iteration shrinkage and insertion ranks; empty for
+///     dynamic runs
+/// dead_birth: (log-likelihood, birth contour) of each posterior point, in
+///     the order of `posterior`
+/// cumulative_log_z: for updating runs, the log evidence of the earlier
+///     and the new data together; None otherwise
+/// cluster_counts: for mixture runs, the posterior of the number of
+///     clusters as (K, probability, standard error); empty otherwise
+/// outputs: the model's outputs at each posterior point, in the order of
+///     `posterior`; empty for models without outputs and for dynamic runs
+/// truncation: signs that the run stopped before log Z converged, also
+///     passed to the observer as warnings; empty for dynamic runs, whose
+///     batches run until their targets are met
+/// prior_kl: per parameter, the divergence in nats of its marginal
+///     posterior from its marginal prior (see `learned::prior_kl`);
+///     empty for updating runs, whose prior is an earlier posterior
+/// log_z_per_volume: log Z minus the posterior mean of the log prior
+///     density (see `learned::log_z_per_volume`), if the config asked for
+///     it; None for updating runs
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct RunResult {
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub info: f64,
+    pub iterations: usize,
+    pub approximate: bool,
+    pub log_lik_variance: Option<f64>,
+    pub model_stats: Vec<(String, f64)>,
+    pub ess: f64,
+    pub targets_met: Option<bool>,
+    pub posterior: Vec<(Vec<f64>, f64)>,
+    pub modes: Vec<Mode>,
+    pub mode_labels: Vec<usize>,
+    pub shrinkage: ShrinkageTrace,
+    pub dead_birth: Vec<(f64, f64)>,
+    pub cumulative_log_z: Option<f64>,
+    pub cluster_counts: Vec<(usize, f64, f64)>,
+    pub outputs: Vec<Vec<f64>>,
+    pub truncation: Vec<Truncation>,
+    pub prior_kl: Vec<f64>,
+    pub log_z_per_volume: Option<f64>,
 }
 
 
+#[cfg(feature = "std")]
+#[allow(dead_code)]
 trait Optimizer {
     fn log_lik(&self) -> f64;
     fn run_objective(&self) -> f64;
@@ -128,158 +988,718 @@ trait Optimizer {
 
 /// defines a particle
 ///
+/// The parameters and fitted values live in the arenas of the `Particles`
+/// that own the particle; read them with `Particles::theta` and
+/// `Particles::yhat`.
+///
 /// Fields:
 /// eps: the likelihood of this particle
-/// theta: the particle's parameter vectors
-/// yhat: the y-values implied by the particle's parameters
-/// w: the weight
+/// theta: slot of the particle's parameter vector
+/// yhat: slot of the y-values implied by the particle's parameters, freed
+///     when the particle dies
+/// log_w: log of the prior-volume weight; kept in logs so that the
+///     weights of long runs do not underflow
 /// i: the iteraction at which this particle was allocated to the dead set
-#[derive(Debug)]
-struct Particle {
+/// birth: the likelihood contour the particle was sampled above, -inf for
+///     draws from the whole prior
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Particle {
     eps: f64,
-    theta: Vec<f64>,
-    yhat: Vec<f64>,
-    w: f64,
+    theta: usize,
+    yhat: Option<usize>,
+    log_w: f64,
     i: usize,
+    birth: f64,
 }
 
 
+#[cfg(feature = "std")]
 impl Particle {
-    fn new(theta: Vec<f64>) -> Particle {
-        let eps = f64::NEG_INFINITY;
-        let yhat: Vec<f64> = Vec::new();
-        let w = 0.0;
+    fn new(eps: f64) -> Particle {
+        let log_w = f64::NEG_INFINITY;
         let i = 0;
-        Particle{ eps, theta, yhat, w, i }
+        Particle{ eps, theta: 0, yhat: None, log_w, i, birth: f64::NEG_INFINITY }
     }
 
+    #[allow(dead_code)]
     fn new_with_all(
             eps: f64,
-            theta: Vec<f64>,
-            yhat: Vec<f64>,
-            w: f64,
+            log_w: f64,
             i: usize,
     ) -> Particle {
-        Particle{ eps, theta, yhat, w, i }
+        Particle{ eps, theta: 0, yhat: None, log_w, i, birth: f64::NEG_INFINITY }
     }
 
+    #[allow(dead_code)]
     fn run(&mut self) {
     }
 
-    fn update_log_lik(&mut self) {
+    /// log-likelihood of the particle
+    pub fn log_l(&self) -> f64 {
+        self.eps
+    }
+
+    /// log of the prior-volume weight, set when the particle dies or the
+    /// run ends
+    pub fn log_weight(&self) -> f64 {
+        self.log_w
+    }
+
+    /// iteration at which the particle died
+    pub fn iteration(&self) -> usize {
+        self.i
+    }
+
+    /// likelihood contour the particle was sampled above
+    pub fn birth(&self) -> f64 {
+        self.birth
     }
 }
 
 
 /// contains the sets of live and dead particles
 /// could contain bayesian evidence, err, etc.
-#[derive(Debug)]
-struct Particles {
+///
+/// The live particles are kept sorted by likelihood and the dead ones in
+/// the order they died, so both are sorted and every dead particle is
+/// below every live one. Samplers read the live set through an immutable
+/// snapshot, which can be shared with worker threads while the particles
+/// keep changing. Parameters and fitted values are stored in arenas sized
+/// for the whole run, so replacing a particle allocates nothing. The mean
+/// and covariance of the live set are updated as particles come and go,
+/// and recomputed in full once per live-set turnover to shed rounding
+/// errors.
+///
+/// Fields:
+/// live: the live particles
+/// dead: the dead particles
+/// theta: parameters of every particle, live and dead
+/// yhat: fitted values of the live particles
+/// outputs: the model's outputs (see `LogLikelihood::outputs`) of every
+///     particle, live and dead, in the same slots as theta
+/// moments: running mean and covariance of the live parameters
+/// generation: number of changes made to the live set
+/// snapshot: copy of the live set at some generation, reused while current
+/// provenance: how each particle, in the slots of theta, was drawn, with
+///     seeds given as slots; None unless tracking was asked for
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Particles {
     live: VecDeque<Particle>,
     dead: Vec<Particle>,
+    theta: Arena,
+    yhat: Arena,
+    outputs: Arena,
+    #[serde(skip)]
+    moments: RunningCovariance,
+    generation: usize,
+    #[serde(skip)]
+    snapshot: Option<Arc<LiveSnapshot>>,
+    provenance: Option<Vec<Provenance>>,
 }
 
 
+#[cfg(feature = "std")]
 impl Particles {
-    fn new(
+    fn new<R: Rng>(
             particle_num: usize,
-            mu: &Vec<f64>,
-            sd: &Vec<f64>,
-            rng: &mut rand::rngs::ThreadRng,
+            sample_num: usize,
+            prior: &dyn Prior,
+            model: &dyn LogLikelihood,
+            rng: &mut R,
     ) -> Result<Particles, Box<dyn Error>> {
 
-        let mut live: VecDeque<Particle> = VecDeque::new();
-        let mut priors: Vec<Vec<f64>> = Vec::new();
+        let mut particles = Particles::with_capacity(prior.dim(), 0, particle_num + sample_num);
+        particles.outputs = Arena::new(model.n_outputs(), particle_num + sample_num);
+        particles.live.reserve(particle_num + 1);
 
-        // priors is a vec of vec<f64>
-        // outer vec is particles, each inner vec
-        // is a given particle's theta
+        // draw each particle's theta from the prior, evaluate it and
+        // insert it in likelihood order
+        let mut theta = vec![0.0; prior.dim()];
         for _ in 0..particle_num {
-            priors.push(Vec::new());
-        }
-
-        for (mu_i, sd_i) in zip(mu, sd) {
-            let beta_i: Vec<f64> = Normal::new(*mu_i, *sd_i)?
-                .sample_iter(&mut *rng)
-                .take(particle_num)
-                .collect();
-            // push each sample from the prior into each particle's
-            // inner vec for the given prior
-            for j in 0..particle_num {
-                priors[j].push(beta_i[j]);
-            }
+            prior.sample_into(&mut theta, rng);
+            let particle = Particle::new(model.log_lik(&theta));
+            particles.add_evaluated(particle, &theta, model)?;
         }
+        particles.generation = 0;
+        Ok(particles)
+    }
 
-        // now that we have the priors samples, intantiate particles
-        //  with their theta vecs, -Inf likelihood, and 0.0 weights
-        for i in 0..particle_num {
-            let theta = priors[i].to_vec();
-            let particle = Particle::new(theta);
-            live.push_back(particle);
+    /// no particles yet, with arena room for `slots` particles of
+    /// `dim` parameters and `n_yhat` fitted values
+    fn with_capacity(dim: usize, n_yhat: usize, slots: usize) -> Particles {
+        Particles{
+            live: VecDeque::new(),
+            dead: Vec::with_capacity(slots),
+            theta: Arena::new(dim, slots),
+            yhat: Arena::new(n_yhat, slots),
+            outputs: Arena::new(0, slots),
+            moments: RunningCovariance::new(dim),
+            generation: 0,
+            snapshot: None,
+            provenance: None,
         }
+    }
 
-        // sort particles by likelihood
-        live.make_contiguous().sort_unstable_by_key(|x| OrderedFloat(x.eps));
-        let dead: Vec<Particle> = Vec::new();
-        Ok(Particles{live, dead})
+    /// record how every particle from now on is drawn; the ones already
+    /// there are taken to be the initial draws from the prior
+    fn track_provenance(&mut self) {
+        if self.provenance.is_none() {
+            self.provenance = Some(vec![Provenance::initial(); self.theta.len()]);
+        }
     }
 
-    fn new_with_particles(
-            live: VecDeque<Particle>,
-            dead: Vec<Particle>,
-    ) -> Particles {
-        Particles{ live, dead }
+    /// (particle, iteration it died at or None if still live, log L, birth
+    /// contour, how it was drawn) of every particle, dead ones first; empty
+    /// unless provenance was tracked
+    pub fn provenance(&self) -> Vec<(usize, Option<usize>, f64, f64, Provenance)> {
+        let records = match &self.provenance {
+            Some(records) => records,
+            None => return Vec::new(),
+        };
+        let dead = self.dead.iter().map(|p| (p, Some(p.i)));
+        let live = self.live.iter().map(|p| (p, None));
+        dead.chain(live)
+            .map(|(p, died)| (p.theta, died, p.eps, p.birth, records[p.theta]))
+            .collect()
     }
 
     fn len(&self) -> usize {
         self.live.len()
     }
 
-    fn sample_to_live(&mut self) {
+    /// live particles, in increasing likelihood
+    pub fn live(&self) -> &VecDeque<Particle> {
+        &self.live
+    }
+
+    /// dead particles, in the order they died
+    pub fn dead(&self) -> &[Particle] {
+        &self.dead
+    }
+
+    /// the live particle with the lowest likelihood, next to die
+    pub fn worst(&self) -> Option<&Particle> {
+        self.live.front()
+    }
+
+    /// every particle, dead then live, in increasing likelihood
+    pub fn iter_sorted(&self) -> impl Iterator<Item = &Particle> {
+        self.dead.iter().chain(self.live.iter())
+    }
+
+    /// parameters of a particle of this set
+    pub fn theta(&self, particle: &Particle) -> &[f64] {
+        self.theta.get(particle.theta)
+    }
+
+    /// fitted values of a particle of this set, if it is still alive
+    pub fn yhat(&self, particle: &Particle) -> Option<&[f64]> {
+        particle.yhat.map(|slot| self.yhat.get(slot))
+    }
+
+    /// the model's outputs at a particle of this set; empty unless the
+    /// model has outputs
+    pub fn outputs(&self, particle: &Particle) -> &[f64] {
+        match self.outputs.width() {
+            0 => &[],
+            _ => self.outputs.get(particle.theta),
+        }
+    }
+
+    /// immutable copy of the live set, taken at most once per change
+    pub fn snapshot(&mut self) -> Arc<LiveSnapshot> {
+        if let Some(snapshot) = &self.snapshot {
+            if snapshot.generation() == self.generation {
+                return Arc::clone(snapshot)
+            }
+        }
+        // every replacement is one removal and one addition
+        if self.moments.updates() > 2 * self.live.len() {
+            let theta = &self.theta;
+            self.moments.refit(self.live.iter().map(|p| theta.get(p.theta)));
+        }
+        let live = self.live.iter().map(|p| (self.theta.get(p.theta), p.eps));
+        let snapshot = LiveSnapshot::new(self.generation, live)
+            .with_moments(self.moments.mean().to_vec(), self.moments.cov())
+            .with_ids(self.live.iter().map(|p| p.theta).collect());
+        let snapshot = Arc::new(snapshot);
+        self.snapshot = Some(Arc::clone(&snapshot));
+        snapshot
+    }
+
+    /// draw a particle that beats the most recently killed one, then
+    /// insert it into the live set; returns its rank among the live particles
+    fn sample_to_live<R: Rng>(
+            &mut self,
+            sampler: &mut Sampler,
+            model: &dyn LogLikelihood,
+            observer: &mut dyn Observer,
+            rng: &mut R,
+    ) -> Result<usize, Box<dyn Error>> {
+        let threshold = self.dead.last().map(|p| p.eps).unwrap_or(f64::NEG_INFINITY);
+        let live = self.snapshot();
+        let (theta, eps) = sampler.draw(model, threshold, &live, observer, rng)?;
+        // the snapshot names a chain's seed by its theta slot
+        if let (Some(records), Some(provenance)) = (self.provenance.as_mut(), sampler.provenance()) {
+            records.push(provenance);
+        }
+        let mut particle = Particle::new(eps);
+        particle.birth = threshold;
+        self.add_evaluated(particle, &theta, model)
     }
 
-    fn add_to_live(&mut self, new_particle: Particle) -> Result<(), Box<dyn Error>> {
+    /// `add_to_live` for a particle drawn in a run, keeping the model's
+    /// outputs at theta alongside its parameters
+    fn add_evaluated(
+            &mut self,
+            particle: Particle,
+            theta: &[f64],
+            model: &dyn LogLikelihood,
+    ) -> Result<usize, Box<dyn Error>> {
+        if self.outputs.width() > 0 {
+            let slot = self.outputs.insert(&model.outputs(theta))?;
+            // theta slots are never freed, so both arenas fill in step
+            debug_assert_eq!(slot, self.theta.len());
+        }
+        self.add_to_live(particle, theta, &[])
+    }
+
+    /// store the particle's parameters and fitted values, then insert it
+    /// into the live set; returns its rank among the live particles
+    fn add_to_live(
+            &mut self,
+            mut new_particle: Particle,
+            theta: &[f64],
+            yhat: &[f64],
+    ) -> Result<usize, Box<dyn Error>> {
+        new_particle.theta = self.theta.insert(theta)?;
+        self.moments.add(theta);
+        new_particle.yhat = Some(self.yhat.insert(yhat)?);
         let pos = self.live
             .binary_search_by_key(
                 &OrderedFloat(new_particle.eps), |a| OrderedFloat(a.eps)
             ).unwrap_or_else(|e| e);
         self.live.insert(pos, new_particle);
-        Ok(())
+        self.generation += 1;
+        Ok(pos)
     }
 
     fn move_worst_to_dead(&mut self) {
-        let worst = self.live.pop_front().unwrap();
+        let mut worst = self.live.pop_front().unwrap();
+        self.moments.remove(self.theta.get(worst.theta));
+        if let Some(slot) = worst.yhat.take() {
+            self.yhat.remove(slot);
+        }
         self.dead.push(worst);
+        self.generation += 1;
     }
 
-    fn update_worst(&mut self, w: f64, iter: usize) {
+    /// recompute the live moments, which checkpoints leave out
+    fn refit_moments(&mut self) {
+        self.moments = RunningCovariance::new(self.theta.width());
+        let theta = &self.theta;
+        self.moments.refit(self.live.iter().map(|p| theta.get(p.theta)));
+    }
+
+    /// every particle, dead then live, with the log of its posterior
+    /// weight given the log evidence; the weights stay in logs until
+    /// they are normalized
+    fn posterior(&self, log_z: f64) -> Vec<(Vec<f64>, f64)> {
+        self.iter_sorted()
+            .map(|p| (self.theta(p).to_vec(), p.log_w + p.eps - log_z))
+            .collect()
+    }
+
+    fn update_worst(&mut self, log_w: f64, iter: usize) {
         self.live[0].i = iter;
-        self.live[0].w = w;
+        self.live[0].log_w = log_w;
     }
 }
 
 
-pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
+/// assemble the likelihood described by the config
+#[cfg(feature = "std")]
+fn build_model<'a>(
+        config: &Config,
+        data: &'a Dataset,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let model: Box<dyn LogLikelihood + 'a> = match &config.workers {
+        Some(workers) => Box::new(WorkerPool::spawn(config, data, workers)?),
+        None => models::construct(config.model_name(), config, data)?,
+    };
+    wrap_model(config, model)
+}
 
-    // read in observed y vals
-    let y: Vec<f64> = fs::read_to_string(&config.data_file)?
-        .split(' ')
-        .map(|x| x.parse().unwrap())
-        .collect();
 
-    let mut rng = thread_rng();
+/// `build_model` checked against the config, so that a run builds its
+/// model, and starts its workers, once
+#[cfg(feature = "std")]
+fn checked_model<'a>(
+        config: &Config,
+        data: &'a Dataset,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let model = build_model(config, data);
+    config.check_model(model.as_deref().map_err(|e| format!("model: {}", e)))?;
+    model
+}
+
+
+/// the counting, averaging, caching and surrogate layers the config puts
+/// around its model
+#[cfg(feature = "std")]
+fn wrap_model<'a>(
+        config: &Config,
+        model: Box<dyn LogLikelihood + 'a>,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let model: Box<dyn LogLikelihood + 'a> = Box::new(Counted::new(model));
+    let model: Box<dyn LogLikelihood + 'a> = match &config.noisy {
+        Some(noisy) => Box::new(Averaged::new(model, noisy)?),
+        None => model,
+    };
+
+    let model: Box<dyn LogLikelihood + 'a> = match config.cache_size {
+        Some(capacity) => Box::new(Cached::new(model, capacity)?),
+        None => model,
+    };
+
+    #[cfg(feature = "emulator")]
+    let model: Box<dyn LogLikelihood + 'a> = match &config.emulator {
+        Some(emulator) => Box::new(NeuralEmulator::new(model, emulator.clone())),
+        None => model,
+    };
+
+    match &config.surrogate {
+        Some(surrogate) => Ok(Box::new(Surrogate::new(model, surrogate.clone()))),
+        None => Ok(model),
+    }
+}
+
+
+/// run the sampler described by the config, printing warnings to stderr
+#[cfg(feature = "std")]
+pub fn run(config: &Config) -> Result<RunResult, Box<dyn Error>> {
+    run_observed(config, &mut observer::Stderr)
+}
+
+
+/// run the sampler described by the config, reporting to `observer`
+#[cfg(feature = "std")]
+pub fn run_observed(config: &Config, observer: &mut dyn Observer) -> Result<RunResult, Box<dyn Error>> {
+    // read in the observed data. Binary data files are memory-mapped, and
+    // the model borrows its columns rather than copying them
+    let data = match Dataset::load(&config.data_file) {
+        Ok(data) => data,
+        Err(e) => {
+            config.check(Err(format!("cannot read {}: {}", config.data_file.display(), e)))?;
+            return Err(e)
+        },
+    };
+    run_with_data(config, &data, observer, &mut config.rng())
+}
+
+
+/// run the sampler described by the config on `data` instead of the
+/// config's data file, drawing all random numbers from `rng`
+#[cfg(feature = "std")]
+pub fn run_with_data<R: Rng>(
+        config: &Config,
+        data: &Dataset,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<RunResult, Box<dyn Error>> {
+    let model = checked_model(config, data)?;
+    run_core(config, model.as_ref(), Some(data), observer, rng, None, None)
+}
+
+
+/// run the sampler on a likelihood built in code, e.g. a
+/// `SummaryLikelihood` with registered summaries, instead of the one the
+/// config describes. The config's data file and likelihood options are
+/// not used
+#[cfg(feature = "std")]
+pub fn run_with_model<R: Rng>(
+        config: &Config,
+        model: &dyn LogLikelihood,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<RunResult, Box<dyn Error>> {
+    config.check_model(Ok(model))?;
+    run_core(config, model, None, observer, rng, None, None)
+}
+
+
+/// run the sampler in a run directory, writing its config there first,
+/// checkpointing into it every `checkpoint_every` iterations and writing
+/// the outputs there at the end. With `resume`, carry on from the run's
+/// latest checkpoint
+#[cfg(feature = "std")]
+pub fn run_in_dir(
+        config: &Config,
+        dir: &RunDir,
+        resume: bool,
+        observer: &mut dyn Observer,
+) -> Result<RunResult, Box<dyn Error>> {
+    // what is run is always recoverable from the directory, even when it fails
+    dir.write_config(config)?;
+    let data = match Dataset::load(&config.data_file) {
+        Ok(data) => data,
+        Err(e) => {
+            config.check(Err(format!("cannot read {}: {}", config.data_file.display(), e)))?;
+            return Err(e)
+        },
+    };
+    let checkpoint = match resume {
+        true => match dir.latest_intact_checkpoint(observer)? {
+            Some(checkpoint) => Some(checkpoint),
+            None => return Err(format!("{} has no intact checkpoint to resume from", dir.path().display()).into()),
+        },
+        false => None,
+    };
+    let model = checked_model(config, &data)?;
+    let rng = &mut config.rng();
+    let result = run_core(config, model.as_ref(), Some(&data), observer, rng, Some(dir), checkpoint)?;
+    dir.write_outputs(&result)?;
+    if let Some(settings) = &config.ppc {
+        match ppc::posterior_predictive(config, &data, &result.posterior, settings, rng) {
+            Ok(report) => {
+                for check in report.flagged() {
+                    observer.warn(&format!(
+                        "posterior predictive p-value of {} is {:.3}: the data are extreme among the model's replicates",
+                        check.statistic, check.p_value,
+                    ));
+                }
+                report.write(&dir.ppc_file())?;
+            },
+            Err(e) => observer.warn(&format!("posterior predictive checks skipped: {}", e)),
+        }
+    }
+    // the run itself succeeded, so a failing step only warns
+    if let Err(e) = postprocess::post_process(dir, config, &config.post_process, observer) {
+        observer.warn(&format!("post-processing stopped: {}", e));
+    }
+    Ok(result)
+}
+
+
+/// carry on the finished run in `dir` with `add_live` more live points
+/// and merge them with its dead points; the run's outputs are replaced by
+/// those of the merged run
+#[cfg(feature = "std")]
+pub fn extend_run(
+        config: &Config,
+        dir: &RunDir,
+        add_live: usize,
+        observer: &mut dyn Observer,
+) -> Result<RunResult, Box<dyn Error>> {
+    if config.warm_start.is_some() || config.inject.is_some() || config.update.is_some() {
+        return Err("runs with warm_start, inject or update cannot be extended".into())
+    }
+    let data = Dataset::load(&config.data_file)?;
+    let model = checked_model(config, &data)?;
+    let points = output::read_dead_birth(&dir.dead_birth_file(), config.n_params())?;
+    let prior = config.prior()?;
+    let mut sampler = Sampler::new(&config.sampler, prior);
+    let rng = &mut config.rng();
+    model.reseed(rng.gen());
+    let batch = DynamicConfig{ batch_size: add_live, ..config.dynamic.clone().unwrap_or_default() };
+    let (result, _) = failed(model.as_ref(), dynamic::extend(model.as_ref(), &mut sampler, points, config.sample_num, &batch, observer, rng))?;
+    let result = finish(result, config, Some(&data), None, false, observer, rng)?;
+    dir.write_outputs(&result)?;
+    Ok(result)
+}
+
+
+/// `result` of a batch of evaluations of `model`, or the model's failure
+/// if it failed meanwhile, whatever the sampler made of its -inf answers
+#[cfg(feature = "std")]
+fn failed<T>(model: &dyn LogLikelihood, result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    match model.failure() {
+        Some(failure) => Err(failure.into()),
+        None => result,
+    }
+}
+
+
+/// the nested sampling loop on a checked config and its model. `data` is
+/// what the model was built from, if it was built from the config
+#[cfg(feature = "std")]
+fn run_core<R: Rng>(
+        config: &Config,
+        model: &dyn LogLikelihood,
+        data: Option<&Dataset>,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+        dir: Option<&RunDir>,
+        resume: Option<Checkpoint>,
+) -> Result<RunResult, Box<dyn Error>> {
+    if resume.is_some() && (config.warm_start.is_some() || config.inject.is_some() || config.update.is_some()
+        || config.dynamic.is_some() || config.tempering.is_some() || config.smc.is_some()) {
+        return Err("only static runs without warm_start, inject or update can be resumed".into())
+    }
+    // stochastic likelihoods draw from the run's random numbers too
+    model.reseed(rng.gen());
+    let mut telemetry;
+    let observer: &mut dyn Observer = match &config.telemetry {
+        Some(settings) => {
+            telemetry = Telemetry::from_config(observer, settings)?;
+            &mut telemetry
+        },
+        None => observer,
+    };
+
+    let prior = config.prior()?;
+    let (prior, previous_log_z): (Arc<dyn Prior>, Option<f64>) = match &config.update {
+        Some(update) => {
+            let (bridge, log_z) = updating::load(update, prior, rng)?;
+            (Arc::new(bridge), Some(log_z))
+        },
+        None => (prior, None),
+    };
+    // a warm start draws from a reference distribution built from the
+    // previous posterior and corrects the likelihood by prior / reference,
+    // which leaves the evidence unchanged; injected points are handled
+    // the same way, with the reference built around them
+    let reference: Option<Arc<dyn Prior>> = match (&config.warm_start, &config.inject) {
+        (Some(warm), _) => Some(Arc::new(WarmStart::load(warm, Arc::clone(&prior), rng)?)),
+        (None, Some(inject)) => {
+            let (reference, rejected) = WarmStart::around(inject, Arc::clone(&prior))?;
+            if !rejected.is_empty() {
+                observer.warn(&format!("injected points {:?} lie outside the prior's support and were left out", rejected));
+            }
+            Some(Arc::new(reference))
+        },
+        (None, None) => None,
+    };
+    let (prior, model): (Arc<dyn Prior>, Box<dyn LogLikelihood + '_>) = match reference {
+        Some(reference) => {
+            let model = Repartitioned::new(model, prior, Arc::clone(&reference));
+            (reference, Box::new(model))
+        },
+        None => (prior, Box::new(model)),
+    };
+    // parameters whose widths differ by orders of magnitude are sampled
+    // in units of their widths and mapped back in the result
+    let scaling = Scaling::choose(config.rescale, prior.as_ref());
+    let (prior, model, sampler_config): (Arc<dyn Prior>, Box<dyn LogLikelihood + '_>, SamplerConfig) = match &scaling {
+        Some(scaling) => {
+            if config.rescale == RescaleMode::Auto {
+                observer.warn(&format!(
+                    "the prior widths span {:.0} orders of magnitude; sampling the parameters in units of their widths",
+                    Scaling::ratio(&scaling.scale).log10(),
+                ));
+            }
+            let sampler_config = SamplerConfig{
+                scales: config.sampler.scales.as_ref().map(|scales| scaling.scaled(scales)),
+                ..config.sampler.clone()
+            };
+            (Arc::new(Rescaled::new(prior, scaling.clone())), Box::new(RescaledModel::new(model, scaling.clone())), sampler_config)
+        },
+        None => (prior, model, config.sampler.clone()),
+    };
+    let restore = |result: RunResult| match &scaling {
+        Some(scaling) => scaling.restore(result),
+        None => result,
+    };
+    // the functional is of the parameters in their own units
+    let functional: Option<(&FunctionalConfig, Functional)> = match &config.functional {
+        Some(settings) => {
+            let f = registered_functional(&settings.name)?;
+            let f: Functional = match &scaling {
+                Some(scaling) => {
+                    let scaling = scaling.clone();
+                    Arc::new(move |z: &[f64]| f(&scaling.unscaled(z)))
+                },
+                None => f,
+            };
+            Some((settings, f))
+        },
+        None => None,
+    };
+    let model: &dyn LogLikelihood = model.as_ref();
+    if let Some(tempering) = &config.tempering {
+        let result = failed(model, tempering::run_tempering(model, prior.as_ref(), tempering, observer, rng))?;
+        return finish(restore(result), config, data, previous_log_z, false, observer, rng)
+    }
+    if let Some(smc) = &config.smc {
+        let result = failed(model, smc::run_smc(model, prior.as_ref(), smc, observer, rng))?;
+        return finish(restore(result), config, data, previous_log_z, false, observer, rng)
+    }
+
+    let mut sampler = Sampler::new(&sampler_config, Arc::clone(&prior));
+    if let (Some(tuning), None) = (&config.sampler.tuning, &resume) {
+        failed(model, sampler.tune(model, tuning, config.particle_num, rng))?;
+    }
+
+    if let Some(dynamic) = &config.dynamic {
+        // the merged dead points are summarized in result.posterior
+        let (result, _) = failed(model, dynamic::run_dynamic(
+            model,
+            &mut sampler,
+            config.particle_num,
+            config.sample_num,
+            dynamic,
+            observer,
+            rng,
+        ))?;
+        return finish(restore(result), config, data, previous_log_z, false, observer, rng)
+    }
 
     // set up live particles
     // each particle should only have loglik, beta vec, weight. Weights
     // should initialize to 0.0 and loglik to -Inf
-    let mut particles = Particles::new(
-        config.particle_num,
-        &config.mu,
-        &config.sd,
-        &mut rng,
-    )?;
-
-    let dist = Beta::new(1.0, particles.len() as f64)?;
+    let (mut particles, mut evidence, mut shrinkage, mut trace, start) = match resume {
+        Some(checkpoint) => {
+            let mut particles = checkpoint.particles;
+            particles.refit_moments();
+            sampler.restore(checkpoint.sampler);
+            (particles, checkpoint.evidence, checkpoint.shrinkage, checkpoint.trace, checkpoint.iteration)
+        },
+        None => {
+            let particles = failed(model, Particles::new(
+                config.particle_num,
+                config.sample_num,
+                prior.as_ref(),
+                model,
+                rng,
+            ))?;
+            (particles, Evidence::new(), Shrinkage::new(config.shrinkage), ShrinkageTrace::default(), 0)
+        },
+    };
+    let mut volume: Box<dyn VolumeModel> = match &config.volume_model {
+        Some(name) => registered_volume_model(name)?,
+        None => Box::new(shrinkage.mode()),
+    };
+    if particles.theta.width() != prior.dim() {
+        return Err(format!(
+            "the checkpoint has {} parameters but the config {}",
+            particles.theta.width(), prior.dim(),
+        ).into())
+    }
+    // dead points are appended to the compact posterior as they die, so a
+    // run cut short still leaves the part it got through on disk; a
+    // resumed run starts the file again from its checkpoint
+    let unscaled = |theta: &[f64]| match &scaling {
+        Some(scaling) => scaling.unscaled(theta),
+        None => theta.to_vec(),
+    };
+    let mut compact = match &config.posterior_file {
+        Some(path) => {
+            let mut writer = posterior::PosteriorWriter::create(path, prior.dim())?;
+            for p in &particles.dead {
+                writer.push(&unscaled(particles.theta(p)), p.log_w + p.eps)?;
+            }
+            Some(writer)
+        },
+        None => None,
+    };
+    if config.provenance_file.is_some() {
+        if start > 0 && particles.provenance.is_none() {
+            observer.warn("the checkpoint did not track provenance; particles drawn before it are listed as initial");
+        }
+        particles.track_provenance();
+    }
 
     // sample new live particle with higher likelihood than current lowest in live set
     // use gaussian proc as described by Khammash?
@@ -289,34 +1709,272 @@ pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
     //let mut w: Vec<f64> = Vec::new();
     //let mut l: Vec<f64> = Vec::new();
 
-    let x_i = 1.0;
+    let mut convergence = config.convergence.as_ref().map(ConvergenceTrace::create).transpose()?;
+    let mut rise = LikelihoodRise::default();
+    let mut last_checkpoint = Instant::now();
+    let mut control = dir.map(|dir| ControlFile::new(dir.control_file(), Duration::from_secs(CONTROL_POLL_SECS)));
+    let (mut sample_num, mut tolerance) = (config.sample_num, config.tolerance);
     // replace definite sample num with some convergence criterion
     //let mut converged = false;
 
     //while !converged {
-    for i in 0..config.sample_num {
+    // values of the functional at the dead points, kept in step with them
+    let mut values: Vec<f64> = match &functional {
+        Some((_, f)) => particles.dead.iter().map(|p| f(particles.theta(p))).collect(),
+        None => Vec::new(),
+    };
+    let mut i = start;
+    while i < sample_num {
 
         // I'll use notations from Mikelson and Khammash, 2020
-        // sample from Beta distribution to get the relative allocation of remaining
-        // volume to this likelihood
-        let t: f64 = dist.sample(&mut rng);
-
-        let x_im = x_i;
-        let x_i = t * x_im;
-        let w_i = x_im - x_i;
+        // shrink the remaining volume by t ~ Beta(N, 1), with N the number
+        // of live particles right now, and allocate the difference to
+        // this likelihood
+        let n_live = particles.len();
+        let worst = particles.live[0].eps;
+        let n_tied = particles.live.iter().take_while(|p| p.eps == worst).count();
+        let (log_w, log_t) = shrinkage.step_with(volume.as_mut(), n_live, n_tied, rng);
 
         // simulate system
 
         //println!("Calculating log-likelihood.");
         //let l_i = 0.0; //signals.log_lik(&y)?;
         //println!("Log likelihood: {:?}", log_lik);
-        particles.update_worst(w_i, i);
+        evidence.add(log_w, particles.live[0].eps);
+        particles.update_worst(log_w, i);
         particles.move_worst_to_dead();
-        particles.sample_to_live();
+        if let (Some((_, f)), Some(dead)) = (&functional, particles.dead.last()) {
+            values.push(f(particles.theta(dead)));
+        }
+        if let (Some(writer), Some(dead)) = (compact.as_mut(), particles.dead.last()) {
+            writer.push(&unscaled(particles.theta(dead)), dead.log_w + dead.eps)?;
+        }
+        let rank = failed(model, particles.sample_to_live(&mut sampler, model, observer, rng))?;
+        trace.push(n_live, log_t, rank);
+        rise.push(shrinkage.log_x(), particles.live.back().map_or(f64::NEG_INFINITY, |p| p.eps));
+        let every = observer.every();
+        if every > 0 && (i + 1) % every == 0 {
+            observer.on_iteration(i, &particles);
+        }
+        if let Some(convergence) = convergence.as_mut() {
+            let best = particles.live.back().map_or(f64::NEG_INFINITY, |p| p.eps);
+            convergence.record(i, evidence.log_z(), best + shrinkage.log_x(), evidence.ess())?;
+        }
+        let (mut requested, mut stop) = (false, false);
+        if let Some(control) = control.as_mut() {
+            match control.poll() {
+                Ok(Some(update)) => {
+                    sample_num = update.sample_num.unwrap_or(sample_num);
+                    tolerance = update.tolerance.or(tolerance);
+                    requested = update.checkpoint || update.stop;
+                    stop = update.stop;
+                },
+                Ok(None) => {},
+                Err(e) => observer.warn(&format!("ignoring the control file: {}", e)),
+            }
+        }
+        if let Some(tolerance) = tolerance {
+            let log_w_live = shrinkage.log_w_live(particles.len());
+            let live_log_z = particles.live.iter().fold(f64::NEG_INFINITY, |z, p| log_add_exp(z, log_w_live + p.eps));
+            stop |= live_log_z - log_add_exp(evidence.log_z(), live_log_z) < tolerance.ln();
+        }
+        if let Some((settings, f)) = &functional {
+            if (i + 1).is_multiple_of(settings.every) {
+                stop |= functional_estimate(&particles, &shrinkage, f, &values).std_error < settings.tolerance;
+            }
+        }
+        if let Some(dir) = dir {
+            let due = requested
+                || config.checkpoint_every.is_some_and(|every| (i + 1) % every == 0)
+                || config.checkpoint.interval_secs.is_some_and(|secs| last_checkpoint.elapsed().as_secs() >= secs);
+            if due {
+                let checkpoint = Checkpoint::new(
+                    i + 1,
+                    particles.clone(),
+                    evidence,
+                    shrinkage,
+                    trace.clone(),
+                    sampler.state(),
+                );
+                checkpoint.write(&dir.checkpoint_dir().join(Checkpoint::file_name(i + 1)))?;
+                if let Some(writer) = compact.as_mut() {
+                    writer.flush()?;
+                }
+                dir.prune_checkpoints(config.checkpoint.keep)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+        i += 1;
+        if stop {
+            break
+        }
+
+    }
+
+    let functional_stats = match &functional {
+        Some((_, f)) => {
+            let estimate = functional_estimate(&particles, &shrinkage, f, &values);
+            vec![
+                ("functional_mean".to_string(), estimate.mean),
+                ("functional_std_error".to_string(), estimate.std_error),
+            ]
+        },
+        None => Vec::new(),
+    };
+    // the remaining volume is shared equally by the live particles
+    let log_w_live = shrinkage.log_w_live(particles.len());
+    let live_log_z = particles.live.iter().fold(f64::NEG_INFINITY, |z, p| log_add_exp(z, log_w_live + p.eps));
+    for particle in particles.live.iter_mut() {
+        particle.log_w = log_w_live;
+        evidence.add(log_w_live, particle.eps);
+    }
+    if let Some(writer) = compact.as_mut() {
+        for p in particles.live.iter() {
+            writer.push(&unscaled(particles.theta(p)), p.log_w + p.eps)?;
+        }
+        writer.flush()?;
+    }
+    let truncation = truncation(live_log_z, evidence.log_z(), rise.slope());
+    for warning in &truncation {
+        observer.warn(&warning.to_string());
+    }
 
+    for warning in trace.warnings() {
+        observer.warn(&warning);
+    }
+    if let Some(warning) = sampler.chain_warning() {
+        observer.warn(&warning);
+    }
+    if let Some(warning) = sampler.parent_warning() {
+        observer.warn(&warning);
+    }
+    if let Some(path) = &config.shrinkage_trace {
+        trace.write_csv(path)?;
+    }
+    if let Some(path) = &config.provenance_file {
+        output::write_provenance(path, &particles.provenance())?;
     }
 
-    Ok(())
+    let log_z = evidence.log_z();
+    let posterior = particles.posterior(log_z);
+    let dead_birth: Vec<(f64, f64)> = particles.dead.iter()
+        .chain(particles.live.iter())
+        .map(|p| (p.eps, p.birth))
+        .collect();
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let outputs: Vec<Vec<f64>> = match model.n_outputs() {
+        0 => Vec::new(),
+        _ => particles.iter_sorted().map(|p| particles.outputs(p).to_vec()).collect(),
+    };
+
+    finish(restore(RunResult{
+        log_z,
+        log_z_err: evidence.log_z_err(particles.len()),
+        info: evidence.info(),
+        iterations: i,
+        approximate: model.is_approximate(),
+        log_lik_variance: model.log_lik_variance(),
+        model_stats: [model.stats(), sampler.stats(), functional_stats].concat(),
+        ess: stats::ess(&weights),
+        targets_met: None,
+        posterior,
+        modes: Vec::new(),
+        mode_labels: Vec::new(),
+        shrinkage: trace,
+        dead_birth,
+        cumulative_log_z: None,
+        cluster_counts: Vec::new(),
+        outputs,
+        truncation,
+        prior_kl: Vec::new(),
+        log_z_per_volume: None,
+    }), config, data, previous_log_z, compact.is_some(), observer, rng)
+}
+
+
+/// the posterior mean of a functional over the dead points, whose values
+/// are given, and the live points sharing the remaining volume
+#[cfg(feature = "std")]
+fn functional_estimate(
+        particles: &Particles,
+        shrinkage: &Shrinkage,
+        f: &Functional,
+        values: &[f64],
+) -> FunctionalEstimate {
+    let dead: Vec<(f64, f64)> = particles.dead.iter().zip(values).map(|(p, v)| (p.log_w + p.eps, *v)).collect();
+    let log_w_live = shrinkage.log_w_live(particles.len());
+    let live: Vec<(f64, f64)> = particles.live.iter().map(|p| (log_w_live + p.eps, f(particles.theta(p)))).collect();
+    FunctionalEstimate::new(&dead, &live)
+}
+
+
+/// cluster the posterior into modes, add the evidence of earlier data,
+/// count mixture clusters and write the output files the config asks for
+#[cfg(feature = "std")]
+fn finish<R: Rng>(
+        mut result: RunResult,
+        config: &Config,
+        data: Option<&Dataset>,
+        previous_log_z: Option<f64>,
+        streamed: bool,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<RunResult, Box<dyn Error>> {
+    if config.restrict.is_some() {
+        let correction = result.model_stats.iter().find(|(k, _)| k == "bounds_log_mass").map(|(_, v)| *v);
+        result.log_z += correction.ok_or("the restricted run did not report the prior mass of its bounds")?;
+    }
+    result.cumulative_log_z = previous_log_z.map(|z| z + result.log_z);
+    if let Some(dpmm) = &config.dpmm {
+        let data = data.ok_or("dpmm cluster counts need the data the model was built from")?;
+        let mixture = DpmmMarginal::from_dataset(data, dpmm)?;
+        result.cluster_counts = mixture.cluster_counts(&result.posterior, dpmm, rng);
+        if let Some(path) = &dpmm.cluster_file {
+            output::write_cluster_counts(path, &result.cluster_counts)?;
+        }
+    }
+    if let Some(mode_config) = &config.modes {
+        let (modes, labels) = modes::find_modes(&result.posterior, result.log_z, mode_config);
+        result.modes = modes;
+        result.mode_labels = labels;
+    }
+    if config.update.is_none() {
+        // its own seed, so that the check leaves the run's random numbers alone
+        if let Ok(prior) = config.prior() {
+            result.prior_kl = learned::prior_kl(prior.as_ref(), &result.posterior, &mut StdRng::seed_from_u64(0));
+            let ratios = learned::width_ratios(prior.as_ref(), &result.posterior);
+            for j in learned::effectively_improper(&ratios) {
+                observer.warn(&format!(
+                    "the prior of {} is {:.0e} times wider than its posterior, so log Z mostly measures the prior's \
+                     width and falls by ln 10 with every tenfold widening; compare models with matched priors or by \
+                     log_z_per_volume",
+                    units::name(&config.parameters, j), ratios[j],
+                ));
+            }
+            if config.evidence_per_volume {
+                result.log_z_per_volume = Some(learned::log_z_per_volume(prior.as_ref(), &result.posterior, result.log_z));
+            }
+        }
+    }
+    if let Some(path) = &config.dead_birth_file {
+        output::write_dead_birth(path, &result.posterior, &result.dead_birth)?;
+    }
+    if let Some(path) = &config.curve_file {
+        curve::LikelihoodCurve::from_result(&result)?.write_csv(path)?;
+    }
+    if let (Some(path), false) = (&config.posterior_file, streamed) {
+        posterior::write_compact(path, &result.posterior)?;
+    }
+    if let Some(export) = &config.export {
+        output::write_equal_weights(export, &result.posterior, &config.parameters, rng)?;
+    }
+    if let Some(getdist) = &config.getdist {
+        output::write_getdist(getdist, &result.posterior, &result.dead_birth, &config.parameters)?;
+    }
+    if let Some(export) = &config.export_bounds {
+        learn_bounds(&result.posterior, export.mass, export.expand)?.save(&export.file)?;
+    }
+    Ok(result)
 }
 
 // Copied from https://gitlab.com/baxe/rv/-/blob/master/examples/dpgmm.rs on 2023-02-02
@@ -350,6 +2008,8 @@ pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
 //
 // This code is general to any type of mixture as long as it has a conjugate
 // prior
+#[cfg(feature = "rv")]
+#[allow(dead_code)]
 struct Dpmm<X, Fx, Pr>
 where
     Fx: Rv<X> + HasSuffStat<X>,
@@ -369,6 +2029,8 @@ where
     components: Vec<ConjugateModel<X, Fx, Pr>>,
 }
 
+#[cfg(feature = "rv")]
+#[allow(dead_code)]
 impl<X, Fx, Pr> Dpmm<X, Fx, Pr>
 where
     Fx: Rv<X> + HasSuffStat<X>,
@@ -512,6 +2174,8 @@ where
     }
 }
 
+#[cfg(feature = "rv")]
+#[allow(dead_code)]
 fn main() {
     let mut rng = rand::thread_rng();
 
@@ -0,0 +1,373 @@
+use std::ops::{Index, IndexMut};
+
+
+/// upper limit on the sweeps of `Matrix::symmetric_eigen`, which converges
+/// quadratically and needs far fewer
+const JACOBI_SWEEPS: usize = 50;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cholesky_solve() {
+        let a = Matrix::from_rows(vec![
+            vec![4.0, 2.0, 0.6],
+            vec![2.0, 5.0, 1.0],
+            vec![0.6, 1.0, 3.0],
+        ]);
+        let chol = a.cholesky().unwrap();
+        let recon = chol.l().mul(&chol.l().transpose());
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((recon[(i, j)] - a[(i, j)]).abs() < 1e-12);
+            }
+        }
+        let b = vec![1.0, -2.0, 0.5];
+        let x = chol.solve(&b);
+        let ax = a.mul_vec(&x);
+        for i in 0..3 {
+            assert!((ax[i] - b[i]).abs() < 1e-12);
+        }
+        let det = 4.0 * (5.0 * 3.0 - 1.0) - 2.0 * (2.0 * 3.0 - 0.6) + 0.6 * (2.0 - 5.0 * 0.6);
+        assert!((chol.ln_det() - f64::ln(det)).abs() < 1e-12);
+        assert!(Matrix::from_rows(vec![vec![1.0, 2.0], vec![2.0, 1.0]]).cholesky().is_none());
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let a = Matrix::from_rows(vec![
+            vec![4.0, 2.0, 0.6],
+            vec![2.0, 5.0, 1.0],
+            vec![0.6, 1.0, 3.0],
+        ]);
+        let (values, vectors) = a.symmetric_eigen();
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        assert!((values.iter().sum::<f64>() - 12.0).abs() < 1e-12);
+        for (k, value) in values.iter().enumerate() {
+            let v: Vec<f64> = (0..3).map(|i| vectors[(i, k)]).collect();
+            let av = a.mul_vec(&v);
+            assert!(av.iter().zip(&v).all(|(x, y)| (x - value * y).abs() < 1e-10));
+            assert!((v.iter().map(|x| x * x).sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+        // singular: the eigenvalue of the direction (1, -1) is zero
+        let (values, vectors) = Matrix::from_rows(vec![vec![1.0, 1.0], vec![1.0, 1.0]]).symmetric_eigen();
+        assert!(values[0].abs() < 1e-12 && (values[1] - 2.0).abs() < 1e-12);
+        assert!((vectors[(0, 0)] + vectors[(1, 0)]).abs() < 1e-12);
+    }
+}
+
+
+/// small dense row-major matrix
+///
+/// Only what the models and samplers need: products, transposes and a
+/// Cholesky factorization. Dimensions are checked with asserts, since a
+/// mismatch is always a programming error.
+///
+/// Fields:
+/// rows: number of rows
+/// cols: number of columns
+/// data: the entries, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+
+impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Matrix {
+        Matrix{ rows, cols, data: vec![0.0; rows * cols] }
+    }
+
+    pub fn identity(n: usize) -> Matrix {
+        let mut m = Matrix::zeros(n, n);
+        for i in 0..n {
+            m[(i, i)] = 1.0;
+        }
+        m
+    }
+
+    pub fn diag(d: &[f64]) -> Matrix {
+        let mut m = Matrix::zeros(d.len(), d.len());
+        for (i, di) in d.iter().enumerate() {
+            m[(i, i)] = *di;
+        }
+        m
+    }
+
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<f64>) -> Matrix {
+        assert_eq!(data.len(), rows * cols, "matrix data has the wrong length");
+        Matrix{ rows, cols, data }
+    }
+
+    pub fn from_rows(rows: Vec<Vec<f64>>) -> Matrix {
+        let n = rows.len();
+        let m = rows.first().map(|r| r.len()).unwrap_or(0);
+        assert!(rows.iter().all(|r| r.len() == m), "matrix rows differ in length");
+        Matrix{ rows: n, cols: m, data: rows.into_iter().flatten().collect() }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    pub fn row(&self, i: usize) -> &[f64] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    pub fn as_slice(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut t = Matrix::zeros(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                t[(j, i)] = self[(i, j)];
+            }
+        }
+        t
+    }
+
+    pub fn mul(&self, other: &Matrix) -> Matrix {
+        assert_eq!(self.cols, other.rows, "matrix product dimensions do not match");
+        let mut out = Matrix::zeros(self.rows, other.cols);
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self[(i, k)];
+                if a == 0.0 {
+                    continue
+                }
+                for j in 0..other.cols {
+                    out.data[i * other.cols + j] += a * other.data[k * other.cols + j];
+                }
+            }
+        }
+        out
+    }
+
+    pub fn mul_vec(&self, v: &[f64]) -> Vec<f64> {
+        assert_eq!(self.cols, v.len(), "matrix-vector dimensions do not match");
+        (0..self.rows)
+            .map(|i| self.row(i).iter().zip(v).map(|(a, b)| a * b).sum())
+            .collect()
+    }
+
+    pub fn add(&self, other: &Matrix) -> Matrix {
+        assert_eq!((self.rows, self.cols), (other.rows, other.cols), "matrix sum dimensions do not match");
+        let data = self.data.iter().zip(&other.data).map(|(a, b)| a + b).collect();
+        Matrix{ rows: self.rows, cols: self.cols, data }
+    }
+
+    pub fn sub(&self, other: &Matrix) -> Matrix {
+        self.add(&other.scale(-1.0))
+    }
+
+    pub fn scale(&self, s: f64) -> Matrix {
+        Matrix{ rows: self.rows, cols: self.cols, data: self.data.iter().map(|a| a * s).collect() }
+    }
+
+    /// (A + A') / 2, to scrub rounding asymmetry from covariance updates
+    pub fn symmetrize(&self) -> Matrix {
+        self.add(&self.transpose()).scale(0.5)
+    }
+
+    /// lower-triangular Cholesky factor, or None if the matrix is not
+    /// symmetric positive definite
+    pub fn cholesky(&self) -> Option<Cholesky> {
+        if !self.is_square() {
+            return None
+        }
+        let n = self.rows;
+        let mut l = Matrix::zeros(n, n);
+        for j in 0..n {
+            let mut d = self[(j, j)];
+            for k in 0..j {
+                d -= l[(j, k)] * l[(j, k)];
+            }
+            if d.is_nan() || d <= 0.0 {
+                return None
+            }
+            let d = d.sqrt();
+            l[(j, j)] = d;
+            for i in j + 1..n {
+                let mut s = self[(i, j)];
+                for k in 0..j {
+                    s -= l[(i, k)] * l[(j, k)];
+                }
+                l[(i, j)] = s / d;
+            }
+        }
+        Some(Cholesky{ l })
+    }
+
+    /// eigenvalues of a symmetric matrix in ascending order, with the unit
+    /// eigenvectors as the columns of the second matrix, by cyclic Jacobi
+    /// rotations
+    pub fn symmetric_eigen(&self) -> (Vec<f64>, Matrix) {
+        assert!(self.is_square(), "eigenvalues of a {}x{} matrix", self.rows, self.cols);
+        let n = self.rows;
+        let mut a = self.symmetrize();
+        let mut v = Matrix::identity(n);
+        let scale = a.data.iter().map(|x| x * x).sum::<f64>().sqrt();
+        for _ in 0..JACOBI_SWEEPS {
+            let off = (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).filter(|(i, j)| i != j)
+                .map(|(i, j)| a[(i, j)] * a[(i, j)])
+                .sum::<f64>()
+                .sqrt();
+            if off <= f64::EPSILON * scale {
+                break
+            }
+            for p in 0..n {
+                for q in p + 1..n {
+                    if a[(p, q)] == 0.0 {
+                        continue
+                    }
+                    // the rotation that zeroes a[p][q]
+                    let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * a[(p, q)]);
+                    let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                    let t = if theta == 0.0 { 1.0 } else { t };
+                    let c = 1.0 / (t * t + 1.0).sqrt();
+                    let s = t * c;
+                    for k in 0..n {
+                        let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                        a[(k, p)] = c * akp - s * akq;
+                        a[(k, q)] = s * akp + c * akq;
+                    }
+                    for k in 0..n {
+                        let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                        a[(p, k)] = c * apk - s * aqk;
+                        a[(q, k)] = s * apk + c * aqk;
+                    }
+                    for k in 0..n {
+                        let (vkp, vkq) = (v[(k, p)], v[(k, q)]);
+                        v[(k, p)] = c * vkp - s * vkq;
+                        v[(k, q)] = s * vkp + c * vkq;
+                    }
+                }
+            }
+        }
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|i, j| a[(*i, *i)].total_cmp(&a[(*j, *j)]));
+        let values = order.iter().map(|i| a[(*i, *i)]).collect();
+        let mut vectors = Matrix::zeros(n, n);
+        for (col, i) in order.iter().enumerate() {
+            for k in 0..n {
+                vectors[(k, col)] = v[(k, *i)];
+            }
+        }
+        (values, vectors)
+    }
+}
+
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &f64 {
+        &self.data[i * self.cols + j]
+    }
+}
+
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut f64 {
+        &mut self.data[i * self.cols + j]
+    }
+}
+
+
+/// Cholesky factorization A = L L'
+#[derive(Debug, Clone)]
+pub struct Cholesky {
+    l: Matrix,
+}
+
+
+impl Cholesky {
+    /// wrap an already lower-triangular factor; None unless it is square
+    /// with a finite, positive diagonal
+    pub fn from_lower(l: Matrix) -> Option<Cholesky> {
+        let valid = l.is_square() && l.data.iter().all(|v| v.is_finite())
+            && (0..l.rows).all(|i| l[(i, i)] > 0.0);
+        valid.then_some(Cholesky{ l })
+    }
+
+    pub fn l(&self) -> &Matrix {
+        &self.l
+    }
+
+    pub fn dim(&self) -> usize {
+        self.l.rows
+    }
+
+    /// solve L x = b
+    pub fn solve_lower(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.dim();
+        assert_eq!(b.len(), n, "right-hand side has the wrong length");
+        let mut x = b.to_vec();
+        for i in 0..n {
+            for k in 0..i {
+                x[i] -= self.l[(i, k)] * x[k];
+            }
+            x[i] /= self.l[(i, i)];
+        }
+        x
+    }
+
+    /// solve L' x = b
+    pub fn solve_upper(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.dim();
+        assert_eq!(b.len(), n, "right-hand side has the wrong length");
+        let mut x = b.to_vec();
+        for i in (0..n).rev() {
+            for k in i + 1..n {
+                x[i] -= self.l[(k, i)] * x[k];
+            }
+            x[i] /= self.l[(i, i)];
+        }
+        x
+    }
+
+    /// solve A x = b
+    pub fn solve(&self, b: &[f64]) -> Vec<f64> {
+        self.solve_upper(&self.solve_lower(b))
+    }
+
+    /// A^{-1}
+    pub fn inverse(&self) -> Matrix {
+        let n = self.dim();
+        let mut inv = Matrix::zeros(n, n);
+        let mut e = vec![0.0; n];
+        for j in 0..n {
+            e[j] = 1.0;
+            let col = self.solve(&e);
+            for i in 0..n {
+                inv[(i, j)] = col[i];
+            }
+            e[j] = 0.0;
+        }
+        inv
+    }
+
+    /// log |A|
+    pub fn ln_det(&self) -> f64 {
+        2.0 * (0..self.dim()).map(|i| self.l[(i, i)].ln()).sum::<f64>()
+    }
+
+    /// x' A^{-1} x, computed as |L^{-1} x|^2
+    pub fn quad_form(&self, x: &[f64]) -> f64 {
+        self.solve_lower(x).iter().map(|z| z * z).sum()
+    }
+}
@@ -0,0 +1,515 @@
+use std::error::Error;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use nested_sampling::array::{self, ArrayConfig};
+use nested_sampling::crossval::{crossval, write_folds, CrossvalConfig};
+use nested_sampling::data::Dataset;
+use nested_sampling::learned::prior_dominated;
+use nested_sampling::models::serve_worker;
+use nested_sampling::observer::Stderr;
+use nested_sampling::overrides::{load_layered, load_value, resolve};
+use nested_sampling::postprocess::{post_process, BUILTINS};
+use nested_sampling::profile::{profile_run, write_profiles, ProfileConfig};
+use nested_sampling::replicate::{replicate, write_spreads, ReplicateConfig};
+use nested_sampling::report::write_report;
+use nested_sampling::rundir::{default_run_name, RunDir};
+use nested_sampling::sbc::{run_sbc, SbcConfig};
+use nested_sampling::sensitivity::{prior_sensitivity, MIN_ESS_FRACTION};
+use nested_sampling::serve::serve;
+use nested_sampling::simulate::{simulate, Truth};
+use nested_sampling::smc::cross_check;
+use nested_sampling::sweep::{sweep, write_table, Axis};
+use nested_sampling::units::{self, ParameterSpec};
+use nested_sampling::{extend_run, run, run_in_dir, Config, RunResult};
+
+
+/// nested sampling for the evidence and posterior of a model of tabular data
+#[derive(Parser, Debug)]
+#[clap(name = "ns", version)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+    /// override a config key, e.g. `--set sampler.steps=50`; repeatable,
+    /// and applied after `NS_`-prefixed environment variables such as
+    /// `NS_SAMPLER__STEPS=50`
+    #[clap(long = "set", global = true, value_name = "KEY=VALUE")]
+    sets: Vec<String>,
+}
+
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// run the sampler described by a TOML config. If the config sets
+    /// `output_root`, the run gets its own directory under it
+    Run {
+        config: PathBuf,
+        /// replace an existing run of the same name
+        #[clap(long)]
+        force: bool,
+        /// carry on from the newest checkpoint under `output_root`, with
+        /// the config that run was started with plus any `--set`s
+        #[clap(long, conflicts_with = "force")]
+        resume_latest: bool,
+    },
+    /// add live points to a finished run in an output directory and merge
+    /// them with its dead points, narrowing the error on log Z
+    Extend {
+        /// the run's directory, holding its config.toml and dead-birth.txt
+        run: PathBuf,
+        /// live points to add
+        #[clap(long)]
+        add_live: usize,
+    },
+    /// profile likelihood and conditional slice of some parameters of a
+    /// finished run, maximizing over the others from its dead points
+    Profile {
+        /// the run's directory, holding its config.toml and dead-birth.txt
+        run: PathBuf,
+        /// index of a parameter to profile; repeatable
+        #[clap(long = "param", required = true)]
+        params: Vec<usize>,
+        /// grid values per parameter
+        #[clap(long, default_value_t = 25)]
+        points: usize,
+        /// CSV file for the profiles
+        #[clap(long, short)]
+        out: PathBuf,
+    },
+    /// run a config several times and report the posterior with the
+    /// scatter between runs, an error bar that also covers modes a single
+    /// run can miss
+    Replicate {
+        config: PathBuf,
+        #[clap(long, default_value_t = 8)]
+        runs: usize,
+        /// probability inside the central credible intervals
+        #[clap(long, default_value_t = 0.9)]
+        level: f64,
+        /// CSV file for the per-parameter summaries
+        #[clap(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// k-fold cross-validation: fit the config to all but one part of the
+    /// data at a time and score the held-out part by its posterior
+    /// predictive density, next to the evidence of the full data
+    Crossval {
+        config: PathBuf,
+        #[clap(long, default_value_t = 5)]
+        folds: usize,
+        /// CSV file for the per-fold scores
+        #[clap(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// the evidence and posterior of a finished run under another prior,
+    /// by reweighting its dead points rather than running again
+    Sensitivity {
+        /// the run's directory, holding its config.toml and dead-birth.txt
+        run: PathBuf,
+        /// a prior key of the alternative, e.g. `--prior sd=[2.0,2.0]`;
+        /// repeatable, and applied on top of the run's config
+        #[clap(long = "prior", value_name = "KEY=VALUE", required = true)]
+        priors: Vec<String>,
+    },
+    /// write a self-contained HTML report of a finished run: its config,
+    /// evidence, parameter summaries, convergence plots and diagnostics
+    Report {
+        /// the run's directory, holding its config.toml and dead-birth.txt
+        run: PathBuf,
+        /// probability inside the credible intervals
+        #[clap(long, default_value_t = 0.9)]
+        level: f64,
+        /// HTML file for the report; report.html in the run's directory
+        /// by default
+        #[clap(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// run post-processing steps on a finished run: those given, else those
+    /// its config names, else the built-in summary, diagnostics and export
+    Summarize {
+        /// the run's directory, holding its config.toml and dead-birth.txt
+        run: PathBuf,
+        /// name of a registered step; repeatable
+        #[clap(long = "step", value_name = "NAME")]
+        steps: Vec<String>,
+    },
+    /// estimate the evidence of a config by nested sampling and by SMC and
+    /// compare the two; fails when they disagree by more than `--max-tension`
+    /// combined standard errors
+    Compare {
+        config: PathBuf,
+        #[clap(long, default_value_t = 3.0)]
+        max_tension: f64,
+    },
+    /// load a config once and run it on every dataset path sent to a local
+    /// TCP socket, one per line, answering each with a line of JSON
+    Serve {
+        config: PathBuf,
+        /// address to listen on; keep it on localhost, the jobs name files
+        /// on this machine
+        #[clap(long, default_value = "127.0.0.1:7878")]
+        addr: String,
+    },
+    /// evaluate likelihoods for a run with `workers` set: read the job on
+    /// stdin and answer on stdout. Started by the run, not by hand
+    #[clap(hide = true)]
+    Worker,
+    /// check a config and list every problem found
+    Check {
+        config: PathBuf,
+    },
+    /// draw a dataset from the configured model at known parameter values,
+    /// keeping the predictors of the config's data file
+    Simulate {
+        config: PathBuf,
+        /// TOML file holding `theta` and optionally `seed`
+        #[clap(long)]
+        truth: PathBuf,
+        /// where to write the dataset; `.nsd` writes the binary format
+        #[clap(long, short)]
+        out: PathBuf,
+    },
+    /// simulation-based calibration: fit many datasets simulated from the
+    /// prior and check that the truth ranks uniformly among the posterior
+    /// draws
+    Sbc {
+        config: PathBuf,
+        #[clap(long, default_value_t = 100)]
+        replications: usize,
+        /// posterior draws each truth is ranked among
+        #[clap(long, default_value_t = 99)]
+        draws: usize,
+        #[clap(long, default_value_t = 10)]
+        bins: usize,
+        /// write every rank to this CSV file
+        #[clap(long)]
+        ranks: Option<PathBuf>,
+    },
+    /// run every combination of values of some config keys and tabulate
+    /// log Z, ESS and likelihood calls
+    Sweep {
+        config: PathBuf,
+        /// a key and its values, e.g. `--axis particle_num=100,200`;
+        /// repeatable
+        #[clap(long = "axis", value_name = "KEY=V1,V2", required = true)]
+        axes: Vec<String>,
+        /// CSV file for the comparison table
+        #[clap(long, short)]
+        out: PathBuf,
+        /// run the combinations in parallel
+        #[clap(long)]
+        parallel: bool,
+    },
+    /// shard a sweep, or replicates of one config, into the tasks of a
+    /// Slurm or PBS array job, writing the job and submit scripts to a
+    /// directory
+    ArrayPrepare {
+        config: PathBuf,
+        /// a key and its values, e.g. `--axis particle_num=100,200`;
+        /// repeatable
+        #[clap(long = "axis", value_name = "KEY=V1,V2")]
+        axes: Vec<String>,
+        /// runs of every combination, each with its own seed
+        #[clap(long, default_value_t = 1)]
+        replicates: usize,
+        /// array tasks the runs are dealt to
+        #[clap(long, default_value_t = 1)]
+        tasks: usize,
+        /// seed of the first run; the others follow it
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+        /// directory for the job and the results of its tasks
+        #[clap(long, short)]
+        dir: PathBuf,
+    },
+    /// make the runs of one task of an array job; started by the submit
+    /// scripts of `array-prepare`
+    ArrayRun {
+        dir: PathBuf,
+        /// the task; from SLURM_ARRAY_TASK_ID, PBS_ARRAY_INDEX or
+        /// PBS_ARRAYID if absent
+        #[clap(long)]
+        task: Option<usize>,
+    },
+    /// merge the results of every task of an array job into one table
+    ArrayCollect {
+        dir: PathBuf,
+        /// CSV file for the table, one row per run
+        #[clap(long, short)]
+        out: PathBuf,
+    },
+}
+
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run_command(cli.command, &cli.sets) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+
+/// the config file with the overrides applied
+fn load_config(path: &Path, sets: &[String]) -> Result<Config, Box<dyn Error>> {
+    Ok(load_layered(path, sets)?.0)
+}
+
+
+/// run in a directory of its own under the config's `output_root`,
+/// starting from the resolved config written there
+fn run_in_root(
+        path: &Path,
+        sets: &[String],
+        force: bool,
+        resume_latest: bool,
+) -> Result<Option<RunResult>, Box<dyn Error>> {
+    let config = load_config(path, sets)?;
+    let root = match &config.output_root {
+        Some(root) => root,
+        None if resume_latest => return Err("--resume-latest needs output_root in the config".into()),
+        None => return Ok(None),
+    };
+    if resume_latest {
+        let dir = RunDir::latest(root)?;
+        let config = load_config(&dir.config_file(), sets)?;
+        eprintln!("resuming {}", dir.path().display());
+        return Ok(Some(run_in_dir(&config, &dir, true, &mut Stderr)?))
+    }
+    let name = config.run_name.clone().unwrap_or_else(default_run_name);
+    let dir = RunDir::create(root, &name, force)?;
+    eprintln!("writing to {}", dir.path().display());
+    Ok(Some(run_in_dir(&config, &dir, false, &mut Stderr)?))
+}
+
+
+fn report(result: &RunResult, specs: &[ParameterSpec]) {
+    println!("log_z = {} +/- {}", result.log_z, result.log_z_err);
+    println!("information = {} nats", result.info);
+    println!("iterations = {}, ess = {}", result.iterations, result.ess);
+    if let Some(log_z) = result.cumulative_log_z {
+        println!("cumulative log_z = {}", log_z);
+    }
+    if result.approximate {
+        println!("the likelihood is approximate, so log_z is too");
+    }
+    if let Some(variance) = result.log_lik_variance {
+        println!("the likelihood is a noisy estimate (log-likelihood variance {:.3}), so log_z carries extra estimator noise", variance);
+    }
+    if let Some(log_z) = result.log_z_per_volume {
+        println!("log_z per unit prior volume = {}", log_z);
+    }
+    let dominated = prior_dominated(&result.prior_kl);
+    if !dominated.is_empty() {
+        let names: Vec<String> = dominated.iter().map(|j| format!("{} ({:.3} nats)", units::name(specs, *j), result.prior_kl[*j])).collect();
+        println!("prior-dominated, the data barely moved them from their prior: {}", names.join(", "));
+    }
+}
+
+
+fn run_command(command: Command, sets: &[String]) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Run{ config: path, force, resume_latest } => {
+            let config = load_config(&path, sets)?;
+            let result = match run_in_root(&path, sets, force, resume_latest)? {
+                Some(result) => result,
+                None => run(&config)?,
+            };
+            report(&result, &config.parameters);
+        },
+        Command::Extend{ run, add_live } => {
+            let dir = RunDir::open(&run)?;
+            let config = load_config(&dir.config_file(), sets)?;
+            report(&extend_run(&config, &dir, add_live, &mut Stderr)?, &config.parameters);
+        },
+        Command::Profile{ run, params, points, out } => {
+            let dir = RunDir::open(&run)?;
+            let config = load_config(&dir.config_file(), sets)?;
+            let settings = ProfileConfig{ points, ..Default::default() };
+            let profiles = profile_run(&config, &dir, &params, &settings)?;
+            write_profiles(&out, &profiles)?;
+            for profile in &profiles {
+                if let Some(best) = profile.profile.iter().max_by(|a, b| a.log_l.total_cmp(&b.log_l)) {
+                    println!(
+                        "{}: profile maximum {} at {}",
+                        units::label(&config.parameters, profile.param), best.log_l, units::shown(&config.parameters, profile.param, best.value),
+                    );
+                }
+            }
+        },
+        Command::Replicate{ config, runs, level, out } => {
+            let config = load_config(&config, sets)?;
+            let data = Dataset::load(&config.data_file)?;
+            let settings = ReplicateConfig{ runs, level };
+            let report = replicate(&config, &settings, &data, &mut Stderr, &mut rand::thread_rng())?;
+            let mean_err = report.log_z_err.iter().sum::<f64>() / runs as f64;
+            println!(
+                "log_z = {} +/- {} between runs ({} reported per run)",
+                report.mean_log_z(), report.log_z_scatter, mean_err,
+            );
+            for p in &report.parameters {
+                let shown = |v: f64| units::shown(&config.parameters, p.param, v);
+                println!(
+                    "{}: median {} +/- {}, {}% interval [{} +/- {}, {} +/- {}]",
+                    units::label(&config.parameters, p.param), shown(p.median), shown(p.median_sd), 100.0 * level,
+                    shown(p.lower), shown(p.lower_sd), shown(p.upper), shown(p.upper_sd),
+                );
+            }
+            if let Some(out) = out {
+                write_spreads(&out, &report)?;
+            }
+        },
+        Command::Crossval{ config, folds, out } => {
+            let config = load_config(&config, sets)?;
+            let data = Dataset::load(&config.data_file)?;
+            let settings = CrossvalConfig{ folds };
+            let report = crossval(&config, &settings, &data, &mut Stderr, &mut rand::thread_rng())?;
+            for f in &report.folds {
+                println!(
+                    "fold {}: {} rows held out, log_pred = {}, training log_z = {} +/- {}",
+                    f.fold, f.n_test, f.log_pred, f.log_z, f.log_z_err,
+                );
+            }
+            println!("cross-validated log_pred = {} +/- {}", report.score(), report.score_err());
+            println!("full-data log_z = {} +/- {}", report.log_z, report.log_z_err);
+            if let Some(out) = out {
+                write_folds(&out, &report)?;
+            }
+        },
+        Command::Sensitivity{ run, priors } => {
+            let dir = RunDir::open(&run)?;
+            let config = load_config(&dir.config_file(), sets)?;
+            let alternative: Config = resolve(load_value(&dir.config_file(), sets)?, std::iter::empty::<(String, String)>(), &priors)?
+                .try_into()
+                .map_err(|e| format!("the alternative prior: {}", e))?;
+            let reweighted = prior_sensitivity(&config, &alternative, &dir)?;
+            println!("log_z = {} +/- {} under the alternative prior", reweighted.log_z, reweighted.log_z_err);
+            println!("ess = {} ({:.1}% of the run's)", reweighted.ess, 100.0 * reweighted.ess_fraction);
+            for j in 0..config.n_params() {
+                let mean: f64 = reweighted.posterior.iter().map(|(t, lw)| t[j] * lw.exp()).sum();
+                let var: f64 = reweighted.posterior.iter().map(|(t, lw)| (t[j] - mean).powi(2) * lw.exp()).sum();
+                let shown = |v: f64| units::shown(&config.parameters, j, v);
+                println!("{}: mean {}, sd {}", units::label(&config.parameters, j), shown(mean), shown(var.sqrt()));
+            }
+            if !reweighted.is_reliable() {
+                return Err(format!(
+                    "the reweighted posterior keeps less than {}% of the run's effective sample size; \
+                     run the alternative prior on its own",
+                    100.0 * MIN_ESS_FRACTION,
+                ).into())
+            }
+        },
+        Command::Summarize{ run, steps } => {
+            let dir = RunDir::open(&run)?;
+            let config = load_config(&dir.config_file(), sets)?;
+            let steps = match (steps.is_empty(), config.post_process.is_empty()) {
+                (false, _) => steps,
+                (true, false) => config.post_process.clone(),
+                (true, true) => BUILTINS.iter().map(|s| s.to_string()).collect(),
+            };
+            post_process(&dir, &config, &steps, &mut Stderr)?;
+            eprintln!("ran {} in {}", steps.join(", "), dir.path().display());
+        },
+        Command::Serve{ config, addr } => {
+            let config = load_config(&config, sets)?;
+            let listener = TcpListener::bind(&addr).map_err(|e| format!("cannot listen on {}: {}", addr, e))?;
+            eprintln!("serving on {}", listener.local_addr()?);
+            serve(config, listener)?;
+        },
+        Command::Worker => {
+            serve_worker(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())?;
+        },
+        Command::Report{ run, level, out } => {
+            let dir = RunDir::open(&run)?;
+            let config = load_config(&dir.config_file(), sets)?;
+            let out = out.unwrap_or_else(|| dir.report_file());
+            write_report(&dir, config.n_params(), level, &out)?;
+            eprintln!("wrote {}", out.display());
+        },
+        Command::Compare{ config, max_tension } => {
+            let config = load_config(&config, sets)?;
+            let data = Dataset::load(&config.data_file)?;
+            let check = cross_check(&config, &data, &mut Stderr, &mut rand::thread_rng())?;
+            println!("nested sampling: log_z = {} +/- {}", check.nested.0, check.nested.1);
+            println!("SMC:             log_z = {} +/- {}", check.smc.0, check.smc.1);
+            println!("tension: {:.2} sigma", check.tension);
+            if check.tension > max_tension {
+                return Err(format!("the engines disagree by {:.2} sigma", check.tension).into())
+            }
+        },
+        Command::Check{ config } => {
+            let loaded = load_layered(&config, sets)?.0;
+            loaded.validate()?;
+            println!("{} is valid", config.display());
+            match loaded.probe_transform()? {
+                None => println!("the prior has no unit-cube transform to probe"),
+                Some(found) if found.is_empty() => println!("the prior transform passed every probe"),
+                Some(found) => {
+                    println!("suspicious points of the prior transform:");
+                    println!("{:<6} {:<24} problem", "param", "at");
+                    for s in found {
+                        let param = s.param.map_or("-".to_string(), |j| j.to_string());
+                        println!("{:<6} {:<24} {}", param, s.at, s.problem);
+                    }
+                },
+            }
+        },
+        Command::Simulate{ config, truth, out } => {
+            let config = load_config(&config, sets)?;
+            let truth = Truth::load(&truth)?;
+            let seed = truth.seed.unwrap_or_else(|| rand::thread_rng().gen());
+            let data = Dataset::load(&config.data_file)?;
+            let simulated = simulate(&config, &data, &truth.theta, &mut StdRng::seed_from_u64(seed))?;
+            simulated.write(&out)?;
+            eprintln!("wrote {} rows to {} (seed {})", simulated.nrows(), out.display(), seed);
+        },
+        Command::Sbc{ config, replications, draws, bins, ranks } => {
+            let config = load_config(&config, sets)?;
+            let sbc = SbcConfig{ replications, draws, bins, rank_file: ranks, ..Default::default() };
+            let data = Dataset::load(&config.data_file)?;
+            let report = run_sbc(&config, &sbc, &data, &mut Stderr, &mut rand::thread_rng())?;
+            for (j, (hist, p)) in report.histograms.iter().zip(&report.p_values).enumerate() {
+                println!("theta{}: ranks {:?}, p = {:.3}", j, hist, p);
+            }
+            if !report.miscalibrated.is_empty() {
+                return Err(format!("miscalibrated parameters: {:?}", report.miscalibrated).into())
+            }
+        },
+        Command::Sweep{ config, axes, out, parallel } => {
+            let base = load_value(&config, sets)?;
+            let axes = axes.iter().map(|a| Axis::parse(a)).collect::<Result<Vec<Axis>, _>>()?;
+            let rows = sweep(&base, &axes, parallel);
+            write_table(&out, &axes, &rows)?;
+            let failed = rows.iter().filter(|r| r.outcome.is_err()).count();
+            eprintln!("wrote {} runs to {} ({} failed)", rows.len(), out.display(), failed);
+        },
+        Command::ArrayPrepare{ config, axes, replicates, tasks, seed, dir } => {
+            let base = load_value(&config, sets)?;
+            let axes = axes.iter().map(|a| Axis::parse(a)).collect::<Result<Vec<Axis>, _>>()?;
+            let settings = ArrayConfig{ tasks, replicates, seed };
+            let manifest = array::prepare(&dir, &base, &axes, &settings, &std::env::current_exe()?)?;
+            eprintln!(
+                "prepared {} runs in {} tasks; submit {} or {}",
+                manifest.jobs.len(), manifest.tasks, dir.join("submit.slurm").display(), dir.join("submit.pbs").display(),
+            );
+        },
+        Command::ArrayRun{ dir, task } => {
+            let task = task.or_else(array::task_from_env)
+                .ok_or("no --task given and no array task id in the environment")?;
+            let result = array::run_task(&dir, task)?;
+            let failed = result.jobs.iter().filter(|j| j.error.is_some()).count();
+            eprintln!("task {} made {} runs ({} failed)", task, result.jobs.len(), failed);
+        },
+        Command::ArrayCollect{ dir, out } => {
+            let (manifest, rows) = array::collect(&dir)?;
+            write_table(&out, &array::collected_axes(&manifest), &rows)?;
+            let failed = rows.iter().filter(|r| r.outcome.is_err()).count();
+            eprintln!("wrote {} runs of version {} to {} ({} failed)", rows.len(), manifest.version, out.display(), failed);
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use super::{LogLikelihood, Screen};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting {
+        calls: AtomicUsize,
+    }
+
+    impl LogLikelihood for Counting {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            -theta[0] * theta[0]
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_lru_hits_and_evicts() {
+        let cached = Cached::new(Counting{ calls: AtomicUsize::new(0) }, 2).unwrap();
+        assert_eq!(cached.log_lik(&[1.0]), -1.0);
+        assert_eq!(cached.log_lik(&[1.0]), -1.0);
+        assert_eq!(cached.log_lik(&[2.0]), -4.0);
+        // touching 1.0 makes 2.0 the least recently used entry
+        cached.log_lik(&[1.0]);
+        cached.log_lik(&[3.0]);
+        cached.log_lik(&[1.0]);
+        cached.log_lik(&[2.0]);
+        assert_eq!(cached.model.calls.load(Ordering::SeqCst), 4);
+        let (hits, misses) = cached.hits_misses();
+        assert_eq!((hits, misses), (3, 4));
+    }
+}
+
+
+/// cache entries plus the recency order used for eviction
+#[derive(Debug, Default)]
+struct LruState {
+    entries: HashMap<u64, Vec<(Vec<f64>, f64, u64)>>,
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    len: usize,
+    hits: usize,
+    misses: usize,
+}
+
+
+/// least-recently-used memoization of an expensive likelihood
+///
+/// Parameter vectors are keyed on a hash of their exact bit patterns (with
+/// -0.0 folded into 0.0), and a hit is only returned if the stored theta is
+/// identical, so hash collisions cannot return a wrong value. Repeated
+/// evaluations come from reflective samplers bouncing back onto an earlier
+/// point, from re-scoring checkpointed live points and from user code that
+/// asks for the likelihood of a known particle. For noisy likelihoods the
+/// cache returns the estimate already drawn for that theta, which is what
+/// the pseudo-marginal interpretation wants anyway.
+///
+/// Fields:
+/// model: the cached likelihood
+/// capacity: maximum number of stored evaluations
+/// state: entries, recency order and hit/miss counters
+pub struct Cached<M: LogLikelihood> {
+    model: M,
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+
+impl<M: LogLikelihood> Cached<M> {
+    pub fn new(model: M, capacity: usize) -> Result<Cached<M>, Box<dyn Error>> {
+        if capacity == 0 {
+            return Err("the likelihood cache needs a capacity of at least one".into())
+        }
+        Ok(Cached{ model, capacity, state: Mutex::new(LruState::default()) })
+    }
+
+    /// (hits, misses) so far
+    pub fn hits_misses(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.hits, state.misses)
+    }
+
+    fn key(theta: &[f64]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for t in theta {
+            let t = if *t == 0.0 { 0.0 } else { *t };
+            t.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn lookup(&self, key: u64, theta: &[f64]) -> Option<f64> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let found = state.entries.get_mut(&key).and_then(|bucket| {
+            bucket.iter_mut()
+                .find(|(t, _, _)| t.as_slice() == theta)
+                .map(|entry| {
+                    let old = entry.2;
+                    entry.2 = tick;
+                    (entry.1, old)
+                })
+        });
+        match found {
+            Some((ll, old)) => {
+                state.order.remove(&old);
+                state.order.insert(tick, key);
+                state.hits += 1;
+                Some(ll)
+            },
+            None => {
+                state.misses += 1;
+                None
+            },
+        }
+    }
+
+    fn insert(&self, key: u64, theta: &[f64], ll: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let bucket = state.entries.entry(key).or_default();
+        if bucket.iter().any(|(t, _, _)| t.as_slice() == theta) {
+            // another thread evaluated the same theta meanwhile
+            return
+        }
+        bucket.push((theta.to_vec(), ll, tick));
+        state.order.insert(tick, key);
+        state.len += 1;
+
+        while state.len > self.capacity {
+            let (oldest, old_key) = match state.order.iter().next() {
+                Some((&t, &k)) => (t, k),
+                None => break,
+            };
+            state.order.remove(&oldest);
+            if let Some(bucket) = state.entries.get_mut(&old_key) {
+                bucket.retain(|(_, _, t)| *t != oldest);
+                if bucket.is_empty() {
+                    state.entries.remove(&old_key);
+                }
+            }
+            state.len -= 1;
+        }
+    }
+}
+
+
+impl<M: LogLikelihood> LogLikelihood for Cached<M> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let key = Cached::<M>::key(theta);
+        if let Some(ll) = self.lookup(key, theta) {
+            return ll
+        }
+        let ll = self.model.log_lik(theta);
+        self.insert(key, theta, ll);
+        ll
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.model.is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        self.model.screen(theta, threshold)
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        let (hits, misses) = self.hits_misses();
+        let mut stats = vec![
+            ("cache_hits".to_string(), hits as f64),
+            ("cache_misses".to_string(), misses as f64),
+        ];
+        stats.extend(self.model.stats());
+        stats
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.model.outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        self.model.log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.model.reseed(seed)
+    }
+}
@@ -0,0 +1,140 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use rand::RngCore;
+use rand::distributions::Distribution;
+
+use crate::data::Dataset;
+use crate::dist::Normal;
+use crate::linalg::{Cholesky, Matrix};
+use super::{LinearGaussian, LogLikelihood, PointwiseLogLikelihood, Simulate};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Noise;
+
+    #[test]
+    fn test_matches_the_dense_density() {
+        let x = vec![0.0, 1.0, 2.0, 3.0];
+        let y = vec![0.3, 1.2, 1.7, 3.4];
+        let cov = Matrix::from_rows(vec![
+            vec![1.0, 0.5, 0.25, 0.0],
+            vec![0.5, 1.0, 0.5, 0.25],
+            vec![0.25, 0.5, 1.0, 0.5],
+            vec![0.0, 0.25, 0.5, 1.0],
+        ]);
+        let theta = [0.1, 0.9, 0.5];
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Sampled).unwrap();
+        let model = CorrelatedNoise::new(base, &cov).unwrap();
+        assert_eq!(model.dim(), 3);
+
+        // the covariance is sigma^2 C, inverted the slow way
+        let scaled = cov.scale(0.25);
+        let inv = scaled.cholesky().unwrap().inverse();
+        let r: Vec<f64> = (0..4).map(|i| y[i] - 0.1 - 0.9 * x[i]).collect();
+        let quad: f64 = (0..4).map(|i| (0..4).map(|j| r[i] * inv[(i, j)] * r[j]).sum::<f64>()).sum();
+        let expected = -0.5 * (4.0 * (2.0 * PI).ln() + scaled.cholesky().unwrap().ln_det() + quad);
+        assert!((model.log_lik(&theta) - expected).abs() < 1e-12);
+        assert_eq!(model.log_lik(&[0.1, 0.9, -0.5]), f64::NEG_INFINITY);
+
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Sampled).unwrap();
+        assert!(CorrelatedNoise::new(base, &Matrix::identity(3)).is_err());
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Sampled).unwrap();
+        let mut asymmetric = cov.clone();
+        asymmetric[(0, 3)] = 0.2;
+        assert!(CorrelatedNoise::new(base, &asymmetric).is_err());
+    }
+}
+
+
+/// read an n x n covariance matrix from a delimited text file, one row
+/// per line
+pub fn read_covariance(path: &Path) -> Result<Matrix, Box<dyn Error>> {
+    let data = Dataset::load(path)?;
+    let rows: Vec<Vec<f64>> = (0..data.ncols()).map(|j| data.column(j).to_vec()).collect();
+    if data.nrows() != data.ncols() {
+        return Err(format!("{}: a covariance must be square, got {} x {}", path.display(), data.nrows(), data.ncols()).into())
+    }
+    // the columns of a symmetric matrix are its rows
+    Ok(Matrix::from_rows(rows))
+}
+
+
+/// linear regression whose residuals have a known covariance matrix,
+/// N(0, sigma^2 C), e.g. instrument noise correlated between data points
+///
+/// C is factorized once when the model is built; every evaluation is then
+/// one triangular solve, O(n^2), and the log determinant comes for free.
+/// With a fixed noise sd of 1 the covariance is C exactly, with a sampled
+/// one C only sets the shape and sigma, the last element of theta, the
+/// overall scale.
+///
+/// Fields:
+/// base: the regression supplying the mean and the noise scale
+/// chol: Cholesky factor of C
+#[derive(Debug)]
+pub struct CorrelatedNoise<'a> {
+    base: LinearGaussian<'a>,
+    chol: Cholesky,
+}
+
+
+impl<'a> CorrelatedNoise<'a> {
+    pub fn new(
+            base: LinearGaussian<'a>,
+            covariance: &Matrix,
+    ) -> Result<CorrelatedNoise<'a>, Box<dyn Error>> {
+        let n = base.n_obs();
+        if covariance.rows() != n || covariance.cols() != n {
+            return Err(format!(
+                "the data have {} observations but the covariance is {} x {}",
+                n, covariance.rows(), covariance.cols(),
+            ).into())
+        }
+        if base.is_censored_or_truncated() {
+            return Err("correlated noise cannot be combined with censored or truncated data".into())
+        }
+        for i in 0..n {
+            for j in 0..i {
+                let (a, b) = (covariance[(i, j)], covariance[(j, i)]);
+                if (a - b).abs() > 1e-9 * (a.abs() + b.abs()).max(f64::MIN_POSITIVE) {
+                    return Err(format!("the covariance is not symmetric at ({}, {})", i, j).into())
+                }
+            }
+        }
+        let chol = covariance.cholesky().ok_or("the data covariance is not positive definite")?;
+        Ok(CorrelatedNoise{ base, chol })
+    }
+}
+
+
+impl<'a> LogLikelihood for CorrelatedNoise<'a> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let sigma = self.base.sigma(theta);
+        if sigma.is_nan() || sigma <= 0.0 {
+            return f64::NEG_INFINITY
+        }
+        let n = self.base.n_obs();
+        let r: Vec<f64> = (0..n).map(|i| self.base.residual(theta, i)).collect();
+        let s2 = sigma * sigma;
+        let ln_det = self.chol.ln_det() + n as f64 * s2.ln();
+        -0.5 * (n as f64 * (2.0 * PI).ln() + ln_det + self.chol.quad_form(&r) / s2)
+    }
+
+    fn dim(&self) -> usize {
+        self.base.dim()
+    }
+}
+
+
+impl<'a> Simulate for CorrelatedNoise<'a> {
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>> {
+        let normal = Normal::new(0.0, self.base.sigma(theta))?;
+        let u: Vec<f64> = (0..self.base.n_obs()).map(|_| normal.sample(rng)).collect();
+        let e = self.chol.l().mul_vec(&u);
+        Ok(e.iter().enumerate().map(|(i, e)| self.base.mean(theta, i) + e).collect())
+    }
+}
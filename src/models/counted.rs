@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::RngCore;
+
+use super::{LogLikelihood, Screen, SlowState};
+
+
+/// counts the evaluations of the likelihood it wraps, reported as the
+/// `likelihood_calls` statistic
+///
+/// It goes directly around the data model, inside any cache or screening
+/// wrapper, so that only evaluations of the model itself count.
+///
+/// Fields:
+/// model: the counted likelihood
+/// calls: full evaluations so far, from any thread
+/// fast_calls: evaluations by `update_fast`, which reuse the slow part
+pub struct Counted<M> {
+    model: M,
+    calls: AtomicUsize,
+    fast_calls: AtomicUsize,
+}
+
+
+impl<M: LogLikelihood> Counted<M> {
+    pub fn new(model: M) -> Counted<M> {
+        Counted{ model, calls: AtomicUsize::new(0), fast_calls: AtomicUsize::new(0) }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    pub fn fast_calls(&self) -> usize {
+        self.fast_calls.load(Ordering::Relaxed)
+    }
+}
+
+
+impl<M: LogLikelihood> LogLikelihood for Counted<M> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.model.log_lik(theta)
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.model.is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        let screen = self.model.screen(theta, threshold);
+        if let Screen::Evaluated(_) = screen {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        screen
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        let mut stats = vec![("likelihood_calls".to_string(), self.calls() as f64)];
+        if self.fast_calls() > 0 {
+            stats.push(("likelihood_fast_calls".to_string(), self.fast_calls() as f64));
+        }
+        stats.extend(self.model.stats());
+        stats
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.model.outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        self.model.log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn log_lik_with(&self, theta: &[f64], rng: &mut dyn RngCore) -> f64 {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.model.log_lik_with(theta, rng)
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.model.reseed(seed)
+    }
+
+    fn log_lik_state(&self, theta: &[f64]) -> Option<(f64, SlowState)> {
+        let evaluated = self.model.log_lik_state(theta);
+        if evaluated.is_some() {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        evaluated
+    }
+
+    fn update_fast(&self, state: &mut SlowState, theta: &[f64]) -> f64 {
+        self.fast_calls.fetch_add(1, Ordering::Relaxed);
+        self.model.update_fast(state, theta)
+    }
+}
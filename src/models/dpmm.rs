@@ -0,0 +1,394 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+use rand::{Rng, RngCore};
+use rand::distributions::Distribution;
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use crate::dist::{ln_gamma, sample_gamma, Normal};
+use crate::stats::systematic_resample;
+use super::particle_filter::{ParticleFilter, StateDynamics};
+use super::Simulate;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::evidence::log_add_exp;
+    use crate::models::LogLikelihood;
+
+    /// exact log marginal likelihood by summing over every partition,
+    /// built up one observation at a time
+    fn exact(model: &DpmmMarginal, theta: &[f64]) -> f64 {
+        fn recurse(model: &DpmmMarginal, h: &Hyper, clusters: &mut Vec<Cluster>, t: usize) -> f64 {
+            if t == model.x.len() {
+                return 0.0
+            }
+            let x = model.x[t];
+            let mut total = f64::NEG_INFINITY;
+            for k in 0..=clusters.len() {
+                if k == clusters.len() {
+                    clusters.push(Cluster::default());
+                }
+                let n = clusters[k].n;
+                let w = if n == 0 { h.alpha } else { n as f64 };
+                let term = (w / (t as f64 + h.alpha)).ln() + h.predictive(&clusters[k], x);
+                clusters[k].add(x);
+                total = log_add_exp(total, term + recurse(model, h, clusters, t + 1));
+                clusters[k].remove(x);
+                if clusters[k].n == 0 {
+                    clusters.pop();
+                }
+            }
+            total
+        }
+        recurse(model, &Hyper::new(theta), &mut Vec::new(), 0)
+    }
+
+    #[test]
+    fn test_filter_matches_the_partition_sum() {
+        let model = DpmmMarginal::new(vec![-2.1, 1.9, -1.8, 2.3, 0.2]).unwrap();
+        let theta = [0.0, 0.0, (0.1f64).ln(), 1.0f64.ln(), 0.5f64.ln()];
+        let truth = exact(&model, &theta);
+        let pf = ParticleFilter::new(model.clone(), 2000).unwrap();
+        let mut rng = StdRng::seed_from_u64(395);
+        let reps = 20;
+        let mean: f64 = (0..reps).map(|_| pf.estimate(&theta, &mut rng)).sum::<f64>() / reps as f64;
+        assert!((mean - truth).abs() < 0.05);
+        assert_eq!(pf.dim(), 5);
+    }
+
+    #[test]
+    fn test_two_groups_give_two_clusters() {
+        let mut rng = StdRng::seed_from_u64(3950);
+        let x: Vec<f64> = (0..40)
+            .map(|i| if i % 2 == 0 { -4.0 } else { 4.0 } + 0.3 * (rng.gen::<f64>() - 0.5))
+            .collect();
+        let model = DpmmMarginal::new(x).unwrap();
+        let theta = vec![0.0, 0.0, (0.05f64).ln(), 2.0f64.ln(), 0.1f64.ln()];
+        // a posterior concentrated on one theta: every draw shares it
+        let posterior = vec![(theta, 0.0)];
+        let config = DpmmConfig{ draws: 10, burn_in: 20, sweeps: 30, ..Default::default() };
+        let counts = model.cluster_counts(&posterior, &config, &mut rng);
+        let total: f64 = counts.iter().map(|c| c.1).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        let two = counts.iter().find(|c| c.0 == 2).unwrap();
+        assert!(two.1 > 0.8);
+        assert!(two.2 >= 0.0);
+    }
+}
+
+
+/// settings of the Dirichlet process mixture model of one data column
+///
+/// Fields:
+/// column: name of the data column to cluster; the last column if absent
+/// filter_particles: particles of the sequential estimate of the
+///     marginal likelihood
+/// cluster_file: write the posterior of the number of clusters to this
+///     CSV file
+/// draws: posterior points the cluster counts are averaged over
+/// burn_in: Gibbs sweeps discarded at each posterior point
+/// sweeps: Gibbs sweeps recorded at each posterior point
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DpmmConfig {
+    pub column: Option<String>,
+    pub filter_particles: usize,
+    pub cluster_file: Option<PathBuf>,
+    pub draws: usize,
+    pub burn_in: usize,
+    pub sweeps: usize,
+}
+
+
+impl Default for DpmmConfig {
+    fn default() -> DpmmConfig {
+        DpmmConfig{
+            column: None,
+            filter_particles: 200,
+            cluster_file: None,
+            draws: 100,
+            burn_in: 20,
+            sweeps: 50,
+        }
+    }
+}
+
+
+/// sufficient statistics of the observations in one cluster
+#[derive(Debug, Clone, Default)]
+struct Cluster {
+    n: usize,
+    sum: f64,
+    sum_sq: f64,
+}
+
+
+impl Cluster {
+    fn add(&mut self, x: f64) {
+        self.n += 1;
+        self.sum += x;
+        self.sum_sq += x * x;
+    }
+
+    fn remove(&mut self, x: f64) {
+        self.n -= 1;
+        self.sum -= x;
+        self.sum_sq -= x * x;
+    }
+}
+
+
+/// concentration and Normal-Inverse-Gamma base measure, from theta =
+/// [ln alpha, m0, ln kappa0, ln a0, ln b0]
+struct Hyper {
+    alpha: f64,
+    m0: f64,
+    kappa0: f64,
+    a0: f64,
+    b0: f64,
+}
+
+
+impl Hyper {
+    fn new(theta: &[f64]) -> Hyper {
+        Hyper{
+            alpha: theta[0].exp(),
+            m0: theta[1],
+            kappa0: theta[2].exp(),
+            a0: theta[3].exp(),
+            b0: theta[4].exp(),
+        }
+    }
+
+    /// log density of x under the Student-t posterior predictive of a
+    /// cluster (the prior predictive if it is empty)
+    fn predictive(&self, c: &Cluster, x: f64) -> f64 {
+        let n = c.n as f64;
+        let kappa = self.kappa0 + n;
+        let m = (self.kappa0 * self.m0 + c.sum) / kappa;
+        let a = self.a0 + 0.5 * n;
+        let b = self.b0 + 0.5 * (c.sum_sq + self.kappa0 * self.m0 * self.m0 - kappa * m * m).max(0.0);
+        let nu = 2.0 * a;
+        let scale_sq = b * (kappa + 1.0) / (a * kappa);
+        ln_gamma(0.5 * (nu + 1.0)) - ln_gamma(0.5 * nu)
+            - 0.5 * (nu * PI * scale_sq).ln()
+            - 0.5 * (nu + 1.0) * (1.0 + (x - m).powi(2) / (nu * scale_sq)).ln()
+    }
+}
+
+
+/// partition of the observations seen so far, and the cluster of the next
+/// one (`clusters.len()` for a new cluster)
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    clusters: Vec<Cluster>,
+    current: usize,
+}
+
+
+/// Dirichlet process mixture of normals for one data column, with the
+/// concentration and the base measure as parameters
+///
+/// Given theta = [ln alpha, m0, ln kappa0, ln a0, ln b0] the cluster means
+/// and variances integrate out in closed form, leaving a sum over
+/// partitions. As `StateDynamics`, the partition is the state: each step
+/// seats the next observation by the Chinese restaurant process and scores
+/// it under the predictive of its table, so `ParticleFilter<DpmmMarginal>`
+/// is an estimate of the marginal likelihood that is unbiased for L, and
+/// nested sampling over theta integrates the hyperparameters out too.
+///
+/// Fields:
+/// x: the observations
+#[derive(Debug, Clone)]
+pub struct DpmmMarginal {
+    x: Vec<f64>,
+}
+
+
+impl DpmmMarginal {
+    pub fn new(x: Vec<f64>) -> Result<DpmmMarginal, Box<dyn Error>> {
+        if x.is_empty() {
+            return Err("the mixture model needs at least one observation".into())
+        }
+        if x.iter().any(|x| !x.is_finite()) {
+            return Err("the mixture model needs finite observations".into())
+        }
+        Ok(DpmmMarginal{ x })
+    }
+
+    /// the column named in the config, or the last column of the data
+    pub fn from_dataset(data: &Dataset, config: &DpmmConfig) -> Result<DpmmMarginal, Box<dyn Error>> {
+        let x = match &config.column {
+            Some(name) => data.column_by_name(name)?,
+            None if data.ncols() > 0 => data.column(data.ncols() - 1),
+            None => return Err("the data file has no columns".into()),
+        };
+        DpmmMarginal::new(x.to_vec())
+    }
+
+    /// the number of clusters after each of `sweeps` collapsed Gibbs sweeps
+    /// (Neal's algorithm 3) at theta, following `burn_in` discarded sweeps
+    pub fn gibbs_counts<R: Rng + ?Sized>(
+            &self,
+            theta: &[f64],
+            burn_in: usize,
+            sweeps: usize,
+            rng: &mut R,
+    ) -> Vec<usize> {
+        let h = Hyper::new(theta);
+        let mut z = vec![0; self.x.len()];
+        let mut clusters = vec![Cluster::default()];
+        for &x in &self.x {
+            clusters[0].add(x);
+        }
+        let mut counts = Vec::with_capacity(sweeps);
+        let mut log_p = Vec::new();
+        for sweep in 0..burn_in + sweeps {
+            for (i, &x) in self.x.iter().enumerate() {
+                let k = z[i];
+                clusters[k].remove(x);
+                if clusters[k].n == 0 {
+                    // move the last cluster into the empty slot
+                    let last = clusters.len() - 1;
+                    clusters.swap_remove(k);
+                    z.iter_mut().filter(|zj| **zj == last).for_each(|zj| *zj = k);
+                }
+                log_p.clear();
+                log_p.extend(clusters.iter().map(|c| (c.n as f64).ln() + h.predictive(c, x)));
+                log_p.push(h.alpha.ln() + h.predictive(&Cluster::default(), x));
+                let top = log_p.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let w: Vec<f64> = log_p.iter().map(|lp| (lp - top).exp()).collect();
+                let k = systematic_resample(&w, 1, rng)[0];
+                if k == clusters.len() {
+                    clusters.push(Cluster::default());
+                }
+                clusters[k].add(x);
+                z[i] = k;
+            }
+            if sweep >= burn_in {
+                counts.push(clusters.len());
+            }
+        }
+        counts
+    }
+
+    /// posterior of the number of clusters as (K, probability, standard
+    /// error), averaging the Gibbs frequencies over `config.draws` points
+    /// resampled from `posterior`, given as (theta, log normalized weight)
+    /// pairs. The standard error is that of the mean over the draws, and so
+    /// covers both the hyperparameter uncertainty and the Gibbs noise.
+    pub fn cluster_counts<R: Rng + ?Sized>(
+            &self,
+            posterior: &[(Vec<f64>, f64)],
+            config: &DpmmConfig,
+            rng: &mut R,
+    ) -> Vec<(usize, f64, f64)> {
+        let w: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+        let draws = systematic_resample(&w, config.draws, rng);
+        let sweeps = config.sweeps.max(1);
+        let freqs: Vec<Vec<f64>> = draws.iter()
+            .map(|&i| {
+                let mut freq = vec![0.0; self.x.len() + 1];
+                for k in self.gibbs_counts(&posterior[i].0, config.burn_in, sweeps, rng) {
+                    freq[k] += 1.0 / sweeps as f64;
+                }
+                freq
+            })
+            .collect();
+        let n = freqs.len() as f64;
+        (1..=self.x.len())
+            .filter_map(|k| {
+                let p = freqs.iter().map(|f| f[k]).sum::<f64>() / n;
+                if p == 0.0 {
+                    return None
+                }
+                let var = freqs.iter().map(|f| (f[k] - p).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+                Some((k, p, (var / n).sqrt()))
+            })
+            .collect()
+    }
+}
+
+
+impl StateDynamics for DpmmMarginal {
+    type State = Allocation;
+
+    fn n_steps(&self) -> usize {
+        self.x.len()
+    }
+
+    fn dim(&self) -> usize {
+        5
+    }
+
+    fn init<R: Rng>(&self, _theta: &[f64], _rng: &mut R) -> Allocation {
+        Allocation{ clusters: Vec::new(), current: 0 }
+    }
+
+    fn step<R: Rng>(&self, theta: &[f64], state: &Allocation, t: usize, rng: &mut R) -> Allocation {
+        let alpha = theta[0].exp();
+        let mut next = state.clone();
+        if next.current == next.clusters.len() {
+            next.clusters.push(Cluster::default());
+        }
+        next.clusters[next.current].add(self.x[t - 1]);
+        // seat observation t: an existing table with probability n_k /
+        // (t + alpha), a new one with alpha / (t + alpha)
+        let mut u = rng.gen::<f64>() * (t as f64 + alpha);
+        next.current = next.clusters.len();
+        for (k, c) in next.clusters.iter().enumerate() {
+            u -= c.n as f64;
+            if u < 0.0 {
+                next.current = k;
+                break
+            }
+        }
+        next
+    }
+
+    fn obs_log_density(&self, theta: &[f64], state: &Allocation, t: usize) -> f64 {
+        let empty = Cluster::default();
+        let cluster = state.clusters.get(state.current).unwrap_or(&empty);
+        Hyper::new(theta).predictive(cluster, self.x[t])
+    }
+}
+
+
+impl Simulate for ParticleFilter<DpmmMarginal> {
+    /// seat the observations by the Chinese restaurant process and draw
+    /// each new cluster's mean and variance from the base measure
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>> {
+        let h = Hyper::new(theta);
+        if !(h.a0.is_finite() && h.b0.is_finite() && h.a0 > 0.0 && h.b0 > 0.0) {
+            return Err(format!("invalid inverse gamma base measure ({}, {})", h.a0, h.b0).into())
+        }
+        let mut clusters: Vec<(usize, Normal)> = Vec::new();
+        let mut x = Vec::with_capacity(self.dynamics().x.len());
+        for t in 0..self.dynamics().x.len() {
+            let mut u = rng.gen::<f64>() * (t as f64 + h.alpha);
+            let mut k = clusters.len();
+            for (j, (n, _)) in clusters.iter().enumerate() {
+                u -= *n as f64;
+                if u < 0.0 {
+                    k = j;
+                    break
+                }
+            }
+            if k == clusters.len() {
+                // an inverse gamma draw, b0 over a Gamma(a0, 1) one
+                let var = h.b0 / sample_gamma(h.a0, rng);
+                let mean = Normal::new(h.m0, (var / h.kappa0).sqrt())?.sample(rng);
+                clusters.push((0, Normal::new(mean, var.sqrt())?));
+            }
+            clusters[k].0 += 1;
+            x.push(clusters[k].1.sample(rng));
+        }
+        Ok(x)
+    }
+}
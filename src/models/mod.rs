@@ -0,0 +1,309 @@
+mod cache;
+mod correlated;
+mod counted;
+mod dpmm;
+mod kernels;
+mod multivariate;
+mod noisy;
+mod nuisance;
+mod particle_filter;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod registry;
+#[cfg(feature = "scripting")]
+mod script;
+mod regression;
+mod seed;
+mod state_space;
+mod subsample;
+mod summary;
+mod timeseries;
+mod workers;
+
+use std::any::Any;
+use std::error::Error;
+
+use rand::RngCore;
+
+pub use cache::Cached;
+pub use correlated::{read_covariance, CorrelatedNoise};
+pub use counted::Counted;
+pub use dpmm::{DpmmConfig, DpmmMarginal};
+pub use multivariate::{Covariance, MultiGaussian, MultivariateConfig};
+pub use noisy::{Averaged, NoisyConfig};
+pub use nuisance::{LinearNuisance, Marginalized, NuisanceConfig};
+pub use particle_filter::{ParticleFilter, StateDynamics};
+#[cfg(feature = "plugins")]
+pub use plugin::{Plugin, PluginColumn, PluginEntry, PluginModel, PLUGIN_ABI_VERSION, PLUGIN_ENTRY};
+pub(crate) use registry::construct;
+pub use registry::{register_model, registered_models, ModelConstructor};
+#[cfg(feature = "scripting")]
+pub use script::{ScriptConfig, Scripted};
+pub use regression::{Censor, LinearGaussian, Noise};
+pub use seed::{split_seed, EstimateSeed};
+pub use state_space::{SsmMatrices, StateSpace};
+pub use subsample::Subsampled;
+pub use summary::{Summary, SummaryLikelihood};
+pub use timeseries::{ArmaNoise, NoiseModel};
+pub use workers::{serve_worker, WorkerPool, WorkersConfig};
+
+
+/// what a likelihood keeps of its computation at one point that depends
+/// on the slow parameters alone, for `LogLikelihood::update_fast`
+pub type SlowState = Box<dyn Any + Send>;
+
+
+/// outcome of `LogLikelihood::screen` for one candidate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Screen {
+    /// the candidate cannot beat the contour and is not evaluated
+    Reject,
+    /// the candidate has to be evaluated with `log_lik`
+    Pass,
+    /// the model already evaluated the candidate while screening it; the
+    /// caller uses this value instead of calling `log_lik` again
+    Evaluated(f64),
+}
+
+
+/// scores a parameter vector against the observed data
+///
+/// Implementations must be callable from several threads at once, since
+/// particles may be evaluated in parallel.
+pub trait LogLikelihood: Sync {
+    /// natural log of the likelihood at theta
+    fn log_lik(&self, theta: &[f64]) -> f64;
+
+    /// number of parameters the model expects in theta
+    fn dim(&self) -> usize;
+
+    /// true when the evidence computed with this model is biased, e.g.
+    /// because `log_lik` is an unbiased estimate of log L rather than of L;
+    /// runs using such a model flag their results. Noisy estimates that are
+    /// unbiased for L itself do not count (see `ParticleFilter`)
+    fn is_approximate(&self) -> bool {
+        false
+    }
+
+    /// cheap pre-check before a candidate is evaluated against `threshold`
+    /// (see `Screen`). Models without a way to tell let everything through.
+    fn screen(&self, _theta: &[f64], _threshold: f64) -> Screen {
+        Screen::Pass
+    }
+
+    /// model-specific counters (e.g. surrogate or cache statistics) that
+    /// are copied into the run results
+    fn stats(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// number of values `outputs` returns; 0 for models that keep nothing
+    /// with their particles
+    fn n_outputs(&self) -> usize {
+        0
+    }
+
+    /// values kept with each particle for posterior-predictive checks,
+    /// e.g. summary statistics of a simulated trajectory. Computed once for
+    /// every particle that enters the live set
+    fn outputs(&self, _theta: &[f64]) -> Vec<f64> {
+        Vec::new()
+    }
+
+    /// variance of `log_lik` as an estimate of log L, for likelihoods that
+    /// are Monte Carlo estimates themselves (see `ParticleFilter` and
+    /// `Averaged`); None for exact likelihoods. Runs report it to flag that
+    /// their evidence carries extra estimator noise
+    fn log_lik_variance(&self) -> Option<f64> {
+        None
+    }
+
+    /// why the likelihood can no longer be evaluated, e.g. a lost worker
+    /// process (see `WorkerPool`); None while it can. A failed likelihood
+    /// answers -inf, and runs check this after every batch of evaluations
+    /// and stop with it as their error
+    fn failure(&self) -> Option<String> {
+        None
+    }
+
+    /// log L at theta, drawing whatever random numbers a stochastic
+    /// likelihood needs from `rng`, so that repeated estimates at one
+    /// theta differ (see `Averaged`). Exact likelihoods ignore it
+    fn log_lik_with(&self, theta: &[f64], _rng: &mut dyn RngCore) -> f64 {
+        self.log_lik(theta)
+    }
+
+    /// seed the random numbers of a stochastic likelihood (see
+    /// `EstimateSeed`). Runs call it before they start, with a seed drawn
+    /// from their own rng, so that a seeded run is reproducible. Wrappers
+    /// pass it on; exact likelihoods ignore it
+    fn reseed(&self, _seed: u64) {}
+
+    /// log L at theta together with the part of its computation that
+    /// depends on the slow parameters only (those not in
+    /// `SamplerConfig::fast`), for `update_fast` to reuse. None, the
+    /// default, for models without a cheap fast path
+    fn log_lik_state(&self, _theta: &[f64]) -> Option<(f64, SlowState)> {
+        None
+    }
+
+    /// log L at theta, which differs only in fast parameters from the
+    /// point `state` was computed at by `log_lik_state`. The sampler keeps
+    /// a state for each live point and for the current point of a chain;
+    /// `state` may be changed, e.g. to reuse buffers, as long as it stays
+    /// valid for the same slow parameters
+    fn update_fast(&self, _state: &mut SlowState, theta: &[f64]) -> f64 {
+        self.log_lik(theta)
+    }
+}
+
+
+/// a likelihood that factorizes over observations, so that it can be
+/// evaluated on a subset of the data
+pub trait PointwiseLogLikelihood: LogLikelihood {
+    /// number of observations
+    fn n_obs(&self) -> usize;
+
+    /// log-likelihood contribution of observation i
+    fn log_lik_point(&self, theta: &[f64], i: usize) -> f64;
+}
+
+
+/// a likelihood that can generate data, for closure tests at known
+/// parameter values and posterior predictive checks
+pub trait Simulate: LogLikelihood {
+    /// a fresh response drawn from the model at theta, in the shape of the
+    /// observed one; models of several response columns return them one
+    /// after the other
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>>;
+
+    /// discrepancy statistics T(y, theta) of a response y in the shape
+    /// `simulate` returns, as (name, value) pairs, which posterior
+    /// predictive checks compare between the observed and replicated
+    /// responses; the checks add the mean, sd, minimum and maximum of y
+    /// to whatever the model defines
+    fn discrepancies(&self, _y: &[f64], _theta: &[f64]) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+}
+
+
+impl<T: LogLikelihood + ?Sized> LogLikelihood for &T {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        (**self).log_lik(theta)
+    }
+
+    fn dim(&self) -> usize {
+        (**self).dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        (**self).is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        (**self).screen(theta, threshold)
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        (**self).stats()
+    }
+
+    fn n_outputs(&self) -> usize {
+        (**self).n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        (**self).outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        (**self).log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        (**self).failure()
+    }
+
+    fn log_lik_with(&self, theta: &[f64], rng: &mut dyn RngCore) -> f64 {
+        (**self).log_lik_with(theta, rng)
+    }
+
+    fn reseed(&self, seed: u64) {
+        (**self).reseed(seed)
+    }
+
+    fn log_lik_state(&self, theta: &[f64]) -> Option<(f64, SlowState)> {
+        (**self).log_lik_state(theta)
+    }
+
+    fn update_fast(&self, state: &mut SlowState, theta: &[f64]) -> f64 {
+        (**self).update_fast(state, theta)
+    }
+}
+
+
+impl<T: LogLikelihood + ?Sized> LogLikelihood for Box<T> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        (**self).log_lik(theta)
+    }
+
+    fn dim(&self) -> usize {
+        (**self).dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        (**self).is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        (**self).screen(theta, threshold)
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        (**self).stats()
+    }
+
+    fn n_outputs(&self) -> usize {
+        (**self).n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        (**self).outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        (**self).log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        (**self).failure()
+    }
+
+    fn log_lik_with(&self, theta: &[f64], rng: &mut dyn RngCore) -> f64 {
+        (**self).log_lik_with(theta, rng)
+    }
+
+    fn reseed(&self, seed: u64) {
+        (**self).reseed(seed)
+    }
+
+    fn log_lik_state(&self, theta: &[f64]) -> Option<(f64, SlowState)> {
+        (**self).log_lik_state(theta)
+    }
+
+    fn update_fast(&self, state: &mut SlowState, theta: &[f64]) -> f64 {
+        (**self).update_fast(state, theta)
+    }
+}
+
+
+impl<T: PointwiseLogLikelihood + ?Sized> PointwiseLogLikelihood for &T {
+    fn n_obs(&self) -> usize {
+        (**self).n_obs()
+    }
+
+    fn log_lik_point(&self, theta: &[f64], i: usize) -> f64 {
+        (**self).log_lik_point(theta, i)
+    }
+}
@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use rand::RngCore;
+use rand::distributions::Distribution;
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use crate::dist::Normal;
+use crate::linalg::{Cholesky, Matrix};
+use super::{LogLikelihood, PointwiseLogLikelihood, Simulate};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{LinearGaussian, Noise};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_bivariate_density() {
+        let x = vec![0.0, 1.0, 2.0];
+        let y0 = vec![0.1, 1.2, 1.9];
+        let y1 = vec![1.0, 0.4, -1.1];
+        let data = Dataset::from_columns(
+            vec![x.clone(), y0.clone(), y1.clone()],
+            Some(vec!["x".into(), "a".into(), "b".into()]),
+        ).unwrap();
+        let responses = vec!["a".to_string(), "b".to_string()];
+
+        // a diagonal covariance is two independent regressions
+        let diagonal = MultiGaussian::from_dataset(&data, &responses, Covariance::Diagonal).unwrap();
+        assert_eq!(diagonal.dim(), 6);
+        let theta = [0.0, 1.0, 1.0, -1.0, 0.5, 0.8];
+        let a = LinearGaussian::new(vec![&x], &y0, Noise::Fixed(0.5)).unwrap();
+        let b = LinearGaussian::new(vec![&x], &y1, Noise::Fixed(0.8)).unwrap();
+        let expected = a.log_lik(&theta[..2]) + b.log_lik(&theta[2..4]);
+        assert!((diagonal.log_lik(&theta) - expected).abs() < 1e-12);
+
+        // full covariance from the Cholesky factor [[1, 0], [0.6, 0.8]]:
+        // unit variances with correlation 0.6
+        let full = MultiGaussian::from_dataset(&data, &responses, Covariance::Full).unwrap();
+        assert_eq!(full.dim(), 7);
+        let theta = [0.0, 1.0, 1.0, -1.0, 1.0, 0.6, 0.8];
+        let rho: f64 = 0.6;
+        let expected: f64 = (0..3).map(|i| {
+            let (u, v) = (y0[i] - x[i], y1[i] - 1.0 + x[i]);
+            let q = (u * u - 2.0 * rho * u * v + v * v) / (1.0 - rho * rho);
+            -(2.0 * PI).ln() - 0.5 * (1.0 - rho * rho).ln() - 0.5 * q
+        }).sum();
+        assert!((full.log_lik(&theta) - expected).abs() < 1e-12);
+        assert_eq!(full.log_lik(&[0.0, 1.0, 1.0, -1.0, -1.0, 0.6, 0.8]), f64::NEG_INFINITY);
+
+        let mut rng = StdRng::seed_from_u64(411);
+        let sim = full.simulate(&theta, &mut rng).unwrap();
+        assert_eq!(sim.len(), 6);
+        assert!(MultiGaussian::from_dataset(&data, &["a".to_string(), "a".to_string()], Covariance::Full).is_err());
+    }
+}
+
+
+/// how the noise of the responses is correlated
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Covariance {
+    /// independent noise; one sd per response
+    #[default]
+    Diagonal,
+    /// correlated noise; the lower Cholesky factor of the covariance
+    Full,
+}
+
+
+/// settings of the regression of several response columns at once
+///
+/// Fields:
+/// responses: names of the response columns; every other column is a
+///     predictor shared by all responses
+/// covariance: "diagonal" (default) or "full" noise covariance
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MultivariateConfig {
+    pub responses: Vec<String>,
+    pub covariance: Covariance,
+}
+
+
+/// y_k = theta_k[0] + sum_j theta_k[j+1] * x_j + e_k for m responses
+/// sharing the predictors, with the noise vector e ~ N(0, Sigma) per row
+///
+/// theta holds the m coefficient blocks of (predictors + 1) values one
+/// after the other, followed by the noise parameters: the m sds for a
+/// diagonal Sigma, or the lower Cholesky factor L of Sigma = L L^T row by
+/// row (L00, L10, L11, L20, ...) for a full one. The diagonal of L must be
+/// positive, the off-diagonal elements are free, so every theta inside
+/// the prior support gives a valid covariance.
+///
+/// Fields:
+/// x: the predictor columns
+/// y: the response columns
+/// covariance: diagonal or full noise covariance
+#[derive(Debug)]
+pub struct MultiGaussian<'a> {
+    x: Vec<&'a [f64]>,
+    y: Vec<&'a [f64]>,
+    covariance: Covariance,
+}
+
+
+impl<'a> MultiGaussian<'a> {
+    pub fn new(
+            x: Vec<&'a [f64]>,
+            y: Vec<&'a [f64]>,
+            covariance: Covariance,
+    ) -> Result<MultiGaussian<'a>, Box<dyn Error>> {
+        let n = y.first().ok_or("a multivariate model needs at least one response")?.len();
+        if x.iter().chain(&y).any(|col| col.len() != n) {
+            return Err("predictor and response columns differ in length".into())
+        }
+        Ok(MultiGaussian{ x, y, covariance })
+    }
+
+    /// the columns named in `responses` are y, every other column is a predictor
+    pub fn from_dataset(
+            data: &'a Dataset,
+            responses: &[String],
+            covariance: Covariance,
+    ) -> Result<MultiGaussian<'a>, Box<dyn Error>> {
+        let y = responses.iter()
+            .map(|name| data.column_by_name(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let x: Vec<&[f64]> = (0..data.ncols())
+            .filter(|&j| !responses.contains(&data.names()[j]))
+            .map(|j| data.column(j))
+            .collect();
+        if x.len() + y.len() != data.ncols() {
+            return Err("a response column is named more than once".into())
+        }
+        MultiGaussian::new(x, y, covariance)
+    }
+
+    pub fn n_responses(&self) -> usize {
+        self.y.len()
+    }
+
+    fn n_coef(&self) -> usize {
+        self.x.len() + 1
+    }
+
+    /// prediction for response k of observation i
+    pub fn mean(&self, theta: &[f64], i: usize, k: usize) -> f64 {
+        let coef = &theta[k * self.n_coef()..(k + 1) * self.n_coef()];
+        let mut yhat = coef[0];
+        for (j, col) in self.x.iter().enumerate() {
+            yhat += coef[j + 1] * col[i];
+        }
+        yhat
+    }
+
+    /// the Cholesky factor of Sigma, or None if theta does not give a
+    /// positive definite covariance
+    fn cholesky(&self, theta: &[f64]) -> Option<Cholesky> {
+        let m = self.n_responses();
+        let noise = &theta[m * self.n_coef()..];
+        let l = match self.covariance {
+            Covariance::Diagonal => Matrix::diag(noise),
+            Covariance::Full => {
+                let mut l = Matrix::zeros(m, m);
+                let mut next = noise.iter();
+                for r in 0..m {
+                    for c in 0..=r {
+                        l[(r, c)] = *next.next()?;
+                    }
+                }
+                l
+            },
+        };
+        Cholesky::from_lower(l)
+    }
+
+    fn residuals(&self, theta: &[f64], i: usize) -> Vec<f64> {
+        (0..self.n_responses()).map(|k| self.y[k][i] - self.mean(theta, i, k)).collect()
+    }
+
+    /// log density of row i under N(0, L L')
+    fn log_lik_row(&self, theta: &[f64], chol: &Cholesky, i: usize) -> f64 {
+        let m = self.n_responses() as f64;
+        -0.5 * (m * (2.0 * PI).ln() + chol.ln_det() + chol.quad_form(&self.residuals(theta, i)))
+    }
+}
+
+
+impl<'a> LogLikelihood for MultiGaussian<'a> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        match self.cholesky(theta) {
+            Some(chol) => (0..self.n_obs()).map(|i| self.log_lik_row(theta, &chol, i)).sum(),
+            None => f64::NEG_INFINITY,
+        }
+    }
+
+    fn dim(&self) -> usize {
+        let m = self.n_responses();
+        let noise = match self.covariance {
+            Covariance::Diagonal => m,
+            Covariance::Full => m * (m + 1) / 2,
+        };
+        m * self.n_coef() + noise
+    }
+}
+
+
+impl<'a> PointwiseLogLikelihood for MultiGaussian<'a> {
+    fn n_obs(&self) -> usize {
+        self.y[0].len()
+    }
+
+    /// joint contribution of all responses of row i
+    fn log_lik_point(&self, theta: &[f64], i: usize) -> f64 {
+        match self.cholesky(theta) {
+            Some(chol) => self.log_lik_row(theta, &chol, i),
+            None => f64::NEG_INFINITY,
+        }
+    }
+}
+
+
+impl<'a> Simulate for MultiGaussian<'a> {
+    /// the simulated responses one after the other, each n_obs long, in
+    /// the order of the configured response columns
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>> {
+        let chol = self.cholesky(theta).ok_or("theta does not give a positive definite noise covariance")?;
+        let (n, m) = (self.n_obs(), self.n_responses());
+        let normal = Normal::new(0.0, 1.0)?;
+        let mut out = vec![0.0; n * m];
+        for i in 0..n {
+            let u: Vec<f64> = (0..m).map(|_| normal.sample(rng)).collect();
+            let e = chol.l().mul_vec(&u);
+            for k in 0..m {
+                out[k * n + i] = self.mean(theta, i, k) + e[k];
+            }
+        }
+        Ok(out)
+    }
+}
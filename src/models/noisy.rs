@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::sync::Mutex;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::evidence::log_add_exp;
+use super::{split_seed, EstimateSeed, LogLikelihood, Screen};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand::distributions::Distribution;
+    use crate::dist::Normal;
+
+    // log L = -theta^2 / 2 + e - s^2 / 2 with e ~ N(0, s^2), so that the
+    // estimate of L is unbiased
+    struct Noisy {
+        sd: f64,
+        seed: AtomicU64,
+    }
+
+    impl LogLikelihood for Noisy {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            let mut rng = StdRng::seed_from_u64(self.seed.fetch_add(1, Ordering::Relaxed));
+            let e = Normal::new(0.0, self.sd).unwrap().sample(&mut rng);
+            -0.5 * theta[0] * theta[0] + e - 0.5 * self.sd * self.sd
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_averaging_shrinks_the_estimator_variance() {
+        let model = Noisy{ sd: 1.0, seed: AtomicU64::new(441) };
+        let settings = NoisyConfig{ variance: None, repeats: 16 };
+        let averaged = Averaged::new(&model, &settings).unwrap();
+        let estimates: Vec<f64> = (0..2000).map(|_| averaged.log_lik(&[0.0])).collect();
+        // still unbiased for L
+        let mean_l = estimates.iter().map(|ll| ll.exp()).sum::<f64>() / estimates.len() as f64;
+        assert!((mean_l - 1.0).abs() < 0.05, "{}", mean_l);
+        // the log of a mean of 16 lognormal estimates has variance near
+        // (e^1 - 1) / 16 by the delta method
+        let m = estimates.iter().sum::<f64>() / estimates.len() as f64;
+        let var = estimates.iter().map(|ll| (ll - m).powi(2)).sum::<f64>() / estimates.len() as f64;
+        assert!((var / ((1f64.exp() - 1.0) / 16.0) - 1.0).abs() < 0.2, "{}", var);
+        // undeclared, the variance is measured from the repeats
+        let observed = averaged.log_lik_variance().unwrap();
+        assert!((observed - 1.0 / 16.0).abs() < 0.01, "{}", observed);
+        let stats = averaged.stats();
+        assert_eq!(stats[0], ("noisy_estimates".to_string(), 32000.0));
+
+        let declared = Averaged::new(&model, &NoisyConfig{ variance: Some(4.0), repeats: 4 }).unwrap();
+        assert_eq!(declared.log_lik_variance(), Some(1.0));
+        assert!(Averaged::new(&model, &NoisyConfig{ variance: Some(-1.0), repeats: 1 }).is_err());
+        assert!(Averaged::new(&model, &NoisyConfig{ variance: None, repeats: 0 }).is_err());
+    }
+}
+
+
+/// settings of a likelihood that is itself a Monte Carlo estimate
+///
+/// Fields:
+/// variance: variance of one estimate of log L, if known; otherwise the
+///     model's own declaration is used, or the spread of the repeats
+/// repeats: estimates averaged, as likelihoods, for every evaluation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NoisyConfig {
+    pub variance: Option<f64>,
+    pub repeats: usize,
+}
+
+
+impl Default for NoisyConfig {
+    fn default() -> NoisyConfig {
+        NoisyConfig{ variance: None, repeats: 1 }
+    }
+}
+
+
+/// running sums of the sample variances of the repeats at each theta
+#[derive(Debug, Default)]
+struct Spread {
+    estimates: usize,
+    points: usize,
+    variance_sum: f64,
+}
+
+
+/// a noisy likelihood evaluated as the mean of several independent
+/// estimates of L
+///
+/// The mean of unbiased estimates of L is another unbiased estimate, so
+/// the acceptance rule stays that of `ParticleFilter`: a candidate is
+/// accepted when its own averaged estimate beats the contour, and keeps
+/// that estimate for good. Averaging only shrinks the variance of log L
+/// by about the number of repeats, which narrows the gap between the
+/// information of the noisy and the exact likelihood and so shortens the
+/// run; each evaluation costs that many estimates. Points are never
+/// re-evaluated, since replacing an estimate after acceptance would select
+/// on the noise and bias the evidence. The repeats draw one after the
+/// other from a single stream, through `LogLikelihood::log_lik_with`, so
+/// that they differ from each other.
+///
+/// Fields:
+/// model: the noisy likelihood
+/// repeats: estimates per evaluation
+/// variance: declared variance of one estimate of log L
+/// spread: the observed spread of the repeats
+/// seed: seed of the stream the repeats at each theta draw from
+pub struct Averaged<M> {
+    model: M,
+    repeats: usize,
+    variance: Option<f64>,
+    spread: Mutex<Spread>,
+    seed: EstimateSeed,
+}
+
+
+impl<M: LogLikelihood> Averaged<M> {
+    pub fn new(model: M, settings: &NoisyConfig) -> Result<Averaged<M>, Box<dyn Error>> {
+        if settings.repeats == 0 {
+            return Err("noisy.repeats must be at least 1".into())
+        }
+        if settings.variance.is_some_and(|v| !(v >= 0.0 && v.is_finite())) {
+            return Err(format!("noisy.variance = {:?} must be finite and not negative", settings.variance).into())
+        }
+        let variance = settings.variance.or_else(|| model.log_lik_variance());
+        Ok(Averaged{
+            model,
+            repeats: settings.repeats,
+            variance,
+            spread: Mutex::new(Spread::default()),
+            seed: EstimateSeed::new(),
+        })
+    }
+}
+
+
+impl<M: LogLikelihood> LogLikelihood for Averaged<M> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        self.log_lik_with(theta, &mut self.seed.rng(theta))
+    }
+
+    fn log_lik_with(&self, theta: &[f64], rng: &mut dyn RngCore) -> f64 {
+        if self.repeats == 1 {
+            self.spread.lock().unwrap().estimates += 1;
+            return self.model.log_lik_with(theta, rng)
+        }
+        let estimates: Vec<f64> = (0..self.repeats).map(|_| self.model.log_lik_with(theta, rng)).collect();
+        let n = self.repeats as f64;
+        let mut spread = self.spread.lock().unwrap();
+        spread.estimates += self.repeats;
+        if estimates.iter().all(|ll| ll.is_finite()) {
+            let mean = estimates.iter().sum::<f64>() / n;
+            spread.variance_sum += estimates.iter().map(|ll| (ll - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            spread.points += 1;
+        }
+        estimates.iter().fold(f64::NEG_INFINITY, |total, ll| log_add_exp(total, *ll)) - n.ln()
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.model.is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        match self.repeats {
+            1 => self.model.screen(theta, threshold),
+            // a screen of one estimate could reject on its noise alone
+            _ => Screen::Pass,
+        }
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        let mut stats = vec![("noisy_estimates".to_string(), self.spread.lock().unwrap().estimates as f64)];
+        if let Some(variance) = self.log_lik_variance() {
+            stats.push(("noisy_log_lik_variance".to_string(), variance));
+        }
+        stats.extend(self.model.stats());
+        stats
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.model.outputs(theta)
+    }
+
+    /// the declared variance, or else the mean sample variance of the
+    /// repeats, divided by the number of repeats (the delta method for
+    /// small variances)
+    fn log_lik_variance(&self) -> Option<f64> {
+        let single = self.variance.or_else(|| {
+            let spread = self.spread.lock().unwrap();
+            (spread.points > 0).then(|| spread.variance_sum / spread.points as f64)
+        });
+        single.map(|v| v / self.repeats as f64)
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.seed.set(seed);
+        self.model.reseed(split_seed(seed))
+    }
+}
@@ -0,0 +1,218 @@
+use std::error::Error;
+
+use rand::{Rng, RngCore};
+
+use crate::evidence::log_add_exp;
+use crate::stats::{ess, systematic_resample};
+use super::{EstimateSeed, LogLikelihood};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use rand::distributions::Distribution;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::dist::Normal;
+    use crate::linalg::Matrix;
+    use crate::models::{SsmMatrices, StateSpace};
+
+    /// x_t = phi x_{t-1} + N(0, 0.5^2), y_t = x_t + N(0, 0.3^2), x_1 ~ N(0, 1)
+    struct Ar1Obs {
+        y: Vec<f64>,
+    }
+
+    impl StateDynamics for Ar1Obs {
+        type State = f64;
+
+        fn n_steps(&self) -> usize {
+            self.y.len()
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+
+        fn init<R: Rng>(&self, _theta: &[f64], rng: &mut R) -> f64 {
+            Normal::new(0.0, 1.0).unwrap().sample(rng)
+        }
+
+        fn step<R: Rng>(&self, theta: &[f64], x: &f64, _t: usize, rng: &mut R) -> f64 {
+            Normal::new(theta[0] * x, 0.5).unwrap().sample(rng)
+        }
+
+        fn obs_log_density(&self, _theta: &[f64], x: &f64, t: usize) -> f64 {
+            let r = self.y[t] - x;
+            -0.5 * (2.0 * PI * 0.09).ln() - r * r / (2.0 * 0.09)
+        }
+    }
+
+    #[test]
+    fn test_estimate_close_to_kalman() {
+        let y = vec![0.1, 0.5, 0.2, -0.3, 0.4];
+        let kalman = StateSpace::new(vec![&y], 1, |theta: &[f64]| Some(SsmMatrices{
+            transition: Matrix::diag(&[theta[0]]),
+            observation: Matrix::identity(1),
+            process_cov: Matrix::diag(&[0.25]),
+            obs_cov: Matrix::diag(&[0.09]),
+            init_mean: vec![0.0],
+            init_cov: Matrix::identity(1),
+        })).unwrap();
+        let exact = kalman.log_lik(&[0.8]);
+
+        let pf = ParticleFilter::new(Ar1Obs{ y: y.clone() }, 2000).unwrap();
+        let mut rng = StdRng::seed_from_u64(373);
+        let reps = 20;
+        let mean: f64 = (0..reps).map(|_| pf.estimate(&[0.8], &mut rng)).sum::<f64>() / reps as f64;
+        assert!((mean - exact).abs() < 0.05);
+        assert!(pf.log_lik_spread(&[0.8], reps, &mut rng) < 0.2);
+        // unbiased for L, so the evidence is exact on the extended space
+        assert!(!pf.is_approximate());
+    }
+}
+
+
+/// nonlinear / non-Gaussian state-space dynamics for the particle filter
+///
+/// The data live inside the implementor; `obs_log_density` scores the
+/// observation at step t given a state.
+pub trait StateDynamics: Sync {
+    type State: Clone;
+
+    /// number of observed time steps
+    fn n_steps(&self) -> usize;
+
+    /// number of parameters in theta
+    fn dim(&self) -> usize;
+
+    /// draw the state at the first step
+    fn init<R: Rng>(&self, theta: &[f64], rng: &mut R) -> Self::State;
+
+    /// draw the state at step t given the state at t - 1
+    fn step<R: Rng>(&self, theta: &[f64], state: &Self::State, t: usize, rng: &mut R) -> Self::State;
+
+    /// log density of the observation at step t given the state
+    fn obs_log_density(&self, theta: &[f64], state: &Self::State, t: usize) -> f64;
+}
+
+
+/// bootstrap particle filter (sequential Monte Carlo) likelihood estimator
+///
+/// Particles are propagated through the dynamics and weighted by the
+/// observation density; the log of the product of the mean weights is
+/// returned. The likelihood estimate itself (not its log) is unbiased, and
+/// the filter resamples systematically whenever the effective sample size
+/// falls below `ess_threshold` times the number of filter particles.
+///
+/// The estimator noise needs no change to the acceptance rule, only that
+/// the rule is applied as it stands: each particle keeps the estimate it
+/// was accepted with and is never re-evaluated, and a candidate is accepted
+/// when its own fresh estimate beats the contour. That is nested sampling
+/// on the space extended by the filter's random numbers, whose evidence is
+/// the true evidence because the estimate of L is unbiased, so runs with
+/// this model are not flagged as approximate. (Re-evaluating live points,
+/// or averaging several estimates on the log scale, would break this;
+/// averaging estimates of L itself does not, see `Averaged`.) The
+/// price of the noise is a larger information H, and so a larger log Z
+/// error and a longer run; raise `n_particles` until `log_lik_spread` at a
+/// typical theta is well below one.
+///
+/// The filter's random numbers at each theta come from a stream derived
+/// from the run's seed (see `EstimateSeed`), so a seeded run is
+/// reproducible.
+///
+/// Fields:
+/// dynamics: the state-space model and its data
+/// n_particles: number of filter particles per likelihood estimate
+/// ess_threshold: resample when ESS / n_particles drops below this
+/// seed: seed of the filter's random numbers
+pub struct ParticleFilter<D: StateDynamics> {
+    dynamics: D,
+    n_particles: usize,
+    ess_threshold: f64,
+    seed: EstimateSeed,
+}
+
+
+impl<D: StateDynamics> ParticleFilter<D> {
+    pub fn new(dynamics: D, n_particles: usize) -> Result<ParticleFilter<D>, Box<dyn Error>> {
+        if n_particles < 2 {
+            return Err("the particle filter needs at least two particles".into())
+        }
+        Ok(ParticleFilter{ dynamics, n_particles, ess_threshold: 0.5, seed: EstimateSeed::new() })
+    }
+
+    pub fn with_ess_threshold(mut self, ess_threshold: f64) -> ParticleFilter<D> {
+        self.ess_threshold = ess_threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn dynamics(&self) -> &D {
+        &self.dynamics
+    }
+
+    pub fn n_particles(&self) -> usize {
+        self.n_particles
+    }
+
+    /// standard deviation of `reps` repeated log-likelihood estimates at theta
+    pub fn log_lik_spread<R: Rng>(&self, theta: &[f64], reps: usize, rng: &mut R) -> f64 {
+        let lls: Vec<f64> = (0..reps).map(|_| self.estimate(theta, rng)).collect();
+        let mean = lls.iter().sum::<f64>() / reps as f64;
+        (lls.iter().map(|ll| (ll - mean).powi(2)).sum::<f64>() / (reps as f64 - 1.0)).sqrt()
+    }
+
+    /// one log-likelihood estimate using the given rng
+    pub fn estimate<R: Rng>(&self, theta: &[f64], rng: &mut R) -> f64 {
+        let n = self.n_particles;
+        let mut states: Vec<D::State> = (0..n).map(|_| self.dynamics.init(theta, rng)).collect();
+        let mut log_w = vec![0.0; n];
+        let mut ll = 0.0;
+
+        for t in 0..self.dynamics.n_steps() {
+            if t > 0 {
+                states = states.iter()
+                    .map(|s| self.dynamics.step(theta, s, t, rng))
+                    .collect();
+            }
+            let prev = log_w.iter().fold(f64::NEG_INFINITY, |a, &b| log_add_exp(a, b));
+            for (lw, s) in log_w.iter_mut().zip(&states) {
+                *lw += self.dynamics.obs_log_density(theta, s, t);
+            }
+            let cur = log_w.iter().fold(f64::NEG_INFINITY, |a, &b| log_add_exp(a, b));
+            if cur == f64::NEG_INFINITY || cur.is_nan() {
+                return f64::NEG_INFINITY
+            }
+            // ratio of total weights is the mean incremental weight
+            ll += cur - prev;
+
+            let w: Vec<f64> = log_w.iter().map(|lw| (lw - cur).exp()).collect();
+            if ess(&w) < self.ess_threshold * n as f64 {
+                let idx = systematic_resample(&w, n, rng);
+                states = idx.iter().map(|&i| states[i].clone()).collect();
+                log_w = vec![0.0; n];
+            }
+        }
+        ll
+    }
+}
+
+
+impl<D: StateDynamics> LogLikelihood for ParticleFilter<D> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        self.estimate(theta, &mut self.seed.rng(theta))
+    }
+
+    fn dim(&self) -> usize {
+        self.dynamics.dim()
+    }
+
+    fn log_lik_with(&self, theta: &[f64], mut rng: &mut dyn RngCore) -> f64 {
+        self.estimate(theta, &mut rng)
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.seed.set(seed)
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::ffi::{c_char, c_void, CString};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use libloading::{Library, Symbol};
+
+use crate::data::Dataset;
+use super::LogLikelihood;
+
+
+/// version of the plugin interface below; plugins built against another
+/// version are refused
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+
+/// name of the symbol every plugin library exports, of type
+/// `PluginEntry`
+pub const PLUGIN_ENTRY: &[u8] = b"ns_plugin_model\0";
+
+
+/// one data column handed to a plugin. The array of columns is only valid
+/// during the call, the names and values they point to while the model
+/// lives
+#[repr(C)]
+pub struct PluginColumn {
+    pub name: *const c_char,
+    pub values: *const f64,
+    pub len: usize,
+}
+
+
+/// the vtable a plugin returns for one model
+///
+/// `state` is the plugin's model, passed back to every call; a null state
+/// means the plugin has no model of that name or could not build it. The
+/// model may be called from several threads at once and must be freed by
+/// `free` exactly once.
+///
+/// Fields:
+/// abi_version: PLUGIN_ABI_VERSION of the plugin's build
+/// state: opaque pointer to the plugin's model
+/// dim: number of parameters the model expects
+/// log_lik: log-likelihood at the `dim` values behind the pointer
+/// free: releases the state
+#[repr(C)]
+pub struct PluginModel {
+    pub abi_version: u32,
+    pub state: *mut c_void,
+    pub dim: usize,
+    pub log_lik: unsafe extern "C" fn(state: *const c_void, theta: *const f64, len: usize) -> f64,
+    pub free: unsafe extern "C" fn(state: *mut c_void),
+}
+
+
+/// builds the model named `name` from the data columns
+pub type PluginEntry = unsafe extern "C" fn(
+    name: *const c_char,
+    columns: *const PluginColumn,
+    n_columns: usize,
+) -> PluginModel;
+
+
+/// a likelihood implemented in a dynamically loaded `cdylib`
+///
+/// `model = "my_plugin::ode_v2"` looks for the library my_plugin (e.g.
+/// libmy_plugin.so on Linux) in the config's plugin_dirs, and asks its
+/// `ns_plugin_model` entry point for the model ode_v2. The library stays
+/// loaded while the model lives, and the columns it was given borrow the
+/// data.
+///
+/// Fields:
+/// model: the vtable returned by the plugin, freed on drop
+/// _names: the column names handed to the plugin
+/// _library: keeps the plugin's code loaded; dropped after `model`
+/// _data: ties the model to the data its columns point into
+pub struct Plugin<'a> {
+    model: PluginModel,
+    _names: Vec<CString>,
+    _library: Library,
+    _data: PhantomData<&'a Dataset>,
+}
+
+
+// the plugin interface requires models to be callable from several threads
+unsafe impl Send for Plugin<'_> {}
+unsafe impl Sync for Plugin<'_> {}
+
+
+impl<'a> Plugin<'a> {
+    pub fn load(
+            library: &str,
+            model: &str,
+            dirs: &[PathBuf],
+            data: &'a Dataset,
+    ) -> Result<Plugin<'a>, Box<dyn Error>> {
+        let path = find_library(library, dirs)?;
+        let lib = unsafe { Library::new(&path) }
+            .map_err(|e| format!("cannot load plugin {}: {}", path.display(), e))?;
+        let names = data.names().iter()
+            .map(|n| CString::new(n.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let columns: Vec<PluginColumn> = (0..data.ncols())
+            .map(|j| {
+                let values = data.column(j);
+                PluginColumn{ name: names[j].as_ptr(), values: values.as_ptr(), len: values.len() }
+            })
+            .collect();
+        let name = CString::new(model)?;
+        let built = unsafe {
+            let entry: Symbol<PluginEntry> = lib.get(PLUGIN_ENTRY)
+                .map_err(|e| format!("{} is not a plugin: {}", path.display(), e))?;
+            entry(name.as_ptr(), columns.as_ptr(), columns.len())
+        };
+        if built.abi_version != PLUGIN_ABI_VERSION {
+            return Err(format!(
+                "{} implements plugin interface version {}, this build version {}",
+                path.display(), built.abi_version, PLUGIN_ABI_VERSION,
+            ).into())
+        }
+        if built.state.is_null() {
+            return Err(format!("plugin {} has no model {:?} or could not build it", path.display(), model).into())
+        }
+        Ok(Plugin{ model: built, _names: names, _library: lib, _data: PhantomData })
+    }
+}
+
+
+/// the first of the directories holding the platform's file name for the
+/// library
+fn find_library(library: &str, dirs: &[PathBuf]) -> Result<PathBuf, Box<dyn Error>> {
+    let file = libloading::library_filename(library);
+    dirs.iter()
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!(
+            "no plugin {} in plugin_dirs ({})",
+            Path::new(&file).display(),
+            dirs.iter().map(|d| d.display().to_string()).collect::<Vec<_>>().join(", "),
+        ).into())
+}
+
+
+impl LogLikelihood for Plugin<'_> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        if theta.len() != self.model.dim {
+            return f64::NEG_INFINITY
+        }
+        let ll = unsafe { (self.model.log_lik)(self.model.state, theta.as_ptr(), theta.len()) };
+        if ll.is_nan() { f64::NEG_INFINITY } else { ll }
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim
+    }
+}
+
+
+impl Drop for Plugin<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.model.free)(self.model.state) }
+    }
+}
+
+
+impl std::fmt::Debug for Plugin<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin").field("dim", &self.model.dim).finish()
+    }
+}
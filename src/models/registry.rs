@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{OnceLock, RwLock};
+
+use crate::data::Dataset;
+use crate::Config;
+use super::{
+    read_covariance, ArmaNoise, CorrelatedNoise, DpmmMarginal, LinearGaussian, LinearNuisance, LogLikelihood,
+    Marginalized, MultiGaussian, NoiseModel, ParticleFilter, Subsampled,
+};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Flat;
+
+    impl LogLikelihood for Flat {
+        fn log_lik(&self, _theta: &[f64]) -> f64 {
+            0.0
+        }
+
+        fn dim(&self) -> usize {
+            4
+        }
+    }
+
+    fn flat<'a>(_config: &Config, _data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+        Ok(Box::new(Flat))
+    }
+
+    #[test]
+    fn test_models_are_found_by_name() {
+        let data = Dataset::from_columns(vec![vec![0.0, 1.0, 2.0], vec![1.0, 3.0, 5.0]], None).unwrap();
+        let config = Config::default();
+        assert_eq!(construct("regression", &config, &data).unwrap().dim(), 3);
+        assert!(construct("dpmm", &config, &data).is_err());
+        assert!(construct("no_such_model", &config, &data).is_err());
+
+        register_model("test_flat", flat).unwrap();
+        assert!(register_model("test_flat", flat).is_err());
+        assert!(register_model("regression", flat).is_err());
+        assert!(register_model("lib::model", flat).is_err());
+        assert_eq!(construct("test_flat", &config, &data).unwrap().dim(), 4);
+        assert!(registered_models().contains(&"test_flat".to_string()));
+    }
+}
+
+
+/// builds a likelihood from the run's config, borrowing from its data
+pub type ModelConstructor = for<'a> fn(&Config, &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>>;
+
+
+/// the models selectable with `model = "name"`, built-ins first
+fn registry() -> &'static RwLock<HashMap<String, ModelConstructor>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, ModelConstructor>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: [(&str, ModelConstructor); 3] = [
+            ("regression", regression),
+            ("multivariate", multivariate),
+            ("dpmm", dpmm),
+        ];
+        let builtins = builtins.into_iter();
+        #[cfg(feature = "scripting")]
+        let builtins = builtins.chain([("script", script as ModelConstructor)]);
+        RwLock::new(builtins.map(|(name, f)| (name.to_string(), f)).collect())
+    })
+}
+
+
+/// make a likelihood selectable from configs as `model = "name"`, e.g.
+/// from a program that embeds the sampler. Names are unique, and names
+/// with `::` are reserved for plugins
+pub fn register_model(name: &str, constructor: ModelConstructor) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() || name.contains("::") {
+        return Err(format!("{:?} is not a valid model name", name).into())
+    }
+    let mut models = registry().write().map_err(|_| "the model registry is poisoned")?;
+    if models.contains_key(name) {
+        return Err(format!("a model named {:?} is already registered", name).into())
+    }
+    models.insert(name.to_string(), constructor);
+    Ok(())
+}
+
+
+/// names of the registered models, sorted
+pub fn registered_models() -> Vec<String> {
+    let mut names: Vec<String> = match registry().read() {
+        Ok(models) => models.keys().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+
+/// build the model registered as `name`, or load it from a plugin for
+/// names of the form `library::model`
+pub(crate) fn construct<'a>(
+        name: &str,
+        config: &Config,
+        data: &'a Dataset,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    if let Some((library, model)) = name.split_once("::") {
+        return load_plugin(library, model, config, data)
+    }
+    let constructor = registry().read().map_err(|_| "the model registry is poisoned")?.get(name).copied();
+    match constructor {
+        Some(constructor) => constructor(config, data),
+        None => Err(format!("unknown model {:?}; registered models are {}", name, registered_models().join(", ")).into()),
+    }
+}
+
+
+#[cfg(feature = "plugins")]
+fn load_plugin<'a>(
+        library: &str,
+        model: &str,
+        config: &Config,
+        data: &'a Dataset,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    Ok(Box::new(super::plugin::Plugin::load(library, model, &config.plugin_dirs, data)?))
+}
+
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugin<'a>(
+        library: &str,
+        model: &str,
+        _config: &Config,
+        _data: &'a Dataset,
+) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    Err(format!("model {}::{} needs a build with the plugins feature", library, model).into())
+}
+
+
+/// linear regression of the last column on the others, with the noise,
+/// censoring and nuisance options of the config
+fn regression<'a>(config: &Config, data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let mut aux_columns: Vec<&str> = [&config.censor_column, &config.upper_column]
+        .iter()
+        .filter_map(|c| c.as_deref())
+        .collect();
+    if let Some(nuisance) = &config.nuisance {
+        aux_columns.extend(nuisance.columns.iter().map(|c| c.as_str()));
+    }
+    let mut regression = LinearGaussian::from_dataset(data, config.noise_sd, &aux_columns)?;
+    if let Some(flags) = &config.censor_column {
+        let upper = match &config.upper_column {
+            Some(name) => Some(data.column_by_name(name)?),
+            None => None,
+        };
+        regression = regression.with_censoring(data.column_by_name(flags)?, upper)?;
+    }
+    if config.truncate_lower.is_some() || config.truncate_upper.is_some() {
+        regression = regression.with_truncation(config.truncate_lower, config.truncate_upper)?;
+    }
+
+    Ok(match (config.noise_model, config.subsample, &config.noise_cov_file) {
+        (NoiseModel::White, None, Some(path)) => Box::new(CorrelatedNoise::new(regression, &read_covariance(path)?)?),
+        (NoiseModel::White, None, None) => match &config.nuisance {
+            Some(nuisance) => Box::new(Marginalized::new(regression, LinearNuisance::from_dataset(data, nuisance)?)?),
+            None => Box::new(regression),
+        },
+        (NoiseModel::White, Some(batch), _) => Box::new(Subsampled::new(
+            regression,
+            batch,
+            config.subsample_reference.as_deref(),
+        )?),
+        (_, Some(_), _) => return Err("subsampling needs independent (white) noise".into()),
+        (noise_model, None, _) => Box::new(ArmaNoise::new(regression, noise_model)?),
+    })
+}
+
+
+/// several responses regressed at once, from the `multivariate` section
+fn multivariate<'a>(config: &Config, data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let multivariate = config.multivariate.as_ref().ok_or("the multivariate model needs a [multivariate] section")?;
+    Ok(Box::new(MultiGaussian::from_dataset(data, &multivariate.responses, multivariate.covariance)?))
+}
+
+
+/// Dirichlet process mixture of one column, from the `dpmm` section
+fn dpmm<'a>(config: &Config, data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let dpmm = config.dpmm.as_ref().ok_or("the dpmm model needs a [dpmm] section")?;
+    let mixture = DpmmMarginal::from_dataset(data, dpmm)?;
+    Ok(Box::new(ParticleFilter::new(mixture, dpmm.filter_particles)?))
+}
+
+
+/// a likelihood written in a script, from the `script` section
+#[cfg(feature = "scripting")]
+fn script<'a>(config: &Config, data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+    let script = config.script.as_ref().ok_or("the script model needs a [script] section")?;
+    Ok(Box::new(super::Scripted::from_config(script, data, config.n_params())?))
+}
@@ -0,0 +1,344 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use rand::RngCore;
+use rand::distributions::Distribution;
+
+use crate::data::Dataset;
+use crate::dist::{erfc, Normal};
+use super::{LogLikelihood, PointwiseLogLikelihood, Simulate};
+use super::kernels::sum_sq_residuals;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_censored_contributions() {
+        let x = vec![0.0, 1.0, 2.0, 3.0];
+        let y = vec![0.2, 1.1, 2.0, 2.5];
+        // observed, left-censored, right-censored, interval [2.5, 3.5]
+        let flags = vec![0.0, 1.0, 2.0, 3.0];
+        let upper = vec![0.0, 0.0, 0.0, 3.5];
+        let model = LinearGaussian::new(vec![&x], &y, Noise::Fixed(1.0)).unwrap()
+            .with_censoring(&flags, Some(&upper)).unwrap();
+        let theta = [0.0, 1.0];
+
+        let phi = |z: f64| 0.5 * erfc(-z / 2f64.sqrt());
+        let expected = -0.5 * (2.0 * PI).ln() - 0.5 * 0.2 * 0.2
+            + phi(0.1).ln()
+            + (1.0 - phi(0.0)).ln()
+            + (phi(0.5) - phi(-0.5)).ln();
+        assert!((model.log_lik(&theta) - expected).abs() < 1e-12);
+
+        let truncated = LinearGaussian::new(vec![&x], &y, Noise::Fixed(1.0)).unwrap()
+            .with_truncation(Some(0.0), None).unwrap();
+        let untruncated = LinearGaussian::new(vec![&x], &y, Noise::Fixed(1.0)).unwrap();
+        let norm: f64 = (0..4).map(|i| (1.0 - phi(-(i as f64))).ln()).sum();
+        assert!((truncated.log_lik(&theta) - (untruncated.log_lik(&theta) - norm)).abs() < 1e-12);
+
+        // a zero-width interval counts as observed rather than zeroing the likelihood
+        let point = LinearGaussian::new(vec![&x], &y, Noise::Fixed(1.0)).unwrap()
+            .with_censoring(&[0.0, 0.0, 0.0, 3.0], Some(&[0.0, 0.0, 0.0, 2.5])).unwrap();
+        assert_eq!(point.log_lik(&theta), untruncated.log_lik(&theta));
+
+        // censored rows only take mass from inside the truncation bounds
+        let both = LinearGaussian::new(vec![&x], &y, Noise::Fixed(1.0)).unwrap()
+            .with_censoring(&flags, Some(&[0.0, 0.0, 0.0, 4.0])).unwrap()
+            .with_truncation(Some(0.0), Some(3.0)).unwrap();
+        let within = |lo: f64, hi: f64| (phi(hi) - phi(lo)).ln();
+        let expected = -0.5 * (2.0 * PI).ln() - 0.5 * 0.2 * 0.2 - within(0.0, 3.0)
+            + within(-1.0, 0.1) - within(-1.0, 2.0)
+            + within(0.0, 1.0) - within(-2.0, 1.0)
+            + within(-0.5, 0.0) - within(-3.0, 0.0);
+        assert!((both.log_lik(&theta) - expected).abs() < 1e-12);
+    }
+}
+
+
+/// how the observation noise of a regression model is set
+#[derive(Debug, Clone, Copy)]
+pub enum Noise {
+    /// known standard deviation
+    Fixed(f64),
+    /// standard deviation is the last element of theta
+    Sampled,
+}
+
+
+/// how an observation entered the data, read from the flag column
+///
+/// Flags are 0 for an exact observation, 1 for left-censored (the true value
+/// is at most y, e.g. below a detection limit), 2 for right-censored (at
+/// least y, e.g. a survival time at the end of follow-up) and 3 for
+/// interval-censored (between y and the value in the upper-bound column).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Censor {
+    Observed,
+    Left,
+    Right,
+    Interval(f64),
+}
+
+
+/// y = theta[0] + sum_k theta[k+1] * x_k + e, with e ~ N(0, sigma^2)
+///
+/// Columns are borrowed from the dataset, so a memory-mapped dataset is
+/// never copied. Censored observations contribute the normal CDF mass of
+/// their censoring region instead of the density, and if the data were
+/// truncated to [lower, upper] every contribution is renormalized by the
+/// mass inside the truncation bounds.
+///
+/// Fields:
+/// x: the predictor columns
+/// y: the response column
+/// noise: fixed or sampled noise standard deviation
+/// censor: per-observation censoring, if any observation is censored
+/// truncation: lower and upper truncation bounds, infinite when open
+#[derive(Debug)]
+pub struct LinearGaussian<'a> {
+    x: Vec<&'a [f64]>,
+    y: &'a [f64],
+    noise: Noise,
+    censor: Option<Vec<Censor>>,
+    truncation: Option<(f64, f64)>,
+}
+
+
+impl<'a> LinearGaussian<'a> {
+    pub fn new(
+            x: Vec<&'a [f64]>,
+            y: &'a [f64],
+            noise: Noise,
+    ) -> Result<LinearGaussian<'a>, Box<dyn Error>> {
+        if x.iter().any(|col| col.len() != y.len()) {
+            return Err("predictor and response columns differ in length".into())
+        }
+        if let Noise::Fixed(sd) = noise {
+            if sd.is_nan() || sd <= 0.0 {
+                return Err(format!("noise sd must be positive, got {}", sd).into())
+            }
+        }
+        Ok(LinearGaussian{ x, y, noise, censor: None, truncation: None })
+    }
+
+    /// the last column of the dataset is y, every other column except those
+    /// named in `skip` (e.g. a censoring flag column) is a predictor.
+    /// A `noise_sd` of None samples the noise sd as the last parameter.
+    pub fn from_dataset(
+            data: &'a Dataset,
+            noise_sd: Option<f64>,
+            skip: &[&str],
+    ) -> Result<LinearGaussian<'a>, Box<dyn Error>> {
+        if data.ncols() == 0 {
+            return Err("the data file has no columns".into())
+        }
+        let last = data.ncols() - 1;
+        if skip.contains(&data.names()[last].as_str()) {
+            return Err(format!(
+                "column {} is the response (last column) and cannot also be an auxiliary column",
+                data.names()[last],
+            ).into())
+        }
+        let x: Vec<&[f64]> = (0..last)
+            .filter(|&j| !skip.contains(&data.names()[j].as_str()))
+            .map(|j| data.column(j))
+            .collect();
+        let noise = match noise_sd {
+            Some(sd) => Noise::Fixed(sd),
+            None => Noise::Sampled,
+        };
+        LinearGaussian::new(x, data.column(last), noise)
+    }
+
+    /// mark observations as censored using a flag column (see `Censor`).
+    /// Interval-censored rows take their upper bound from `upper`.
+    pub fn with_censoring(
+            mut self,
+            flags: &[f64],
+            upper: Option<&[f64]>,
+    ) -> Result<LinearGaussian<'a>, Box<dyn Error>> {
+        if flags.len() != self.y.len() || upper.is_some_and(|u| u.len() != self.y.len()) {
+            return Err("censoring columns differ in length from the response".into())
+        }
+        let mut censor = Vec::with_capacity(flags.len());
+        for (i, flag) in flags.iter().enumerate() {
+            let c = match *flag as i64 {
+                _ if flag.fract() != 0.0 => {
+                    return Err(format!("censoring flag {} on row {} is not an integer", flag, i).into())
+                },
+                0 => Censor::Observed,
+                1 => Censor::Left,
+                2 => Censor::Right,
+                3 => {
+                    let hi = upper
+                        .ok_or("interval-censored rows need an upper-bound column")?[i];
+                    if hi.is_nan() || hi < self.y[i] {
+                        return Err(format!(
+                            "row {} has interval upper bound {} below its lower bound {}",
+                            i, hi, self.y[i],
+                        ).into())
+                    }
+                    // a zero-width interval is an exact observation; its
+                    // interval mass would be 0 for every theta
+                    if hi == self.y[i] { Censor::Observed } else { Censor::Interval(hi) }
+                },
+                _ => return Err(format!("unknown censoring flag {} on row {}", flag, i).into()),
+            };
+            censor.push(c);
+        }
+        self.censor = Some(censor);
+        Ok(self)
+    }
+
+    /// declare that only responses inside [lower, upper] could have been
+    /// recorded; None leaves that side open
+    pub fn with_truncation(
+            mut self,
+            lower: Option<f64>,
+            upper: Option<f64>,
+    ) -> Result<LinearGaussian<'a>, Box<dyn Error>> {
+        let lower = lower.unwrap_or(f64::NEG_INFINITY);
+        let upper = upper.unwrap_or(f64::INFINITY);
+        if lower.is_nan() || upper.is_nan() || lower >= upper {
+            return Err(format!("truncation bounds [{}, {}] are not ordered", lower, upper).into())
+        }
+        if let Some(i) = self.y.iter().position(|&y| y < lower || y > upper) {
+            return Err(format!("observation {} lies outside the truncation bounds", i).into())
+        }
+        self.truncation = Some((lower, upper));
+        Ok(self)
+    }
+
+    /// number of regression coefficients, including the intercept
+    pub(crate) fn n_coef(&self) -> usize {
+        self.x.len() + 1
+    }
+
+    pub(crate) fn is_censored_or_truncated(&self) -> bool {
+        self.censor.is_some() || self.truncation.is_some()
+    }
+
+    pub(crate) fn sigma(&self, theta: &[f64]) -> f64 {
+        match self.noise {
+            Noise::Fixed(sd) => sd,
+            Noise::Sampled => theta[theta.len() - 1],
+        }
+    }
+
+    /// prediction for observation i
+    pub fn mean(&self, theta: &[f64], i: usize) -> f64 {
+        let mut yhat = theta[0];
+        for (k, col) in self.x.iter().enumerate() {
+            yhat += theta[k + 1] * col[i];
+        }
+        yhat
+    }
+
+    /// residual of observation i
+    pub fn residual(&self, theta: &[f64], i: usize) -> f64 {
+        self.y[i] - self.mean(theta, i)
+    }
+}
+
+
+impl<'a> LogLikelihood for LinearGaussian<'a> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let sigma = self.sigma(theta);
+        if sigma.is_nan() || sigma <= 0.0 {
+            return f64::NEG_INFINITY
+        }
+        if self.censor.is_some() || self.truncation.is_some() {
+            return (0..self.y.len()).map(|i| self.log_lik_point(theta, i)).sum()
+        }
+        let ss = sum_sq_residuals(&self.x, self.y, &theta[..self.n_coef()]);
+        let n = self.y.len() as f64;
+        -0.5 * n * (2.0 * PI * sigma * sigma).ln() - ss / (2.0 * sigma * sigma)
+    }
+
+    fn dim(&self) -> usize {
+        match self.noise {
+            Noise::Fixed(_) => self.x.len() + 1,
+            Noise::Sampled => self.x.len() + 2,
+        }
+    }
+}
+
+
+impl<'a> Simulate for LinearGaussian<'a> {
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>> {
+        if self.is_censored_or_truncated() {
+            return Err("censored or truncated data cannot be simulated".into())
+        }
+        let noise = Normal::new(0.0, self.sigma(theta))?;
+        Ok((0..self.y.len()).map(|i| self.mean(theta, i) + noise.sample(rng)).collect())
+    }
+
+    /// the chi-square of y about the regression line
+    fn discrepancies(&self, y: &[f64], theta: &[f64]) -> Vec<(String, f64)> {
+        let sigma = self.sigma(theta);
+        let chi_sq = y.iter().enumerate().map(|(i, y)| ((y - self.mean(theta, i)) / sigma).powi(2)).sum();
+        vec![("chi_square".to_string(), chi_sq)]
+    }
+}
+
+
+impl<'a> PointwiseLogLikelihood for LinearGaussian<'a> {
+    fn n_obs(&self) -> usize {
+        self.y.len()
+    }
+
+    fn log_lik_point(&self, theta: &[f64], i: usize) -> f64 {
+        let sigma = self.sigma(theta);
+        if sigma.is_nan() || sigma <= 0.0 {
+            return f64::NEG_INFINITY
+        }
+        let mu = self.mean(theta, i);
+        let z = (self.y[i] - mu) / sigma;
+        let censor = self.censor.as_ref().map_or(Censor::Observed, |c| c[i]);
+        let (lower, upper) = match self.truncation {
+            Some(bounds) => bounds,
+            None => return match censor {
+                Censor::Observed => -0.5 * (2.0 * PI * sigma * sigma).ln() - 0.5 * z * z,
+                Censor::Left => ln_normal_cdf(z),
+                Censor::Right => ln_normal_cdf(-z),
+                Censor::Interval(hi) => ln_normal_interval(z, (hi - mu) / sigma),
+            },
+        };
+        // a censored row's mass can only come from the recordable range, so
+        // its tails stop at the truncation bounds
+        let (a, b) = ((lower - mu) / sigma, (upper - mu) / sigma);
+        let ll = match censor {
+            Censor::Observed => -0.5 * (2.0 * PI * sigma * sigma).ln() - 0.5 * z * z,
+            Censor::Left => ln_normal_interval(a, z),
+            Censor::Right => ln_normal_interval(z, b),
+            Censor::Interval(hi) => ln_normal_interval(z, (hi.min(upper) - mu) / sigma),
+        };
+        ll - ln_normal_interval(a, b)
+    }
+}
+
+
+/// log of the standard normal CDF, accurate far into the lower tail
+pub(crate) fn ln_normal_cdf(z: f64) -> f64 {
+    if z > -30.0 {
+        (0.5 * erfc(-z / 2f64.sqrt())).ln()
+    } else {
+        // leading term of the asymptotic expansion of the Mills ratio
+        -0.5 * z * z - (-z).ln() - 0.5 * (2.0 * PI).ln()
+    }
+}
+
+
+/// log of the standard normal mass between a and b
+pub(crate) fn ln_normal_interval(a: f64, b: f64) -> f64 {
+    if a >= b {
+        return f64::NEG_INFINITY
+    }
+    // work in the lower tail, where the CDF keeps its precision
+    let (a, b) = if a > 0.0 { (-b, -a) } else { (a, b) };
+    let ln_b = ln_normal_cdf(b);
+    let ln_a = ln_normal_cdf(a);
+    ln_b + (-(ln_a - ln_b).exp()).ln_1p()
+}
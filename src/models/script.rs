@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use super::LogLikelihood;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_regression() {
+        let data = Dataset::from_columns(
+            vec![vec![1.0, 3.0, 5.0], vec![0.0, 1.0, 2.0]],
+            Some(vec!["y".to_string(), "x".to_string()]),
+        ).unwrap();
+        let source = r#"
+            fn log_lik(theta, data) {
+                let y = data["y"];
+                let x = data["x"];
+                let total = 0.0;
+                for i in 0..data.rows {
+                    let r = y[i] - theta[0] - theta[1] * x[i];
+                    total -= 0.5 * r * r;
+                }
+                total
+            }
+
+            fn derived(theta, data) {
+                [theta[0] + theta[1]]
+            }
+        "#;
+        let model = Scripted::compile(source, &data, 2, vec!["at_one".to_string()]).unwrap();
+        assert_eq!(model.dim(), 2);
+        assert_eq!(model.log_lik(&[1.0, 2.0]), 0.0);
+        assert_eq!(model.log_lik(&[0.0, 2.0]), -1.5);
+        assert_eq!(model.n_outputs(), 1);
+        assert_eq!(model.outputs(&[1.0, 2.0]), vec![3.0]);
+
+        assert!(Scripted::compile("fn log_lik(theta) { 0.0 }", &data, 2, Vec::new()).is_err());
+        assert!(Scripted::compile("fn log_lik(theta, data) { data[\"z\"][0] }", &data, 2, Vec::new()).is_err());
+        assert!(Scripted::compile("fn other(theta, data) { 0.0 }", &data, 2, Vec::new()).is_err());
+    }
+}
+
+
+/// a likelihood written in a Rhai script
+///
+/// The script defines `fn log_lik(theta, data)`, returning the log
+/// likelihood, and, if `derived` names any quantities, `fn derived(theta,
+/// data)`, returning an array of their values. `theta` is an array of
+/// floats and `data["name"]` a data column, indexed by row, with
+/// `data.rows` rows.
+///
+/// Fields:
+/// file: the script
+/// derived: names of the derived quantities, kept with every particle
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ScriptConfig {
+    pub file: PathBuf,
+    pub derived: Vec<String>,
+}
+
+
+/// one data column as seen by a script
+#[derive(Clone)]
+struct Column(Arc<Vec<f64>>);
+
+
+/// the data as seen by a script; cheap to clone into every call
+#[derive(Clone)]
+struct Columns {
+    names: Arc<Vec<String>>,
+    columns: Arc<Vec<Column>>,
+    rows: usize,
+}
+
+
+impl Columns {
+    fn get(&mut self, name: &str) -> Result<Column, Box<EvalAltResult>> {
+        match self.names.iter().position(|n| n == name) {
+            Some(j) => Ok(self.columns[j].clone()),
+            None => Err(format!("no data column named {:?}", name).into()),
+        }
+    }
+}
+
+
+/// a likelihood defined by an embedded script, for exploratory models
+/// that should not need a rebuild. Scripts run one to two orders of
+/// magnitude slower than compiled models.
+///
+/// Fields:
+/// engine: the interpreter, with the data types registered
+/// ast: the compiled script
+/// data: the data columns, copied once when the script is compiled
+/// dim: number of parameters, from the prior
+/// derived: names of the derived quantities
+pub struct Scripted {
+    engine: Engine,
+    ast: AST,
+    data: Columns,
+    dim: usize,
+    derived: Vec<String>,
+}
+
+
+impl Scripted {
+    pub fn from_config(config: &ScriptConfig, data: &Dataset, dim: usize) -> Result<Scripted, Box<dyn Error>> {
+        let source = std::fs::read_to_string(&config.file)
+            .map_err(|e| format!("cannot read {}: {}", config.file.display(), e))?;
+        Scripted::compile(&source, data, dim, config.derived.clone())
+            .map_err(|e| format!("{}: {}", config.file.display(), e).into())
+    }
+
+    /// compile a script and check that it runs at theta = 0
+    pub fn compile(
+            source: &str,
+            data: &Dataset,
+            dim: usize,
+            derived: Vec<String>,
+    ) -> Result<Scripted, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.register_type_with_name::<Columns>("Data")
+            .register_indexer_get(Columns::get)
+            .register_get("rows", |c: &mut Columns| c.rows as INT);
+        engine.register_type_with_name::<Column>("Column")
+            .register_indexer_get(|c: &mut Column, i: INT| -> Result<f64, Box<EvalAltResult>> {
+                c.0.get(i as usize).copied().ok_or_else(|| format!("row {} out of range", i).into())
+            })
+            .register_get("len", |c: &mut Column| c.0.len() as INT);
+        let ast = engine.compile(source)?;
+
+        let data = Columns{
+            names: Arc::new(data.names().to_vec()),
+            columns: Arc::new((0..data.ncols()).map(|j| Column(Arc::new(data.column(j).to_vec()))).collect()),
+            rows: data.nrows(),
+        };
+        let model = Scripted{ engine, ast, data, dim, derived };
+        let origin = vec![0.0; dim];
+        let _ = model.call("log_lik", &origin)?;
+        if !model.derived.is_empty() {
+            let _ = model.call("derived", &origin)?;
+        }
+        Ok(model)
+    }
+
+    fn call(&self, function: &str, theta: &[f64]) -> Result<Dynamic, Box<EvalAltResult>> {
+        let theta: Array = theta.iter().map(|&t| Dynamic::from_float(t)).collect();
+        self.engine.call_fn(&mut Scope::new(), &self.ast, function, (theta, self.data.clone()))
+    }
+
+    pub fn derived_names(&self) -> &[String] {
+        &self.derived
+    }
+}
+
+
+/// a script's number, whether it came out as a float or an integer
+fn as_f64(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|i| i as f64))
+}
+
+
+impl LogLikelihood for Scripted {
+    /// runtime errors in the script count as impossible parameters
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        match self.call("log_lik", theta).ok().as_ref().and_then(as_f64) {
+            Some(ll) if !ll.is_nan() => ll,
+            _ => f64::NEG_INFINITY,
+        }
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.derived.len()
+    }
+
+    /// the derived quantities at theta; NaN where the script fails
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        let values: Option<Vec<f64>> = self.call("derived", theta).ok()
+            .and_then(|v| v.try_cast::<Array>())
+            .and_then(|a| a.iter().map(as_f64).collect());
+        match values {
+            Some(values) if values.len() == self.derived.len() => values,
+            _ => vec![f64::NAN; self.derived.len()],
+        }
+    }
+}
+
+
+impl std::fmt::Debug for Scripted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scripted").field("dim", &self.dim).field("derived", &self.derived).finish()
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams_follow_seed_and_theta() {
+        let seed = EstimateSeed::new();
+        seed.set(465);
+        let first: f64 = seed.rng(&[0.5, 1.0]).gen();
+        assert_eq!(first, seed.rng(&[0.5, 1.0]).gen::<f64>());
+        assert_ne!(first, seed.rng(&[0.5, 1.5]).gen::<f64>());
+        seed.set(466);
+        assert_ne!(first, seed.rng(&[0.5, 1.0]).gen::<f64>());
+        assert_ne!(split_seed(465), 465);
+    }
+}
+
+
+/// the random numbers of a stochastic likelihood, e.g. a minibatch or a
+/// particle filter estimate
+///
+/// Each estimate draws from a stream of its own, derived from the seed and
+/// theta alone, so that the estimates of a run do not depend on which
+/// thread makes them or in what order. Runs set the seed from their own
+/// rng through `LogLikelihood::reseed`; until then it is drawn at random.
+///
+/// Fields:
+/// seed: the seed the streams are derived from
+#[derive(Debug)]
+pub struct EstimateSeed {
+    seed: AtomicU64,
+}
+
+
+impl EstimateSeed {
+    pub fn new() -> EstimateSeed {
+        EstimateSeed{ seed: AtomicU64::new(thread_rng().gen()) }
+    }
+
+    pub fn set(&self, seed: u64) {
+        self.seed.store(seed, Ordering::Relaxed);
+    }
+
+    /// the rng of the estimate at theta
+    pub fn rng(&self, theta: &[f64]) -> StdRng {
+        let hash = theta.iter().fold(mix(self.seed.load(Ordering::Relaxed)), |h, x| mix(h ^ x.to_bits()));
+        StdRng::seed_from_u64(hash)
+    }
+}
+
+
+impl Default for EstimateSeed {
+    fn default() -> EstimateSeed {
+        EstimateSeed::new()
+    }
+}
+
+
+/// the seed a wrapper passes on to the model it wraps, so that the two do
+/// not draw the same random numbers at the same theta
+pub fn split_seed(seed: u64) -> u64 {
+    mix(seed ^ 0x6A09_E667_F3BC_C909)
+}
+
+
+/// the splitmix64 finalizer
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use crate::linalg::Matrix;
+use super::LogLikelihood;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_level_matches_joint_gaussian() {
+        // x_t = x_{t-1} + w_t, y_t = x_t + v_t, x_1 ~ N(0, p0)
+        let y = vec![0.4, 0.9, 0.7, 1.6];
+        let build = |theta: &[f64]| Some(SsmMatrices{
+            transition: Matrix::identity(1),
+            observation: Matrix::identity(1),
+            process_cov: Matrix::diag(&[theta[0]]),
+            obs_cov: Matrix::diag(&[theta[1]]),
+            init_mean: vec![0.0],
+            init_cov: Matrix::diag(&[2.0]),
+        });
+        let model = StateSpace::new(vec![&y], 2, build).unwrap();
+        let (q, r, p0) = (0.3, 0.2, 2.0);
+
+        // with 0-based steps, cov(y_s, y_t) = p0 + q * min(s, t) + r [s == t]
+        let n = y.len();
+        let mut cov = Matrix::zeros(n, n);
+        for s in 0..n {
+            for t in 0..n {
+                cov[(s, t)] = p0 + q * s.min(t) as f64 + if s == t { r } else { 0.0 };
+            }
+        }
+        let chol = cov.cholesky().unwrap();
+        let expected = -0.5 * (n as f64 * (2.0 * PI).ln() + chol.ln_det() + chol.quad_form(&y));
+        assert!((model.log_lik(&[q, r]) - expected).abs() < 1e-10);
+    }
+}
+
+
+/// system matrices of a linear-Gaussian state-space model
+///
+/// ```text
+/// x_t = F x_{t-1} + w_t,   w_t ~ N(0, Q)
+/// y_t = H x_t + v_t,       v_t ~ N(0, R)
+/// ```
+///
+/// with x_1 ~ N(init_mean, init_cov).
+///
+/// Fields:
+/// transition: F, state_dim x state_dim
+/// observation: H, obs_dim x state_dim
+/// process_cov: Q, state_dim x state_dim
+/// obs_cov: R, obs_dim x obs_dim
+/// init_mean: mean of the first state
+/// init_cov: covariance of the first state
+#[derive(Debug, Clone)]
+pub struct SsmMatrices {
+    pub transition: Matrix,
+    pub observation: Matrix,
+    pub process_cov: Matrix,
+    pub obs_cov: Matrix,
+    pub init_mean: Vec<f64>,
+    pub init_cov: Matrix,
+}
+
+
+impl SsmMatrices {
+    fn check(&self, obs_dim: usize) -> bool {
+        let k = self.init_mean.len();
+        (self.transition.rows(), self.transition.cols()) == (k, k)
+            && (self.observation.rows(), self.observation.cols()) == (obs_dim, k)
+            && (self.process_cov.rows(), self.process_cov.cols()) == (k, k)
+            && (self.obs_cov.rows(), self.obs_cov.cols()) == (obs_dim, obs_dim)
+            && (self.init_cov.rows(), self.init_cov.cols()) == (k, k)
+    }
+}
+
+
+/// linear-Gaussian state-space model with its exact marginal likelihood
+/// computed by the Kalman filter
+///
+/// The user supplies `build`, which maps theta to the system matrices (or
+/// None for parameters outside the model's support). Each data column is
+/// one component of the observation vector, and each row one time step.
+/// NaN observations are treated as missing and skipped in the update.
+///
+/// Fields:
+/// y: the observed columns
+/// dim: the number of parameters
+/// build: theta -> system matrices
+pub struct StateSpace<'a, F>
+where
+    F: Fn(&[f64]) -> Option<SsmMatrices> + Sync,
+{
+    y: Vec<&'a [f64]>,
+    dim: usize,
+    build: F,
+}
+
+
+impl<'a, F> StateSpace<'a, F>
+where
+    F: Fn(&[f64]) -> Option<SsmMatrices> + Sync,
+{
+    pub fn new(
+            y: Vec<&'a [f64]>,
+            dim: usize,
+            build: F,
+    ) -> Result<StateSpace<'a, F>, Box<dyn Error>> {
+        if y.is_empty() {
+            return Err("a state-space model needs at least one observed column".into())
+        }
+        if y.iter().any(|col| col.len() != y[0].len()) {
+            return Err("observed columns differ in length".into())
+        }
+        Ok(StateSpace{ y, dim, build })
+    }
+
+    pub fn n_steps(&self) -> usize {
+        self.y[0].len()
+    }
+
+    /// run the filter, returning the log-likelihood and the filtered state
+    /// means, or None if the matrices are invalid or a covariance stops being
+    /// positive definite
+    pub fn filter(&self, theta: &[f64]) -> Option<(f64, Vec<Vec<f64>>)> {
+        let m = (self.build)(theta)?;
+        let obs_dim = self.y.len();
+        if !m.check(obs_dim) {
+            return None
+        }
+        let k = m.init_mean.len();
+        let f_t = m.transition.transpose();
+        let h_t = m.observation.transpose();
+
+        let mut x = m.init_mean.clone();
+        let mut p = m.init_cov.clone();
+        let mut ll = 0.0;
+        let mut means = Vec::with_capacity(self.n_steps());
+        for t in 0..self.n_steps() {
+            if t > 0 {
+                x = m.transition.mul_vec(&x);
+                p = m.transition.mul(&p).mul(&f_t).add(&m.process_cov).symmetrize();
+            }
+
+            let y_t: Vec<f64> = self.y.iter().map(|col| col[t]).collect();
+            if y_t.iter().any(|v| v.is_nan()) {
+                means.push(x.clone());
+                continue
+            }
+            let hx = m.observation.mul_vec(&x);
+            let innov: Vec<f64> = y_t.iter().zip(&hx).map(|(a, b)| a - b).collect();
+            let ph_t = p.mul(&h_t);
+            let s = m.observation.mul(&ph_t).add(&m.obs_cov).symmetrize();
+            let chol = s.cholesky()?;
+            ll += -0.5 * (obs_dim as f64 * (2.0 * PI).ln() + chol.ln_det() + chol.quad_form(&innov));
+
+            // K = P H' S^{-1}, one row of K at a time since S is symmetric
+            let mut gain = Matrix::zeros(k, obs_dim);
+            for i in 0..k {
+                let row = chol.solve(ph_t.row(i));
+                for j in 0..obs_dim {
+                    gain[(i, j)] = row[j];
+                }
+            }
+            let correction = gain.mul_vec(&innov);
+            for i in 0..k {
+                x[i] += correction[i];
+            }
+            // Joseph form keeps P symmetric positive definite
+            let i_kh = Matrix::identity(k).sub(&gain.mul(&m.observation));
+            p = i_kh.mul(&p).mul(&i_kh.transpose())
+                .add(&gain.mul(&m.obs_cov).mul(&gain.transpose()))
+                .symmetrize();
+            means.push(x.clone());
+        }
+        Some((ll, means))
+    }
+}
+
+
+impl<'a, F> LogLikelihood for StateSpace<'a, F>
+where
+    F: Fn(&[f64]) -> Option<SsmMatrices> + Sync,
+{
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        match self.filter(theta) {
+            Some((ll, _)) if ll.is_finite() => ll,
+            _ => f64::NEG_INFINITY,
+        }
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+
+use rand::seq::index;
+use rand::{Rng, RngCore};
+
+use super::{EstimateSeed, LogLikelihood, PointwiseLogLikelihood};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Dataset;
+    use crate::models::LinearGaussian;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn line_data() -> Dataset {
+        let x: Vec<f64> = (0..200).map(|i| i as f64 / 20.0).collect();
+        let y: Vec<f64> = x.iter().map(|xi| 1.0 + 2.0 * xi + 0.1 * xi.sin()).collect();
+        Dataset::from_columns(vec![x, y], None).unwrap()
+    }
+
+    #[test]
+    fn test_subsampled_is_unbiased() {
+        let data = line_data();
+        let model = LinearGaussian::from_dataset(&data, Some(0.5), &[]).unwrap();
+        let theta = [1.1, 1.9];
+        let exact = model.log_lik(&theta);
+
+        let cv = Subsampled::new(&model, 20, Some(&[1.0, 2.0])).unwrap();
+        let plain = Subsampled::new(&model, 20, None).unwrap();
+        let mut rng = StdRng::seed_from_u64(369);
+        let reps = 4000;
+        let cv_mean: f64 = (0..reps).map(|_| cv.estimate(&theta, &mut rng)).sum::<f64>() / reps as f64;
+        let plain_mean: f64 = (0..reps).map(|_| plain.estimate(&theta, &mut rng)).sum::<f64>() / reps as f64;
+        assert!((cv_mean - exact).abs() < 0.01 * exact.abs());
+        assert!((plain_mean - exact).abs() < 0.05 * exact.abs());
+        assert!(cv.is_approximate());
+        assert!(Subsampled::new(&model, 20, Some(&[1.0])).is_err());
+    }
+}
+
+
+/// minibatch estimate of a pointwise likelihood
+///
+/// Each call draws `batch` observations without replacement and scales
+/// their sum by n / batch, which is unbiased for the full log-likelihood.
+/// When a reference point is given, the difference estimator
+///
+/// ```text
+/// sum_i l_i(ref) + n / batch * sum_{i in batch} (l_i(theta) - l_i(ref))
+/// ```
+///
+/// is used instead. It is still unbiased, and its variance shrinks as
+/// theta approaches the reference, i.e. as the run contracts onto the
+/// posterior bulk.
+///
+/// The estimate is noisy, so the model reports itself as approximate and
+/// the evidence of any run using it must be treated as approximate too.
+/// The minibatch at each theta is drawn from a stream derived from the
+/// run's seed (see `EstimateSeed`).
+///
+/// A reference to a model can be wrapped as well as an owned one.
+///
+/// Fields:
+/// model: the full-data likelihood
+/// batch: minibatch size
+/// reference: control variate as (per-observation values, their sum)
+/// seed: seed of the minibatch draws
+pub struct Subsampled<M: PointwiseLogLikelihood> {
+    model: M,
+    batch: usize,
+    reference: Option<(Vec<f64>, f64)>,
+    seed: EstimateSeed,
+}
+
+
+impl<M: PointwiseLogLikelihood> Subsampled<M> {
+    pub fn new(
+            model: M,
+            batch: usize,
+            reference: Option<&[f64]>,
+    ) -> Result<Subsampled<M>, Box<dyn Error>> {
+        let n = model.n_obs();
+        if batch == 0 || batch > n {
+            return Err(format!(
+                "subsample size must be between 1 and the {} observations, got {}", n, batch
+            ).into())
+        }
+        if let Some(theta_ref) = reference {
+            if theta_ref.len() != model.dim() {
+                return Err(format!(
+                    "the subsample reference has {} entries but the model has {} parameters",
+                    theta_ref.len(), model.dim(),
+                ).into())
+            }
+        }
+        let reference = reference.map(|theta_ref| {
+            let vals: Vec<f64> = (0..n).map(|i| model.log_lik_point(theta_ref, i)).collect();
+            let total = vals.iter().sum();
+            (vals, total)
+        });
+        Ok(Subsampled{ model, batch, reference, seed: EstimateSeed::new() })
+    }
+
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// one log-likelihood estimate using the given rng
+    pub fn estimate<R: Rng + ?Sized>(&self, theta: &[f64], rng: &mut R) -> f64 {
+        let n = self.model.n_obs();
+        let scale = n as f64 / self.batch as f64;
+        let idx = index::sample(rng, n, self.batch);
+        match &self.reference {
+            None => scale * idx.iter()
+                .map(|i| self.model.log_lik_point(theta, i))
+                .sum::<f64>(),
+            Some((vals, total)) => total + scale * idx.iter()
+                .map(|i| self.model.log_lik_point(theta, i) - vals[i])
+                .sum::<f64>(),
+        }
+    }
+}
+
+
+impl<M: PointwiseLogLikelihood> LogLikelihood for Subsampled<M> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        self.estimate(theta, &mut self.seed.rng(theta))
+    }
+
+    fn log_lik_with(&self, theta: &[f64], rng: &mut dyn RngCore) -> f64 {
+        self.estimate(theta, rng)
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        true
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.seed.set(seed)
+    }
+}
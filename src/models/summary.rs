@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use crate::linalg::{Cholesky, Matrix};
+use super::LogLikelihood;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summaries_of_a_trajectory() {
+        // a decaying trajectory y(t) = a exp(-k t), summarized by its mean
+        // and its first and last values
+        let forward = |theta: &[f64]| -> Option<Vec<f64>> {
+            (theta[1] >= 0.0).then(|| (0..20).map(|t| theta[0] * (-theta[1] * t as f64).exp()).collect())
+        };
+        let observed: Vec<f64> = forward(&[2.0, 0.1]).unwrap();
+        let summaries = vec![
+            Summary::new("mean", |y| vec![y.iter().sum::<f64>() / y.len() as f64]),
+            Summary::new("ends", |y| vec![y[0], y[y.len() - 1]]),
+        ];
+        let covariance = Matrix::from_rows(vec![
+            vec![0.04, 0.0, 0.0],
+            vec![0.0, 0.01, 0.005],
+            vec![0.0, 0.005, 0.01],
+        ]);
+        let model = SummaryLikelihood::new(forward, 2, &observed, summaries, &covariance).unwrap();
+        assert_eq!(model.dim(), 2);
+        assert_eq!(model.n_outputs(), 3);
+        assert_eq!(model.names(), vec!["mean", "ends[0]", "ends[1]"]);
+
+        let chol = covariance.cholesky().unwrap();
+        let peak = -0.5 * (3.0 * (2.0 * PI).ln() + chol.ln_det());
+        assert!((model.log_lik(&[2.0, 0.1]) - peak).abs() < 1e-12);
+        assert!(model.log_lik(&[2.2, 0.1]) < peak);
+        assert_eq!(model.log_lik(&[2.0, -1.0]), f64::NEG_INFINITY);
+        assert_eq!(model.outputs(&[2.0, 0.1]), model.observed());
+        assert!(model.outputs(&[2.0, -1.0]).iter().all(|v| v.is_nan()));
+
+        let wrong = Matrix::identity(2);
+        let summaries = vec![Summary::new("mean", |y| vec![y[0]])];
+        assert!(SummaryLikelihood::new(forward, 2, &observed, summaries, &wrong).is_err());
+    }
+}
+
+
+/// maps the output of a forward model to the values of one statistic
+type SummaryFn = dyn Fn(&[f64]) -> Vec<f64> + Send + Sync;
+
+
+/// a named statistic of the output of a forward model, which may have
+/// several values (e.g. the power in a few frequency bands)
+///
+/// Fields:
+/// name: label of the statistic in outputs and reports
+/// f: maps the whole output to the values of the statistic
+pub struct Summary {
+    name: String,
+    f: Box<SummaryFn>,
+}
+
+
+impl Summary {
+    pub fn new<F>(name: &str, f: F) -> Summary
+    where
+        F: Fn(&[f64]) -> Vec<f64> + Send + Sync + 'static,
+    {
+        Summary{ name: name.to_string(), f: Box::new(f) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+
+impl std::fmt::Debug for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Summary").field("name", &self.name).finish()
+    }
+}
+
+
+/// Gaussian likelihood of summary statistics of a functional output
+///
+/// The forward model maps theta to a whole trajectory or spectrum, or None
+/// where it cannot be computed. The registered summaries reduce that
+/// output and the observed one to a short vector s, and the likelihood is
+/// N(s_obs; s(theta), C) with a fixed covariance C, factorized once. The
+/// summaries at theta are the model's `outputs`, so a run keeps them for
+/// every particle for posterior-predictive checks.
+///
+/// Fields:
+/// forward: the forward model
+/// dim: number of parameters the forward model takes
+/// summaries: the registered statistics, concatenated in order
+/// lengths: number of values of each statistic
+/// observed: the summaries of the observed output
+/// chol: Cholesky factor of the summary covariance
+#[derive(Debug)]
+pub struct SummaryLikelihood<F> {
+    forward: F,
+    dim: usize,
+    summaries: Vec<Summary>,
+    lengths: Vec<usize>,
+    observed: Vec<f64>,
+    chol: Cholesky,
+}
+
+
+impl<F> SummaryLikelihood<F>
+where
+    F: Fn(&[f64]) -> Option<Vec<f64>> + Sync,
+{
+    pub fn new(
+            forward: F,
+            dim: usize,
+            observed: &[f64],
+            summaries: Vec<Summary>,
+            covariance: &Matrix,
+    ) -> Result<SummaryLikelihood<F>, Box<dyn Error>> {
+        if summaries.is_empty() {
+            return Err("register at least one summary statistic".into())
+        }
+        let values: Vec<Vec<f64>> = summaries.iter().map(|s| (s.f)(observed)).collect();
+        let lengths = values.iter().map(|v| v.len()).collect();
+        let observed: Vec<f64> = values.concat();
+        if covariance.rows() != observed.len() {
+            return Err(format!(
+                "the summaries have {} values but the covariance is {} x {}",
+                observed.len(), covariance.rows(), covariance.cols(),
+            ).into())
+        }
+        if observed.iter().any(|v| !v.is_finite()) {
+            return Err("the summaries of the observed output are not all finite".into())
+        }
+        let chol = covariance.cholesky().ok_or("the summary covariance is not positive definite")?;
+        Ok(SummaryLikelihood{ forward, dim, summaries, lengths, observed, chol })
+    }
+
+    /// the registered summaries of an output, concatenated
+    pub fn summarize(&self, output: &[f64]) -> Vec<f64> {
+        self.summaries.iter().flat_map(|s| (s.f)(output)).collect()
+    }
+
+    /// the summaries at theta, or None if the forward model fails there
+    pub fn summaries_at(&self, theta: &[f64]) -> Option<Vec<f64>> {
+        let s = self.summarize(&(self.forward)(theta)?);
+        (s.len() == self.observed.len()).then_some(s)
+    }
+
+    pub fn observed(&self) -> &[f64] {
+        &self.observed
+    }
+
+    /// one label per summary value: the statistic's name, followed by the
+    /// index for statistics with several values
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::with_capacity(self.observed.len());
+        for (summary, &n) in self.summaries.iter().zip(&self.lengths) {
+            match n {
+                1 => names.push(summary.name.clone()),
+                n => names.extend((0..n).map(|k| format!("{}[{}]", summary.name, k))),
+            }
+        }
+        names
+    }
+}
+
+
+impl<F> LogLikelihood for SummaryLikelihood<F>
+where
+    F: Fn(&[f64]) -> Option<Vec<f64>> + Sync,
+{
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let s = match self.summaries_at(theta) {
+            Some(s) => s,
+            None => return f64::NEG_INFINITY,
+        };
+        let diff: Vec<f64> = self.observed.iter().zip(&s).map(|(o, s)| o - s).collect();
+        let k = diff.len() as f64;
+        let ll = -0.5 * (k * (2.0 * PI).ln() + self.chol.ln_det() + self.chol.quad_form(&diff));
+        if ll.is_nan() { f64::NEG_INFINITY } else { ll }
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.observed.len()
+    }
+
+    /// the summaries at theta; NaN where the forward model fails
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.summaries_at(theta).unwrap_or_else(|| vec![f64::NAN; self.observed.len()])
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use rand::RngCore;
+use rand::distributions::Distribution;
+use serde::{Deserialize, Serialize};
+
+use crate::dist::Normal;
+use super::{LinearGaussian, LogLikelihood, PointwiseLogLikelihood, Simulate};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linalg::Matrix;
+    use crate::models::Noise;
+
+    #[test]
+    fn test_ar1_matches_conditional_form() {
+        let x = vec![0.0, 1.0, 2.0, 3.0, 4.0];
+        let y = vec![0.3, 1.2, 1.7, 3.4, 4.1];
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Fixed(0.5)).unwrap();
+        let ar1 = ArmaNoise::new(base, NoiseModel::Ar1).unwrap();
+        let (phi, sigma) = (0.6, 0.5);
+        let theta = [0.0, 1.0, phi];
+
+        let r: Vec<f64> = (0..5).map(|i| y[i] - x[i]).collect();
+        let norm = |e: f64, v: f64| -0.5 * (2.0 * PI * v).ln() - e * e / (2.0 * v);
+        let mut expected = norm(r[0], sigma * sigma / (1.0 - phi * phi));
+        for t in 1..5 {
+            expected += norm(r[t] - phi * r[t - 1], sigma * sigma);
+        }
+        assert!((ar1.log_lik(&theta) - expected).abs() < 1e-12);
+
+        // an ARMA(1,1) with a zero MA coefficient is the same AR(1)
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Fixed(0.5)).unwrap();
+        let arma = ArmaNoise::new(base, NoiseModel::Arma11).unwrap();
+        assert!((arma.log_lik(&[0.0, 1.0, phi, 0.0]) - expected).abs() < 1e-12);
+        assert_eq!(arma.log_lik(&[0.0, 1.0, 1.0, 0.0]), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_arma11_matches_dense_covariance() {
+        let x = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let y = vec![0.3, 1.2, 1.7, 3.4, 4.1, 4.6];
+        let base = LinearGaussian::new(vec![&x], &y, Noise::Sampled).unwrap();
+        let arma = ArmaNoise::new(base, NoiseModel::Arma11).unwrap();
+        let (phi, ma, sigma) = (0.6, -0.4, 0.7);
+        let theta = [0.1, 0.9, phi, ma, sigma];
+
+        // stationary ARMA(1,1) autocovariances
+        let s2 = sigma * sigma;
+        let gamma0 = s2 * (1.0 + 2.0 * phi * ma + ma * ma) / (1.0 - phi * phi);
+        let gamma1 = s2 * (1.0 + phi * ma) * (phi + ma) / (1.0 - phi * phi);
+        let n = y.len();
+        let mut cov = Matrix::zeros(n, n);
+        for i in 0..n {
+            for j in 0..n {
+                let lag = i.abs_diff(j);
+                cov[(i, j)] = if lag == 0 { gamma0 } else { gamma1 * phi.powi(lag as i32 - 1) };
+            }
+        }
+        let chol = cov.cholesky().unwrap();
+        let r: Vec<f64> = (0..n).map(|i| y[i] - 0.1 - 0.9 * x[i]).collect();
+        let expected = -0.5 * n as f64 * (2.0 * PI).ln() - 0.5 * chol.ln_det() - 0.5 * chol.quad_form(&r);
+        assert!((arma.log_lik(&theta) - expected).abs() < 1e-10);
+    }
+}
+
+
+/// correlation structure of the regression residuals
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseModel {
+    /// independent residuals
+    #[default]
+    White,
+    /// r_t = phi r_{t-1} + e_t
+    Ar1,
+    /// r_t = phi r_{t-1} + e_t + theta_ma e_{t-1}
+    Arma11,
+}
+
+
+/// linear regression whose residuals follow a stationary AR(1) or ARMA(1,1)
+/// process, with the rows of the data taken as equally spaced in time
+///
+/// The likelihood is the exact Gaussian one, computed with the innovations
+/// algorithm (Brockwell & Davis, Example 5.2.6): the one-step predictions
+/// and their variances are updated recursively, so the cost is linear in
+/// the number of observations and no covariance matrix is ever formed.
+///
+/// theta holds the regression coefficients, then phi, then the MA
+/// coefficient for ARMA(1,1), then the innovation sd if it is sampled.
+/// Non-stationary (|phi| >= 1) or non-invertible (|theta_ma| >= 1) values
+/// get zero likelihood.
+///
+/// Fields:
+/// base: the regression supplying the mean and the innovation sd
+/// model: AR(1) or ARMA(1,1)
+#[derive(Debug)]
+pub struct ArmaNoise<'a> {
+    base: LinearGaussian<'a>,
+    model: NoiseModel,
+}
+
+
+impl<'a> ArmaNoise<'a> {
+    pub fn new(
+            base: LinearGaussian<'a>,
+            model: NoiseModel,
+    ) -> Result<ArmaNoise<'a>, Box<dyn Error>> {
+        if model == NoiseModel::White {
+            return Err("ArmaNoise needs an AR(1) or ARMA(1,1) noise model".into())
+        }
+        if base.is_censored_or_truncated() {
+            return Err("correlated noise cannot be combined with censored or truncated data".into())
+        }
+        Ok(ArmaNoise{ base, model })
+    }
+
+    /// indices of phi and the MA coefficient within theta
+    fn noise_params(&self) -> (usize, Option<usize>) {
+        let n_coef = self.base.n_coef();
+        match self.model {
+            NoiseModel::Arma11 => (n_coef, Some(n_coef + 1)),
+            _ => (n_coef, None),
+        }
+    }
+}
+
+
+impl<'a> LogLikelihood for ArmaNoise<'a> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let (i_phi, i_ma) = self.noise_params();
+        let phi = theta[i_phi];
+        let ma = i_ma.map_or(0.0, |i| theta[i]);
+        let sigma = self.base.sigma(theta);
+        let admissible = phi.abs() < 1.0 && ma.abs() < 1.0 && sigma > 0.0;
+        if !admissible {
+            return f64::NEG_INFINITY
+        }
+        let s2 = sigma * sigma;
+
+        // r_n is the prediction variance of step n + 1 in units of sigma^2
+        let mut r_n = (1.0 + 2.0 * phi * ma + ma * ma) / (1.0 - phi * phi);
+        let mut pred = 0.0;
+        let mut ll = 0.0;
+        for t in 0..self.base.n_obs() {
+            let resid = self.base.residual(theta, t);
+            let innov = resid - pred;
+            let v = s2 * r_n;
+            ll += -0.5 * (2.0 * PI * v).ln() - innov * innov / (2.0 * v);
+
+            let theta_n = ma / r_n;
+            pred = phi * resid + theta_n * innov;
+            r_n = 1.0 + ma * ma - ma * theta_n;
+        }
+        ll
+    }
+
+    fn dim(&self) -> usize {
+        let extra = match self.model {
+            NoiseModel::Arma11 => 2,
+            _ => 1,
+        };
+        self.base.dim() + extra
+    }
+}
+
+
+impl<'a> Simulate for ArmaNoise<'a> {
+    fn simulate(&self, theta: &[f64], rng: &mut dyn RngCore) -> Result<Vec<f64>, Box<dyn Error>> {
+        let (i_phi, i_ma) = self.noise_params();
+        let phi = theta[i_phi];
+        let ma = i_ma.map_or(0.0, |i| theta[i]);
+        if phi.abs() >= 1.0 || ma.abs() >= 1.0 {
+            return Err("simulation needs a stationary, invertible noise process".into())
+        }
+        let innovations = Normal::new(0.0, self.base.sigma(theta))?;
+        // start in the stationary distribution: r_0 = e_0 + w with w
+        // independent of e_0 and var w = sigma^2 (phi + ma)^2 / (1 - phi^2)
+        let mut e = innovations.sample(rng);
+        let mut r = e + (phi + ma) / (1.0 - phi * phi).sqrt() * innovations.sample(rng);
+        let mut y = Vec::with_capacity(self.base.n_obs());
+        for t in 0..self.base.n_obs() {
+            if t > 0 {
+                let e_next = innovations.sample(rng);
+                r = phi * r + e_next + ma * e;
+                e = e_next;
+            }
+            y.push(self.base.mean(theta, t) + r);
+        }
+        Ok(y)
+    }
+}
@@ -0,0 +1,406 @@
+use std::error::Error;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Condvar, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use crate::Config;
+use super::{construct, LogLikelihood};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use rand::thread_rng;
+    use crate::observer::Collect;
+
+    struct Constant;
+
+    impl LogLikelihood for Constant {
+        fn log_lik(&self, _theta: &[f64]) -> f64 {
+            0.0
+        }
+
+        fn dim(&self) -> usize {
+            0
+        }
+    }
+
+    fn no_parameters<'a>(_config: &Config, _data: &'a Dataset) -> Result<Box<dyn LogLikelihood + 'a>, Box<dyn Error>> {
+        Ok(Box::new(Constant))
+    }
+
+    #[test]
+    fn test_a_worker_answers_like_the_model() {
+        let x: Vec<f64> = (0..10).map(|i| i as f64).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 0.5 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], Some(vec!["x".to_string(), "y".to_string()])).unwrap();
+        let config = Config{ mu: vec![0.0, 0.0], sd: vec![1.0, 1.0], noise_sd: Some(0.3), ..Default::default() };
+        let model = construct(config.model_name(), &config, &data).unwrap();
+
+        let thetas = [[1.0, 0.5], [0.0, 2.0]];
+        let mut input = Vec::new();
+        write_job(&mut input, &config, &data).unwrap();
+        for theta in &thetas {
+            write_f64s(&mut input, theta).unwrap();
+        }
+        let mut output = Vec::new();
+        serve_worker(&mut Cursor::new(input), &mut output).unwrap();
+        let mut reply = Cursor::new(output);
+        assert_eq!(read_ready(&mut reply).unwrap(), (2, false));
+        for theta in &thetas {
+            assert_eq!(read_f64(&mut reply).unwrap(), model.log_lik(theta));
+        }
+        assert!(read_f64(&mut reply).is_err());
+
+        // a worker that cannot build the model says why instead of serving
+        let bad = Config{ model: Some("no_such_model".to_string()), ..config };
+        let mut input = Vec::new();
+        write_job(&mut input, &bad, &data).unwrap();
+        let mut output = Vec::new();
+        serve_worker(&mut Cursor::new(input), &mut output).unwrap();
+        let e = read_ready(&mut Cursor::new(output)).unwrap_err();
+        assert!(e.to_string().contains("no_such_model"), "{}", e);
+
+        // nor can it serve a model without parameters
+        crate::models::register_model("test_no_parameters", no_parameters).unwrap();
+        let empty = Config{ model: Some("test_no_parameters".to_string()), ..bad };
+        let mut input = Vec::new();
+        write_job(&mut input, &empty, &data).unwrap();
+        let mut output = Vec::new();
+        serve_worker(&mut Cursor::new(input), &mut output).unwrap();
+        let e = read_ready(&mut Cursor::new(output)).unwrap_err();
+        assert!(e.to_string().contains("without parameters"), "{}", e);
+    }
+
+    #[test]
+    fn test_a_lost_worker_stops_the_run() {
+        // a worker that exits before answering
+        let worker = Worker::start(Path::new("true"), &[]).unwrap();
+        let idle = Idle{ workers: vec![worker], failure: None };
+        let pool = WorkerPool{ idle: Mutex::new(idle), returned: Condvar::new(), dim: 1, approximate: false };
+        assert_eq!(pool.log_lik(&[0.5]), f64::NEG_INFINITY);
+        assert!(pool.failure().is_some());
+        // it is not handed to the next caller, who does not wait for it either
+        assert!(pool.idle.lock().unwrap().workers.is_empty());
+        assert_eq!(pool.log_lik(&[0.5]), f64::NEG_INFINITY);
+
+        let config = Config{ mu: vec![0.0], sd: vec![1.0], particle_num: 10, sample_num: 50, ..Default::default() };
+        let e = crate::run_with_model(&config, &pool, &mut Collect::default(), &mut thread_rng()).unwrap_err();
+        assert!(e.to_string().contains("likelihood worker"), "{}", e);
+    }
+}
+
+
+/// settings of likelihood worker processes
+///
+/// Fields:
+/// processes: worker processes, each evaluating one likelihood at a time
+/// command: program started for every worker; the running executable if
+///     absent, which must then be `ns` or a program that calls
+///     `serve_worker` when given `args`
+/// args: arguments of the command
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WorkersConfig {
+    pub processes: usize,
+    pub command: Option<PathBuf>,
+    pub args: Vec<String>,
+}
+
+
+impl Default for WorkersConfig {
+    fn default() -> WorkersConfig {
+        WorkersConfig{
+            processes: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            command: None,
+            args: vec!["worker".to_string()],
+        }
+    }
+}
+
+
+fn write_u64(out: &mut dyn Write, v: u64) -> Result<(), Box<dyn Error>> {
+    Ok(out.write_all(&v.to_le_bytes())?)
+}
+
+
+fn read_u64(input: &mut dyn Read) -> Result<u64, Box<dyn Error>> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+
+fn write_bytes(out: &mut dyn Write, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    write_u64(out, bytes.len() as u64)?;
+    Ok(out.write_all(bytes)?)
+}
+
+
+fn read_string(input: &mut dyn Read) -> Result<String, Box<dyn Error>> {
+    let mut bytes = vec![0; read_u64(input)? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+
+fn write_f64s(out: &mut dyn Write, values: &[f64]) -> Result<(), Box<dyn Error>> {
+    for v in values {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+
+fn read_f64(input: &mut dyn Read) -> Result<f64, Box<dyn Error>> {
+    Ok(f64::from_bits(read_u64(input)?))
+}
+
+
+/// what a worker is sent before its first evaluation: the config, then
+/// the data column by column, each as its name and values
+fn write_job(out: &mut dyn Write, config: &Config, data: &Dataset) -> Result<(), Box<dyn Error>> {
+    // the worker builds the model itself and must not start workers of its own
+    let config = Config{ workers: None, ..config.clone() };
+    write_bytes(out, config.to_toml()?.as_bytes())?;
+    write_u64(out, data.ncols() as u64)?;
+    write_u64(out, data.nrows() as u64)?;
+    for (j, name) in data.names().iter().enumerate() {
+        write_bytes(out, name.as_bytes())?;
+        write_f64s(out, data.column(j))?;
+    }
+    Ok(())
+}
+
+
+fn read_job(input: &mut dyn Read) -> Result<(Config, Dataset), Box<dyn Error>> {
+    let config: Config = toml::from_str(&read_string(input)?)?;
+    let (ncols, nrows) = (read_u64(input)? as usize, read_u64(input)? as usize);
+    let mut names = Vec::with_capacity(ncols);
+    let mut columns = Vec::with_capacity(ncols);
+    for _ in 0..ncols {
+        names.push(read_string(input)?);
+        columns.push((0..nrows).map(|_| read_f64(input)).collect::<Result<Vec<f64>, _>>()?);
+    }
+    Ok((config, Dataset::from_columns(columns, Some(names))?))
+}
+
+
+/// the worker's answer to its job: 0, the model's dimension and whether
+/// it is approximate, or 1 and why the model could not be built
+fn read_ready(input: &mut dyn Read) -> Result<(usize, bool), Box<dyn Error>> {
+    match read_u64(input)? {
+        0 => Ok((read_u64(input)? as usize, read_u64(input)? == 1)),
+        _ => Err(read_string(input)?.into()),
+    }
+}
+
+
+/// the loop of a worker process: read a job from `input`, build its
+/// model, then answer every theta that follows with its log-likelihood
+/// until `input` closes
+///
+/// `ns worker` runs this on stdin and stdout, so the likelihood code must
+/// not print to stdout; stderr is passed through. A program embedding the
+/// library that starts its own workers calls this when given the
+/// `workers.args` of its config.
+pub fn serve_worker(input: &mut dyn Read, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let (config, data) = read_job(input)?;
+    // a model without parameters would be answered without reading
+    // anything, so the worker would never see its input close
+    let built = construct(config.model_name(), &config, &data).and_then(|model| match model.dim() {
+        0 => Err("a model without parameters cannot be evaluated by workers".into()),
+        _ => Ok(model),
+    });
+    let model = match built {
+        Ok(model) => model,
+        Err(e) => {
+            write_u64(output, 1)?;
+            write_bytes(output, e.to_string().as_bytes())?;
+            output.flush()?;
+            return Ok(())
+        },
+    };
+    write_u64(output, 0)?;
+    write_u64(output, model.dim() as u64)?;
+    write_u64(output, model.is_approximate() as u64)?;
+    output.flush()?;
+
+    let mut theta = vec![0.0; model.dim()];
+    loop {
+        for (i, t) in theta.iter_mut().enumerate() {
+            match read_f64(input) {
+                Ok(v) => *t = v,
+                // the manager is done
+                Err(e) if i == 0 && e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        write_f64s(output, &[model.log_lik(&theta)])?;
+        output.flush()?;
+    }
+}
+
+
+/// one worker process and its pipes
+struct Worker {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+
+impl Worker {
+    /// start `command` with `args` on pipes
+    fn start(command: &Path, args: &[String]) -> Result<Worker, Box<dyn Error>> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot start worker {}: {}", command.display(), e))?;
+        let stdin = BufWriter::new(child.stdin.take().ok_or("the worker has no stdin")?);
+        let stdout = BufReader::new(child.stdout.take().ok_or("the worker has no stdout")?);
+        Ok(Worker{ child, stdin, stdout })
+    }
+
+    fn log_lik(&mut self, theta: &[f64]) -> Result<f64, Box<dyn Error>> {
+        write_f64s(&mut self.stdin, theta)?;
+        self.stdin.flush()?;
+        read_f64(&mut self.stdout)
+    }
+}
+
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // a worker keeps no state worth waiting for
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+
+/// a likelihood evaluated by worker processes, for likelihood code that
+/// is not thread-safe
+///
+/// Every worker builds the model from the config and data it is sent and
+/// evaluates one theta at a time. The sampler keeps the live set in this
+/// process; each evaluation takes an idle worker, waiting for one if all
+/// are busy, so the threads of `sampler.parallel`, smc and tempering keep
+/// every worker busy while the likelihood code itself never shares a
+/// process. A worker whose pipes fail is dropped, and the pool answers
+/// -inf from then on and reports why through `failure`, which stops the
+/// run.
+///
+/// Fields:
+/// idle: workers not evaluating anything, and why one was lost
+/// returned: signalled when a worker becomes idle or is lost
+/// dim: parameters of the model
+/// approximate: whether the workers' model is
+pub struct WorkerPool {
+    idle: Mutex<Idle>,
+    returned: Condvar,
+    dim: usize,
+    approximate: bool,
+}
+
+
+/// the idle workers of a pool
+///
+/// Fields:
+/// workers: workers not evaluating anything
+/// failure: why a worker was lost, once one was
+struct Idle {
+    workers: Vec<Worker>,
+    failure: Option<String>,
+}
+
+
+impl WorkerPool {
+    /// start `settings.processes` workers on the model `config` describes
+    /// of `data`
+    pub fn spawn(config: &Config, data: &Dataset, settings: &WorkersConfig) -> Result<WorkerPool, Box<dyn Error>> {
+        if settings.processes == 0 {
+            return Err("a worker pool needs at least one process".into())
+        }
+        let mut job = Vec::new();
+        write_job(&mut job, config, data)?;
+        let command = match &settings.command {
+            Some(command) => command.clone(),
+            None => std::env::current_exe()?,
+        };
+        let mut workers = Vec::with_capacity(settings.processes);
+        let mut shape = None;
+        for _ in 0..settings.processes {
+            let mut worker = Worker::start(&command, &settings.args)?;
+            worker.stdin.write_all(&job)?;
+            worker.stdin.flush()?;
+            let ready = read_ready(&mut worker.stdout).map_err(|e| format!("worker {}: {}", command.display(), e))?;
+            shape.get_or_insert(ready);
+            workers.push(worker);
+        }
+        let (dim, approximate) = shape.expect("at least one worker");
+        Ok(WorkerPool{ idle: Mutex::new(Idle{ workers, failure: None }), returned: Condvar::new(), dim, approximate })
+    }
+}
+
+
+impl LogLikelihood for WorkerPool {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        if theta.len() != self.dim {
+            return f64::NEG_INFINITY
+        }
+        let mut idle = self.idle.lock().expect("the worker pool is poisoned");
+        let mut worker = loop {
+            if idle.failure.is_some() {
+                return f64::NEG_INFINITY
+            }
+            match idle.workers.pop() {
+                Some(worker) => break worker,
+                None => idle = self.returned.wait(idle).expect("the worker pool is poisoned"),
+            }
+        };
+        drop(idle);
+        let log_l = worker.log_lik(theta);
+        let mut idle = self.idle.lock().expect("the worker pool is poisoned");
+        match log_l {
+            Ok(ll) => {
+                idle.workers.push(worker);
+                self.returned.notify_one();
+                match ll.is_nan() {
+                    true => f64::NEG_INFINITY,
+                    false => ll,
+                }
+            },
+            // a run on a lost worker's guess is no run: the worker is
+            // dropped, which kills it, and everyone waiting learns of it
+            Err(e) => {
+                idle.failure.get_or_insert_with(|| format!(
+                    "likelihood worker {} failed at theta = {:?}: {}", worker.child.id(), theta, e,
+                ));
+                drop(idle);
+                drop(worker);
+                self.returned.notify_all();
+                f64::NEG_INFINITY
+            },
+        }
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.approximate
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.idle.lock().expect("the worker pool is poisoned").failure.clone()
+    }
+}
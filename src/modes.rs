@@ -0,0 +1,218 @@
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+use crate::evidence::log_add_exp;
+use crate::linalg::Matrix;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand::distributions::Distribution;
+    use crate::dist::Normal;
+
+    #[test]
+    fn test_two_separated_modes() {
+        let mut rng = StdRng::seed_from_u64(379);
+        let noise = Normal::new(0.0, 0.3).unwrap();
+        let mut posterior = Vec::new();
+        for k in 0..1000 {
+            let center = if k < 700 { [-3.0, 0.0] } else { [3.0, 1.0] };
+            let theta = vec![center[0] + noise.sample(&mut rng), center[1] + noise.sample(&mut rng)];
+            posterior.push((theta, -(1000f64.ln())));
+        }
+        let (modes, labels) = find_modes(&posterior, -2.0, &ModeConfig::default());
+        assert_eq!(modes.len(), 2);
+        assert!((modes[0].mass - 0.7).abs() < 1e-9);
+        assert!((modes[1].mass - 0.3).abs() < 1e-9);
+        assert!((modes[0].log_z - (-2.0 + 0.7f64.ln())).abs() < 1e-9);
+        assert!((modes[0].mean[0] + 3.0).abs() < 0.1);
+        assert!((modes[1].mean[1] - 1.0).abs() < 0.1);
+        assert!((modes[1].cov[(0, 0)] - 0.09).abs() < 0.03);
+        assert!(labels[..700].iter().all(|&l| l == 0));
+        assert!(labels[700..].iter().all(|&l| l == 1));
+    }
+}
+
+
+/// settings of the posterior mode clustering
+///
+/// Fields:
+/// n_seed: the clusters are built from this many points resampled from the
+///     posterior
+/// neighbours: every seed is linked to this many of its nearest seeds
+/// min_size: clusters with fewer seeds are merged into the nearest larger one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModeConfig {
+    pub n_seed: usize,
+    pub neighbours: usize,
+    pub min_size: usize,
+}
+
+
+impl Default for ModeConfig {
+    fn default() -> ModeConfig {
+        ModeConfig{ n_seed: 500, neighbours: 10, min_size: 10 }
+    }
+}
+
+
+/// one separated mode of the posterior
+///
+/// Fields:
+/// mean: posterior mean within the mode
+/// cov: posterior covariance within the mode
+/// log_z: local log evidence, the part of log Z contributed by the mode
+/// mass: fraction of the posterior mass in the mode
+/// n_points: number of posterior points labelled with the mode
+#[derive(Debug, Clone)]
+pub struct Mode {
+    pub mean: Vec<f64>,
+    pub cov: Matrix,
+    pub log_z: f64,
+    pub mass: f64,
+    pub n_points: usize,
+}
+
+
+/// representative of x's set in a union-find forest
+fn find(parent: &mut [usize], mut x: usize) -> usize {
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+
+fn sq_dist(a: &[f64], b: &[f64], scale: &[f64]) -> f64 {
+    a.iter().zip(b).zip(scale).map(|((x, y), s)| ((x - y) / s).powi(2)).sum()
+}
+
+
+/// split the posterior into separated modes
+///
+/// Seeds are drawn from the posterior by systematic resampling, standardized
+/// per dimension and linked to their nearest neighbours. The connected
+/// components of that graph are the modes: a mode can have any shape, and
+/// sparse tails stay attached because every seed links to the same number
+/// of neighbours whatever the local density. Components too small to be a
+/// mode join their nearest larger one. Every posterior point is then labelled
+/// with the mode of its nearest seed, and the modes are summarized from
+/// the posterior weights of their points. `posterior` holds
+/// (theta, log normalized weight) pairs as in `RunResult`. Returns the
+/// modes, largest mass first, and one label per posterior point.
+pub fn find_modes(
+        posterior: &[(Vec<f64>, f64)],
+        log_z: f64,
+        config: &ModeConfig,
+) -> (Vec<Mode>, Vec<usize>) {
+    if posterior.is_empty() {
+        return (Vec::new(), Vec::new())
+    }
+    let d = posterior[0].0.len();
+    // evenly spaced pointers through the cumulative posterior mass, so the
+    // seeds follow the posterior and every mode gets its share
+    let n_seed = config.n_seed.max(1);
+    let mut order = Vec::with_capacity(n_seed);
+    let mut cum = 0.0;
+    for (i, (_, lw)) in posterior.iter().enumerate() {
+        cum += lw.exp();
+        while order.len() < n_seed && (order.len() as f64 + 0.5) / n_seed as f64 <= cum {
+            order.push(i);
+        }
+    }
+    order.dedup();
+    if order.is_empty() {
+        order.push(posterior.len() - 1);
+    }
+    let seeds: Vec<&[f64]> = order.iter().map(|&i| posterior[i].0.as_slice()).collect();
+    let n = seeds.len();
+
+    let scale: Vec<f64> = (0..d)
+        .map(|j| {
+            let m = seeds.iter().map(|s| s[j]).sum::<f64>() / n as f64;
+            let v = seeds.iter().map(|s| (s[j] - m).powi(2)).sum::<f64>() / n as f64;
+            v.sqrt().max(1e-12)
+        })
+        .collect();
+    let dist: Vec<Vec<f64>> = seeds.iter()
+        .map(|a| seeds.iter().map(|b| sq_dist(a, b, &scale)).collect())
+        .collect();
+
+    let mut parent: Vec<usize> = (0..n).collect();
+    for (i, row) in dist.iter().enumerate() {
+        let mut by_dist: Vec<usize> = (0..n).filter(|&j| j != i).collect();
+        by_dist.sort_by_key(|&j| OrderedFloat(row[j]));
+        for &j in by_dist.iter().take(config.neighbours) {
+            let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+    let roots: Vec<usize> = (0..n).map(|i| find(&mut parent, i)).collect();
+    let size = |r: usize| roots.iter().filter(|&&q| q == r).count();
+    let big: Vec<bool> = roots.iter().map(|&r| size(r) >= config.min_size).collect();
+    // seeds of small clusters take the cluster of their nearest big seed
+    let cluster: Vec<usize> = if big.iter().any(|b| *b) {
+        (0..n)
+            .map(|i| {
+                if big[i] {
+                    return roots[i]
+                }
+                let j = (0..n).filter(|&j| big[j]).min_by_key(|&j| OrderedFloat(dist[i][j])).unwrap();
+                roots[j]
+            })
+            .collect()
+    } else {
+        vec![0; n]
+    };
+
+    let mut ids: Vec<usize> = cluster.clone();
+    ids.sort_unstable();
+    ids.dedup();
+    let labels: Vec<usize> = posterior.iter()
+        .map(|(theta, _)| {
+            let s = (0..n).min_by_key(|&s| OrderedFloat(sq_dist(theta, seeds[s], &scale))).unwrap();
+            ids.binary_search(&cluster[s]).unwrap()
+        })
+        .collect();
+
+    let mut modes: Vec<Mode> = (0..ids.len())
+        .map(|k| {
+            let members: Vec<usize> = (0..posterior.len()).filter(|&i| labels[i] == k).collect();
+            let log_mass = members.iter()
+                .fold(f64::NEG_INFINITY, |acc, &i| log_add_exp(acc, posterior[i].1));
+            let w: Vec<f64> = members.iter().map(|&i| (posterior[i].1 - log_mass).exp()).collect();
+            let mut mean = vec![0.0; d];
+            for (&i, wi) in members.iter().zip(&w) {
+                for (m, t) in mean.iter_mut().zip(&posterior[i].0) {
+                    *m += wi * t;
+                }
+            }
+            let mut cov = Matrix::zeros(d, d);
+            for (&i, wi) in members.iter().zip(&w) {
+                let theta = &posterior[i].0;
+                for a in 0..d {
+                    for b in 0..d {
+                        cov[(a, b)] += wi * (theta[a] - mean[a]) * (theta[b] - mean[b]);
+                    }
+                }
+            }
+            Mode{ mean, cov, log_z: log_z + log_mass, mass: log_mass.exp(), n_points: members.len() }
+        })
+        .collect();
+
+    // relabel so that mode 0 has the most mass
+    let mut rank: Vec<usize> = (0..modes.len()).collect();
+    rank.sort_by_key(|&k| std::cmp::Reverse(OrderedFloat(modes[k].mass)));
+    let mut new_label = vec![0; modes.len()];
+    for (new, &old) in rank.iter().enumerate() {
+        new_label[old] = new;
+    }
+    let labels = labels.into_iter().map(|l| new_label[l]).collect();
+    modes = rank.iter().map(|&k| modes[k].clone()).collect();
+    (modes, labels)
+}
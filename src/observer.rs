@@ -0,0 +1,47 @@
+use crate::Particles;
+
+
+/// callbacks from a running sampler
+///
+/// Every method has an empty default, so an observer only implements the
+/// events it cares about.
+pub trait Observer {
+    /// the run noticed something suspicious but carries on
+    fn warn(&mut self, _message: &str) {}
+
+    /// call `on_iteration` after every this many iterations; 0 never does
+    fn every(&self) -> usize {
+        0
+    }
+
+    /// borrowed view of the live and dead particles after `iteration`, for
+    /// custom in-run analysis; only static runs keep a `Particles` set
+    fn on_iteration(&mut self, _iteration: usize, _particles: &Particles) {}
+}
+
+
+/// observer that prints warnings to stderr; what `run` uses
+#[derive(Debug, Default)]
+pub struct Stderr;
+
+
+impl Observer for Stderr {
+    fn warn(&mut self, message: &str) {
+        eprintln!("warning: {}", message);
+    }
+}
+
+
+/// observer that keeps every warning, e.g. for tests or for reporting
+/// them alongside the results
+#[derive(Debug, Default)]
+pub struct Collect {
+    pub warnings: Vec<String>,
+}
+
+
+impl Observer for Collect {
+    fn warn(&mut self, message: &str) {
+        self.warnings.push(message.to_string());
+    }
+}
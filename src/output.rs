@@ -0,0 +1,426 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::dynamic::{self, DeadPoint};
+use crate::evidence::log_add_exp;
+use crate::sampler::{Origin, Provenance};
+use crate::stats::{ess, stratified_resample, systematic_resample, weighted_quantile};
+use crate::units::{self, ParameterSpec};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_birth_rows() {
+        let path = std::env::temp_dir().join(format!("ns_output_{}_dead-birth.txt", std::process::id()));
+        let posterior = vec![(vec![0.5, -1.0], -2.0), (vec![0.25, 2.0], -0.5)];
+        let dead_birth = vec![(-3.0, f64::NEG_INFINITY), (-1.5, -3.0)];
+        write_dead_birth(&path, &posterior, &dead_birth).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<f64>> = text.lines()
+            .map(|l| l.split_whitespace().map(|v| v.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(rows, vec![vec![0.5, -1.0, -3.0, f64::NEG_INFINITY], vec![0.25, 2.0, -1.5, -3.0]]);
+
+        std::fs::write(&path, text).unwrap();
+        let points = read_dead_birth(&path, 2).unwrap();
+        let wrong_dim = read_dead_birth(&path, 3);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(points[1].theta, vec![0.25, 2.0]);
+        assert_eq!((points[1].log_l, points[1].log_l_birth), (-1.5, -3.0));
+        assert!(wrong_dim.is_err());
+    }
+
+    #[test]
+    fn test_thinned_export() {
+        use rand::SeedableRng;
+        let path = std::env::temp_dir().join(format!("ns_output_{}_thinned.csv", std::process::id()));
+        // one point carries almost all the weight
+        let posterior: Posterior = (0..1000)
+            .map(|i| (vec![i as f64], if i == 7 { 0.0 } else { -20.0 }))
+            .collect();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(408);
+        for scheme in [Resampling::Systematic, Resampling::Stratified] {
+            let export = ExportConfig{ file: path.clone(), size: Some(50), scheme };
+            assert_eq!(write_equal_weights(&export, &posterior, &[], &mut rng).unwrap(), 50);
+            let text = std::fs::read_to_string(&path).unwrap();
+            let mut lines = text.lines();
+            assert_eq!(lines.next(), Some("theta0"));
+            assert!(lines.all(|l| l == "7"));
+        }
+        let export = ExportConfig{ file: path.clone(), size: None, scheme: Resampling::Systematic };
+        assert_eq!(write_equal_weights(&export, &posterior, &[], &mut rng).unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_getdist_summaries() {
+        // a dotted root, whose part after the dot must survive
+        let root = std::env::temp_dir().join(format!("ns_output_{}_getdist_1.5", std::process::id()));
+        // theta0 = 0..99 in equal weight, theta1 constant; log L peaks at 50
+        let posterior: Posterior = (0..100).map(|i| (vec![i as f64, 2.0], -(100f64).ln())).collect();
+        let dead_birth: Vec<(f64, f64)> = (0..100).map(|i| (-((i as f64 - 50.0) / 10.0).powi(2), f64::NEG_INFINITY)).collect();
+        let settings = GetdistConfig{ root: root.clone(), limits: vec![0.675, 0.955] };
+        write_getdist(&settings, &posterior, &dead_birth, &[]).unwrap();
+
+        let file = |suffix: &str| PathBuf::from(format!("{}.{}", root.display(), suffix));
+        assert!(!root.with_extension("margestats").exists());
+        let marge = std::fs::read_to_string(file("margestats")).unwrap();
+        let like = std::fs::read_to_string(file("likestats")).unwrap();
+        std::fs::remove_file(file("margestats")).unwrap();
+        std::fs::remove_file(file("likestats")).unwrap();
+        let lines: Vec<&str> = marge.lines().collect();
+        assert_eq!(lines[0], "Marginalized limits: 0.675; 0.955");
+        assert_eq!(lines[1], "");
+        let header: Vec<&str> = lines[2].split_whitespace().collect();
+        assert_eq!(header, ["parameter", "mean", "sddev", "lower1", "upper1", "limit1", "lower2", "upper2", "limit2"]);
+        let row: Vec<&str> = lines[3].split_whitespace().collect();
+        assert_eq!(row[..3], ["theta0", "4.9500000E+01", "2.8866070E+01"]);
+        assert_eq!(row[3..], ["1.6000000E+01", "8.3000000E+01", "two", "2.0000000E+00", "9.7000000E+01", "two", "\\theta_{0}"]);
+
+        let lines: Vec<&str> = like.lines().collect();
+        assert_eq!(lines[0], "Best fit sample -log(Like) = 0.000000E+00");
+        let row: Vec<&str> = lines.iter().find(|l| l.starts_with("theta0")).unwrap().split_whitespace().collect();
+        // 67.5% of the mass takes the 68 points of highest likelihood,
+        // theta0 in 17..=83 and the first of the tie at 16 and 84
+        assert_eq!(row[..4], ["theta0", "5.0000000E+01", "1.6000000E+01", "8.3000000E+01"]);
+        assert!(write_getdist(&settings, &posterior, &dead_birth[1..], &[]).is_err());
+    }
+}
+
+
+/// posterior points as (theta, log normalized weight) pairs
+pub type Posterior = Vec<(Vec<f64>, f64)>;
+
+
+/// write the dead points in the dead-birth format of anesthetic (and
+/// PolyChord): one row per point holding the parameters, log L and the
+/// log L contour the point was born above, separated by spaces. Points
+/// drawn from the whole prior are born at -inf.
+pub fn write_dead_birth(
+        path: &Path,
+        posterior: &[(Vec<f64>, f64)],
+        dead_birth: &[(f64, f64)],
+) -> Result<(), Box<dyn Error>> {
+    if posterior.len() != dead_birth.len() {
+        return Err("posterior and dead-birth records differ in length".into())
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    for ((theta, _), (log_l, birth)) in posterior.iter().zip(dead_birth) {
+        let mut row: Vec<String> = theta.iter().map(|t| format!("{:e}", t)).collect();
+        row.push(format!("{:e}", log_l));
+        row.push(format!("{:e}", birth));
+        writeln!(out, "{}", row.join(" "))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+/// write one row per particle: its number, the iteration it died at
+/// (empty if still live), log L, the contour it was born above, and how it
+/// was drawn, with the number of the particle its chain started from
+pub fn write_provenance(
+        path: &Path,
+        rows: &[(usize, Option<usize>, f64, f64, Provenance)],
+) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "particle,died,log_l,birth,origin,likelihood_calls,seed,accepted_steps")?;
+    let optional = |v: Option<usize>| v.map_or(String::new(), |v| v.to_string());
+    for (particle, died, log_l, birth, p) in rows {
+        let origin = match p.origin {
+            Origin::Initial => "initial",
+            Origin::Rejection => "rejection",
+            Origin::RandomWalk => "random_walk",
+            Origin::HitAndRun => "hit_and_run",
+            Origin::Region => "region",
+        };
+        writeln!(
+            out, "{},{},{:e},{:e},{},{},{},{}",
+            particle, optional(*died), log_l, birth, origin, p.likelihood_calls, optional(p.seed), p.accepted_steps,
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+/// how an equally weighted sample is drawn from weighted points
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Resampling {
+    /// one uniform offset and evenly spaced pointers
+    #[default]
+    Systematic,
+    /// one uniform pointer per stratum
+    Stratified,
+}
+
+
+/// settings of the equally weighted posterior sample
+///
+/// Fields:
+/// file: CSV file the sample is written to, one row per draw
+/// size: number of draws; the posterior ESS if absent
+/// scheme: "systematic" or "stratified" resampling
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportConfig {
+    pub file: PathBuf,
+    pub size: Option<usize>,
+    #[serde(default)]
+    pub scheme: Resampling,
+}
+
+
+/// resample the posterior into draws of equal weight, in random order,
+/// and write their parameters as CSV, named and scaled as `specs` shows
+/// them; returns the number of draws. Points of high weight repeat, so a
+/// few thousand rows stand in for any number of weighted ones
+pub fn write_equal_weights<R: Rng + ?Sized>(
+        export: &ExportConfig,
+        posterior: &[(Vec<f64>, f64)],
+        specs: &[ParameterSpec],
+        rng: &mut R,
+) -> Result<usize, Box<dyn Error>> {
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let size = export.size.unwrap_or_else(|| ess(&weights).round().max(1.0) as usize);
+    let mut idx = match export.scheme {
+        Resampling::Systematic => systematic_resample(&weights, size, rng),
+        Resampling::Stratified => stratified_resample(&weights, size, rng),
+    };
+    idx.shuffle(rng);
+    let dim = posterior.first().map_or(0, |(t, _)| t.len());
+    let mut out = BufWriter::new(File::create(&export.file)?);
+    let header: Vec<String> = (0..dim).map(|j| units::label(specs, j)).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for i in &idx {
+        let row: Vec<String> = posterior[*i].0.iter().enumerate().map(|(j, t)| units::shown(specs, j, *t).to_string()).collect();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    out.flush()?;
+    Ok(idx.len())
+}
+
+
+/// write the posterior of the number of mixture clusters as CSV rows of
+/// K, probability and standard error
+pub fn write_cluster_counts(path: &Path, counts: &[(usize, f64, f64)]) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "clusters,probability,std_error")?;
+    for (k, p, se) in counts {
+        writeln!(out, "{},{:e},{:e}", k, p, se)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+/// read a dead-birth file written by `write_dead_birth` (or PolyChord) for
+/// a model with `dim` parameters
+pub fn read_dead_birth(path: &Path, dim: usize) -> Result<Vec<DeadPoint>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut points = Vec::new();
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let row = line.split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("line {} of {}: {}", i + 1, path.display(), e))?;
+        if row.len() != dim + 2 {
+            return Err(format!(
+                "line {} of {} has {} columns, expected {} parameters, logL and logL_birth",
+                i + 1, path.display(), row.len(), dim,
+            ).into())
+        }
+        points.push(DeadPoint{ theta: row[..dim].to_vec(), log_l: row[dim], log_l_birth: row[dim + 1] });
+    }
+    Ok(points)
+}
+
+
+/// the posterior of a previous run, as (theta, log normalized weight)
+/// pairs, and its log evidence, recomputed from its dead-birth file
+pub fn read_posterior<R: Rng + ?Sized>(
+        path: &Path,
+        dim: usize,
+        rng: &mut R,
+) -> Result<(Posterior, f64), Box<dyn Error>> {
+    let mut points = read_dead_birth(path, dim)?;
+    let summary = dynamic::summarize(&mut points, 0, rng);
+    let posterior = points.into_iter()
+        .zip(&summary.log_wt)
+        .map(|(p, lw)| (p.theta, lw - summary.log_z))
+        .collect();
+    Ok((posterior, summary.log_z))
+}
+
+
+/// settings of the summary files of getdist (Lewis 2019), for tools that
+/// read its tables
+///
+/// Fields:
+/// root: file root; the files are <root>.margestats and <root>.likestats
+/// limits: probabilities inside the credible intervals, in getdist's
+///     default order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GetdistConfig {
+    pub root: PathBuf,
+    pub limits: Vec<f64>,
+}
+
+
+impl Default for GetdistConfig {
+    fn default() -> GetdistConfig {
+        GetdistConfig{
+            root: PathBuf::from("posterior"),
+            limits: vec![0.68, 0.95, 0.99],
+        }
+    }
+}
+
+
+/// a number as getdist, after Fortran, writes it: `%.<digits>E`, with a
+/// signed exponent of at least two digits
+fn fortran_e(v: f64, digits: usize) -> String {
+    let text = format!("{:.*E}", digits, v);
+    match text.split_once('E') {
+        Some((mantissa, exp)) => {
+            let (sign, exp) = match exp.strip_prefix('-') {
+                Some(exp) => ('-', exp),
+                None => ('+', exp),
+            };
+            format!("{}E{}{:0>2}", mantissa, sign, exp)
+        },
+        None => text,
+    }
+}
+
+
+/// a parameter value in a column of getdist's tables, `%15.7E`
+fn column(v: f64) -> String {
+    format!("{:>15}", fortran_e(v, 7))
+}
+
+
+/// the name and LaTeX label getdist shows for parameter j; a given name
+/// stands for both, with the unit added to the label
+fn getdist_name(specs: &[ParameterSpec], j: usize) -> (String, String) {
+    let label = match specs.get(j).and_then(|s| s.name.as_ref()) {
+        Some(_) => units::label(specs, j),
+        None => format!("\\theta_{{{}}}", j),
+    };
+    (units::name(specs, j), label)
+}
+
+
+/// `<root>.<suffix>`, keeping any dot already in the root
+fn root_file(root: &Path, suffix: &str) -> PathBuf {
+    let mut path = root.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+
+/// write `<root>.margestats`, the mean, standard deviation and central
+/// credible intervals of each parameter, and `<root>.likestats`, the best
+/// fit and the extent of the points of highest likelihood holding each
+/// limit's share of the mass, in getdist's layout. `dead_birth` gives the
+/// log L of each posterior point, so runs that keep no dead points, such
+/// as SMC runs, cannot write them
+///
+/// The intervals are equal-tailed quantiles of the weighted points rather
+/// than getdist's smoothed marginal densities, so they are always marked
+/// two-tailed. Parameters are named and scaled as `specs` shows them
+pub fn write_getdist(
+        settings: &GetdistConfig,
+        posterior: &[(Vec<f64>, f64)],
+        dead_birth: &[(f64, f64)],
+        specs: &[ParameterSpec],
+) -> Result<(), Box<dyn Error>> {
+    if posterior.is_empty() || posterior.len() != dead_birth.len() {
+        return Err("getdist summaries need the log-likelihood of every posterior point".into())
+    }
+    let dim = posterior[0].0.len();
+    let names: Vec<(String, String)> = (0..dim).map(|j| getdist_name(specs, j)).collect();
+    let width = names.iter().map(|(n, _)| n.len()).max().unwrap_or(0).max("parameter".len()) + 1;
+    let weights: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+    let total: f64 = weights.iter().sum();
+    let limits: Vec<String> = settings.limits.iter().map(|l| l.to_string()).collect();
+
+    let mut out = BufWriter::new(File::create(root_file(&settings.root, "margestats"))?);
+    writeln!(out, "Marginalized limits: {}", limits.join("; "))?;
+    writeln!(out)?;
+    write!(out, "{:<width$} {:<15} {:<15}", "parameter", "mean", "sddev", width = width)?;
+    for i in 1..=limits.len() {
+        write!(out, " {:<15} {:<15} {:<7}", format!("lower{}", i), format!("upper{}", i), format!("limit{}", i))?;
+    }
+    writeln!(out)?;
+    for (j, (name, label)) in names.iter().enumerate() {
+        let values: Vec<(f64, f64)> = posterior.iter().zip(&weights).map(|((t, _), w)| (units::shown(specs, j, t[j]), *w)).collect();
+        let mean = values.iter().map(|(v, w)| v * w).sum::<f64>() / total;
+        let var = values.iter().map(|(v, w)| w * (v - mean).powi(2)).sum::<f64>() / total;
+        write!(out, "{:<width$} {} {}", name, column(mean), column(var.sqrt()), width = width)?;
+        for limit in &settings.limits {
+            let tail = 0.5 * (1.0 - limit);
+            let (lower, upper) = (weighted_quantile(&values, tail), weighted_quantile(&values, 1.0 - tail));
+            write!(out, " {} {} {:<7}", column(lower), column(upper), "two")?;
+        }
+        writeln!(out, " {}", label)?;
+    }
+    out.flush()?;
+
+    // points from the highest likelihood down
+    let mut order: Vec<usize> = (0..posterior.len()).collect();
+    order.sort_by(|&a, &b| dead_birth[b].0.total_cmp(&dead_birth[a].0));
+    let best = order[0];
+    let mean_log_l = dead_birth.iter().zip(&weights).map(|((l, _), w)| w * l).sum::<f64>() / total;
+    let var_log_l = dead_birth.iter().zip(&weights).map(|((l, _), w)| w * (l - mean_log_l).powi(2)).sum::<f64>() / total;
+    let log_mean_like = dead_birth.iter().zip(posterior)
+        .fold(f64::NEG_INFINITY, |acc, ((l, _), (_, lw))| log_add_exp(acc, l + lw))
+        - total.ln();
+
+    let mut out = BufWriter::new(File::create(root_file(&settings.root, "likestats"))?);
+    writeln!(out, "Best fit sample -log(Like) = {}", fortran_e(-dead_birth[best].0, 6))?;
+    writeln!(out, "mean(-Ln(like)) = {}", fortran_e(-mean_log_l, 6))?;
+    writeln!(out, "-Ln(mean like)  = {}", fortran_e(-log_mean_like, 6))?;
+    writeln!(out, "2*Var(Ln(like)) = {}", fortran_e(2.0 * var_log_l, 6))?;
+    writeln!(out)?;
+    write!(out, "{:<width$} {:<15}", "parameter", "bestfit", width = width)?;
+    for i in 1..=limits.len() {
+        write!(out, " {:<15} {:<15}", format!("lower{}", i), format!("upper{}", i))?;
+    }
+    writeln!(out)?;
+    // the number of top points holding each limit's share of the mass
+    let counts: Vec<usize> = settings.limits.iter()
+        .map(|limit| {
+            let mut mass = 0.0;
+            order.iter().take_while(|&&i| {
+                let inside = mass < limit * total;
+                mass += weights[i];
+                inside
+            }).count()
+        })
+        .collect();
+    for (j, (name, label)) in names.iter().enumerate() {
+        write!(out, "{:<width$} {}", name, column(units::shown(specs, j, posterior[best].0[j])), width = width)?;
+        for count in &counts {
+            let (lower, upper) = order[..*count].iter()
+                .map(|&i| units::shown(specs, j, posterior[i].0[j]))
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+            write!(out, " {} {}", column(lower), column(upper))?;
+        }
+        writeln!(out, " {}", label)?;
+    }
+    out.flush()?;
+    Ok(())
+}
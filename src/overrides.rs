@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::Config;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers_and_precedence() {
+        let base: Value = toml::from_str(
+            "data_file = 'a.csv'\nsample_num = 10\nparticle_num = 5\nbeta_num = 0\nmu = [0.0]\nsd = [1.0]\n",
+        ).unwrap();
+        let env = vec![
+            ("NS_SAMPLE_NUM".to_string(), "20".to_string()),
+            ("NS_SAMPLER__STEPS".to_string(), "7".to_string()),
+            ("PATH".to_string(), "/bin".to_string()),
+        ];
+        let sets = vec!["sampler.steps=50".to_string(), "data_file=b.csv".to_string(), "mu=[1.5]".to_string()];
+        let resolved = resolve(base, env, &sets).unwrap();
+        let config: Config = resolved.clone().try_into().unwrap();
+        assert_eq!(config.sample_num, 20);
+        assert_eq!(config.sampler.steps, 50);
+        assert_eq!(config.data_file, Path::new("b.csv"));
+        assert_eq!(config.mu, vec![1.5]);
+        assert_eq!(resolved["sampler"]["steps"].as_integer(), Some(50));
+
+        assert!(resolve(Value::Table(Default::default()), Vec::new(), &["steps".to_string()]).is_err());
+        assert!(resolve(resolved, Vec::new(), &["mu.x=1".to_string()]).is_err());
+    }
+}
+
+
+/// prefix of the environment variables that override config keys
+pub const ENV_PREFIX: &str = "NS_";
+
+
+/// read a TOML config and apply overrides on top: first environment
+/// variables, then `key=value` assignments such as those given with
+/// `--set` on the command line, so the command line wins
+///
+/// Keys are dotted paths into the config (`sampler.steps`). In the
+/// environment the path is upper case with `__` for the dots, after the
+/// prefix: `NS_SAMPLER__STEPS=50`. Values are read as TOML (`50`, `true`,
+/// `[1.0, 2.0]`, `"text"`), and as a plain string if they do not parse.
+///
+/// Returns the config and the resolved TOML it came from, to be echoed
+/// next to the results.
+pub fn load_layered(
+        path: &Path,
+        sets: &[String],
+) -> Result<(Config, Value), Box<dyn Error>> {
+    let resolved = load_value(path, sets)?;
+    let config = resolved.clone().try_into().map_err(|e| format!("{} with overrides: {}", path.display(), e))?;
+    Ok((config, resolved))
+}
+
+
+/// the TOML of `load_layered`, before it is read as a config
+pub fn load_value(path: &Path, sets: &[String]) -> Result<Value, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let base: Value = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    resolve(base, std::env::vars(), sets)
+}
+
+
+/// apply the environment variables carrying `ENV_PREFIX`, then `sets`
+pub fn resolve(
+        mut config: Value,
+        env: impl IntoIterator<Item = (String, String)>,
+        sets: &[String],
+) -> Result<Value, Box<dyn Error>> {
+    let mut env: Vec<(String, String)> = env.into_iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix(ENV_PREFIX)?.to_lowercase().replace("__", "."), v)))
+        .collect();
+    // apply in a fixed order whatever order the environment lists them in
+    env.sort();
+    for (key, raw) in env {
+        set(&mut config, &key, &raw)?;
+    }
+    for assignment in sets {
+        let (key, raw) = assignment.split_once('=')
+            .ok_or_else(|| format!("override {:?} is not of the form key=value", assignment))?;
+        set(&mut config, key.trim(), raw.trim())?;
+    }
+    Ok(config)
+}
+
+
+/// set the value at a dotted key path, creating tables along the way
+fn set(config: &mut Value, key: &str, raw: &str) -> Result<(), Box<dyn Error>> {
+    let mut node = config;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let table = node.as_table_mut().ok_or_else(|| format!("cannot set {}: {:?} is not a table", key, part))?;
+        if parts.peek().is_none() {
+            table.insert(part.to_string(), parse_value(raw));
+            return Ok(())
+        }
+        node = table.entry(part.to_string()).or_insert_with(|| Value::Table(Default::default()));
+    }
+    Err(format!("empty override key for value {:?}", raw).into())
+}
+
+
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+
+/// write the resolved config as TOML
+pub fn write_resolved(path: &Path, resolved: &Value) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, toml::to_string_pretty(resolved)?)?;
+    Ok(())
+}
@@ -0,0 +1,490 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::curve::LikelihoodCurve;
+use crate::dynamic::{live_counts, summarize_tempered, DeadPoint};
+use crate::evidence::log_add_exp;
+use crate::output::read_dead_birth;
+use crate::stats::weighted_quantile;
+use crate::RunResult;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ess;
+
+    #[test]
+    fn test_compact_posterior_matches_the_points() {
+        let path = std::env::temp_dir().join(format!("ns_posterior_{}.nsp", std::process::id()));
+        // unnormalized weights, as a run streaming its dead points writes them
+        let points: Vec<(Vec<f64>, f64)> = (0..500)
+            .map(|i| {
+                let x = i as f64 / 50.0;
+                (vec![x, -2.0 * x], -0.5 * (x - 4.0).powi(2) + 7.0)
+            })
+            .collect();
+        let mut writer = PosteriorWriter::create(&path, 2).unwrap();
+        for (theta, lw) in &points {
+            writer.push(theta, *lw).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let log_total = points.iter().fold(f64::NEG_INFINITY, |t, (_, lw)| log_add_exp(t, *lw));
+        let weights: Vec<f64> = points.iter().map(|(_, lw)| (lw - log_total).exp()).collect();
+        let mean0: f64 = points.iter().zip(&weights).map(|((t, _), w)| w * t[0]).sum();
+        let column: Vec<(f64, f64)> = points.iter().zip(&weights).map(|((t, _), w)| (t[1], *w)).collect();
+        for posterior in [CompactPosterior::open(&path).unwrap(), CompactPosterior::open_mapped(&path).unwrap()] {
+            assert_eq!((posterior.len(), posterior.dim()), (500, 2));
+            assert!((posterior.log_total() - log_total).abs() < 1e-12);
+            let mean = posterior.mean().unwrap();
+            assert!((mean[0] - mean0).abs() < 1e-12 && (mean[1] + 2.0 * mean0).abs() < 1e-12);
+            let second = posterior.expectation(|t| t[0] * t[0]).unwrap();
+            assert!((second - mean0 * mean0 - 1.0).abs() < 0.01, "{}", second);
+            assert_eq!(posterior.quantile(1, 0.25).unwrap(), weighted_quantile(&column, 0.25));
+            assert!((posterior.ess().unwrap() - ess(&weights)).abs() < 1e-9);
+            let (theta, lw) = posterior.records().unwrap().nth(200).unwrap().unwrap();
+            assert_eq!(theta, points[200].0);
+            assert!((lw - (points[200].1 - log_total)).abs() < 1e-12);
+        }
+
+        // a record cut short by a crash is refused rather than misread
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(CompactPosterior::open(&path).is_err());
+        std::fs::write(&path, b"NSPOST02").unwrap();
+        assert!(CompactPosterior::open_mapped(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_tempered_posterior() {
+        // theta uniform on [0, 1] with log L = -c theta, so X = theta, run
+        // with 50 live points: the i-th dead point sits at X = exp(-(i + 1) / 50)
+        let (n, c) = (50, 20.0);
+        let x: Vec<f64> = (0..1000).map(|i| (-(i as f64 + 1.0) / n as f64).exp()).collect();
+        let points: Vec<DeadPoint> = (0..1000)
+            .map(|i| DeadPoint{
+                theta: vec![x[i]],
+                log_l: -c * x[i],
+                log_l_birth: if i < n { f64::NEG_INFINITY } else { -c * x[i - n] },
+            })
+            .collect();
+        let curve = LikelihoodCurve::from_dead_points(&mut points.clone()).unwrap();
+        let posterior = Posterior::from_dead_points(points);
+        for beta in [1.0, 0.25] {
+            let tempered = posterior.at_temperature(beta).unwrap();
+            // one answer for Z(beta) across the crate
+            assert_eq!(tempered.log_z, curve.log_z(beta));
+            // Z(beta) = (1 - exp(-beta c)) / (beta c), and the mean of
+            // theta under exp(-beta c theta) on [0, 1]
+            let k = beta * c;
+            let log_z = ((1.0 - (-k).exp()) / k).ln();
+            let mean = 1.0 / k - (-k).exp() / (1.0 - (-k).exp());
+            assert!((tempered.log_z - log_z).abs() < 0.05, "{} {}", tempered.log_z, log_z);
+            assert!(tempered.log_z_err > 0.0 && tempered.log_z_err < 0.5);
+            let total: f64 = tempered.points.iter().map(|(_, lw)| lw.exp()).sum();
+            assert!((total - 1.0).abs() < 1e-9);
+            let m: f64 = tempered.points.iter().map(|(t, lw)| t[0] * lw.exp()).sum();
+            assert!((m - mean).abs() < 0.05 * mean, "{} {}", m, mean);
+        }
+        // the prior: every volume counts in full
+        let prior = posterior.at_temperature(0.0).unwrap();
+        assert!(prior.log_z.abs() < 1e-6 && prior.info.abs() < 1e-6);
+        assert!(posterior.at_temperature(-1.0).is_err());
+        assert!(posterior.at_temperature(f64::NAN).is_err());
+    }
+}
+
+
+/// magic bytes at the start of every compact posterior file
+const MAGIC: &[u8; 8] = b"NSPOST01";
+/// size of the header: magic and the u64 number of parameters
+const HEADER_LEN: u64 = 16;
+
+
+/// writes posterior points one at a time to a compact posterior file, so
+/// a run can stream them out as they die
+///
+/// Layout (little-endian): 8 magic bytes, u64 number of parameters, then
+/// one record per point of its parameters and its log weight as f64. The
+/// weights need not be normalized, since the total is only known once the
+/// run ends; readers normalize them.
+#[derive(Debug)]
+pub struct PosteriorWriter {
+    dim: usize,
+    out: BufWriter<File>,
+}
+
+
+impl PosteriorWriter {
+    pub fn create(path: &Path, dim: usize) -> Result<PosteriorWriter, Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(dim as u64).to_le_bytes())?;
+        Ok(PosteriorWriter{ dim, out })
+    }
+
+    pub fn push(&mut self, theta: &[f64], log_w: f64) -> Result<(), Box<dyn Error>> {
+        if theta.len() != self.dim {
+            return Err(format!("a point of {} parameters in a posterior of {}", theta.len(), self.dim).into())
+        }
+        for v in theta.iter().chain([&log_w]) {
+            self.out.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// write out the points pushed so far, e.g. alongside a checkpoint
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()
+    }
+}
+
+
+/// write (theta, log weight) points as a compact posterior file
+pub fn write_compact(path: &Path, posterior: &[(Vec<f64>, f64)]) -> Result<(), Box<dyn Error>> {
+    let dim = posterior.first().map_or(0, |(t, _)| t.len());
+    let mut writer = PosteriorWriter::create(path, dim)?;
+    for (theta, lw) in posterior {
+        writer.push(theta, *lw)?;
+    }
+    writer.finish()
+}
+
+
+/// where the records of a compact posterior are read from
+#[derive(Debug)]
+enum Source {
+    File(PathBuf),
+    Mapped(Mmap),
+}
+
+
+/// a posterior left on disk in the compact format, read a record at a
+/// time whenever it is summarized, so runs too long to hold in memory can
+/// still be analysed
+///
+/// Opening reads the file once to normalize the weights; each summary
+/// reads it again. A memory-mapped file lets the OS keep the pages it has
+/// room for between passes.
+///
+/// Fields:
+/// source: the file, or its memory map
+/// dim: parameters per point
+/// len: number of points
+/// log_total: log of the sum of the stored weights
+#[derive(Debug)]
+pub struct CompactPosterior {
+    source: Source,
+    dim: usize,
+    len: usize,
+    log_total: f64,
+}
+
+
+impl CompactPosterior {
+    /// open a compact posterior file, reading it through a buffer
+    pub fn open(path: &Path) -> Result<CompactPosterior, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        if file.read_exact(&mut header).is_err() {
+            return Err(format!("{} is not a compact posterior file", path.display()).into())
+        }
+        let size = file.metadata()?.len();
+        CompactPosterior::from_header(Source::File(path.to_path_buf()), &header, size, path)
+    }
+
+    /// open a compact posterior file by mapping it into memory
+    pub fn open_mapped(path: &Path) -> Result<CompactPosterior, Box<dyn Error>> {
+        let file = File::open(path)?;
+        // the map is read-only; callers are expected not to rewrite the
+        // file while it is open
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_LEN as usize {
+            return Err(format!("{} is not a compact posterior file", path.display()).into())
+        }
+        let header: [u8; HEADER_LEN as usize] = map[..HEADER_LEN as usize].try_into()?;
+        let size = map.len() as u64;
+        CompactPosterior::from_header(Source::Mapped(map), &header, size, path)
+    }
+
+    fn from_header(source: Source, header: &[u8], size: u64, path: &Path) -> Result<CompactPosterior, Box<dyn Error>> {
+        if &header[..8] != MAGIC {
+            return Err(format!("{} is not a compact posterior file", path.display()).into())
+        }
+        let dim = u64::from_le_bytes(header[8..16].try_into()?);
+        // the header is untrusted, so the record size may not wrap around
+        let record = dim.checked_add(1).and_then(|n| n.checked_mul(8)).ok_or("corrupt record size")?;
+        let body = size - HEADER_LEN;
+        if !body.is_multiple_of(record) {
+            return Err(format!("{} ends in a partial record; it is truncated or corrupt", path.display()).into())
+        }
+        let mut posterior = CompactPosterior{ source, dim: dim as usize, len: (body / record) as usize, log_total: 0.0 };
+        let mut log_total = f64::NEG_INFINITY;
+        for point in posterior.records()? {
+            log_total = log_add_exp(log_total, point?.1);
+        }
+        posterior.log_total = log_total;
+        Ok(posterior)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// log of the sum of the stored weights, log Z for the dead points of
+    /// a run stored with weights w L
+    pub fn log_total(&self) -> f64 {
+        self.log_total
+    }
+
+    /// the points in file order, as (theta, log normalized weight)
+    pub fn records(&self) -> Result<Records<'_>, Box<dyn Error>> {
+        let reader = match &self.source {
+            Source::File(path) => {
+                let mut file = BufReader::new(File::open(path)?);
+                file.seek(SeekFrom::Start(HEADER_LEN))?;
+                Reader::File(file)
+            },
+            Source::Mapped(map) => Reader::Mapped(&map[HEADER_LEN as usize..]),
+        };
+        Ok(Records{ posterior: self, reader, next: 0 })
+    }
+
+    /// posterior expectation of `f`
+    pub fn expectation(&self, f: impl Fn(&[f64]) -> f64) -> Result<f64, Box<dyn Error>> {
+        let mut total = 0.0;
+        for point in self.records()? {
+            let (theta, lw) = point?;
+            total += lw.exp() * f(&theta);
+        }
+        Ok(total)
+    }
+
+    /// posterior mean of every parameter
+    pub fn mean(&self) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mut mean = vec![0.0; self.dim];
+        for point in self.records()? {
+            let (theta, lw) = point?;
+            let w = lw.exp();
+            mean.iter_mut().zip(&theta).for_each(|(m, t)| *m += w * t);
+        }
+        Ok(mean)
+    }
+
+    /// the p-quantile of parameter `param`; holds that one parameter of
+    /// every point in memory
+    pub fn quantile(&self, param: usize, p: f64) -> Result<f64, Box<dyn Error>> {
+        if param >= self.dim {
+            return Err(format!("parameter {} does not exist; the posterior has {}", param, self.dim).into())
+        }
+        let mut column = Vec::with_capacity(self.len);
+        for point in self.records()? {
+            let (theta, lw) = point?;
+            column.push((theta[param], lw.exp()));
+        }
+        Ok(weighted_quantile(&column, p))
+    }
+
+    /// Kish effective sample size of the weights
+    pub fn ess(&self) -> Result<f64, Box<dyn Error>> {
+        let mut sum_sq = 0.0;
+        for point in self.records()? {
+            sum_sq += (2.0 * point?.1).exp();
+        }
+        Ok(1.0 / sum_sq)
+    }
+}
+
+
+/// the byte source of one pass over the records
+#[derive(Debug)]
+enum Reader<'a> {
+    File(BufReader<File>),
+    Mapped(&'a [u8]),
+}
+
+
+/// one pass over the records of a compact posterior
+#[derive(Debug)]
+pub struct Records<'a> {
+    posterior: &'a CompactPosterior,
+    reader: Reader<'a>,
+    next: usize,
+}
+
+
+impl Iterator for Records<'_> {
+    type Item = io::Result<(Vec<f64>, f64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.posterior.len {
+            return None
+        }
+        let width = self.posterior.dim + 1;
+        let mut values = vec![0.0; width];
+        match &mut self.reader {
+            Reader::File(file) => {
+                let mut buf = [0u8; 8];
+                for v in values.iter_mut() {
+                    if let Err(e) = file.read_exact(&mut buf) {
+                        return Some(Err(e))
+                    }
+                    *v = f64::from_le_bytes(buf);
+                }
+            },
+            Reader::Mapped(bytes) => {
+                let start = self.next * width * 8;
+                for (v, chunk) in values.iter_mut().zip(bytes[start..start + width * 8].chunks_exact(8)) {
+                    *v = f64::from_le_bytes(chunk.try_into().expect("8-byte chunk"));
+                }
+            },
+        }
+        self.next += 1;
+        let lw = values.pop().unwrap_or(f64::NEG_INFINITY);
+        Some(Ok((values, lw - self.posterior.log_total)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.posterior.len - self.next;
+        (left, Some(left))
+    }
+}
+
+
+/// simulated shrinkage sequences behind the log Z error of a tempered
+/// posterior
+const TEMPERED_SIMULATIONS: usize = 100;
+
+
+/// the dead points of a nested sampling run in order of log L, with the
+/// live points at each contour
+///
+/// Their prior volumes do not depend on the likelihood, so the posterior
+/// and evidence of L^beta follow from the same points for any beta
+/// without new likelihood calls: beta < 1 flattens the likelihood toward
+/// the prior, e.g. to see how much a conclusion rests on the data, and
+/// beta > 1 sharpens it, as long as enough points lie near the peak.
+///
+/// Fields:
+/// points: the dead points, sorted by log L
+/// n_live: live points at each one's contour
+#[derive(Debug, Clone)]
+pub struct Posterior {
+    points: Vec<DeadPoint>,
+    n_live: Vec<usize>,
+}
+
+
+/// the posterior and evidence of L^beta
+///
+/// Fields:
+/// beta: the inverse temperature
+/// log_z, log_z_err: log of int L^beta dX, as `LikelihoodCurve::log_z`
+///     integrates it, and its standard deviation over simulated shrinkages
+/// info: the information of the tempered posterior, in nats
+/// ess: Kish effective sample size of its weights
+/// points: every dead point as (theta, log of its normalized weight)
+#[derive(Debug, Clone)]
+pub struct Tempered {
+    pub beta: f64,
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub info: f64,
+    pub ess: f64,
+    pub points: Vec<(Vec<f64>, f64)>,
+}
+
+
+impl Posterior {
+    pub fn from_dead_points(mut points: Vec<DeadPoint>) -> Posterior {
+        let n_live = live_counts(&mut points);
+        Posterior{ points, n_live }
+    }
+
+    /// the posterior of a nested sampling run; tempering and SMC runs keep
+    /// no birth contours to make one from
+    pub fn from_result(result: &RunResult) -> Result<Posterior, Box<dyn Error>> {
+        if result.dead_birth.len() != result.posterior.len() || result.dead_birth.is_empty() {
+            return Err("the run kept no dead points with birth contours".into())
+        }
+        let points = result.posterior.iter().zip(&result.dead_birth)
+            .map(|((theta, _), (log_l, log_l_birth))| DeadPoint{ theta: theta.clone(), log_l: *log_l, log_l_birth: *log_l_birth })
+            .collect();
+        Ok(Posterior::from_dead_points(points))
+    }
+
+    /// open the compact posterior file a run streamed its dead points to,
+    /// summarized from disk rather than loaded (see `CompactPosterior`)
+    pub fn open(path: &Path) -> Result<CompactPosterior, Box<dyn Error>> {
+        CompactPosterior::open(path)
+    }
+
+    /// the posterior of a dead-birth file of a model with `dim` parameters
+    pub fn read_dead_birth(path: &Path, dim: usize) -> Result<Posterior, Box<dyn Error>> {
+        Ok(Posterior::from_dead_points(read_dead_birth(path, dim)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// the posterior and evidence of the likelihood raised to `beta` >= 0;
+    /// beta = 1 gives back the run's own posterior
+    ///
+    /// Z(beta) is that of the run's `LikelihoodCurve`, so every tempered
+    /// evidence of the crate comes from the one quadrature; the points
+    /// keep their shrinkage weights, normalized among themselves.
+    pub fn at_temperature(&self, beta: f64) -> Result<Tempered, Box<dyn Error>> {
+        if !(beta >= 0.0 && beta.is_finite()) {
+            return Err(format!("the inverse temperature {} must be finite and not negative", beta).into())
+        }
+        let log_z = LikelihoodCurve::from_sorted(&self.points, &self.n_live)?.log_z(beta);
+        // a fixed seed, so the same beta always gets the same error
+        let mut rng = StdRng::seed_from_u64(0);
+        let summary = summarize_tempered(&self.points, &self.n_live, beta, TEMPERED_SIMULATIONS, &mut rng);
+        let points: Vec<(Vec<f64>, f64)> = self.points.iter().zip(&summary.log_wt)
+            .map(|(p, lw)| (p.theta.clone(), lw - summary.log_z))
+            .collect();
+        // the posterior mean of log L^beta less log Z(beta); L^0 = 1 even where L = 0
+        let mean_log_l: f64 = match beta == 0.0 {
+            true => 0.0,
+            false => self.points.iter().zip(&points)
+                .filter(|(_, (_, lw))| *lw > f64::NEG_INFINITY)
+                .map(|(p, (_, lw))| lw.exp() * beta * p.log_l)
+                .sum(),
+        };
+        Ok(Tempered{
+            beta,
+            log_z,
+            log_z_err: summary.log_z_err,
+            info: mean_log_l - log_z,
+            ess: summary.ess,
+            points,
+        })
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use toml::Value;
+
+use crate::observer::Observer;
+use crate::output::{write_equal_weights, ExportConfig, Resampling};
+use crate::posterior::Posterior;
+use crate::report::RunReport;
+use crate::rundir::RunDir;
+use crate::units;
+use crate::Config;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Collect;
+    use crate::units::ParameterSpec;
+
+    struct CountRows;
+
+    impl PostProcessor for CountRows {
+        fn process(&self, dir: &RunDir, _config: &Config, _observer: &mut dyn Observer) -> Result<(), Box<dyn Error>> {
+            let rows = fs::read_to_string(dir.dead_birth_file())?.lines().count();
+            fs::write(dir.path().join("rows.txt"), rows.to_string())?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_builtins_and_a_registered_step() {
+        let root = std::env::temp_dir().join(format!("ns_postprocess_{}", std::process::id()));
+        let dir = RunDir::create(&root, "peak", false).unwrap();
+        let config = Config{
+            mu: vec![0.5],
+            sd: vec![1.0],
+            parameters: vec![ParameterSpec{ name: Some("x".to_string()), unit: Some("mm".to_string()), scale: 1e3 }],
+            ..Default::default()
+        };
+        dir.write_config(&config).unwrap();
+        // 20 live points closing in on a peak at 0.5
+        let n = 20;
+        let log_l = |x: f64| -0.5 * ((x - 0.5) / 0.05).powi(2);
+        let mut contours = Vec::new();
+        let mut rows = Vec::new();
+        for i in 0..400 {
+            let half = 0.5 * (-(i as f64) / n as f64).exp();
+            let x = if i % 2 == 0 { 0.5 - half } else { 0.5 + half };
+            let birth = if i < n { f64::NEG_INFINITY } else { contours[i - n] };
+            contours.push(log_l(x));
+            rows.push(format!("{:e} {:e} {:e}", x, log_l(x), birth));
+        }
+        fs::write(dir.dead_birth_file(), rows.join("\n")).unwrap();
+        fs::write(dir.summary_file(), "log_z = -2.5\nlog_z_err = 0.2\ntruncation = []\n").unwrap();
+
+        register_post_processor("count_rows", Arc::new(CountRows)).unwrap();
+        assert!(register_post_processor("count_rows", Arc::new(CountRows)).is_err());
+        assert!(register_post_processor("summary", Arc::new(CountRows)).is_err());
+
+        let names: Vec<String> = ["summary", "diagnostics", "export", "count_rows"].iter().map(|s| s.to_string()).collect();
+        post_process(&dir, &config, &names, &mut Collect::default()).unwrap();
+        let parameters = fs::read_to_string(dir.parameters_file()).unwrap();
+        assert!(parameters.starts_with("param,name,unit,mean,sd,median,lower,upper\n0,x,mm,"), "{}", parameters);
+        // shown in mm: the peak at 0.5 is at 500
+        let median: f64 = parameters.lines().nth(1).unwrap().split(',').nth(5).unwrap().parse().unwrap();
+        assert!((median - 500.0).abs() < 10.0, "{}", median);
+        let diagnostics: toml::Table = toml::from_str(&fs::read_to_string(dir.diagnostics_file()).unwrap()).unwrap();
+        assert_eq!(diagnostics["checks"].as_array().unwrap().len(), 4);
+        let samples = fs::read_to_string(dir.samples_file()).unwrap();
+        assert!(samples.starts_with("x [mm]\n") && samples.lines().count() > 1);
+        post_process(&dir, &config, &["export".to_string()], &mut Collect::default()).unwrap();
+        assert_eq!(fs::read_to_string(dir.samples_file()).unwrap(), samples);
+        assert_eq!(fs::read_to_string(dir.path().join("rows.txt")).unwrap(), "400");
+
+        // an unknown name stops everything before anything runs
+        fs::remove_file(dir.parameters_file()).unwrap();
+        let names = vec!["summary".to_string(), "no_such_step".to_string()];
+        assert!(post_process(&dir, &config, &names, &mut Collect::default()).is_err());
+        assert!(!dir.parameters_file().exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
+
+
+/// probability inside the credible intervals of the built-in summary
+pub const SUMMARY_LEVEL: f64 = 0.9;
+/// the names of the built-in steps, which `ns summarize` runs when the
+/// run's config names none
+pub const BUILTINS: [&str; 3] = ["summary", "diagnostics", "export"];
+
+
+/// a step of analysis run on the directory of a finished run, once its
+/// outputs are written, e.g. a lab's standard plots or tables
+///
+/// Steps read what they need from the directory, so the same step runs at
+/// the end of a run and later on by `ns summarize`. They report through
+/// `observer` and write their own files into the directory.
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, dir: &RunDir, config: &Config, observer: &mut dyn Observer) -> Result<(), Box<dyn Error>>;
+}
+
+
+/// writes parameters.csv: the posterior mean, sd, median and central
+/// `SUMMARY_LEVEL` credible interval of every parameter, with the names,
+/// units and scales of the config's `parameters`
+struct Summary;
+
+impl PostProcessor for Summary {
+    fn process(&self, dir: &RunDir, config: &Config, _observer: &mut dyn Observer) -> Result<(), Box<dyn Error>> {
+        let report = RunReport::read(dir, config.n_params(), SUMMARY_LEVEL)?;
+        let mut out = BufWriter::new(File::create(dir.parameters_file())?);
+        writeln!(out, "param,name,unit,mean,sd,median,lower,upper")?;
+        let specs = &config.parameters;
+        for p in &report.parameters {
+            let unit = specs.get(p.param).and_then(|s| s.unit.as_deref()).unwrap_or("");
+            let [mean, sd, median, lower, upper] = [p.mean, p.sd, p.median, p.lower, p.upper].map(|v| units::shown(specs, p.param, v));
+            writeln!(
+                out, "{},{},{},{:e},{:e},{:e},{:e},{:e}",
+                p.param, units::name(specs, p.param), unit, mean, sd, median, lower, upper,
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+
+/// writes diagnostics.toml: the checks of the HTML report, warning of
+/// each one the run failed
+struct Diagnostics;
+
+impl PostProcessor for Diagnostics {
+    fn process(&self, dir: &RunDir, config: &Config, observer: &mut dyn Observer) -> Result<(), Box<dyn Error>> {
+        let report = RunReport::read(dir, config.n_params(), SUMMARY_LEVEL)?;
+        let checks = report.verdicts.iter()
+            .map(|v| {
+                if v.passed == Some(false) {
+                    observer.warn(&format!("{} failed: {}", v.check, v.detail));
+                }
+                let mut table = toml::Table::new();
+                table.insert("check".into(), Value::String(v.check.clone()));
+                if let Some(passed) = v.passed {
+                    table.insert("passed".into(), Value::Boolean(passed));
+                }
+                table.insert("detail".into(), Value::String(v.detail.clone()));
+                Value::Table(table)
+            })
+            .collect();
+        let mut diagnostics = toml::Table::new();
+        diagnostics.insert("checks".into(), Value::Array(checks));
+        fs::write(dir.diagnostics_file(), toml::to_string_pretty(&diagnostics)?)?;
+        Ok(())
+    }
+}
+
+
+/// writes samples.csv: an equally weighted posterior sample of the
+/// posterior's effective size, named and scaled as the config shows it
+struct Export;
+
+impl PostProcessor for Export {
+    fn process(&self, dir: &RunDir, config: &Config, _observer: &mut dyn Observer) -> Result<(), Box<dyn Error>> {
+        let posterior = Posterior::read_dead_birth(&dir.dead_birth_file(), config.n_params())?.at_temperature(1.0)?;
+        let export = ExportConfig{ file: dir.samples_file(), size: None, scheme: Resampling::Systematic };
+        // seeded, so the same run always exports the same sample
+        let mut rng = StdRng::seed_from_u64(config.seed.unwrap_or(0));
+        write_equal_weights(&export, &posterior.points, &config.parameters, &mut rng)?;
+        Ok(())
+    }
+}
+
+
+/// the steps selectable from configs as `post_process = ["name", ...]`,
+/// built-ins first
+fn registry() -> &'static RwLock<HashMap<String, Arc<dyn PostProcessor>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Arc<dyn PostProcessor>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: [(&str, Arc<dyn PostProcessor>); 3] = [
+            ("summary", Arc::new(Summary)),
+            ("diagnostics", Arc::new(Diagnostics)),
+            ("export", Arc::new(Export)),
+        ];
+        RwLock::new(builtins.into_iter().map(|(name, p)| (name.to_string(), p)).collect())
+    })
+}
+
+
+/// make a step selectable from configs as `post_process = ["name"]`, so
+/// a crate can ship a standard analysis that every run then makes. Names
+/// are unique
+pub fn register_post_processor(name: &str, processor: Arc<dyn PostProcessor>) -> Result<(), Box<dyn Error>> {
+    let mut processors = registry().write().map_err(|_| "the post-processor registry is poisoned")?;
+    if name.is_empty() || processors.contains_key(name) {
+        return Err(format!("a post-processor named {:?} is already registered or the name is empty", name).into())
+    }
+    processors.insert(name.to_string(), processor);
+    Ok(())
+}
+
+
+/// the step registered as `name`
+pub fn post_processor(name: &str) -> Result<Arc<dyn PostProcessor>, Box<dyn Error>> {
+    let processors = registry().read().map_err(|_| "the post-processor registry is poisoned")?;
+    processors.get(name).cloned().ok_or_else(|| format!("no post-processor registered as {:?}", name).into())
+}
+
+
+/// run the steps registered as `names`, in order, on the finished run in
+/// `dir` made with `config`; stops at the first that fails. Every name is
+/// looked up before any step runs
+pub fn post_process(dir: &RunDir, config: &Config, names: &[String], observer: &mut dyn Observer) -> Result<(), Box<dyn Error>> {
+    let processors = names.iter().map(|name| post_processor(name)).collect::<Result<Vec<_>, _>>()?;
+    for (name, processor) in names.iter().zip(processors) {
+        processor.process(dir, config, observer).map_err(|e| format!("post-processor {:?}: {}", name, e))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use crate::simulate::{build_simulator, observed_response};
+use crate::Config;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::dist::Normal;
+
+    #[test]
+    fn test_a_misfit_is_flagged() {
+        let mut rng = StdRng::seed_from_u64(457);
+        let noise = Normal::new(0.0, 0.5).unwrap();
+        let x: Vec<f64> = (0..200).map(|i| i as f64 / 100.0).collect();
+        let line: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x + noise.sample(&mut rng)).collect();
+        // the same points bent into a parabola the line cannot follow
+        let bent: Vec<f64> = x.iter().zip(&line).map(|(x, y)| y + 3.0 * (x - 1.0).powi(2)).collect();
+        let config = Config{ noise_sd: Some(0.5), ..Default::default() };
+        // a posterior concentrated at the generating line
+        let posterior = vec![(vec![1.0, 2.0], 0.5f64.ln()), (vec![1.01, 1.99], 0.5f64.ln())];
+        let settings = PpcConfig{ draws: 200, alpha: 0.01 };
+
+        let data = Dataset::from_columns(vec![x.clone(), line], None).unwrap();
+        let fit = posterior_predictive(&config, &data, &posterior, &settings, &mut rng).unwrap();
+        let names: Vec<&str> = fit.checks.iter().map(|c| c.statistic.as_str()).collect();
+        assert_eq!(names, vec!["mean", "sd", "min", "max", "chi_square"]);
+        assert!(fit.flagged().is_empty(), "{:?}", fit.checks);
+
+        let data = Dataset::from_columns(vec![x, bent], None).unwrap();
+        let misfit = posterior_predictive(&config, &data, &posterior, &settings, &mut rng).unwrap();
+        let chi_sq = misfit.checks.iter().find(|c| c.statistic == "chi_square").unwrap();
+        assert_eq!(chi_sq.p_value, 0.0);
+        assert!(chi_sq.observed > chi_sq.replicated);
+        assert!(misfit.flagged().contains(&chi_sq));
+
+        let path = std::env::temp_dir().join(format!("ns_ppc_{}.toml", std::process::id()));
+        misfit.write(&path).unwrap();
+        let read = PpcReport::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.checks.len(), misfit.checks.len());
+        assert_eq!(read.checks[4].p_value, 0.0);
+    }
+}
+
+
+/// settings of posterior predictive checks
+///
+/// Fields:
+/// draws: posterior draws to simulate a replicate response at
+/// alpha: a statistic is flagged when the replicates exceed the observed
+///     value in less than alpha / 2 or more than 1 - alpha / 2 of the draws
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PpcConfig {
+    pub draws: usize,
+    pub alpha: f64,
+}
+
+
+impl Default for PpcConfig {
+    fn default() -> PpcConfig {
+        PpcConfig{ draws: 500, alpha: 0.05 }
+    }
+}
+
+
+/// the posterior predictive check of one discrepancy statistic
+///
+/// Fields:
+/// statistic: the statistic's name
+/// observed: posterior mean of T(y, theta) at the observed response
+/// replicated: posterior mean of T(y_rep, theta) at the replicates
+/// p_value: the share of draws whose replicate exceeds the observed
+///     response, P(T(y_rep, theta) >= T(y, theta) | y)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PpcCheck {
+    pub statistic: String,
+    pub observed: f64,
+    pub replicated: f64,
+    pub p_value: f64,
+}
+
+
+/// the outcome of posterior predictive checks, which a run with an output
+/// directory keeps in its ppc.toml
+///
+/// Fields:
+/// draws: posterior draws the replicates were simulated at
+/// alpha: the two-sided level statistics are flagged at
+/// checks: one per statistic
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PpcReport {
+    pub draws: usize,
+    pub alpha: f64,
+    pub checks: Vec<PpcCheck>,
+}
+
+
+impl PpcReport {
+    /// the checks whose p-value is in either tail
+    pub fn flagged(&self) -> Vec<&PpcCheck> {
+        self.checks.iter()
+            .filter(|c| c.p_value < 0.5 * self.alpha || c.p_value > 1.0 - 0.5 * self.alpha)
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<PpcReport, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+}
+
+
+/// the statistics every check compares: the mean, sd, minimum and maximum
+/// of the response, then those the model defines
+fn statistics(y: &[f64], model_defined: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let n = y.len() as f64;
+    let mean = y.iter().sum::<f64>() / n;
+    let sd = (y.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let min = y.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = y.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let mut stats = vec![
+        ("mean".to_string(), mean),
+        ("sd".to_string(), sd),
+        ("min".to_string(), min),
+        ("max".to_string(), max),
+    ];
+    stats.extend(model_defined);
+    stats
+}
+
+
+/// posterior predictive p-values of the configured model on `data`
+///
+/// Draws `settings.draws` parameter vectors from the weighted posterior,
+/// simulates a replicate response at each, and compares every discrepancy
+/// statistic between the replicate and the observed response at the same
+/// draw (Gelman, Meng and Stern 1996). A p-value near 0 or 1 means the
+/// observed data are extreme among those the fitted model produces, in
+/// the respect the statistic measures.
+pub fn posterior_predictive<R: Rng>(
+        config: &Config,
+        data: &Dataset,
+        posterior: &[(Vec<f64>, f64)],
+        settings: &PpcConfig,
+        rng: &mut R,
+) -> Result<PpcReport, Box<dyn Error>> {
+    if settings.draws == 0 {
+        return Err("posterior predictive checks need at least one draw".into())
+    }
+    let model = build_simulator(config, data)?;
+    let observed = observed_response(config, data)?;
+    let weights = WeightedIndex::new(posterior.iter().map(|(_, lw)| lw.exp()))
+        .map_err(|e| format!("cannot draw from the posterior: {}", e))?;
+
+    let mut totals: Vec<(String, f64, f64, usize)> = Vec::new();
+    for _ in 0..settings.draws {
+        let theta = &posterior[weights.sample(rng)].0;
+        let replicate = model.simulate(theta, rng)?;
+        let at_observed = statistics(&observed, model.discrepancies(&observed, theta));
+        let at_replicate = statistics(&replicate, model.discrepancies(&replicate, theta));
+        if totals.is_empty() {
+            totals = at_observed.iter().map(|(name, _)| (name.clone(), 0.0, 0.0, 0)).collect();
+        }
+        for ((total, (_, t_obs)), (_, t_rep)) in totals.iter_mut().zip(&at_observed).zip(&at_replicate) {
+            total.1 += t_obs;
+            total.2 += t_rep;
+            if t_rep >= t_obs {
+                total.3 += 1;
+            }
+        }
+    }
+
+    let n = settings.draws as f64;
+    let checks = totals.into_iter()
+        .map(|(statistic, observed, replicated, exceed)| PpcCheck{
+            statistic,
+            observed: observed / n,
+            replicated: replicated / n,
+            p_value: exceed as f64 / n,
+        })
+        .collect();
+    Ok(PpcReport{ draws: settings.draws, alpha: settings.alpha, checks })
+}
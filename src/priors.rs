@@ -0,0 +1,1032 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::iter::zip;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand::distributions::Distribution;
+#[cfg(feature = "rv")]
+use rv::traits::Rv;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "statrs")]
+use statrs::distribution::{Beta, Continuous, ContinuousCDF, Gamma, StudentsT};
+
+use crate::dist::Normal;
+use crate::evidence::log_add_exp;
+use crate::geometry::mean_cov;
+use crate::linalg::{Cholesky, Matrix};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marginal_quantiles_invert_the_cdf() {
+        let marginals = [
+            Marginal::Normal{ mu: 1.0, sd: 2.0 },
+            Marginal::LogNormal{ mu: 0.5, sigma: 0.3 },
+            Marginal::Uniform{ lower: -1.0, upper: 3.0 },
+            #[cfg(feature = "statrs")]
+            Marginal::Gamma{ shape: 2.5, rate: 4.0 },
+            #[cfg(feature = "statrs")]
+            Marginal::Beta{ a: 0.5, b: 3.0 },
+            #[cfg(feature = "statrs")]
+            Marginal::StudentT{ location: 0.0, scale: 1.5, dof: 3.0 },
+        ];
+        for marginal in &marginals {
+            marginal.check().unwrap();
+            for p in [1e-6, 0.01, 0.3, 0.5, 0.9, 1.0 - 1e-6] {
+                let x = marginal.quantile(p);
+                assert!((marginal.cdf(x) - p).abs() < 1e-9, "{:?} at {}: {}", marginal, p, marginal.cdf(x));
+            }
+        }
+        assert!(Marginal::LogNormal{ mu: 0.0, sigma: -1.0 }.check().is_err());
+        assert!(Marginal::Uniform{ lower: 1.0, upper: 1.0 }.check().is_err());
+        #[cfg(feature = "statrs")]
+        assert!(Marginal::Gamma{ shape: -1.0, rate: 1.0 }.check().is_err());
+    }
+
+    #[test]
+    fn test_spike_and_slab_mixture() {
+        let text = r#"
+            dist = "mixture"
+            components = [
+                { weight = 1, dist = "normal", mu = 0, sd = 0.01 },
+                { weight = 3, dist = "uniform", lower = -10, upper = 10 },
+            ]
+        "#;
+        let marginal: Marginal = toml::from_str(text).unwrap();
+        marginal.check().unwrap();
+        let expected = (0.25 * Normal::new(0.0, 0.01).unwrap().pdf(0.005) + 0.75 / 20.0).ln();
+        assert!((marginal.ln_pdf(0.005) - expected).abs() < 1e-12);
+        for p in [1e-6, 0.2, 0.4, 0.5, 0.6, 0.9] {
+            assert!((marginal.cdf(marginal.quantile(p)) - p).abs() < 1e-9);
+        }
+        let spike = marginal.responsibilities(0.0);
+        assert!(spike[0] > 0.99 && (spike.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+
+        // a quarter of the draws sit in the spike
+        let prior = CopulaPrior::new(vec![marginal], &[]).unwrap();
+        let mut rng = StdRng::seed_from_u64(424);
+        let mut theta = [0.0];
+        let near = (0..20000).filter(|_| { prior.sample_into(&mut theta, &mut rng); theta[0].abs() < 0.05 }).count();
+        let expected_near = 20000.0 * (0.25 + 0.75 * 0.1 / 20.0);
+        assert!((near as f64 - expected_near).abs() < 4.0 * expected_near.sqrt(), "{}", near);
+
+        let empty = Marginal::Mixture{ components: Vec::new() };
+        assert!(empty.check().is_err());
+        let negative = Marginal::Mixture{ components: vec![Component{ weight: -1.0, marginal: Marginal::Normal{ mu: 0.0, sd: 1.0 } }] };
+        assert!(negative.check().is_err());
+    }
+
+    #[test]
+    fn test_gaussian_copula_of_normals_is_a_bivariate_normal() {
+        let marginals = vec![Marginal::Normal{ mu: 1.0, sd: 2.0 }, Marginal::Normal{ mu: -1.0, sd: 0.5 }];
+        let correlation = vec![vec![1.0, 0.6], vec![0.6, 1.0]];
+        let prior = CopulaPrior::new(marginals, &correlation).unwrap();
+
+        // the density of N(mu, S) with S = D R D
+        let cov = Matrix::from_rows(vec![vec![4.0, 0.6], vec![0.6, 0.25]]).cholesky().unwrap();
+        let theta = [2.0, -0.7];
+        let d = [theta[0] - 1.0, theta[1] + 1.0];
+        let expected = -0.5 * (2.0 * (2.0 * std::f64::consts::PI).ln() + cov.ln_det() + cov.quad_form(&d));
+        assert!((prior.log_density(&theta) - expected).abs() < 1e-9);
+
+        let mut rng = StdRng::seed_from_u64(420);
+        let mut theta = [0.0; 2];
+        let draws: Vec<[f64; 2]> = (0..20000).map(|_| { prior.sample_into(&mut theta, &mut rng); theta }).collect();
+        let mean = |j: usize| draws.iter().map(|t| t[j]).sum::<f64>() / draws.len() as f64;
+        let (m0, m1) = (mean(0), mean(1));
+        let cov01 = draws.iter().map(|t| (t[0] - m0) * (t[1] - m1)).sum::<f64>() / draws.len() as f64;
+        assert!((m0 - 1.0).abs() < 0.05 && (m1 + 1.0).abs() < 0.02);
+        assert!((cov01 - 0.6).abs() < 0.05, "{}", cov01);
+        assert!((prior.scale()[0] - 2.0).abs() < 1e-6);
+
+        assert!(CopulaPrior::new(vec![Marginal::Normal{ mu: 0.0, sd: 1.0 }; 2], &[vec![1.0, 1.5], vec![1.5, 1.0]]).is_err());
+    }
+
+    #[test]
+    fn test_consistency_check_catches_mismatched_closures() {
+        let mut rng = StdRng::seed_from_u64(421);
+        // exponential(2) on the first parameter, uniform(0, 4) on the second
+        let log_density = |t: &[f64]| {
+            if t[0] < 0.0 || !(0.0..4.0).contains(&t[1]) { f64::NEG_INFINITY } else { 2f64.ln() - 2.0 * t[0] - 4f64.ln() }
+        };
+        let good = ClosurePrior::from_inverse_cdf(2, |u, t| { t[0] = -(1.0 - u[0]).ln() / 2.0; t[1] = 4.0 * u[1] }, log_density);
+        assert!(check_prior(&good, 20000, &mut rng).is_empty(), "{:?}", check_prior(&good, 20000, &mut rng));
+        assert!((good.scale()[1] - 4.0 / 12f64.sqrt()).abs() < 0.1);
+
+        // the common slip: the density misses its normalizing constant
+        let unnormalized = ClosurePrior::from_inverse_cdf(
+            2,
+            |u, t| { t[0] = -(1.0 - u[0]).ln() / 2.0; t[1] = 4.0 * u[1] },
+            move |t| log_density(t) + 4f64.ln(),
+        );
+        assert!(check_prior(&unnormalized, 20000, &mut rng).iter().any(|p| p.contains("integrates to")));
+
+        // a sampler with the rate where the density has the scale
+        let swapped = ClosurePrior::from_inverse_cdf(2, |u, t| { t[0] = -(1.0 - u[0]).ln() * 2.0; t[1] = 4.0 * u[1] }, log_density);
+        assert!(!check_prior(&swapped, 20000, &mut rng).is_empty());
+
+        register_prior("test_exponential", Arc::new(good)).unwrap();
+        assert!(register_prior("test_exponential", Arc::new(swapped)).is_err());
+        assert_eq!(registered_prior("test_exponential").unwrap().dim(), 2);
+        assert!(registered_prior("no_such_prior").is_err());
+    }
+
+    // Exp(rate), implemented against rv's trait as any rv distribution is
+    #[cfg(feature = "rv")]
+    #[derive(Debug)]
+    struct Exponential {
+        rate: f64,
+    }
+
+    #[cfg(feature = "rv")]
+    impl Rv<f64> for Exponential {
+        fn ln_f(&self, x: &f64) -> f64 {
+            if *x < 0.0 { f64::NEG_INFINITY } else { self.rate.ln() - self.rate * x }
+        }
+
+        fn draw<R: Rng>(&self, rng: &mut R) -> f64 {
+            -(1.0 - rng.gen::<f64>()).ln() / self.rate
+        }
+    }
+
+    #[cfg(feature = "rv")]
+    #[test]
+    fn test_rv_distributions_are_parameter_priors() {
+        let mut rng = StdRng::seed_from_u64(444);
+        let prior = IndependentPrior::new(vec![Box::new(Exponential{ rate: 2.0 }), Box::new(Exponential{ rate: 0.1 })]);
+        assert_eq!(prior.dim(), 2);
+        assert!(check_prior(&prior, 20000, &mut rng).is_empty(), "{:?}", check_prior(&prior, 20000, &mut rng));
+        assert!((prior.log_density(&[0.5, 1.0]) - (2f64.ln() - 1.0 + 0.1f64.ln() - 0.1)).abs() < 1e-12);
+        assert_eq!(prior.log_density(&[-0.5, 1.0]), f64::NEG_INFINITY);
+        // half the central 68% of Exp(rate): (ln 0.8413 - ln 0.1587) / (2 rate)
+        let expected = (0.8413f64.ln() - 0.1587f64.ln()) / 2.0;
+        for (scale, rate) in prior.scale().iter().zip([2.0, 0.1]) {
+            assert!((scale * rate / expected - 1.0).abs() < 0.15, "{}", scale);
+        }
+    }
+
+    #[test]
+    fn test_constraints_are_walls_of_a_renormalized_prior() {
+        let mut rng = StdRng::seed_from_u64(423);
+        let base: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 1.0]).unwrap());
+        // the half plane above the diagonal holds half the prior
+        let above: Constraint = Arc::new(|t: &[f64]| t[0] - t[1]);
+        let prior = Constrained::new(base.clone(), vec![("above".to_string(), above.clone())]).unwrap();
+        assert!((prior.log_volume() - 0.5f64.ln()).abs() < 0.02);
+        let mut theta = [0.0; 2];
+        for _ in 0..1000 {
+            prior.sample_into(&mut theta, &mut rng);
+            assert!(theta[0] <= theta[1]);
+        }
+        assert_eq!(prior.log_density(&[1.0, 0.0]), f64::NEG_INFINITY);
+        assert!((prior.log_density(&[0.0, 1.0]) - base.log_density(&[0.0, 1.0]) - 2f64.ln()).abs() < 0.02);
+        assert!(check_prior(&prior, 20000, &mut rng).is_empty());
+        let stats = prior.stats();
+        let fraction = stats.iter().find(|(name, _)| name == "prior_constraint_rejections").unwrap().1;
+        assert!(fraction > 0.3 && fraction < 0.7, "{}", fraction);
+
+        let nowhere: Constraint = Arc::new(|t: &[f64]| 1.0 + t[0].abs());
+        assert!(Constrained::new(base, vec![("nowhere".to_string(), nowhere)]).is_err());
+        register_constraint("test_above", above.clone()).unwrap();
+        assert!(register_constraint("test_above", above).is_err());
+        assert!(registered_constraint("test_above").is_ok());
+    }
+}
+
+
+/// the distribution new points are drawn from before the likelihood
+/// constraint is applied
+///
+/// The prior volume the evidence is integrated over is measured in this
+/// distribution, so `log_density` must be normalized.
+pub trait Prior: Debug + Send + Sync {
+    /// number of parameters
+    fn dim(&self) -> usize;
+
+    /// overwrite `theta` with an independent draw
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore);
+
+    /// normalized log density at theta
+    fn log_density(&self, theta: &[f64]) -> f64;
+
+    /// typical width of each parameter, for sizing proposals before there
+    /// are live points to measure
+    fn scale(&self) -> Vec<f64>;
+
+    /// prior-specific counters (e.g. constraint rejections) that are
+    /// copied into the run results
+    fn stats(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// the point that uniform coordinates u on the unit cube map to, for
+    /// priors defined by such a transform; `ns check` probes it at the
+    /// faces and corners of the cube
+    fn unit_transform(&self, _u: &[f64]) -> Option<Vec<f64>> {
+        None
+    }
+
+    /// an upper bound on `log_density` over all theta, for samplers that
+    /// weight uniform draws by the prior; None if the prior knows none
+    fn max_log_density(&self) -> Option<f64> {
+        None
+    }
+}
+
+
+/// independent normal priors, N(mu_i, sd_i^2) on parameter i
+#[derive(Debug, Clone)]
+pub struct NormalPrior {
+    sd: Vec<f64>,
+    dists: Vec<Normal>,
+}
+
+
+impl NormalPrior {
+    pub fn new(mu: &[f64], sd: &[f64]) -> Result<NormalPrior, Box<dyn Error>> {
+        if mu.len() != sd.len() {
+            return Err(format!("mu has {} entries but sd has {}", mu.len(), sd.len()).into())
+        }
+        let dists = zip(mu, sd)
+            .map(|(m, s)| Normal::new(*m, *s))
+            .collect::<Result<Vec<Normal>, _>>()?;
+        Ok(NormalPrior{ sd: sd.to_vec(), dists })
+    }
+}
+
+
+impl Prior for NormalPrior {
+    fn dim(&self) -> usize {
+        self.dists.len()
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        for (t, d) in theta.iter_mut().zip(&self.dists) {
+            *t = d.sample(rng);
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        theta.iter().zip(&self.dists).map(|(t, d)| d.ln_pdf(*t)).sum()
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.sd.clone()
+    }
+
+    fn unit_transform(&self, u: &[f64]) -> Option<Vec<f64>> {
+        Some(u.iter().zip(&self.dists).map(|(u, d)| d.inverse_cdf(*u)).collect())
+    }
+
+    /// the density at the means
+    fn max_log_density(&self) -> Option<f64> {
+        Some(self.sd.iter().map(|s| -0.5 * (2.0 * std::f64::consts::PI).ln() - s.ln()).sum())
+    }
+}
+
+
+/// the marginal distribution of one parameter under a copula prior; the
+/// gamma, beta and Student-t marginals need the `statrs` feature
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "dist", rename_all = "snake_case")]
+pub enum Marginal {
+    Normal{ mu: f64, sd: f64 },
+    /// exp of N(mu, sigma^2)
+    LogNormal{ mu: f64, sigma: f64 },
+    Uniform{ lower: f64, upper: f64 },
+    #[cfg(feature = "statrs")]
+    Gamma{ shape: f64, rate: f64 },
+    #[cfg(feature = "statrs")]
+    Beta{ a: f64, b: f64 },
+    #[cfg(feature = "statrs")]
+    StudentT{ location: f64, scale: f64, dof: f64 },
+    /// a weighted mixture, e.g. spike and slab: a narrow normal at zero
+    /// plus a broad uniform; weights need not sum to one
+    Mixture{ components: Vec<Component> },
+}
+
+
+/// one weighted component of a mixture marginal, written as the marginal
+/// with an extra `weight`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Component {
+    pub weight: f64,
+    #[serde(flatten)]
+    pub marginal: Marginal,
+}
+
+
+/// bisection steps when a quantile has no closed form, enough to reach
+/// machine precision from any bracket
+const QUANTILE_STEPS: usize = 200;
+
+
+impl Marginal {
+    /// an error naming the problem if the parameters are invalid
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        let ok = match *self {
+            Marginal::Normal{ mu, sd } => Normal::new(mu, sd).is_ok(),
+            Marginal::LogNormal{ mu, sigma } => Normal::new(mu, sigma).is_ok(),
+            Marginal::Uniform{ lower, upper } => lower.is_finite() && upper.is_finite() && lower < upper,
+            #[cfg(feature = "statrs")]
+            Marginal::Gamma{ shape, rate } => shape.is_finite() && rate.is_finite() && Gamma::new(shape, rate).is_ok(),
+            #[cfg(feature = "statrs")]
+            Marginal::Beta{ a, b } => a.is_finite() && b.is_finite() && Beta::new(a, b).is_ok(),
+            #[cfg(feature = "statrs")]
+            Marginal::StudentT{ location, scale, dof } => dof.is_finite() && StudentsT::new(location, scale, dof).is_ok(),
+            Marginal::Mixture{ ref components } => {
+                for c in components {
+                    c.marginal.check()?;
+                }
+                !components.is_empty() && components.iter().all(|c| c.weight.is_finite() && c.weight > 0.0)
+            },
+        };
+        if ok { Ok(()) } else { Err(format!("invalid marginal {:?}", self).into()) }
+    }
+
+    pub fn cdf(&self, x: f64) -> f64 {
+        match *self {
+            Marginal::Normal{ mu, sd } => Normal::new(mu, sd).map_or(f64::NAN, |d| d.cdf(x)),
+            Marginal::LogNormal{ .. } if x <= 0.0 => 0.0,
+            Marginal::LogNormal{ mu, sigma } => Normal::new(mu, sigma).map_or(f64::NAN, |d| d.cdf(x.ln())),
+            Marginal::Uniform{ lower, upper } => ((x - lower) / (upper - lower)).clamp(0.0, 1.0),
+            #[cfg(feature = "statrs")]
+            Marginal::Gamma{ shape, rate } => Gamma::new(shape, rate).map_or(f64::NAN, |d| d.cdf(x)),
+            #[cfg(feature = "statrs")]
+            Marginal::Beta{ a, b } => Beta::new(a, b).map_or(f64::NAN, |d| d.cdf(x)),
+            #[cfg(feature = "statrs")]
+            Marginal::StudentT{ location, scale, dof } => StudentsT::new(location, scale, dof).map_or(f64::NAN, |d| d.cdf(x)),
+            Marginal::Mixture{ ref components } => {
+                let total: f64 = components.iter().map(|c| c.weight).sum();
+                components.iter().map(|c| c.weight / total * c.marginal.cdf(x)).sum()
+            },
+        }
+    }
+
+    pub fn ln_pdf(&self, x: f64) -> f64 {
+        match *self {
+            Marginal::Normal{ mu, sd } => Normal::new(mu, sd).map_or(f64::NAN, |d| d.ln_pdf(x)),
+            Marginal::LogNormal{ .. } if x <= 0.0 => f64::NEG_INFINITY,
+            Marginal::LogNormal{ mu, sigma } => Normal::new(mu, sigma).map_or(f64::NAN, |d| d.ln_pdf(x.ln()) - x.ln()),
+            Marginal::Uniform{ lower, upper } if (lower..=upper).contains(&x) => -(upper - lower).ln(),
+            Marginal::Uniform{ .. } => f64::NEG_INFINITY,
+            #[cfg(feature = "statrs")]
+            Marginal::Gamma{ shape, rate } => Gamma::new(shape, rate).map_or(f64::NAN, |d| d.ln_pdf(x)),
+            #[cfg(feature = "statrs")]
+            Marginal::Beta{ a, b } => Beta::new(a, b).map_or(f64::NAN, |d| d.ln_pdf(x)),
+            #[cfg(feature = "statrs")]
+            Marginal::StudentT{ location, scale, dof } => StudentsT::new(location, scale, dof).map_or(f64::NAN, |d| d.ln_pdf(x)),
+            Marginal::Mixture{ ref components } => {
+                let total: f64 = components.iter().map(|c| c.weight).sum();
+                components.iter()
+                    .map(|c| (c.weight / total).ln() + c.marginal.ln_pdf(x))
+                    .fold(f64::NEG_INFINITY, log_add_exp)
+            },
+        }
+    }
+
+    /// the value below which a fraction p of the distribution lies, for p
+    /// strictly between 0 and 1
+    pub fn quantile(&self, p: f64) -> f64 {
+        let z = || Normal::new(0.0, 1.0).unwrap().inverse_cdf(p);
+        match *self {
+            Marginal::Normal{ mu, sd } => mu + sd * z(),
+            Marginal::LogNormal{ mu, sigma } => (mu + sigma * z()).exp(),
+            Marginal::Uniform{ lower, upper } => lower + p * (upper - lower),
+            #[cfg(feature = "statrs")]
+            Marginal::Gamma{ .. } => self.bisect(p, 0.0, f64::INFINITY),
+            #[cfg(feature = "statrs")]
+            Marginal::Beta{ .. } => self.bisect(p, 0.0, 1.0),
+            #[cfg(feature = "statrs")]
+            Marginal::StudentT{ .. } => self.bisect(p, f64::NEG_INFINITY, f64::INFINITY),
+            Marginal::Mixture{ .. } => self.bisect(p, f64::NEG_INFINITY, f64::INFINITY),
+        }
+    }
+
+    /// for a mixture, the probability that each component produced x, e.g.
+    /// the posterior odds of spike against slab averaged over the draws;
+    /// a single 1 for other marginals
+    pub fn responsibilities(&self, x: f64) -> Vec<f64> {
+        match self {
+            Marginal::Mixture{ components } => {
+                let log_p: Vec<f64> = components.iter().map(|c| c.weight.ln() + c.marginal.ln_pdf(x)).collect();
+                let log_total = log_p.iter().copied().fold(f64::NEG_INFINITY, log_add_exp);
+                log_p.iter().map(|lp| (lp - log_total).exp()).collect()
+            },
+            _ => vec![1.0],
+        }
+    }
+
+    /// invert the cdf by bisection on the support [lower, upper], growing
+    /// infinite ends until they bracket p
+    fn bisect(&self, p: f64, lower: f64, upper: f64) -> f64 {
+        let (mut lo, mut hi) = (lower.max(-1.0), upper.min(1.0));
+        while lo > lower && self.cdf(lo) > p {
+            lo = if lo < 0.0 { 2.0 * lo } else { lo - 1.0 };
+        }
+        while hi < upper && self.cdf(hi) < p {
+            hi = if hi > 0.0 { 2.0 * hi } else { hi + 1.0 };
+        }
+        for _ in 0..QUANTILE_STEPS {
+            let mid = 0.5 * (lo + hi);
+            if mid <= lo || mid >= hi {
+                break
+            }
+            if self.cdf(mid) < p { lo = mid } else { hi = mid }
+        }
+        0.5 * (lo + hi)
+    }
+}
+
+
+/// marginals per parameter tied together by a Gaussian copula, for
+/// correlated prior knowledge about parameters that are not normal
+///
+/// A draw is z ~ N(0, R) mapped through the standard normal cdf and the
+/// inverse cdf of each marginal, so each parameter keeps its marginal and
+/// R sets how they move together. The density is the product of the
+/// marginal densities times the copula density
+/// |R|^(-1/2) exp(-z'(R^-1 - I)z / 2), with z_i = Phi^-1(F_i(theta_i)).
+///
+/// Fields:
+/// marginals: the distribution of each parameter
+/// chol: Cholesky factor of the correlation matrix R
+#[derive(Debug, Clone)]
+pub struct CopulaPrior {
+    marginals: Vec<Marginal>,
+    chol: Cholesky,
+}
+
+
+/// the standard normal that links the marginals to the copula
+fn unit_normal() -> Normal {
+    Normal::new(0.0, 1.0).unwrap()
+}
+
+
+/// keeps cdf values off 0 and 1, whose normal quantiles are infinite
+fn clamp_probability(u: f64) -> f64 {
+    u.clamp(1e-16, 1.0 - 1e-16)
+}
+
+
+impl CopulaPrior {
+    /// an empty correlation is the identity, independent parameters
+    pub fn new(marginals: Vec<Marginal>, correlation: &[Vec<f64>]) -> Result<CopulaPrior, Box<dyn Error>> {
+        let k = marginals.len();
+        for marginal in &marginals {
+            marginal.check()?;
+        }
+        let correlation = match correlation.len() {
+            0 => Matrix::identity(k),
+            _ => Matrix::from_rows(correlation.to_vec()),
+        };
+        if correlation.rows() != k || correlation.cols() != k {
+            return Err(format!("{} marginals need a {} x {} correlation matrix", k, k, k).into())
+        }
+        for i in 0..k {
+            if (correlation[(i, i)] - 1.0).abs() > 1e-12 {
+                return Err(format!("the correlation matrix has {} on its diagonal at {}", correlation[(i, i)], i).into())
+            }
+            for j in 0..i {
+                if correlation[(i, j)] != correlation[(j, i)] {
+                    return Err(format!("the correlation matrix is not symmetric at ({}, {})", i, j).into())
+                }
+            }
+        }
+        let chol = correlation.cholesky().ok_or("the correlation matrix is not positive definite")?;
+        Ok(CopulaPrior{ marginals, chol })
+    }
+}
+
+
+impl Prior for CopulaPrior {
+    fn dim(&self) -> usize {
+        self.marginals.len()
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        let unit = unit_normal();
+        let u: Vec<f64> = (0..theta.len()).map(|_| unit.sample(rng)).collect();
+        let z = self.chol.l().mul_vec(&u);
+        for ((t, z), marginal) in theta.iter_mut().zip(z).zip(&self.marginals) {
+            *t = marginal.quantile(clamp_probability(unit.cdf(z)));
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        let unit = unit_normal();
+        let mut log_marginals = 0.0;
+        let mut z = Vec::with_capacity(theta.len());
+        for (t, marginal) in theta.iter().zip(&self.marginals) {
+            let ln_pdf = marginal.ln_pdf(*t);
+            if !ln_pdf.is_finite() {
+                return f64::NEG_INFINITY
+            }
+            log_marginals += ln_pdf;
+            z.push(unit.inverse_cdf(clamp_probability(marginal.cdf(*t))));
+        }
+        let zz: f64 = z.iter().map(|z| z * z).sum();
+        log_marginals - 0.5 * (self.chol.quad_form(&z) - zz + self.chol.ln_det())
+    }
+
+    /// half the width of each marginal's central 68% interval, the sd for
+    /// normal marginals
+    fn scale(&self) -> Vec<f64> {
+        let (lo, hi) = (unit_normal().cdf(-1.0), unit_normal().cdf(1.0));
+        self.marginals.iter().map(|m| 0.5 * (m.quantile(hi) - m.quantile(lo))).collect()
+    }
+
+    /// correlates the normal scores of u, so parameter i moves with u_i
+    /// and the u_j before it
+    fn unit_transform(&self, u: &[f64]) -> Option<Vec<f64>> {
+        let unit = unit_normal();
+        let scores: Vec<f64> = u.iter().map(|u| unit.inverse_cdf(clamp_probability(*u))).collect();
+        let z = self.chol.l().mul_vec(&scores);
+        Some(z.iter().zip(&self.marginals).map(|(z, m)| m.quantile(clamp_probability(unit.cdf(*z)))).collect())
+    }
+}
+
+
+/// a copula prior as written in the config
+///
+/// Fields:
+/// marginals: one distribution per parameter, e.g.
+///     `{ dist = "gamma", shape = 2.0, rate = 1.0 }`, or a mixture
+///     `{ dist = "mixture", components = [{ weight = 0.5, dist = ... }, ...] }`
+/// correlation: correlation matrix of the Gaussian copula, one row per
+///     parameter; independent parameters if empty
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CopulaConfig {
+    pub marginals: Vec<Marginal>,
+    pub correlation: Vec<Vec<f64>>,
+}
+
+
+/// log density of a prior defined in code
+type LogDensityFn = dyn Fn(&[f64]) -> f64 + Send + Sync;
+
+
+/// overwrites theta with a draw from a prior defined in code
+type SampleFn = dyn Fn(&mut [f64], &mut dyn RngCore) + Send + Sync;
+
+
+/// maps the unit cube onto a prior defined in code
+type TransformFn = dyn Fn(&[f64], &mut [f64]) + Send + Sync;
+
+
+/// draws used to estimate the typical width of a prior defined in code
+const SCALE_DRAWS: usize = 1000;
+
+
+/// a prior given by closures for its log density and for drawing from it
+///
+/// Nothing ties the two closures together, so `check_prior` (run by
+/// `ns check` for registered priors) tests that they describe the same
+/// normalized distribution.
+///
+/// Fields:
+/// dim: number of parameters
+/// log_density: normalized log density
+/// sample: fills theta with an independent draw
+/// transform: the map from the unit cube, for priors built from one
+/// scale: per-parameter standard deviation, estimated from draws
+pub struct ClosurePrior {
+    dim: usize,
+    log_density: Box<LogDensityFn>,
+    sample: Box<SampleFn>,
+    transform: Option<Arc<TransformFn>>,
+    scale: Vec<f64>,
+}
+
+
+impl ClosurePrior {
+    pub fn new<D, S>(dim: usize, sample: S, log_density: D) -> ClosurePrior
+    where
+        D: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+        S: Fn(&mut [f64], &mut dyn RngCore) + Send + Sync + 'static,
+    {
+        let mut prior = ClosurePrior{
+            dim,
+            log_density: Box::new(log_density),
+            sample: Box::new(sample),
+            transform: None,
+            scale: Vec::new(),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let draws = prior.draws(SCALE_DRAWS, &mut rng);
+        let (_, cov) = mean_cov(draws.iter().map(|d| d.as_slice()));
+        prior.scale = (0..dim).map(|j| cov[(j, j)].sqrt()).collect();
+        prior
+    }
+
+    /// a prior given by the transform of uniform draws on the unit cube,
+    /// e.g. the inverse cdf of each parameter
+    pub fn from_inverse_cdf<D, T>(dim: usize, transform: T, log_density: D) -> ClosurePrior
+    where
+        D: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+        T: Fn(&[f64], &mut [f64]) + Send + Sync + 'static,
+    {
+        let transform: Arc<TransformFn> = Arc::new(transform);
+        let map = Arc::clone(&transform);
+        let sample = move |theta: &mut [f64], rng: &mut dyn RngCore| {
+            let u: Vec<f64> = (0..theta.len()).map(|_| rng.gen()).collect();
+            map(&u, theta);
+        };
+        ClosurePrior{ transform: Some(transform), ..ClosurePrior::new(dim, sample, log_density) }
+    }
+
+    fn draws(&self, n: usize, rng: &mut dyn RngCore) -> Vec<Vec<f64>> {
+        (0..n).map(|_| {
+            let mut theta = vec![0.0; self.dim];
+            (self.sample)(&mut theta, rng);
+            theta
+        }).collect()
+    }
+}
+
+
+impl Debug for ClosurePrior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClosurePrior").field("dim", &self.dim).field("scale", &self.scale).finish()
+    }
+}
+
+
+impl Prior for ClosurePrior {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        (self.sample)(theta, rng)
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        (self.log_density)(theta)
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.scale.clone()
+    }
+
+    fn unit_transform(&self, u: &[f64]) -> Option<Vec<f64>> {
+        let transform = self.transform.as_ref()?;
+        let mut theta = vec![0.0; self.dim];
+        transform(u, &mut theta);
+        Some(theta)
+    }
+}
+
+
+/// the prior of a single parameter
+///
+/// With the `rv` feature, every `rv` distribution over f64 is one, so
+/// rv's catalog can be used as is, one distribution per parameter of an
+/// `IndependentPrior`.
+pub trait Univariate: Debug + Send + Sync {
+    /// normalized log density at x
+    fn log_density(&self, x: f64) -> f64;
+
+    /// an independent draw
+    fn sample(&self, rng: &mut dyn RngCore) -> f64;
+}
+
+
+#[cfg(feature = "rv")]
+impl<D: Rv<f64> + Debug + Send + Sync> Univariate for D {
+    fn log_density(&self, x: f64) -> f64 {
+        self.ln_f(&x)
+    }
+
+    fn sample(&self, mut rng: &mut dyn RngCore) -> f64 {
+        self.draw(&mut rng)
+    }
+}
+
+
+/// independent priors on the parameters, one `Univariate` each, e.g. rv
+/// distributions:
+///
+/// ```ignore
+/// let prior = IndependentPrior::new(vec![
+///     Box::new(rv::dist::Gaussian::new(0.0, 1.0)?),
+///     Box::new(rv::dist::Gamma::new(2.0, 1.0)?),
+/// ]);
+/// ```
+///
+/// Fields:
+/// params: the prior of each parameter
+/// scale: half the width of the central 68% of each prior, estimated from
+///     draws; the sd for normal priors, and finite for heavy tails too
+#[derive(Debug)]
+pub struct IndependentPrior {
+    params: Vec<Box<dyn Univariate>>,
+    scale: Vec<f64>,
+}
+
+
+impl IndependentPrior {
+    pub fn new(params: Vec<Box<dyn Univariate>>) -> IndependentPrior {
+        let mut rng = StdRng::seed_from_u64(0);
+        let scale = params.iter()
+            .map(|param| {
+                let mut draws: Vec<f64> = (0..SCALE_DRAWS).map(|_| param.sample(&mut rng)).collect();
+                draws.sort_by(f64::total_cmp);
+                let at = |p: f64| draws[((p * SCALE_DRAWS as f64) as usize).min(SCALE_DRAWS - 1)];
+                0.5 * (at(0.8413) - at(0.1587))
+            })
+            .collect();
+        IndependentPrior{ params, scale }
+    }
+}
+
+
+impl Prior for IndependentPrior {
+    fn dim(&self) -> usize {
+        self.params.len()
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        for (t, param) in theta.iter_mut().zip(&self.params) {
+            *t = param.sample(rng);
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        theta.iter().zip(&self.params).map(|(t, param)| param.log_density(*t)).sum()
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.scale.clone()
+    }
+}
+
+
+/// priors selectable from configs as `registered_prior = "name"`
+fn prior_registry() -> &'static RwLock<HashMap<String, Arc<dyn Prior>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Arc<dyn Prior>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+
+/// make a prior built in code, e.g. a `ClosurePrior`, selectable from
+/// configs as `registered_prior = "name"`
+pub fn register_prior(name: &str, prior: Arc<dyn Prior>) -> Result<(), Box<dyn Error>> {
+    let mut priors = prior_registry().write().map_err(|_| "the prior registry is poisoned")?;
+    if priors.contains_key(name) {
+        return Err(format!("a prior named {:?} is already registered", name).into())
+    }
+    priors.insert(name.to_string(), prior);
+    Ok(())
+}
+
+
+/// the prior registered as `name`
+pub fn registered_prior(name: &str) -> Result<Arc<dyn Prior>, Box<dyn Error>> {
+    let priors = prior_registry().read().map_err(|_| "the prior registry is poisoned")?;
+    priors.get(name).cloned().ok_or_else(|| format!("no prior registered as {:?}", name).into())
+}
+
+
+/// Monte Carlo check that a prior's sampler and density agree, returning
+/// what is wrong, if anything
+///
+/// The draws must have finite density, and importance sampling from a
+/// Gaussian twice as wide as the draws must find that the density
+/// integrates to one and has the same mean as the draws. This catches
+/// the usual slips: a density without its normalizing constant, a rate
+/// used where a scale was meant, or a sampler that ignores a bound the
+/// density enforces.
+pub fn check_prior(prior: &dyn Prior, draws: usize, rng: &mut dyn RngCore) -> Vec<String> {
+    let mut problems = Vec::new();
+    let dim = prior.dim();
+    let n = draws.max(100);
+    let points: Vec<Vec<f64>> = (0..n).map(|_| {
+        let mut theta = vec![0.0; dim];
+        prior.sample_into(&mut theta, rng);
+        theta
+    }).collect();
+    let outside = points.iter().filter(|t| !prior.log_density(t).is_finite()).count();
+    if outside > 0 {
+        problems.push(format!("{} of {} draws from the prior have zero or invalid density", outside, n));
+    }
+
+    let (mean, cov) = mean_cov(points.iter().map(|p| p.as_slice()));
+    let sd: Vec<f64> = (0..dim).map(|j| cov[(j, j)].sqrt()).collect();
+    let chol = match cov.scale(4.0).cholesky() {
+        Some(chol) => chol,
+        None => {
+            problems.push("the draws from the prior are degenerate (their covariance is singular)".to_string());
+            return problems
+        },
+    };
+    let unit = Normal::new(0.0, 1.0).unwrap();
+    let ln_norm = -0.5 * (dim as f64 * (2.0 * PI).ln() + chol.ln_det());
+    let mut weights = Vec::with_capacity(n);
+    let mut weighted_sum = vec![0.0; dim];
+    for _ in 0..n {
+        let u: Vec<f64> = (0..dim).map(|_| unit.sample(rng)).collect();
+        let z = chol.l().mul_vec(&u);
+        let x: Vec<f64> = mean.iter().zip(&z).map(|(m, z)| m + z).collect();
+        let log_g = ln_norm - 0.5 * u.iter().map(|u| u * u).sum::<f64>();
+        let log_p = prior.log_density(&x);
+        let w = if log_p.is_nan() { 0.0 } else { (log_p - log_g).exp() };
+        for (s, x) in weighted_sum.iter_mut().zip(&x) {
+            *s += w * x;
+        }
+        weights.push(w);
+    }
+    let total: f64 = weights.iter().sum();
+    let z = total / n as f64;
+    let z_err = (weights.iter().map(|w| (w - z).powi(2)).sum::<f64>() / (n * (n - 1)) as f64).sqrt();
+    if (z - 1.0).abs() > 0.05 && (z - 1.0).abs() > 4.0 * z_err {
+        problems.push(format!(
+            "the prior density integrates to {:.3} +/- {:.3} instead of 1; is it missing its normalizing constant?",
+            z, z_err,
+        ));
+    }
+    if total > 0.0 {
+        for j in 0..dim {
+            let implied = weighted_sum[j] / total;
+            if (implied - mean[j]).abs() > 0.1 * sd[j] {
+                problems.push(format!(
+                    "parameter {}: the draws have mean {:.4} but the density implies {:.4}; the sampler and the density disagree",
+                    j, mean[j], implied,
+                ));
+            }
+        }
+    }
+    problems
+}
+
+
+/// an inequality constraint g(theta) <= 0 on the parameters
+pub type Constraint = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+
+/// draws used to measure the prior volume inside constraints
+const VOLUME_DRAWS: usize = 100_000;
+
+
+/// a prior restricted to where every constraint g(theta) <= 0 holds, e.g.
+/// the stability region of a dynamical model
+///
+/// Outside the constraints the density is zero, so the samplers reject
+/// steps there as they reject steps below the likelihood contour, and
+/// never spend a likelihood evaluation on them. Inside, the density is the
+/// prior's divided by the volume the constraints leave, measured once from
+/// prior draws, so that the evidence is that of the restricted prior.
+///
+/// Fields:
+/// prior: the unrestricted prior
+/// constraints: named constraint functions
+/// log_volume: log of the prior mass inside the constraints
+/// checked: points tested against the constraints
+/// rejected: points that violated one of them
+pub struct Constrained {
+    prior: Arc<dyn Prior>,
+    constraints: Vec<(String, Constraint)>,
+    log_volume: f64,
+    checked: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+
+impl Constrained {
+    pub fn new(prior: Arc<dyn Prior>, constraints: Vec<(String, Constraint)>) -> Result<Constrained, Box<dyn Error>> {
+        let mut constrained = Constrained{
+            prior,
+            constraints,
+            log_volume: 0.0,
+            checked: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut theta = vec![0.0; constrained.dim()];
+        let inside = (0..VOLUME_DRAWS)
+            .filter(|_| {
+                constrained.prior.sample_into(&mut theta, &mut rng);
+                constrained.satisfied(&theta)
+            })
+            .count();
+        if inside == 0 {
+            let names: Vec<&str> = constrained.constraints.iter().map(|(n, _)| n.as_str()).collect();
+            return Err(format!(
+                "none of {} prior draws satisfies the constraints {}",
+                VOLUME_DRAWS, names.join(", "),
+            ).into())
+        }
+        constrained.log_volume = (inside as f64 / VOLUME_DRAWS as f64).ln();
+        constrained.checked.store(0, Ordering::Relaxed);
+        constrained.rejected.store(0, Ordering::Relaxed);
+        Ok(constrained)
+    }
+
+    /// log of the prior mass inside the constraints
+    pub fn log_volume(&self) -> f64 {
+        self.log_volume
+    }
+
+    fn satisfied(&self, theta: &[f64]) -> bool {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        // NaN counts as a violation
+        let ok = self.constraints.iter().all(|(_, g)| g(theta) <= 0.0);
+        if !ok {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        ok
+    }
+}
+
+
+impl Debug for Constrained {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.constraints.iter().map(|(n, _)| n.as_str()).collect();
+        f.debug_struct("Constrained")
+            .field("prior", &self.prior)
+            .field("constraints", &names)
+            .field("log_volume", &self.log_volume)
+            .finish()
+    }
+}
+
+
+impl Prior for Constrained {
+    fn dim(&self) -> usize {
+        self.prior.dim()
+    }
+
+    /// rejection sampling from the unrestricted prior; the constraints
+    /// leave enough volume for it, as `new` checked
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        loop {
+            self.prior.sample_into(theta, rng);
+            if self.satisfied(theta) {
+                return
+            }
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        if self.satisfied(theta) {
+            self.prior.log_density(theta) - self.log_volume
+        } else {
+            f64::NEG_INFINITY
+        }
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.prior.scale()
+    }
+
+    /// the share of tested points, prior draws and sampler steps alike,
+    /// that the constraints rejected
+    fn stats(&self) -> Vec<(String, f64)> {
+        let checked = self.checked.load(Ordering::Relaxed);
+        let rejected = self.rejected.load(Ordering::Relaxed);
+        let mut stats = self.prior.stats();
+        stats.push(("prior_constraint_checks".to_string(), checked as f64));
+        stats.push(("prior_constraint_rejections".to_string(), rejected as f64 / checked.max(1) as f64));
+        stats.push(("prior_constrained_volume".to_string(), self.log_volume.exp()));
+        stats
+    }
+
+    fn max_log_density(&self) -> Option<f64> {
+        self.prior.max_log_density().map(|m| m - self.log_volume)
+    }
+}
+
+
+/// constraints selectable from configs as `constraints = ["name", ...]`
+fn constraint_registry() -> &'static RwLock<HashMap<String, Constraint>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Constraint>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+
+/// make a constraint g(theta) <= 0 selectable from configs by name
+pub fn register_constraint(name: &str, constraint: Constraint) -> Result<(), Box<dyn Error>> {
+    let mut constraints = constraint_registry().write().map_err(|_| "the constraint registry is poisoned")?;
+    if constraints.contains_key(name) {
+        return Err(format!("a constraint named {:?} is already registered", name).into())
+    }
+    constraints.insert(name.to_string(), constraint);
+    Ok(())
+}
+
+
+/// the constraint registered as `name`
+pub fn registered_constraint(name: &str) -> Result<Constraint, Box<dyn Error>> {
+    let constraints = constraint_registry().read().map_err(|_| "the constraint registry is poisoned")?;
+    constraints.get(name).cloned().ok_or_else(|| format!("no constraint registered as {:?}", name).into())
+}
@@ -0,0 +1,345 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+
+use crate::data::Dataset;
+use crate::dynamic::DeadPoint;
+use crate::models::LogLikelihood;
+use crate::output::read_dead_birth;
+use crate::rundir::RunDir;
+use crate::{checked_model, Config};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct Banana;
+
+    impl LogLikelihood for Banana {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            // correlated Gaussian in (a, b - a^2): the profile over a is
+            // flat in b, the slice at the best fit is not
+            let (a, b) = (theta[0], theta[1] - theta[0] * theta[0]);
+            -0.5 * (a * a / 0.25 + b * b / 0.01)
+        }
+
+        fn dim(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn test_profile_maximizes_over_the_rest() {
+        let mut rng = StdRng::seed_from_u64(415);
+        let points: Vec<DeadPoint> = (0..400)
+            .map(|_| {
+                let theta = vec![rng.gen_range(-2.0..2.0), rng.gen_range(-1.0..4.0)];
+                DeadPoint{ log_l: Banana.log_lik(&theta), theta, log_l_birth: f64::NEG_INFINITY }
+            })
+            .collect();
+        let settings = ProfileConfig::default();
+        let values = [-1.0, 0.0, 1.0];
+        let profile = profile(&Banana, &points, 0, &values, &settings);
+        for (point, a) in profile.iter().zip(values) {
+            // maximizing over b puts it on the ridge b = a^2
+            assert_eq!(point.value, a);
+            assert!((point.log_l + 0.5 * a * a / 0.25).abs() < 1e-4);
+            assert!((point.theta[1] - a * a).abs() < 1e-2);
+        }
+
+        let best = best_fit(&Banana, &points, &settings).unwrap();
+        assert!(best.1 > -1e-4);
+        let slice = conditional_slice(&Banana, &best.0, 0, &values);
+        assert!(slice[0].log_l < profile[0].log_l - 1.0);
+        assert!((slice[1].log_l - profile[1].log_l).abs() < 1e-3);
+
+        let values = grid(&points, 0, &ProfileConfig{ points: 5, ..settings });
+        assert_eq!(values.len(), 5);
+        assert!(values[0] < -1.0 && values[4] > 1.0);
+    }
+}
+
+
+/// settings of the profile likelihood
+///
+/// Fields:
+/// points: grid values of each profiled parameter
+/// seeds: dead points nearest to each grid value that the maximization
+///     starts from
+/// max_evals: likelihood evaluations allowed per maximization
+/// band: the grid spans the dead points whose log-likelihood is within
+///     this of the best one
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub points: usize,
+    pub seeds: usize,
+    pub max_evals: usize,
+    pub band: f64,
+}
+
+
+impl Default for ProfileConfig {
+    fn default() -> ProfileConfig {
+        ProfileConfig{ points: 25, seeds: 5, max_evals: 2000, band: 8.0 }
+    }
+}
+
+
+/// the likelihood at one grid value of a profiled parameter
+///
+/// Fields:
+/// value: the grid value
+/// log_l: the profile (or slice) log-likelihood there
+/// theta: the parameters it was reached at
+#[derive(Debug, Clone)]
+pub struct ProfilePoint {
+    pub value: f64,
+    pub log_l: f64,
+    pub theta: Vec<f64>,
+}
+
+
+/// profile and conditional slice of one parameter on the same grid
+///
+/// Fields:
+/// param: index of the parameter
+/// profile: maximum over the other parameters at each grid value
+/// slice: likelihood with the other parameters at the best fit
+#[derive(Debug, Clone)]
+pub struct ParameterProfile {
+    pub param: usize,
+    pub profile: Vec<ProfilePoint>,
+    pub slice: Vec<ProfilePoint>,
+}
+
+
+/// `settings.points` evenly spaced values of parameter `param` over the
+/// dead points within `settings.band` of the best log-likelihood
+pub fn grid(points: &[DeadPoint], param: usize, settings: &ProfileConfig) -> Vec<f64> {
+    let best = points.iter().map(|p| p.log_l).fold(f64::NEG_INFINITY, f64::max);
+    let (lo, hi) = points.iter()
+        .filter(|p| p.log_l >= best - settings.band)
+        .map(|p| p.theta[param])
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    match settings.points {
+        0 => Vec::new(),
+        1 => vec![0.5 * (lo + hi)],
+        n => (0..n).map(|k| lo + (hi - lo) * k as f64 / (n - 1) as f64).collect(),
+    }
+}
+
+
+/// profile and conditional slice of each parameter in `params` from the
+/// dead points of the finished run in `dir`, on the model its config
+/// describes
+pub fn profile_run(
+        config: &Config,
+        dir: &RunDir,
+        params: &[usize],
+        settings: &ProfileConfig,
+) -> Result<Vec<ParameterProfile>, Box<dyn Error>> {
+    let data = Dataset::load(&config.data_file)?;
+    let model = checked_model(config, &data)?;
+    let points = read_dead_birth(&dir.dead_birth_file(), model.dim())?;
+    if let Some(&j) = params.iter().find(|&&j| j >= model.dim()) {
+        return Err(format!("parameter {} does not exist; the model has {}", j, model.dim()).into())
+    }
+    let (best, _) = best_fit(model.as_ref(), &points, settings).ok_or("the run has no finite dead points")?;
+    Ok(params.iter()
+        .map(|&param| {
+            let grid = grid(&points, param, settings);
+            ParameterProfile{
+                param,
+                profile: profile(model.as_ref(), &points, param, &grid, settings),
+                slice: conditional_slice(model.as_ref(), &best, param, &grid),
+            }
+        })
+        .collect())
+}
+
+
+/// the highest likelihood found by polishing the best few dead points
+pub fn best_fit(
+        model: &dyn LogLikelihood,
+        points: &[DeadPoint],
+        settings: &ProfileConfig,
+) -> Option<(Vec<f64>, f64)> {
+    let mut ranked: Vec<&DeadPoint> = points.iter().filter(|p| p.log_l.is_finite()).collect();
+    ranked.sort_unstable_by_key(|p| std::cmp::Reverse(OrderedFloat(p.log_l)));
+    let dim = model.dim();
+    let free: Vec<usize> = (0..dim).collect();
+    let steps = spreads(points, dim);
+    ranked.iter()
+        .take(settings.seeds.max(1))
+        .map(|p| maximize(model, &p.theta, &free, &steps, settings.max_evals))
+        .max_by_key(|(_, ll)| OrderedFloat(*ll))
+}
+
+
+/// the profile log-likelihood of parameter `param` at each grid value:
+/// the maximum over every other parameter, found by Nelder-Mead started
+/// from the dead points nearest to the grid value
+pub fn profile(
+        model: &dyn LogLikelihood,
+        points: &[DeadPoint],
+        param: usize,
+        grid: &[f64],
+        settings: &ProfileConfig,
+) -> Vec<ProfilePoint> {
+    let dim = model.dim();
+    let free: Vec<usize> = (0..dim).filter(|&j| j != param).collect();
+    let steps = spreads(points, dim);
+    grid.iter()
+        .map(|&value| {
+            let mut nearest: Vec<&DeadPoint> = points.iter().filter(|p| p.log_l.is_finite()).collect();
+            nearest.sort_unstable_by_key(|p| OrderedFloat((p.theta[param] - value).abs()));
+            let (theta, log_l) = nearest.iter()
+                .take(settings.seeds.max(1))
+                .map(|p| {
+                    let mut start = p.theta.clone();
+                    start[param] = value;
+                    maximize(model, &start, &free, &steps, settings.max_evals)
+                })
+                .max_by_key(|(_, ll)| OrderedFloat(*ll))
+                .unwrap_or_else(|| (Vec::new(), f64::NEG_INFINITY));
+            ProfilePoint{ value, log_l, theta }
+        })
+        .collect()
+}
+
+
+/// the log-likelihood along parameter `param` with every other parameter
+/// held at `at`, usually the best fit
+pub fn conditional_slice(
+        model: &dyn LogLikelihood,
+        at: &[f64],
+        param: usize,
+        grid: &[f64],
+) -> Vec<ProfilePoint> {
+    grid.iter()
+        .map(|&value| {
+            let mut theta = at.to_vec();
+            theta[param] = value;
+            ProfilePoint{ value, log_l: model.log_lik(&theta), theta }
+        })
+        .collect()
+}
+
+
+/// profiles as CSV, one row per grid value: parameter, value, profile and
+/// slice log-likelihood, then the parameters the profile maximum was
+/// reached at
+pub fn write_profiles(path: &Path, profiles: &[ParameterProfile]) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let dim = profiles.iter().flat_map(|p| &p.profile).map(|p| p.theta.len()).max().unwrap_or(0);
+    let theta: Vec<String> = (0..dim).map(|j| format!("theta{}", j)).collect();
+    writeln!(out, "parameter,value,profile_log_l,slice_log_l,{}", theta.join(","))?;
+    for profile in profiles {
+        for (p, s) in profile.profile.iter().zip(&profile.slice) {
+            let theta: Vec<String> = p.theta.iter().map(|v| v.to_string()).collect();
+            writeln!(out, "{},{},{},{},{}", profile.param, p.value, p.log_l, s.log_l, theta.join(","))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+
+/// sd of each parameter over the dead points, the initial simplex size
+fn spreads(points: &[DeadPoint], dim: usize) -> Vec<f64> {
+    let n = points.len().max(1) as f64;
+    (0..dim)
+        .map(|j| {
+            let mean = points.iter().map(|p| p.theta[j]).sum::<f64>() / n;
+            let var = points.iter().map(|p| (p.theta[j] - mean).powi(2)).sum::<f64>() / n;
+            // a tenth of the spread starts the simplex inside the bulk
+            (0.1 * var.sqrt()).max(1e-8)
+        })
+        .collect()
+}
+
+
+/// maximize the likelihood over the coordinates `free` of `start` by
+/// Nelder-Mead, leaving the others where they are
+fn maximize(
+        model: &dyn LogLikelihood,
+        start: &[f64],
+        free: &[usize],
+        steps: &[f64],
+        max_evals: usize,
+) -> (Vec<f64>, f64) {
+    let k = free.len();
+    let eval = |x: &[f64]| {
+        let mut theta = start.to_vec();
+        for (&j, v) in free.iter().zip(x) {
+            theta[j] = *v;
+        }
+        let ll = model.log_lik(&theta);
+        (theta, if ll.is_nan() { f64::NEG_INFINITY } else { ll })
+    };
+    if k == 0 {
+        return eval(&[])
+    }
+
+    let x0: Vec<f64> = free.iter().map(|&j| start[j]).collect();
+    let mut simplex: Vec<(Vec<f64>, f64)> = Vec::with_capacity(k + 1);
+    simplex.push((x0.clone(), eval(&x0).1));
+    for (i, &j) in free.iter().enumerate() {
+        let mut x = x0.clone();
+        x[i] += steps[j];
+        let ll = eval(&x).1;
+        simplex.push((x, ll));
+    }
+    let mut evals = k + 1;
+    // standard coefficients: reflection 1, expansion 2, contraction and shrink 1/2
+    while evals < max_evals {
+        simplex.sort_unstable_by_key(|(_, ll)| std::cmp::Reverse(OrderedFloat(*ll)));
+        let (best, worst) = (simplex[0].1, simplex[k].1);
+        if best.is_finite() && (best - worst).abs() < 1e-10 * (1.0 + best.abs()) {
+            break
+        }
+        let centroid: Vec<f64> = (0..k)
+            .map(|i| simplex[..k].iter().map(|(x, _)| x[i]).sum::<f64>() / k as f64)
+            .collect();
+        let towards = |t: f64| -> Vec<f64> {
+            centroid.iter().zip(&simplex[k].0).map(|(c, w)| c + t * (w - c)).collect()
+        };
+        let reflected = towards(-1.0);
+        let r = eval(&reflected).1;
+        evals += 1;
+        if r > best {
+            let expanded = towards(-2.0);
+            let e = eval(&expanded).1;
+            evals += 1;
+            simplex[k] = if e > r { (expanded, e) } else { (reflected, r) };
+        } else if r > simplex[k - 1].1 {
+            simplex[k] = (reflected, r);
+        } else {
+            let contracted = if r > worst { towards(-0.5) } else { towards(0.5) };
+            let c = eval(&contracted).1;
+            evals += 1;
+            if c > worst.max(r) {
+                simplex[k] = (contracted, c);
+            } else {
+                let top = simplex[0].0.clone();
+                for (x, ll) in simplex.iter_mut().skip(1) {
+                    for (v, t) in x.iter_mut().zip(&top) {
+                        *v = t + 0.5 * (*v - t);
+                    }
+                    *ll = eval(x).1;
+                }
+                evals += k;
+            }
+        }
+    }
+    let (x, _) = simplex.into_iter().max_by_key(|(_, ll)| OrderedFloat(*ll)).unwrap();
+    eval(&x)
+}
@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use rand::Rng;
+
+use crate::data::Dataset;
+use crate::observer::{Collect, Observer};
+use crate::stats::weighted_quantile;
+use crate::{run_with_data, Config};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::sampler::{Method, SamplerConfig};
+
+    #[test]
+    fn test_pooled_intervals_and_scatter() {
+        let x: Vec<f64> = (0..20).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let config = Config{
+            sample_num: 300,
+            particle_num: 30,
+            mu: vec![0.0, 0.0],
+            sd: vec![5.0, 5.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let settings = ReplicateConfig{ runs: 4, level: 0.9 };
+        let mut rng = StdRng::seed_from_u64(419);
+        let report = replicate(&config, &settings, &data, &mut Collect::default(), &mut rng).unwrap();
+        assert_eq!(report.log_z.len(), 4);
+        assert!(report.log_z_scatter > 0.0);
+        assert_eq!(report.parameters.len(), 2);
+        let slope = &report.parameters[1];
+        assert!(slope.lower < 2.0 && 2.0 < slope.upper, "{:?}", slope);
+        assert!(slope.lower < slope.median && slope.median < slope.upper);
+        assert!(slope.lower_sd > 0.0 && slope.wide_lower() < slope.lower && slope.wide_upper() > slope.upper);
+
+        assert!(replicate(&config, &ReplicateConfig{ runs: 1, ..settings }, &data, &mut Collect::default(), &mut rng).is_err());
+    }
+}
+
+
+/// settings of replicated runs
+///
+/// Fields:
+/// runs: independent runs of the config; lower its particle_num to keep
+///     the cost of all of them near that of one long run
+/// level: probability inside the central credible intervals
+#[derive(Debug, Clone)]
+pub struct ReplicateConfig {
+    pub runs: usize,
+    pub level: f64,
+}
+
+
+impl Default for ReplicateConfig {
+    fn default() -> ReplicateConfig {
+        ReplicateConfig{ runs: 8, level: 0.9 }
+    }
+}
+
+
+/// a parameter's posterior summary from the pooled runs, with the spread
+/// of each number between runs
+///
+/// Fields:
+/// param: index of the parameter
+/// mean, median, lower, upper: posterior mean, median and central
+///     interval ends of the runs pooled with equal weight
+/// mean_sd, median_sd, lower_sd, upper_sd: standard deviations of the
+///     same numbers computed from each run alone
+#[derive(Debug, Clone)]
+pub struct ParameterSpread {
+    pub param: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub mean_sd: f64,
+    pub median_sd: f64,
+    pub lower_sd: f64,
+    pub upper_sd: f64,
+}
+
+
+impl ParameterSpread {
+    /// the interval widened by the scatter of its ends between runs, for
+    /// an uncertainty that includes how much a single run can be off
+    pub fn wide_lower(&self) -> f64 {
+        self.lower - self.lower_sd
+    }
+
+    pub fn wide_upper(&self) -> f64 {
+        self.upper + self.upper_sd
+    }
+}
+
+
+/// the outcome of replicated runs
+///
+/// Fields:
+/// log_z: log evidence of each run
+/// log_z_err: the sqrt(H / N) error each run reported
+/// log_z_scatter: standard deviation of log_z between runs, the error of
+///     a single run as observed rather than estimated
+/// parameters: per parameter, the pooled summaries and their scatter
+#[derive(Debug, Clone)]
+pub struct ReplicateReport {
+    pub log_z: Vec<f64>,
+    pub log_z_err: Vec<f64>,
+    pub log_z_scatter: f64,
+    pub parameters: Vec<ParameterSpread>,
+}
+
+
+impl ReplicateReport {
+    /// mean log evidence of the runs
+    pub fn mean_log_z(&self) -> f64 {
+        self.log_z.iter().sum::<f64>() / self.log_z.len() as f64
+    }
+}
+
+
+/// sample mean and standard deviation
+fn mean_sd(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    (mean, var.sqrt())
+}
+
+
+/// mean, median and central interval ends of weighted values
+fn summarize(points: &[(f64, f64)], level: f64) -> [f64; 4] {
+    let total: f64 = points.iter().map(|(_, w)| w).sum();
+    let mean = points.iter().map(|(v, w)| v * w).sum::<f64>() / total;
+    let tail = 0.5 * (1.0 - level);
+    [mean, weighted_quantile(points, 0.5), weighted_quantile(points, tail), weighted_quantile(points, 1.0 - tail)]
+}
+
+
+/// run the config `settings.runs` times on `data` and summarize the
+/// posteriors with the scatter between runs
+///
+/// The sqrt(H / N) error of one run assumes a single, well-explored mode;
+/// a run that finds some modes of a multimodal posterior and misses
+/// others is off by far more. Independent runs miss different modes, so
+/// their scatter measures that error directly. Warnings of the runs are
+/// passed on to `observer`, and one more is raised when the scatter of
+/// log Z exceeds twice the mean reported error.
+pub fn replicate<R: Rng>(
+        config: &Config,
+        settings: &ReplicateConfig,
+        data: &Dataset,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<ReplicateReport, Box<dyn Error>> {
+    if settings.runs < 2 {
+        return Err("replication needs at least two runs".into())
+    }
+    if !(settings.level > 0.0 && settings.level < 1.0) {
+        return Err(format!("the credible level {} must be in (0, 1)", settings.level).into())
+    }
+    let mut results = Vec::with_capacity(settings.runs);
+    for r in 0..settings.runs {
+        let mut warnings = Collect::default();
+        let result = run_with_data(config, data, &mut warnings, rng)?;
+        for warning in warnings.warnings {
+            observer.warn(&format!("run {}: {}", r, warning));
+        }
+        results.push(result);
+    }
+
+    let dim = config.n_params();
+    let mut parameters = Vec::with_capacity(dim);
+    for j in 0..dim {
+        // every run's posterior weights sum to one, so pooling them gives
+        // each run the same say
+        let per_run: Vec<Vec<(f64, f64)>> = results.iter()
+            .map(|r| r.posterior.iter().map(|(theta, lw)| (theta[j], lw.exp())).collect())
+            .collect();
+        let pooled: Vec<(f64, f64)> = per_run.concat();
+        let [mean, median, lower, upper] = summarize(&pooled, settings.level);
+        let single: Vec<[f64; 4]> = per_run.iter().map(|p| summarize(p, settings.level)).collect();
+        let sd = |k: usize| mean_sd(&single.iter().map(|s| s[k]).collect::<Vec<f64>>()).1;
+        parameters.push(ParameterSpread{
+            param: j,
+            mean,
+            median,
+            lower,
+            upper,
+            mean_sd: sd(0),
+            median_sd: sd(1),
+            lower_sd: sd(2),
+            upper_sd: sd(3),
+        });
+    }
+
+    let log_z: Vec<f64> = results.iter().map(|r| r.log_z).collect();
+    let log_z_err: Vec<f64> = results.iter().map(|r| r.log_z_err).collect();
+    let log_z_scatter = mean_sd(&log_z).1;
+    let reported = mean_sd(&log_z_err).0;
+    if log_z_scatter > 2.0 * reported {
+        observer.warn(&format!(
+            "log Z scatters by {:.3} between runs, but the runs report errors of {:.3}: \
+             they likely explore the posterior differently, e.g. find different modes",
+            log_z_scatter, reported,
+        ));
+    }
+    Ok(ReplicateReport{ log_z, log_z_err, log_z_scatter, parameters })
+}
+
+
+/// write one row per parameter: the pooled summaries, each followed by
+/// its scatter between runs, and the widened interval
+pub fn write_spreads(path: &Path, report: &ReplicateReport) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "parameter,mean,mean_sd,median,median_sd,lower,lower_sd,upper,upper_sd,wide_lower,wide_upper")?;
+    for p in &report.parameters {
+        writeln!(
+            out,
+            "{},{:e},{:e},{:e},{:e},{:e},{:e},{:e},{:e},{:e},{:e}",
+            p.param, p.mean, p.mean_sd, p.median, p.median_sd, p.lower, p.lower_sd, p.upper, p.upper_sd,
+            p.wide_lower(), p.wide_upper(),
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
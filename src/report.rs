@@ -0,0 +1,534 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use toml::Value;
+
+use crate::diagnostics::{ShrinkageTrace, MAX_DEVIATION, RANK_P_VALUE};
+use crate::dynamic::{live_counts, summarize};
+use crate::learned::PRIOR_DOMINATED_KL;
+use crate::output::read_dead_birth;
+use crate::ppc::PpcReport;
+use crate::rundir::RunDir;
+use crate::stats::weighted_quantile;
+use crate::units::{self, ParameterSpec};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_of_a_run_directory() {
+        let root = std::env::temp_dir().join(format!("ns_report_{}", std::process::id()));
+        let dir = RunDir::create(&root, "peak", false).unwrap();
+        fs::write(dir.config_file(), "data_file = \"a<b>.csv\"\n[[parameters]]\nname = \"x\"\nunit = \"m\"\nscale = 100.0\n").unwrap();
+        // a run with 50 live points on a uniform prior over [0, 1] and a
+        // narrow peak at 0.5: the i-th dead point sits where X = exp(-i / 50)
+        let n = 50;
+        let mut rows = Vec::new();
+        let log_l = |x: f64| -0.5 * ((x - 0.5) / 0.02).powi(2);
+        let mut contours = Vec::new();
+        for i in 0..1000 {
+            let half = 0.5 * (-(i as f64) / n as f64).exp();
+            let x = if i % 2 == 0 { 0.5 - half } else { 0.5 + half };
+            let birth = if i < n { f64::NEG_INFINITY } else { contours[i - n] };
+            contours.push(log_l(x));
+            rows.push(format!("{:e} {:e} {:e}", x, log_l(x), birth));
+        }
+        fs::write(dir.dead_birth_file(), rows.join("\n")).unwrap();
+        fs::write(dir.summary_file(), "log_z = -3.0\nlog_z_err = 0.1\ntruncation = [\"stopped <early>\"]\n").unwrap();
+        let mut trace = ShrinkageTrace::default();
+        for i in 0..950 {
+            trace.push(n, -1.0 / n as f64, (i * 7) % n);
+        }
+        trace.write_csv(&dir.shrinkage_file()).unwrap();
+
+        let report = RunReport::read(&dir, 1, 0.9).unwrap();
+        let p = &report.parameters[0];
+        assert!((p.mean - 0.5).abs() < 0.01 && (p.median - 0.5).abs() < 0.01, "{:?}", p);
+        assert!(p.lower < 0.5 && p.upper > 0.5 && p.upper - p.lower < 0.1, "{:?}", p);
+        let passed: Vec<Option<bool>> = report.verdicts.iter().map(|v| v.passed).collect();
+        assert_eq!(passed, vec![Some(true), Some(true), Some(false), Some(true)]);
+
+        let html = report.to_html();
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>\n"));
+        assert!(html.contains("a&lt;b&gt;.csv") && html.contains("stopped &lt;early&gt;"));
+        // the table shows x scaled by 100
+        let mean: f64 = html.split("<tr><td>x [m]</td><td>").nth(1).and_then(|r| r.split('<').next()).unwrap().parse().unwrap();
+        assert!((mean - 50.0).abs() < 1.0, "{}", mean);
+        assert_eq!(html.matches("<svg").count(), 4);
+        // nothing is fetched from elsewhere
+        assert!(!html.contains("src=") && !html.contains("href="));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
+
+
+/// most points drawn in one curve of a plot
+const MAX_PLOT_POINTS: usize = 800;
+/// bins of the insertion-rank histogram
+const RANK_BINS: usize = 10;
+
+
+/// posterior summary of one parameter
+///
+/// Fields:
+/// param: index of the parameter
+/// mean, sd: posterior mean and standard deviation
+/// median: posterior median
+/// lower, upper: ends of the central credible interval
+#[derive(Debug, Clone)]
+pub struct ParameterSummary {
+    pub param: usize,
+    pub mean: f64,
+    pub sd: f64,
+    pub median: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+
+/// the outcome of one check of a run
+///
+/// Fields:
+/// check: what was checked
+/// passed: whether the run passed it; None if the run kept nothing to
+///     check it with
+/// detail: the figures behind the verdict
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub check: String,
+    pub passed: Option<bool>,
+    pub detail: String,
+}
+
+
+/// everything the HTML report of a finished run shows, read back from its
+/// directory
+///
+/// Fields:
+/// name: the run's directory name
+/// config: text of the run's config.toml
+/// summary: the entries of summary.toml
+/// level: probability inside the credible intervals
+/// parameters: posterior summary of each parameter
+/// specs: names, units and scales the config shows the parameters with
+/// log_x, log_l, mass: per dead point, in order of log L, the expected log
+///     prior volume, log L and the share of the posterior mass
+/// shrinkage: the per-iteration shrinkage trace, if the run kept one
+/// ppc: the posterior predictive checks, if the run made them
+/// verdicts: the checks of the run
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub name: String,
+    pub config: String,
+    pub summary: toml::Table,
+    pub level: f64,
+    pub parameters: Vec<ParameterSummary>,
+    pub specs: Vec<ParameterSpec>,
+    pub log_x: Vec<f64>,
+    pub log_l: Vec<f64>,
+    pub mass: Vec<f64>,
+    pub shrinkage: Option<ShrinkageTrace>,
+    pub ppc: Option<PpcReport>,
+    pub verdicts: Vec<Verdict>,
+}
+
+
+impl RunReport {
+    /// read the report of the run in `dir` of a model with `dim` parameters
+    pub fn read(dir: &RunDir, dim: usize, level: f64) -> Result<RunReport, Box<dyn Error>> {
+        if !(level > 0.0 && level < 1.0) {
+            return Err(format!("the credible level {} must be in (0, 1)", level).into())
+        }
+        let name = dir.path().file_name().map_or_else(|| dir.path().display().to_string(), |n| n.to_string_lossy().into_owned());
+        let config = fs::read_to_string(dir.config_file())?;
+        let specs: Vec<ParameterSpec> = match toml::from_str::<toml::Table>(&config)?.remove("parameters") {
+            Some(specs) => specs.try_into().map_err(|e| format!("{}: parameters: {}", dir.config_file().display(), e))?,
+            None => Vec::new(),
+        };
+        let summary: toml::Table = toml::from_str(&fs::read_to_string(dir.summary_file())?)
+            .map_err(|e| format!("{}: {}", dir.summary_file().display(), e))?;
+
+        let mut points = read_dead_birth(&dir.dead_birth_file(), dim)?;
+        if points.is_empty() {
+            return Err(format!("{} holds no points", dir.dead_birth_file().display()).into())
+        }
+        // sorts the points by log L; the deterministic shrinkage draws nothing
+        let evidence = summarize(&mut points, 0, &mut StdRng::seed_from_u64(0));
+        let n_live = live_counts(&mut points);
+        let log_x: Vec<f64> = n_live.iter()
+            .scan(0.0, |acc, n| {
+                *acc -= 1.0 / *n as f64;
+                Some(*acc)
+            })
+            .collect();
+        let mass: Vec<f64> = evidence.log_wt.iter().map(|lw| (lw - evidence.log_z).exp()).collect();
+        let tail = 0.5 * (1.0 - level);
+        let parameters = (0..dim)
+            .map(|j| {
+                let values: Vec<(f64, f64)> = points.iter().zip(&mass).map(|(p, w)| (p.theta[j], *w)).collect();
+                let total: f64 = mass.iter().sum();
+                let mean = values.iter().map(|(v, w)| v * w).sum::<f64>() / total;
+                let var = values.iter().map(|(v, w)| w * (v - mean).powi(2)).sum::<f64>() / total;
+                ParameterSummary{
+                    param: j,
+                    mean,
+                    sd: var.sqrt(),
+                    median: weighted_quantile(&values, 0.5),
+                    lower: weighted_quantile(&values, tail),
+                    upper: weighted_quantile(&values, 1.0 - tail),
+                }
+            })
+            .collect();
+
+        let shrinkage = match dir.shrinkage_file().is_file() {
+            true => Some(ShrinkageTrace::read_csv(&dir.shrinkage_file())?),
+            false => None,
+        };
+        let ppc = match dir.ppc_file().is_file() {
+            true => Some(PpcReport::read(&dir.ppc_file())?),
+            false => None,
+        };
+        let verdicts = verdicts(&summary, shrinkage.as_ref());
+        Ok(RunReport{
+            name,
+            config,
+            summary,
+            level,
+            parameters,
+            specs,
+            log_x,
+            log_l: points.iter().map(|p| p.log_l).collect(),
+            mass,
+            shrinkage,
+            ppc,
+            verdicts,
+        })
+    }
+
+    /// the report as one HTML page with its styles and plots inline, so it
+    /// can be attached or mailed on its own
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let title = format!("nested sampling run {}", escape(&self.name));
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            title, STYLE, title,
+        );
+
+        html.push_str("<h2>Evidence</h2>\n<table>\n");
+        let float = |key: &str| self.summary.get(key).and_then(Value::as_float);
+        if let (Some(log_z), Some(err)) = (float("log_z"), float("log_z_err")) {
+            let _ = writeln!(html, "<tr><th>log Z</th><td>{} &plusmn; {}</td></tr>", number(log_z), number(err));
+        }
+        for (key, label) in [("cumulative_log_z", "cumulative log Z"), ("info", "information (nats)"), ("ess", "effective sample size")] {
+            if let Some(v) = float(key) {
+                let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", label, number(v));
+            }
+        }
+        if let Some(iterations) = self.summary.get("iterations").and_then(Value::as_integer) {
+            let _ = writeln!(html, "<tr><th>iterations</th><td>{}</td></tr>", iterations);
+        }
+        if let Some(stats) = self.summary.get("stats").and_then(Value::as_table) {
+            for (key, v) in stats {
+                let v = v.as_float().map_or_else(|| v.to_string(), number);
+                let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", escape(key), v);
+            }
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Diagnostics</h2>\n<table>\n<tr><th>check</th><th>verdict</th><th>detail</th></tr>\n");
+        for v in &self.verdicts {
+            let (class, word) = match v.passed {
+                Some(true) => ("pass", "pass"),
+                Some(false) => ("fail", "fail"),
+                None => ("unknown", "not checked"),
+            };
+            let _ = writeln!(
+                html, "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td></tr>",
+                escape(&v.check), class, word, escape(&v.detail),
+            );
+        }
+        html.push_str("</table>\n");
+
+        // divergences from the prior, with prior-dominated parameters greyed out
+        let prior_kl: Vec<f64> = self.summary.get("prior_kl")
+            .and_then(Value::as_array)
+            .map_or_else(Vec::new, |a| a.iter().filter_map(Value::as_float).collect());
+        let _ = write!(
+            html,
+            "<h2>Parameters</h2>\n<table>\n<tr><th>parameter</th><th>mean</th><th>sd</th><th>median</th><th>{}% interval</th>{}</tr>\n",
+            100.0 * self.level,
+            if prior_kl.is_empty() { "" } else { "<th>from prior (nats)</th>" },
+        );
+        for p in &self.parameters {
+            let kl = match prior_kl.get(p.param) {
+                Some(&d) if d < PRIOR_DOMINATED_KL => format!("<td class=\"unknown\">{} (prior-dominated)</td>", number(d)),
+                Some(&d) => format!("<td>{}</td>", number(d)),
+                None => String::new(),
+            };
+            let shown = |v: f64| number(units::shown(&self.specs, p.param, v));
+            let _ = writeln!(
+                html, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>[{}, {}]</td>{}</tr>",
+                escape(&units::label(&self.specs, p.param)), shown(p.mean), shown(p.sd), shown(p.median),
+                shown(p.lower), shown(p.upper), kl,
+            );
+        }
+        html.push_str("</table>\n");
+
+        if let Some(ppc) = &self.ppc {
+            let _ = write!(
+                html,
+                "<h2>Posterior predictive checks</h2>\n<p>{} replicates simulated from the posterior; \
+                 p-values within {} of 0 or 1 are marked</p>\n<table>\n\
+                 <tr><th>statistic</th><th>observed</th><th>replicated</th><th>p-value</th></tr>\n",
+                ppc.draws, number(0.5 * ppc.alpha),
+            );
+            let flagged = ppc.flagged();
+            for c in &ppc.checks {
+                let class = if flagged.contains(&c) { "fail" } else { "pass" };
+                let _ = writeln!(
+                    html, "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\">{}</td></tr>",
+                    escape(&c.statistic), number(c.observed), number(c.replicated), class, number(c.p_value),
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("<h2>Convergence</h2>\n");
+        let curve = |y: &[f64]| -> Vec<(f64, f64)> { self.log_x.iter().copied().zip(y.iter().copied()).collect() };
+        html.push_str(&line_plot("log-likelihood of the dead points", "log X", "log L", &[(curve(&self.log_l), "#1f77b4")]));
+        html.push_str(&line_plot("posterior mass of the dead points", "log X", "share of the mass", &[(curve(&self.mass), "#d62728")]));
+        if let Some(trace) = self.shrinkage.as_ref().filter(|t| !t.is_empty()) {
+            let expected = trace.expected_log_x();
+            let realized: Vec<(f64, f64)> = trace.log_x().into_iter().enumerate().map(|(i, lx)| (i as f64, lx)).collect();
+            let band = |k: f64| -> Vec<(f64, f64)> {
+                expected.iter().enumerate().map(|(i, (m, sd))| (i as f64, m + k * sd)).collect()
+            };
+            html.push_str(&line_plot(
+                "realized log X against its expectation \u{b1} 2 sd",
+                "iteration",
+                "log X",
+                &[(band(-2.0), "#aaaaaa"), (band(2.0), "#aaaaaa"), (realized, "#1f77b4")],
+            ));
+            let mut bins = vec![0.0; RANK_BINS];
+            for (r, n) in trace.insertion.iter().zip(&trace.n_live) {
+                bins[(r * RANK_BINS / n).min(RANK_BINS - 1)] += 1.0;
+            }
+            html.push_str(&histogram("insertion ranks of new live points", "rank / live points", &bins));
+        }
+
+        html.push_str("<h2>Configuration</h2>\n<pre>");
+        html.push_str(&escape(&self.config));
+        html.push_str("</pre>\n</body>\n</html>\n");
+        html
+    }
+}
+
+
+/// write the report of the run in `dir` to `out`
+pub fn write_report(dir: &RunDir, dim: usize, level: f64, out: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(out, RunReport::read(dir, dim, level)?.to_html())?;
+    Ok(())
+}
+
+
+/// the checks of a run: uniform insertion ranks, log X near its
+/// expectation, no sign of truncation, and an exact likelihood
+fn verdicts(summary: &toml::Table, shrinkage: Option<&ShrinkageTrace>) -> Vec<Verdict> {
+    let mut verdicts = Vec::new();
+    let trace = shrinkage.filter(|t| !t.is_empty());
+    let missing = "the run kept no shrinkage trace".to_string();
+    verdicts.push(match trace {
+        Some(t) => {
+            let p = t.insertion_p_value();
+            Verdict{
+                check: "insertion ranks are uniform".into(),
+                passed: Some(p >= RANK_P_VALUE),
+                detail: format!("KS p = {:.3e}, flagged below {}", p, RANK_P_VALUE),
+            }
+        },
+        None => Verdict{ check: "insertion ranks are uniform".into(), passed: None, detail: missing.clone() },
+    });
+    verdicts.push(match trace {
+        Some(t) => {
+            let dev = t.max_deviation();
+            Verdict{
+                check: "log X follows its expectation".into(),
+                passed: Some(dev <= MAX_DEVIATION),
+                detail: format!("largest departure {:.2} sd, flagged above {}", dev, MAX_DEVIATION),
+            }
+        },
+        None => Verdict{ check: "log X follows its expectation".into(), passed: None, detail: missing },
+    });
+    verdicts.push(match summary.get("truncation").and_then(Value::as_array) {
+        Some(found) if found.is_empty() => Verdict{
+            check: "log Z converged".into(),
+            passed: Some(true),
+            detail: "no sign the run stopped early".into(),
+        },
+        Some(found) => Verdict{
+            check: "log Z converged".into(),
+            passed: Some(false),
+            detail: found.iter().map(|t| t.as_str().map_or_else(|| t.to_string(), str::to_string)).collect::<Vec<_>>().join("; "),
+        },
+        None => Verdict{ check: "log Z converged".into(), passed: None, detail: "the summary records no truncation checks".into() },
+    });
+    let approximate = summary.get("approximate").and_then(Value::as_bool).unwrap_or(false);
+    let noise = summary.get("log_lik_variance").and_then(Value::as_float);
+    verdicts.push(Verdict{
+        check: "the likelihood is exact".into(),
+        passed: Some(!approximate && noise.is_none()),
+        detail: match (approximate, noise) {
+            (true, _) => "the likelihood was approximated, so log Z is too".into(),
+            (false, Some(variance)) => format!(
+                "the likelihood is a Monte Carlo estimate (log-likelihood variance {}), so log Z carries extra estimator noise",
+                number(variance),
+            ),
+            (false, None) => "no approximation was reported".into(),
+        },
+    });
+    verdicts
+}
+
+
+const STYLE: &str = "\
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+td.pass { color: #2a7d2a; font-weight: bold; }
+td.fail { color: #b22222; font-weight: bold; }
+td.unknown { color: #777; }
+pre { background: #f6f6f6; padding: 0.8em; overflow-x: auto; }
+svg { display: block; margin-bottom: 1.5em; }
+";
+
+
+const PLOT_WIDTH: f64 = 640.0;
+const PLOT_HEIGHT: f64 = 300.0;
+const MARGIN: f64 = 60.0;
+
+
+/// text with the characters HTML reserves escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+/// a value with four significant figures
+fn number(v: f64) -> String {
+    if v == 0.0 || !v.is_finite() || (1e-3..1e5).contains(&v.abs()) {
+        let digits = if v == 0.0 || !v.is_finite() { 0 } else { (3 - v.abs().log10().floor() as i32).max(0) as usize };
+        format!("{:.*}", digits, v)
+    } else {
+        format!("{:.3e}", v)
+    }
+}
+
+
+/// the smallest and largest finite values, widened if they coincide
+fn range(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    let (lo, hi) = values.filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    match lo <= hi {
+        true if lo == hi => Some((lo - 0.5, hi + 0.5)),
+        true => Some((lo, hi)),
+        false => None,
+    }
+}
+
+
+/// the frame, title and axis labels of a plot, with its ranges marked at
+/// the ends of each axis
+fn frame(svg: &mut String, title: &str, x_label: &str, y_label: &str, x: (f64, f64), y: (f64, f64)) {
+    let (right, bottom) = (PLOT_WIDTH - 10.0, PLOT_HEIGHT - MARGIN);
+    let _ = write!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-size=\"12\">\n\
+         <text x=\"{cx}\" y=\"18\" text-anchor=\"middle\" font-size=\"14\">{title}</text>\n\
+         <rect x=\"{m}\" y=\"30\" width=\"{pw}\" height=\"{ph}\" fill=\"none\" stroke=\"#444\"/>\n\
+         <text x=\"{m}\" y=\"{ty}\" text-anchor=\"start\">{x0}</text>\n\
+         <text x=\"{right}\" y=\"{ty}\" text-anchor=\"end\">{x1}</text>\n\
+         <text x=\"{cx}\" y=\"{lx}\" text-anchor=\"middle\">{x_label}</text>\n\
+         <text x=\"{yx}\" y=\"{bottom}\" text-anchor=\"end\">{y0}</text>\n\
+         <text x=\"{yx}\" y=\"40\" text-anchor=\"end\">{y1}</text>\n\
+         <text x=\"14\" y=\"{cy}\" text-anchor=\"middle\" transform=\"rotate(-90 14 {cy})\">{y_label}</text>\n",
+        w = PLOT_WIDTH, h = PLOT_HEIGHT, m = MARGIN, pw = right - MARGIN, ph = bottom - 30.0,
+        cx = 0.5 * (MARGIN + right), cy = 0.5 * (30.0 + bottom), ty = bottom + 16.0, lx = bottom + 34.0,
+        yx = MARGIN - 4.0, right = right, bottom = bottom,
+        title = escape(title), x_label = escape(x_label), y_label = escape(y_label),
+        x0 = number(x.0), x1 = number(x.1), y0 = number(y.0), y1 = number(y.1),
+    );
+}
+
+
+/// map x and y ranges onto the plotting area
+fn scale(x: (f64, f64), y: (f64, f64)) -> impl Fn(f64, f64) -> (f64, f64) {
+    let (right, bottom) = (PLOT_WIDTH - 10.0, PLOT_HEIGHT - MARGIN);
+    move |u, v| (
+        MARGIN + (u - x.0) / (x.1 - x.0) * (right - MARGIN),
+        bottom - (v - y.0) / (y.1 - y.0) * (bottom - 30.0),
+    )
+}
+
+
+/// an SVG plot of curves given as (points, colour), each thinned to at
+/// most `MAX_PLOT_POINTS` points; points that are not finite are skipped
+fn line_plot(title: &str, x_label: &str, y_label: &str, curves: &[(Vec<(f64, f64)>, &str)]) -> String {
+    let all = || curves.iter().flat_map(|(c, _)| c.iter()).filter(|(u, v)| u.is_finite() && v.is_finite());
+    let (x, y) = match (range(all().map(|p| p.0)), range(all().map(|p| p.1))) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return String::new(),
+    };
+    let mut svg = String::new();
+    frame(&mut svg, title, x_label, y_label, x, y);
+    let to_svg = scale(x, y);
+    for (curve, colour) in curves {
+        let stride = curve.len().div_ceil(MAX_PLOT_POINTS).max(1);
+        let points: Vec<String> = curve.iter()
+            .step_by(stride)
+            .filter(|(u, v)| u.is_finite() && v.is_finite())
+            .map(|&(u, v)| {
+                let (px, py) = to_svg(u, v);
+                format!("{:.1},{:.1}", px, py)
+            })
+            .collect();
+        let _ = writeln!(svg, "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>", colour, points.join(" "));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+
+/// an SVG bar chart of counts in equal bins over [0, 1], with the level a
+/// uniform would give marked
+fn histogram(title: &str, x_label: &str, counts: &[f64]) -> String {
+    let total: f64 = counts.iter().sum();
+    let top = counts.iter().fold(0.0, |m: f64, c| m.max(*c)).max(1.0);
+    let mut svg = String::new();
+    frame(&mut svg, title, x_label, "count", (0.0, 1.0), (0.0, top));
+    let to_svg = scale((0.0, 1.0), (0.0, top));
+    let width = 1.0 / counts.len() as f64;
+    for (i, c) in counts.iter().enumerate() {
+        let (x0, y0) = to_svg(i as f64 * width, *c);
+        let (x1, y1) = to_svg((i + 1) as f64 * width, 0.0);
+        let _ = writeln!(
+            svg, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#1f77b4\" stroke=\"white\"/>",
+            x0, y0, x1 - x0, y1 - y0,
+        );
+    }
+    let (x0, level) = to_svg(0.0, total / counts.len() as f64);
+    let (x1, _) = to_svg(1.0, 0.0);
+    let _ = writeln!(svg, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#d62728\" stroke-dasharray=\"4 3\"/>", x0, level, x1, level);
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{LogLikelihood, Screen, SlowState};
+use crate::priors::Prior;
+use crate::RunResult;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::observer::Collect;
+    use crate::sampler::{Method, SamplerConfig};
+    use crate::{run_core, Config};
+
+    // exp(-|theta_j / (sd_j / 10)|^2 / 2) under N(0, sd_j^2) priors
+    struct Narrow {
+        sd: Vec<f64>,
+    }
+
+    impl LogLikelihood for Narrow {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            -0.5 * theta.iter().zip(&self.sd).map(|(t, s)| (10.0 * t / s).powi(2)).sum::<f64>()
+        }
+
+        fn dim(&self) -> usize {
+            self.sd.len()
+        }
+    }
+
+    #[test]
+    fn test_badly_scaled_priors_are_sampled_in_unit_scales() {
+        let sd = vec![1e-3, 1e4];
+        let config = Config{
+            sample_num: 1000,
+            particle_num: 100,
+            mu: vec![0.0, 0.0],
+            sd: sd.clone(),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let prior = config.prior().unwrap();
+        let scaling = Scaling::choose(RescaleMode::Auto, prior.as_ref()).unwrap();
+        assert_eq!(scaling.scale, sd);
+        assert!(Scaling::choose(RescaleMode::Never, prior.as_ref()).is_none());
+        let scaled = Rescaled::new(Arc::clone(&prior), scaling.clone());
+        let theta = [2e-3, -5e3];
+        let z = scaling.scaled(&theta);
+        assert_eq!(z, vec![2.0, -0.5]);
+        assert_eq!(scaling.unscaled(&z), theta);
+        let jacobian = (1e-3f64).ln() + (1e4f64).ln();
+        assert!((scaled.log_density(&z) - prior.log_density(&theta) - jacobian).abs() < 1e-9);
+        assert_eq!(scaled.scale(), vec![1.0, 1.0]);
+
+        let model = Narrow{ sd: sd.clone() };
+        let mut observer = Collect::default();
+        let result = run_core(&config, &model, None, &mut observer, &mut StdRng::seed_from_u64(439), None, None).unwrap();
+        assert!(observer.warnings.iter().any(|w| w.contains("7 orders of magnitude")), "{:?}", observer.warnings);
+        let truth = 2.0 * (0.1f64 / 1.01f64.sqrt()).ln();
+        assert!((result.log_z - truth).abs() < 4.0 * result.log_z_err, "{} +/- {} vs {}", result.log_z, result.log_z_err, truth);
+        // the posterior comes back in the parameters' own units
+        let var = |j: usize| result.posterior.iter().map(|(t, lw)| lw.exp() * t[j] * t[j]).sum::<f64>();
+        for (j, s) in sd.iter().enumerate() {
+            let expected = s * s * 0.01 / 1.01;
+            assert!((var(j) / expected - 1.0).abs() < 0.3, "theta{}: {} vs {}", j, var(j), expected);
+        }
+    }
+}
+
+
+/// ratio of the widest to the narrowest prior width above which
+/// `RescaleMode::Auto` samples in rescaled parameters
+pub const MAX_SCALE_RATIO: f64 = 1e3;
+
+
+/// whether a run samples its parameters divided by their prior widths
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RescaleMode {
+    /// when the prior widths span more than `MAX_SCALE_RATIO`
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+
+/// division of each parameter by its prior width, so the sampler sees
+/// parameters of unit scale
+///
+/// Fields:
+/// scale: the width of each parameter
+#[derive(Debug, Clone)]
+pub struct Scaling {
+    pub scale: Vec<f64>,
+}
+
+
+impl Scaling {
+    /// the scaling the mode asks for with this prior, if any. Widths that
+    /// are not positive and finite are left alone
+    pub fn choose(mode: RescaleMode, prior: &dyn Prior) -> Option<Scaling> {
+        let scale: Vec<f64> = prior.scale().into_iter()
+            .map(|s| if s.is_finite() && s > 0.0 { s } else { 1.0 })
+            .collect();
+        let wanted = match mode {
+            RescaleMode::Never => false,
+            RescaleMode::Always => true,
+            RescaleMode::Auto => Scaling::ratio(&scale) > MAX_SCALE_RATIO,
+        };
+        match wanted && !scale.is_empty() {
+            true => Some(Scaling{ scale }),
+            false => None,
+        }
+    }
+
+    /// ratio of the widest to the narrowest width
+    pub fn ratio(scale: &[f64]) -> f64 {
+        let (lo, hi) = scale.iter().fold((f64::INFINITY, 0.0f64), |(lo, hi), s| (lo.min(*s), hi.max(*s)));
+        hi / lo
+    }
+
+    /// the sampler's coordinates of theta
+    pub fn scaled(&self, theta: &[f64]) -> Vec<f64> {
+        theta.iter().zip(&self.scale).map(|(t, s)| t / s).collect()
+    }
+
+    /// theta at the sampler's coordinates z
+    pub fn unscaled(&self, z: &[f64]) -> Vec<f64> {
+        z.iter().zip(&self.scale).map(|(z, s)| z * s).collect()
+    }
+
+    /// a result sampled in scaled coordinates, with its posterior in the
+    /// parameters' own units. The evidence is unchanged, since the prior
+    /// density of the coordinates carries the Jacobian
+    pub fn restore(&self, mut result: RunResult) -> RunResult {
+        for (theta, _) in result.posterior.iter_mut() {
+            *theta = self.unscaled(theta);
+        }
+        result
+    }
+}
+
+
+/// a prior over parameters divided by their widths
+///
+/// Fields:
+/// prior: the prior of the parameters in their own units
+/// scaling: their widths
+/// log_jacobian: sum of the log widths, the log density of the
+///     coordinates less that of theta
+#[derive(Debug)]
+pub struct Rescaled {
+    prior: Arc<dyn Prior>,
+    scaling: Scaling,
+    log_jacobian: f64,
+}
+
+
+impl Rescaled {
+    pub fn new(prior: Arc<dyn Prior>, scaling: Scaling) -> Rescaled {
+        let log_jacobian = scaling.scale.iter().map(|s| s.ln()).sum();
+        Rescaled{ prior, scaling, log_jacobian }
+    }
+}
+
+
+impl Prior for Rescaled {
+    fn dim(&self) -> usize {
+        self.prior.dim()
+    }
+
+    fn sample_into(&self, z: &mut [f64], rng: &mut dyn RngCore) {
+        self.prior.sample_into(z, rng);
+        z.iter_mut().zip(&self.scaling.scale).for_each(|(v, s)| *v /= s);
+    }
+
+    fn log_density(&self, z: &[f64]) -> f64 {
+        self.prior.log_density(&self.scaling.unscaled(z)) + self.log_jacobian
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.prior.scale().iter().zip(&self.scaling.scale).map(|(w, s)| w / s).collect()
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        self.prior.stats()
+    }
+
+    fn unit_transform(&self, u: &[f64]) -> Option<Vec<f64>> {
+        self.prior.unit_transform(u).map(|theta| self.scaling.scaled(&theta))
+    }
+
+    fn max_log_density(&self) -> Option<f64> {
+        self.prior.max_log_density().map(|m| m + self.log_jacobian)
+    }
+}
+
+
+/// a likelihood of parameters divided by their widths
+///
+/// Fields:
+/// model: the likelihood of the parameters in their own units
+/// scaling: their widths
+pub struct RescaledModel<M> {
+    model: M,
+    scaling: Scaling,
+}
+
+
+impl<M: LogLikelihood> RescaledModel<M> {
+    pub fn new(model: M, scaling: Scaling) -> RescaledModel<M> {
+        RescaledModel{ model, scaling }
+    }
+}
+
+
+impl<M: LogLikelihood> LogLikelihood for RescaledModel<M> {
+    fn log_lik(&self, z: &[f64]) -> f64 {
+        self.model.log_lik(&self.scaling.unscaled(z))
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.model.is_approximate()
+    }
+
+    fn screen(&self, z: &[f64], threshold: f64) -> Screen {
+        self.model.screen(&self.scaling.unscaled(z), threshold)
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        self.model.stats()
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, z: &[f64]) -> Vec<f64> {
+        self.model.outputs(&self.scaling.unscaled(z))
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        self.model.log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.model.reseed(seed)
+    }
+
+    fn log_lik_state(&self, z: &[f64]) -> Option<(f64, SlowState)> {
+        self.model.log_lik_state(&self.scaling.unscaled(z))
+    }
+
+    fn update_fast(&self, state: &mut SlowState, z: &[f64]) -> f64 {
+        self.model.update_fast(state, &self.scaling.unscaled(z))
+    }
+}
@@ -0,0 +1,275 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use toml::Value;
+
+use crate::{Config, RunResult};
+use crate::checkpoint::Checkpoint;
+use crate::curve::LikelihoodCurve;
+use crate::learned::prior_dominated;
+use crate::observer::Observer;
+use crate::output::write_dead_birth;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collisions_and_latest() {
+        let root = std::env::temp_dir().join(format!("ns_rundir_{}", std::process::id()));
+        let first = RunDir::create(&root, "first", false).unwrap();
+        assert!(RunDir::create(&root, "first", false).is_err());
+        fs::write(first.path().join("stale.txt"), "x").unwrap();
+        let first = RunDir::create(&root, "first", true).unwrap();
+        assert!(!first.path().join("stale.txt").exists());
+        assert!(RunDir::create(&root, "../escape", false).is_err());
+
+        let second = RunDir::create(&root, "second", false).unwrap();
+        assert!(RunDir::latest(&root).is_err());
+        for (dir, iteration) in [(&first, 100), (&second, 50), (&first, 300)] {
+            fs::write(dir.checkpoint_dir().join(Checkpoint::file_name(iteration)), "").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let latest = RunDir::latest(&root).unwrap();
+        assert_eq!(latest.path(), first.path());
+        assert!(latest.latest_checkpoint().unwrap().unwrap().ends_with(Checkpoint::file_name(300)));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
+
+
+/// the directory a run keeps its files in, `<root>/<run name>/`:
+///
+/// - `config.toml`: the config the run used, overrides applied and
+///   defaults filled in
+/// - `dead-birth.txt`: the dead points with their birth contours
+/// - `shrinkage.csv`: the per-iteration shrinkage trace
+/// - `curve.csv`: log L against the expected log X of the dead points
+/// - `summary.toml`: log Z, its error, the information, the counters, any
+///   signs of truncation, the parameters the data barely constrain and,
+///   if asked for, log Z per unit prior volume
+/// - `checkpoints/`: the state every `checkpoint_every` iterations, the
+///   most recent `checkpoint.keep` of them
+/// - `ppc.toml`: posterior predictive p-values, if the config asked for them
+/// - `parameters.csv`, `diagnostics.toml`, `samples.csv`: written by the
+///   built-in post-processing steps, if the config or `ns summarize` ran them
+/// - `control.toml`: written by the user to change or stop the running job
+/// - `report.html`: the report written by `ns report`
+#[derive(Debug, Clone)]
+pub struct RunDir {
+    path: PathBuf,
+}
+
+
+impl RunDir {
+    /// make the directory of a new run. An existing run of the same name is
+    /// refused, unless `force` is set, in which case it is deleted first
+    pub fn create(root: &Path, name: &str, force: bool) -> Result<RunDir, Box<dyn Error>> {
+        let plain = !name.is_empty() && name != "." && name != ".."
+            && !name.contains(['/', '\\']);
+        if !plain {
+            return Err(format!("run name {:?} must be a plain directory name", name).into())
+        }
+        let path = root.join(name);
+        if path.exists() {
+            if !force {
+                return Err(format!(
+                    "{} already holds a run; pick another run_name or pass --force to replace it",
+                    path.display(),
+                ).into())
+            }
+            fs::remove_dir_all(&path)?;
+        }
+        let dir = RunDir{ path };
+        fs::create_dir_all(dir.checkpoint_dir())?;
+        Ok(dir)
+    }
+
+    /// an existing run directory
+    pub fn open(path: &Path) -> Result<RunDir, Box<dyn Error>> {
+        let dir = RunDir{ path: path.to_path_buf() };
+        if !dir.config_file().is_file() {
+            return Err(format!("{} is not a run directory: it has no config.toml", path.display()).into())
+        }
+        Ok(dir)
+    }
+
+    /// the run under `root` whose latest checkpoint was written most recently
+    pub fn latest(root: &Path) -> Result<RunDir, Box<dyn Error>> {
+        let mut newest: Option<(SystemTime, PathBuf)> = None;
+        for entry in fs::read_dir(root)? {
+            let dir = RunDir{ path: entry?.path() };
+            if let Some(checkpoint) = dir.latest_checkpoint()? {
+                let modified = fs::metadata(&checkpoint)?.modified()?;
+                if newest.as_ref().is_none_or(|(t, _)| modified > *t) {
+                    newest = Some((modified, dir.path));
+                }
+            }
+        }
+        match newest {
+            Some((_, path)) => Ok(RunDir{ path }),
+            None => Err(format!("no run under {} has a checkpoint", root.display()).into()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.path.join("config.toml")
+    }
+
+    pub fn dead_birth_file(&self) -> PathBuf {
+        self.path.join("dead-birth.txt")
+    }
+
+    /// instructions to the running job, see `control::Control`
+    pub fn control_file(&self) -> PathBuf {
+        self.path.join("control.toml")
+    }
+
+    pub fn shrinkage_file(&self) -> PathBuf {
+        self.path.join("shrinkage.csv")
+    }
+
+    pub fn curve_file(&self) -> PathBuf {
+        self.path.join("curve.csv")
+    }
+
+    pub fn summary_file(&self) -> PathBuf {
+        self.path.join("summary.toml")
+    }
+
+    /// posterior predictive p-values, see `ppc::PpcReport`
+    pub fn ppc_file(&self) -> PathBuf {
+        self.path.join("ppc.toml")
+    }
+
+    /// per-parameter posterior summaries of the "summary" post-processor
+    pub fn parameters_file(&self) -> PathBuf {
+        self.path.join("parameters.csv")
+    }
+
+    /// the checks of the run, written by the "diagnostics" post-processor
+    pub fn diagnostics_file(&self) -> PathBuf {
+        self.path.join("diagnostics.toml")
+    }
+
+    /// equally weighted posterior draws of the "export" post-processor
+    pub fn samples_file(&self) -> PathBuf {
+        self.path.join("samples.csv")
+    }
+
+    pub fn report_file(&self) -> PathBuf {
+        self.path.join("report.html")
+    }
+
+    pub fn checkpoint_dir(&self) -> PathBuf {
+        self.path.join("checkpoints")
+    }
+
+    /// the checkpoint with the most iterations, if there is one
+    pub fn latest_checkpoint(&self) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        Ok(self.checkpoints()?.pop())
+    }
+
+    /// the checkpoints, fewest iterations first
+    pub fn checkpoints(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let dir = self.checkpoint_dir();
+        if !dir.is_dir() {
+            return Ok(Vec::new())
+        }
+        let mut checkpoints = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with("checkpoint-") && name.ends_with(".bin") {
+                checkpoints.push(path);
+            }
+        }
+        checkpoints.sort();
+        Ok(checkpoints)
+    }
+
+    /// delete all but the `keep` most recent checkpoints
+    pub fn prune_checkpoints(&self, keep: usize) -> Result<(), Box<dyn Error>> {
+        let checkpoints = self.checkpoints()?;
+        for path in &checkpoints[..checkpoints.len().saturating_sub(keep)] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// the most recent checkpoint that reads back intact, warning about
+    /// each newer one that does not, e.g. because the job was killed while
+    /// the disk was full
+    pub fn latest_intact_checkpoint(&self, observer: &mut dyn Observer) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        for path in self.checkpoints()?.iter().rev() {
+            match Checkpoint::read(path) {
+                Ok(checkpoint) => return Ok(Some(checkpoint)),
+                Err(e) => observer.warn(&format!("{}; falling back to the checkpoint before it", e)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// write the config of the run as `config.toml`, with its defaults
+    pub fn write_config(&self, config: &Config) -> Result<(), Box<dyn Error>> {
+        fs::write(self.config_file(), config.to_toml()?)?;
+        Ok(())
+    }
+
+    /// write the chains, the shrinkage trace and the summary of a finished run
+    pub fn write_outputs(&self, result: &RunResult) -> Result<(), Box<dyn Error>> {
+        write_dead_birth(&self.dead_birth_file(), &result.posterior, &result.dead_birth)?;
+        if !result.shrinkage.n_live.is_empty() {
+            result.shrinkage.write_csv(&self.shrinkage_file())?;
+        }
+        // runs without dead points, or with fewer than two of finite
+        // likelihood, have no curve
+        if let Ok(curve) = LikelihoodCurve::from_result(result) {
+            curve.write_csv(&self.curve_file())?;
+        }
+        let mut summary = toml::Table::new();
+        summary.insert("log_z".into(), Value::Float(result.log_z));
+        summary.insert("log_z_err".into(), Value::Float(result.log_z_err));
+        summary.insert("info".into(), Value::Float(result.info));
+        summary.insert("iterations".into(), Value::Integer(result.iterations as i64));
+        summary.insert("ess".into(), Value::Float(result.ess));
+        summary.insert("approximate".into(), Value::Boolean(result.approximate));
+        if let Some(variance) = result.log_lik_variance {
+            summary.insert("log_lik_variance".into(), Value::Float(variance));
+        }
+        if let Some(log_z) = result.cumulative_log_z {
+            summary.insert("cumulative_log_z".into(), Value::Float(log_z));
+        }
+        let truncation = result.truncation.iter().map(|t| Value::String(t.to_string())).collect();
+        summary.insert("truncation".into(), Value::Array(truncation));
+        if !result.prior_kl.is_empty() {
+            let kl = result.prior_kl.iter().map(|d| Value::Float(*d)).collect();
+            summary.insert("prior_kl".into(), Value::Array(kl));
+            let dominated = prior_dominated(&result.prior_kl).into_iter().map(|j| Value::Integer(j as i64)).collect();
+            summary.insert("prior_dominated".into(), Value::Array(dominated));
+        }
+        if let Some(log_z) = result.log_z_per_volume {
+            summary.insert("log_z_per_volume".into(), Value::Float(log_z));
+        }
+        let stats: toml::Table = result.model_stats.iter()
+            .map(|(k, v)| (k.clone(), Value::Float(*v)))
+            .collect();
+        summary.insert("stats".into(), Value::Table(stats));
+        fs::write(self.summary_file(), toml::to_string_pretty(&summary)?)?;
+        Ok(())
+    }
+}
+
+
+/// a run name from the current time, `run-<unix seconds>`
+pub fn default_run_name() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("run-{}", secs)
+}
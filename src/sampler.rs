@@ -0,0 +1,2362 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::iter::zip;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::dist::Normal;
+use crate::geometry::{self, Whitening};
+use crate::linalg::{Cholesky, Matrix};
+use crate::models::{Counted, LogLikelihood, Screen, SlowState};
+use crate::observer::Observer;
+use crate::priors::Prior;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Collect;
+    use crate::priors::NormalPrior;
+    use crate::test_support::Bowl;
+
+    fn unit_prior(dim: usize) -> Arc<dyn Prior> {
+        Arc::new(NormalPrior::new(&vec![0.0; dim], &vec![1.0; dim]).unwrap())
+    }
+
+    /// flat likelihood on a narrow box around the origin
+    struct Slab;
+
+    impl LogLikelihood for Slab {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            if theta[0].abs() < 0.01 { 0.0 } else { f64::NEG_INFINITY }
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_stuck_chains_are_detected_and_lengthened() {
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 1,
+            scale: 100.0,
+            duplicate_window: 10,
+            duplicate_fraction: 0.5,
+            ..Default::default()
+        };
+        let mut sampler = Sampler::new(&config, unit_prior(1));
+        let live = LiveSnapshot::new(0, [(&[0.005][..], 0.0), (&[-0.005][..], 0.0)].into_iter());
+        let mut observer = Collect::default();
+        let mut rng = StdRng::seed_from_u64(380);
+        for _ in 0..10 {
+            let (theta, log_l) = sampler.draw(&Slab, -1.0, &live, &mut observer, &mut rng).unwrap();
+            assert!(theta[0].abs() < 0.01 && log_l == 0.0);
+        }
+        assert!(!observer.warnings.is_empty());
+        let stats = sampler.stats();
+        assert!(stats.contains(&("sampler_steps".to_string(), 2.0)));
+        assert!(stats.iter().any(|(k, v)| k == "sampler_duplicates" && *v >= 5.0));
+    }
+
+    #[test]
+    fn test_auto_leaves_rejection_when_it_gets_slow() {
+        let config = SamplerConfig{ method: Method::Auto, ..Default::default() };
+        let mut sampler = Sampler::new(&config, unit_prior(1));
+        let live = LiveSnapshot::new(0, [(&[0.005][..], 0.0), (&[-0.005][..], 0.0)].into_iter());
+        let mut rng = StdRng::seed_from_u64(392);
+        for _ in 0..200 {
+            sampler.draw(&Slab, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+        }
+        // about 1 in 125 prior draws lands in the slab, but most draws
+        // from around the live points do
+        assert_eq!(sampler.method, Method::Region);
+        assert!(sampler.stats().iter().any(|(k, v)| k == "sampler_switched_at" && *v < 200.0));
+
+        // live points far wider than the slab leave the region for chains
+        let spread = LiveSnapshot::new(0, [(&[5.0][..], 0.0), (&[-5.0][..], 0.0)].into_iter());
+        for _ in 0..200 {
+            sampler.draw(&Slab, -1.0, &spread, &mut Collect::default(), &mut rng).unwrap();
+        }
+        assert_eq!(sampler.method, Method::RandomWalk);
+
+        let wide = Sampler::new(&config, unit_prior(11));
+        assert_eq!(wide.method, Method::HitAndRun);
+    }
+
+    #[test]
+    fn test_per_parameter_scales_and_bounds() {
+        // the second parameter is a thousand times wider than the first
+        struct Wide;
+
+        impl LogLikelihood for Wide {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta[0].abs() < 1.0 && theta[1].abs() < 1000.0 { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                2
+            }
+        }
+
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 10,
+            scales: Some(vec![0.1, 100.0]),
+            min_scale: 0.5,
+            max_scale: 1.5,
+            ..Default::default()
+        };
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 1000.0]).unwrap());
+        let mut sampler = Sampler::new(&config, prior);
+        let live = LiveSnapshot::new(0, [(&[0.0, 0.0][..], 0.0), (&[0.1, 50.0][..], 0.0)].into_iter());
+        let mut rng = StdRng::seed_from_u64(403);
+        let mut moved = [0.0f64; 2];
+        for _ in 0..50 {
+            let (theta, _) = sampler.draw(&Wide, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            moved[0] = moved[0].max(theta[0].abs());
+            moved[1] = moved[1].max(theta[1].abs());
+        }
+        // nearly every step is accepted, so the scale runs up to its bound
+        assert!(moved[1] > 100.0 * moved[0]);
+        let stats = sampler.stats();
+        assert!(stats.contains(&("sampler_scale".to_string(), 1.5)));
+        assert!(stats.contains(&("sampler_scale_1".to_string(), 150.0)));
+    }
+
+    #[test]
+    fn test_blocks_adapt_their_own_scales() {
+        // a narrow first parameter and a wide second one
+        struct Box2;
+
+        impl LogLikelihood for Box2 {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta[0].abs() < 0.01 && theta[1].abs() < 100.0 { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                2
+            }
+        }
+
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 20,
+            scales: Some(vec![1.0, 1.0]),
+            blocks: Some(vec![vec![0], vec![1]]),
+            ..Default::default()
+        };
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 100.0]).unwrap());
+        let mut sampler = Sampler::new(&config, prior);
+        let live = LiveSnapshot::new(0, [(&[0.0, 0.0][..], 0.0), (&[0.005, 50.0][..], 0.0)].into_iter());
+        let mut rng = StdRng::seed_from_u64(404);
+        for _ in 0..100 {
+            let (theta, _) = sampler.draw(&Box2, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            assert!(Box2.log_lik(&theta) == 0.0);
+        }
+        let stats = sampler.stats();
+        let scale = |b: usize| stats.iter().find(|(k, _)| *k == format!("sampler_scale_block_{}", b)).unwrap().1;
+        assert!(scale(0) < 0.1 && scale(1) > 10.0);
+        assert_eq!(sampler.proposed, 100 * 20 * 2);
+    }
+
+    #[test]
+    fn test_fast_parameters_are_oversampled() {
+        struct Box3;
+
+        impl LogLikelihood for Box3 {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta.iter().all(|t| t.abs() < 1.0) { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                3
+            }
+        }
+
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0; 3], &[1.0; 3]).unwrap());
+        let live = LiveSnapshot::new(0, [(&[0.0, 0.0, 0.0][..], 0.0), (&[0.5, -0.5, 0.2][..], 0.0)].into_iter());
+        let mut rng = StdRng::seed_from_u64(454);
+        for method in [Method::RandomWalk, Method::HitAndRun] {
+            let config = SamplerConfig{ method, steps: 10, fast: Some(vec![2]), oversample: 4, ..Default::default() };
+            let mut sampler = Sampler::new(&config, Arc::clone(&prior));
+            assert_eq!(sampler.blocks, vec![vec![0, 1], vec![2]]);
+            assert_eq!(sampler.repeats, vec![1, 4]);
+            for _ in 0..20 {
+                let (theta, _) = sampler.draw(&Box3, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+                assert!(Box3.log_lik(&theta) == 0.0);
+            }
+            if method == Method::RandomWalk {
+                assert_eq!(sampler.proposed, 20 * 10 * (1 + 4));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fast_moves_reuse_the_slow_state() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // an expensive part in theta[0] and theta[1] and a cheap offset in theta[2]
+        struct Split {
+            slow_evaluations: AtomicUsize,
+        }
+
+        impl Split {
+            fn slow(&self, theta: &[f64]) -> f64 {
+                self.slow_evaluations.fetch_add(1, Ordering::Relaxed);
+                -0.5 * (theta[0] * theta[0] + theta[1] * theta[1])
+            }
+        }
+
+        impl LogLikelihood for Split {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                self.slow(theta) - 0.5 * theta[2] * theta[2]
+            }
+
+            fn dim(&self) -> usize {
+                3
+            }
+
+            fn log_lik_state(&self, theta: &[f64]) -> Option<(f64, SlowState)> {
+                let slow = self.slow(theta);
+                Some((slow - 0.5 * theta[2] * theta[2], Box::new(slow)))
+            }
+
+            fn update_fast(&self, state: &mut SlowState, theta: &[f64]) -> f64 {
+                state.downcast_ref::<f64>().unwrap() - 0.5 * theta[2] * theta[2]
+            }
+        }
+
+        let model = Split{ slow_evaluations: AtomicUsize::new(0) };
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 10,
+            fast: Some(vec![2]),
+            oversample: 8,
+            ..Default::default()
+        };
+        let mut sampler = Sampler::new(&config, unit_prior(3));
+        let live = LiveSnapshot::new(0, [(&[0.1, 0.2, 0.3][..], -0.07), (&[-0.4, 0.1, 0.5][..], -0.21)].into_iter());
+        let mut rng = StdRng::seed_from_u64(455);
+        for _ in 0..20 {
+            let (theta, log_l) = sampler.draw(&model, -3.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            assert!((log_l - model.log_lik(&theta)).abs() < 1e-12);
+        }
+        // one slow evaluation per slow move and at most one per chain to
+        // start the state, against 8 fast moves per slow one
+        let slow = model.slow_evaluations.load(Ordering::Relaxed) - 20;
+        assert!(slow <= 20 * (10 + 1), "{}", slow);
+        assert!(sampler.states.entries.len() <= 2 * live.len());
+    }
+
+    #[test]
+    fn test_delayed_rejection_keeps_the_constrained_prior() {
+        // the unit normal prior cut to the square |theta_j| < 0.5
+        struct Square;
+
+        impl LogLikelihood for Square {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta.iter().all(|t| t.abs() < 0.5) { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                2
+            }
+        }
+
+        // steps far too wide for the square, and kept that way
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 20,
+            scale: 1.0,
+            min_scale: 1.0,
+            max_scale: 1.0,
+            scales: Some(vec![1.0, 1.0]),
+            delayed_rejection: true,
+            ..Default::default()
+        };
+        let prior = unit_prior(2);
+        let mut rng = StdRng::seed_from_u64(442);
+        // chains start from the target itself, so any drift is the kernel's
+        let mut points = Vec::new();
+        while points.len() < 500 {
+            let mut theta = vec![0.0; 2];
+            prior.sample_into(&mut theta, &mut rng);
+            if Square.log_lik(&theta) == 0.0 {
+                points.push(theta);
+            }
+        }
+        let live = LiveSnapshot::new(0, points.iter().map(|p| (p.as_slice(), 0.0)));
+        let mut sampler = Sampler::new(&config, prior);
+        let mut second_moment = 0.0;
+        let draws = 2000;
+        for _ in 0..draws {
+            let (theta, _) = sampler.draw(&Square, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            assert_eq!(Square.log_lik(&theta), 0.0);
+            second_moment += theta[0] * theta[0] / draws as f64;
+        }
+        // the variance of a unit normal truncated to (-0.5, 0.5)
+        assert!((second_moment - 0.0806).abs() < 0.003, "{}", second_moment);
+        let stats = sampler.stats();
+        let stat = |k: &str| stats.iter().find(|(name, _)| name == k).unwrap().1;
+        let first = stat("sampler_first_stage_accepted");
+        let second = stat("sampler_second_stage_accepted");
+        // most moves come from the second stage
+        assert!(second > first, "{} vs {}", second, first);
+        assert!(stat("sampler_second_stage_tries") > second);
+    }
+
+    #[test]
+    fn test_degenerate_directions_are_found_and_set_aside() {
+        // the data pin theta0 + theta1 and say nothing about theta2
+        struct Pinned;
+
+        impl LogLikelihood for Pinned {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                -0.5 * ((theta[0] + theta[1]) / 1e-5).powi(2)
+            }
+
+            fn dim(&self) -> usize {
+                3
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(443);
+        let points: Vec<Vec<f64>> = (0..100)
+            .map(|_| {
+                let (a, b): (f64, f64) = (rng.gen::<f64>() * 2.0 - 1.0, rng.gen::<f64>() * 2.0 - 1.0);
+                vec![a, -a + 1e-6 * (rng.gen::<f64>() - 0.5), b]
+            })
+            .collect();
+        let live = LiveSnapshot::new(0, points.iter().map(|p| (p.as_slice(), Pinned.log_lik(p))));
+        let threshold = points.iter().map(|p| Pinned.log_lik(p)).fold(f64::INFINITY, f64::min);
+        let config = SamplerConfig{ method: Method::RandomWalk, reduce_degenerate: true, ..Default::default() };
+        let mut sampler = Sampler::new(&config, unit_prior(3));
+        let mut observer = Collect::default();
+        for _ in 0..50 {
+            let (theta, log_l) = sampler.draw(&Pinned, threshold, &live, &mut observer, &mut rng).unwrap();
+            assert!(log_l > threshold && (theta[0] + theta[1]).abs() < 1e-5);
+        }
+        assert!(observer.warnings.iter().any(|w| w.contains("no spread: theta[0] + theta[1];")), "{:?}", observer.warnings);
+        let direction = &sampler.degenerate_directions()[0];
+        assert!((direction[0] - 1.0).abs() < 1e-6 && (direction[1] - 1.0).abs() < 1e-3 && direction[2].abs() < 1e-3);
+        // steps along the rest of the space are not shrunk to the pinned width
+        let stats = sampler.stats();
+        let stat = |k: &str| stats.iter().find(|(name, _)| name == k).unwrap().1;
+        assert_eq!(stat("sampler_degenerate_directions"), 1.0);
+        assert!(stat("sampler_acceptance") > 0.2, "{}", stat("sampler_acceptance"));
+        assert_eq!(combination(&[1.0, -0.5, 0.0, -1.0]), "theta[0] - 0.500 theta[1] - theta[3]");
+    }
+
+    #[test]
+    fn test_hit_and_run_travels_along_a_ridge() {
+        struct Ridge;
+
+        impl LogLikelihood for Ridge {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if (theta[0] - theta[1]).abs() < 0.01 && theta[0].abs() < 1.0 { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                2
+            }
+        }
+
+        let config = SamplerConfig{ method: Method::HitAndRun, steps: 10, ..Default::default() };
+        let mut sampler = Sampler::new(&config, unit_prior(2));
+        let live = LiveSnapshot::new(0, [(&[0.0, 0.0][..], 0.0), (&[0.1, 0.1][..], 0.0)].into_iter());
+        let mut rng = StdRng::seed_from_u64(405);
+        let (mut low, mut high) = (0.0f64, 0.0f64);
+        for _ in 0..200 {
+            let (theta, log_l) = sampler.draw(&Ridge, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            assert_eq!(log_l, 0.0);
+            assert_eq!(Ridge.log_lik(&theta), 0.0);
+            low = low.min(theta[0]);
+            high = high.max(theta[0]);
+        }
+        // the live points only span a tenth of the ridge
+        assert!(low < -0.5 && high > 0.5);
+        assert_eq!(sampler.stats()[1].1, 0.0);
+    }
+
+    #[test]
+    fn test_stalled_chains_escalate_then_give_up() {
+        // nothing but the two live points beats the contour
+        struct Point;
+
+        impl LogLikelihood for Point {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta[0] == 0.0 || theta[0] == 1.0 { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                1
+            }
+        }
+
+        let dump = std::env::temp_dir().join(format!("ns_stall_{}.csv", std::process::id()));
+        let config = SamplerConfig{
+            method: Method::RandomWalk,
+            steps: 1,
+            max_steps: 4,
+            stall_chains: 3,
+            stall_dump: Some(dump.clone()),
+            remedy_duplicates: false,
+            ..Default::default()
+        };
+        let mut sampler = Sampler::new(&config, unit_prior(1));
+        let live = LiveSnapshot::new(0, [(&[0.0][..], 0.0), (&[1.0][..], 0.0)].into_iter());
+        let mut observer = Collect::default();
+        let mut rng = StdRng::seed_from_u64(406);
+        for _ in 0..9 {
+            sampler.draw(&Point, -1.0, &live, &mut observer, &mut rng).unwrap();
+        }
+        assert_eq!(sampler.steps, 4);
+        assert_eq!(sampler.escalation, Escalation::Region);
+        assert_eq!(observer.warnings.len(), 3);
+        // a region that finds nothing gives up rather than copy a live point
+        let error = sampler.draw(&Point, -1.0, &live, &mut observer, &mut rng).unwrap_err();
+        assert!(error.to_string().contains("stuck"));
+        assert_eq!(std::fs::read_to_string(&dump).unwrap(), "theta0,log_l\n0,0\n1,0\n");
+        std::fs::remove_file(dump).unwrap();
+    }
+
+    #[test]
+    fn test_short_chains_have_poor_quality() {
+        // uniform live points in the unit box, which is the whole contour
+        struct Unit;
+
+        impl LogLikelihood for Unit {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                if theta.iter().all(|t| t.abs() < 0.5) { 0.0 } else { f64::NEG_INFINITY }
+            }
+
+            fn dim(&self) -> usize {
+                3
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(409);
+        let points: Vec<Vec<f64>> = (0..100).map(|_| (0..3).map(|_| rng.gen::<f64>() - 0.5).collect()).collect();
+        let live = LiveSnapshot::new(0, points.iter().map(|p| (p.as_slice(), 0.0)));
+        let mut quality = Vec::new();
+        let mut correlation = Vec::new();
+        for steps in [1, 100] {
+            let config = SamplerConfig{ method: Method::RandomWalk, steps, ..Default::default() };
+            let mut sampler = Sampler::new(&config, unit_prior(3));
+            assert_eq!(sampler.chain_quality(), None);
+            for _ in 0..200 {
+                sampler.draw(&Unit, -1.0, &live, &mut Collect::default(), &mut rng).unwrap();
+            }
+            quality.push(sampler.chain_quality().unwrap());
+            assert_eq!(sampler.chain_warning().is_some(), steps == 1);
+            correlation.push(sampler.parent_correlation().unwrap());
+            assert_eq!(sampler.parent_warning().is_some(), steps == 1);
+        }
+        assert!(quality[0] < 0.3 && quality[1] > 0.7, "{:?}", quality);
+        assert!(correlation[0] > 0.7 && correlation[1].abs() < 0.15, "{:?}", correlation);
+    }
+
+    #[test]
+    fn test_tuning_reaches_its_targets_and_freezes() {
+        // a narrow Gaussian likelihood, so the prior scale is far too wide
+        struct Narrow;
+
+        impl LogLikelihood for Narrow {
+            fn log_lik(&self, theta: &[f64]) -> f64 {
+                -0.5 * theta.iter().map(|t| (t / 0.05).powi(2)).sum::<f64>()
+            }
+
+            fn dim(&self) -> usize {
+                2
+            }
+        }
+
+        let config = SamplerConfig{ method: Method::RandomWalk, steps: 2, scale: 5.0, ..Default::default() };
+        let settings = TuningConfig{ target_acceptance: 0.3, quantile: 0.9, ..Default::default() };
+        let mut sampler = Sampler::new(&config, unit_prior(2));
+        let mut rng = StdRng::seed_from_u64(418);
+        let report = sampler.tune(&Narrow, &settings, 200, &mut rng).unwrap().unwrap();
+        assert!((report.acceptance - 0.3).abs() < 0.1, "{:?}", report);
+        assert!(report.chain_quality >= MIN_CHAIN_QUALITY && report.steps > 2, "{:?}", report);
+        assert!(report.scale[0] < 5.0);
+        assert_eq!(sampler.chain_quality(), None);
+
+        // the tuned settings stay put during the run
+        let points: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.gen::<f64>() * 0.1 - 0.05, 0.0]).collect();
+        let live = LiveSnapshot::new(0, points.iter().map(|p| (p.as_slice(), Narrow.log_lik(p))));
+        for _ in 0..20 {
+            sampler.draw(&Narrow, -2.0, &live, &mut Collect::default(), &mut rng).unwrap();
+        }
+        assert_eq!(sampler.scale, report.scale);
+        assert_eq!(sampler.steps, report.steps);
+        assert!(sampler.stats().iter().any(|(k, _)| k == "sampler_tuned_acceptance"));
+
+        let mut rejection = Sampler::new(&SamplerConfig::default(), unit_prior(2));
+        assert!(rejection.tune(&Narrow, &settings, 200, &mut rng).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_region_draws_follow_a_prior_peaking_between_the_live_points() {
+        struct Flat;
+
+        impl LogLikelihood for Flat {
+            fn log_lik(&self, _theta: &[f64]) -> f64 {
+                0.0
+            }
+
+            fn dim(&self) -> usize {
+                1
+            }
+        }
+
+        // the prior's mode lies in the region but away from every live point
+        let points = [-3.0, -2.5, 0.5];
+        let live = LiveSnapshot::new(0, points.iter().map(|t| (std::slice::from_ref(t), 0.0)));
+        let mean = points.iter().sum::<f64>() / 3.0;
+        let reach = 1.25 * points.iter().map(|t| (t - mean).abs()).fold(0.0, f64::max);
+        let mass = |a: f64, b: f64| (0..10000).map(|i| (-0.5 * (a + (i as f64 + 0.5) * (b - a) / 1e4).powi(2)).exp()).sum::<f64>() * (b - a);
+        let expected = mass(-0.5, 0.5) / mass(mean - reach, mean + reach);
+
+        let mut sampler = Sampler::new(&SamplerConfig{ method: Method::Region, ..Default::default() }, unit_prior(1));
+        let mut rng = StdRng::seed_from_u64(406);
+        let draws = 40000;
+        let central = (0..draws)
+            .filter(|_| sampler.region(&Flat, -1.0, &live, &mut rng).unwrap().0[0].abs() < 0.5)
+            .count();
+        let found = central as f64 / draws as f64;
+        assert!((found - expected).abs() < 0.01, "{} of the draws near the mode, expected {}", found, expected);
+    }
+
+    #[test]
+    fn test_parallel_chains_do_not_depend_on_the_threads() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let points: Vec<Vec<f64>> = (0..20).map(|_| vec![rng.gen_range(-0.7..0.7), rng.gen_range(-0.7..0.7)]).collect();
+        let live = LiveSnapshot::new(0, points.iter().map(|t| (t.as_slice(), Bowl.log_lik(t))));
+        let config = SamplerConfig{ method: Method::RandomWalk, steps: 5, parallel: true, parallel_chains: 4, ..Default::default() };
+        let mut runs = Vec::new();
+        for threads in [1, 3] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let mut sampler = Sampler::new(&config, unit_prior(2));
+            let mut rng = StdRng::seed_from_u64(387);
+            // the contour rises past points drawn ahead of their turn
+            let draws: Vec<(Vec<f64>, f64)> = pool.install(|| (0..12)
+                .map(|i| {
+                    let threshold = -1.5 + 0.08 * i as f64;
+                    let (theta, log_l) = sampler.draw(&Bowl, threshold, &live, &mut Collect::default(), &mut rng).unwrap();
+                    assert!(log_l > threshold && log_l == Bowl.log_lik(&theta));
+                    (theta, log_l)
+                })
+                .collect());
+            assert!(sampler.chains >= 12);
+            runs.push(draws);
+        }
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn test_parallel_rejection_is_reproducible() {
+        let config = SamplerConfig{ parallel: true, ..Default::default() };
+        let empty = LiveSnapshot::default();
+        let mut draws = Vec::new();
+        for _ in 0..2 {
+            let mut sampler = Sampler::new(&config, unit_prior(1));
+            let mut rng = StdRng::seed_from_u64(387);
+            let (theta, log_l) = sampler.draw(&Slab, -1.0, &empty, &mut Collect::default(), &mut rng).unwrap();
+            assert!(theta[0].abs() < 0.01 && log_l == 0.0);
+            draws.push(theta);
+        }
+        assert_eq!(draws[0], draws[1]);
+    }
+}
+
+
+/// prior draws tried for a single replacement before the run gives up,
+/// e.g. because the likelihood is -inf almost everywhere
+pub(crate) const MAX_ATTEMPTS: usize = 10_000_000;
+/// acceptance rate the random-walk scale is adapted towards
+const TARGET_ACCEPTANCE: f64 = 0.5;
+/// prior draws per task when rejection sampling is spread over threads
+const CHUNK: usize = 64;
+/// chain quality below which the chains are too short
+const MIN_CHAIN_QUALITY: f64 = 0.5;
+/// correlation between new points and the live points their chains
+/// started from above which the chains are too short
+const MAX_PARENT_CORRELATION: f64 = 0.3;
+/// the correlation the recommended chain length aims for
+const TARGET_PARENT_CORRELATION: f64 = 0.1;
+/// draws from the live-point region per replacement before it counts as stalled
+const MAX_REGION_ATTEMPTS: usize = 100_000;
+/// times a hit-and-run slice is stepped out on each side
+const MAX_STEP_OUT: usize = 100;
+/// times a hit-and-run slice is shrunk before the move is given up
+const MAX_SHRINK: usize = 200;
+/// dual averaging: shrinkage towards ten times the initial scale, the
+/// offset damping early updates, and the decay of the averaging weights
+const DA_GAMMA: f64 = 0.05;
+const DA_T0: f64 = 10.0;
+const DA_KAPPA: f64 = 0.75;
+
+
+/// how new live points are drawn above the contour
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// independent draws from the whole prior until one beats the contour
+    #[default]
+    Rejection,
+    /// Metropolis chain started from a copy of a random live point
+    RandomWalk,
+    /// slice sampling along random directions from a copy of a random
+    /// live point, stepping the slice out and shrinking it back; needs
+    /// no covariance and keeps moving in long, narrow regions
+    HitAndRun,
+    /// independent draws from the ellipsoid around the live points,
+    /// enlarged by a quarter, weighted by the prior
+    Region,
+    /// rejection while enough prior draws beat the contour, then the
+    /// region around the live points while enough of its draws do, then
+    /// the random walk; hit-and-run from the start in high dimensions
+    Auto,
+}
+
+
+/// settings of the constrained sampler
+///
+/// Fields:
+/// method: rejection, random walk, hit-and-run, region, or auto
+/// steps: Metropolis steps per random-walk replacement
+/// scale: initial proposal width, in units of the live-set covariance;
+///     adapted after every chain
+/// scales: initial random-walk step of each parameter, in the parameter's
+///     own units; replaces the live-set covariance as the shape of the
+///     proposal, with `scale` multiplying all of them
+/// min_scale, max_scale: bounds on the adapted `scale`
+/// blocks: parameter indices updated together, e.g. `[[0, 1], [2]]` to
+///     move the noise sd separately from the regression coefficients.
+///     Every random-walk step updates each block in turn, and each block
+///     adapts a scale of its own; all parameters form one block if absent
+/// duplicate_tolerance: a new point is a duplicate if every parameter is
+///     within this many live-set standard deviations of a live point
+/// duplicate_window: duplicates are counted over this many replacements
+/// duplicate_fraction: warn once duplicates exceed this fraction of the window
+/// remedy_duplicates: on a warning, double the steps; once max_steps is
+///     reached, switch to rejection sampling
+/// max_steps: upper limit for the doubled steps
+/// parallel: spread the prior draws of rejection sampling over rayon's
+///     thread pool, and run the chains of the random walk, hit-and-run
+///     and region samplers `parallel_chains` at a time on it, all from
+///     one snapshot of the live set; the result does not depend on the
+///     number of threads
+/// parallel_chains: chains run at once from one snapshot; their points
+///     replace the next live points in turn, as long as they beat the
+///     contour risen meanwhile
+/// auto_min_efficiency: in auto mode, move from rejection to the region
+///     around the live points, and from there to the random walk, once
+///     the fraction of draws that beat the contour drops below this,
+///     averaged over recent replacements
+/// auto_max_dim: in auto mode, models with more parameters go straight to
+///     hit-and-run: the efficiency of rejection from the prior and from
+///     an ellipsoid falls off exponentially with dimension
+/// stall_chains: after this many consecutive chains without a single
+///     accepted move, escalate: lengthen the chains, then shrink the
+///     steps, then draw from the region around the live points, and
+///     finally give up
+/// stall_dump: where the live set is written when the sampler gives up;
+///     a file in the temporary directory if absent
+/// tuning: tune the scales and the chain length before the run and keep
+///     them fixed during it, instead of adapting them as it goes
+/// delayed_rejection: when a random-walk proposal fails the contour, try
+///     a second, smaller move of a single parameter before giving up. The
+///     scales still adapt to the acceptance of the first proposals
+/// second_stage_scale: size of the second move relative to the first
+/// degeneracy_every: look for degenerate directions of the live points
+///     every this many draws; 0 never looks
+/// degeneracy_ratio: a direction is degenerate when the variance of the
+///     live points along it, in units of the prior widths, is below this
+///     fraction of the largest
+/// reduce_degenerate: once degenerate directions are found, move the
+///     random walk only along the others
+/// fast: parameters the likelihood recomputes cheaply, e.g. calibration
+///     nuisances of an expensive physical model. A block made of fast
+///     parameters alone is updated `oversample` times for every update of
+///     the others; without `blocks`, the slow parameters form one block
+///     and the fast ones another. Random-walk moves of fast blocks go
+///     through `LogLikelihood::update_fast` for models that implement it
+/// oversample: updates of each fast block per update of a slow block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SamplerConfig {
+    pub method: Method,
+    pub steps: usize,
+    pub scale: f64,
+    pub scales: Option<Vec<f64>>,
+    pub min_scale: f64,
+    pub max_scale: f64,
+    pub blocks: Option<Vec<Vec<usize>>>,
+    pub duplicate_tolerance: f64,
+    pub duplicate_window: usize,
+    pub duplicate_fraction: f64,
+    pub remedy_duplicates: bool,
+    pub max_steps: usize,
+    pub parallel: bool,
+    pub parallel_chains: usize,
+    pub auto_min_efficiency: f64,
+    pub auto_max_dim: usize,
+    pub stall_chains: usize,
+    pub stall_dump: Option<PathBuf>,
+    pub tuning: Option<TuningConfig>,
+    pub delayed_rejection: bool,
+    pub second_stage_scale: f64,
+    pub degeneracy_every: usize,
+    pub degeneracy_ratio: f64,
+    pub reduce_degenerate: bool,
+    pub fast: Option<Vec<usize>>,
+    pub oversample: usize,
+}
+
+
+impl Default for SamplerConfig {
+    fn default() -> SamplerConfig {
+        SamplerConfig{
+            method: Method::Rejection,
+            steps: 25,
+            scale: 1.0,
+            scales: None,
+            min_scale: 0.0,
+            max_scale: f64::INFINITY,
+            blocks: None,
+            duplicate_tolerance: 1e-9,
+            duplicate_window: 100,
+            duplicate_fraction: 0.05,
+            remedy_duplicates: true,
+            max_steps: 1000,
+            parallel: false,
+            parallel_chains: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            auto_min_efficiency: 0.02,
+            auto_max_dim: 10,
+            stall_chains: 50,
+            stall_dump: None,
+            tuning: None,
+            delayed_rejection: false,
+            second_stage_scale: 0.2,
+            degeneracy_every: 100,
+            degeneracy_ratio: 1e-6,
+            reduce_degenerate: false,
+            fast: None,
+            oversample: 5,
+        }
+    }
+}
+
+
+/// settings of the tuning phase run before the sampler starts
+///
+/// Fields:
+/// chains: chains run while the scales are tuned; a quarter as many per
+///     trial chain length afterwards
+/// target_acceptance: acceptance rate the random-walk scales are tuned to
+/// target_quality: the chains are lengthened until they travel this far
+///     towards independent draws (see `Sampler::chain_quality`)
+/// quantile: tune above the contour at this quantile of the likelihoods
+///     of the prior draws, a target closer to the later, narrower
+///     contours; 0 tunes on the prior itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TuningConfig {
+    pub chains: usize,
+    pub target_acceptance: f64,
+    pub target_quality: f64,
+    pub quantile: f64,
+}
+
+
+impl Default for TuningConfig {
+    fn default() -> TuningConfig {
+        TuningConfig{
+            chains: 200,
+            target_acceptance: TARGET_ACCEPTANCE,
+            target_quality: MIN_CHAIN_QUALITY,
+            quantile: 0.0,
+        }
+    }
+}
+
+
+/// the settings a tuning phase chose, and how they did on its last chains
+///
+/// Fields:
+/// scale: the scale of each block
+/// steps: the chain length
+/// acceptance: fraction of proposals accepted
+/// chain_quality: mean travel of the chains (see `Sampler::chain_quality`)
+/// chains: chains run in the whole tuning phase
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TuningReport {
+    pub scale: Vec<f64>,
+    pub steps: usize,
+    pub acceptance: f64,
+    pub chain_quality: f64,
+    pub chains: usize,
+}
+
+
+/// Nesterov's dual averaging of the log of a scale, as used to tune the
+/// step size of NUTS (Hoffman & Gelman 2014). Each update takes the error
+/// of the last chain, positive when the scale should shrink; the iterates
+/// explore, and their weighted average is the tuned value
+struct DualAveraging {
+    mu: f64,
+    h_bar: f64,
+    log_x: f64,
+    log_x_bar: f64,
+    t: f64,
+}
+
+
+impl DualAveraging {
+    fn new(x0: f64) -> DualAveraging {
+        DualAveraging{ mu: (10.0 * x0).ln(), h_bar: 0.0, log_x: x0.ln(), log_x_bar: x0.ln(), t: 0.0 }
+    }
+
+    /// the next scale to try
+    fn update(&mut self, error: f64) -> f64 {
+        self.t += 1.0;
+        let w = 1.0 / (self.t + DA_T0);
+        self.h_bar = (1.0 - w) * self.h_bar + w * error;
+        self.log_x = self.mu - self.t.sqrt() / DA_GAMMA * self.h_bar;
+        let eta = self.t.powf(-DA_KAPPA);
+        self.log_x_bar = eta * self.log_x + (1.0 - eta) * self.log_x_bar;
+        self.log_x.exp()
+    }
+
+    fn value(&self) -> f64 {
+        self.log_x_bar.exp()
+    }
+}
+
+
+/// draw from the prior until a particle's likelihood beats `threshold`;
+/// returns theta, its log-likelihood and the number of draws it took.
+/// Every draw is written into the same buffer, so only the accepted theta
+/// is allocated
+pub(crate) fn sample_above<R: Rng + ?Sized>(
+        prior: &dyn Prior,
+        model: &dyn LogLikelihood,
+        threshold: f64,
+        mut rng: &mut R,
+) -> Result<(Vec<f64>, f64, usize), Box<dyn Error>> {
+    let mut theta = vec![0.0; prior.dim()];
+    for attempt in 1..=MAX_ATTEMPTS {
+        prior.sample_into(&mut theta, &mut rng);
+        let log_l = match model.screen(&theta, threshold) {
+            Screen::Reject => continue,
+            Screen::Pass => model.log_lik(&theta),
+            Screen::Evaluated(ll) => ll,
+        };
+        if log_l > threshold {
+            return Ok((theta, log_l, attempt))
+        }
+    }
+    Err(format!(
+        "no prior draw beat the log-likelihood contour {} in {} attempts",
+        threshold, MAX_ATTEMPTS,
+    ).into())
+}
+
+
+/// `sample_above` with the draws split into chunks of `CHUNK` that run on
+/// rayon's thread pool, a few chunks per thread at a time. Chunk k uses its
+/// own rng seeded from `seed` and k, and the successful chunk with the
+/// lowest index wins, so the result is the same for any number of threads.
+/// Rejection sampling stays exact: which chunk succeeds first is
+/// independent of where its point lies.
+pub(crate) fn sample_above_parallel(
+        prior: &dyn Prior,
+        model: &dyn LogLikelihood,
+        threshold: f64,
+        seed: u64,
+) -> Result<(Vec<f64>, f64, usize), Box<dyn Error>> {
+    let try_chunk = |chunk: usize| {
+        let mut rng = StdRng::seed_from_u64(seed ^ (chunk as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut theta = vec![0.0; prior.dim()];
+        for k in 1..=CHUNK {
+            prior.sample_into(&mut theta, &mut rng);
+            let log_l = match model.screen(&theta, threshold) {
+                Screen::Reject => continue,
+                Screen::Pass => model.log_lik(&theta),
+                Screen::Evaluated(ll) => ll,
+            };
+            if log_l > threshold {
+                return Some((theta, log_l, chunk * CHUNK + k))
+            }
+        }
+        None
+    };
+    // a wave of chunks per round, so at most one wave of work is wasted
+    let wave = 4 * rayon::current_num_threads();
+    let mut start = 0;
+    while start < MAX_ATTEMPTS / CHUNK {
+        let results: Vec<Option<(Vec<f64>, f64, usize)>> = (start..start + wave).into_par_iter().map(try_chunk).collect();
+        if let Some(found) = results.into_iter().flatten().next() {
+            return Ok(found)
+        }
+        start += wave;
+    }
+    Err(format!(
+        "no prior draw beat the log-likelihood contour {} in {} attempts",
+        threshold, MAX_ATTEMPTS,
+    ).into())
+}
+
+
+/// immutable copy of the live set at one generation
+///
+/// The owner of the particles keeps replacing them while worker threads
+/// hold an `Arc` of the snapshot they started from, so every proposal
+/// sees one consistent live set.
+///
+/// Fields:
+/// generation: number of changes to the live set before the copy was taken
+/// dim: parameters per point
+/// theta: parameters of the live points, back to back
+/// log_l: log-likelihood of each live point
+/// moments: mean and covariance of the live points, if the owner tracks them
+/// ids: how the owner knows each live point, if it gave them
+#[derive(Debug, Clone, Default)]
+pub struct LiveSnapshot {
+    generation: usize,
+    dim: usize,
+    theta: Vec<f64>,
+    log_l: Vec<f64>,
+    moments: Option<(Vec<f64>, Matrix)>,
+    ids: Vec<usize>,
+}
+
+
+impl LiveSnapshot {
+    pub fn new<'a>(generation: usize, points: impl Iterator<Item = (&'a [f64], f64)>) -> LiveSnapshot {
+        let mut snapshot = LiveSnapshot{ generation, ..Default::default() };
+        for (theta, log_l) in points {
+            snapshot.dim = theta.len();
+            snapshot.theta.extend_from_slice(theta);
+            snapshot.log_l.push(log_l);
+        }
+        snapshot
+    }
+
+    /// attach the mean and covariance of the points, so that samplers need
+    /// not compute them again
+    pub fn with_moments(mut self, mean: Vec<f64>, cov: Matrix) -> LiveSnapshot {
+        self.moments = Some((mean, cov));
+        self
+    }
+
+    /// attach the owner's id of each point, which provenance records then
+    /// name the seeds of chains by, since a chain may be drawn from a
+    /// snapshot older than the live set its point joins
+    pub fn with_ids(mut self, ids: Vec<usize>) -> LiveSnapshot {
+        self.ids = ids;
+        self
+    }
+
+    /// the owner's id of the k-th live point, or k if it gave none
+    pub fn id(&self, k: usize) -> usize {
+        self.ids.get(k).copied().unwrap_or(k)
+    }
+
+    /// mean and covariance of the live points
+    pub fn moments(&self) -> (Vec<f64>, Matrix) {
+        match &self.moments {
+            Some(moments) => moments.clone(),
+            None => geometry::mean_cov(self.iter().map(|(t, _)| t)),
+        }
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.log_l.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log_l.is_empty()
+    }
+
+    /// parameters of the k-th live point
+    pub fn theta(&self, k: usize) -> &[f64] {
+        &self.theta[k * self.dim..(k + 1) * self.dim]
+    }
+
+    /// the live points as (theta, log-likelihood) pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&[f64], f64)> {
+        (0..self.len()).map(|k| self.theta(k)).zip(self.log_l.iter().copied())
+    }
+
+    /// per-parameter standard deviation of the live points, falling back
+    /// to `fallback` while there are too few of them
+    pub fn spread(&self, fallback: &[f64]) -> Vec<f64> {
+        if self.len() < 2 {
+            return fallback.to_vec()
+        }
+        let (_, cov) = self.moments();
+        (0..fallback.len()).map(|j| cov[(j, j)].sqrt()).collect()
+    }
+
+    /// whitening of the live points, None while there are too few of
+    /// them to span every parameter
+    pub fn whitening(&self) -> Option<Whitening> {
+        if self.len() <= self.dim {
+            return None
+        }
+        let (mean, cov) = self.moments();
+        Whitening::new(mean, &cov)
+    }
+}
+
+
+/// where a particle came from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// one of the first live points, drawn from the whole prior
+    Initial,
+    Rejection,
+    RandomWalk,
+    HitAndRun,
+    /// the region around the live points, by choice, in auto mode or
+    /// after chains stalled
+    Region,
+}
+
+
+/// how one particle was drawn, for tracing sampler pathologies to the
+/// draws that caused them
+///
+/// Fields:
+/// origin: the sampler that produced it
+/// likelihood_calls: likelihood evaluations the draw took, including ones
+///     answered from a cache
+/// seed: the live point its chain started from, as the snapshot it was
+///     drawn from names it (see `LiveSnapshot::id`); None for independent
+///     draws
+/// accepted_steps: accepted moves along its chain
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Provenance {
+    pub origin: Origin,
+    pub likelihood_calls: usize,
+    pub seed: Option<usize>,
+    pub accepted_steps: usize,
+}
+
+
+impl Provenance {
+    /// one of the first live points
+    pub fn initial() -> Provenance {
+        Provenance{ origin: Origin::Initial, likelihood_calls: 1, seed: None, accepted_steps: 0 }
+    }
+}
+
+
+/// the constrained sampler and the state it adapts during a run
+///
+/// Every new point is compared with the live set. A sampler that keeps
+/// returning (near) copies of live points, typically a random walk that
+/// rejects every proposal, breaks the assumption that new points are
+/// independent draws from the constrained prior and silently biases the
+/// shrinkage; when that happens the observer is warned and, if enabled,
+/// the chains are lengthened or the sampler falls back to rejection.
+///
+/// Fields:
+/// config: the settings
+/// prior: the distribution new points are drawn from
+/// method: current method, which may have been switched by the remedy
+/// steps: current random-walk steps per replacement
+/// blocks: parameters updated together by the random walk
+/// fast_blocks: which blocks hold fast parameters only
+/// repeats: updates of each block per step, `oversample` for fast blocks
+///     and 1 for the others
+/// slow: the parameters not declared fast
+/// states: slow-parameter states of the model at recent points
+/// scale: current random-walk proposal width of each block
+/// recent: duplicate flags of the most recent replacements
+/// draws, duplicates: replacements made, and how many were duplicates
+/// proposed, accepted: random-walk moves proposed and accepted
+/// auto: whether the auto mode may still switch methods
+/// efficiency: moving average of the fraction of prior draws that beat
+///     the contour in rejection sampling
+/// switched: replacement at which the auto mode left rejection sampling
+/// stalled: consecutive chains without an accepted move
+/// escalation: remedies applied to stalled chains so far
+/// chains: random-walk or hit-and-run chains run
+/// travel: sum over the chains of the squared distance from start to end,
+///     in live-set standard deviations, over that of two independent
+///     live points
+/// signals: how far each block's last chain was from its target, as the
+///     log of the factor its scale is adapted by
+/// parent_cov, parent_var: sums over the chains of the product of start
+///     and end, and of their mean square, in coordinates whitened by the
+///     live points; their ratio is the correlation of new points with
+///     their seeds
+/// frozen: the scales were tuned before the run and no longer adapt
+/// tuning: what the tuning phase chose, if there was one
+/// seed: live point the current chain started from
+/// last: how the latest point was drawn
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    config: SamplerConfig,
+    prior: Arc<dyn Prior>,
+    method: Method,
+    steps: usize,
+    blocks: Vec<Vec<usize>>,
+    fast_blocks: Vec<bool>,
+    repeats: Vec<usize>,
+    slow: Vec<usize>,
+    states: StateCache,
+    scale: Vec<f64>,
+    recent: VecDeque<bool>,
+    draws: usize,
+    duplicates: usize,
+    proposed: usize,
+    accepted: usize,
+    second_tries: usize,
+    second_accepted: usize,
+    auto: bool,
+    efficiency: f64,
+    switched: Option<usize>,
+    stalled: usize,
+    escalation: Escalation,
+    chains: usize,
+    travel: f64,
+    parent_cov: f64,
+    parent_var: f64,
+    signals: Vec<f64>,
+    frozen: bool,
+    tuning: Option<TuningReport>,
+    seed: Option<usize>,
+    last: Option<Provenance>,
+    degenerate: Vec<Vec<f64>>,
+    subspace: Option<Matrix>,
+    pending: VecDeque<Drawn>,
+}
+
+
+/// a point drawn above a contour, possibly ahead of its turn on a worker
+/// thread
+///
+/// Fields:
+/// theta, log_l: the point
+/// threshold: the contour it was drawn above
+/// moved: whether its chain left the live point it started from
+/// provenance: how it was drawn
+#[derive(Debug, Clone)]
+struct Drawn {
+    theta: Vec<f64>,
+    log_l: f64,
+    threshold: f64,
+    moved: bool,
+    provenance: Provenance,
+}
+
+
+/// a linear combination of the parameters as text, e.g.
+/// "theta[0] - 0.5 theta[2]", leaving out negligible coefficients
+fn combination(coefficients: &[f64]) -> String {
+    let mut text = String::new();
+    for (j, a) in coefficients.iter().enumerate().filter(|(_, a)| a.abs() >= 1e-3) {
+        let sign = if *a < 0.0 { "-" } else { "+" };
+        let size = match (a.abs() - 1.0).abs() < 1e-3 {
+            true => String::new(),
+            false => format!("{:.3} ", a.abs()),
+        };
+        match text.is_empty() {
+            true if *a < 0.0 => text.push_str(&format!("-{}theta[{}]", size, j)),
+            true => text.push_str(&format!("{}theta[{}]", size, j)),
+            false => text.push_str(&format!(" {} {}theta[{}]", sign, size, j)),
+        }
+    }
+    text
+}
+
+
+/// slow-parameter states of the model at recent points, keyed by their
+/// slow parameters, so that chains starting from a live point can move
+/// its fast parameters without recomputing the slow part. Clones start
+/// empty, since the states cannot be copied
+#[derive(Default)]
+struct StateCache {
+    entries: VecDeque<(Vec<f64>, SlowState)>,
+}
+
+
+impl StateCache {
+    fn take(&mut self, key: &[f64]) -> Option<SlowState> {
+        let i = self.entries.iter().position(|(k, _)| k.as_slice() == key)?;
+        self.entries.remove(i).map(|(_, state)| state)
+    }
+
+    /// keep `state`, forgetting the oldest states beyond `capacity`
+    fn put(&mut self, key: Vec<f64>, state: SlowState, capacity: usize) {
+        self.entries.push_back((key, state));
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+}
+
+
+impl Clone for StateCache {
+    fn clone(&self) -> StateCache {
+        StateCache::default()
+    }
+}
+
+
+impl fmt::Debug for StateCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StateCache({} states)", self.entries.len())
+    }
+}
+
+
+/// log L at `point`, a move from `current` of a fast block or a slow one.
+/// Fast moves reuse `state`, the slow-parameter state at `current`,
+/// computing it first if need be; slow moves also return the state at
+/// `point` when the model keeps one
+fn evaluate_move(
+        model: &dyn LogLikelihood,
+        point: &[f64],
+        current: &[f64],
+        threshold: f64,
+        fast: bool,
+        state: &mut Option<SlowState>,
+) -> (f64, Option<SlowState>) {
+    match model.screen(point, threshold) {
+        Screen::Reject => return (f64::NEG_INFINITY, None),
+        Screen::Evaluated(ll) => return (ll, None),
+        Screen::Pass => {},
+    }
+    if !fast {
+        return match model.log_lik_state(point) {
+            Some((ll, fresh)) => (ll, Some(fresh)),
+            None => (model.log_lik(point), None),
+        }
+    }
+    if state.is_none() {
+        *state = model.log_lik_state(current).map(|(_, state)| state);
+    }
+    match state {
+        Some(state) => (model.update_fast(state, point), None),
+        None => (model.log_lik(point), None),
+    }
+}
+
+
+/// the second proposal of a delayed-rejection step in one block
+///
+/// After a first proposal y1 from x passes the prior test but fails the
+/// contour, a single parameter j of the block moves by gamma * sd_j * scale
+/// times a standard normal, to y2. The move is symmetric and does not
+/// depend on y1, so with p the prior density and q the density of the
+/// first proposal, y2 is accepted when it beats the contour and with
+/// probability
+///
+/// ```text
+/// min(1, min(p(y2), p(y1)) q(y2 -> y1) / (min(p(x), p(y1)) q(x -> y1)))
+/// ```
+///
+/// the rule of Tierney and Mira (1999) for a first stage that rejects y1
+/// with probability min(1, p(y1) / p(.)) whenever y1 fails the contour.
+///
+/// Fields:
+/// chol: Cholesky factor of the block's part of the first proposal's
+///     covariance, before the scale
+/// sd: step of each parameter of the block in the second move, before
+///     the scale
+struct SecondStage {
+    chol: Cholesky,
+    sd: Vec<f64>,
+}
+
+
+impl SecondStage {
+    /// None when the block's covariance is degenerate
+    fn new(block: &[usize], whitening: Option<&Whitening>, spread: &[f64], gamma: f64) -> Option<SecondStage> {
+        let rows: Vec<Vec<f64>> = match whitening {
+            Some(w) => {
+                let l = w.chol().l();
+                block.iter()
+                    .map(|&i| block.iter().map(|&j| zip(l.row(i), l.row(j)).map(|(a, b)| a * b).sum()).collect())
+                    .collect()
+            },
+            None => block.iter()
+                .map(|&i| block.iter().map(|&j| if i == j { spread[i] * spread[i] } else { 0.0 }).collect())
+                .collect(),
+        };
+        let sd = rows.iter().enumerate().map(|(k, row)| gamma * row[k].sqrt()).collect();
+        let chol = Matrix::from_rows(rows).cholesky()?;
+        Some(SecondStage{ chol, sd })
+    }
+
+    /// log q(y2 -> y1) - log q(x -> y1) for the first proposal of the block
+    fn log_q_ratio(&self, block: &[usize], x: &[f64], y1: &[f64], y2: &[f64], scale: f64) -> f64 {
+        let from = |start: &[f64]| {
+            let d: Vec<f64> = block.iter().map(|&j| y1[j] - start[j]).collect();
+            self.chol.quad_form(&d) / (scale * scale)
+        };
+        -0.5 * (from(y2) - from(x))
+    }
+}
+
+
+/// the adapted part of a sampler, kept in checkpoints so that a resumed
+/// run carries on with the same method, steps and scale (see `Sampler`
+/// for the fields)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplerState {
+    method: Method,
+    steps: usize,
+    scale: Vec<f64>,
+    recent: VecDeque<bool>,
+    draws: usize,
+    duplicates: usize,
+    proposed: usize,
+    accepted: usize,
+    second_tries: usize,
+    second_accepted: usize,
+    auto: bool,
+    efficiency: f64,
+    switched: Option<usize>,
+    stalled: usize,
+    escalation: Escalation,
+    chains: usize,
+    travel: f64,
+    parent_cov: f64,
+    parent_var: f64,
+    frozen: bool,
+    tuning: Option<TuningReport>,
+}
+
+
+/// remedies for chains that stop moving, in the order they are tried
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+enum Escalation {
+    #[default]
+    None,
+    Lengthened,
+    Shrunk,
+    Region,
+}
+
+
+impl Sampler {
+    pub fn new(config: &SamplerConfig, prior: Arc<dyn Prior>) -> Sampler {
+        let method = match config.method {
+            Method::Auto if prior.dim() > config.auto_max_dim => Method::HitAndRun,
+            Method::Auto => Method::Rejection,
+            method => method,
+        };
+        let dim = prior.dim();
+        let blocks = match (&config.blocks, &config.fast) {
+            (Some(blocks), _) => blocks.clone(),
+            (None, Some(fast)) if !fast.is_empty() && fast.len() < dim => {
+                vec![(0..dim).filter(|j| !fast.contains(j)).collect(), fast.clone()]
+            },
+            _ => vec![(0..dim).collect()],
+        };
+        let fast_blocks: Vec<bool> = blocks.iter()
+            .map(|block| config.fast.as_ref().is_some_and(|fast| block.iter().all(|j| fast.contains(j))))
+            .collect();
+        let repeats = fast_blocks.iter().map(|&fast| if fast { config.oversample.max(1) } else { 1 }).collect();
+        let slow = (0..dim).filter(|j| !config.fast.as_ref().is_some_and(|fast| fast.contains(j))).collect();
+        Sampler{
+            config: config.clone(),
+            prior,
+            method,
+            steps: config.steps.max(1),
+            blocks: blocks.clone(),
+            fast_blocks,
+            repeats,
+            slow,
+            states: StateCache::default(),
+            scale: vec![config.scale; blocks.len()],
+            recent: VecDeque::new(),
+            draws: 0,
+            duplicates: 0,
+            proposed: 0,
+            accepted: 0,
+            second_tries: 0,
+            second_accepted: 0,
+            auto: config.method == Method::Auto && method == Method::Rejection,
+            efficiency: 1.0,
+            switched: None,
+            stalled: 0,
+            escalation: Escalation::None,
+            chains: 0,
+            travel: 0.0,
+            parent_cov: 0.0,
+            parent_var: 0.0,
+            signals: vec![0.0; blocks.len()],
+            frozen: false,
+            tuning: None,
+            seed: None,
+            last: None,
+            degenerate: Vec::new(),
+            subspace: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn prior(&self) -> &Arc<dyn Prior> {
+        &self.prior
+    }
+
+    /// how the point returned by the latest `draw` was drawn
+    pub fn provenance(&self) -> Option<Provenance> {
+        self.last
+    }
+
+    pub fn state(&self) -> SamplerState {
+        SamplerState{
+            method: self.method,
+            steps: self.steps,
+            scale: self.scale.clone(),
+            recent: self.recent.clone(),
+            draws: self.draws,
+            duplicates: self.duplicates,
+            proposed: self.proposed,
+            accepted: self.accepted,
+            second_tries: self.second_tries,
+            second_accepted: self.second_accepted,
+            auto: self.auto,
+            efficiency: self.efficiency,
+            switched: self.switched,
+            stalled: self.stalled,
+            escalation: self.escalation,
+            chains: self.chains,
+            travel: self.travel,
+            parent_cov: self.parent_cov,
+            parent_var: self.parent_var,
+            frozen: self.frozen,
+            tuning: self.tuning.clone(),
+        }
+    }
+
+    /// carry on from a saved state
+    pub fn restore(&mut self, state: SamplerState) {
+        self.method = state.method;
+        self.steps = state.steps;
+        self.scale = state.scale;
+        self.recent = state.recent;
+        self.draws = state.draws;
+        self.duplicates = state.duplicates;
+        self.proposed = state.proposed;
+        self.accepted = state.accepted;
+        self.second_tries = state.second_tries;
+        self.second_accepted = state.second_accepted;
+        self.auto = state.auto;
+        self.efficiency = state.efficiency;
+        self.switched = state.switched;
+        self.stalled = state.stalled;
+        self.escalation = state.escalation;
+        self.chains = state.chains;
+        self.travel = state.travel;
+        self.parent_cov = state.parent_cov;
+        self.parent_var = state.parent_var;
+        self.frozen = state.frozen;
+        self.tuning = state.tuning;
+        self.pending.clear();
+    }
+
+    /// tune the scales, then the chain length, on chains started from
+    /// `points` prior draws, and freeze both for the rest of the run.
+    /// The scales follow dual averaging towards the target acceptance
+    /// (for hit-and-run, towards balanced stepping out and shrinking);
+    /// the chain length doubles until the chains reach the target
+    /// quality or max_steps. None for rejection sampling, which has
+    /// nothing to tune
+    pub fn tune<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            settings: &TuningConfig,
+            points: usize,
+            mut rng: &mut R,
+    ) -> Result<Option<TuningReport>, Box<dyn Error>> {
+        if !matches!(self.method, Method::RandomWalk | Method::HitAndRun) {
+            return Ok(None)
+        }
+        let mut draws: Vec<(Vec<f64>, f64)> = (0..points.max(2))
+            .map(|_| {
+                let mut theta = vec![0.0; self.prior.dim()];
+                self.prior.sample_into(&mut theta, &mut rng);
+                let log_l = model.log_lik(&theta);
+                (theta, log_l)
+            })
+            .collect();
+        draws.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let cut = ((settings.quantile * draws.len() as f64) as usize).min(draws.len() - 2);
+        let threshold = if cut == 0 { f64::NEG_INFINITY } else { draws[cut - 1].1 };
+        let live = LiveSnapshot::new(0, draws[cut..].iter().map(|(t, l)| (t.as_slice(), *l)));
+        if live.log_l.iter().all(|l| *l == f64::NEG_INFINITY) {
+            return Err("no prior draw has a finite likelihood to tune the sampler on".into())
+        }
+        let spread = live.spread(&self.prior.scale());
+        let chain = |sampler: &mut Sampler, rng: &mut R| match sampler.method {
+            Method::HitAndRun => sampler.hit_and_run(model, threshold, &live, &spread, rng),
+            _ => sampler.random_walk(model, threshold, &live, &spread, rng),
+        };
+        let offset = match self.method {
+            Method::RandomWalk => TARGET_ACCEPTANCE - settings.target_acceptance,
+            _ => 0.0,
+        };
+
+        // the scales, with the chains as they are
+        self.frozen = true;
+        let mut averages: Vec<DualAveraging> = self.scale.iter().map(|s| DualAveraging::new(*s)).collect();
+        for _ in 0..settings.chains {
+            chain(self, rng);
+            for ((scale, average), signal) in self.scale.iter_mut().zip(&mut averages).zip(&self.signals) {
+                *scale = average.update(-(signal + offset)).clamp(self.config.min_scale, self.config.max_scale);
+            }
+        }
+        for (scale, average) in self.scale.iter_mut().zip(&averages) {
+            *scale = average.value().clamp(self.config.min_scale, self.config.max_scale);
+        }
+
+        // then the chain length, with the scales fixed
+        let round = (settings.chains / 4).max(10);
+        let mut chains = settings.chains;
+        loop {
+            self.reset_chains();
+            for _ in 0..round {
+                chain(self, rng);
+            }
+            chains += round;
+            let quality = self.chain_quality().unwrap_or(0.0);
+            if quality >= settings.target_quality || self.steps >= self.config.max_steps {
+                break
+            }
+            self.steps = (2 * self.steps).min(self.config.max_steps);
+        }
+        let report = TuningReport{
+            scale: self.scale.clone(),
+            steps: self.steps,
+            acceptance: self.accepted as f64 / self.proposed.max(1) as f64,
+            chain_quality: self.chain_quality().unwrap_or(0.0),
+            chains,
+        };
+        self.reset_chains();
+        self.tuning = Some(report.clone());
+        Ok(Some(report))
+    }
+
+    /// move each block's scale by its last signal, unless the scales were
+    /// tuned before the run
+    fn adapt(&mut self) {
+        if self.frozen {
+            return
+        }
+        for (scale, signal) in self.scale.iter_mut().zip(&self.signals) {
+            *scale = (*scale * signal.exp()).clamp(self.config.min_scale, self.config.max_scale);
+        }
+    }
+
+    /// a new point from the prior above `threshold`, given a snapshot of
+    /// the current live points
+    pub fn draw<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            observer: &mut dyn Observer,
+            rng: &mut R,
+    ) -> Result<(Vec<f64>, f64), Box<dyn Error>> {
+        let every = self.config.degeneracy_every;
+        if every > 0 && self.draws.is_multiple_of(every) && live.len() > live.dim {
+            self.diagnose(live, observer);
+        }
+        let spread = live.spread(&self.prior.scale());
+        let ahead = self.config.parallel && !live.is_empty()
+            && (self.escalation == Escalation::Region || matches!(self.method, Method::RandomWalk | Method::HitAndRun | Method::Region));
+        if !ahead {
+            self.pending.clear();
+        }
+        let drawn = match ahead {
+            true => self.draw_ahead(model, threshold, live, &spread, rng)?,
+            false => self.propose(model, threshold, live, &spread, rng)?,
+        };
+        self.last = Some(drawn.provenance);
+        self.check_duplicate(&drawn.theta, live, &spread, observer);
+        if drawn.moved {
+            self.stalled = 0;
+        } else {
+            self.stalled += 1;
+            if self.stalled >= self.config.stall_chains {
+                self.stalled = 0;
+                // points drawn ahead were drawn the way that stalled
+                self.pending.clear();
+                self.escalate(threshold, live, observer)?;
+            }
+        }
+        Ok((drawn.theta, drawn.log_l))
+    }
+
+    /// one point above `threshold` by the current method
+    fn propose<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            spread: &[f64],
+            rng: &mut R,
+    ) -> Result<Drawn, Box<dyn Error>> {
+        let counted = Counted::new(model);
+        let model: &dyn LogLikelihood = &counted;
+        // whether the draw left the live point it started from
+        let accepted = self.accepted;
+        let mut moved = true;
+        self.seed = None;
+        let (origin, (theta, log_l)) = match self.method {
+            _ if (self.escalation == Escalation::Region || self.method == Method::Region) && !live.is_empty() => {
+                let proposed = self.proposed;
+                let found = self.region(model, threshold, live, rng);
+                self.update_efficiency((self.proposed - proposed).max(1), live);
+                match found {
+                    Some(found) => (Origin::Region, found),
+                    // the region was the last remedy of stalled chains
+                    None if self.escalation == Escalation::Region => return Err(self.stuck(threshold, live)),
+                    // a chain from a live point instead of a copy of it
+                    None => {
+                        let found = self.random_walk(model, threshold, live, spread, rng);
+                        moved = self.accepted > accepted;
+                        (Origin::RandomWalk, found)
+                    },
+                }
+            },
+            Method::RandomWalk if !live.is_empty() => {
+                let found = self.random_walk(model, threshold, live, spread, rng);
+                moved = self.accepted > accepted;
+                (Origin::RandomWalk, found)
+            },
+            Method::HitAndRun if !live.is_empty() => {
+                let found = self.hit_and_run(model, threshold, live, spread, rng);
+                moved = self.accepted > accepted;
+                (Origin::HitAndRun, found)
+            },
+            _ => {
+                let (theta, log_l, attempts) = if self.config.parallel {
+                    sample_above_parallel(self.prior.as_ref(), model, threshold, rng.gen())?
+                } else {
+                    sample_above(self.prior.as_ref(), model, threshold, rng)?
+                };
+                self.update_efficiency(attempts, live);
+                (Origin::Rejection, (theta, log_l))
+            },
+        };
+        let provenance = Provenance{
+            origin,
+            likelihood_calls: counted.calls(),
+            seed: self.seed.map(|k| live.id(k)),
+            accepted_steps: self.accepted - accepted,
+        };
+        Ok(Drawn{ theta, log_l, threshold, moved, provenance })
+    }
+
+    /// the next point drawn ahead, or else `parallel_chains` new ones
+    /// drawn at once from `live` on rayon's thread pool. Each chain runs on
+    /// a clone of the sampler with an rng seeded from `rng` in turn, and
+    /// the points are used in that order, so the run does not depend on
+    /// the number of threads. A point drawn above an earlier, lower contour
+    /// is a draw above this one if it beats it, and is dropped otherwise,
+    /// as are points drawn above a higher one, e.g. by another batch of a
+    /// dynamic run
+    fn draw_ahead<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            spread: &[f64],
+            rng: &mut R,
+    ) -> Result<Drawn, Box<dyn Error>> {
+        while let Some(drawn) = self.pending.pop_front() {
+            if drawn.threshold == threshold || (drawn.threshold < threshold && drawn.log_l > threshold) {
+                return Ok(drawn)
+            }
+        }
+        let workers: Vec<(Sampler, u64)> = (0..self.config.parallel_chains.max(1))
+            .map(|_| (self.clone(), rng.gen()))
+            .collect();
+        let results: Vec<(Sampler, Result<Drawn, String>)> = workers.into_par_iter()
+            .map(|(mut worker, seed)| {
+                let drawn = worker.propose(model, threshold, live, spread, &mut StdRng::seed_from_u64(seed));
+                (worker, drawn.map_err(|e| e.to_string()))
+            })
+            .collect();
+        let (workers, drawn): (Vec<Sampler>, Vec<Result<Drawn, String>>) = results.into_iter().unzip();
+        self.merge(&workers);
+        for drawn in drawn {
+            self.pending.push_back(drawn?);
+        }
+        Ok(self.pending.pop_front().expect("at least one chain"))
+    }
+
+    /// take in what the chains run on `workers`, clones of this sampler,
+    /// learned: their counters add up, the scales become the geometric
+    /// mean of theirs, and the method, as auto mode may have switched it,
+    /// and the latest signals are the last one's
+    fn merge(&mut self, workers: &[Sampler]) {
+        let Some(last) = workers.last() else { return };
+        let proposed: usize = workers.iter().map(|w| w.proposed - self.proposed).sum();
+        let accepted: usize = workers.iter().map(|w| w.accepted - self.accepted).sum();
+        let second_tries: usize = workers.iter().map(|w| w.second_tries - self.second_tries).sum();
+        let second_accepted: usize = workers.iter().map(|w| w.second_accepted - self.second_accepted).sum();
+        let chains: usize = workers.iter().map(|w| w.chains - self.chains).sum();
+        let travel: f64 = workers.iter().map(|w| w.travel - self.travel).sum();
+        let parent_cov: f64 = workers.iter().map(|w| w.parent_cov - self.parent_cov).sum();
+        let parent_var: f64 = workers.iter().map(|w| w.parent_var - self.parent_var).sum();
+        self.proposed += proposed;
+        self.accepted += accepted;
+        self.second_tries += second_tries;
+        self.second_accepted += second_accepted;
+        self.chains += chains;
+        self.travel += travel;
+        self.parent_cov += parent_cov;
+        self.parent_var += parent_var;
+        for (b, scale) in self.scale.iter_mut().enumerate() {
+            *scale = (workers.iter().map(|w| w.scale[b].ln()).sum::<f64>() / workers.len() as f64).exp();
+        }
+        self.signals = last.signals.clone();
+        (self.method, self.auto, self.efficiency) = (last.method, last.auto, last.efficiency);
+        self.switched = self.switched.or(workers.iter().find_map(|w| w.switched));
+    }
+
+    /// the linear combinations of the parameters along which the live
+    /// points have all but no spread, each scaled so its largest
+    /// coefficient is one, as of the latest check
+    pub fn degenerate_directions(&self) -> &[Vec<f64>] {
+        &self.degenerate
+    }
+
+    /// eigen-decompose the covariance of the live points in units of the
+    /// prior widths, and take the directions of the smallest eigenvalues as
+    /// degenerate. With `reduce_degenerate` the random walk then steps
+    /// along the remaining eigenvectors only, each scaled by its own
+    /// spread. The degenerate combinations are left as they are in the
+    /// live point a chain starts from, so they vary only between the live
+    /// points, which earlier draws put on the degenerate surface
+    fn diagnose(&mut self, live: &LiveSnapshot, observer: &mut dyn Observer) {
+        let widths = self.prior.scale();
+        let n = widths.len();
+        let (_, cov) = live.moments();
+        let mut scaled = Matrix::zeros(n, n);
+        for i in 0..n {
+            for j in 0..n {
+                scaled[(i, j)] = cov[(i, j)] / (widths[i] * widths[j]);
+            }
+        }
+        let (values, vectors) = scaled.symmetric_eigen();
+        let largest = values.last().copied().unwrap_or(0.0);
+        if !(largest > 0.0 && largest.is_finite()) {
+            return
+        }
+        let pinned: Vec<usize> = (0..n).filter(|k| values[*k] < self.config.degeneracy_ratio * largest).collect();
+        let directions: Vec<Vec<f64>> = pinned.iter()
+            .map(|&k| {
+                let a: Vec<f64> = (0..n).map(|j| vectors[(j, k)] / widths[j]).collect();
+                let lead = a.iter().fold(0.0f64, |m, x| if x.abs() > m.abs() { *x } else { m });
+                a.iter().map(|x| x / lead).collect()
+            })
+            .collect();
+        if directions.len() > self.degenerate.len() {
+            let listed: Vec<String> = directions.iter().map(|a| combination(a)).collect();
+            observer.warn(&format!(
+                "the live points are degenerate along {} direction(s), where they have all but no spread: {}{}",
+                directions.len(),
+                listed.join("; "),
+                if self.config.reduce_degenerate { "; the random walk now moves along the others only" } else { "" },
+            ));
+        }
+        self.degenerate = directions;
+        self.subspace = match self.config.reduce_degenerate && !pinned.is_empty() && pinned.len() < n {
+            true => {
+                let rows = (pinned.len()..n)
+                    .map(|k| (0..n).map(|j| widths[j] * values[k].max(0.0).sqrt() * vectors[(j, k)]).collect())
+                    .collect();
+                Some(Matrix::from_rows(rows))
+            },
+            false => None,
+        };
+    }
+
+    /// apply the next remedy for stalled chains, or give up with the live
+    /// set written out for inspection
+    fn escalate(
+            &mut self,
+            threshold: f64,
+            live: &LiveSnapshot,
+            observer: &mut dyn Observer,
+    ) -> Result<(), Box<dyn Error>> {
+        let chains = self.config.stall_chains;
+        if self.escalation < Escalation::Lengthened && self.steps < self.config.max_steps {
+            self.escalation = Escalation::Lengthened;
+            self.steps = (4 * self.steps).min(self.config.max_steps);
+            observer.warn(&format!("{} chains in a row found no new point; chains lengthened to {} steps", chains, self.steps));
+        } else if self.escalation < Escalation::Shrunk {
+            self.escalation = Escalation::Shrunk;
+            for scale in self.scale.iter_mut() {
+                *scale = (*scale / 10.0).max(self.config.min_scale);
+            }
+            observer.warn(&format!("{} chains in a row found no new point; proposal steps shrunk tenfold", chains));
+        } else if self.escalation < Escalation::Region {
+            self.escalation = Escalation::Region;
+            observer.warn(&format!(
+                "{} chains in a row found no new point; drawing from the region around the live points instead",
+                chains,
+            ));
+        } else {
+            return Err(self.stuck(threshold, live))
+        }
+        Ok(())
+    }
+
+    /// the error of a sampler that ran out of remedies, with the live set
+    /// written out for inspection
+    fn stuck(&self, threshold: f64, live: &LiveSnapshot) -> Box<dyn Error> {
+        let path = match &self.config.stall_dump {
+            Some(path) => path.clone(),
+            None => std::env::temp_dir().join(format!("ns-stall-{}.csv", std::process::id())),
+        };
+        let written = write_live(&path, live).map(|_| format!("the live set is in {}", path.display()));
+        format!(
+            "the sampler is stuck: nothing above log L* = {} after lengthening the chains, shrinking the steps \
+             and drawing from the region around the {} live points; {}",
+            threshold, live.len(), written.unwrap_or_else(|e| format!("writing the live set failed: {}", e)),
+        ).into()
+    }
+
+    /// rejection sampling from the ellipsoid around the live points,
+    /// enlarged by a quarter, weighting uniform draws in it by the prior
+    /// density relative to the prior's largest value anywhere. Priors that
+    /// do not know that value are drawn from instead, keeping the draws
+    /// that fall in the ellipsoid. None if there are too few live points
+    /// or no draw beat the contour
+    fn region<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            mut rng: &mut R,
+    ) -> Option<(Vec<f64>, f64)> {
+        let whitening = live.whitening()?;
+        let norm = |z: &[f64]| z.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let radius = 1.25 * live.iter().map(|(t, _)| norm(&whitening.whiten(t))).fold(0.0, f64::max);
+        let log_bound = self.prior.max_log_density();
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        let dim = live.dim as f64;
+        let mut z = vec![0.0; live.dim];
+        let mut theta = vec![0.0; live.dim];
+        for _ in 0..MAX_REGION_ATTEMPTS {
+            self.proposed += 1;
+            match log_bound {
+                Some(log_bound) => {
+                    z.iter_mut().for_each(|v| *v = unit.sample(rng));
+                    let r = radius * rng.gen::<f64>().powf(1.0 / dim) / norm(&z);
+                    z.iter_mut().for_each(|v| *v *= r);
+                    theta = whitening.unwhiten(&z);
+                    if rng.gen::<f64>().ln() >= self.prior.log_density(&theta) - log_bound {
+                        continue
+                    }
+                },
+                None => {
+                    self.prior.sample_into(&mut theta, &mut rng);
+                    if norm(&whitening.whiten(&theta)) > radius {
+                        continue
+                    }
+                },
+            }
+            let log_l = match model.screen(&theta, threshold) {
+                Screen::Reject => continue,
+                Screen::Pass => model.log_lik(&theta),
+                Screen::Evaluated(ll) => ll,
+            };
+            if log_l > threshold {
+                return Some((theta, log_l))
+            }
+        }
+        None
+    }
+
+    /// in auto mode, move on once too few draws beat the contour: from
+    /// rejection to the region around the live points, if there are enough
+    /// of them to span it, and from the region to the random walk. The
+    /// contour only rises, so every switch is final
+    fn update_efficiency(&mut self, attempts: usize, live: &LiveSnapshot) {
+        if !self.auto {
+            return
+        }
+        // average over roughly the last 20 replacements
+        self.efficiency += 0.05 * (1.0 / attempts as f64 - self.efficiency);
+        if self.efficiency < self.config.auto_min_efficiency && !live.is_empty() {
+            self.method = match self.method {
+                Method::Rejection if live.len() > live.dim => Method::Region,
+                _ => Method::RandomWalk,
+            };
+            self.auto = self.method == Method::Region;
+            self.efficiency = 1.0;
+            self.switched.get_or_insert(self.draws);
+        }
+    }
+
+    fn random_walk<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            spread: &[f64],
+            rng: &mut R,
+    ) -> (Vec<f64>, f64) {
+        let k = rng.gen_range(0..live.len());
+        self.seed = Some(k);
+        let mut theta = live.theta(k).to_vec();
+        let mut proposal = theta.clone();
+        let mut log_l = live.log_l[k];
+        let log_prior = |t: &[f64]| self.prior.log_density(t);
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        let live_spread = spread;
+        // steps follow the configured scales if there are any, then the
+        // correlations of the live points when they are known, otherwise
+        // each parameter moves on its own scale
+        let (whitening, spread) = match &self.config.scales {
+            Some(scales) => (None, scales.as_slice()),
+            None => (live.whitening(), spread),
+        };
+        // the directions left once degenerate ones are set aside
+        let subspace = match &self.config.scales {
+            Some(_) => None,
+            None => self.subspace.clone(),
+        };
+        let stages: Vec<Option<SecondStage>> = match self.config.delayed_rejection && subspace.is_none() {
+            true => self.blocks.iter()
+                .map(|block| SecondStage::new(block, whitening.as_ref(), spread, self.config.second_stage_scale))
+                .collect(),
+            false => Vec::new(),
+        };
+        let mut z = vec![0.0; theta.len()];
+        let mut dx = vec![0.0; theta.len()];
+        let mut second = theta.clone();
+        let mut accepted = vec![0; self.blocks.len()];
+        let (mut second_tries, mut second_accepted) = (0, 0);
+        // the model's slow-parameter state at theta, if fast moves use one
+        let partial = self.config.fast.is_some();
+        let capacity = 2 * live.len();
+        let slow_key = |t: &[f64]| -> Vec<f64> { self.slow.iter().map(|&j| t[j]).collect() };
+        let mut states = std::mem::take(&mut self.states);
+        let mut state = match partial {
+            true => states.take(&slow_key(&theta)),
+            false => None,
+        };
+        for _ in 0..self.steps {
+            for (b, block) in self.blocks.iter().enumerate() {
+                // fast blocks are updated several times per slow update
+                for _ in 0..self.repeats[b] {
+                    for z in z.iter_mut() {
+                        *z = self.scale[b] * unit.sample(rng);
+                    }
+                    match (&subspace, &whitening) {
+                        (Some(basis), _) => {
+                            dx.iter_mut().for_each(|d| *d = 0.0);
+                            for (k, z) in z.iter().enumerate().take(basis.rows()) {
+                                dx.iter_mut().zip(basis.row(k)).for_each(|(d, e)| *d += z * e);
+                            }
+                        },
+                        (None, Some(w)) => w.step(&z, &mut dx),
+                        (None, None) => {
+                            for (dx, (z, s)) in dx.iter_mut().zip(z.iter().zip(spread)) {
+                                *dx = z * s;
+                            }
+                        },
+                    }
+                    // the block's part of a symmetric step is itself symmetric
+                    proposal.copy_from_slice(&theta);
+                    for &j in block {
+                        proposal[j] += dx[j];
+                    }
+                    let (log_prior_theta, log_prior_proposal) = (log_prior(&theta), log_prior(&proposal));
+                    if rng.gen::<f64>().ln() >= log_prior_proposal - log_prior_theta {
+                        continue
+                    }
+                    let fast = self.fast_blocks[b];
+                    let (ll, fresh) = match partial {
+                        true => evaluate_move(model, &proposal, &theta, threshold, fast, &mut state),
+                        false => (match model.screen(&proposal, threshold) {
+                            Screen::Reject => f64::NEG_INFINITY,
+                            Screen::Pass => model.log_lik(&proposal),
+                            Screen::Evaluated(ll) => ll,
+                        }, None),
+                    };
+                    if ll > threshold {
+                        // a slow move leaves the state behind with its point
+                        if partial && !fast {
+                            if let Some(old) = std::mem::replace(&mut state, fresh) {
+                                states.put(slow_key(&theta), old, capacity);
+                            }
+                        }
+                        std::mem::swap(&mut theta, &mut proposal);
+                        log_l = ll;
+                        accepted[b] += 1;
+                        continue
+                    }
+                    // the proposal passed the prior but not the contour
+                    let Some(stage) = stages.get(b).and_then(Option::as_ref) else { continue };
+                    second_tries += 1;
+                    second.copy_from_slice(&theta);
+                    let j = rng.gen_range(0..block.len());
+                    second[block[j]] += self.scale[b] * stage.sd[j] * unit.sample(rng);
+                    let log_prior_second = log_prior(&second);
+                    let log_ratio = log_prior_second.min(log_prior_proposal) - log_prior_theta.min(log_prior_proposal)
+                        + stage.log_q_ratio(block, &theta, &proposal, &second, self.scale[b]);
+                    if rng.gen::<f64>().ln() >= log_ratio {
+                        continue
+                    }
+                    let (ll, fresh) = match partial {
+                        true => evaluate_move(model, &second, &theta, threshold, fast, &mut state),
+                        false => (match model.screen(&second, threshold) {
+                            Screen::Reject => continue,
+                            Screen::Pass => model.log_lik(&second),
+                            Screen::Evaluated(ll) => ll,
+                        }, None),
+                    };
+                    if ll > threshold {
+                        if partial && !fast {
+                            if let Some(old) = std::mem::replace(&mut state, fresh) {
+                                states.put(slow_key(&theta), old, capacity);
+                            }
+                        }
+                        std::mem::swap(&mut theta, &mut second);
+                        log_l = ll;
+                        second_accepted += 1;
+                    }
+                }
+            }
+        }
+        if let Some(state) = state {
+            states.put(slow_key(&theta), state, capacity);
+        }
+        self.states = states;
+        self.proposed += self.steps * self.repeats.iter().sum::<usize>();
+        self.accepted += accepted.iter().sum::<usize>();
+        self.second_tries += second_tries;
+        self.second_accepted += second_accepted;
+        for ((signal, accepted), repeats) in self.signals.iter_mut().zip(accepted).zip(&self.repeats) {
+            *signal = accepted as f64 / (self.steps * repeats) as f64 - TARGET_ACCEPTANCE;
+        }
+        self.adapt();
+        self.record_chain(live, live.theta(k), &theta, live_spread);
+        (theta, log_l)
+    }
+
+    /// `steps` slice-sampling moves per block, each along a random
+    /// direction through the current point. Directions are scaled by the
+    /// configured scales or the live-set spread, and the initial bracket
+    /// by the block's scale, which adapts so that stepping out and
+    /// shrinking balance
+    fn hit_and_run<R: Rng + ?Sized>(
+            &mut self,
+            model: &dyn LogLikelihood,
+            threshold: f64,
+            live: &LiveSnapshot,
+            spread: &[f64],
+            rng: &mut R,
+    ) -> (Vec<f64>, f64) {
+        let k = rng.gen_range(0..live.len());
+        self.seed = Some(k);
+        let mut theta = live.theta(k).to_vec();
+        let mut log_l = live.log_l[k];
+        let live_spread = spread;
+        let spread = self.config.scales.as_deref().unwrap_or(spread);
+        let prior = self.prior.as_ref();
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        let mut direction = vec![0.0; theta.len()];
+        let mut point = theta.clone();
+        let (mut proposed, mut accepted) = (0, 0);
+        for (b, (block, scale)) in self.blocks.iter().zip(self.scale.iter_mut()).enumerate() {
+            let (mut expanded, mut shrunk) = (0, 0);
+            let moves = self.steps * self.repeats[b];
+            for _ in 0..moves {
+                direction.iter_mut().for_each(|d| *d = 0.0);
+                for &j in block {
+                    direction[j] = unit.sample(rng);
+                }
+                let norm = direction.iter().map(|d| d * d).sum::<f64>().sqrt();
+                for (d, s) in direction.iter_mut().zip(spread) {
+                    *d *= s / norm;
+                }
+                // the slice of the prior density above a uniform height,
+                // cut down to the points beating the contour
+                let line = Line{ prior, model, threshold, log_height: prior.log_density(&theta) + rng.gen::<f64>().ln() };
+                let mut lower = -rng.gen::<f64>() * *scale;
+                let mut upper = lower + *scale;
+                for _ in 0..MAX_STEP_OUT {
+                    proposed += 1;
+                    if line.at(&theta, &direction, lower, &mut point).is_none() {
+                        break
+                    }
+                    lower -= *scale;
+                    expanded += 1;
+                }
+                for _ in 0..MAX_STEP_OUT {
+                    proposed += 1;
+                    if line.at(&theta, &direction, upper, &mut point).is_none() {
+                        break
+                    }
+                    upper += *scale;
+                    expanded += 1;
+                }
+                for _ in 0..MAX_SHRINK {
+                    let t = lower + rng.gen::<f64>() * (upper - lower);
+                    proposed += 1;
+                    if let Some(ll) = line.at(&theta, &direction, t, &mut point) {
+                        std::mem::swap(&mut theta, &mut point);
+                        log_l = ll;
+                        accepted += 1;
+                        break
+                    }
+                    shrunk += 1;
+                    if t < 0.0 { lower = t } else { upper = t }
+                }
+            }
+            let balance = (1 + expanded) as f64 / (1 + shrunk) as f64;
+            self.signals[b] = balance.ln() / moves as f64;
+        }
+        self.adapt();
+        self.proposed += proposed;
+        self.accepted += accepted;
+        self.record_chain(live, live.theta(k), &theta, live_spread);
+        (theta, log_l)
+    }
+
+    /// add a chain from `start` to `end` to the travel statistics
+    fn record_chain(&mut self, live: &LiveSnapshot, start: &[f64], end: &[f64], spread: &[f64]) {
+        let d2: f64 = zip(zip(start, end), spread)
+            .filter(|(_, s)| **s > 0.0)
+            .map(|((a, b), s)| ((b - a) / s).powi(2))
+            .sum();
+        // two independent points are 2 variances apart per parameter
+        self.chains += 1;
+        self.travel += d2 / (2.0 * start.len() as f64);
+        // whitened, the live points have mean zero and unit covariance,
+        // so an end independent of its start has zero product with it
+        if let Some(whitening) = live.whitening() {
+            let (a, b) = (whitening.whiten(start), whitening.whiten(end));
+            self.parent_cov += zip(&a, &b).map(|(a, b)| a * b).sum::<f64>();
+            self.parent_var += zip(&a, &b).map(|(a, b)| 0.5 * (a * a + b * b)).sum::<f64>();
+        }
+    }
+
+    fn reset_chains(&mut self) {
+        (self.chains, self.travel, self.proposed, self.accepted) = (0, 0.0, 0, 0);
+        (self.parent_cov, self.parent_var) = (0.0, 0.0);
+    }
+
+    /// correlation, in whitened coordinates, between new points and the
+    /// live points their chains started from: 0 for independent draws, 1
+    /// for chains that never move. None before any whitened chain
+    pub fn parent_correlation(&self) -> Option<f64> {
+        (self.parent_var > 0.0).then(|| self.parent_cov / self.parent_var)
+    }
+
+    /// a warning with the chain length that would decorrelate new points
+    /// from their seeds, if they stay too correlated. Each step keeps a
+    /// fraction of the correlation, so a chain of s steps with correlation
+    /// r needs s ln(target) / ln(r) steps to reach the target
+    pub fn parent_warning(&self) -> Option<String> {
+        let r = self.parent_correlation()?;
+        if r <= MAX_PARENT_CORRELATION {
+            return None
+        }
+        let needed = match r < 1.0 {
+            true => format!(
+                "about {} steps would bring it to {}",
+                (self.steps as f64 * TARGET_PARENT_CORRELATION.ln() / r.ln()).ceil(),
+                TARGET_PARENT_CORRELATION,
+            ),
+            false => "the chains do not move at all".to_string(),
+        };
+        Some(format!(
+            "new live points keep a correlation of {:.2} with the points their chains started from \
+             (whitened, over {} chains of {} steps); {}, so raise sampler.steps: \
+             correlated replacements make the shrinkage, and so log Z, biased",
+            r, self.chains, self.steps, needed,
+        ))
+    }
+
+    /// a warning if the chains travel too little to forget where they
+    /// started
+    pub fn chain_warning(&self) -> Option<String> {
+        let quality = self.chain_quality()?;
+        (quality < MIN_CHAIN_QUALITY).then(|| format!(
+            "the sampler's chains travel only {:.2} of the way to independent draws; raise sampler.steps",
+            quality,
+        ))
+    }
+
+    /// mean travel of the chains: near 1 when a chain ends about as far
+    /// from its start as an independent draw would be, near 0 when the
+    /// chains barely leave their starting points; None before any chain
+    pub fn chain_quality(&self) -> Option<f64> {
+        (self.chains > 0).then(|| self.travel / self.chains as f64)
+    }
+
+    fn check_duplicate(
+            &mut self,
+            theta: &[f64],
+            live: &LiveSnapshot,
+            spread: &[f64],
+            observer: &mut dyn Observer,
+    ) {
+        let tol = self.config.duplicate_tolerance;
+        let duplicate = live.iter().any(|(t, _)| {
+            zip(theta, zip(t, spread)).all(|(a, (b, s))| (a - b).abs() <= tol * s)
+        });
+        self.draws += 1;
+        self.duplicates += duplicate as usize;
+        self.recent.push_back(duplicate);
+        if self.recent.len() > self.config.duplicate_window {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < self.config.duplicate_window {
+            return
+        }
+        let frac = self.recent.iter().filter(|d| **d).count() as f64 / self.recent.len() as f64;
+        if frac <= self.config.duplicate_fraction {
+            return
+        }
+        self.recent.clear();
+        let mut message = format!(
+            "{:.0}% of the last {} new live points duplicate existing ones",
+            100.0 * frac, self.config.duplicate_window,
+        );
+        if self.config.remedy_duplicates && matches!(self.method, Method::RandomWalk | Method::HitAndRun) {
+            if self.steps < self.config.max_steps {
+                self.steps = (2 * self.steps).min(self.config.max_steps);
+                message += &format!("; random-walk steps raised to {}", self.steps);
+            } else {
+                self.method = Method::Rejection;
+                self.auto = false;
+                message += "; switching to rejection sampling";
+            }
+        }
+        observer.warn(&message);
+    }
+
+    /// counters copied into the run statistics
+    pub fn stats(&self) -> Vec<(String, f64)> {
+        let mut stats = vec![
+            ("sampler_draws".to_string(), self.draws as f64),
+            ("sampler_duplicates".to_string(), self.duplicates as f64),
+        ];
+        if let Some(draw) = self.switched {
+            stats.push(("sampler_switched_at".to_string(), draw as f64));
+        }
+        if self.config.method != Method::Rejection {
+            stats.push(("sampler_steps".to_string(), self.steps as f64));
+            if self.blocks.len() == 1 {
+                stats.push(("sampler_scale".to_string(), self.scale[0]));
+            } else {
+                for (b, scale) in self.scale.iter().enumerate() {
+                    stats.push((format!("sampler_scale_block_{}", b), *scale));
+                }
+            }
+            for (b, block) in self.blocks.iter().enumerate() {
+                for &j in block {
+                    if let Some(s) = self.config.scales.as_ref().map(|s| s[j]) {
+                        stats.push((format!("sampler_scale_{}", j), self.scale[b] * s));
+                    }
+                }
+            }
+            stats.push(("sampler_acceptance".to_string(), self.accepted as f64 / self.proposed.max(1) as f64));
+            if self.config.degeneracy_every > 0 {
+                stats.push(("sampler_degenerate_directions".to_string(), self.degenerate.len() as f64));
+                for (k, direction) in self.degenerate.iter().enumerate() {
+                    for (j, a) in direction.iter().enumerate() {
+                        stats.push((format!("sampler_degenerate_{}_theta{}", k, j), *a));
+                    }
+                }
+            }
+            if self.config.delayed_rejection {
+                stats.push(("sampler_first_stage_accepted".to_string(), self.accepted as f64));
+                stats.push(("sampler_second_stage_tries".to_string(), self.second_tries as f64));
+                stats.push(("sampler_second_stage_accepted".to_string(), self.second_accepted as f64));
+            }
+        }
+        if let Some(tuning) = &self.tuning {
+            stats.push(("sampler_tuning_chains".to_string(), tuning.chains as f64));
+            stats.push(("sampler_tuned_acceptance".to_string(), tuning.acceptance));
+            stats.push(("sampler_tuned_chain_quality".to_string(), tuning.chain_quality));
+        }
+        if let Some(quality) = self.chain_quality() {
+            stats.push(("sampler_chain_quality".to_string(), quality));
+            stats.push(("sampler_effective_steps".to_string(), self.accepted as f64 / self.chains as f64));
+        }
+        if let Some(r) = self.parent_correlation() {
+            stats.push(("sampler_parent_correlation".to_string(), r));
+        }
+        stats.extend(self.prior.stats());
+        stats
+    }
+}
+
+
+/// a slice through the constrained prior along one line
+struct Line<'a> {
+    prior: &'a dyn Prior,
+    model: &'a dyn LogLikelihood,
+    threshold: f64,
+    log_height: f64,
+}
+
+
+impl Line<'_> {
+    /// the log-likelihood at `theta + t * direction`, written to `point`,
+    /// if that point is in the slice
+    fn at(&self, theta: &[f64], direction: &[f64], t: f64, point: &mut [f64]) -> Option<f64> {
+        for ((p, x), d) in point.iter_mut().zip(theta).zip(direction) {
+            *p = x + t * d;
+        }
+        if self.prior.log_density(point) <= self.log_height {
+            return None
+        }
+        let ll = match self.model.screen(point, self.threshold) {
+            Screen::Reject => return None,
+            Screen::Pass => self.model.log_lik(point),
+            Screen::Evaluated(ll) => ll,
+        };
+        (ll > self.threshold).then_some(ll)
+    }
+}
+
+
+/// write the live points as CSV, one row of parameters and log-likelihood each
+fn write_live(path: &Path, live: &LiveSnapshot) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let header: Vec<String> = (0..live.dim).map(|j| format!("theta{}", j)).chain(["log_l".to_string()]).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for (theta, log_l) in live.iter() {
+        let row: Vec<String> = theta.iter().chain([&log_l]).map(|v| v.to_string()).collect();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
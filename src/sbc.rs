@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::Deserialize;
+
+use crate::data::Dataset;
+use crate::dist::gamma_q;
+use crate::observer::{Collect, Observer};
+use crate::simulate::simulate;
+use crate::{run_with_data, Config};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::sampler::{Method, SamplerConfig};
+
+    #[test]
+    fn test_calibrated_regression() {
+        let x: Vec<f64> = (0..10).map(|i| i as f64 / 10.0).collect();
+        let data = Dataset::from_columns(vec![x, vec![0.0; 10]], None).unwrap();
+        let config = Config{
+            sample_num: 300,
+            particle_num: 30,
+            mu: vec![0.0, 0.0],
+            sd: vec![1.0, 1.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let sbc = SbcConfig{ replications: 40, draws: 19, bins: 5, ..Default::default() };
+        let mut rng = StdRng::seed_from_u64(397);
+        let report = run_sbc(&config, &sbc, &data, &mut Collect::default(), &mut rng).unwrap();
+        assert_eq!(report.ranks.len(), 40);
+        assert!(report.ranks.iter().flatten().all(|&r| r <= 19));
+        assert_eq!(report.histograms[1].iter().sum::<usize>(), 40);
+        assert!(report.miscalibrated.is_empty());
+    }
+
+    #[test]
+    fn test_uniformity() {
+        assert!(uniformity_p_value(&[20, 21, 19, 20, 20]) > 0.9);
+        // the truth too often in the tails: posteriors too narrow
+        assert!(uniformity_p_value(&[40, 5, 5, 5, 45]) < 1e-6);
+    }
+}
+
+
+/// settings of simulation-based calibration (Talts et al. 2018)
+///
+/// Fields:
+/// replications: datasets simulated from the prior and fitted
+/// draws: posterior draws each truth is ranked among, so ranks run from 0
+///     to `draws`
+/// bins: histogram bins of the ranks; `draws + 1` should be a multiple
+/// alpha: a parameter is flagged when the uniformity test of its rank
+///     histogram has a p-value below this
+/// rank_file: write the rank of every parameter in every replication to
+///     this CSV file
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SbcConfig {
+    pub replications: usize,
+    pub draws: usize,
+    pub bins: usize,
+    pub alpha: f64,
+    pub rank_file: Option<PathBuf>,
+}
+
+
+impl Default for SbcConfig {
+    fn default() -> SbcConfig {
+        SbcConfig{ replications: 100, draws: 99, bins: 10, alpha: 0.01, rank_file: None }
+    }
+}
+
+
+/// rank statistics of a calibration run
+///
+/// Fields:
+/// ranks: per replication, the rank of each true parameter among the
+///     posterior draws
+/// histograms: per parameter, counts of the ranks in each bin
+/// p_values: per parameter, the chi-square p-value for uniform ranks
+/// miscalibrated: the parameters whose p-value is below `alpha`
+#[derive(Debug, Clone)]
+pub struct SbcReport {
+    pub ranks: Vec<Vec<usize>>,
+    pub histograms: Vec<Vec<usize>>,
+    pub p_values: Vec<f64>,
+    pub miscalibrated: Vec<usize>,
+}
+
+
+/// draw parameters from the prior, simulate a dataset at them with the
+/// predictors of `data`, fit it with the config and rank the truth among
+/// posterior draws, `sbc.replications` times
+///
+/// If the sampler and the evidence weights are right, every rank is
+/// uniform on 0..=draws. Ranks piling up at both ends mean posteriors that
+/// are too narrow (e.g. a run stopped before the bulk or a sampler stuck
+/// in a mode), a hump in the middle too wide, and a slope a bias.
+/// Warnings of the individual runs are passed on to `observer`.
+pub fn run_sbc<R: Rng>(
+        config: &Config,
+        sbc: &SbcConfig,
+        data: &Dataset,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<SbcReport, Box<dyn Error>> {
+    if config.update.is_some() || config.warm_start.is_some() {
+        return Err("calibration draws from the config's prior, which updates and warm starts replace".into())
+    }
+    if sbc.replications < 2 || sbc.bins < 2 || sbc.draws + 1 < sbc.bins {
+        return Err("calibration needs two or more replications and bins, and at least one draw per bin".into())
+    }
+    let prior = config.prior()?;
+    let mut truth = vec![0.0; prior.dim()];
+    let mut ranks = Vec::with_capacity(sbc.replications);
+    for r in 0..sbc.replications {
+        prior.sample_into(&mut truth, rng);
+        let simulated = simulate(config, data, &truth, rng)?;
+        let mut warnings = Collect::default();
+        let result = run_with_data(config, &simulated, &mut warnings, rng)?;
+        for warning in warnings.warnings {
+            observer.warn(&format!("replication {}: {}", r, warning));
+        }
+
+        let weights = WeightedIndex::new(result.posterior.iter().map(|(_, lw)| lw.exp()))?;
+        let mut rank = vec![0; truth.len()];
+        for _ in 0..sbc.draws {
+            let theta = &result.posterior[weights.sample(rng)].0;
+            for ((rank, t), truth) in rank.iter_mut().zip(theta).zip(&truth) {
+                if t < truth {
+                    *rank += 1;
+                }
+            }
+        }
+        ranks.push(rank);
+    }
+
+    let mut histograms = vec![vec![0; sbc.bins]; truth.len()];
+    for rank in &ranks {
+        for (hist, r) in histograms.iter_mut().zip(rank) {
+            hist[r * sbc.bins / (sbc.draws + 1)] += 1;
+        }
+    }
+    let p_values: Vec<f64> = histograms.iter().map(|h| uniformity_p_value(h)).collect();
+    let miscalibrated: Vec<usize> = (0..p_values.len()).filter(|&j| p_values[j] < sbc.alpha).collect();
+    for &j in &miscalibrated {
+        observer.warn(&format!(
+            "the ranks of parameter {} are not uniform (p = {:.2e}): the posterior or the sampler is miscalibrated",
+            j, p_values[j],
+        ));
+    }
+    if let Some(path) = &sbc.rank_file {
+        let mut out = BufWriter::new(File::create(path)?);
+        let header: Vec<String> = (0..truth.len()).map(|j| format!("theta{}", j)).collect();
+        writeln!(out, "{}", header.join(","))?;
+        for rank in &ranks {
+            let row: Vec<String> = rank.iter().map(|r| r.to_string()).collect();
+            writeln!(out, "{}", row.join(","))?;
+        }
+        out.flush()?;
+    }
+    Ok(SbcReport{ ranks, histograms, p_values, miscalibrated })
+}
+
+
+/// chi-square test p-value of a histogram against equal bin counts
+pub fn uniformity_p_value(hist: &[usize]) -> f64 {
+    let n: usize = hist.iter().sum();
+    let expected = n as f64 / hist.len() as f64;
+    let stat: f64 = hist.iter().map(|&c| (c as f64 - expected).powi(2) / expected).sum();
+    // chi-square with one degree of freedom fewer than bins; NaN for one bin
+    gamma_q(0.5 * (hist.len() as f64 - 1.0), 0.5 * stat)
+}
@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use rand::Rng;
+
+use crate::models::{split_seed, EstimateSeed, LogLikelihood, Screen};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// predicts log L = -|theta|^2 / 2 exactly, with a fixed sd of 0.1
+    struct Exact;
+
+    impl Predictor for Exact {
+        type Config = ScreenSettings;
+        const NAME: &'static str = "exact";
+
+        fn settings(config: &ScreenSettings) -> ScreenSettings {
+            config.clone()
+        }
+
+        fn from_training(_xs: &[Vec<f64>], _ys: &[f64], _config: &ScreenSettings) -> Result<Exact, Box<dyn Error>> {
+            Ok(Exact)
+        }
+
+        fn estimate(&self, theta: &[f64]) -> (f64, Option<f64>) {
+            (-0.5 * theta.iter().map(|t| t * t).sum::<f64>(), Some(0.1))
+        }
+    }
+
+    struct Bowl;
+
+    impl LogLikelihood for Bowl {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            -0.5 * theta.iter().map(|t| t * t).sum::<f64>()
+        }
+
+        fn dim(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_audit_returns_the_evaluation() {
+        let settings = ScreenSettings{ min_train: 2, max_train: 10, retrain_every: 1, margin: 1.0, audit: 1.0, acquire: 0.0 };
+        let screened: Screened<Bowl, Exact> = Screened::new(Bowl, settings.clone());
+        assert!(!screened.is_approximate());
+        screened.log_lik(&[0.0]);
+        screened.log_lik(&[1.0]);
+        // every rejection is audited, and the audit hands back its value
+        assert_eq!(screened.screen(&[3.0], -1.0), Screen::Evaluated(-4.5));
+        assert_eq!(screened.screen(&[0.5], -1.0), Screen::Pass);
+        let stats = screened.stats();
+        assert!(stats.contains(&("exact_evaluated".to_string(), 3.0)));
+        assert!(stats.contains(&("exact_audited".to_string(), 1.0)));
+        // audited candidates were still evaluated, so nothing was skipped yet
+        assert!(!screened.is_approximate());
+
+        let settings = ScreenSettings{ audit: 0.0, ..settings };
+        let screened: Screened<Bowl, Exact> = Screened::new(Bowl, settings);
+        screened.log_lik(&[0.0]);
+        screened.log_lik(&[1.0]);
+        assert_eq!(screened.screen(&[3.0], -1.0), Screen::Reject);
+        assert!(screened.is_approximate());
+    }
+
+    #[test]
+    fn test_candidates_nearest_the_contour_are_acquired() {
+        let settings = ScreenSettings{ min_train: 2, max_train: 10, retrain_every: 1, margin: 1.0, audit: 0.0, acquire: 0.1 };
+        let screened: Screened<Bowl, Exact> = Screened::new(Bowl, settings);
+        screened.log_lik(&[0.0]);
+        screened.log_lik(&[1.0]);
+        for _ in 0..MIN_SCORES {
+            assert_eq!(screened.screen(&[3.0], -1.0), Screen::Reject);
+        }
+        // ruled out too, but by far the closest call so far
+        assert_eq!(screened.screen(&[1.6], -1.0), Screen::Evaluated(Bowl.log_lik(&[1.6])));
+        assert_eq!(screened.screen(&[3.0], -1.0), Screen::Reject);
+        assert!(screened.stats().contains(&("exact_acquired".to_string(), 1.0)));
+        assert!(screened.stats().contains(&("exact_evaluated".to_string(), 3.0)));
+    }
+}
+
+
+/// settings shared by every predictor-based screening layer
+///
+/// Fields:
+/// min_train: number of true evaluations before screening starts
+/// max_train: the predictor is fit to at most this many of the most recent points
+/// retrain_every: refit after this many new true evaluations
+/// margin: a candidate is skipped only if prediction + margin * sd is below
+///     the contour
+/// audit: fraction of skipped candidates that are evaluated anyway, to
+///     measure how often the predictor wrongly rejects
+/// acquire: fraction of skipped candidates evaluated to train the
+///     predictor, those whose upper bound comes closest to the contour
+#[derive(Debug, Clone)]
+pub struct ScreenSettings {
+    pub min_train: usize,
+    pub max_train: usize,
+    pub retrain_every: usize,
+    pub margin: f64,
+    pub audit: f64,
+    pub acquire: f64,
+}
+
+
+/// a cheap regression of log L on theta that `Screened` refits during the run
+pub trait Predictor: Sized + Send + Sync {
+    /// user-facing settings of the predictor, including the screening ones
+    type Config: Sync;
+
+    /// prefix of the counters reported in the run statistics
+    const NAME: &'static str;
+
+    /// the screening part of the config
+    fn settings(config: &Self::Config) -> ScreenSettings;
+
+    /// fit to the (theta, log L) pairs evaluated so far
+    fn from_training(xs: &[Vec<f64>], ys: &[f64], config: &Self::Config) -> Result<Self, Box<dyn Error>>;
+
+    /// predicted log L at theta, and the sd of that prediction if the
+    /// predictor has its own; otherwise the rmse measured on recent true
+    /// evaluations is used
+    fn estimate(&self, theta: &[f64]) -> (f64, Option<f64>);
+}
+
+
+/// squared errors needed before the measured rmse is trusted
+const MIN_ERRORS: usize = 20;
+/// squared errors kept for the rmse
+const MAX_ERRORS: usize = 500;
+/// acquisition scores of recent candidates that new ones are ranked among
+const ACQUISITION_WINDOW: usize = 200;
+/// scores needed before candidates are acquired
+const MIN_SCORES: usize = 20;
+
+
+/// training set, fitted predictor and counters, behind the wrapper's lock
+struct ScreenState<P> {
+    xs: Vec<Vec<f64>>,
+    ys: Vec<f64>,
+    predictor: Option<Arc<P>>,
+    since_fit: usize,
+    /// squared errors of the current predictor on true evaluations
+    sq_errors: VecDeque<f64>,
+    /// acquisition scores of recent candidates the predictor ruled out
+    scores: VecDeque<f64>,
+    evaluated: usize,
+    skipped: usize,
+    audited: usize,
+    acquired: usize,
+    false_rejects: usize,
+}
+
+
+impl<P> ScreenState<P> {
+    fn new() -> ScreenState<P> {
+        ScreenState{
+            xs: Vec::new(),
+            ys: Vec::new(),
+            predictor: None,
+            since_fit: 0,
+            sq_errors: VecDeque::new(),
+            scores: VecDeque::new(),
+            evaluated: 0,
+            skipped: 0,
+            audited: 0,
+            acquired: 0,
+            false_rejects: 0,
+        }
+    }
+
+    fn rmse(&self) -> Option<f64> {
+        if self.sq_errors.len() < MIN_ERRORS {
+            return None
+        }
+        Some((self.sq_errors.iter().sum::<f64>() / self.sq_errors.len() as f64).sqrt())
+    }
+
+    /// whether a ruled-out candidate of acquisition score `score` ranks
+    /// among the top `fraction` of the recent ones
+    fn acquires(&mut self, score: f64, fraction: f64) -> bool {
+        let above = self.scores.iter().filter(|s| **s >= score).count();
+        let acquired = self.scores.len() >= MIN_SCORES && (above as f64) < fraction * self.scores.len() as f64;
+        self.scores.push_back(score);
+        if self.scores.len() > ACQUISITION_WINDOW {
+            self.scores.pop_front();
+        }
+        acquired
+    }
+}
+
+
+/// expensive likelihood whose candidates are pre-screened by a predictor
+///
+/// Every true evaluation is added to the training set, and the predictor
+/// is refit periodically. Before a candidate is evaluated, the sampler
+/// asks `screen` whether it could beat the current contour; if the upper
+/// bound prediction + margin * sd is below the contour, the true
+/// likelihood is not called. Training on what the sampler evaluates alone
+/// would leave the predictor blind just where it rules candidates out, so
+/// some of those are acquired: a ruled-out candidate is evaluated anyway
+/// when its upper bound comes closer to the contour than that of all but
+/// the `acquire` fraction of recent ones, i.e. where the predictor is
+/// least sure near the contour. Accepted particles always
+/// carry their true likelihood, so the only error screening introduces is
+/// wrongly skipping a candidate that would have been accepted. That makes
+/// new live points non-uniform in the constrained prior and biases log Z,
+/// so the model reports itself as approximate as soon as it has skipped a
+/// candidate. The audit fraction measures how often the skip is wrong:
+/// audited candidates are drawn at random, unlike acquired ones. Both are
+/// evaluated and handed back through `Screen::Evaluated` so the sampler
+/// does not evaluate them again.
+///
+/// Fields:
+/// model: the true likelihood
+/// config: predictor settings
+/// settings: screening settings taken from the config
+/// state: training data, fitted predictor and counters
+/// seed: seed of the audit draws
+pub struct Screened<M: LogLikelihood, P: Predictor> {
+    model: M,
+    config: P::Config,
+    settings: ScreenSettings,
+    state: Mutex<ScreenState<P>>,
+    seed: EstimateSeed,
+}
+
+
+impl<M: LogLikelihood, P: Predictor> Screened<M, P> {
+    pub fn new(model: M, config: P::Config) -> Screened<M, P> {
+        let settings = P::settings(&config);
+        Screened{ model, config, settings, state: Mutex::new(ScreenState::new()), seed: EstimateSeed::new() }
+    }
+
+    fn record(&self, theta: &[f64], ll: f64) {
+        if !ll.is_finite() {
+            return
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(predictor) = &state.predictor {
+            let err = (predictor.estimate(theta).0 - ll).powi(2);
+            state.sq_errors.push_back(err);
+            if state.sq_errors.len() > MAX_ERRORS {
+                state.sq_errors.pop_front();
+            }
+        }
+        state.xs.push(theta.to_vec());
+        state.ys.push(ll);
+        if state.xs.len() > self.settings.max_train {
+            let excess = state.xs.len() - self.settings.max_train;
+            state.xs.drain(0..excess);
+            state.ys.drain(0..excess);
+        }
+        state.since_fit += 1;
+        let due = state.predictor.is_none() || state.since_fit >= self.settings.retrain_every;
+        if state.xs.len() >= self.settings.min_train && due {
+            // a failed refit keeps the previous predictor
+            if let Ok(predictor) = P::from_training(&state.xs, &state.ys, &self.config) {
+                state.predictor = Some(Arc::new(predictor));
+                state.sq_errors.clear();
+            }
+            state.since_fit = 0;
+        }
+    }
+}
+
+
+impl<M: LogLikelihood, P: Predictor> LogLikelihood for Screened<M, P> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        let ll = self.model.log_lik(theta);
+        self.state.lock().unwrap().evaluated += 1;
+        self.record(theta, ll);
+        ll
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.state.lock().unwrap().skipped > 0 || self.model.is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        // never hold the lock while calling into the wrapped model
+        let fitted = {
+            let state = self.state.lock().unwrap();
+            state.predictor.as_ref().map(|p| (Arc::clone(p), state.rmse()))
+        };
+        let (predictor, rmse) = match fitted {
+            Some(fitted) => fitted,
+            None => return self.model.screen(theta, threshold),
+        };
+        let (mean, sd) = predictor.estimate(theta);
+        let sd = match sd.or(rmse) {
+            Some(sd) => sd,
+            None => return self.model.screen(theta, threshold),
+        };
+        if mean + self.settings.margin * sd >= threshold {
+            return self.model.screen(theta, threshold)
+        }
+        if self.seed.rng(theta).gen::<f64>() < self.settings.audit {
+            let ll = self.log_lik(theta);
+            let mut state = self.state.lock().unwrap();
+            state.audited += 1;
+            if ll > threshold {
+                state.false_rejects += 1;
+            }
+            return Screen::Evaluated(ll)
+        }
+        let score = mean + self.settings.margin * sd - threshold;
+        if self.state.lock().unwrap().acquires(score, self.settings.acquire) {
+            let ll = self.log_lik(theta);
+            self.state.lock().unwrap().acquired += 1;
+            return Screen::Evaluated(ll)
+        }
+        self.state.lock().unwrap().skipped += 1;
+        Screen::Reject
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        let state = self.state.lock().unwrap();
+        let mut stats = vec![
+            (format!("{}_evaluated", P::NAME), state.evaluated as f64),
+            (format!("{}_skipped", P::NAME), state.skipped as f64),
+            (format!("{}_audited", P::NAME), state.audited as f64),
+            (format!("{}_acquired", P::NAME), state.acquired as f64),
+            (format!("{}_false_rejects", P::NAME), state.false_rejects as f64),
+            (format!("{}_rmse", P::NAME), state.rmse().unwrap_or(f64::NAN)),
+        ];
+        drop(state);
+        stats.extend(self.model.stats());
+        stats
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.model.outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        self.model.log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.seed.set(seed);
+        self.model.reseed(split_seed(seed))
+    }
+}
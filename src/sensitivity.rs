@@ -0,0 +1,161 @@
+use std::error::Error;
+
+use crate::evidence::log_add_exp;
+use crate::posterior::Posterior;
+use crate::priors::Prior;
+use crate::rundir::RunDir;
+use crate::Config;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand::distributions::Distribution;
+    use crate::dist::Normal;
+    use crate::priors::NormalPrior;
+
+    #[test]
+    fn test_reweighting_to_a_wider_prior() {
+        // a likelihood N(0.3; theta, 0.4^2) under N(mu, sd^2) has evidence
+        // N(0.3; mu, 0.4^2 + sd^2) and a normal posterior
+        let log_z = |mu: f64, sd: f64| {
+            let var = 0.16 + sd * sd;
+            -0.5 * (2.0 * PI * var).ln() - 0.5 * (0.3 - mu).powi(2) / var
+        };
+        let (post_var, post_mean): (f64, f64) = (1.0 / (1.0 / 0.16 + 1.0), 0.3 / 0.16 / (1.0 / 0.16 + 1.0));
+        let draws = Normal::new(post_mean, post_var.sqrt()).unwrap();
+        let mut rng = StdRng::seed_from_u64(461);
+        let log_w = -(5000f64).ln();
+        let posterior: Vec<(Vec<f64>, f64)> = (0..5000).map(|_| (vec![draws.sample(&mut rng)], log_w)).collect();
+
+        let prior = NormalPrior::new(&[0.0], &[1.0]).unwrap();
+        let wider = NormalPrior::new(&[0.5], &[1.5]).unwrap();
+        let reweighted = reweight_prior(&posterior, log_z(0.0, 1.0), 0.05, &prior, &wider).unwrap();
+        assert!((reweighted.log_z - log_z(0.5, 1.5)).abs() < 0.02, "{} {}", reweighted.log_z, log_z(0.5, 1.5));
+        assert!(reweighted.log_z_err > 0.05);
+        assert!(reweighted.ess_fraction > 0.8 && reweighted.is_reliable());
+        let total: f64 = reweighted.posterior.iter().map(|(_, lw)| lw.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        // the wider prior pulls the posterior mean toward the data
+        let mean: f64 = reweighted.posterior.iter().map(|(t, lw)| t[0] * lw.exp()).sum();
+        assert!(mean > post_mean);
+
+        // a narrow prior far from the posterior bulk leaves a few points to carry it
+        let far = NormalPrior::new(&[1.5], &[0.05]).unwrap();
+        let reweighted = reweight_prior(&posterior, log_z(0.0, 1.0), 0.05, &prior, &far).unwrap();
+        assert!(!reweighted.is_reliable(), "{}", reweighted.ess_fraction);
+
+        let other_dim = NormalPrior::new(&[0.0, 0.0], &[1.0, 1.0]).unwrap();
+        assert!(reweight_prior(&posterior, 0.0, 0.0, &prior, &other_dim).is_err());
+    }
+}
+
+
+/// a reweighted posterior whose effective sample size is below this
+/// fraction of the original's rests on too few points to trust
+pub const MIN_ESS_FRACTION: f64 = 0.1;
+
+
+/// the posterior and evidence under an alternative prior, from the points
+/// of a run under another
+///
+/// Fields:
+/// log_z: log evidence under the alternative prior
+/// log_z_err: its standard error, that of the run combined with that of
+///     the reweighting
+/// ess: Kish effective sample size of the reweighted posterior
+/// ess_fraction: ess as a fraction of that of the run's own posterior
+/// posterior: every point as (theta, log of its normalized weight under
+///     the alternative prior)
+#[derive(Debug, Clone)]
+pub struct Reweighted {
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub ess: f64,
+    pub ess_fraction: f64,
+    pub posterior: Vec<(Vec<f64>, f64)>,
+}
+
+
+impl Reweighted {
+    /// whether enough points carry the reweighted posterior; when not, the
+    /// alternative prior puts its mass where the run has few points and
+    /// needs a run of its own
+    pub fn is_reliable(&self) -> bool {
+        self.ess_fraction >= MIN_ESS_FRACTION
+    }
+}
+
+
+/// Kish effective sample size of normalized log weights
+fn ess(log_w: impl Iterator<Item = f64>) -> f64 {
+    1.0 / log_w.map(|lw| (2.0 * lw).exp()).sum::<f64>()
+}
+
+
+/// reweight a posterior with normalized log weights and log evidence
+/// `log_z` +/- `log_z_err` under `prior` to `alternative`
+///
+/// Each point's weight is multiplied by the prior density ratio
+/// alternative / prior at it, and the evidence by the posterior mean of
+/// that ratio, so no likelihood is evaluated again. The error of that mean
+/// is the delta-method variance of a weighted average. Only regions the
+/// run explored are reweighted: mass the alternative prior puts where the
+/// likelihood is high but the original prior kept the run away is missed,
+/// which a low effective sample size usually gives away.
+pub fn reweight_prior(
+        posterior: &[(Vec<f64>, f64)],
+        log_z: f64,
+        log_z_err: f64,
+        prior: &dyn Prior,
+        alternative: &dyn Prior,
+) -> Result<Reweighted, Box<dyn Error>> {
+    if prior.dim() != alternative.dim() {
+        return Err(format!("the alternative prior has {} parameters, the run's {}", alternative.dim(), prior.dim()).into())
+    }
+    if posterior.iter().any(|(theta, _)| theta.len() != prior.dim()) {
+        return Err(format!("the posterior points do not have the prior's {} parameters", prior.dim()).into())
+    }
+    let log_ratio: Vec<f64> = posterior.iter()
+        .map(|(theta, _)| alternative.log_density(theta) - prior.log_density(theta))
+        .collect();
+    if log_ratio.iter().any(|r| r.is_nan() || *r == f64::INFINITY) {
+        return Err("the run's prior has no density at some of its points, so they cannot be reweighted".into())
+    }
+    let log_mean = posterior.iter().zip(&log_ratio)
+        .fold(f64::NEG_INFINITY, |acc, ((_, lw), lr)| log_add_exp(acc, lw + lr));
+    if log_mean == f64::NEG_INFINITY {
+        return Err("the alternative prior has no mass at any point of the run".into())
+    }
+    // Var(log mean) ~ sum_i p_i^2 (r_i / mean - 1)^2
+    let var: f64 = posterior.iter().zip(&log_ratio)
+        .map(|((_, lw), lr)| (2.0 * lw).exp() * ((lr - log_mean).exp() - 1.0).powi(2))
+        .sum();
+    let reweighted: Vec<(Vec<f64>, f64)> = posterior.iter().zip(&log_ratio)
+        .map(|((theta, lw), lr)| (theta.clone(), lw + lr - log_mean))
+        .collect();
+    let new_ess = ess(reweighted.iter().map(|(_, lw)| *lw));
+    Ok(Reweighted{
+        log_z: log_z + log_mean,
+        log_z_err: (log_z_err * log_z_err + var).sqrt(),
+        ess: new_ess,
+        ess_fraction: new_ess / ess(posterior.iter().map(|(_, lw)| *lw)),
+        posterior: reweighted,
+    })
+}
+
+
+/// reweight the finished run in `dir`, made with `config`, to the prior of
+/// `alternative`
+pub fn prior_sensitivity(config: &Config, alternative: &Config, dir: &RunDir) -> Result<Reweighted, Box<dyn Error>> {
+    if config.update.is_some() || config.warm_start.is_some() || config.inject.is_some()
+        || config.restrict.is_some() {
+        return Err("updating, warm-started and restricted runs do not draw their points from the prior their config describes".into())
+    }
+    let tempered = Posterior::read_dead_birth(&dir.dead_birth_file(), config.n_params())?.at_temperature(1.0)?;
+    let (prior, alternative) = (config.prior()?, alternative.prior()?);
+    reweight_prior(&tempered.points, tempered.log_z, tempered.log_z_err, prior.as_ref(), alternative.as_ref())
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use crate::data::Dataset;
+use crate::observer::Collect;
+use crate::telemetry::{json_number, json_string};
+use crate::{run_with_data, Config, RunResult};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{Method, SamplerConfig};
+
+    #[test]
+    fn test_jobs_over_a_socket() {
+        let path = std::env::temp_dir().join(format!("ns_serve_{}.csv", std::process::id()));
+        let rows: Vec<String> = (0..20).map(|i| format!("{},{}", i as f64 / 10.0, 1.0 + 0.2 * i as f64)).collect();
+        std::fs::write(&path, format!("x,y\n{}\n", rows.join("\n"))).unwrap();
+        let config = Config{
+            sample_num: 200,
+            particle_num: 30,
+            mu: vec![0.0, 0.0],
+            sd: vec![5.0, 5.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _ = serve(config, listener);
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut out = stream;
+        writeln!(out, "{}\n\n/no/such/file.csv", path.display()).unwrap();
+        let fitted = lines.next().unwrap().unwrap();
+        assert!(fitted.starts_with("{\"data_file\":") && fitted.contains("\"log_z\":"), "{}", fitted);
+        assert!(fitted.contains("\"mean\":[") && !fitted.contains("\"error\""));
+        // the blank line gets no answer
+        let failed = lines.next().unwrap().unwrap();
+        assert!(failed.contains("\"error\":") && failed.contains("/no/such/file.csv"), "{}", failed);
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+
+/// the outcome of one job as a line of JSON: the data file, the evidence,
+/// the posterior mean and sd of every parameter and the run's warnings
+fn result_json(data_file: &str, result: &RunResult, warnings: &[String]) -> String {
+    let dim = result.posterior.first().map_or(0, |(t, _)| t.len());
+    let (mut mean, mut sd) = (vec![0.0; dim], vec![0.0; dim]);
+    for (theta, lw) in &result.posterior {
+        let w = lw.exp();
+        for j in 0..dim {
+            mean[j] += w * theta[j];
+        }
+    }
+    for (theta, lw) in &result.posterior {
+        let w = lw.exp();
+        for j in 0..dim {
+            sd[j] += w * (theta[j] - mean[j]).powi(2);
+        }
+    }
+    let array = |values: Vec<String>| format!("[{}]", values.join(","));
+    format!(
+        "{{\"data_file\":{},\"log_z\":{},\"log_z_err\":{},\"info\":{},\"ess\":{},\"iterations\":{},\
+         \"mean\":{},\"sd\":{},\"warnings\":{}}}",
+        json_string(data_file), json_number(result.log_z), json_number(result.log_z_err), json_number(result.info),
+        json_number(result.ess), result.iterations,
+        array(mean.iter().map(|m| json_number(*m)).collect()),
+        array(sd.iter().map(|v| json_number(v.sqrt())).collect()),
+        array(warnings.iter().map(|w| json_string(w)).collect()),
+    )
+}
+
+
+/// run `config` on the dataset at `data_file` and answer with a line of
+/// JSON, `{"data_file": ..., "error": ...}` if the job failed
+pub fn run_job(config: &Config, data_file: &str) -> String {
+    let mut warnings = Collect::default();
+    let result = Dataset::load(Path::new(data_file))
+        .and_then(|data| run_with_data(config, &data, &mut warnings, &mut config.rng()));
+    match result {
+        Ok(result) => result_json(data_file, &result, &warnings.warnings),
+        Err(e) => format!("{{\"data_file\":{},\"error\":{}}}", json_string(data_file), json_string(&e.to_string())),
+    }
+}
+
+
+/// answer the jobs of one connection, in order, until it closes
+fn answer(config: &Config, stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(stream.try_clone()?);
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let data_file = line.trim();
+        if data_file.is_empty() {
+            continue
+        }
+        writeln!(out, "{}", run_job(config, data_file))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+
+/// accept inference jobs on `listener` until the process ends
+///
+/// The config is loaded once and serves every job, so a pipeline with
+/// many small datasets pays no start-up per run. A client writes the path
+/// of a dataset per line and reads one line of JSON back per job, in the
+/// same order; the config's own data file is not used. Every connection
+/// is answered on a thread of its own, so clients do not wait for each
+/// other, while the jobs of one client run one after another.
+pub fn serve(config: Config, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(config);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let config = Arc::clone(&config);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map_or_else(|_| "a client".to_string(), |a| a.to_string());
+            if let Err(e) = answer(&config, stream) {
+                eprintln!("warning: connection from {} dropped: {}", peer, e);
+            }
+        });
+    }
+    Ok(())
+}
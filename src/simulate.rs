@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::path::Path;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::Config;
+use crate::data::Dataset;
+use crate::models::{read_covariance, ArmaNoise, CorrelatedNoise, DpmmMarginal, LinearGaussian, MultiGaussian, NoiseModel, ParticleFilter, Simulate};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_regression_recovers_the_truth() {
+        let x: Vec<f64> = (0..2000).map(|i| i as f64 / 1000.0).collect();
+        let data = Dataset::from_columns(vec![x, vec![0.0; 2000]], None).unwrap();
+        let config = Config{ noise_model: NoiseModel::Ar1, ..Default::default() };
+        let truth = [1.0, 2.0, 0.6, 0.3];
+        let mut rng = StdRng::seed_from_u64(396);
+        let sim = simulate(&config, &data, &truth, &mut rng).unwrap();
+        assert_eq!(sim.names(), data.names());
+        assert_eq!(sim.column(0), data.column(0));
+
+        let (x, y) = (sim.column(0), sim.column(1));
+        let n = x.len() as f64;
+        let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+        let sxy: f64 = x.iter().zip(y).map(|(x, y)| (x - mx) * (y - my)).sum();
+        let sxx: f64 = x.iter().map(|x| (x - mx).powi(2)).sum();
+        let slope = sxy / sxx;
+        assert!((slope - 2.0).abs() < 0.2);
+        let r: Vec<f64> = x.iter().zip(y).map(|(x, y)| y - 1.0 - 2.0 * x).collect();
+        let var = r.iter().map(|r| r * r).sum::<f64>() / n;
+        let lag1 = r.windows(2).map(|w| w[0] * w[1]).sum::<f64>() / n / var;
+        // stationary AR(1): var = sigma^2 / (1 - phi^2), lag-1 correlation phi
+        assert!((var - 0.09 / 0.64).abs() < 0.02);
+        assert!((lag1 - 0.6).abs() < 0.05);
+
+        assert!(simulate(&config, &data, &truth[..3], &mut rng).is_err());
+    }
+}
+
+
+/// parameter values to simulate data at, read from a TOML file such as
+///
+/// ```toml
+/// theta = [1.0, 2.0, 0.5]
+/// seed = 7
+/// ```
+///
+/// Fields:
+/// theta: the true parameters, in the order the configured model uses
+/// seed: seed of the simulation, so that datasets can be regenerated;
+///     drawn at random if absent
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Truth {
+    pub theta: Vec<f64>,
+    pub seed: Option<u64>,
+}
+
+
+impl Truth {
+    pub fn load(path: &Path) -> Result<Truth, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+}
+
+
+/// the data-generating part of the model described by the config.
+/// Caching, screening and subsampling change how the likelihood is
+/// evaluated, not the data it describes, so they are left out
+pub(crate) fn build_simulator<'a>(
+        config: &Config,
+        data: &'a Dataset,
+) -> Result<Box<dyn Simulate + 'a>, Box<dyn Error>> {
+    if !matches!(config.model_name(), "regression" | "dpmm" | "multivariate") {
+        return Err(format!("model {} cannot simulate data", config.model_name()).into())
+    }
+    if let Some(dpmm) = &config.dpmm {
+        let mixture = DpmmMarginal::from_dataset(data, dpmm)?;
+        return Ok(Box::new(ParticleFilter::new(mixture, dpmm.filter_particles.max(2))?))
+    }
+    if let Some(multivariate) = &config.multivariate {
+        return Ok(Box::new(MultiGaussian::from_dataset(data, &multivariate.responses, multivariate.covariance)?))
+    }
+    if config.censor_column.is_some() || config.truncate_lower.is_some() || config.truncate_upper.is_some() {
+        return Err("censored or truncated data cannot be simulated".into())
+    }
+    if config.nuisance.is_some() {
+        return Err("marginalized nuisance coefficients have no value to simulate at; \
+            move them into the sampled parameters first".into())
+    }
+    let regression = LinearGaussian::from_dataset(data, config.noise_sd, &[])?;
+    match (config.noise_model, &config.noise_cov_file) {
+        (NoiseModel::White, Some(path)) => Ok(Box::new(CorrelatedNoise::new(regression, &read_covariance(path)?)?)),
+        (NoiseModel::White, None) => Ok(Box::new(regression)),
+        (noise_model, _) => Ok(Box::new(ArmaNoise::new(regression, noise_model)?)),
+    }
+}
+
+
+/// the columns of `data` the configured model describes, in the order
+/// `Simulate::simulate` returns them
+fn response_columns(config: &Config, data: &Dataset) -> Result<Vec<usize>, Box<dyn Error>> {
+    let position = |name: &String| data.names().iter().position(|n| n == name).ok_or("no such data column");
+    Ok(match (&config.multivariate, config.dpmm.as_ref().and_then(|d| d.column.as_ref())) {
+        (Some(multivariate), _) => multivariate.responses.iter().map(position).collect::<Result<Vec<_>, _>>()?,
+        (None, Some(name)) => vec![position(name)?],
+        (None, None) => vec![data.ncols() - 1],
+    })
+}
+
+
+/// the observed response in the shape `Simulate::simulate` returns it
+pub(crate) fn observed_response(config: &Config, data: &Dataset) -> Result<Vec<f64>, Box<dyn Error>> {
+    Ok(response_columns(config, data)?.into_iter().flat_map(|j| data.column(j).iter().copied()).collect())
+}
+
+
+/// a copy of `data` with the response replaced by a draw from the
+/// configured model at theta. The predictors stay as observed, so the
+/// result can be fed back through the same config to check that the
+/// truth is recovered.
+pub fn simulate<R: Rng>(
+        config: &Config,
+        data: &Dataset,
+        theta: &[f64],
+        rng: &mut R,
+) -> Result<Dataset, Box<dyn Error>> {
+    let model = build_simulator(config, data)?;
+    if theta.len() != model.dim() {
+        return Err(format!("the model has {} parameters but the truth has {}", model.dim(), theta.len()).into())
+    }
+    let responses = response_columns(config, data)?;
+    // the simulated responses come one after the other, nrows values each
+    let simulated = model.simulate(theta, rng)?;
+    let columns: Vec<Vec<f64>> = (0..data.ncols())
+        .map(|j| match responses.iter().position(|&r| r == j) {
+            Some(k) => simulated[k * data.nrows()..(k + 1) * data.nrows()].to_vec(),
+            None => data.column(j).to_vec(),
+        })
+        .collect();
+    Dataset::from_columns(columns, Some(data.names().to_vec()))
+}
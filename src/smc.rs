@@ -0,0 +1,287 @@
+use std::error::Error;
+
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::data::Dataset;
+use crate::diagnostics::ShrinkageTrace;
+use crate::dist::Normal;
+use crate::evidence::log_add_exp;
+use crate::geometry::mean_cov;
+use crate::models::LogLikelihood;
+use crate::observer::Observer;
+use crate::priors::Prior;
+use crate::stats::{ess, systematic_resample};
+use crate::{run_with_data, Config, RunResult};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::observer::Collect;
+    use crate::priors::NormalPrior;
+    use crate::test_support::Peak;
+
+    #[test]
+    fn test_adaptive_tempering_recovers_the_evidence() {
+        let model = Peak{ s: 0.05, dim: 2 };
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 1.0]).unwrap());
+        let truth = 2.0 * (0.05f64 / (1.0f64 + 0.0025).sqrt()).ln();
+        let config = SmcConfig{ particles: 500, ..Default::default() };
+        let mut rng = StdRng::seed_from_u64(428);
+        let result = run_smc(&model, prior.as_ref(), &config, &mut Collect::default(), &mut rng).unwrap();
+        assert!((result.log_z - truth).abs() < 4.0 * result.log_z_err + 0.05, "{} +/- {} vs {}", result.log_z, result.log_z_err, truth);
+        // each stage keeps half the ESS, so the stages follow the information
+        let stages = result.iterations as f64;
+        assert!(stages > 3.0 && stages < 5.0 * result.info.max(1.0), "{} stages for H = {}", stages, result.info);
+        let total: f64 = result.posterior.iter().map(|(_, lw)| lw.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        let var = result.posterior.iter().map(|(t, lw)| lw.exp() * t[1] * t[1]).sum::<f64>();
+        assert!((var / (0.0025 / 1.0025) - 1.0).abs() < 0.3, "{}", var);
+    }
+}
+
+
+/// settings of adaptive-tempering sequential Monte Carlo
+///
+/// Fields:
+/// particles: particles carried from the prior to the posterior
+/// target_ess: fraction of the particles the effective sample size may
+///     drop to in one stage; sets how far each stage raises beta
+/// moves: Metropolis steps of every particle after each resampling
+/// max_stages: give up after this many stages
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SmcConfig {
+    pub particles: usize,
+    pub target_ess: f64,
+    pub moves: usize,
+    pub max_stages: usize,
+}
+
+
+impl Default for SmcConfig {
+    fn default() -> SmcConfig {
+        SmcConfig{
+            particles: 1000,
+            target_ess: 0.5,
+            moves: 10,
+            max_stages: 1000,
+        }
+    }
+}
+
+
+/// bisection steps for the next temperature
+const BETA_STEPS: usize = 60;
+
+
+/// the largest beta' in (beta, 1] at which reweighting particles of equal
+/// weight by L^(beta' - beta) keeps the ESS at `target` of them
+fn next_beta(beta: f64, log_l: &[f64], target: f64) -> f64 {
+    let ess_at = |next: f64| {
+        let d = next - beta;
+        let top = log_l.iter().fold(f64::NEG_INFINITY, |m, l| m.max(d * l));
+        let w: Vec<f64> = log_l.iter().map(|l| (d * l - top).exp()).collect();
+        ess(&w) / log_l.len() as f64
+    };
+    if ess_at(1.0) >= target {
+        return 1.0
+    }
+    let (mut lo, mut hi) = (beta, 1.0);
+    for _ in 0..BETA_STEPS {
+        let mid = 0.5 * (lo + hi);
+        if ess_at(mid) >= target { lo = mid } else { hi = mid }
+    }
+    // never stall, even when one particle dominates at any step
+    lo.max(beta + 1e-12).min(1.0)
+}
+
+
+/// sample the posterior by sequential Monte Carlo with adaptive tempering
+/// and estimate the evidence, returning a result like a nested sampling
+/// run's
+///
+/// The particles start as prior draws. Each stage raises beta as far as
+/// the ESS target allows, multiplies log Z by the mean incremental weight,
+/// resamples systematically and moves every particle by Metropolis steps
+/// at the new beta, with proposals shaped by the particles' covariance
+/// (Del Moral et al. 2006; Jasra et al. 2011). The final weighted
+/// particles are the posterior. The log Z error sums the relative variance
+/// of each stage's weights, sum_t (1 / ESS_t - 1 / N), which ignores the
+/// dependence resampling introduces and so errs on the small side.
+pub fn run_smc<R: Rng>(
+        model: &dyn LogLikelihood,
+        prior: &dyn Prior,
+        config: &SmcConfig,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<RunResult, Box<dyn Error>> {
+    let n = config.particles;
+    if n < 2 {
+        return Err("SMC needs at least two particles".into())
+    }
+    if !(config.target_ess > 0.0 && config.target_ess < 1.0) {
+        return Err(format!("the SMC target ESS fraction {} must be in (0, 1)", config.target_ess).into())
+    }
+    let dim = prior.dim();
+    let mut theta: Vec<Vec<f64>> = (0..n).map(|_| {
+        let mut t = vec![0.0; dim];
+        prior.sample_into(&mut t, rng);
+        t
+    }).collect();
+    let mut log_l: Vec<f64> = theta.par_iter().map(|t| model.log_lik(t)).collect();
+    let mut log_w = vec![0.0; n];
+
+    let unit = Normal::new(0.0, 1.0).unwrap();
+    let mut beta = 0.0;
+    let mut log_z = 0.0;
+    let mut var_log_z = 0.0;
+    let mut stages = 0;
+    let mut scale = 2.38 / (dim as f64).sqrt();
+    let (mut proposed, mut accepted) = (0usize, 0usize);
+    while beta < 1.0 {
+        if stages == config.max_stages {
+            return Err(format!("SMC reached beta = {:.3e} after {} stages; raise smc.max_stages", beta, stages).into())
+        }
+        stages += 1;
+        let next = next_beta(beta, &log_l, config.target_ess);
+        let d = next - beta;
+        for (w, l) in log_w.iter_mut().zip(&log_l) {
+            *w = if d > 0.0 { d * l } else { 0.0 };
+        }
+        let total = log_w.iter().fold(f64::NEG_INFINITY, |t, w| log_add_exp(t, *w));
+        log_z += total - (n as f64).ln();
+        let weights: Vec<f64> = log_w.iter().map(|w| (w - total).exp()).collect();
+        var_log_z += 1.0 / ess(&weights) - 1.0 / n as f64;
+        beta = next;
+        if beta >= 1.0 {
+            for w in log_w.iter_mut() {
+                *w -= total;
+            }
+            break
+        }
+
+        let picks = systematic_resample(&weights, n, rng);
+        theta = picks.iter().map(|&i| theta[i].clone()).collect();
+        log_l = picks.iter().map(|&i| log_l[i]).collect();
+        let (_, cov) = mean_cov(theta.iter().map(|t| t.as_slice()));
+        let chol = match cov.cholesky() {
+            Some(chol) => chol,
+            None => return Err(format!("the SMC particles collapsed onto a subspace at beta = {:.3e}", beta).into()),
+        };
+        let seeds: Vec<u64> = (0..n).map(|_| rng.gen()).collect();
+        let moved: Vec<(usize, usize)> = theta.par_iter_mut()
+            .zip(log_l.par_iter_mut())
+            .zip(seeds)
+            .map(|((t, l), seed)| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut z = vec![0.0; dim];
+                let mut lp = prior.log_density(t);
+                let mut hits = 0;
+                for _ in 0..config.moves {
+                    z.iter_mut().for_each(|v| *v = unit.sample(&mut rng));
+                    let step = chol.l().mul_vec(&z);
+                    let proposal: Vec<f64> = t.iter().zip(&step).map(|(t, s)| t + scale * s).collect();
+                    let lp_new = prior.log_density(&proposal);
+                    if !lp_new.is_finite() {
+                        continue
+                    }
+                    let l_new = model.log_lik(&proposal);
+                    if l_new > f64::NEG_INFINITY && rng.gen::<f64>().ln() < lp_new - lp + beta * (l_new - *l) {
+                        *t = proposal;
+                        (*l, lp) = (l_new, lp_new);
+                        hits += 1;
+                    }
+                }
+                (config.moves, hits)
+            })
+            .collect();
+        let (p, a) = moved.iter().fold((0, 0), |(p, a), (dp, da)| (p + dp, a + da));
+        proposed += p;
+        accepted += a;
+        // widen the proposals when most moves succeed, narrow them when few do
+        scale *= (a as f64 / p.max(1) as f64 - 0.3).exp();
+    }
+
+    let acceptance = accepted as f64 / proposed.max(1) as f64;
+    if stages > 1 && acceptance < 0.05 {
+        observer.warn(&format!(
+            "only {:.1}% of SMC moves were accepted; the particles barely moved between resamplings, so raise smc.moves",
+            100.0 * acceptance,
+        ));
+    }
+    let weights: Vec<f64> = log_w.iter().map(|w| w.exp()).collect();
+    let info = weights.iter().zip(&log_l).map(|(w, l)| if *w > 0.0 { w * l } else { 0.0 }).sum::<f64>() - log_z;
+    let mut model_stats = model.stats();
+    model_stats.push(("smc_stages".to_string(), stages as f64));
+    model_stats.push(("smc_acceptance".to_string(), acceptance));
+    model_stats.extend(prior.stats());
+    let outputs = match model.n_outputs() {
+        0 => Vec::new(),
+        _ => theta.iter().map(|t| model.outputs(t)).collect(),
+    };
+    Ok(RunResult{
+        log_z,
+        log_z_err: var_log_z.sqrt(),
+        info,
+        iterations: stages,
+        approximate: model.is_approximate(),
+        log_lik_variance: model.log_lik_variance(),
+        model_stats,
+        ess: ess(&weights),
+        targets_met: None,
+        posterior: theta.into_iter().zip(log_w).collect(),
+        modes: Vec::new(),
+        mode_labels: Vec::new(),
+        shrinkage: ShrinkageTrace::default(),
+        dead_birth: Vec::new(),
+        cumulative_log_z: None,
+        cluster_counts: Vec::new(),
+        outputs,
+        truncation: Vec::new(),
+        prior_kl: Vec::new(),
+        log_z_per_volume: None,
+    })
+}
+
+
+/// log Z of one config by nested sampling and by SMC
+///
+/// Fields:
+/// nested, smc: (log Z, its error) from each engine
+/// tension: their difference in combined standard errors
+#[derive(Debug, Clone)]
+pub struct CrossCheck {
+    pub nested: (f64, f64),
+    pub smc: (f64, f64),
+    pub tension: f64,
+}
+
+
+/// run the config by nested sampling and by SMC, with the config's smc
+/// section or the defaults, and compare the evidence. The engines share
+/// nothing but the model and prior, so agreement is evidence that neither
+/// sampler went astray
+pub fn cross_check<R: Rng>(
+        config: &Config,
+        data: &Dataset,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<CrossCheck, Box<dyn Error>> {
+    let nested_config = Config{ smc: None, tempering: None, ..config.clone() };
+    let smc_config = Config{
+        smc: Some(config.smc.clone().unwrap_or_default()),
+        tempering: None,
+        dynamic: None,
+        ..config.clone()
+    };
+    let nested = run_with_data(&nested_config, data, observer, rng)?;
+    let smc = run_with_data(&smc_config, data, observer, rng)?;
+    let tension = (nested.log_z - smc.log_z).abs() / nested.log_z_err.hypot(smc.log_z_err);
+    Ok(CrossCheck{ nested: (nested.log_z, nested.log_z_err), smc: (smc.log_z, smc.log_z_err), tension })
+}
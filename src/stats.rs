@@ -0,0 +1,393 @@
+use rand::Rng;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systematic_resample_counts() {
+        let mut rng = rand::thread_rng();
+        let idx = systematic_resample(&[0.1, 0.0, 0.6, 0.3], 10, &mut rng);
+        assert_eq!(idx.len(), 10);
+        let count = |k| idx.iter().filter(|&&i| i == k).count();
+        // systematic resampling keeps every count within one of n * w
+        assert!(count(0) <= 2);
+        assert_eq!(count(1), 0);
+        assert!(count(2) >= 5 && count(2) <= 7);
+        assert!(count(3) >= 2 && count(3) <= 4);
+    }
+
+    #[test]
+    fn test_stratified_resample_counts() {
+        let mut rng = rand::thread_rng();
+        let idx = stratified_resample(&[0.1, 0.0, 0.6, 0.3], 10, &mut rng);
+        assert_eq!(idx.len(), 10);
+        let count = |k| idx.iter().filter(|&&i| i == k).count();
+        // each stratum holds one pointer, so a count is off by at most two
+        assert_eq!(count(1), 0);
+        assert!(count(2) >= 4 && count(2) <= 8);
+        assert!(idx.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_weighted_quantile() {
+        let points = [(3.0, 1.0), (1.0, 1.0), (2.0, 2.0), (4.0, 0.0)];
+        assert_eq!(weighted_quantile(&points, 0.0), 1.0);
+        assert_eq!(weighted_quantile(&points, 0.25), 1.0);
+        assert_eq!(weighted_quantile(&points, 0.5), 2.0);
+        assert_eq!(weighted_quantile(&points, 0.8), 3.0);
+        assert_eq!(weighted_quantile(&points, 1.0), 3.0);
+        assert!(weighted_quantile(&[], 0.5).is_nan());
+
+        // 100 draws of weight 0.01: 16 of them hold 0.16 of the weight,
+        // though the rounded sums fall either side of it
+        let even: Vec<(f64, f64)> = (0..100).map(|i| (i as f64, 0.01)).collect();
+        assert_eq!(weighted_quantile(&even, 0.16), 15.0);
+        assert_eq!(weighted_quantile(&even, 0.84), 83.0);
+        let mut reversed = even.clone();
+        reversed.reverse();
+        assert_eq!(weighted_quantile(&reversed, 0.16), 15.0);
+        // ties are one value whatever their order
+        let tied = [(1.0, 0.3), (2.0, 0.2), (1.0, 0.2), (3.0, 0.3)];
+        assert_eq!(weighted_quantile(&tied, 0.5), 1.0);
+        assert_eq!(weighted_quantile(&tied, 0.7), 2.0);
+    }
+
+    /// a density tabulated on a fine grid over [lo, hi], as weighted values
+    fn tabulated(lo: f64, hi: f64, density: impl Fn(f64) -> f64) -> Vec<(f64, f64)> {
+        let n = 40000;
+        (0..=n).map(|i| lo + (hi - lo) * i as f64 / n as f64).map(|x| (x, density(x))).collect()
+    }
+
+    #[test]
+    fn test_intervals_of_analytic_posteriors() {
+        let z975 = 1.959963984540054;
+        let normal = |m: f64| move |x: f64| (-0.5 * (x - m).powi(2)).exp();
+        let points = tabulated(-10.0, 10.0, normal(0.0));
+        assert!((weighted_quantile(&points, 0.975) - z975).abs() < 1e-3);
+        // the width is flat to second order around the shortest interval,
+        // so the grid moves its ends by more than the grid spacing
+        let (lo, hi) = hpd_interval(&points, 0.95).unwrap();
+        assert!((lo + z975).abs() < 0.01 && (hi - z975).abs() < 0.01, "{} {}", lo, hi);
+        let region = hpd_region(&points, 0.95);
+        assert_eq!(region.len(), 1);
+        assert!((region[0].0 + z975).abs() < 0.01 && (region[0].1 - z975).abs() < 0.01, "{:?}", region);
+
+        // the HPD interval of an exponential starts at zero, unlike the
+        // central one, [-ln 0.95, -ln 0.05]
+        let points = tabulated(0.0, 30.0, |x| (-x).exp());
+        let (lo, hi) = hpd_interval(&points, 0.9).unwrap();
+        assert!(lo == 0.0 && (hi - 10f64.ln()).abs() < 0.01, "{} {}", lo, hi);
+        assert!((weighted_quantile(&points, 0.05) + 0.95f64.ln()).abs() < 1e-3);
+        assert!((weighted_quantile(&points, 0.95) - 20f64.ln()).abs() < 1e-3);
+        let region = hpd_region(&points, 0.9);
+        assert!(region.len() == 1 && region[0].0 == 0.0 && (region[0].1 - 10f64.ln()).abs() < 0.01, "{:?}", region);
+
+        // two separated modes of equal mass: the region is the 95% interval
+        // of each, where the single shortest interval spans the gap
+        let points = tabulated(-12.0, 12.0, |x| normal(-5.0)(x) + normal(5.0)(x));
+        let region = hpd_region(&points, 0.95);
+        assert_eq!(region.len(), 2, "{:?}", region);
+        for ((lo, hi), m) in region.iter().zip([-5.0, 5.0]) {
+            assert!((lo - m + z975).abs() < 0.01 && (hi - m - z975).abs() < 0.01, "{:?}", region);
+        }
+        let (lo, hi) = hpd_interval(&points, 0.95).unwrap();
+        assert!(lo < -6.0 && hi > 6.0);
+        assert!(hpd_region(&[], 0.9).is_empty() && hpd_interval(&[(1.0, 0.0)], 0.9).is_none());
+    }
+
+    #[test]
+    fn test_chain_ess() {
+        let mut rng = rand::thread_rng();
+        let iid: Vec<f64> = (0..10000).map(|_| rng.gen::<f64>()).collect();
+        assert!((chain_ess(&iid) / 10000.0 - 1.0).abs() < 0.15);
+        // AR(1) with coefficient 0.9: n (1 - 0.9) / (1 + 0.9)
+        let mut ar = vec![0.0];
+        for _ in 1..20000 {
+            ar.push(0.9 * ar[ar.len() - 1] + rng.gen::<f64>() - 0.5);
+        }
+        let expected = 20000.0 * 0.1 / 1.9;
+        assert!((chain_ess(&ar) / expected - 1.0).abs() < 0.3, "{}", chain_ess(&ar));
+        assert_eq!(chain_ess(&[1.0; 10]), 10.0);
+    }
+}
+
+
+/// draw `n` indices in proportion to the (unnormalized) weights using a
+/// single uniform offset and evenly spaced pointers
+///
+/// Every index i appears floor(n w_i) or ceil(n w_i) times, which gives
+/// much lower variance than multinomial resampling.
+pub fn systematic_resample<R: Rng + ?Sized>(weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+    let total: f64 = weights.iter().sum();
+    let mut idx = Vec::with_capacity(n);
+    if n == 0 || total.is_nan() || total <= 0.0 {
+        return idx
+    }
+    let step = total / n as f64;
+    let mut pointer = rng.gen::<f64>() * step;
+    let mut cum = 0.0;
+    for (i, w) in weights.iter().enumerate() {
+        cum += w;
+        while pointer < cum && idx.len() < n {
+            idx.push(i);
+            pointer += step;
+        }
+    }
+    // rounding can leave the last pointer just past the final cumulative sum
+    let last = weights.iter().rposition(|&w| w > 0.0).unwrap_or(0);
+    while idx.len() < n {
+        idx.push(last);
+    }
+    idx
+}
+
+
+/// draw `n` indices in proportion to the (unnormalized) weights with one
+/// uniform pointer in each of `n` equal strata of the cumulative weight
+///
+/// Counts stay close to n w_i as with systematic resampling, but the
+/// pointers are independent, so no periodicity in the order of the
+/// weights can line up with them.
+pub fn stratified_resample<R: Rng + ?Sized>(weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+    let total: f64 = weights.iter().sum();
+    let mut idx = Vec::with_capacity(n);
+    if n == 0 || total.is_nan() || total <= 0.0 {
+        return idx
+    }
+    let step = total / n as f64;
+    let mut i = 0;
+    let mut cum = weights[0];
+    for k in 0..n {
+        let pointer = (k as f64 + rng.gen::<f64>()) * step;
+        while pointer >= cum && i + 1 < weights.len() {
+            i += 1;
+            cum += weights[i];
+        }
+        idx.push(i);
+    }
+    idx
+}
+
+
+/// effective sample size of a set of (unnormalized) weights, (sum w)^2 / sum w^2
+pub fn ess(weights: &[f64]) -> f64 {
+    let s: f64 = weights.iter().sum();
+    let s2: f64 = weights.iter().map(|w| w * w).sum();
+    if s2 > 0.0 { s * s / s2 } else { 0.0 }
+}
+
+
+/// effective sample size of a Markov chain's values, n over the
+/// integrated autocorrelation time, summing autocorrelations in pairs
+/// until a pair turns negative (Geyer's initial positive sequence). A
+/// constant chain counts as independent draws
+pub fn chain_ess(values: &[f64]) -> f64 {
+    let n = values.len();
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n as f64;
+    if n < 4 || var <= 0.0 {
+        return n as f64
+    }
+    let rho = |lag: usize| {
+        values[..n - lag].iter().zip(&values[lag..]).map(|(a, b)| (a - mean) * (b - mean)).sum::<f64>()
+            / (n as f64 * var)
+    };
+    let mut tau = -1.0;
+    let mut lag = 0;
+    while lag + 1 < n {
+        let pair = rho(lag) + rho(lag + 1);
+        if pair <= 0.0 {
+            break
+        }
+        tau += 2.0 * pair;
+        lag += 2;
+    }
+    n as f64 / tau.max(1.0)
+}
+
+
+/// relative slack, in units of the total weight, allowed when comparing a
+/// cumulative weight with a target: sums of weights that should meet a
+/// target exactly, such as 16 draws of weight 0.01 against 0.16, miss it by
+/// a few ulps in either direction
+const WEIGHT_SLACK: f64 = 1e-12;
+
+
+/// sum with Neumaier's compensation, exact to a few ulps however many
+/// terms there are
+#[derive(Debug, Clone, Copy, Default)]
+struct CompensatedSum {
+    sum: f64,
+    carry: f64,
+}
+
+
+impl CompensatedSum {
+    fn add(&mut self, v: f64) {
+        let t = self.sum + v;
+        if self.sum.abs() >= v.abs() {
+            self.carry += (self.sum - t) + v;
+        } else {
+            self.carry += (v - t) + self.sum;
+        }
+        self.sum = t;
+    }
+
+    fn value(&self) -> f64 {
+        self.sum + self.carry
+    }
+}
+
+
+/// the finite values of positive weight, sorted, with the weights of equal
+/// values added together, and the running total weight after each one
+fn cumulative_weights(points: &[(f64, f64)]) -> (Vec<f64>, Vec<f64>) {
+    let mut sorted: Vec<(f64, f64)> = points.iter()
+        .copied()
+        .filter(|(v, w)| v.is_finite() && *w > 0.0 && w.is_finite())
+        .collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut values: Vec<f64> = Vec::with_capacity(sorted.len());
+    let mut cumulative = Vec::with_capacity(sorted.len());
+    let mut running = CompensatedSum::default();
+    for (value, w) in sorted {
+        running.add(w);
+        if values.last() == Some(&value) {
+            *cumulative.last_mut().unwrap() = running.value();
+        } else {
+            values.push(value);
+            cumulative.push(running.value());
+        }
+    }
+    (values, cumulative)
+}
+
+
+/// the p-quantile of weighted values given as (value, weight): the
+/// smallest value whose cumulative weight reaches p of the total. NaN
+/// without positive weight
+///
+/// The weights are summed with compensation and a cumulative weight within
+/// `WEIGHT_SLACK` of the target counts as reaching it, so a quantile that
+/// falls exactly between two values does not flip between them with the
+/// order or the rounding of the weights.
+pub fn weighted_quantile(points: &[(f64, f64)], p: f64) -> f64 {
+    let (values, cumulative) = cumulative_weights(points);
+    let total = match cumulative.last() {
+        Some(total) => *total,
+        None => return f64::NAN,
+    };
+    let target = p * total - WEIGHT_SLACK * total;
+    let i = cumulative.partition_point(|c| *c < target);
+    values[i.min(values.len() - 1)]
+}
+
+
+/// the shortest interval between weighted values that holds `mass` of the
+/// total weight, the highest-density interval of a unimodal posterior.
+/// None without positive weight
+pub fn hpd_interval(points: &[(f64, f64)], mass: f64) -> Option<(f64, f64)> {
+    let (values, cumulative) = cumulative_weights(points);
+    let total = *cumulative.last()?;
+    let target = mass.clamp(0.0, 1.0) * total - WEIGHT_SLACK * total;
+    let before = |i: usize| if i == 0 { 0.0 } else { cumulative[i - 1] };
+    let mut best: Option<(f64, f64)> = None;
+    let mut end = 0;
+    for start in 0..values.len() {
+        end = end.max(start);
+        while end < values.len() && cumulative[end] - before(start) < target {
+            end += 1;
+        }
+        if end == values.len() {
+            break
+        }
+        if best.is_none_or(|(lo, hi)| values[end] - values[start] < hi - lo) {
+            best = Some((values[start], values[end]));
+        }
+    }
+    best
+}
+
+
+/// the highest-posterior-density region holding `mass` of the total weight,
+/// as disjoint intervals in increasing order; a multimodal posterior gives
+/// one interval per separated mode
+///
+/// The density at each value is the weight of its k nearest values on
+/// either side over the width they span, with k about half the square root
+/// of the effective sample size, which follows each mode on its own scale
+/// where a single kernel bandwidth would blur separated modes together.
+/// Values are added from the densest down until they hold `mass`, and runs
+/// of them parted by fewer than k values are joined, since such short gaps
+/// are noise in the density rather than space between modes.
+pub fn hpd_region(points: &[(f64, f64)], mass: f64) -> Vec<(f64, f64)> {
+    let (values, cumulative) = cumulative_weights(points);
+    let n = values.len();
+    let total = match cumulative.last() {
+        Some(total) => *total,
+        None => return Vec::new(),
+    };
+    if n == 1 {
+        return vec![(values[0], values[0])]
+    }
+    let weights: Vec<f64> = (0..n).map(|i| cumulative[i] - if i == 0 { 0.0 } else { cumulative[i - 1] }).collect();
+    let k = ((0.5 * ess(&weights).sqrt()).round() as usize).clamp(1, n - 1);
+    let density: Vec<f64> = (0..n)
+        .map(|i| {
+            let (lo, hi) = (i.saturating_sub(k), (i + k).min(n - 1));
+            let held = cumulative[hi] - if lo == 0 { 0.0 } else { cumulative[lo - 1] };
+            held / (values[hi] - values[lo])
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| density[b].total_cmp(&density[a]).then(a.cmp(&b)));
+    let target = mass.clamp(0.0, 1.0) * total - WEIGHT_SLACK * total;
+    let mut inside = vec![false; n];
+    let mut held = CompensatedSum::default();
+    for &i in &order {
+        if held.value() >= target {
+            break
+        }
+        inside[i] = true;
+        held.add(weights[i]);
+    }
+
+    let mut region: Vec<(usize, usize)> = Vec::new();
+    for i in (0..n).filter(|&i| inside[i]) {
+        match region.last_mut() {
+            Some((_, end)) if i - *end <= k => *end = i,
+            _ => region.push((i, i)),
+        }
+    }
+    region.into_iter().map(|(a, b)| (values[a], values[b])).collect()
+}
+
+
+/// Kolmogorov-Smirnov p-value of samples against the uniform on [0, 1],
+/// from the asymptotic distribution with Stephens' small-sample correction
+pub fn ks_uniform_p_value(u: &[f64]) -> f64 {
+    let n = u.len();
+    if n == 0 {
+        return 1.0
+    }
+    let mut sorted = u.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let d = sorted.iter()
+        .enumerate()
+        .map(|(i, x)| (x - i as f64 / n as f64).max((i + 1) as f64 / n as f64 - x))
+        .fold(0.0, f64::max);
+    let sqrt_n = (n as f64).sqrt();
+    let lambda = (sqrt_n + 0.12 + 0.11 / sqrt_n) * d;
+    if lambda < 0.2 {
+        return 1.0
+    }
+    let p: f64 = (1..=100)
+        .map(|k| {
+            let sign = if k % 2 == 1 { 1.0 } else { -1.0 };
+            sign * (-2.0 * (k * k) as f64 * lambda * lambda).exp()
+        })
+        .sum();
+    (2.0 * p).clamp(0.0, 1.0)
+}
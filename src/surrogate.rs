@@ -0,0 +1,206 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::linalg::{Cholesky, Matrix};
+use crate::screen::{Predictor, ScreenSettings, Screened};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use crate::models::{LogLikelihood, Screen};
+    use crate::test_support::Bowl;
+
+    #[test]
+    fn test_gp_interpolates() {
+        let xs: Vec<Vec<f64>> = (0..25)
+            .map(|i| vec![(i % 5) as f64 - 2.0, (i / 5) as f64 - 2.0])
+            .collect();
+        let ys: Vec<f64> = xs.iter().map(|x| Bowl.log_lik(x)).collect();
+        let gp = GaussianProcess::fit(&xs, &ys).unwrap();
+        let (mean, var) = gp.predict(&[0.5, -0.5]);
+        assert!((mean - Bowl.log_lik(&[0.5, -0.5])).abs() < 0.1);
+        assert!(var >= 0.0);
+        let (mean, _) = gp.predict(&[1.0, 1.0]);
+        assert!((mean - Bowl.log_lik(&[1.0, 1.0])).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_screen_rejects_hopeless_points() {
+        let surrogate = Surrogate::new(Bowl, SurrogateConfig{ audit: 0.0, acquire: 0.0, ..Default::default() });
+        let mut rng = StdRng::seed_from_u64(374);
+        for _ in 0..60 {
+            let theta = [rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0)];
+            surrogate.log_lik(&theta);
+        }
+        assert_eq!(surrogate.screen(&[0.1, 0.1], -1.0), Screen::Pass);
+        assert_eq!(surrogate.screen(&[2.9, 2.9], -1.0), Screen::Reject);
+        assert!(surrogate.is_approximate());
+    }
+}
+
+
+/// settings of the likelihood surrogate
+///
+/// Fields:
+/// min_train: number of true evaluations before screening starts
+/// max_train: the GP is fit to at most this many of the most recent points
+/// retrain_every: refit after this many new true evaluations
+/// kappa: a candidate is skipped only if mean + kappa * sd is below the contour
+/// audit: fraction of skipped candidates that are evaluated anyway, to
+///     measure how often the surrogate wrongly rejects
+/// acquire: fraction of ruled-out candidates evaluated to train the GP,
+///     those where its sd near the contour is largest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SurrogateConfig {
+    pub min_train: usize,
+    pub max_train: usize,
+    pub retrain_every: usize,
+    pub kappa: f64,
+    pub audit: f64,
+    pub acquire: f64,
+}
+
+
+impl Default for SurrogateConfig {
+    fn default() -> SurrogateConfig {
+        SurrogateConfig{ min_train: 50, max_train: 500, retrain_every: 25, kappa: 3.0, audit: 0.02, acquire: 0.05 }
+    }
+}
+
+
+/// Gaussian-process regression with a squared-exponential kernel
+///
+/// Hyperparameters are set from the training data rather than optimized:
+/// a constant mean at the average of y, signal variance at the variance of
+/// y, and one length scale per dimension at the spread of the inputs along
+/// that dimension. That is crude but cheap and stable, which matters more
+/// for pre-screening than a perfect fit.
+///
+/// Fields:
+/// xs: training inputs
+/// length: per-dimension length scales
+/// signal_var: kernel amplitude
+/// mean: constant prior mean
+/// chol: Cholesky factor of the training covariance
+/// alpha: K^{-1} (y - mean)
+#[derive(Debug, Clone)]
+pub struct GaussianProcess {
+    xs: Vec<Vec<f64>>,
+    length: Vec<f64>,
+    signal_var: f64,
+    mean: f64,
+    chol: Cholesky,
+    alpha: Vec<f64>,
+}
+
+
+impl GaussianProcess {
+    pub fn fit(xs: &[Vec<f64>], ys: &[f64]) -> Result<GaussianProcess, Box<dyn Error>> {
+        let n = xs.len();
+        if n < 2 || ys.len() != n {
+            return Err("a GP needs at least two training points with matching outputs".into())
+        }
+        let d = xs[0].len();
+        let mean = ys.iter().sum::<f64>() / n as f64;
+        let signal_var = (ys.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / n as f64).max(1e-12);
+        let length: Vec<f64> = (0..d)
+            .map(|j| {
+                let m = xs.iter().map(|x| x[j]).sum::<f64>() / n as f64;
+                let v = xs.iter().map(|x| (x[j] - m).powi(2)).sum::<f64>() / n as f64;
+                v.sqrt().max(1e-8)
+            })
+            .collect();
+
+        let mut gp = GaussianProcess{
+            xs: xs.to_vec(),
+            length,
+            signal_var,
+            mean,
+            chol: Matrix::identity(1).cholesky().unwrap(),
+            alpha: Vec::new(),
+        };
+        let mut k = Matrix::zeros(n, n);
+        for i in 0..n {
+            for j in 0..=i {
+                let kij = gp.kernel(&xs[i], &xs[j]);
+                k[(i, j)] = kij;
+                k[(j, i)] = kij;
+            }
+        }
+        // grow the nugget until the factorization succeeds
+        let mut jitter = 1e-8 * signal_var;
+        let chol = loop {
+            let mut kj = k.clone();
+            for i in 0..n {
+                kj[(i, i)] += jitter;
+            }
+            if let Some(chol) = kj.cholesky() {
+                break chol
+            }
+            jitter *= 10.0;
+            if jitter > signal_var {
+                return Err("GP covariance is not positive definite".into())
+            }
+        };
+        let centered: Vec<f64> = ys.iter().map(|y| y - mean).collect();
+        gp.alpha = chol.solve(&centered);
+        gp.chol = chol;
+        Ok(gp)
+    }
+
+    fn kernel(&self, a: &[f64], b: &[f64]) -> f64 {
+        let r2: f64 = a.iter().zip(b).zip(&self.length)
+            .map(|((ai, bi), l)| ((ai - bi) / l).powi(2))
+            .sum();
+        self.signal_var * (-0.5 * r2).exp()
+    }
+
+    /// posterior mean and variance at x
+    pub fn predict(&self, x: &[f64]) -> (f64, f64) {
+        let k_star: Vec<f64> = self.xs.iter().map(|xi| self.kernel(x, xi)).collect();
+        let mean = self.mean + k_star.iter().zip(&self.alpha).map(|(k, a)| k * a).sum::<f64>();
+        let v = self.chol.solve_lower(&k_star);
+        let var = self.signal_var - v.iter().map(|vi| vi * vi).sum::<f64>();
+        (mean, var.max(0.0))
+    }
+}
+
+
+impl Predictor for GaussianProcess {
+    type Config = SurrogateConfig;
+    const NAME: &'static str = "surrogate";
+
+    fn settings(config: &SurrogateConfig) -> ScreenSettings {
+        ScreenSettings{
+            min_train: config.min_train,
+            max_train: config.max_train,
+            retrain_every: config.retrain_every,
+            margin: config.kappa,
+            audit: config.audit,
+            acquire: config.acquire,
+        }
+    }
+
+    fn from_training(xs: &[Vec<f64>], ys: &[f64], _config: &SurrogateConfig) -> Result<GaussianProcess, Box<dyn Error>> {
+        GaussianProcess::fit(xs, ys)
+    }
+
+    fn estimate(&self, theta: &[f64]) -> (f64, Option<f64>) {
+        let (mean, var) = self.predict(theta);
+        (mean, Some(var.sqrt()))
+    }
+}
+
+
+/// expensive likelihood with a GP surrogate that pre-screens candidates
+///
+/// A candidate is skipped when the GP's upper bound mean + kappa * sd is
+/// below the contour, unless it is acquired for training; see `Screened`
+/// for the training, acquisition, audit and approximation rules shared
+/// with the neural emulator.
+pub type Surrogate<M> = Screened<M, GaussianProcess>;
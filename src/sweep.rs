@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::data::Dataset;
+use crate::observer::Collect;
+use crate::overrides::resolve;
+use crate::{run_with_data, Config};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_combination_runs() {
+        let data_file = std::env::temp_dir().join(format!("ns_sweep_{}_line.csv", std::process::id()));
+        let rows: String = (0..20).map(|i| format!("{},{}\n", i as f64 / 10.0, 1.0 + 2.0 * i as f64 / 10.0)).collect();
+        std::fs::write(&data_file, format!("x,y\n{}", rows)).unwrap();
+        let base: Value = toml::from_str(&format!(
+            "data_file = {:?}\nsample_num = 100\nparticle_num = 10\nbeta_num = 0\nmu = [1.0, 2.0]\nsd = [1.0, 1.0]\nnoise_sd = 0.5\n",
+            data_file,
+        )).unwrap();
+        let axes = vec![
+            Axis::parse("particle_num=10,1").unwrap(),
+            Axis::parse("sampler.method=rejection,random_walk").unwrap(),
+            Axis::parse("mu=[1.0, 2.0],[0.0, 0.0]").unwrap(),
+        ];
+        assert_eq!(axes[2].values, vec!["[1.0, 2.0]", "[0.0, 0.0]"]);
+        let rows = sweep(&base, &axes, true);
+        std::fs::remove_file(data_file).unwrap();
+
+        assert_eq!(rows.len(), 8);
+        assert_eq!(rows[1].settings, vec!["10", "rejection", "[0.0, 0.0]"]);
+        for row in &rows {
+            let valid = row.settings[0] == "10";
+            assert_eq!(row.outcome.is_ok(), valid);
+            if let Ok(outcome) = &row.outcome {
+                assert!(outcome.log_z.is_finite());
+                assert!(outcome.likelihood_calls >= 110);
+            }
+        }
+    }
+}
+
+
+/// one config key and the values a sweep tries for it
+///
+/// Fields:
+/// key: dotted config key, as for `--set`
+/// values: raw values, read as TOML like `--set` values
+#[derive(Debug, Clone)]
+pub struct Axis {
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+
+impl Axis {
+    /// parse `key=v1,v2,...`; commas inside brackets or quotes belong to
+    /// the value, so `mu=[0, 1],[2, 3]` has two values
+    pub fn parse(spec: &str) -> Result<Axis, Box<dyn Error>> {
+        let (key, list) = spec.split_once('=')
+            .ok_or_else(|| format!("sweep axis {:?} is not of the form key=v1,v2", spec))?;
+        let mut values = Vec::new();
+        let (mut depth, mut quoted, mut start) = (0i32, false, 0);
+        for (i, c) in list.char_indices() {
+            match c {
+                '"' | '\'' => quoted = !quoted,
+                '[' | '{' if !quoted => depth += 1,
+                ']' | '}' if !quoted => depth -= 1,
+                ',' if !quoted && depth == 0 => {
+                    values.push(list[start..i].trim().to_string());
+                    start = i + 1;
+                },
+                _ => (),
+            }
+        }
+        values.push(list[start..].trim().to_string());
+        if values.iter().any(|v| v.is_empty()) {
+            return Err(format!("sweep axis {:?} has an empty value", spec).into())
+        }
+        Ok(Axis{ key: key.trim().to_string(), values })
+    }
+}
+
+
+/// results of one run of a sweep
+///
+/// Fields:
+/// log_z, log_z_err, info, ess: as in `RunResult`
+/// likelihood_calls: evaluations of the data model, including the
+///     initial live points
+/// seconds: wall-clock time of the run
+/// warnings: number of warnings the run raised
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SweepOutcome {
+    pub log_z: f64,
+    pub log_z_err: f64,
+    pub info: f64,
+    pub ess: f64,
+    pub likelihood_calls: usize,
+    pub seconds: f64,
+    pub warnings: usize,
+}
+
+
+/// one combination of a sweep: the value of each axis, in axis order, and
+/// what the run gave, or why it failed
+#[derive(Debug)]
+pub struct SweepRow {
+    pub settings: Vec<String>,
+    pub outcome: Result<SweepOutcome, String>,
+}
+
+
+/// run the config `base` once for every combination of the axis values,
+/// the last axis varying fastest. A combination that fails records its
+/// error and the others carry on; with `parallel` the runs share rayon's
+/// thread pool
+pub fn sweep(base: &Value, axes: &[Axis], parallel: bool) -> Vec<SweepRow> {
+    let run_one = |settings: Vec<String>| {
+        let outcome = run_combination(base, &overrides(axes, &settings), &mut thread_rng()).map_err(|e| e.to_string());
+        SweepRow{ settings, outcome }
+    };
+    if parallel {
+        combinations(axes).into_par_iter().map(run_one).collect()
+    } else {
+        combinations(axes).into_iter().map(run_one).collect()
+    }
+}
+
+
+/// every combination of the axis values, the last axis varying fastest
+pub(crate) fn combinations(axes: &[Axis]) -> Vec<Vec<String>> {
+    let mut combinations: Vec<Vec<String>> = vec![Vec::new()];
+    for axis in axes {
+        combinations = combinations.into_iter()
+            .flat_map(|c| axis.values.iter().map(move |v| [c.clone(), vec![v.clone()]].concat()))
+            .collect();
+    }
+    combinations
+}
+
+
+/// the `--set`s of one combination
+pub(crate) fn overrides(axes: &[Axis], settings: &[String]) -> Vec<String> {
+    axes.iter().zip(settings).map(|(a, v)| format!("{}={}", a.key, v)).collect()
+}
+
+
+/// run `base` with `sets` applied, drawing all random numbers from `rng`
+pub(crate) fn run_combination<R: Rng>(base: &Value, sets: &[String], rng: &mut R) -> Result<SweepOutcome, Box<dyn Error>> {
+    let config: Config = resolve(base.clone(), Vec::new(), sets)?.try_into()?;
+    let mut warnings = Collect::default();
+    let start = Instant::now();
+    let data = Dataset::load(&config.data_file).map_err(|e| format!("cannot read {}: {}", config.data_file.display(), e))?;
+    let result = run_with_data(&config, &data, &mut warnings, rng)?;
+    let calls = result.model_stats.iter()
+        .find(|(k, _)| k == "likelihood_calls")
+        .map_or(0, |(_, v)| *v as usize);
+    Ok(SweepOutcome{
+        log_z: result.log_z,
+        log_z_err: result.log_z_err,
+        info: result.info,
+        ess: result.ess,
+        likelihood_calls: calls,
+        seconds: start.elapsed().as_secs_f64(),
+        warnings: warnings.warnings.len(),
+    })
+}
+
+
+/// write the sweep as a CSV table with one column per axis followed by
+/// the outcome, and the error message for failed runs
+pub fn write_table(path: &Path, axes: &[Axis], rows: &[SweepRow]) -> Result<(), Box<dyn Error>> {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut header: Vec<String> = axes.iter().map(|a| quote(&a.key)).collect();
+    header.extend(["log_z", "log_z_err", "info", "ess", "likelihood_calls", "seconds", "warnings", "error"].map(String::from));
+    writeln!(out, "{}", header.join(","))?;
+    for row in rows {
+        let mut fields: Vec<String> = row.settings.iter().map(|s| quote(s)).collect();
+        match &row.outcome {
+            Ok(o) => fields.extend([
+                o.log_z.to_string(),
+                o.log_z_err.to_string(),
+                o.info.to_string(),
+                o.ess.to_string(),
+                o.likelihood_calls.to_string(),
+                format!("{:.3}", o.seconds),
+                o.warnings.to_string(),
+                String::new(),
+            ]),
+            Err(e) => {
+                fields.extend(std::iter::repeat_n(String::new(), 7));
+                fields.push(quote(e));
+            },
+        }
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::evidence::log_add_exp;
+use crate::observer::Observer;
+use crate::Particles;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::data::Dataset;
+    use crate::observer::Collect;
+    use crate::sampler::{Method, SamplerConfig};
+    use crate::{run_with_data, Config};
+
+    static SEEN: OnceLock<Arc<Mutex<Vec<Record>>>> = OnceLock::new();
+
+    struct Keep;
+
+    impl TelemetrySink for Keep {
+        fn record(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+            SEEN.get_or_init(Default::default).lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    fn keep(_config: &SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+        Ok(Box::new(Keep))
+    }
+
+    #[test]
+    fn test_sinks_receive_the_run_as_it_goes() {
+        register_sink("test_keep", keep).unwrap();
+        assert!(register_sink("csv", keep).is_err());
+        let csv = std::env::temp_dir().join(format!("ns_telemetry_{}.csv", std::process::id()));
+        let jsonl = csv.with_extension("jsonl");
+
+        let x: Vec<f64> = (0..20).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|x| 1.0 + 2.0 * x).collect();
+        let data = Dataset::from_columns(vec![x, y], None).unwrap();
+        let sinks = vec![
+            SinkConfig{ sink: "csv".to_string(), file: Some(csv.clone()) },
+            SinkConfig{ sink: "jsonl".to_string(), file: Some(jsonl.clone()) },
+            SinkConfig{ sink: "test_keep".to_string(), file: None },
+        ];
+        let config = Config{
+            sample_num: 300,
+            particle_num: 30,
+            mu: vec![0.0, 0.0],
+            sd: vec![5.0, 5.0],
+            noise_sd: Some(0.5),
+            sampler: SamplerConfig{ method: Method::RandomWalk, ..Default::default() },
+            telemetry: Some(TelemetryConfig{ every: 50, sinks }),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(434);
+        let result = run_with_data(&config, &data, &mut Collect::default(), &mut rng).unwrap();
+
+        let seen = SEEN.get().unwrap().lock().unwrap().clone();
+        assert_eq!(seen.iter().map(|r| r.iteration).collect::<Vec<usize>>(), vec![50, 100, 150, 200, 250, 300]);
+        assert!(seen.windows(2).all(|w| w[1].log_z >= w[0].log_z && w[1].log_x < w[0].log_x));
+        // by the end the dead points hold nearly all of the evidence
+        let last = seen.last().unwrap();
+        assert!((last.log_z - result.log_z).abs() < 0.1 && last.live_fraction < 0.1, "{:?} {}", last, result.log_z);
+
+        let csv_text = std::fs::read_to_string(&csv).unwrap();
+        assert_eq!(csv_text.lines().count(), 7);
+        assert!(csv_text.starts_with("iteration,elapsed_secs,n_live,log_z,log_x"));
+        let json_text = std::fs::read_to_string(&jsonl).unwrap();
+        assert!(json_text.lines().all(|l| l.starts_with("{\"") && l.ends_with('}')));
+        assert_eq!(json_text.lines().filter(|l| l.starts_with("{\"iteration\":")).count(), 6);
+        assert_eq!(json_string("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+        std::fs::remove_file(csv).unwrap();
+        std::fs::remove_file(jsonl).unwrap();
+    }
+}
+
+
+/// the state of a static run after an iteration, as sent to the sinks
+///
+/// Fields:
+/// iteration: iterations completed
+/// elapsed_secs: wall time since the sinks were set up
+/// n_live: live points
+/// log_z: log evidence of the dead points so far, a lower bound on the
+///     final log Z
+/// log_x: log prior volume left inside the contour
+/// worst_log_l, best_log_l: lowest and highest live log likelihood
+/// live_fraction: share of the evidence the live points would add if
+///     the run ended now
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub iteration: usize,
+    pub elapsed_secs: f64,
+    pub n_live: usize,
+    pub log_z: f64,
+    pub log_x: f64,
+    pub worst_log_l: f64,
+    pub best_log_l: f64,
+    pub live_fraction: f64,
+}
+
+
+/// somewhere the progress of a run goes, e.g. a file or a message queue
+///
+/// Sinks see only `Record`s and warnings, never the engine's internals.
+/// A sink that fails is reported once as a warning and dropped, so that
+/// monitoring never stops a run.
+pub trait TelemetrySink: Send {
+    fn record(&mut self, record: &Record) -> Result<(), Box<dyn Error>>;
+
+    fn warn(&mut self, _message: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// called once when the run ends
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+
+/// one sink of the `telemetry` section
+///
+/// Fields:
+/// sink: "stdout", "csv", "jsonl" or the name of a sink registered with
+///     `register_sink`
+/// file: the file of the csv and jsonl sinks
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SinkConfig {
+    pub sink: String,
+    pub file: Option<PathBuf>,
+}
+
+
+/// settings of telemetry
+///
+/// Fields:
+/// every: send a record after every this many iterations
+/// sinks: where the records go
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub every: usize,
+    pub sinks: Vec<SinkConfig>,
+}
+
+
+impl Default for TelemetryConfig {
+    fn default() -> TelemetryConfig {
+        TelemetryConfig{ every: 100, sinks: Vec::new() }
+    }
+}
+
+
+/// a line per record on stdout
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+
+impl TelemetrySink for StdoutSink {
+    fn record(&mut self, r: &Record) -> Result<(), Box<dyn Error>> {
+        println!(
+            "iteration {}: log_z >= {:.4}, log_x = {:.3}, log_l in [{:.4}, {:.4}], live share {:.2e}",
+            r.iteration, r.log_z, r.log_x, r.worst_log_l, r.best_log_l, r.live_fraction,
+        );
+        Ok(())
+    }
+}
+
+
+/// a row per record in a CSV file; warnings are left out
+pub struct CsvSink {
+    out: BufWriter<File>,
+}
+
+
+impl CsvSink {
+    pub fn create(path: &std::path::Path) -> Result<CsvSink, Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "iteration,elapsed_secs,n_live,log_z,log_x,worst_log_l,best_log_l,live_fraction")?;
+        Ok(CsvSink{ out })
+    }
+}
+
+
+impl TelemetrySink for CsvSink {
+    fn record(&mut self, r: &Record) -> Result<(), Box<dyn Error>> {
+        writeln!(
+            self.out,
+            "{},{:.3},{},{:e},{:e},{:e},{:e},{:e}",
+            r.iteration, r.elapsed_secs, r.n_live, r.log_z, r.log_x, r.worst_log_l, r.best_log_l, r.live_fraction,
+        )?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(self.out.flush()?)
+    }
+}
+
+
+/// a JSON object per line for each record and warning, flushed as it is
+/// written so that the file can be followed while the run goes on
+pub struct JsonLinesSink {
+    out: BufWriter<File>,
+}
+
+
+impl JsonLinesSink {
+    pub fn create(path: &std::path::Path) -> Result<JsonLinesSink, Box<dyn Error>> {
+        Ok(JsonLinesSink{ out: BufWriter::new(File::create(path)?) })
+    }
+}
+
+
+/// a float as JSON, which has no infinities or NaN
+pub(crate) fn json_number(x: f64) -> String {
+    match x.is_finite() {
+        true => format!("{:e}", x),
+        false => "null".to_string(),
+    }
+}
+
+
+/// a string as a quoted JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+
+impl TelemetrySink for JsonLinesSink {
+    fn record(&mut self, r: &Record) -> Result<(), Box<dyn Error>> {
+        writeln!(
+            self.out,
+            "{{\"iteration\":{},\"elapsed_secs\":{},\"n_live\":{},\"log_z\":{},\"log_x\":{},\"worst_log_l\":{},\
+             \"best_log_l\":{},\"live_fraction\":{}}}",
+            r.iteration, json_number(r.elapsed_secs), r.n_live, json_number(r.log_z), json_number(r.log_x),
+            json_number(r.worst_log_l), json_number(r.best_log_l), json_number(r.live_fraction),
+        )?;
+        Ok(self.out.flush()?)
+    }
+
+    fn warn(&mut self, message: &str) -> Result<(), Box<dyn Error>> {
+        writeln!(self.out, "{{\"warning\":{}}}", json_string(message))?;
+        Ok(self.out.flush()?)
+    }
+}
+
+
+/// builds a sink from its section of the config
+pub type SinkConstructor = fn(&SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>>;
+
+
+fn stdout_sink(_config: &SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+    Ok(Box::new(StdoutSink))
+}
+
+
+fn csv_sink(config: &SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+    Ok(Box::new(CsvSink::create(config.file.as_ref().ok_or("the csv sink needs a file")?)?))
+}
+
+
+fn jsonl_sink(config: &SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+    Ok(Box::new(JsonLinesSink::create(config.file.as_ref().ok_or("the jsonl sink needs a file")?)?))
+}
+
+
+/// the sinks selectable with `sink = "name"`, built-ins first
+fn registry() -> &'static RwLock<HashMap<String, SinkConstructor>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, SinkConstructor>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: [(&str, SinkConstructor); 3] = [("stdout", stdout_sink), ("csv", csv_sink), ("jsonl", jsonl_sink)];
+        RwLock::new(builtins.into_iter().map(|(name, f)| (name.to_string(), f)).collect())
+    })
+}
+
+
+/// make a sink selectable from configs as `sink = "name"`, e.g. one that
+/// pushes records to a message queue. Names are unique
+pub fn register_sink(name: &str, constructor: SinkConstructor) -> Result<(), Box<dyn Error>> {
+    let mut sinks = registry().write().map_err(|_| "the sink registry is poisoned")?;
+    if name.is_empty() || sinks.contains_key(name) {
+        return Err(format!("a sink named {:?} is already registered or the name is empty", name).into())
+    }
+    sinks.insert(name.to_string(), constructor);
+    Ok(())
+}
+
+
+/// build the sink registered as `config.sink`
+pub fn build_sink(config: &SinkConfig) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+    let constructor = registry().read().map_err(|_| "the sink registry is poisoned")?.get(&config.sink).copied();
+    match constructor {
+        Some(constructor) => constructor(config),
+        None => Err(format!("unknown telemetry sink {:?}", config.sink).into()),
+    }
+}
+
+
+/// an observer that turns the particles into `Record`s for its sinks,
+/// passing every event on to the observer it wraps
+///
+/// Fields:
+/// inner: the wrapped observer
+/// sinks: the sinks still working
+/// every: iterations between records
+/// start: when the sinks were set up
+/// counted: dead points already summed into log_z and log_x
+/// log_z, log_x: evidence and prior volume of the counted dead points
+pub struct Telemetry<'a> {
+    inner: &'a mut dyn Observer,
+    sinks: Vec<Box<dyn TelemetrySink>>,
+    every: usize,
+    start: Instant,
+    counted: usize,
+    log_z: f64,
+    log_x: f64,
+}
+
+
+impl<'a> Telemetry<'a> {
+    pub fn new(inner: &'a mut dyn Observer, sinks: Vec<Box<dyn TelemetrySink>>, every: usize) -> Telemetry<'a> {
+        Telemetry{ inner, sinks, every, start: Instant::now(), counted: 0, log_z: f64::NEG_INFINITY, log_x: 0.0 }
+    }
+
+    /// the sinks of a `telemetry` section
+    pub fn from_config(inner: &'a mut dyn Observer, config: &TelemetryConfig) -> Result<Telemetry<'a>, Box<dyn Error>> {
+        let sinks = config.sinks.iter().map(build_sink).collect::<Result<Vec<_>, _>>()?;
+        Ok(Telemetry::new(inner, sinks, config.every))
+    }
+
+    fn record(&mut self, iteration: usize, particles: &Particles) -> Record {
+        // the dead points in order of death, each weighted by the shell it
+        // took from the volume left
+        for p in &particles.dead()[self.counted..] {
+            self.log_z = log_add_exp(self.log_z, p.log_weight() + p.log_l());
+            self.log_x += (-(p.log_weight() - self.log_x).exp()).ln_1p();
+        }
+        self.counted = particles.dead().len();
+        let live = particles.live();
+        let n_live = live.len();
+        let live_log_z = live.iter().fold(f64::NEG_INFINITY, |z, p| log_add_exp(z, p.log_l()))
+            + self.log_x - (n_live as f64).ln();
+        Record{
+            iteration,
+            elapsed_secs: self.start.elapsed().as_secs_f64(),
+            n_live,
+            log_z: self.log_z,
+            log_x: self.log_x,
+            worst_log_l: live.front().map_or(f64::NAN, |p| p.log_l()),
+            best_log_l: live.back().map_or(f64::NAN, |p| p.log_l()),
+            live_fraction: (live_log_z - log_add_exp(self.log_z, live_log_z)).exp(),
+        }
+    }
+
+    /// send to every sink, dropping those that fail
+    fn send(&mut self, event: impl Fn(&mut dyn TelemetrySink) -> Result<(), Box<dyn Error>>) {
+        let mut failed = Vec::new();
+        self.sinks.retain_mut(|sink| match event(sink.as_mut()) {
+            Ok(()) => true,
+            Err(e) => {
+                failed.push(e.to_string());
+                false
+            },
+        });
+        for e in failed {
+            self.inner.warn(&format!("a telemetry sink failed and was dropped: {}", e));
+        }
+    }
+}
+
+
+/// greatest common divisor, for the iterations both observers want
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+
+impl Observer for Telemetry<'_> {
+    fn warn(&mut self, message: &str) {
+        self.send(|sink| sink.warn(message));
+        self.inner.warn(message);
+    }
+
+    fn every(&self) -> usize {
+        gcd(self.every, self.inner.every())
+    }
+
+    fn on_iteration(&mut self, iteration: usize, particles: &Particles) {
+        // done >= 1, so an every of 0 never matches
+        let done = iteration + 1;
+        if done.is_multiple_of(self.every) {
+            let record = self.record(done, particles);
+            self.send(|sink| sink.record(&record));
+        }
+        if done.is_multiple_of(self.inner.every()) {
+            self.inner.on_iteration(iteration, particles);
+        }
+    }
+}
+
+
+impl Drop for Telemetry<'_> {
+    fn drop(&mut self) {
+        self.send(|sink| sink.flush());
+    }
+}
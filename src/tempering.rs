@@ -0,0 +1,374 @@
+use std::error::Error;
+use std::iter::zip;
+
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::ShrinkageTrace;
+use crate::dist::Normal;
+use crate::evidence::log_add_exp;
+use crate::geometry::mean_cov;
+use crate::linalg::Cholesky;
+use crate::models::LogLikelihood;
+use crate::observer::Observer;
+use crate::priors::Prior;
+use crate::sampler::MAX_ATTEMPTS;
+use crate::stats::chain_ess;
+use crate::RunResult;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::observer::Collect;
+    use crate::priors::NormalPrior;
+    use crate::test_support::Peak;
+
+    #[test]
+    fn test_stepping_stones_recover_the_evidence() {
+        let model = Peak{ s: 0.1, dim: 2 };
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 1.0]).unwrap());
+        let truth = 2.0 * (0.1f64 / (1.0f64 + 0.01).sqrt()).ln();
+        let config = TemperingConfig{ sweeps: 1000, burn_in: 300, ..Default::default() };
+        let mut rng = StdRng::seed_from_u64(427);
+        let mut observer = Collect::default();
+        let result = run_tempering(&model, prior.as_ref(), &config, &mut observer, &mut rng).unwrap();
+        assert!(result.log_z_err > 0.0 && result.log_z_err < 0.2, "{}", result.log_z_err);
+        assert!((result.log_z - truth).abs() < 4.0 * result.log_z_err + 0.05, "{} vs {}", result.log_z, truth);
+        assert_eq!(result.posterior.len(), 1000);
+        // the posterior is N(0, s^2 / (1 + s^2)) in each parameter
+        let var = result.posterior.iter().map(|(t, lw)| lw.exp() * t[0] * t[0]).sum::<f64>();
+        assert!((var / (0.01 / 1.01) - 1.0).abs() < 0.25, "{}", var);
+        assert!(result.ess > 50.0 && result.ess <= 1000.0);
+        let swaps = result.model_stats.iter().find(|(k, _)| k == "tempering_min_swap_acceptance").unwrap().1;
+        assert!(swaps > MIN_SWAP_ACCEPTANCE, "{}", swaps);
+        assert!(observer.warnings.is_empty(), "{:?}", observer.warnings);
+
+        let too_few = TemperingConfig{ chains: 1, ..config };
+        assert!(run_tempering(&model, prior.as_ref(), &too_few, &mut observer, &mut rng).is_err());
+    }
+}
+
+
+/// swap acceptance between neighbouring temperatures below which the
+/// ladder is too sparse for the chains to exchange states
+const MIN_SWAP_ACCEPTANCE: f64 = 0.1;
+
+
+/// settings of parallel tempering, an MCMC alternative to nested sampling
+/// for comparison runs and for posteriors the live points cannot follow
+///
+/// Fields:
+/// chains: tempered chains, from the prior (beta = 0) to the posterior
+///     (beta = 1)
+/// ladder_power: the chains sit at beta_k = (k / (chains - 1))^ladder_power,
+///     most of them near the prior, where log L changes fastest with beta
+/// sweeps: sweeps of every chain after burn-in, each followed by swaps
+///     between neighbouring temperatures
+/// burn_in: sweeps before those, while the proposals adapt; discarded
+/// steps: Metropolis steps per chain and sweep
+/// target_acceptance: acceptance the proposal widths adapt towards
+/// batches: blocks of consecutive sweeps whose scatter in log Z gives its
+///     error
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TemperingConfig {
+    pub chains: usize,
+    pub ladder_power: f64,
+    pub sweeps: usize,
+    pub burn_in: usize,
+    pub steps: usize,
+    pub target_acceptance: f64,
+    pub batches: usize,
+}
+
+
+impl Default for TemperingConfig {
+    fn default() -> TemperingConfig {
+        TemperingConfig{
+            chains: 16,
+            ladder_power: 4.0,
+            sweeps: 2000,
+            burn_in: 500,
+            steps: 5,
+            target_acceptance: 0.3,
+            batches: 10,
+        }
+    }
+}
+
+
+/// one chain of the ladder; temperatures stay with their chain, states
+/// move between chains by swaps
+///
+/// Fields:
+/// beta: power of the likelihood this chain targets
+/// theta, log_l, log_prior: the current state
+/// rng: the chain's own random numbers, so sweeps can run in parallel
+/// scale: proposal width, relative to `chol` or to the prior scale
+/// chol: Cholesky factor of the chain's covariance, once burn-in measured it
+/// history: states of the first half of burn-in, for that covariance
+/// proposed, accepted: Metropolis steps since the last reset
+struct Chain {
+    beta: f64,
+    theta: Vec<f64>,
+    log_l: f64,
+    log_prior: f64,
+    rng: StdRng,
+    scale: f64,
+    chol: Option<Cholesky>,
+    history: Vec<Vec<f64>>,
+    proposed: usize,
+    accepted: usize,
+}
+
+
+impl Chain {
+    /// `steps` Metropolis steps on prior * L^beta; the chain at beta = 0
+    /// draws independently from the prior instead
+    fn sweep(
+            &mut self,
+            model: &dyn LogLikelihood,
+            prior: &dyn Prior,
+            steps: usize,
+            base: &[f64],
+            adapt: Option<(f64, f64)>,
+    ) {
+        if self.beta == 0.0 {
+            prior.sample_into(&mut self.theta, &mut self.rng);
+            self.log_l = model.log_lik(&self.theta);
+            self.log_prior = prior.log_density(&self.theta);
+            return
+        }
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        let mut z = vec![0.0; self.theta.len()];
+        let mut proposal = self.theta.clone();
+        for _ in 0..steps {
+            z.iter_mut().for_each(|v| *v = unit.sample(&mut self.rng));
+            let step = match &self.chol {
+                Some(chol) => chol.l().mul_vec(&z),
+                None => zip(&z, base).map(|(z, b)| z * b).collect(),
+            };
+            for ((p, t), s) in proposal.iter_mut().zip(&self.theta).zip(&step) {
+                *p = t + self.scale * s;
+            }
+            self.proposed += 1;
+            let log_prior = prior.log_density(&proposal);
+            let mut accept = false;
+            if log_prior.is_finite() {
+                let log_l = model.log_lik(&proposal);
+                let log_ratio = log_prior - self.log_prior + self.beta * (log_l - self.log_l);
+                if log_l > f64::NEG_INFINITY && self.rng.gen::<f64>().ln() < log_ratio {
+                    self.theta.copy_from_slice(&proposal);
+                    (self.log_l, self.log_prior) = (log_l, log_prior);
+                    self.accepted += 1;
+                    accept = true;
+                }
+            }
+            // Robbins-Monro steps towards the target acceptance
+            if let Some((target, gain)) = adapt {
+                self.scale *= (gain * (accept as u8 as f64 - target)).exp();
+            }
+        }
+    }
+}
+
+
+/// log of the mean of exp(values)
+fn log_mean_exp(values: impl Iterator<Item = f64>) -> f64 {
+    let (total, n) = values.fold((f64::NEG_INFINITY, 0), |(t, n), v| (log_add_exp(t, v), n + 1));
+    total - (n as f64).ln()
+}
+
+
+/// the stepping-stone estimate of log Z from each chain's log L after
+/// burn-in: the product over the ladder of E_k[L^(beta_{k+1} - beta_k)],
+/// each taken under the chain at beta_k (Xie et al. 2011)
+fn stepping_stones(betas: &[f64], log_l: &[Vec<f64>], sweeps: std::ops::Range<usize>) -> f64 {
+    betas.windows(2)
+        .zip(log_l)
+        .map(|(b, ll)| {
+            let db = b[1] - b[0];
+            log_mean_exp(ll[sweeps.clone()].iter().map(|l| if db > 0.0 { db * l } else { 0.0 }))
+        })
+        .sum()
+}
+
+
+/// sample the posterior by parallel tempering and estimate the evidence
+/// with stepping stones, returning a result like a nested sampling run's
+///
+/// The posterior holds the chain at beta = 1 after burn-in with equal
+/// weights; its ess is the chain's autocorrelation-corrected sample size
+/// in log L. The log Z error is the scatter of the estimate between
+/// `batches` blocks of sweeps. Swaps between neighbouring temperatures
+/// that are accepted too rarely are reported to `observer`: the chains
+/// then fail to pass states down from the prior, and more chains are
+/// needed.
+pub fn run_tempering<R: Rng + ?Sized>(
+        model: &dyn LogLikelihood,
+        prior: &dyn Prior,
+        config: &TemperingConfig,
+        observer: &mut dyn Observer,
+        rng: &mut R,
+) -> Result<RunResult, Box<dyn Error>> {
+    if config.chains < 2 {
+        return Err("parallel tempering needs at least two chains".into())
+    }
+    if config.batches < 2 || config.sweeps < config.batches {
+        return Err(format!("{} sweeps cannot be split into {} batches for the log Z error", config.sweeps, config.batches).into())
+    }
+    let dim = prior.dim();
+    let last = (config.chains - 1) as f64;
+    let betas: Vec<f64> = (0..config.chains).map(|k| (k as f64 / last).powf(config.ladder_power)).collect();
+    let base = prior.scale();
+    let mut chains = Vec::with_capacity(config.chains);
+    for &beta in &betas {
+        let mut chain_rng = StdRng::seed_from_u64(rng.gen());
+        let mut theta = vec![0.0; dim];
+        let mut log_l = f64::NEG_INFINITY;
+        for _ in 0..MAX_ATTEMPTS {
+            prior.sample_into(&mut theta, &mut chain_rng);
+            log_l = model.log_lik(&theta);
+            if log_l > f64::NEG_INFINITY {
+                break
+            }
+        }
+        if log_l == f64::NEG_INFINITY {
+            return Err(format!("no prior draw in {} has a finite likelihood", MAX_ATTEMPTS).into())
+        }
+        let log_prior = prior.log_density(&theta);
+        chains.push(Chain{
+            beta,
+            theta,
+            log_l,
+            log_prior,
+            rng: chain_rng,
+            scale: 2.38 / (dim as f64).sqrt(),
+            chol: None,
+            history: Vec::new(),
+            proposed: 0,
+            accepted: 0,
+        });
+    }
+
+    let half = config.burn_in / 2;
+    let mut log_l: Vec<Vec<f64>> = vec![Vec::with_capacity(config.sweeps); config.chains];
+    let mut posterior = Vec::with_capacity(config.sweeps);
+    let mut swaps = vec![(0usize, 0usize); config.chains - 1];
+    for sweep in 0..config.burn_in + config.sweeps {
+        let adapt = (sweep < config.burn_in)
+            .then(|| (config.target_acceptance, 1.0 / (1.0 + (sweep % half.max(1)) as f64).powf(0.6)));
+        chains.par_iter_mut().for_each(|chain| chain.sweep(model, prior, config.steps, &base, adapt));
+
+        // the first half of burn-in measures each chain's covariance, the
+        // second adapts the width of proposals shaped by it
+        if sweep < half {
+            for chain in chains.iter_mut() {
+                chain.history.push(chain.theta.clone());
+            }
+        } else if sweep == half {
+            for chain in chains.iter_mut() {
+                let (_, cov) = mean_cov(chain.history.iter().map(|t| t.as_slice()));
+                if let Some(chol) = cov.cholesky() {
+                    chain.chol = Some(chol);
+                    chain.scale = 2.38 / (dim as f64).sqrt();
+                }
+                chain.history = Vec::new();
+            }
+        }
+        if sweep == config.burn_in {
+            for chain in chains.iter_mut() {
+                (chain.proposed, chain.accepted) = (0, 0);
+            }
+        }
+
+        // swap neighbours, even pairs on even sweeps and odd ones on odd
+        for k in (sweep % 2..config.chains - 1).step_by(2) {
+            let (lower, upper) = chains.split_at_mut(k + 1);
+            let (a, b) = (&mut lower[k], &mut upper[0]);
+            let log_ratio = (b.beta - a.beta) * (a.log_l - b.log_l);
+            let accept = rng.gen::<f64>().ln() < log_ratio;
+            if accept {
+                std::mem::swap(&mut a.theta, &mut b.theta);
+                std::mem::swap(&mut a.log_l, &mut b.log_l);
+                std::mem::swap(&mut a.log_prior, &mut b.log_prior);
+            }
+            if sweep >= config.burn_in {
+                swaps[k].0 += 1;
+                swaps[k].1 += accept as usize;
+            }
+        }
+
+        if sweep >= config.burn_in {
+            for (trace, chain) in log_l.iter_mut().zip(&chains) {
+                trace.push(chain.log_l);
+            }
+            posterior.push(chains[config.chains - 1].theta.clone());
+        }
+    }
+
+    let log_z = stepping_stones(&betas, &log_l, 0..config.sweeps);
+    let size = config.sweeps / config.batches;
+    let batches: Vec<f64> = (0..config.batches)
+        .map(|b| stepping_stones(&betas, &log_l, b * size..(b + 1) * size))
+        .collect();
+    let mean = batches.iter().sum::<f64>() / batches.len() as f64;
+    let var = batches.iter().map(|z| (z - mean).powi(2)).sum::<f64>() / (batches.len() - 1) as f64;
+    let log_z_err = (var / batches.len() as f64).sqrt();
+
+    let swap_rates: Vec<f64> = swaps.iter().map(|(tried, accepted)| *accepted as f64 / (*tried).max(1) as f64).collect();
+    let (worst, min_swap) = swap_rates.iter().copied().enumerate()
+        .fold((0, f64::INFINITY), |best, (k, r)| if r < best.1 { (k, r) } else { best });
+    if min_swap < MIN_SWAP_ACCEPTANCE {
+        observer.warn(&format!(
+            "only {:.1}% of swaps between beta = {:.3e} and {:.3e} are accepted; add tempering chains",
+            100.0 * min_swap, betas[worst], betas[worst + 1],
+        ));
+    }
+
+    let cold = &log_l[config.chains - 1];
+    let n = posterior.len();
+    let info = cold.iter().sum::<f64>() / n as f64 - log_z;
+    let cold_chain = &chains[config.chains - 1];
+    let mut model_stats = model.stats();
+    model_stats.push(("tempering_chains".to_string(), config.chains as f64));
+    model_stats.push(("tempering_mean_swap_acceptance".to_string(), swap_rates.iter().sum::<f64>() / swap_rates.len() as f64));
+    model_stats.push(("tempering_min_swap_acceptance".to_string(), min_swap));
+    model_stats.push((
+        "tempering_cold_acceptance".to_string(),
+        cold_chain.accepted as f64 / cold_chain.proposed.max(1) as f64,
+    ));
+    model_stats.extend(prior.stats());
+    let outputs = match model.n_outputs() {
+        0 => Vec::new(),
+        _ => posterior.iter().map(|t| model.outputs(t)).collect(),
+    };
+    let log_w = -(n as f64).ln();
+    Ok(RunResult{
+        log_z,
+        log_z_err,
+        info,
+        iterations: config.sweeps,
+        approximate: model.is_approximate(),
+        log_lik_variance: model.log_lik_variance(),
+        model_stats,
+        ess: chain_ess(cold),
+        targets_met: None,
+        posterior: posterior.into_iter().map(|t| (t, log_w)).collect(),
+        modes: Vec::new(),
+        mode_labels: Vec::new(),
+        shrinkage: ShrinkageTrace::default(),
+        dead_birth: Vec::new(),
+        cumulative_log_z: None,
+        cluster_counts: Vec::new(),
+        outputs,
+        truncation: Vec::new(),
+        prior_kl: Vec::new(),
+        log_z_per_volume: None,
+    })
+}
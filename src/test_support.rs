@@ -0,0 +1,34 @@
+use crate::models::LogLikelihood;
+
+
+/// unnormalized Gaussian likelihood of width s in each of `dim`
+/// parameters, exp(-|x|^2 / 2 s^2); under a standard normal prior
+/// Z = (s / sqrt(1 + s^2))^dim
+pub(crate) struct Peak {
+    pub s: f64,
+    pub dim: usize,
+}
+
+impl LogLikelihood for Peak {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        -0.5 * theta.iter().map(|t| (t / self.s).powi(2)).sum::<f64>()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+}
+
+
+/// log L = -|x|^2 / 2 in two dimensions
+pub(crate) struct Bowl;
+
+impl LogLikelihood for Bowl {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        -0.5 * theta.iter().map(|t| t * t).sum::<f64>()
+    }
+
+    fn dim(&self) -> usize {
+        2
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_units_and_scales() {
+        let specs = vec![
+            ParameterSpec{ name: Some("E".to_string()), unit: Some("keV".to_string()), scale: 1e-3 },
+            ParameterSpec::default(),
+            ParameterSpec{ unit: Some("1e-9".to_string()), scale: 1e9, ..Default::default() },
+        ];
+        assert_eq!((name(&specs, 0), label(&specs, 0)), ("E".to_string(), "E [keV]".to_string()));
+        assert_eq!((name(&specs, 1), label(&specs, 1)), ("theta1".to_string(), "theta1".to_string()));
+        assert_eq!(label(&specs, 2), "theta2 [1e-9]");
+        assert_eq!(shown(&specs, 0, 2500.0), 2.5);
+        assert_eq!(shown(&specs, 1, 2500.0), 2500.0);
+        assert!((shown(&specs, 2, 3.2e-9) - 3.2).abs() < 1e-12);
+        // parameters without a spec keep their index and values
+        assert_eq!((label(&[], 4), shown(&[], 4, -1.5)), ("theta4".to_string(), -1.5));
+    }
+}
+
+
+/// how one parameter appears in summaries and exported tables; the
+/// sampler and every file read back by later runs keep the parameter as
+/// the model defines it
+///
+/// Fields:
+/// name: shown in place of theta<j>
+/// unit: unit of the shown values, e.g. "keV"
+/// scale: the shown value is the parameter times this, e.g. 1e-3 to show
+///     a parameter in eV in keV, or 1e9 to show it in units of 1e-9
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ParameterSpec {
+    pub name: Option<String>,
+    pub unit: Option<String>,
+    pub scale: f64,
+}
+
+
+impl Default for ParameterSpec {
+    fn default() -> ParameterSpec {
+        ParameterSpec{ name: None, unit: None, scale: 1.0 }
+    }
+}
+
+
+/// the name of parameter `j`, theta<j> unless `specs` gives one
+pub fn name(specs: &[ParameterSpec], j: usize) -> String {
+    specs.get(j).and_then(|s| s.name.clone()).unwrap_or_else(|| format!("theta{}", j))
+}
+
+
+/// the name of parameter `j` followed by its unit in brackets, if it has one
+pub fn label(specs: &[ParameterSpec], j: usize) -> String {
+    match specs.get(j).and_then(|s| s.unit.as_ref()) {
+        Some(unit) => format!("{} [{}]", name(specs, j), unit),
+        None => name(specs, j),
+    }
+}
+
+
+/// a value, or spread, of parameter `j` as it is shown
+pub fn shown(specs: &[ParameterSpec], j: usize, v: f64) -> f64 {
+    specs.get(j).map_or(v, |s| v * s.scale)
+}
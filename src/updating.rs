@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::output::read_posterior;
+use crate::priors::Prior;
+use crate::warm::{WarmStart, WarmStartConfig};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand::distributions::Distribution;
+    use crate::dist::Normal;
+    use crate::priors::NormalPrior;
+
+    #[test]
+    fn test_bridge_follows_the_previous_posterior() {
+        let mut rng = StdRng::seed_from_u64(394);
+        let previous = Normal::new(0.5, 0.2).unwrap();
+        let posterior: Vec<(Vec<f64>, f64)> = (0..2000)
+            .map(|_| (vec![previous.sample(&mut rng)], -(2000f64.ln())))
+            .collect();
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0], &[1.0]).unwrap());
+        let bridge = posterior_prior(&posterior, &UpdateConfig::default(), prior).unwrap();
+        let mut theta = [0.0];
+        let draws: Vec<f64> = (0..4000)
+            .map(|_| {
+                bridge.sample_into(&mut theta, &mut rng);
+                theta[0]
+            })
+            .collect();
+        let mean = draws.iter().sum::<f64>() / 4000.0;
+        let sd = (draws.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / 3999.0).sqrt();
+        assert!((mean - 0.5).abs() < 0.02);
+        // the kernels widen the posterior a little
+        assert!(sd > 0.19 && sd < 0.23);
+    }
+}
+
+
+/// settings for using the posterior of a previous run, on earlier data, as
+/// the prior of this run
+///
+/// The evidence of the previous run is recomputed from its file and added
+/// to that of this run. If the previous run was itself an update, that is
+/// only its increment, so chains of updates keep their own running total.
+///
+/// Fields:
+/// dead_birth_file: dead-birth file of the previous run
+/// kernels: number of points resampled from the previous posterior
+/// jitter: kernel width relative to the rule-of-thumb bandwidth
+/// prior_fraction: share of the original prior mixed into the new one;
+///     0 is exact Bayesian updating, more guards against new data that
+///     disagree with the old
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UpdateConfig {
+    pub dead_birth_file: PathBuf,
+    pub kernels: usize,
+    pub jitter: f64,
+    pub prior_fraction: f64,
+}
+
+
+impl Default for UpdateConfig {
+    fn default() -> UpdateConfig {
+        UpdateConfig{ dead_birth_file: PathBuf::new(), kernels: 1000, jitter: 1.0, prior_fraction: 0.0 }
+    }
+}
+
+
+/// a kernel density estimate of `posterior`, given as (theta, log
+/// normalized weight) pairs, to serve as the prior of the next run. The
+/// evidence of that run is then the incremental evidence p(new data | old
+/// data), up to the error of the density estimate
+pub fn posterior_prior(
+        posterior: &[(Vec<f64>, f64)],
+        config: &UpdateConfig,
+        prior: Arc<dyn Prior>,
+) -> Result<WarmStart, Box<dyn Error>> {
+    let kde = WarmStartConfig{
+        dead_birth_file: config.dead_birth_file.clone(),
+        kernels: config.kernels,
+        jitter: config.jitter,
+        prior_fraction: config.prior_fraction,
+    };
+    WarmStart::new(posterior, &kde, prior)
+}
+
+
+/// the prior for an updating run, read from the previous run's dead-birth
+/// file, and the log evidence of that run
+pub fn load<R: Rng + ?Sized>(
+        config: &UpdateConfig,
+        prior: Arc<dyn Prior>,
+        rng: &mut R,
+) -> Result<(WarmStart, f64), Box<dyn Error>> {
+    let (posterior, log_z) = read_posterior(&config.dead_birth_file, prior.dim(), rng)?;
+    Ok((posterior_prior(&posterior, config, prior)?, log_z))
+}
@@ -0,0 +1,713 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bounds::Bounds;
+use crate::data::Dataset;
+use crate::evidence::registered_volume_model;
+use crate::functional::registered_functional;
+use crate::models::{self, LogLikelihood, NoiseModel};
+use crate::postprocess::post_processor;
+use crate::priors::{check_prior, registered_constraint, registered_prior, CopulaPrior, Prior};
+use crate::sampler::Method;
+use crate::{build_model, wrap_model, Config};
+
+
+/// draws of the Monte Carlo consistency check of registered priors
+const PRIOR_CHECK_DRAWS: usize = 20000;
+
+
+/// distance of the probed faces from the edge of the unit cube; exactly
+/// at 0 and 1 unbounded priors map to infinity, so there only NaN counts
+const PROBE_EDGE: f64 = 1e-9;
+
+
+/// points along each axis of the unit cube in the monotonicity probe
+const PROBE_GRID: usize = 201;
+
+
+/// up to this many parameters every corner of the cube is probed, above
+/// it a seeded sample of them
+const MAX_CORNER_DIM: usize = 12;
+
+
+/// corners probed in higher dimensions, and at most how many failing
+/// corners are listed
+const PROBED_CORNERS: usize = 4096;
+const LISTED_CORNERS: usize = 5;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::WorkersConfig;
+    use crate::priors::{ClosurePrior, NormalPrior};
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let data = Dataset::from_columns(vec![vec![0.0, 1.0], vec![1.0, 3.0]], None).unwrap();
+        let mut config = Config{
+            particle_num: 1,
+            sample_num: 10,
+            mu: vec![0.0, 0.0, 0.0],
+            sd: vec![1.0, -1.0],
+            truncate_lower: Some(2.0),
+            truncate_upper: Some(1.0),
+            censor_column: Some("flags".to_string()),
+            ..Default::default()
+        };
+        config.sampler.scale = 0.0;
+        let problems = config.problems(Ok(&data));
+        assert!(problems.iter().any(|p| p.contains("particle_num")));
+        assert!(problems.iter().any(|p| p.contains("mu has 3 entries but sd has 2")));
+        assert!(problems.iter().any(|p| p.contains("sd[1]")));
+        assert!(problems.iter().any(|p| p.contains("truncate_lower")));
+        assert!(problems.iter().any(|p| p.contains("\"flags\"")));
+        assert!(problems.iter().any(|p| p.contains("sampler.scale")));
+
+        config = Config{ particle_num: 10, sample_num: 10, mu: vec![0.0; 3], sd: vec![1.0; 3], ..Default::default() };
+        assert!(config.problems(Ok(&data)).is_empty());
+        config.mu.pop();
+        config.sd.pop();
+        // slope, intercept and noise sd
+        assert!(config.problems(Ok(&data))[0].contains("3 parameters"));
+        assert_eq!(config.problems(Err("missing".to_string())), vec!["data_file: missing".to_string()]);
+
+        // workers are not started to validate their model
+        config.workers = Some(WorkersConfig{ command: Some("no/such/worker".into()), ..Default::default() });
+        assert!(config.problems(Ok(&data))[0].contains("3 parameters"));
+    }
+
+    struct Bowl;
+
+    impl LogLikelihood for Bowl {
+        fn log_lik(&self, theta: &[f64]) -> f64 {
+            match theta[1] > 0.0 {
+                true => -theta[0].powi(2) - theta[1].ln().powi(2),
+                false => f64::NAN,
+            }
+        }
+
+        fn dim(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn test_probe_finds_bad_transforms() {
+        let good = NormalPrior::new(&[0.0, 3.0], &[1.0, 0.1]).unwrap();
+        assert!(probe_transform(&good, &Bowl).unwrap().is_empty());
+
+        // the first parameter folds back on itself, the second reaches zero,
+        // where the likelihood is NaN, from the face u_1 = 0 inwards
+        let bad = ClosurePrior::from_inverse_cdf(
+            2,
+            |u, theta| {
+                theta[0] = (6.0 * u[0]).sin();
+                theta[1] = u[1] - 1e-6;
+            },
+            |theta| if theta.iter().all(|t| (-1.0..=1.0).contains(t)) { 0.0 } else { f64::NEG_INFINITY },
+        );
+        let found = probe_transform(&bad, &Bowl).unwrap();
+        assert!(found.iter().any(|s| s.param == Some(0) && s.problem.contains("not monotone")), "{:?}", found);
+        assert!(found.iter().any(|s| s.param == Some(1) && s.problem.contains("log likelihood")), "{:?}", found);
+        assert!(found.iter().any(|s| s.param.is_none() && s.at.starts_with("corner")), "{:?}", found);
+        assert!(found.iter().all(|s| s.param != Some(0) || !s.problem.contains("likelihood")), "{:?}", found);
+
+        let sampled = ClosurePrior::new(2, |theta, _| theta.fill(0.0), |_| 0.0);
+        assert!(probe_transform(&sampled, &Bowl).is_none());
+    }
+}
+
+
+/// every problem found in a config, reported together
+#[derive(Debug, Clone)]
+pub struct ConfigErrors(pub Vec<String>);
+
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the config has {} problem(s):", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl Error for ConfigErrors {}
+
+
+impl Config {
+    /// check the whole config, including that its files exist, that the
+    /// prior matches the model and that a registered prior samples from its
+    /// own density, and report every problem at once
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let data = Dataset::load(&self.data_file);
+        let mut problems = self.problems(data.as_ref().map_err(|e| format!("cannot read {}: {}", self.data_file.display(), e)));
+        if let Some(name) = &self.registered_prior {
+            if let Ok(prior) = registered_prior(name) {
+                let mut rng = StdRng::seed_from_u64(0);
+                problems.extend(
+                    check_prior(prior.as_ref(), PRIOR_CHECK_DRAWS, &mut rng).into_iter()
+                        .map(|p| format!("registered_prior {:?}: {}", name, p)),
+                );
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(ConfigErrors(problems)))
+        }
+    }
+
+    /// `validate` with the data already loaded, or the reason it could not be
+    pub(crate) fn check(&self, data: Result<&Dataset, String>) -> Result<(), Box<dyn Error>> {
+        let problems = self.problems(data);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(ConfigErrors(problems)))
+        }
+    }
+
+    /// `check` for a run on a model built in code rather than from the data
+    pub(crate) fn check_model(&self, model: Result<&dyn LogLikelihood, String>) -> Result<(), Box<dyn Error>> {
+        let problems = self.model_problems(model);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(ConfigErrors(problems)))
+        }
+    }
+
+    fn problems(&self, data: Result<&Dataset, String>) -> Vec<String> {
+        match data {
+            // the model the workers would build, without starting them
+            Ok(data) => match models::construct(self.model_name(), self, data).and_then(|model| wrap_model(self, model)) {
+                Ok(model) => self.model_problems(Ok(model.as_ref())),
+                Err(e) => self.model_problems(Err(format!("model: {}", e))),
+            },
+            Err(e) => self.model_problems(Err(format!("data_file: {}", e))),
+        }
+    }
+
+    /// whether the run writes checkpoints, by iteration or by time
+    fn checkpoints(&self) -> bool {
+        self.checkpoint_every.is_some() || self.checkpoint.interval_secs.is_some()
+    }
+
+    fn model_problems(&self, model: Result<&dyn LogLikelihood, String>) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: String| if !ok { problems.push(problem) };
+
+        // the model and its parameters
+        match (model, &self.copula, &self.registered_prior) {
+            (Ok(model), Some(copula), _) => check(
+                model.dim() == copula.marginals.len(),
+                format!(
+                    "copula.marginals has {} entries, but the model has {} parameters",
+                    copula.marginals.len(), model.dim(),
+                ),
+            ),
+            (Ok(model), None, Some(name)) => match registered_prior(name) {
+                Ok(prior) => check(
+                    model.dim() == prior.dim(),
+                    format!("registered_prior {:?} has {} parameters, but the model has {}", name, prior.dim(), model.dim()),
+                ),
+                Err(e) => check(false, format!("registered_prior: {}", e)),
+            },
+            (Ok(model), None, None) => check(
+                model.dim() == self.mu.len() && model.dim() == self.sd.len(),
+                format!(
+                    "mu has {} and sd {} entries, but the model has {} parameters",
+                    self.mu.len(), self.sd.len(), model.dim(),
+                ),
+            ),
+            (Err(e), _, _) => check(false, e),
+        }
+        for name in &self.constraints {
+            if let Err(e) = registered_constraint(name) {
+                check(false, format!("constraints: {}", e));
+            }
+        }
+        if self.registered_prior.is_some() {
+            check(self.copula.is_none(), "registered_prior and copula are alternative priors; give one".to_string());
+            check(
+                self.mu.is_empty() && self.sd.is_empty(),
+                "registered_prior replaces mu and sd, which must be left out".to_string(),
+            );
+        }
+        if let Some(copula) = &self.copula {
+            check(self.mu.is_empty() && self.sd.is_empty(), "copula replaces mu and sd, which must be left out".to_string());
+            if let Err(e) = CopulaPrior::new(copula.marginals.clone(), &copula.correlation) {
+                check(false, format!("copula: {}", e));
+            }
+        }
+        check(
+            self.mu.len() == self.sd.len(),
+            format!("mu has {} entries but sd has {}", self.mu.len(), self.sd.len()),
+        );
+        for (j, mu) in self.mu.iter().enumerate() {
+            check(mu.is_finite(), format!("mu[{}] = {} is not finite", j, mu));
+        }
+        for (j, sd) in self.sd.iter().enumerate() {
+            check(sd.is_finite() && *sd > 0.0, format!("sd[{}] = {} must be positive and finite", j, sd));
+        }
+
+        // run size
+        check(self.particle_num >= 2, format!("particle_num = {} must be at least 2", self.particle_num));
+        check(self.sample_num > 0, "sample_num must be positive".to_string());
+        check(self.checkpoint_every != Some(0), "checkpoint_every must be positive".to_string());
+        if let Some(tolerance) = self.tolerance {
+            check(tolerance > 0.0 && tolerance < 1.0, format!("tolerance = {} must be in (0, 1)", tolerance));
+        }
+        check(self.checkpoint.keep > 0, "checkpoint.keep must be at least 1".to_string());
+        check(self.checkpoint.interval_secs != Some(0), "checkpoint.interval_secs must be positive".to_string());
+
+        // likelihood options
+        if let Some(sd) = self.noise_sd {
+            check(sd.is_finite() && sd > 0.0, format!("noise_sd = {} must be positive", sd));
+        }
+        if let Some(batch) = self.subsample {
+            check(batch > 0, "subsample must be positive".to_string());
+            check(self.noise_model == NoiseModel::White, "subsample needs the white noise model".to_string());
+        }
+        if let (Some(lower), Some(upper)) = (self.truncate_lower, self.truncate_upper) {
+            check(lower < upper, format!("truncate_lower = {} must be below truncate_upper = {}", lower, upper));
+        }
+        check(
+            self.upper_column.is_none() || self.censor_column.is_some(),
+            "upper_column needs a censor_column".to_string(),
+        );
+        if let Some(path) = &self.noise_cov_file {
+            check(path.is_file(), format!("noise_cov_file: {} does not exist", path.display()));
+            check(
+                self.noise_model == NoiseModel::White && self.subsample.is_none() && self.censor_column.is_none()
+                    && self.truncate_lower.is_none() && self.truncate_upper.is_none(),
+                "noise_cov_file cannot be combined with noise_model, subsample, censoring or truncation".to_string(),
+            );
+        }
+        if let Some(nuisance) = &self.nuisance {
+            let k = nuisance.columns.len();
+            check(
+                k > 0 && nuisance.mu.len() == k && nuisance.sd.len() == k,
+                format!(
+                    "nuisance has {} columns, {} prior means and {} prior sds; they must match and not be empty",
+                    k, nuisance.mu.len(), nuisance.sd.len(),
+                ),
+            );
+            for (j, sd) in nuisance.sd.iter().enumerate() {
+                check(sd.is_finite() && *sd > 0.0, format!("nuisance.sd[{}] = {} must be positive and finite", j, sd));
+            }
+            check(
+                self.noise_model == NoiseModel::White && self.subsample.is_none() && self.noise_cov_file.is_none()
+                    && self.censor_column.is_none() && self.truncate_lower.is_none() && self.truncate_upper.is_none(),
+                "nuisance needs white noise without subsample, noise_cov_file, censoring or truncation".to_string(),
+            );
+        }
+        if let Some(tempering) = &self.tempering {
+            check(tempering.chains >= 2, format!("tempering.chains = {} must be at least 2", tempering.chains));
+            check(
+                tempering.batches >= 2 && tempering.sweeps >= tempering.batches,
+                format!("tempering.sweeps = {} must cover tempering.batches = {} >= 2", tempering.sweeps, tempering.batches),
+            );
+            check(
+                tempering.ladder_power.is_finite() && tempering.ladder_power > 0.0,
+                format!("tempering.ladder_power = {} must be positive", tempering.ladder_power),
+            );
+            check(
+                tempering.target_acceptance > 0.0 && tempering.target_acceptance < 1.0,
+                format!("tempering.target_acceptance = {} must be in (0, 1)", tempering.target_acceptance),
+            );
+            check(
+                self.dynamic.is_none() && self.dead_birth_file.is_none() && !self.checkpoints(),
+                "tempering replaces nested sampling and cannot be combined with dynamic, dead_birth_file or checkpoints"
+                    .to_string(),
+            );
+        }
+        if let Some(smc) = &self.smc {
+            check(smc.particles >= 2, format!("smc.particles = {} must be at least 2", smc.particles));
+            check(
+                smc.target_ess > 0.0 && smc.target_ess < 1.0,
+                format!("smc.target_ess = {} must be in (0, 1)", smc.target_ess),
+            );
+            check(smc.max_stages >= 1, "smc.max_stages must be at least 1".to_string());
+            check(
+                self.tempering.is_none() && self.dynamic.is_none() && self.dead_birth_file.is_none()
+                    && !self.checkpoints(),
+                "smc replaces nested sampling and cannot be combined with tempering, dynamic, dead_birth_file or \
+                 checkpoints".to_string(),
+            );
+        }
+        if let Some(convergence) = &self.convergence {
+            check(convergence.every > 0, "convergence.every must be positive".to_string());
+        }
+        if let Some(export) = &self.export {
+            check(export.size != Some(0), "export.size must be positive".to_string());
+        }
+        if !self.parameters.is_empty() {
+            check(
+                self.parameters.len() == self.n_params(),
+                format!("parameters has {} entries but the prior has {} parameters", self.parameters.len(), self.n_params()),
+            );
+        }
+        for (j, spec) in self.parameters.iter().enumerate() {
+            check(
+                spec.scale > 0.0 && spec.scale.is_finite(),
+                format!("parameters[{}].scale = {} must be positive and finite", j, spec.scale),
+            );
+            check(
+                !spec.name.as_ref().is_some_and(|name| name.is_empty() || name.contains(char::is_whitespace)),
+                format!("parameters[{}].name must be non-empty and without spaces, as tables use it for a column", j),
+            );
+        }
+        if let Some(getdist) = &self.getdist {
+            check(!getdist.limits.is_empty(), "getdist.limits must list at least one probability".to_string());
+            for limit in &getdist.limits {
+                check(*limit > 0.0 && *limit < 1.0, format!("getdist.limits entry {} must be in (0, 1)", limit));
+            }
+            check(
+                self.tempering.is_none() && self.smc.is_none(),
+                "getdist summaries need the dead points of nested sampling, which tempering and smc do not keep".to_string(),
+            );
+        }
+        check(
+            self.curve_file.is_none() || (self.tempering.is_none() && self.smc.is_none()),
+            "curve_file needs the dead points of nested sampling, which tempering and smc do not keep".to_string(),
+        );
+        if let Some(ppc) = &self.ppc {
+            check(ppc.draws >= 1, "ppc.draws must be at least 1".to_string());
+            check(ppc.alpha > 0.0 && ppc.alpha < 1.0, format!("ppc.alpha = {} must be in (0, 1)", ppc.alpha));
+        }
+        for name in &self.post_process {
+            if let Err(e) = post_processor(name) {
+                check(false, format!("post_process: {}", e));
+            }
+        }
+        if let Some(workers) = &self.workers {
+            check(workers.processes >= 1, "workers.processes must be at least 1".to_string());
+        }
+        if let Some(noisy) = &self.noisy {
+            check(noisy.repeats >= 1, "noisy.repeats must be at least 1".to_string());
+            check(
+                noisy.variance.is_none_or(|v| v >= 0.0 && v.is_finite()),
+                format!("noisy.variance = {:?} must be finite and not negative", noisy.variance),
+            );
+            check(
+                self.cache_size.is_none(),
+                "a cache would hand back one estimate of a noisy likelihood again and again; drop cache_size".to_string(),
+            );
+        }
+        if let Some(name) = &self.volume_model {
+            if let Err(e) = registered_volume_model(name) {
+                check(false, format!("volume_model: {}", e));
+            }
+        }
+        if let Some(functional) = &self.functional {
+            if let Err(e) = registered_functional(&functional.name) {
+                check(false, format!("functional.name: {}", e));
+            }
+            check(
+                functional.tolerance > 0.0,
+                format!("functional.tolerance = {} must be positive", functional.tolerance),
+            );
+            check(functional.every > 0, "functional.every must be positive".to_string());
+            check(
+                self.tempering.is_none() && self.smc.is_none() && self.dynamic.is_none(),
+                "stopping on a functional needs static nested sampling, not tempering, smc or dynamic".to_string(),
+            );
+        }
+        if let Some(export) = &self.export_bounds {
+            check(
+                export.mass > 0.0 && export.mass <= 1.0,
+                format!("export_bounds.mass = {} must be in (0, 1]", export.mass),
+            );
+            check(export.expand >= 1.0, format!("export_bounds.expand = {} must be at least 1", export.expand));
+        }
+        if let Some(restrict) = &self.restrict {
+            match Bounds::load(&restrict.file) {
+                Ok(bounds) => check(
+                    bounds.lower.len() == self.n_params(),
+                    format!("restrict: {} has {} parameters, not {}", restrict.file.display(), bounds.lower.len(), self.n_params()),
+                ),
+                Err(e) => check(false, format!("restrict: {}", e)),
+            }
+            check(self.update.is_none(), "restrict cannot be combined with update".to_string());
+        }
+        if let Some(inject) = &self.inject {
+            check(!inject.points.is_empty(), "inject.points must hold at least one point".to_string());
+            check(
+                inject.points.iter().all(|theta| theta.len() == self.n_params()),
+                format!("every point of inject.points must have the {} parameters of the prior", self.n_params()),
+            );
+            check(inject.fraction > 0.0 && inject.fraction < 1.0, format!("inject.fraction = {} must be in (0, 1)", inject.fraction));
+            check(
+                inject.width > 0.0 && inject.width.is_finite(),
+                format!("inject.width = {} must be positive and finite", inject.width),
+            );
+            check(self.warm_start.is_none(), "inject and warm_start both set what the run draws from; give one".to_string());
+        }
+        if let Some(multivariate) = &self.multivariate {
+            check(!multivariate.responses.is_empty(), "multivariate.responses must name at least one column".to_string());
+            check(
+                self.noise_sd.is_none() && self.censor_column.is_none() && self.truncate_lower.is_none()
+                    && self.truncate_upper.is_none() && self.noise_model == NoiseModel::White
+                    && self.subsample.is_none() && self.dpmm.is_none() && self.noise_cov_file.is_none(),
+                "multivariate samples its noise covariance and cannot be combined with noise_sd, \
+                    censoring, truncation, noise_model, subsample, dpmm or noise_cov_file".to_string(),
+            );
+        }
+        if let Some(model) = &self.model {
+            check(
+                (self.dpmm.is_none() || model == "dpmm") && (self.multivariate.is_none() || model == "multivariate"),
+                format!("model = {:?} conflicts with the dpmm or multivariate section", model),
+            );
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &self.script {
+            check(script.file.is_file(), format!("script.file: {} does not exist", script.file.display()));
+        }
+        for dir in &self.plugin_dirs {
+            check(dir.is_dir(), format!("plugin_dirs: {} is not a directory", dir.display()));
+        }
+        if let Some(dpmm) = &self.dpmm {
+            check(dpmm.filter_particles >= 2, "dpmm.filter_particles must be at least 2".to_string());
+            check(dpmm.draws > 0 && dpmm.sweeps > 0, "dpmm.draws and dpmm.sweeps must be positive".to_string());
+        }
+
+        // sampler
+        let sampler = &self.sampler;
+        check(sampler.scale > 0.0, format!("sampler.scale = {} must be positive", sampler.scale));
+        if sampler.method != Method::Rejection {
+            check(sampler.steps > 0, "sampler.steps must be positive".to_string());
+            check(sampler.max_steps >= sampler.steps, "sampler.max_steps must be at least sampler.steps".to_string());
+        }
+        check(
+            sampler.min_scale <= sampler.scale && sampler.scale <= sampler.max_scale,
+            format!(
+                "sampler.scale = {} must be within sampler.min_scale = {} and sampler.max_scale = {}",
+                sampler.scale, sampler.min_scale, sampler.max_scale,
+            ),
+        );
+        if let Some(scales) = &sampler.scales {
+            check(
+                scales.len() == self.n_params(),
+                format!("sampler.scales has {} entries but the prior has {} parameters", scales.len(), self.n_params()),
+            );
+            for (j, s) in scales.iter().enumerate() {
+                check(s.is_finite() && *s > 0.0, format!("sampler.scales[{}] = {} must be positive and finite", j, s));
+            }
+        }
+        if let Some(blocks) = &sampler.blocks {
+            let mut members: Vec<usize> = blocks.iter().flatten().copied().collect();
+            members.sort_unstable();
+            check(
+                members == (0..self.n_params()).collect::<Vec<usize>>() && blocks.iter().all(|b| !b.is_empty()),
+                format!("sampler.blocks must put each of the {} parameters in exactly one non-empty block", self.n_params()),
+            );
+        }
+        if let Some(fast) = &sampler.fast {
+            let mut sorted = fast.clone();
+            sorted.sort_unstable();
+            sorted.dedup();
+            check(
+                sorted.len() == fast.len() && fast.iter().all(|j| *j < self.n_params()),
+                format!("sampler.fast must list distinct parameters below {}", self.n_params()),
+            );
+            check(
+                sampler.blocks.iter().flatten().all(|b| b.iter().all(|j| fast.contains(j)) || !b.iter().any(|j| fast.contains(j))),
+                "a block of sampler.blocks mixes fast and slow parameters, so it would not be oversampled".to_string(),
+            );
+            check(sampler.oversample >= 1, "sampler.oversample must be at least 1".to_string());
+        }
+        check(sampler.stall_chains > 0, "sampler.stall_chains must be positive".to_string());
+        check(
+            sampler.degeneracy_ratio > 0.0 && sampler.degeneracy_ratio < 1.0,
+            format!("sampler.degeneracy_ratio = {} must be in (0, 1)", sampler.degeneracy_ratio),
+        );
+        check(
+            !sampler.reduce_degenerate || sampler.degeneracy_every > 0,
+            "sampler.reduce_degenerate needs sampler.degeneracy_every to be positive".to_string(),
+        );
+        if sampler.delayed_rejection {
+            check(
+                matches!(sampler.method, Method::RandomWalk | Method::Auto),
+                "sampler.delayed_rejection needs the random walk (method \"random_walk\" or \"auto\")".to_string(),
+            );
+            check(
+                sampler.second_stage_scale > 0.0 && sampler.second_stage_scale <= 1.0,
+                format!("sampler.second_stage_scale = {} must be in (0, 1]", sampler.second_stage_scale),
+            );
+        }
+        if let Some(tuning) = &sampler.tuning {
+            check(tuning.chains > 0, "sampler.tuning.chains must be positive".to_string());
+            check(
+                tuning.target_acceptance > 0.0 && tuning.target_acceptance < 1.0,
+                format!("sampler.tuning.target_acceptance = {} must be in (0, 1)", tuning.target_acceptance),
+            );
+            check(
+                (0.0..1.0).contains(&tuning.quantile),
+                format!("sampler.tuning.quantile = {} must be in [0, 1)", tuning.quantile),
+            );
+        }
+        check(
+            (0.0..1.0).contains(&sampler.auto_min_efficiency),
+            format!("sampler.auto_min_efficiency = {} must be in [0, 1)", sampler.auto_min_efficiency),
+        );
+
+        // files from earlier runs
+        let mut exists = |what: &str, path: &Path| check(path.is_file(), format!("{}: {} does not exist", what, path.display()));
+        if let Some(warm) = &self.warm_start {
+            exists("warm_start.dead_birth_file", &warm.dead_birth_file);
+        }
+        if let Some(update) = &self.update {
+            exists("update.dead_birth_file", &update.dead_birth_file);
+        }
+        problems
+    }
+}
+
+
+/// a suspicious point of a prior's unit-cube transform
+///
+/// Fields:
+/// param: the parameter whose axis was probed; None for corners
+/// at: where in the cube, e.g. `u = 1e-9` or `corner 0110` (1 for the
+///     upper face)
+/// problem: what looked wrong there
+#[derive(Debug, Clone)]
+pub struct Suspicion {
+    pub param: Option<usize>,
+    pub at: String,
+    pub problem: String,
+}
+
+
+/// what is wrong at one point of the cube, if anything: NaN coordinates
+/// anywhere, and inside it infinite coordinates, points outside the
+/// prior's support and, when `model` is given, non-finite likelihoods
+fn probe_point(prior: &dyn Prior, model: Option<&dyn LogLikelihood>, u: &[f64], inside: bool) -> Option<String> {
+    let theta = prior.unit_transform(u)?;
+    if theta.len() != prior.dim() {
+        return Some(format!("the transform returns {} values for {} parameters", theta.len(), prior.dim()))
+    }
+    if theta.iter().any(|t| t.is_nan()) {
+        return Some(format!("the transform gives NaN: {:?}", theta))
+    }
+    if !inside {
+        return None
+    }
+    if theta.iter().any(|t| t.is_infinite()) {
+        return Some(format!("the transform is infinite inside the cube: {:?}", theta))
+    }
+    let log_density = prior.log_density(&theta);
+    if !log_density.is_finite() {
+        return Some(format!("{:?} is outside the prior's support (log density {})", theta, log_density))
+    }
+    let log_l = model.map(|m| m.log_lik(&theta))?;
+    match log_l.is_finite() {
+        true => None,
+        false => Some(format!("the log likelihood at {:?} is {}", theta, log_l)),
+    }
+}
+
+
+/// probe a prior's unit-cube transform at the faces and corners of the
+/// cube and along each axis, or None if the prior is not defined by one
+///
+/// Each parameter is moved along its own axis with the others at 0.5: at
+/// 0 and 1 the transform must not give NaN, at PROBE_EDGE from either face
+/// it must give a point of the prior's support with a finite likelihood,
+/// and across the axis it must be monotone, without folding back. Every
+/// corner (or, in many dimensions, a sample of them) is checked like the
+/// faces. Most failed runs with hand-written transforms trace back to one
+/// of these.
+pub fn probe_transform(prior: &dyn Prior, model: &dyn LogLikelihood) -> Option<Vec<Suspicion>> {
+    let dim = prior.dim();
+    let centre = vec![0.5; dim];
+    prior.unit_transform(&centre)?;
+    let mut found = Vec::new();
+    for j in 0..dim {
+        let mut u = centre.clone();
+        for (edge, inside) in [(0.0, false), (PROBE_EDGE, true), (1.0 - PROBE_EDGE, true), (1.0, false)] {
+            u[j] = edge;
+            if let Some(problem) = probe_point(prior, Some(model), &u, inside) {
+                found.push(Suspicion{ param: Some(j), at: format!("u = {:e}", edge), problem });
+            }
+        }
+
+        let mut path = Vec::with_capacity(PROBE_GRID);
+        for k in 0..PROBE_GRID {
+            u[j] = PROBE_EDGE + (1.0 - 2.0 * PROBE_EDGE) * k as f64 / (PROBE_GRID - 1) as f64;
+            if let Some(problem) = probe_point(prior, None, &u, true) {
+                if !found.iter().any(|s| s.param == Some(j) && s.problem == problem) {
+                    found.push(Suspicion{ param: Some(j), at: format!("u = {:e}", u[j]), problem });
+                }
+                break
+            }
+            path.push((u[j], prior.unit_transform(&u).map_or(f64::NAN, |t| t[j])));
+        }
+        if path.len() < PROBE_GRID {
+            continue
+        }
+        // the first step that moves sets the direction
+        let direction = match path.windows(2).map(|w| w[1].1 - w[0].1).find(|d| *d != 0.0) {
+            Some(d) => d.signum(),
+            None => {
+                found.push(Suspicion{ param: Some(j), at: "the whole axis".to_string(), problem: "the transform is constant".to_string() });
+                continue
+            },
+        };
+        if let Some(w) = path.windows(2).find(|w| (w[1].1 - w[0].1) * direction < 0.0) {
+            found.push(Suspicion{
+                param: Some(j),
+                at: format!("u = {:.3}..{:.3}", w[0].0, w[1].0),
+                problem: format!("the transform is not monotone: it goes from {} to {}", w[0].1, w[1].1),
+            });
+        }
+    }
+
+    let corners: Vec<Vec<bool>> = match dim <= MAX_CORNER_DIM {
+        true => (0..1usize << dim).map(|c| (0..dim).map(|j| c >> j & 1 == 1).collect()).collect(),
+        false => {
+            let mut rng = StdRng::seed_from_u64(0);
+            (0..PROBED_CORNERS).map(|_| (0..dim).map(|_| rng.gen()).collect()).collect()
+        },
+    };
+    let mut failed = 0;
+    for corner in corners {
+        let u: Vec<f64> = corner.iter().map(|&up| if up { 1.0 - PROBE_EDGE } else { PROBE_EDGE }).collect();
+        if let Some(problem) = probe_point(prior, Some(model), &u, true) {
+            failed += 1;
+            if failed <= LISTED_CORNERS {
+                let at: String = corner.iter().map(|&up| if up { '1' } else { '0' }).collect();
+                found.push(Suspicion{ param: None, at: format!("corner {}", at), problem });
+            }
+        }
+    }
+    if failed > LISTED_CORNERS {
+        found.push(Suspicion{
+            param: None,
+            at: "other corners".to_string(),
+            problem: format!("{} more corners fail", failed - LISTED_CORNERS),
+        });
+    }
+    Some(found)
+}
+
+
+impl Config {
+    /// `probe_transform` for the prior and model of the config, or None if
+    /// the prior is not defined by a unit-cube transform (e.g. constrained
+    /// or sampled by a closure)
+    pub fn probe_transform(&self) -> Result<Option<Vec<Suspicion>>, Box<dyn Error>> {
+        let data = Dataset::load(&self.data_file)?;
+        let model = build_model(self, &data)?;
+        Ok(probe_transform(self.prior()?.as_ref(), model.as_ref()))
+    }
+}
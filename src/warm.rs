@@ -0,0 +1,373 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rand::{Rng, RngCore};
+use rand::distributions::Distribution;
+use serde::{Deserialize, Serialize};
+
+use crate::dist::Normal;
+use crate::evidence::log_add_exp;
+use crate::models::{LogLikelihood, Screen};
+use crate::output::read_posterior;
+use crate::priors::Prior;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::dynamic;
+    use crate::observer::Collect;
+    use crate::priors::{ClosurePrior, NormalPrior};
+    use crate::sampler::{Method, Sampler, SamplerConfig};
+    use crate::test_support::Peak;
+
+    #[test]
+    fn test_warm_start_keeps_the_evidence() {
+        let mut rng = StdRng::seed_from_u64(393);
+        let s = 0.1;
+        let truth = (s / (1.0f64 + s * s).sqrt()).ln();
+        // a previous posterior, slightly off the current one
+        let previous = Normal::new(0.02, s).unwrap();
+        let posterior: Vec<(Vec<f64>, f64)> = (0..1000)
+            .map(|_| (vec![previous.sample(&mut rng)], -(1000f64.ln())))
+            .collect();
+
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0], &[1.0]).unwrap());
+        let config = WarmStartConfig{ kernels: 100, ..Default::default() };
+        let reference = Arc::new(WarmStart::new(&posterior, &config, Arc::clone(&prior)).unwrap());
+        let step = 1e-3;
+        let mass: f64 = (-8000..8000).map(|i| reference.log_density(&[i as f64 * step]).exp() * step).sum();
+        assert!((mass - 1.0).abs() < 1e-6);
+
+        let model = Repartitioned::new(Peak{ s, dim: 1 }, prior, reference.clone());
+        let sampler_config = SamplerConfig{ method: Method::RandomWalk, ..Default::default() };
+        let mut sampler = Sampler::new(&sampler_config, reference);
+        let mut points = dynamic::run_batch(
+            &model, &mut sampler, 100, (f64::NEG_INFINITY, f64::INFINITY), 10_000, &mut Collect::default(), &mut rng,
+        ).unwrap();
+        let summary = dynamic::summarize(&mut points, 50, &mut rng);
+        assert!((summary.log_z - truth).abs() < 3.0 * summary.log_z_err.max(0.05));
+        // the reference is close to the posterior, so little is left to learn
+        assert!(summary.info < 1.0);
+    }
+
+    #[test]
+    fn test_injected_points_keep_the_evidence() {
+        let mut rng = StdRng::seed_from_u64(466);
+        let s = 0.1;
+        // uniform prior on [-5, 5], so Z = s sqrt(2 pi) / 10
+        let truth = (s * (2.0 * std::f64::consts::PI).sqrt() / 10.0).ln();
+        let prior: Arc<dyn Prior> = Arc::new(ClosurePrior::new(
+            1,
+            |theta: &mut [f64], rng: &mut dyn RngCore| theta[0] = rng.gen_range(-5.0..5.0),
+            |theta: &[f64]| if theta[0].abs() <= 5.0 { -(10f64.ln()) } else { f64::NEG_INFINITY },
+        ));
+        // a known good fit, one nearby, and one the prior rules out
+        let config = InjectConfig{ points: vec![vec![0.0], vec![0.05], vec![7.0]], ..Default::default() };
+        let (reference, rejected) = WarmStart::around(&config, Arc::clone(&prior)).unwrap();
+        assert_eq!(rejected, vec![2]);
+        let reference = Arc::new(reference);
+        let model = Repartitioned::new(Peak{ s, dim: 1 }, prior, reference.clone());
+        let sampler_config = SamplerConfig{ method: Method::RandomWalk, ..Default::default() };
+        let mut sampler = Sampler::new(&sampler_config, reference);
+        let mut points = dynamic::run_batch(
+            &model, &mut sampler, 100, (f64::NEG_INFINITY, f64::INFINITY), 10_000, &mut Collect::default(), &mut rng,
+        ).unwrap();
+        let summary = dynamic::summarize(&mut points, 50, &mut rng);
+        assert!((summary.log_z - truth).abs() < 3.0 * summary.log_z_err.max(0.05), "{} {}", summary.log_z, truth);
+
+        let outside = InjectConfig{ points: vec![vec![7.0]], ..Default::default() };
+        let prior: Arc<dyn Prior> = Arc::new(NormalPrior::new(&[0.0], &[1.0]).unwrap());
+        assert!(WarmStart::around(&outside, Arc::new(NormalPrior::new(&[0.0, 0.0], &[1.0, 1.0]).unwrap())).is_err());
+        assert!(WarmStart::around(&outside, prior).is_ok());
+    }
+}
+
+
+/// settings of a warm start from a previous posterior
+///
+/// Fields:
+/// dead_birth_file: dead-birth file of the previous run
+/// kernels: number of points resampled from the previous posterior
+/// jitter: width of the Gaussian kernel around each resampled point,
+///     relative to the rule-of-thumb bandwidth of a kernel density estimate
+/// prior_fraction: share of the prior mixed into the reference, so that it
+///     covers everything the prior does
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WarmStartConfig {
+    pub dead_birth_file: PathBuf,
+    pub kernels: usize,
+    pub jitter: f64,
+    pub prior_fraction: f64,
+}
+
+
+impl Default for WarmStartConfig {
+    fn default() -> WarmStartConfig {
+        WarmStartConfig{ dead_birth_file: PathBuf::new(), kernels: 500, jitter: 1.0, prior_fraction: 0.1 }
+    }
+}
+
+
+/// settings of a run seeded with known parameter values, e.g. good fits
+/// from earlier work
+///
+/// Fields:
+/// points: the parameter vectors
+/// fraction: expected share of the live points drawn around them, at the
+///     start and throughout the run; the rest are drawn from the prior
+/// width: standard deviation of the Gaussian kernel around each point,
+///     relative to the prior's scale of each parameter
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct InjectConfig {
+    pub points: Vec<Vec<f64>>,
+    pub fraction: f64,
+    pub width: f64,
+}
+
+
+impl Default for InjectConfig {
+    fn default() -> InjectConfig {
+        InjectConfig{ points: Vec::new(), fraction: 0.25, width: 0.05 }
+    }
+}
+
+
+/// reference distribution built from a previous posterior: a mixture of
+/// the prior and Gaussian kernels around points resampled from the
+/// posterior, i.e. the posterior resampled and jittered. Built from
+/// injected points instead, the kernels sit around those
+///
+/// The run draws its points from this reference rather than from the prior,
+/// and the likelihood is multiplied by prior / reference (see
+/// `Repartitioned`), so the evidence is still relative to the declared
+/// prior. The prior component keeps the ratio bounded in the tails.
+///
+/// Fields:
+/// prior: the declared prior
+/// centers: the resampled posterior points
+/// width: kernel standard deviation of each parameter
+/// prior_fraction: weight of the prior in the mixture
+#[derive(Debug)]
+pub struct WarmStart {
+    prior: Arc<dyn Prior>,
+    centers: Vec<Vec<f64>>,
+    width: Vec<f64>,
+    prior_fraction: f64,
+}
+
+
+impl WarmStart {
+    /// build the reference from `posterior`, as (theta, log normalized
+    /// weight) pairs like `RunResult::posterior`
+    pub fn new(
+            posterior: &[(Vec<f64>, f64)],
+            config: &WarmStartConfig,
+            prior: Arc<dyn Prior>,
+    ) -> Result<WarmStart, Box<dyn Error>> {
+        let d = prior.dim();
+        if posterior.is_empty() || config.kernels == 0 {
+            return Err("a warm start needs a non-empty previous posterior".into())
+        }
+        if let Some((theta, _)) = posterior.iter().find(|(t, _)| t.len() != d) {
+            return Err(format!("previous posterior has {} parameters, the prior {}", theta.len(), d).into())
+        }
+        if !(0.0..1.0).contains(&config.prior_fraction) || config.jitter.is_nan() || config.jitter <= 0.0 {
+            return Err("warm start needs prior_fraction in [0, 1) and a positive jitter".into())
+        }
+        let w: Vec<f64> = posterior.iter().map(|(_, lw)| lw.exp()).collect();
+        let total: f64 = w.iter().sum();
+        let mean: Vec<f64> = (0..d)
+            .map(|j| posterior.iter().zip(&w).map(|((t, _), w)| w * t[j]).sum::<f64>() / total)
+            .collect();
+        let bandwidth = config.jitter * (config.kernels as f64).powf(-1.0 / (d as f64 + 4.0));
+        let width: Vec<f64> = (0..d)
+            .map(|j| {
+                let var = posterior.iter().zip(&w).map(|((t, _), w)| w * (t[j] - mean[j]).powi(2)).sum::<f64>() / total;
+                bandwidth * var.sqrt().max(1e-12)
+            })
+            .collect();
+
+        // systematic resampling: evenly spaced pointers through the
+        // cumulative posterior mass
+        let mut centers = Vec::with_capacity(config.kernels);
+        let mut cum = 0.0;
+        for ((theta, _), w) in posterior.iter().zip(&w) {
+            cum += w / total;
+            while centers.len() < config.kernels && (centers.len() as f64 + 0.5) / config.kernels as f64 <= cum {
+                centers.push(theta.clone());
+            }
+        }
+        while centers.len() < config.kernels {
+            centers.push(posterior[posterior.len() - 1].0.clone());
+        }
+        Ok(WarmStart{ prior, centers, width, prior_fraction: config.prior_fraction })
+    }
+
+    /// build the reference from the injected points of `config`: the prior
+    /// mixed with kernels around every point the prior supports. Also
+    /// returns the indices of the points left out because the prior has
+    /// no density there, which no draw of the run could ever reach
+    pub fn around(config: &InjectConfig, prior: Arc<dyn Prior>) -> Result<(WarmStart, Vec<usize>), Box<dyn Error>> {
+        let d = prior.dim();
+        if let Some(theta) = config.points.iter().find(|t| t.len() != d) {
+            return Err(format!("an injected point has {} parameters, the prior {}", theta.len(), d).into())
+        }
+        if !(config.fraction > 0.0 && config.fraction < 1.0 && config.width > 0.0 && config.width.is_finite()) {
+            return Err("injected points need fraction in (0, 1) and a positive width".into())
+        }
+        let (centers, rejected): (Vec<_>, Vec<_>) = config.points.iter()
+            .enumerate()
+            .partition(|(_, theta)| prior.log_density(theta) > f64::NEG_INFINITY);
+        if centers.is_empty() {
+            return Err("the prior has no density at any injected point".into())
+        }
+        let width = prior.scale().iter().map(|s| config.width * s).collect();
+        let centers = centers.into_iter().map(|(_, theta)| theta.clone()).collect();
+        let rejected = rejected.into_iter().map(|(i, _)| i).collect();
+        Ok((WarmStart{ prior, centers, width, prior_fraction: 1.0 - config.fraction }, rejected))
+    }
+
+    /// build the reference from the dead-birth file of a previous run
+    pub fn load<R: Rng + ?Sized>(
+            config: &WarmStartConfig,
+            prior: Arc<dyn Prior>,
+            rng: &mut R,
+    ) -> Result<WarmStart, Box<dyn Error>> {
+        let (posterior, _) = read_posterior(&config.dead_birth_file, prior.dim(), rng)?;
+        WarmStart::new(&posterior, config, prior)
+    }
+}
+
+
+impl Prior for WarmStart {
+    fn dim(&self) -> usize {
+        self.width.len()
+    }
+
+    fn sample_into(&self, theta: &mut [f64], rng: &mut dyn RngCore) {
+        if rng.gen::<f64>() < self.prior_fraction {
+            self.prior.sample_into(theta, rng);
+            return
+        }
+        let center = &self.centers[rng.gen_range(0..self.centers.len())];
+        let unit = Normal::new(0.0, 1.0).unwrap();
+        for ((t, c), w) in theta.iter_mut().zip(center).zip(&self.width) {
+            *t = c + w * unit.sample(rng);
+        }
+    }
+
+    fn log_density(&self, theta: &[f64]) -> f64 {
+        let log_norm: f64 = self.width.iter()
+            .map(|w| -0.5 * (2.0 * std::f64::consts::PI).ln() - w.ln())
+            .sum::<f64>() - (self.centers.len() as f64).ln();
+        let kernels = self.centers.iter()
+            .map(|c| {
+                let q: f64 = theta.iter().zip(c).zip(&self.width).map(|((t, c), w)| ((t - c) / w).powi(2)).sum();
+                log_norm - 0.5 * q
+            })
+            .fold(f64::NEG_INFINITY, log_add_exp);
+        let mut log_q = (1.0 - self.prior_fraction).ln() + kernels;
+        if self.prior_fraction > 0.0 {
+            log_q = log_add_exp(log_q, self.prior_fraction.ln() + self.prior.log_density(theta));
+        }
+        log_q
+    }
+
+    fn scale(&self) -> Vec<f64> {
+        self.width.clone()
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        self.prior.stats()
+    }
+
+    /// no sum of the kernels exceeds the peak of one of them
+    fn max_log_density(&self) -> Option<f64> {
+        let kernel: f64 = self.width.iter().map(|w| -0.5 * (2.0 * std::f64::consts::PI).ln() - w.ln()).sum();
+        let log_q = (1.0 - self.prior_fraction).ln() + kernel;
+        match self.prior_fraction > 0.0 {
+            true => self.prior.max_log_density().map(|m| log_add_exp(log_q, self.prior_fraction.ln() + m)),
+            false => Some(log_q),
+        }
+    }
+}
+
+
+/// likelihood times prior / reference, for runs that draw from the
+/// reference instead of the prior: the integral of this likelihood over
+/// the reference is the evidence of the original likelihood over the prior
+///
+/// Fields:
+/// model: the original likelihood
+/// prior: the declared prior
+/// reference: the distribution the run draws from
+pub struct Repartitioned<M> {
+    model: M,
+    prior: Arc<dyn Prior>,
+    reference: Arc<dyn Prior>,
+}
+
+
+impl<M: LogLikelihood> Repartitioned<M> {
+    pub fn new(model: M, prior: Arc<dyn Prior>, reference: Arc<dyn Prior>) -> Repartitioned<M> {
+        Repartitioned{ model, prior, reference }
+    }
+
+    fn log_ratio(&self, theta: &[f64]) -> f64 {
+        self.prior.log_density(theta) - self.reference.log_density(theta)
+    }
+}
+
+
+impl<M: LogLikelihood> LogLikelihood for Repartitioned<M> {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        self.model.log_lik(theta) + self.log_ratio(theta)
+    }
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    fn is_approximate(&self) -> bool {
+        self.model.is_approximate()
+    }
+
+    fn screen(&self, theta: &[f64], threshold: f64) -> Screen {
+        let ratio = self.log_ratio(theta);
+        match self.model.screen(theta, threshold - ratio) {
+            Screen::Evaluated(ll) => Screen::Evaluated(ll + ratio),
+            Screen::Pass => Screen::Evaluated(self.model.log_lik(theta) + ratio),
+            Screen::Reject => Screen::Reject,
+        }
+    }
+
+    fn stats(&self) -> Vec<(String, f64)> {
+        self.model.stats()
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.model.n_outputs()
+    }
+
+    fn outputs(&self, theta: &[f64]) -> Vec<f64> {
+        self.model.outputs(theta)
+    }
+
+    fn log_lik_variance(&self) -> Option<f64> {
+        self.model.log_lik_variance()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.model.failure()
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.model.reseed(seed)
+    }
+}
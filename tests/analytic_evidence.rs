@@ -0,0 +1,101 @@
+// End-to-end checks of log Z against conjugate problems whose evidence is
+// known in closed form: a Gaussian likelihood in theta under independent
+// Gaussian priors, where Z = prod_j N(m_j; mu_j, s_j^2 + sd_j^2).
+#![cfg(feature = "std")]
+
+use std::f64::consts::PI;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use nested_sampling::models::LogLikelihood;
+use nested_sampling::observer::Collect;
+use nested_sampling::sampler::{Method, SamplerConfig};
+use nested_sampling::{run_with_model, Config};
+
+
+/// log N(theta; m, diag(s^2))
+struct GaussianLikelihood {
+    m: Vec<f64>,
+    s: Vec<f64>,
+}
+
+
+impl LogLikelihood for GaussianLikelihood {
+    fn log_lik(&self, theta: &[f64]) -> f64 {
+        theta.iter().zip(&self.m).zip(&self.s)
+            .map(|((t, m), s)| -0.5 * ((t - m) / s).powi(2) - s.ln() - 0.5 * (2.0 * PI).ln())
+            .sum()
+    }
+
+    fn dim(&self) -> usize {
+        self.m.len()
+    }
+}
+
+
+/// the likelihood and prior of a problem in `dim` dimensions, with the
+/// likelihood off the prior mean so the evidence is not just a width ratio
+fn problem(dim: usize) -> (GaussianLikelihood, Vec<f64>, Vec<f64>) {
+    let m: Vec<f64> = (0..dim).map(|j| 0.5 * j as f64 - 0.3).collect();
+    let s: Vec<f64> = (0..dim).map(|j| 0.4 + 0.1 * j as f64).collect();
+    let mu = vec![0.0; dim];
+    let sd: Vec<f64> = (0..dim).map(|j| 2.0 + 0.5 * j as f64).collect();
+    (GaussianLikelihood{ m, s }, mu, sd)
+}
+
+
+fn exact_log_z(model: &GaussianLikelihood, mu: &[f64], sd: &[f64]) -> f64 {
+    (0..mu.len())
+        .map(|j| {
+            let var = model.s[j].powi(2) + sd[j].powi(2);
+            -0.5 * (2.0 * PI * var).ln() - 0.5 * (model.m[j] - mu[j]).powi(2) / var
+        })
+        .sum()
+}
+
+
+/// run every seed and require each log Z within four of its own standard
+/// errors of the exact value, plus a little for the bias of short chains
+fn check(dim: usize, method: Method, seeds: &[u64]) {
+    let (model, mu, sd) = problem(dim);
+    let exact = exact_log_z(&model, &mu, &sd);
+    let config = Config{
+        sample_num: 5000,
+        particle_num: 100,
+        mu,
+        sd,
+        tolerance: Some(1e-3),
+        sampler: SamplerConfig{ method, ..Default::default() },
+        ..Default::default()
+    };
+    for &seed in seeds {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let result = run_with_model(&config, &model, &mut Collect::default(), &mut rng).unwrap();
+        assert!(
+            (result.log_z - exact).abs() < 4.0 * result.log_z_err + 0.1,
+            "{} dimensions, {:?}, seed {}: log Z {} +- {} against {}",
+            dim, method, seed, result.log_z, result.log_z_err, exact,
+        );
+        assert!(result.truncation.is_empty(), "{:?}", result.truncation);
+    }
+}
+
+
+#[test]
+fn test_one_dimension() {
+    check(1, Method::Rejection, &[1, 2, 3]);
+}
+
+
+#[test]
+fn test_three_dimensions() {
+    check(3, Method::RandomWalk, &[4, 5, 6]);
+}
+
+
+#[test]
+fn test_six_dimensions() {
+    check(6, Method::RandomWalk, &[7, 8]);
+    check(6, Method::HitAndRun, &[9]);
+}